@@ -0,0 +1,116 @@
+//! `cargo bench -p epoch-encoding`
+//!
+//! Varints make up most of a `SetBlockNumbersForNextEpoch` payload, one acceleration per network,
+//! so these cover both single varints of every length and whole epochs with many networks.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use epoch_encoding::varint::{decode_i64, decode_u64, encode_i64, encode_u64};
+use epoch_encoding::{BlockPtr, Encoder, Message, Network};
+
+/// A value that takes `len` bytes.
+fn value_of_len(len: u32) -> u64 {
+    match len {
+        9 => u64::MAX,
+        len => (1 << (7 * len - 1)) | 1,
+    }
+}
+
+fn single_varints(c: &mut Criterion) {
+    let mut group = c.benchmark_group("u64");
+    for len in [1, 2, 4, 8, 9] {
+        let value = value_of_len(len);
+        let mut encoded = vec![];
+        encode_u64(value, &mut encoded);
+        group.bench_with_input(BenchmarkId::new("encode", len), &value, |b, value| {
+            let mut bytes = Vec::with_capacity(16);
+            b.iter(|| {
+                bytes.clear();
+                encode_u64(black_box(*value), &mut bytes);
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("decode", len), &encoded, |b, encoded| {
+            b.iter(|| decode_u64(black_box(encoded)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Accelerations as they show up in practice: mostly small, in both directions.
+fn accelerations(count: usize) -> Vec<i64> {
+    (0..count as i64)
+        .map(|i| (i * 7919 % 2001 - 1000) * if i % 10 == 0 { 1000 } else { 1 })
+        .collect()
+}
+
+fn accelerations_of_an_epoch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("accelerations");
+    for count in [10, 100, 500] {
+        let accelerations = accelerations(count);
+        let mut encoded = vec![];
+        for acceleration in &accelerations {
+            encode_i64(*acceleration, &mut encoded);
+        }
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(
+            BenchmarkId::new("encode", count),
+            &accelerations,
+            |b, accelerations| {
+                let mut bytes = Vec::with_capacity(encoded.len());
+                b.iter(|| {
+                    bytes.clear();
+                    for acceleration in accelerations {
+                        encode_i64(black_box(*acceleration), &mut bytes);
+                    }
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("decode", count), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut offset = 0;
+                while offset < encoded.len() {
+                    offset += decode_i64(black_box(&encoded[offset..])).unwrap().1;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn payload_of_an_epoch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("payload");
+    for count in [100, 500] {
+        let networks: Vec<_> = (0..count)
+            .map(|i| {
+                let id = format!("eip155:{i}");
+                (id, Network::new(1_000_000, 100, i as u64))
+            })
+            .collect();
+        let message = Message::SetBlockNumbersForNextEpoch(
+            networks
+                .iter()
+                .zip(accelerations(count))
+                .map(|((id, _), acceleration)| {
+                    let number = (1_000_100 + acceleration.clamp(-100_000, 100_000)) as u64;
+                    (id.clone(), BlockPtr::new(number, [1; 32]))
+                })
+                .collect(),
+        );
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("encode", count), &message, |b, message| {
+            b.iter(|| {
+                let mut encoder = Encoder::new(0, networks.clone()).unwrap();
+                let compressed = encoder.compress(std::slice::from_ref(message)).unwrap();
+                encoder.encode(&compressed).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    single_varints,
+    accelerations_of_an_epoch,
+    payload_of_an_epoch
+);
+criterion_main!(benches);
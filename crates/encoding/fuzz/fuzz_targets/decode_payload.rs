@@ -0,0 +1,28 @@
+//! Feeds arbitrary calldata to the decoder, which must return an error instead of panicking. The
+//! first byte is the number of networks registered before the payload.
+//!
+//! Payloads that decode must decode to the same messages after serializing them again.
+
+#![no_main]
+
+use epoch_encoding::{decode_messages_with_networks, serialize_messages};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((network_count, payload)) = data.split_first() else {
+        return;
+    };
+    let network_count = u64::from(*network_count);
+    let Ok(messages) = decode_messages_with_networks(payload, network_count) else {
+        return;
+    };
+
+    let mut bytes = vec![];
+    // Permissions for unknown messages decode, but can't be serialized.
+    if serialize_messages(&messages, &mut bytes).is_err() {
+        return;
+    }
+    let decoded = decode_messages_with_networks(&bytes, network_count)
+        .expect("Serialized messages must decode");
+    assert_eq!(decoded, messages);
+});
@@ -0,0 +1,239 @@
+//! [`Arbitrary`] implementations for generating messages, and [`roundtrip`] for checking that
+//! whatever the [`Encoder`] writes decodes to the same messages.
+//!
+//! Generated messages only use a handful of network IDs, so that block numbers and corrections
+//! often refer to registered networks. They can still be invalid, e.g. by removing networks that
+//! don't exist, in which case [`roundtrip`] fails with [`RoundtripError::Encode`].
+
+use crate::messages::*;
+use crate::{
+    decode_messages, DecodeError, EncodeError, Encoder, CURRENT_ENCODING_VERSION,
+    SUPPORTED_ENCODING_VERSIONS,
+};
+use proptest::collection::{btree_map, vec};
+use proptest::prelude::*;
+use proptest::sample::select;
+
+const NETWORK_IDS: &[&str] = &[
+    "eip155:1",
+    "eip155:100",
+    "eip155:42161",
+    "bip122:000000000019d6689c085ae165831e93",
+];
+
+/// Something that went wrong in [`roundtrip`].
+#[derive(Debug, thiserror::Error)]
+pub enum RoundtripError {
+    #[error("Failed to encode the messages: {0}")]
+    Encode(#[from] EncodeError),
+    #[error("Failed to decode the payload: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("The payload decodes to different messages: expected {expected:?}, got {decoded:?}")]
+    Mismatch {
+        expected: Vec<CompressedMessage>,
+        decoded: Vec<CompressedMessage>,
+    },
+}
+
+/// Encodes `messages` as a single payload, starting from an [`Encoder`] without any networks, and
+/// checks that decoding the payload gives back the compressed messages. Returns the payload.
+pub fn roundtrip(messages: &[Message]) -> Result<Vec<u8>, RoundtripError> {
+    let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![])?;
+    let expected = encoder.compress(messages)?;
+    let payload = encoder.encode(&expected)?;
+    let decoded = decode_messages(&payload)?;
+    if decoded != expected {
+        return Err(RoundtripError::Mismatch { expected, decoded });
+    }
+    Ok(payload)
+}
+
+fn network_id() -> impl Strategy<Value = String> {
+    select(NETWORK_IDS).prop_map(str::to_owned)
+}
+
+fn epoch_details() -> impl Strategy<Value = EpochDetails> {
+    any::<(Bytes32, Bytes32)>()
+        .prop_map(|(tx_hash, merkle_root)| EpochDetails::new(tx_hash, merkle_root))
+}
+
+/// Realistic block numbers, far from overflowing accelerations.
+impl Arbitrary for BlockPtr {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (0..1u64 << 48, any::<Bytes32>())
+            .prop_map(|(number, hash)| BlockPtr::new(number, hash))
+            .boxed()
+    }
+}
+
+impl Arbitrary for Message {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let removals = || vec(0..NETWORK_IDS.len() as NetworkIndex, 0..2);
+        prop_oneof![
+            8 => btree_map(network_id(), any::<BlockPtr>(), 0..=NETWORK_IDS.len())
+                .prop_map(Message::SetBlockNumbersForNextEpoch),
+            4 => (removals(), vec(network_id(), 0..3))
+                .prop_map(|(remove, add)| Message::RegisterNetworks { remove, add }),
+            2 => (removals(), vec((network_id(), "[a-z]{1,8}"), 0..3))
+                .prop_map(|(remove, add)| Message::RegisterNetworksAndAliases { remove, add }),
+            2 => btree_map(0..NETWORK_IDS.len() as NetworkIndex, epoch_details(), 0..3)
+                .prop_map(|data_by_network_id| Message::CorrectEpochs { data_by_network_id }),
+            2 => (any::<[u8; 20]>(), any::<u64>(), vec(select(&MESSAGE_NAMES[..]), 0..4)).prop_map(
+                |(address, valid_through, permissions)| Message::ChangePermissions {
+                    address,
+                    valid_through,
+                    permissions: permissions.into_iter().map(str::to_owned).collect(),
+                }
+            ),
+            2 => (
+                removals(),
+                vec(network_id(), 0..3),
+                btree_map(network_id(), any::<BlockPtr>(), 0..=NETWORK_IDS.len())
+            )
+                .prop_map(|(remove, add, block_ptrs)| {
+                    Message::RegisterNetworksAndSetBlockNumbers {
+                        remove,
+                        add,
+                        block_ptrs,
+                    }
+                }),
+            1 => (0..=SUPPORTED_ENCODING_VERSIONS.end() + 1)
+                .prop_map(|version_number| Message::UpdateVersion { version_number }),
+            1 => Just(Message::Reset),
+        ]
+        .boxed()
+    }
+}
+
+/// The parameter is the number of networks that are registered before the message, which the
+/// message is valid for.
+impl Arbitrary for CompressedMessage {
+    type Parameters = u64;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(network_count: u64) -> Self::Strategy {
+        let set_block_numbers = move |network_count: u64| {
+            if network_count == 0 {
+                any::<u64>()
+                    .prop_map(|count| CompressedSetBlockNumbersForNextEpoch::Empty { count })
+                    .boxed()
+            } else {
+                (vec(any::<i64>(), network_count as usize), any::<Bytes32>())
+                    .prop_map(|(accelerations, root)| {
+                        CompressedSetBlockNumbersForNextEpoch::NonEmpty {
+                            accelerations,
+                            root,
+                        }
+                    })
+                    .boxed()
+            }
+        };
+        // Removals can't repeat, so they're a subset of the registered networks.
+        let removals = move || {
+            let max_len = network_count.min(2) as usize;
+            proptest::sample::subsequence((0..network_count).collect::<Vec<_>>(), 0..=max_len)
+                .prop_shuffle()
+        };
+        let corrections = if network_count == 0 {
+            Just(Default::default()).boxed()
+        } else {
+            btree_map(0..network_count, epoch_details(), 0..3).boxed()
+        };
+        // The block numbers are for the networks after the registration.
+        let register_and_set_block_numbers = (removals(), vec(network_id(), 0..3))
+            .prop_flat_map(move |(remove, add)| {
+                let count = network_count - remove.len() as u64 + add.len() as u64;
+                (Just(remove), Just(add), set_block_numbers(count))
+            })
+            .prop_map(|(remove, add, block_numbers)| {
+                CompressedMessage::RegisterNetworksAndSetBlockNumbers {
+                    remove,
+                    add,
+                    block_numbers,
+                }
+            });
+        prop_oneof![
+            set_block_numbers(network_count)
+                .prop_map(CompressedMessage::SetBlockNumbersForNextEpoch),
+            (removals(), vec(network_id(), 0..3))
+                .prop_map(|(remove, add)| CompressedMessage::RegisterNetworks { remove, add }),
+            (removals(), vec((network_id(), "[a-z]{1,8}"), 0..3)).prop_map(|(remove, add)| {
+                CompressedMessage::RegisterNetworksAndAliases { remove, add }
+            }),
+            corrections.prop_map(|data_by_network_id| CompressedMessage::CorrectEpochs {
+                data_by_network_id
+            }),
+            (
+                any::<[u8; 20]>(),
+                any::<u64>(),
+                vec(0..UNKNOWN_MESSAGE_TAG, 0..4)
+            )
+                .prop_map(|(address, valid_through, permissions)| {
+                    CompressedMessage::ChangePermissions {
+                        address,
+                        valid_through,
+                        permissions,
+                    }
+                }),
+            any::<u64>()
+                .prop_map(|version_number| CompressedMessage::UpdateVersion { version_number }),
+            Just(CompressedMessage::Reset),
+            register_and_set_block_numbers,
+        ]
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_messages_with_networks, serialize_messages};
+
+    proptest! {
+        #[test]
+        fn messages_round_trip(messages in vec(any::<Message>(), 0..8)) {
+            match roundtrip(&messages) {
+                Ok(_) | Err(RoundtripError::Encode(_)) => {}
+                Err(error) => prop_assert!(false, "{}", error),
+            }
+        }
+
+        #[test]
+        fn pushed_messages_match_batches(messages in vec(any::<Message>(), 0..8)) {
+            let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+            let Ok(batch) = encoder
+                .clone()
+                .compress(&messages)
+                .and_then(|compressed| encoder.encode(&compressed))
+            else {
+                return Ok(());
+            };
+            let mut bytes = vec![];
+            for message in &messages {
+                encoder.push_message(message, &mut bytes).unwrap();
+            }
+            encoder.finish(&mut bytes).unwrap();
+            prop_assert_eq!(bytes, batch);
+        }
+
+        #[test]
+        fn compressed_messages_round_trip(
+            (network_count, message) in (0..4u64).prop_flat_map(|network_count| {
+                (Just(network_count), any_with::<CompressedMessage>(network_count))
+            })
+        ) {
+            let mut bytes = vec![];
+            serialize_messages(std::slice::from_ref(&message), &mut bytes).unwrap();
+            prop_assert_eq!(
+                decode_messages_with_networks(&bytes, network_count).unwrap(),
+                vec![message]
+            );
+        }
+    }
+}
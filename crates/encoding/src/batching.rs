@@ -0,0 +1,248 @@
+//! Batching of pending [`Message`]s into the payloads of sequential transactions.
+//!
+//! [`Encoder::split_into_payloads`] writes messages in the order it's given them. [`batch`] first
+//! rearranges them:
+//!
+//! - Registrations that only add networks move ahead of the `SetBlockNumbersForNextEpoch`
+//!   messages right before them, so that the new networks get their first block numbers in the
+//!   same batch. Registrations that remove networks stay where they are, since block numbers for
+//!   the removed networks wouldn't encode anymore. No registration moves past any other message,
+//!   as those may refer to networks by index.
+//! - A `RegisterNetworks` message right before a `SetBlockNumbersForNextEpoch` message is merged
+//!   with it into a `RegisterNetworksAndSetBlockNumbers` message, which takes a single tag and
+//!   always lands in the same payload.
+//!
+//! The messages are then split into as few payloads as fit in `max_bytes` each. If a merged
+//! message doesn't fit in a payload by itself, nothing is merged, so that the registration and the
+//! block numbers can go in consecutive payloads instead.
+//!
+//! Every payload only decodes once the ones before it have, so they must be submitted in order,
+//! each after the previous one was confirmed.
+
+use crate::{messages::Message, EncodeError, Encoder};
+use alloc::vec::Vec;
+
+/// The payload of a single transaction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Batch {
+    /// The names of the messages in the payload, see [`Message::name`].
+    pub messages: Vec<&'static str>,
+    pub payload: Vec<u8>,
+    /// How many networks are registered before the payload, which the subgraph decodes it with.
+    pub network_count: usize,
+}
+
+/// Orders, merges and splits `messages` into batches, see the [module documentation](self). The
+/// [`Encoder`] is only updated if all of them fit.
+pub fn batch(
+    encoder: &mut Encoder,
+    messages: Vec<Message>,
+    max_bytes: usize,
+) -> Result<Vec<Batch>, EncodeError> {
+    let ordered = order(messages);
+    let merged = merge(ordered.clone());
+    if merged.len() < ordered.len() {
+        match split(encoder, &merged, max_bytes) {
+            Err(EncodeError::PayloadTooLarge { .. }) => {}
+            result => return result,
+        }
+    }
+    split(encoder, &ordered, max_bytes)
+}
+
+fn order(messages: Vec<Message>) -> Vec<Message> {
+    let mut ordered: Vec<Message> = Vec::with_capacity(messages.len());
+    for message in messages {
+        let only_adds = match &message {
+            Message::RegisterNetworks { remove, .. }
+            | Message::RegisterNetworksAndAliases { remove, .. } => remove.is_empty(),
+            _ => false,
+        };
+        let mut at = ordered.len();
+        while only_adds && at > 0 {
+            if !matches!(ordered[at - 1], Message::SetBlockNumbersForNextEpoch(_)) {
+                break;
+            }
+            at -= 1;
+        }
+        ordered.insert(at, message);
+    }
+    ordered
+}
+
+fn merge(messages: Vec<Message>) -> Vec<Message> {
+    let mut merged = Vec::with_capacity(messages.len());
+    let mut messages = messages.into_iter().peekable();
+    while let Some(message) = messages.next() {
+        let Message::RegisterNetworks { remove, add } = message else {
+            merged.push(message);
+            continue;
+        };
+        let next = messages.next_if(|next| matches!(next, Message::SetBlockNumbersForNextEpoch(_)));
+        merged.push(match next {
+            Some(Message::SetBlockNumbersForNextEpoch(block_ptrs)) => {
+                Message::RegisterNetworksAndSetBlockNumbers {
+                    remove,
+                    add,
+                    block_ptrs,
+                }
+            }
+            _ => Message::RegisterNetworks { remove, add },
+        });
+    }
+    merged
+}
+
+/// Only updates `encoder` if all `messages` fit.
+fn split(
+    encoder: &mut Encoder,
+    messages: &[Message],
+    max_bytes: usize,
+) -> Result<Vec<Batch>, EncodeError> {
+    let mut attempt = encoder.clone();
+    let payloads = attempt.split(messages, max_bytes)?;
+    *encoder = attempt;
+    let mut start = 0;
+    Ok(payloads
+        .into_iter()
+        .map(|payload| {
+            let messages = messages[start..payload.end]
+                .iter()
+                .map(Message::name)
+                .collect();
+            start = payload.end;
+            Batch {
+                messages,
+                payload: payload.bytes,
+                network_count: payload.network_count,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_messages_with_networks, BlockPtr, CompressedMessage};
+    use alloc::{collections::BTreeMap, string::ToString, vec};
+
+    fn register(remove: &[u64], add: &[&str]) -> Message {
+        Message::RegisterNetworks {
+            remove: remove.to_vec(),
+            add: add.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn block_numbers(chain_ids: &[&str]) -> Message {
+        let block_ptrs: BTreeMap<_, _> = chain_ids
+            .iter()
+            .map(|id| (id.to_string(), BlockPtr::new(100, [1; 32])))
+            .collect();
+        Message::SetBlockNumbersForNextEpoch(block_ptrs)
+    }
+
+    fn names(batches: &[Batch]) -> Vec<Vec<&'static str>> {
+        batches.iter().map(|batch| batch.messages.clone()).collect()
+    }
+
+    #[test]
+    fn registrations_go_first_and_merge() {
+        let mut encoder = Encoder::new(0, vec![]).unwrap();
+        encoder
+            .compress(&[register(&[], &["eip155:1"]), block_numbers(&[])])
+            .unwrap();
+        let batches = batch(
+            &mut encoder,
+            vec![
+                block_numbers(&["eip155:1", "eip155:100"]),
+                register(&[], &["eip155:100"]),
+            ],
+            usize::MAX,
+        )
+        .unwrap();
+        assert_eq!(names(&batches), [["RegisterNetworksAndSetBlockNumbers"]]);
+        assert_eq!(batches[0].network_count, 1);
+        assert_eq!(encoder.network_deltas().len(), 2);
+
+        let [message] = &decode_messages_with_networks(&batches[0].payload, 1).unwrap()[..] else {
+            panic!("expected a single message");
+        };
+        assert!(matches!(
+            message,
+            CompressedMessage::RegisterNetworksAndSetBlockNumbers { add, .. } if add == &["eip155:100"]
+        ));
+    }
+
+    #[test]
+    fn removals_and_other_messages_keep_their_place() {
+        let messages = vec![
+            block_numbers(&[]),
+            register(&[0], &["eip155:1"]),
+            Message::Reset,
+            block_numbers(&[]),
+            register(&[], &["eip155:1"]),
+        ];
+        let ordered: Vec<_> = order(messages).iter().map(Message::name).collect();
+        assert_eq!(
+            ordered,
+            [
+                "SetBlockNumbersForNextEpoch",
+                "RegisterNetworks",
+                "Reset",
+                "RegisterNetworks",
+                "SetBlockNumbersForNextEpoch",
+            ]
+        );
+    }
+
+    #[test]
+    fn large_merges_are_split() {
+        let chain_ids = ["eip155:1", "eip155:10", "eip155:100", "eip155:137"];
+        let messages = vec![block_numbers(&chain_ids), register(&[], &chain_ids)];
+
+        let mut merged = Encoder::new(0, vec![]).unwrap();
+        let batches = batch(&mut merged, messages.clone(), usize::MAX).unwrap();
+        assert_eq!(names(&batches), [["RegisterNetworksAndSetBlockNumbers"]]);
+        let max_bytes = batches[0].payload.len() - 1;
+
+        let mut encoder = Encoder::new(0, vec![]).unwrap();
+        let batches = batch(&mut encoder, messages, max_bytes).unwrap();
+        assert_eq!(
+            names(&batches),
+            [["RegisterNetworks"], ["SetBlockNumbersForNextEpoch"]]
+        );
+        assert!(batches.iter().all(|batch| batch.payload.len() <= max_bytes));
+        assert_eq!(encoder.network_table(), merged.network_table());
+        // The block numbers only decode once the networks are registered.
+        assert_eq!(batches[1].network_count, 4);
+        assert_eq!(
+            decode_messages_with_networks(&batches[1].payload, 4)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn nothing_changes_unless_everything_fits() {
+        let messages = vec![
+            register(&[], &["eip155:1"]),
+            register(&[], &[&"eip155:1".repeat(10)]),
+        ];
+        let max_bytes = batch(
+            &mut Encoder::new(0, vec![]).unwrap(),
+            messages[..1].to_vec(),
+            usize::MAX,
+        )
+        .unwrap()[0]
+            .payload
+            .len();
+
+        let mut encoder = Encoder::new(0, vec![]).unwrap();
+        assert!(matches!(
+            batch(&mut encoder, messages, max_bytes),
+            Err(EncodeError::PayloadTooLarge { .. })
+        ));
+        assert!(encoder.network_deltas().is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+//! Prints the messages of a payload as JSON, see [`epoch_encoding::json`].
+//!
+//! ```text
+//! epoch-decode [--networks <COUNT>] [--version <VERSION>] [--segments] [HEX]
+//! ```
+//!
+//! The hex is read from stdin if it isn't an argument. It can be either the payload itself, or the
+//! input data of a DataEdge transaction as shown by block explorers. `--networks` is the number of
+//! networks that were registered before the payload, which block number messages depend on, and
+//! `--version` is the encoding version the subgraph was on. With `--segments`, it prints which
+//! bytes every message was decoded from, one line each, instead of JSON.
+
+use std::io::Read;
+use std::process::ExitCode;
+use tiny_keccak::{Hasher, Keccak};
+
+const USAGE: &str =
+    "Usage: epoch-decode [--networks <COUNT>] [--version <VERSION>] [--segments] [HEX]";
+const DATA_EDGE_FUNCTION: &str = "crossChainEpochOracle(bytes)";
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<String, String> {
+    let mut network_count = 0;
+    let mut encoding_version = 0;
+    let mut segments = false;
+    let mut hex = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--networks" => {
+                network_count = args
+                    .next()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or(USAGE)?;
+            }
+            "--version" => {
+                encoding_version = args
+                    .next()
+                    .and_then(|version| version.parse().ok())
+                    .ok_or(USAGE)?;
+            }
+            "--segments" => segments = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if hex.is_none() => hex = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    let hex = match hex {
+        Some(hex) => hex,
+        None => {
+            let mut hex = String::new();
+            std::io::stdin()
+                .read_to_string(&mut hex)
+                .map_err(|e| format!("Failed to read stdin: {e}"))?;
+            hex
+        }
+    };
+
+    let bytes = hex::decode(hex.trim().trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid hex: {e}"))?;
+    let payload = strip_calldata(&bytes).unwrap_or(&bytes);
+    if segments {
+        let segments = epoch_encoding::inspect(payload, network_count, encoding_version)
+            .map_err(|e| e.to_string())?;
+        let lines: Vec<String> = segments.iter().map(ToString::to_string).collect();
+        return Ok(lines.join("\n"));
+    }
+    let messages =
+        epoch_encoding::decode_messages_with_version(payload, network_count, encoding_version)
+            .map_err(|e| e.to_string())?;
+    epoch_encoding::json::compressed_to_json(messages)
+        .map(|json| format!("{json:#}"))
+        .map_err(|e| e.to_string())
+}
+
+/// Returns the payload if `bytes` is the input data of a DataEdge transaction, i.e. the function
+/// selector followed by the ABI encoding of a single `bytes` argument.
+fn strip_calldata(bytes: &[u8]) -> Option<&[u8]> {
+    let mut selector = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(DATA_EDGE_FUNCTION.as_bytes());
+    hasher.finalize(&mut selector);
+
+    let arguments = bytes.strip_prefix(&selector[..4])?;
+    let word = |offset: usize| -> Option<usize> {
+        let word = arguments.get(offset..offset.checked_add(32)?)?;
+        // Anything that doesn't fit in 8 bytes is out of bounds anyway.
+        let (high, low) = word.split_at(24);
+        if high.iter().any(|byte| *byte != 0) {
+            return None;
+        }
+        usize::try_from(u64::from_be_bytes(low.try_into().ok()?)).ok()
+    };
+    let offset = word(0)?;
+    let len = word(offset)?;
+    let start = offset.checked_add(32)?;
+    arguments.get(start..start.checked_add(len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_calldata() {
+        let calldata = hex::decode(
+            "a1dce332\
+             0000000000000000000000000000000000000000000000000000000000000020\
+             0000000000000000000000000000000000000000000000000000000000000002\
+             0501000000000000000000000000000000000000000000000000000000000000",
+        )
+        .unwrap();
+        assert_eq!(strip_calldata(&calldata), Some(&[0x05, 0x01][..]));
+        assert_eq!(strip_calldata(&[0x05, 0x01]), None);
+        assert_eq!(strip_calldata(&calldata[..calldata.len() - 32]), None);
+    }
+}
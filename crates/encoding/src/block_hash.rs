@@ -0,0 +1,148 @@
+//! Normalization of block hashes into the [`Bytes32`] that payloads commit to.
+//!
+//! Every chain has its own way of writing down block hashes: EVM chains use `0x`-prefixed hex,
+//! Tendermint chains upper-case hex, and Solana or NEAR base58. The CAIP-2 namespace of a chain
+//! decides how its hashes are decoded, according to [`HASH_ENCODINGS`]:
+//!
+//! | Namespace | Encoding |
+//! |-----------|----------|
+//! | `eip155`, `bip122`, `cosmos` and anything else | hex, optionally `0x`-prefixed, any case |
+//! | `solana`, `near` | base58, Bitcoin alphabet |
+//!
+//! Decoded hashes of exactly 32 bytes are used as they are. Hashes of any other length are
+//! replaced with their keccak256 digest, so that they still fit in a [`Bytes32`]. Anyone
+//! recomputing a payload's merkle root (e.g. the subgraph or a decoder) must apply the same rules.
+
+use crate::merkle::keccak;
+use crate::messages::Bytes32;
+use crate::{Caip2ChainId, EncodeError};
+use alloc::{borrow::ToOwned, vec, vec::Vec};
+
+/// How a chain writes down its block hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashEncoding {
+    Hex,
+    Base58,
+}
+
+/// CAIP-2 namespaces whose block hashes aren't hex-encoded.
+pub const HASH_ENCODINGS: &[(&str, HashEncoding)] = &[
+    ("solana", HashEncoding::Base58),
+    ("near", HashEncoding::Base58),
+];
+
+impl HashEncoding {
+    pub fn for_chain(chain_id: &Caip2ChainId) -> Self {
+        HASH_ENCODINGS
+            .iter()
+            .find(|(namespace, _)| *namespace == chain_id.namespace_part())
+            .map_or(HashEncoding::Hex, |(_, encoding)| *encoding)
+    }
+
+    fn decode(self, hash: &str) -> Option<Vec<u8>> {
+        match self {
+            HashEncoding::Hex => {
+                let hash = hash.strip_prefix("0x").unwrap_or(hash);
+                hex::decode(hash).ok()
+            }
+            HashEncoding::Base58 => decode_base58(hash),
+        }
+    }
+}
+
+/// Decodes `hash` according to the rules of `chain_id`'s namespace.
+///
+/// Fails with [`EncodeError::InvalidBlockHash`] if `hash` is empty or not validly encoded.
+pub fn normalize_block_hash(chain_id: &Caip2ChainId, hash: &str) -> Result<Bytes32, EncodeError> {
+    let invalid = || EncodeError::InvalidBlockHash {
+        chain_id: chain_id.as_str().to_owned(),
+        hash: hash.to_owned(),
+    };
+    let bytes = HashEncoding::for_chain(chain_id)
+        .decode(hash)
+        .filter(|bytes| !bytes.is_empty())
+        .ok_or_else(invalid)?;
+    Ok(bytes
+        .as_slice()
+        .try_into()
+        .unwrap_or_else(|_| keccak([&bytes])))
+}
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn decode_base58(s: &str) -> Option<Vec<u8>> {
+    // Big-endian base 256 digits, built up one base58 digit at a time.
+    let mut bytes: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|a| *a == c)? as u32;
+        for byte in bytes.iter_mut().rev() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    // Leading '1's stand for leading zero bytes.
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes);
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chain_id(s: &str) -> Caip2ChainId {
+        Caip2ChainId::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn hex_hashes() {
+        let hash = [0xab; 32];
+        let lower = format!("0x{}", hex::encode(hash));
+        let upper = hex::encode_upper(hash);
+        assert_eq!(
+            normalize_block_hash(&chain_id("eip155:1"), &lower).unwrap(),
+            hash
+        );
+        assert_eq!(
+            normalize_block_hash(&chain_id("cosmos:cosmoshub-4"), &upper).unwrap(),
+            hash
+        );
+        assert!(matches!(
+            normalize_block_hash(&chain_id("eip155:1"), "0xzz"),
+            Err(EncodeError::InvalidBlockHash { .. })
+        ));
+    }
+
+    #[test]
+    fn base58_hashes() {
+        let solana = chain_id("solana:5eykt4UsFv8P8NJdTREpY1vzqKqZKvdp");
+        // 32 zero bytes.
+        assert_eq!(
+            normalize_block_hash(&solana, "11111111111111111111111111111111").unwrap(),
+            [0; 32]
+        );
+        let hash =
+            normalize_block_hash(&solana, "4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZAMdL4VZHirAn").unwrap();
+        assert_eq!(
+            hex::encode(hash),
+            "3973e330c29b831f3fcb0e49374ed8d0388f410a23e4ebf23328505036efbd03"
+        );
+        assert!(matches!(
+            normalize_block_hash(&chain_id("near:mainnet"), "0OIl"),
+            Err(EncodeError::InvalidBlockHash { .. })
+        ));
+    }
+
+    #[test]
+    fn other_lengths_are_hashed() {
+        let short = normalize_block_hash(&chain_id("eip155:1"), "0x1234").unwrap();
+        assert_eq!(short, keccak([&[0x12, 0x34]]));
+    }
+}
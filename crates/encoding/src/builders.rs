@@ -0,0 +1,177 @@
+//! Builders for [`Message`]s that can't be assembled in a structurally invalid way.
+//!
+//! [`Message`]'s variants are plain data and will happily hold duplicate networks or malformed
+//! chain IDs, which the [`Encoder`](crate::Encoder) (and ultimately the subgraph) would then
+//! have to make sense of. Prefer these builders when constructing messages by hand.
+
+use crate::messages::{BlockPtr, Message, NetworkIndex};
+use crate::{Caip2ChainId, EncodeError};
+use alloc::{borrow::ToOwned, collections::BTreeMap, string::String, vec::Vec};
+use core::str::FromStr;
+
+/// Builds a [`Message::SetBlockNumbersForNextEpoch`] with at most one block per network.
+///
+/// ```
+/// use epoch_encoding::{BlockPtr, Caip2ChainId, SetBlockNumbersBuilder};
+///
+/// let message = SetBlockNumbersBuilder::new()
+///     .block_ptr(Caip2ChainId::ethereum_mainnet(), BlockPtr::new(42, [0; 32]))?
+///     .build();
+/// # Ok::<(), epoch_encoding::EncodeError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SetBlockNumbersBuilder {
+    block_ptrs: BTreeMap<String, BlockPtr>,
+}
+
+impl SetBlockNumbersBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails with [`EncodeError::DuplicateNetworkId`] if `chain_id` already has a block.
+    pub fn block_ptr(
+        mut self,
+        chain_id: Caip2ChainId,
+        block_ptr: BlockPtr,
+    ) -> Result<Self, EncodeError> {
+        let chain_id = chain_id.as_str().to_owned();
+        if self.block_ptrs.contains_key(&chain_id) {
+            return Err(EncodeError::DuplicateNetworkId(chain_id));
+        }
+        self.block_ptrs.insert(chain_id, block_ptr);
+        Ok(self)
+    }
+
+    pub fn block_ptrs(
+        self,
+        block_ptrs: impl IntoIterator<Item = (Caip2ChainId, BlockPtr)>,
+    ) -> Result<Self, EncodeError> {
+        block_ptrs
+            .into_iter()
+            .try_fold(self, |builder, (chain_id, block_ptr)| {
+                builder.block_ptr(chain_id, block_ptr)
+            })
+    }
+
+    /// An empty builder results in an empty set of block numbers, which the [`Encoder`](crate::Encoder)
+    /// compresses into a single byte.
+    pub fn build(self) -> Message {
+        Message::SetBlockNumbersForNextEpoch(self.block_ptrs)
+    }
+}
+
+/// Builds a [`Message::RegisterNetworks`] out of valid CAIP-2 chain IDs, without duplicates.
+///
+/// Removals are applied before additions, so a network can be removed and added back in the same
+/// message.
+#[derive(Debug, Clone, Default)]
+pub struct RegisterNetworksBuilder {
+    remove: Vec<NetworkIndex>,
+    add: Vec<String>,
+}
+
+impl RegisterNetworksBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails with [`EncodeError::DuplicateNetworkIndex`] if `index` is already being removed.
+    pub fn remove_network(mut self, index: NetworkIndex) -> Result<Self, EncodeError> {
+        if self.remove.contains(&index) {
+            return Err(EncodeError::DuplicateNetworkIndex(index));
+        }
+        self.remove.push(index);
+        Ok(self)
+    }
+
+    /// Fails with [`EncodeError::InvalidNetworkId`] if `chain_id` isn't a valid CAIP-2 chain ID, or
+    /// [`EncodeError::DuplicateNetworkId`] if it's already being added.
+    pub fn add_network(mut self, chain_id: &str) -> Result<Self, EncodeError> {
+        let chain_id = Caip2ChainId::from_str(chain_id)
+            .map_err(|_| EncodeError::InvalidNetworkId(chain_id.to_owned()))?;
+        let chain_id = chain_id.as_str().to_owned();
+        if self.add.contains(&chain_id) {
+            return Err(EncodeError::DuplicateNetworkId(chain_id));
+        }
+        self.add.push(chain_id);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Message {
+        Message::RegisterNetworks {
+            remove: self.remove,
+            add: self.add,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain_id(s: &str) -> Caip2ChainId {
+        Caip2ChainId::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn set_block_numbers() {
+        let message = SetBlockNumbersBuilder::new()
+            .block_ptrs([
+                (chain_id("eip155:1"), BlockPtr::new(1, [1; 32])),
+                (chain_id("eip155:100"), BlockPtr::new(2, [2; 32])),
+            ])
+            .unwrap()
+            .build();
+        let Message::SetBlockNumbersForNextEpoch(block_ptrs) = message else {
+            panic!("unexpected message: {message:?}");
+        };
+        assert_eq!(block_ptrs["eip155:100"], BlockPtr::new(2, [2; 32]));
+        assert_eq!(block_ptrs.len(), 2);
+    }
+
+    #[test]
+    fn set_block_numbers_rejects_duplicates() {
+        let result = SetBlockNumbersBuilder::new().block_ptrs([
+            (chain_id("eip155:1"), BlockPtr::new(1, [1; 32])),
+            (chain_id("eip155:1"), BlockPtr::new(2, [2; 32])),
+        ]);
+        assert!(matches!(result, Err(EncodeError::DuplicateNetworkId(id)) if id == "eip155:1"));
+    }
+
+    #[test]
+    fn register_networks() {
+        let message = RegisterNetworksBuilder::new()
+            .remove_network(0)
+            .unwrap()
+            .add_network("eip155:1")
+            .unwrap()
+            .build();
+        let Message::RegisterNetworks { remove, add } = message else {
+            panic!("unexpected message: {message:?}");
+        };
+        assert_eq!(remove, [0]);
+        assert_eq!(add, ["eip155:1"]);
+    }
+
+    #[test]
+    fn register_networks_rejects_invalid_input() {
+        let builder = RegisterNetworksBuilder::new();
+        assert!(matches!(
+            builder.clone().add_network("mainnet"),
+            Err(EncodeError::InvalidNetworkId(_))
+        ));
+        assert!(matches!(
+            builder
+                .clone()
+                .add_network("eip155:1")
+                .unwrap()
+                .add_network("eip155:1"),
+            Err(EncodeError::DuplicateNetworkId(_))
+        ));
+        assert!(matches!(
+            builder.remove_network(3).unwrap().remove_network(3),
+            Err(EncodeError::DuplicateNetworkIndex(3))
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+//! Canonical forms of [`Message`]s, so that independent encoders agree on payloads byte for byte.
+//!
+//! The [`Encoder`](crate::Encoder) writes messages in the order it's given them, and most lists
+//! inside a message in their given order too. Two oracle instances that observe the same state can
+//! still build their messages differently, e.g. by registering networks in the order their config
+//! files list them. [`canonicalize`] removes those differences:
+//!
+//! - The order of the messages themselves is kept, since later messages depend on earlier ones.
+//! - Network removals and permissions are sorted and deduplicated. Removals are by index before
+//!   the message is applied, so their order never mattered.
+//! - Network registrations are sorted by chain ID and deduplicated, keeping the first alias of a
+//!   chain ID after sorting. Networks are assigned indices in this order.
+//! - Block numbers and epoch corrections are kept in maps, which are already ordered.
+//!
+//! Canonicalizing twice is the same as canonicalizing once.
+
+use crate::messages::Message;
+use alloc::vec::Vec;
+
+/// Rewrites `messages` into their canonical form, see the [module documentation](self).
+pub fn canonicalize(messages: Vec<Message>) -> Vec<Message> {
+    messages.into_iter().map(canonicalize_message).collect()
+}
+
+fn canonicalize_message(message: Message) -> Message {
+    match message {
+        Message::RegisterNetworks {
+            mut remove,
+            mut add,
+        } => {
+            remove.sort_unstable();
+            remove.dedup();
+            add.sort_unstable();
+            add.dedup();
+            Message::RegisterNetworks { remove, add }
+        }
+        Message::RegisterNetworksAndSetBlockNumbers {
+            mut remove,
+            mut add,
+            block_ptrs,
+        } => {
+            remove.sort_unstable();
+            remove.dedup();
+            add.sort_unstable();
+            add.dedup();
+            Message::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_ptrs,
+            }
+        }
+        Message::RegisterNetworksAndAliases {
+            mut remove,
+            mut add,
+        } => {
+            remove.sort_unstable();
+            remove.dedup();
+            add.sort_unstable();
+            add.dedup_by(|(id, _), (first_id, _)| id == first_id);
+            Message::RegisterNetworksAndAliases { remove, add }
+        }
+        Message::ChangePermissions {
+            address,
+            valid_through,
+            mut permissions,
+        } => {
+            permissions.sort_unstable();
+            permissions.dedup();
+            Message::ChangePermissions {
+                address,
+                valid_through,
+                permissions,
+            }
+        }
+        message @ (Message::SetBlockNumbersForNextEpoch(_)
+        | Message::CorrectEpochs { .. }
+        | Message::UpdateVersion { .. }
+        | Message::Reset) => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Encoder;
+    use alloc::{string::ToString, vec};
+
+    fn register(remove: &[u64], add: &[&str]) -> Message {
+        Message::RegisterNetworks {
+            remove: remove.to_vec(),
+            add: add.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn encode(messages: Vec<Message>) -> Vec<u8> {
+        let mut encoder = Encoder::new(0, vec![]).unwrap();
+        let compressed = encoder.compress(&canonicalize(messages)).unwrap();
+        encoder.encode(&compressed).unwrap()
+    }
+
+    #[test]
+    fn same_inputs_same_bytes() {
+        let a = encode(vec![
+            register(&[], &["eip155:100", "eip155:1", "eip155:1"]),
+            register(&[1, 0], &[]),
+        ]);
+        let b = encode(vec![
+            register(&[], &["eip155:1", "eip155:100"]),
+            register(&[0, 1, 0], &[]),
+        ]);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn aliases_and_permissions() {
+        let messages = canonicalize(vec![
+            Message::RegisterNetworksAndAliases {
+                remove: vec![],
+                add: vec![
+                    ("eip155:100".into(), "xdai".into()),
+                    ("eip155:100".into(), "gnosis".into()),
+                    ("eip155:1".into(), "mainnet".into()),
+                ],
+            },
+            Message::ChangePermissions {
+                address: [0; 20],
+                valid_through: 1,
+                permissions: vec!["ResetStateMessage".into(), "CorrectEpochsMessage".into()],
+            },
+        ]);
+        assert_eq!(
+            messages,
+            [
+                Message::RegisterNetworksAndAliases {
+                    remove: vec![],
+                    add: vec![
+                        ("eip155:1".into(), "mainnet".into()),
+                        ("eip155:100".into(), "gnosis".into()),
+                    ],
+                },
+                Message::ChangePermissions {
+                    address: [0; 20],
+                    valid_through: 1,
+                    permissions: vec!["CorrectEpochsMessage".into(), "ResetStateMessage".into()],
+                },
+            ]
+        );
+        assert_eq!(canonicalize(messages.clone()), messages);
+    }
+}
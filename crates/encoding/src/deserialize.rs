@@ -0,0 +1,688 @@
+//! The counterpart of [`serialize_messages`](crate::serialize_messages), following the subgraph's
+//! decoding rules.
+//!
+//! Payloads don't say how many accelerations a `SetBlockNumbersForNextEpoch` message carries:
+//! there's one per registered network, so the decoder keeps track of how many networks there are
+//! as it goes, just like the subgraph. [`decode_messages`] assumes that there are none before the
+//! payload; use [`decode_messages_with_networks`] for payloads that were submitted later on. Both
+//! decode version 0 of the encoding, see [`decode_messages_with_version`] for others.
+//!
+//! [`decode_message_refs`] decodes messages that borrow their strings and hashes from the payload
+//! instead, for when lots of payloads are decoded and mostly thrown away. [`inspect`] also tells
+//! which bytes every message was decoded from.
+
+use crate::messages::*;
+use crate::serialize::checksum;
+use crate::varint::{decode_i64, decode_u64, VarintError};
+use crate::{
+    CHECKSUM_BYTES, CHECKSUM_ENCODING_VERSION, DELTA_ACCELERATIONS_ENCODING_VERSION,
+    SUPPORTED_ENCODING_VERSIONS,
+};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use core::ops::Range;
+
+const PREAMBLE_BIT_LENGTH: usize = 8;
+const TAG_BIT_LENGTH: usize = 4;
+const PREAMBLE_CAPACITY: usize = PREAMBLE_BIT_LENGTH / TAG_BIT_LENGTH;
+
+/// One of the decoding functions in [`varint`](crate::varint).
+type DecodeVarint<T> = fn(&[u8]) -> Result<(T, usize), VarintError>;
+
+/// Something that went wrong when decoding a payload. Offsets are in bytes from the start of the
+/// payload.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("Invalid varint at offset {offset}: {source}")]
+    Varint { offset: usize, source: VarintError },
+    #[error("Expected {expected} more bytes at offset {offset}, but only {available} are left")]
+    Truncated {
+        offset: usize,
+        expected: usize,
+        available: usize,
+    },
+    #[error("Invalid message tag {tag} at offset {offset}")]
+    InvalidTag { offset: usize, tag: u8 },
+    #[error("Invalid UTF-8 string at offset {offset}")]
+    InvalidUtf8 { offset: usize },
+    #[error("Network index {index} is out of bounds, as there are only {network_count} networks")]
+    NetworkIndexOutOfBounds {
+        index: NetworkIndex,
+        network_count: u64,
+    },
+    #[error("Network index {0} appears more than once in the same message")]
+    DuplicateNetworkIndex(NetworkIndex),
+    #[error("Unsupported encoding version: {0}")]
+    UnsupportedEncodingVersion(u64),
+    #[error(
+        "The payload's trailer is 0x{}, but its checksum is 0x{}",
+        hex::encode(trailer),
+        hex::encode(checksum)
+    )]
+    ChecksumMismatch {
+        trailer: [u8; CHECKSUM_BYTES],
+        checksum: [u8; CHECKSUM_BYTES],
+    },
+}
+
+/// Decodes a payload that was submitted when no networks were registered yet.
+pub fn decode_messages(bytes: &[u8]) -> Result<Vec<CompressedMessage>, DecodeError> {
+    decode_messages_with_networks(bytes, 0)
+}
+
+/// Decodes a payload that was submitted when `network_count` networks were registered.
+pub fn decode_messages_with_networks(
+    bytes: &[u8],
+    network_count: u64,
+) -> Result<Vec<CompressedMessage>, DecodeError> {
+    decode_messages_with_version(bytes, network_count, 0)
+}
+
+/// Decodes a payload that was submitted when `network_count` networks were registered, and the
+/// subgraph was on `encoding_version`.
+pub fn decode_messages_with_version(
+    bytes: &[u8],
+    network_count: u64,
+    encoding_version: u64,
+) -> Result<Vec<CompressedMessage>, DecodeError> {
+    Ok(decode_message_refs(bytes, network_count, encoding_version)?
+        .into_iter()
+        .map(CompressedMessage::from)
+        .collect())
+}
+
+/// Like [`decode_messages_with_version`], without copying strings and hashes out of `bytes`.
+pub fn decode_message_refs(
+    bytes: &[u8],
+    network_count: u64,
+    encoding_version: u64,
+) -> Result<Vec<CompressedMessageRef<'_>>, DecodeError> {
+    let mut messages = vec![];
+    walk(bytes, network_count, encoding_version, |_, content| {
+        if let SegmentContent::Message { message, .. } = content {
+            messages.push(message);
+        }
+    })?;
+    Ok(messages)
+}
+
+/// A part of a payload, see [`inspect`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment<'a> {
+    /// Where the segment is in the payload.
+    pub range: Range<usize>,
+    pub content: SegmentContent<'a>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SegmentContent<'a> {
+    /// The tags of the messages that follow it, without padding.
+    Preamble { tags: Vec<u8> },
+    Message {
+        tag: u8,
+        message: CompressedMessageRef<'a>,
+    },
+    /// The trailer of [`CHECKSUM_ENCODING_VERSION`] payloads, which has already been checked.
+    Checksum,
+}
+
+impl Segment<'_> {
+    /// `Preamble`, `Checksum`, or the name of the message as used in permissions.
+    pub fn name(&self) -> &'static str {
+        match &self.content {
+            SegmentContent::Preamble { .. } => "Preamble",
+            SegmentContent::Message { tag, .. } => {
+                Message::u64_to_str(u64::from(*tag)).unwrap_or("UnknownMessage")
+            }
+            SegmentContent::Checksum => "Checksum",
+        }
+    }
+
+    /// What the segment decodes to, on a single line.
+    pub fn summary(&self) -> String {
+        let message = match &self.content {
+            SegmentContent::Preamble { tags } => return format!("tags {tags:?}"),
+            SegmentContent::Message { message, .. } => message,
+            SegmentContent::Checksum => return "valid".to_string(),
+        };
+        match message {
+            CompressedMessageRef::SetBlockNumbersForNextEpoch(block_numbers) => {
+                block_numbers_summary(block_numbers)
+            }
+            CompressedMessageRef::CorrectEpochs { data_by_network_id } => {
+                let indices: Vec<_> = data_by_network_id.keys().collect();
+                format!("networks {indices:?}")
+            }
+            CompressedMessageRef::RegisterNetworks { remove, add } => {
+                format!("remove {remove:?}, add {add:?}")
+            }
+            CompressedMessageRef::UpdateVersion { version_number } => {
+                format!("version {version_number}")
+            }
+            CompressedMessageRef::Reset => String::new(),
+            CompressedMessageRef::RegisterNetworksAndAliases { remove, add } => {
+                format!("remove {remove:?}, add {add:?}")
+            }
+            CompressedMessageRef::ChangePermissions {
+                address,
+                valid_through,
+                permissions,
+            } => format!(
+                "address 0x{}, valid through {valid_through}, permissions {permissions:?}",
+                hex::encode(address)
+            ),
+            CompressedMessageRef::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_numbers,
+            } => format!(
+                "remove {remove:?}, add {add:?}, {}",
+                block_numbers_summary(block_numbers)
+            ),
+        }
+    }
+}
+
+fn block_numbers_summary(block_numbers: &CompressedSetBlockNumbersForNextEpochRef) -> String {
+    match block_numbers {
+        CompressedSetBlockNumbersForNextEpochRef::Empty { count } => {
+            format!("{count} epochs without networks")
+        }
+        CompressedSetBlockNumbersForNextEpochRef::NonEmpty {
+            accelerations,
+            root,
+        } => format!(
+            "root 0x{}, accelerations {accelerations:?}",
+            hex::encode(root)
+        ),
+    }
+}
+
+impl fmt::Display for Segment<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} {}", self.range, self.name())?;
+        match self.summary() {
+            summary if summary.is_empty() => Ok(()),
+            summary => write!(f, ": {summary}"),
+        }
+    }
+}
+
+/// Decodes a payload like [`decode_message_refs`], into the segments that it's made of, in order.
+/// This tells exactly which bytes every message was decoded from.
+pub fn inspect(
+    bytes: &[u8],
+    network_count: u64,
+    encoding_version: u64,
+) -> Result<Vec<Segment<'_>>, DecodeError> {
+    let mut segments: Vec<Segment> = vec![];
+    let mut preamble = 0;
+    walk(bytes, network_count, encoding_version, |range, content| {
+        match &content {
+            SegmentContent::Preamble { .. } => preamble = segments.len(),
+            SegmentContent::Message { tag, .. } => {
+                if let SegmentContent::Preamble { tags } = &mut segments[preamble].content {
+                    tags.push(*tag);
+                }
+            }
+            SegmentContent::Checksum => {}
+        }
+        segments.push(Segment { range, content });
+    })?;
+    Ok(segments)
+}
+
+/// Decodes a payload, and passes every segment to `f` as soon as it's decoded. Preambles are
+/// passed without tags.
+fn walk<'a>(
+    bytes: &'a [u8],
+    network_count: u64,
+    encoding_version: u64,
+    mut f: impl FnMut(Range<usize>, SegmentContent<'a>),
+) -> Result<(), DecodeError> {
+    if !SUPPORTED_ENCODING_VERSIONS.contains(&encoding_version) {
+        return Err(DecodeError::UnsupportedEncodingVersion(encoding_version));
+    }
+    let payload = if encoding_version == CHECKSUM_ENCODING_VERSION {
+        strip_checksum(bytes)?
+    } else {
+        bytes
+    };
+    let mut decoder = Decoder {
+        bytes: payload,
+        offset: 0,
+        network_count,
+        encoding_version,
+    };
+    while !decoder.is_empty() {
+        let start = decoder.offset;
+        let preamble = decoder.advance(1)?[0];
+        f(
+            start..decoder.offset,
+            SegmentContent::Preamble { tags: vec![] },
+        );
+        for i in 0..PREAMBLE_CAPACITY {
+            // A preamble that isn't full is padded with zero tags, so trailing tags are only
+            // meaningful if there's something left to decode.
+            if decoder.is_empty() {
+                break;
+            }
+            let tag = (preamble >> (TAG_BIT_LENGTH * i)) & ((1 << TAG_BIT_LENGTH) - 1);
+            let start = decoder.offset;
+            let message = decoder.message(tag)?;
+            f(
+                start..decoder.offset,
+                SegmentContent::Message { tag, message },
+            );
+        }
+    }
+    if payload.len() < bytes.len() {
+        f(payload.len()..bytes.len(), SegmentContent::Checksum);
+    }
+    Ok(())
+}
+
+/// Checks the trailer of a [`CHECKSUM_ENCODING_VERSION`] payload, and returns the payload without it.
+fn strip_checksum(bytes: &[u8]) -> Result<&[u8], DecodeError> {
+    let len = bytes
+        .len()
+        .checked_sub(CHECKSUM_BYTES)
+        .ok_or(DecodeError::Truncated {
+            offset: 0,
+            expected: CHECKSUM_BYTES,
+            available: bytes.len(),
+        })?;
+    let (payload, trailer) = bytes.split_at(len);
+    let checksum = checksum(payload);
+    if trailer != checksum {
+        return Err(DecodeError::ChecksumMismatch {
+            // Unwrap: the slice has exactly the right length.
+            trailer: trailer.try_into().unwrap(),
+            checksum,
+        });
+    }
+    Ok(payload)
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    network_count: u64,
+    encoding_version: u64,
+}
+
+impl<'a> Decoder<'a> {
+    fn is_empty(&self) -> bool {
+        self.offset == self.bytes.len()
+    }
+
+    fn advance(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let remaining = &self.bytes[self.offset..];
+        let taken = remaining.get(..len).ok_or(DecodeError::Truncated {
+            offset: self.offset,
+            expected: len,
+            available: remaining.len(),
+        })?;
+        self.offset += len;
+        Ok(taken)
+    }
+
+    fn varint<T>(&mut self, decode: DecodeVarint<T>) -> Result<T, DecodeError> {
+        let (value, len) =
+            decode(&self.bytes[self.offset..]).map_err(|source| DecodeError::Varint {
+                offset: self.offset,
+                source,
+            })?;
+        self.offset += len;
+        Ok(value)
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.varint(decode_u64)
+    }
+
+    fn i64(&mut self) -> Result<i64, DecodeError> {
+        self.varint(decode_i64)
+    }
+
+    fn bytes32(&mut self) -> Result<&'a Bytes32, DecodeError> {
+        // Unwrap: `advance` returns exactly as many bytes as requested.
+        Ok(self.advance(32)?.try_into().unwrap())
+    }
+
+    fn str(&mut self) -> Result<&'a str, DecodeError> {
+        let len = self.u64()? as usize;
+        let offset = self.offset;
+        let bytes = self.advance(len)?;
+        core::str::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8 { offset })
+    }
+
+    fn message(&mut self, tag: u8) -> Result<CompressedMessageRef<'a>, DecodeError> {
+        let offset = self.offset;
+        Ok(match tag {
+            0 => CompressedMessageRef::SetBlockNumbersForNextEpoch(self.set_block_numbers()?),
+            1 => CompressedMessageRef::CorrectEpochs {
+                data_by_network_id: self.correct_epochs()?,
+            },
+            2 => CompressedMessageRef::UpdateVersion {
+                version_number: self.u64()?,
+            },
+            3 => {
+                let remove = self.removals()?;
+                let add = self.list(Self::str)?;
+                self.network_count = self.network_count.saturating_add(add.len() as u64);
+                CompressedMessageRef::RegisterNetworks { remove, add }
+            }
+            4 => {
+                // Unwrap: `advance` returns exactly as many bytes as requested.
+                let address = self.advance(20)?.try_into().unwrap();
+                let valid_through = self.u64()?;
+                let permissions = self.list(Self::u64)?;
+                CompressedMessageRef::ChangePermissions {
+                    address,
+                    valid_through,
+                    permissions,
+                }
+            }
+            5 => {
+                // The subgraph only needs some byte to be there.
+                self.advance(1)?;
+                // The subgraph also goes back to version 0, for the rest of the payload too.
+                self.network_count = 0;
+                self.encoding_version = 0;
+                CompressedMessageRef::Reset
+            }
+            6 => {
+                let remove = self.removals()?;
+                let add = self.list(|decoder| Ok((decoder.str()?, decoder.str()?)))?;
+                self.network_count = self.network_count.saturating_add(add.len() as u64);
+                CompressedMessageRef::RegisterNetworksAndAliases { remove, add }
+            }
+            7 => {
+                let remove = self.removals()?;
+                let add = self.list(Self::str)?;
+                self.network_count = self.network_count.saturating_add(add.len() as u64);
+                let block_numbers = self.set_block_numbers()?;
+                CompressedMessageRef::RegisterNetworksAndSetBlockNumbers {
+                    remove,
+                    add,
+                    block_numbers,
+                }
+            }
+            tag => return Err(DecodeError::InvalidTag { offset, tag }),
+        })
+    }
+
+    fn set_block_numbers(
+        &mut self,
+    ) -> Result<CompressedSetBlockNumbersForNextEpochRef<'a>, DecodeError> {
+        if self.network_count == 0 {
+            return Ok(CompressedSetBlockNumbersForNextEpochRef::Empty { count: self.u64()? });
+        }
+        let root = self.bytes32()?;
+        let mut accelerations: Vec<i64> = (0..self.network_count)
+            .map(|_| self.i64())
+            .collect::<Result<_, _>>()?;
+        if self.encoding_version == DELTA_ACCELERATIONS_ENCODING_VERSION {
+            for i in 1..accelerations.len() {
+                accelerations[i] = accelerations[i].wrapping_add(accelerations[i - 1]);
+            }
+        }
+        Ok(CompressedSetBlockNumbersForNextEpochRef::NonEmpty {
+            accelerations,
+            root,
+        })
+    }
+
+    fn correct_epochs(
+        &mut self,
+    ) -> Result<BTreeMap<NetworkIndex, EpochDetailsRef<'a>>, DecodeError> {
+        let corrections = self.list(|decoder| {
+            let index = decoder.u64()?;
+            let details = EpochDetailsRef {
+                tx_hash: decoder.bytes32()?,
+                merkle_root: decoder.bytes32()?,
+            };
+            Ok((index, details))
+        })?;
+        let mut data_by_network_id = BTreeMap::new();
+        for (index, details) in corrections {
+            if index >= self.network_count {
+                return Err(DecodeError::NetworkIndexOutOfBounds {
+                    index,
+                    network_count: self.network_count,
+                });
+            }
+            if data_by_network_id.insert(index, details).is_some() {
+                return Err(DecodeError::DuplicateNetworkIndex(index));
+            }
+        }
+        Ok(data_by_network_id)
+    }
+
+    /// Removals refer to indices from before the message, so they're checked against the network
+    /// count before any of them is applied.
+    fn removals(&mut self) -> Result<Vec<NetworkIndex>, DecodeError> {
+        let remove = self.list(Self::u64)?;
+        for (i, index) in remove.iter().enumerate() {
+            if remove[..i].contains(index) {
+                return Err(DecodeError::DuplicateNetworkIndex(*index));
+            }
+            if *index >= self.network_count {
+                return Err(DecodeError::NetworkIndexOutOfBounds {
+                    index: *index,
+                    network_count: self.network_count,
+                });
+            }
+        }
+        self.network_count -= remove.len() as u64;
+        Ok(remove)
+    }
+
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        let len = self.u64()?;
+        (0..len).map(|_| item(self)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize_messages;
+    use proptest::prelude::*;
+
+    fn round_trip(messages: Vec<CompressedMessage>, network_count: u64) {
+        let mut bytes = vec![];
+        serialize_messages(&messages, &mut bytes).unwrap();
+        assert_eq!(
+            decode_messages_with_networks(&bytes, network_count).unwrap(),
+            messages
+        );
+    }
+
+    #[test]
+    fn any_number_of_messages() {
+        let messages = [
+            CompressedMessage::Reset,
+            CompressedMessage::UpdateVersion { version_number: 0 },
+            CompressedMessage::SetBlockNumbersForNextEpoch(
+                CompressedSetBlockNumbersForNextEpoch::Empty { count: 1 },
+            ),
+        ];
+        for len in 0..8 {
+            round_trip(messages.iter().cycle().take(len).cloned().collect(), 0);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(
+            vec![
+                CompressedMessage::RegisterNetworks {
+                    remove: vec![],
+                    add: vec!["eip155:1".to_string(), "eip155:100".to_string()],
+                },
+                CompressedMessage::SetBlockNumbersForNextEpoch(
+                    CompressedSetBlockNumbersForNextEpoch::NonEmpty {
+                        accelerations: vec![15, -3],
+                        root: [7; 32],
+                    },
+                ),
+                CompressedMessage::ChangePermissions {
+                    address: [1; 20],
+                    valid_through: 123,
+                    permissions: vec![0, 3],
+                },
+            ],
+            0,
+        );
+        round_trip(
+            vec![
+                CompressedMessage::RegisterNetworksAndAliases {
+                    remove: vec![1],
+                    add: vec![(
+                        "bip122:000000000019d6689c085ae165831e93".into(),
+                        "btc".into(),
+                    )],
+                },
+                CompressedMessage::UpdateVersion { version_number: 0 },
+            ],
+            2,
+        );
+        round_trip(
+            vec![CompressedMessage::CorrectEpochs {
+                data_by_network_id: [
+                    (0, EpochDetails::new([1; 32], [2; 32])),
+                    (2, EpochDetails::new([3; 32], [4; 32])),
+                ]
+                .into_iter()
+                .collect(),
+            }],
+            3,
+        );
+        round_trip(
+            vec![
+                CompressedMessage::Reset,
+                CompressedMessage::SetBlockNumbersForNextEpoch(
+                    CompressedSetBlockNumbersForNextEpoch::Empty { count: 3 },
+                ),
+            ],
+            5,
+        );
+    }
+
+    proptest! {
+        /// The same property as the `decode_payload` fuzz target.
+        #[test]
+        fn arbitrary_payloads(network_count in 0..4u64, payload in any::<Vec<u8>>()) {
+            if let Ok(messages) = decode_messages_with_networks(&payload, network_count) {
+                let mut bytes = vec![];
+                if serialize_messages(&messages, &mut bytes).is_ok() {
+                    prop_assert_eq!(
+                        decode_messages_with_networks(&bytes, network_count).unwrap(),
+                        messages
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn message_refs_borrow_from_the_payload() {
+        let messages = vec![CompressedMessage::RegisterNetworksAndAliases {
+            remove: vec![],
+            add: vec![("eip155:1".into(), "mainnet".into())],
+        }];
+        let mut bytes = vec![];
+        serialize_messages(&messages, &mut bytes).unwrap();
+
+        let refs = decode_message_refs(&bytes, 0, 0).unwrap();
+        let [CompressedMessageRef::RegisterNetworksAndAliases { add, .. }] = &refs[..] else {
+            panic!("unexpected messages: {refs:?}");
+        };
+        assert!(bytes.as_ptr_range().contains(&add[0].0.as_ptr()));
+        assert_eq!(
+            refs.into_iter()
+                .map(CompressedMessage::from)
+                .collect::<Vec<_>>(),
+            messages
+        );
+    }
+
+    #[test]
+    fn segments() {
+        let messages = vec![
+            CompressedMessage::RegisterNetworks {
+                remove: vec![],
+                add: vec!["eip155:1".into()],
+            },
+            CompressedMessage::UpdateVersion { version_number: 1 },
+            CompressedMessage::Reset,
+        ];
+        let mut bytes = vec![];
+        serialize_messages(&messages, &mut bytes).unwrap();
+
+        let segments = inspect(&bytes, 0, 0).unwrap();
+        let lines: Vec<_> = segments.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            [
+                "0..1 Preamble: tags [3, 2]",
+                r#"1..12 RegisterNetworksMessage: remove [], add ["eip155:1"]"#,
+                "12..13 UpdateVersionMessage: version 1",
+                "13..14 Preamble: tags [5]",
+                "14..15 ResetStateMessage",
+            ]
+        );
+        assert_eq!(segments.last().unwrap().range.end, bytes.len());
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        assert_eq!(
+            decode_messages_with_networks(&[0x03, 0x05, 0x01, 0x01, 0x01], 2),
+            Err(DecodeError::DuplicateNetworkIndex(0))
+        );
+        // A single RegisterNetworks tag, with nothing after it.
+        assert!(matches!(
+            decode_messages(&[0x03, 0x03]),
+            Err(DecodeError::Varint { offset: 2, .. })
+        ));
+        assert_eq!(
+            decode_messages(&[0x0f, 0x01]),
+            Err(DecodeError::InvalidTag {
+                offset: 1,
+                tag: 0x0f
+            })
+        );
+        // Removes network 0 while there are none.
+        assert_eq!(
+            decode_messages(&[0x03, 0x03, 0x01, 0x01]),
+            Err(DecodeError::NetworkIndexOutOfBounds {
+                index: 0,
+                network_count: 0
+            })
+        );
+        // A string that claims to be longer than the whole payload.
+        assert!(matches!(
+            decode_messages(&[
+                0x03, 0x01, 0x03, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+            ]),
+            Err(DecodeError::Truncated { offset: 12, .. })
+        ));
+        // Lots of networks, but no accelerations for them.
+        assert!(matches!(
+            decode_messages_with_networks(&[0x00; 33], u64::MAX),
+            Err(DecodeError::Varint { offset: 33, .. })
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+//! Signed envelopes around payloads, so that payloads can be encoded and signed on an offline
+//! machine and then handed to a relayer that has no keys of its own.
+//!
+//! An envelope is a version byte ([`ENVELOPE_VERSION`]), a 65-byte recoverable secp256k1
+//! signature and then the payload itself. The signature is Ethereum's `r || s || v` with `v` being
+//! 27 or 28, over the [EIP-191] personal message hash of the payload (see [`signing_hash`]), so
+//! that the signer can also be recovered with `ecrecover` or any Ethereum wallet.
+//!
+//! [EIP-191]: https://eips.ethereum.org/EIPS/eip-191
+
+use alloc::{format, vec::Vec};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, PublicKey, Secp256k1};
+use tiny_keccak::{Hasher, Keccak};
+
+pub use secp256k1::SecretKey;
+
+/// The only envelope format so far.
+pub const ENVELOPE_VERSION: u8 = 0;
+
+const SIGNATURE_BYTES: usize = 65;
+
+/// Something that went wrong when reading an envelope.
+#[derive(Debug, thiserror::Error)]
+pub enum EnvelopeError {
+    #[error("An envelope of {0} bytes is too short to hold a signature")]
+    Truncated(usize),
+    #[error("Unsupported envelope version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Invalid signature")]
+    InvalidSignature,
+}
+
+/// A payload and the signature over it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedPayload {
+    pub payload: Vec<u8>,
+    /// `r || s || v`, with `v` being 27 or 28.
+    pub signature: [u8; SIGNATURE_BYTES],
+}
+
+impl SignedPayload {
+    /// Signs `payload` with `secret_key`.
+    pub fn sign(payload: Vec<u8>, secret_key: &SecretKey) -> Self {
+        let message = Message::from_slice(&signing_hash(&payload)).unwrap();
+        let (recovery_id, compact) = Secp256k1::signing_only()
+            .sign_ecdsa_recoverable(&message, secret_key)
+            .serialize_compact();
+        let mut signature = [0; SIGNATURE_BYTES];
+        signature[..64].copy_from_slice(&compact);
+        signature[64] = 27 + recovery_id.to_i32() as u8;
+        Self { payload, signature }
+    }
+
+    /// Reads an envelope, without checking its signature; see [`Self::recover_signer`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        match bytes {
+            [ENVELOPE_VERSION, rest @ ..] if rest.len() >= SIGNATURE_BYTES => {
+                let (signature, payload) = rest.split_at(SIGNATURE_BYTES);
+                Ok(Self {
+                    payload: payload.to_vec(),
+                    // Unwrap: the slice has exactly the right length.
+                    signature: signature.try_into().unwrap(),
+                })
+            }
+            [version, ..] if *version != ENVELOPE_VERSION => {
+                Err(EnvelopeError::UnsupportedVersion(*version))
+            }
+            _ => Err(EnvelopeError::Truncated(bytes.len())),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + SIGNATURE_BYTES + self.payload.len());
+        bytes.push(ENVELOPE_VERSION);
+        bytes.extend_from_slice(&self.signature);
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// The public key that signed the payload.
+    pub fn recover_public_key(&self) -> Result<PublicKey, EnvelopeError> {
+        let recovery_id = match self.signature[64] {
+            v @ (27 | 28) => RecoveryId::from_i32(i32::from(v - 27)),
+            _ => return Err(EnvelopeError::InvalidSignature),
+        };
+        let signature = recovery_id
+            .and_then(|id| RecoverableSignature::from_compact(&self.signature[..64], id))
+            .map_err(|_| EnvelopeError::InvalidSignature)?;
+        let message = Message::from_slice(&signing_hash(&self.payload)).unwrap();
+        Secp256k1::verification_only()
+            .recover_ecdsa(&message, &signature)
+            .map_err(|_| EnvelopeError::InvalidSignature)
+    }
+
+    /// The Ethereum address that signed the payload, e.g. to compare it against the owner of the
+    /// DataEdge contract before relaying the payload.
+    pub fn recover_signer(&self) -> Result<[u8; 20], EnvelopeError> {
+        self.recover_public_key().map(|key| address(&key))
+    }
+}
+
+/// The Ethereum address of `public_key`.
+pub fn address(public_key: &PublicKey) -> [u8; 20] {
+    let uncompressed = public_key.serialize_uncompressed();
+    let hash = keccak(&[&uncompressed[1..]]);
+    // Unwrap: the slice has exactly the right length.
+    hash[12..].try_into().unwrap()
+}
+
+/// The hash that envelopes sign: Keccak-256 of the EIP-191 prefix, the payload length in decimal
+/// and the payload.
+pub fn signing_hash(payload: &[u8]) -> [u8; 32] {
+    let len = format!("{}", payload.len());
+    keccak(&[b"\x19Ethereum Signed Message:\n", len.as_bytes(), payload])
+}
+
+fn keccak(chunks: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    for chunk in chunks {
+        hasher.update(chunk);
+    }
+    let mut hash = [0; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn secret_key() -> SecretKey {
+        SecretKey::from_slice(&[0x11; 32]).unwrap()
+    }
+
+    #[test]
+    fn sign_and_recover() {
+        let signed = SignedPayload::sign(vec![0x00, 0x07], &secret_key());
+        let envelope = signed.to_bytes();
+        assert_eq!(envelope[0], ENVELOPE_VERSION);
+
+        let read = SignedPayload::from_bytes(&envelope).unwrap();
+        assert_eq!(read, signed);
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key());
+        assert_eq!(read.recover_signer().unwrap(), address(&public_key));
+    }
+
+    #[test]
+    fn tampering_changes_the_signer() {
+        let signed = SignedPayload::sign(vec![0x00, 0x07], &secret_key());
+        let signer = signed.recover_signer().unwrap();
+
+        let mut tampered = signed.clone();
+        tampered.payload[1] = 0x08;
+        assert!(tampered.recover_signer().map_or(true, |s| s != signer));
+
+        let mut tampered = signed;
+        tampered.signature[64] = 0;
+        assert!(matches!(
+            tampered.recover_signer(),
+            Err(EnvelopeError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn known_address() {
+        // The well-known address of the private key 0x11..11.
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key());
+        assert_eq!(
+            hex::encode(address(&public_key)),
+            "19e7e376e7c213b7e7e7e46cc70a5dd086daff2a"
+        );
+    }
+
+    #[test]
+    fn malformed_envelopes() {
+        assert!(matches!(
+            SignedPayload::from_bytes(&[]),
+            Err(EnvelopeError::Truncated(0))
+        ));
+        assert!(matches!(
+            SignedPayload::from_bytes(&[ENVELOPE_VERSION; 10]),
+            Err(EnvelopeError::Truncated(10))
+        ));
+        assert!(matches!(
+            SignedPayload::from_bytes(&[1; 70]),
+            Err(EnvelopeError::UnsupportedVersion(1))
+        ));
+    }
+}
@@ -0,0 +1,336 @@
+//! A stable JSON representation of payloads, for printing human-readable dumps of them.
+//!
+//! A payload is a JSON array of its messages, in order. Every message is an object whose `message`
+//! field names its type, and whose other fields depend on that type:
+//!
+//! | `message`                     | Fields                                                             |
+//! |-------------------------------|--------------------------------------------------------------------|
+//! | `SetBlockNumbersForNextEpoch` | `count`, or `merkleRoot` and `accelerations` (one per network)     |
+//! | `CorrectEpochs`               | `corrections`, each with `networkIndex`, `txHash` and `merkleRoot` |
+//! | `RegisterNetworks`            | `remove` (network indices) and `add` (CAIP-2 IDs)                  |
+//! | `UpdateVersion`               | `versionNumber`                                                    |
+//! | `Reset`                       |                                                                    |
+//! | `RegisterNetworksAndAliases`  | `remove` (network indices) and `add` (pairs of CAIP-2 ID and alias) |
+//! | `ChangePermissions`           | `address`, `valid_through` and `permissions` (message names)       |
+//! | `RegisterNetworksAndSetBlockNumbers` | `remove`, `add` and `blockNumbers`, as in `RegisterNetworks` and `SetBlockNumbersForNextEpoch` |
+//!
+//! Hashes and addresses are `0x`-prefixed hex strings. This is also the format that the
+//! `block-oracle encode` command accepts, so dumps can be edited and encoded again.
+
+use crate::messages::*;
+use crate::{decode_messages_with_networks, serialize_messages, DecodeError, EncodeError};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Something that went wrong in [`to_json`] or [`from_json`].
+#[derive(Debug, thiserror::Error)]
+pub enum JsonError {
+    #[error("Invalid JSON payload: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    Encode(#[from] EncodeError),
+    /// The decoder accepts permissions for any tag, but only known messages have names.
+    #[error("Permission for unknown message tag {0}")]
+    UnknownPermission(u64),
+}
+
+/// Decodes a payload into its JSON representation. `network_count` is the number of networks that
+/// were registered before the payload.
+pub fn to_json(payload: &[u8], network_count: u64) -> Result<serde_json::Value, JsonError> {
+    compressed_to_json(decode_messages_with_networks(payload, network_count)?)
+}
+
+/// Like [`to_json`], for messages that are already decoded, e.g. in another encoding version.
+pub fn compressed_to_json(
+    messages: Vec<CompressedMessage>,
+) -> Result<serde_json::Value, JsonError> {
+    let messages = messages
+        .into_iter()
+        .map(JsonMessage::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(serde_json::to_value(messages)?)
+}
+
+/// The inverse of [`to_json`].
+pub fn from_json(json: serde_json::Value) -> Result<Vec<u8>, JsonError> {
+    let messages = Vec::<JsonMessage>::deserialize(json)?
+        .into_iter()
+        .map(CompressedMessage::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut bytes = vec![];
+    serialize_messages(&messages, &mut bytes)?;
+    Ok(bytes)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "message")]
+enum JsonMessage {
+    SetBlockNumbersForNextEpoch(JsonBlockNumbers),
+    CorrectEpochs {
+        corrections: Vec<JsonCorrection>,
+    },
+    RegisterNetworks {
+        remove: Vec<NetworkIndex>,
+        add: Vec<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    UpdateVersion {
+        version_number: u64,
+    },
+    Reset,
+    RegisterNetworksAndAliases {
+        remove: Vec<NetworkIndex>,
+        add: Vec<(String, String)>,
+    },
+    ChangePermissions {
+        #[serde(with = "prefixed_hex")]
+        address: [u8; 20],
+        valid_through: u64,
+        permissions: Vec<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    RegisterNetworksAndSetBlockNumbers {
+        remove: Vec<NetworkIndex>,
+        add: Vec<String>,
+        block_numbers: JsonBlockNumbers,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonBlockNumbers {
+    Empty {
+        count: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    NonEmpty {
+        #[serde(with = "prefixed_hex")]
+        merkle_root: Bytes32,
+        accelerations: Vec<i64>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonCorrection {
+    network_index: NetworkIndex,
+    #[serde(with = "prefixed_hex")]
+    tx_hash: Bytes32,
+    #[serde(with = "prefixed_hex")]
+    merkle_root: Bytes32,
+}
+
+impl From<CompressedSetBlockNumbersForNextEpoch> for JsonBlockNumbers {
+    fn from(block_numbers: CompressedSetBlockNumbersForNextEpoch) -> Self {
+        match block_numbers {
+            CompressedSetBlockNumbersForNextEpoch::Empty { count } => Self::Empty { count },
+            CompressedSetBlockNumbersForNextEpoch::NonEmpty {
+                accelerations,
+                root,
+            } => Self::NonEmpty {
+                merkle_root: root,
+                accelerations,
+            },
+        }
+    }
+}
+
+impl From<JsonBlockNumbers> for CompressedSetBlockNumbersForNextEpoch {
+    fn from(block_numbers: JsonBlockNumbers) -> Self {
+        match block_numbers {
+            JsonBlockNumbers::Empty { count } => Self::Empty { count },
+            JsonBlockNumbers::NonEmpty {
+                merkle_root,
+                accelerations,
+            } => Self::NonEmpty {
+                accelerations,
+                root: merkle_root,
+            },
+        }
+    }
+}
+
+impl TryFrom<CompressedMessage> for JsonMessage {
+    type Error = JsonError;
+
+    fn try_from(message: CompressedMessage) -> Result<Self, Self::Error> {
+        Ok(match message {
+            CompressedMessage::SetBlockNumbersForNextEpoch(block_numbers) => {
+                Self::SetBlockNumbersForNextEpoch(block_numbers.into())
+            }
+            CompressedMessage::CorrectEpochs { data_by_network_id } => Self::CorrectEpochs {
+                corrections: data_by_network_id
+                    .into_iter()
+                    .map(|(network_index, details)| JsonCorrection {
+                        network_index,
+                        tx_hash: details.tx_hash,
+                        merkle_root: details.merkle_root,
+                    })
+                    .collect(),
+            },
+            CompressedMessage::RegisterNetworks { remove, add } => {
+                Self::RegisterNetworks { remove, add }
+            }
+            CompressedMessage::UpdateVersion { version_number } => {
+                Self::UpdateVersion { version_number }
+            }
+            CompressedMessage::Reset => Self::Reset,
+            CompressedMessage::RegisterNetworksAndAliases { remove, add } => {
+                Self::RegisterNetworksAndAliases { remove, add }
+            }
+            CompressedMessage::ChangePermissions {
+                address,
+                valid_through,
+                permissions,
+            } => Self::ChangePermissions {
+                address,
+                valid_through,
+                permissions: permissions
+                    .into_iter()
+                    .map(|tag| {
+                        Message::u64_to_str(tag)
+                            .map(str::to_string)
+                            .ok_or(JsonError::UnknownPermission(tag))
+                    })
+                    .collect::<Result<_, _>>()?,
+            },
+            CompressedMessage::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_numbers,
+            } => Self::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_numbers: block_numbers.into(),
+            },
+        })
+    }
+}
+
+impl TryFrom<JsonMessage> for CompressedMessage {
+    type Error = EncodeError;
+
+    fn try_from(message: JsonMessage) -> Result<Self, Self::Error> {
+        Ok(match message {
+            JsonMessage::SetBlockNumbersForNextEpoch(block_numbers) => {
+                Self::SetBlockNumbersForNextEpoch(block_numbers.into())
+            }
+            JsonMessage::CorrectEpochs { corrections } => Self::CorrectEpochs {
+                data_by_network_id: corrections
+                    .into_iter()
+                    .map(|c| (c.network_index, EpochDetails::new(c.tx_hash, c.merkle_root)))
+                    .collect(),
+            },
+            JsonMessage::RegisterNetworks { remove, add } => Self::RegisterNetworks { remove, add },
+            JsonMessage::UpdateVersion { version_number } => Self::UpdateVersion { version_number },
+            JsonMessage::Reset => Self::Reset,
+            JsonMessage::RegisterNetworksAndAliases { remove, add } => {
+                Self::RegisterNetworksAndAliases { remove, add }
+            }
+            JsonMessage::ChangePermissions {
+                address,
+                valid_through,
+                permissions,
+            } => Self::ChangePermissions {
+                address,
+                valid_through,
+                permissions: permissions
+                    .into_iter()
+                    .map(|name| match Message::str_to_u64(&name) {
+                        UNKNOWN_MESSAGE_TAG => Err(EncodeError::UnsupportedMessage(name)),
+                        tag => Ok(tag),
+                    })
+                    .collect::<Result<_, _>>()?,
+            },
+            JsonMessage::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_numbers,
+            } => Self::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_numbers: block_numbers.into(),
+            },
+        })
+    }
+}
+
+/// Fixed-size byte arrays as `0x`-prefixed hex strings.
+mod prefixed_hex {
+    use super::*;
+
+    pub fn serialize<S, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&format!("0x{}", hex::encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        let mut bytes = [0; N];
+        hex::decode_to_slice(s.trim_start_matches("0x"), &mut bytes)
+            .map_err(serde::de::Error::custom)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn payloads_round_trip() {
+        let json = json!([
+            { "message": "RegisterNetworks", "remove": [], "add": ["eip155:1", "eip155:100"] },
+            {
+                "message": "SetBlockNumbersForNextEpoch",
+                "merkleRoot": format!("0x{}", "33".repeat(32)),
+                "accelerations": [10, -2]
+            },
+            {
+                "message": "CorrectEpochs",
+                "corrections": [{
+                    "networkIndex": 1,
+                    "txHash": format!("0x{}", "11".repeat(32)),
+                    "merkleRoot": format!("0x{}", "22".repeat(32))
+                }]
+            },
+            {
+                "message": "ChangePermissions",
+                "address": format!("0x{}", "44".repeat(20)),
+                "valid_through": 100,
+                "permissions": ["ResetStateMessage"]
+            },
+            { "message": "UpdateVersion", "versionNumber": 0 },
+        ]);
+        let payload = from_json(json.clone()).unwrap();
+        assert_eq!(to_json(&payload, 0).unwrap(), json);
+    }
+
+    #[test]
+    fn empty_block_numbers() {
+        let json = json!([{ "message": "SetBlockNumbersForNextEpoch", "count": 3 }]);
+        let payload = from_json(json.clone()).unwrap();
+        assert_eq!(to_json(&payload, 0).unwrap(), json);
+    }
+
+    #[test]
+    fn rejects_unknown_permissions() {
+        let json = json!([{
+            "message": "ChangePermissions",
+            "address": format!("0x{}", "44".repeat(20)),
+            "valid_through": 100,
+            "permissions": ["FooMessage"]
+        }]);
+        assert!(matches!(
+            from_json(json),
+            Err(JsonError::Encode(EncodeError::UnsupportedMessage(name))) if name == "FooMessage"
+        ));
+    }
+}
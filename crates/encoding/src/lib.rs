@@ -1,19 +1,84 @@
-mod merkle;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(any(test, feature = "proptest"))]
+mod arbitrary;
+mod batching;
+mod block_hash;
+mod builders;
+mod caip2;
+mod canonical;
+mod deserialize;
+#[cfg(any(test, feature = "signing"))]
+pub mod envelope;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod merkle;
 pub mod messages;
 mod serialize;
+pub mod test_vectors;
+pub mod varint;
 
-use merkle::{merkle_root, MerkleLeaf};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{cmp::Ordering, ops::RangeInclusive};
 use messages::*;
-use std::collections::BTreeMap;
 
-pub use messages::{BlockPtr, CompressedMessage, CompressedSetBlockNumbersForNextEpoch, Message};
+#[cfg(any(test, feature = "proptest"))]
+pub use arbitrary::{roundtrip, RoundtripError};
+pub use batching::{batch, Batch};
+pub use block_hash::{normalize_block_hash, HashEncoding, HASH_ENCODINGS};
+pub use builders::{RegisterNetworksBuilder, SetBlockNumbersBuilder};
+pub use caip2::Caip2ChainId;
+pub use canonical::canonicalize;
+pub use deserialize::{
+    decode_message_refs, decode_messages, decode_messages_with_networks,
+    decode_messages_with_version, inspect, DecodeError, Segment, SegmentContent,
+};
+pub use merkle::{merkle_root, MerkleLeaf};
+pub use messages::{
+    BlockPtr, CompressedMessage, CompressedMessageRef, CompressedSetBlockNumbersForNextEpoch,
+    Message,
+};
 pub use serialize::serialize_messages;
+use serialize::MessageWriter;
 
 pub const CURRENT_ENCODING_VERSION: u64 = 0;
 
+/// Like version 0, except that the accelerations of `SetBlockNumbersForNextEpoch` messages are
+/// written as differences from the previous network's acceleration. It's opt-in: the Epoch Subgraph
+/// only switches to it through an explicit `UpdateVersion` message, as the oracle only ever
+/// upgrades to [`CURRENT_ENCODING_VERSION`].
+///
+/// On simulated mainnet-like epochs (see `cargo xtask payload-sizes`), the accelerations of
+/// different networks are mostly unrelated, so their differences take about 13% more bytes. It
+/// only pays off when accelerations move together, e.g. after the epoch length changes.
+pub const DELTA_ACCELERATIONS_ENCODING_VERSION: u64 = 1;
+
+/// Like version 0, except that every payload ends with a trailer of [`CHECKSUM_BYTES`] bytes: the
+/// start of the Keccak-256 hash of the rest of the payload. The Epoch Subgraph rejects payloads
+/// whose trailer doesn't match, instead of decoding corrupted or truncated calldata into wrong
+/// block numbers. Like [`DELTA_ACCELERATIONS_ENCODING_VERSION`], it's opt-in, and the two can't be
+/// combined.
+///
+/// The payload with the `UpdateVersion` message that switches to this version doesn't have a
+/// trailer yet; the ones after it do.
+pub const CHECKSUM_ENCODING_VERSION: u64 = 2;
+
+/// The length of the trailer of [`CHECKSUM_ENCODING_VERSION`] payloads.
+pub const CHECKSUM_BYTES: usize = 4;
+
+/// The encoding versions that the [`Encoder`] can write, each in its own layout.
+pub const SUPPORTED_ENCODING_VERSIONS: RangeInclusive<u64> = 0..=CHECKSUM_ENCODING_VERSION;
+
 /// Something that went wrong when using the [`Encoder`].
 #[derive(Debug, thiserror::Error)]
-pub enum Error {
+pub enum EncodeError {
     #[error("Unsuported encoding version: {0}")]
     UnsupportedEncodingVersion(u64),
     #[error(
@@ -22,6 +87,53 @@ pub enum Error {
     MessageAfterEncodingVersionChange,
     #[error("Invalid Network ID: {0}")]
     InvalidNetworkId(String),
+    #[error("Network ID {0} appears more than once in the same message")]
+    DuplicateNetworkId(String),
+    #[error("Network index {0} appears more than once in the same message")]
+    DuplicateNetworkIndex(NetworkIndex),
+    #[error("Invalid block hash for {chain_id}: {hash}")]
+    InvalidBlockHash { chain_id: String, hash: String },
+    #[error("There's no registered network with index {0}")]
+    UnregisteredNetwork(NetworkIndex),
+    #[error("There's no registered network with ID {0}")]
+    UnregisteredNetworkId(String),
+    #[error(
+        "The acceleration of {network_id}'s block number, {acceleration}, doesn't fit in 64 bits"
+    )]
+    AccelerationOverflow {
+        network_id: String,
+        acceleration: i128,
+    },
+    #[error("The difference between {network_id}'s latest block numbers doesn't fit in 64 bits")]
+    DeltaOverflow { network_id: String },
+    #[error("Unsupported message: {0}")]
+    UnsupportedMessage(String),
+    #[error(
+        "A {message} message needs a payload of {size} bytes, but payloads can only have \
+         {max_bytes}"
+    )]
+    PayloadTooLarge {
+        message: &'static str,
+        size: usize,
+        max_bytes: usize,
+    },
+}
+
+/// What the [`Encoder`] does with block numbers whose acceleration doesn't fit in 64 bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum OverflowPolicy {
+    /// Fails with [`EncodeError::AccelerationOverflow`].
+    #[default]
+    Strict,
+    /// Saturates the acceleration, so the Epoch Subgraph records a different block number than
+    /// the one it was given, and the [`Encoder`] keeps track of that one instead. The merkle root
+    /// still commits to the given block.
+    Lossy,
 }
 
 #[derive(Clone, Default, Debug, PartialEq, Eq)]
@@ -39,6 +151,57 @@ impl Network {
             array_index,
         }
     }
+
+    /// The acceleration from the latest block number to `block_number`. It's computed in 128 bits,
+    /// where it can't overflow, so that it's up to the caller to check whether it fits in a
+    /// payload, rather than silently wrapping around, e.g. after a long downtime.
+    pub fn exact_acceleration(&self, block_number: u64) -> i128 {
+        let delta = i128::from(block_number) - i128::from(self.block_number);
+        delta - i128::from(self.block_delta)
+    }
+}
+
+/// A registered network, as the [`Encoder`] keeps track of it. See [`Encoder::network_table`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkTableEntry<'a> {
+    pub chain_id: &'a str,
+    /// The network's index in the Epoch Subgraph, which is what messages refer to it by.
+    pub index: NetworkIndex,
+    /// The latest block number, or 0 if the network has none yet.
+    pub block_number: u64,
+    /// The difference between the latest two block numbers.
+    pub block_delta: i64,
+}
+
+/// How large a payload that [`Encoder::encode_with_stats`] wrote is, compared to a naive
+/// encoding that writes every block number and hash as is, in 8 and 32 bytes, instead of an
+/// acceleration per network and a merkle root.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// The bytes of every message, including the preamble that it starts, if any. These add up to
+    /// [`CompressionStats::total_bytes`].
+    pub message_bytes: Vec<usize>,
+    pub total_bytes: usize,
+    pub naive_bytes: usize,
+}
+
+impl CompressionStats {
+    /// The payload's size relative to the naive encoding. Lower is better.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.naive_bytes == 0 {
+            return 1.0;
+        }
+        self.total_bytes as f64 / self.naive_bytes as f64
+    }
+}
+
+/// A payload of [`Encoder::split`].
+pub(crate) struct SplitPayload {
+    pub bytes: Vec<u8>,
+    /// The index of the first message after the payload.
+    pub end: usize,
+    /// How many networks were registered before the payload, which the subgraph decodes it with.
+    pub network_count: usize,
 }
 
 /// The [`Encoder`]'s job is to take in sequences of high-level [`Message`]s, compress them,
@@ -46,32 +209,71 @@ impl Network {
 ///
 /// # Panics
 ///
-/// The [`Encoder`] should never panic on malformed [`Message`]s, but rather return an [`Error`].
+/// The [`Encoder`] should never panic on malformed [`Message`]s, but rather return an
+/// [`EncodeError`].
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Encoder {
     networks: Vec<(String, Network)>,
     encoding_version: u64,
+    overflow_policy: OverflowPolicy,
     compressed: Vec<CompressedMessage>,
+    /// The encoding version at the start of the last [`Encoder::compress`] call, which is the one
+    /// [`Encoder::encode`] writes.
+    payload_version: u64,
+    /// The payload that [`Encoder::push_message`] is writing, if any.
+    writer: Option<MessageWriter>,
 }
 
 impl Encoder {
     /// Creates a new [`Encoder`] with the specificied initial state.
-    pub fn new(encoding_version: u64, networks: Vec<(String, Network)>) -> Result<Self, Error> {
-        if encoding_version != CURRENT_ENCODING_VERSION {
-            return Err(Error::UnsupportedEncodingVersion(encoding_version));
+    ///
+    /// `encoding_version` is the one the Epoch Subgraph currently decodes, and can be any of
+    /// [`SUPPORTED_ENCODING_VERSIONS`]. Payloads are written in its layout, until an
+    /// `UpdateVersion` message switches to another one for the following payloads.
+    pub fn new(
+        encoding_version: u64,
+        networks: Vec<(String, Network)>,
+    ) -> Result<Self, EncodeError> {
+        if !SUPPORTED_ENCODING_VERSIONS.contains(&encoding_version) {
+            return Err(EncodeError::UnsupportedEncodingVersion(encoding_version));
         }
 
         Ok(Self {
             encoding_version,
             networks,
+            overflow_policy: OverflowPolicy::default(),
             compressed: Vec::new(),
+            payload_version: encoding_version,
+            writer: None,
         })
     }
 
+    /// Defaults to [`OverflowPolicy::Strict`].
+    pub fn with_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
     pub fn network_deltas(&self) -> &[(String, Network)] {
         &self.networks
     }
 
+    /// Returns the registered networks in index order, as they are after the latest
+    /// [`Encoder::compress`] call. Unless the [`Encoder`] was created with a different state than
+    /// the Epoch Subgraph's, this is what the subgraph ends up with once it decodes the payload.
+    pub fn network_table(&self) -> Vec<NetworkTableEntry<'_>> {
+        self.networks
+            .iter()
+            .enumerate()
+            .map(|(i, (chain_id, network))| NetworkTableEntry {
+                chain_id,
+                index: i as NetworkIndex,
+                block_number: network.block_number,
+                block_delta: network.block_delta,
+            })
+            .collect()
+    }
+
     /// Gets the network's index from the ID, if the network exists.
     pub fn network_index(&self, network_id: &str) -> Option<NetworkIndex> {
         self.networks
@@ -81,49 +283,222 @@ impl Encoder {
             .map(|(i, _)| i as NetworkIndex)
     }
 
+    /// Builds a [`Message::RegisterNetworks`] that removes networks by their chain ID rather than
+    /// by index. The IDs are resolved against the networks that are registered right now, so the
+    /// message must be compressed before any other that adds or removes networks.
+    ///
+    /// Fails with [`EncodeError::UnregisteredNetworkId`] if a network to remove isn't registered,
+    /// or [`EncodeError::DuplicateNetworkId`] if it's being removed twice.
+    pub fn register_networks_message(
+        &self,
+        remove: &[String],
+        add: Vec<String>,
+    ) -> Result<Message, EncodeError> {
+        let mut indexes = Vec::with_capacity(remove.len());
+        for (i, id) in remove.iter().enumerate() {
+            if remove[..i].contains(id) {
+                return Err(EncodeError::DuplicateNetworkId(id.clone()));
+            }
+            let index = self
+                .network_index(id)
+                .ok_or_else(|| EncodeError::UnregisteredNetworkId(id.clone()))?;
+            indexes.push(index);
+        }
+        Ok(Message::RegisterNetworks {
+            remove: indexes,
+            add,
+        })
+    }
+
     /// Returns the latest encoding version used by this [`Encoder`].
     pub fn encoding_version(&self) -> u64 {
         self.encoding_version
     }
 
+    /// Returns the message that brings the encoding version up to [`CURRENT_ENCODING_VERSION`],
+    /// if it's older.
+    pub fn update_version_message(&self) -> Option<Message> {
+        match self.encoding_version.cmp(&CURRENT_ENCODING_VERSION) {
+            Ordering::Less => Some(Message::UpdateVersion {
+                version_number: CURRENT_ENCODING_VERSION,
+            }),
+            Ordering::Equal | Ordering::Greater => None,
+        }
+    }
+
     /// Compression is a stateful operation. After this call, the [`Encoder`] is
     /// ready to be used again and some of its internal state might have
     /// changed.
-    pub fn compress(&mut self, messages: &[Message]) -> Result<Vec<CompressedMessage>, Error> {
+    pub fn compress(
+        &mut self,
+        messages: &[Message],
+    ) -> Result<Vec<CompressedMessage>, EncodeError> {
+        self.payload_version = self.encoding_version;
         for m in messages {
             self.compress_message(m)?;
         }
-        Ok(std::mem::take(&mut self.compressed))
+        Ok(core::mem::take(&mut self.compressed))
     }
 
-    pub fn encode(&self, compressed: &[CompressedMessage]) -> Vec<u8> {
+    pub fn encode(&self, compressed: &[CompressedMessage]) -> Result<Vec<u8>, EncodeError> {
+        Ok(self.encode_with_stats(compressed)?.0)
+    }
+
+    /// Like [`Encoder::encode`], but also measures the payload.
+    pub fn encode_with_stats(
+        &self,
+        compressed: &[CompressedMessage],
+    ) -> Result<(Vec<u8>, CompressionStats), EncodeError> {
         let mut bytes = vec![];
-        serialize_messages(compressed, &mut bytes);
-        bytes
+        let mut stats = CompressionStats::default();
+        let mut writer = MessageWriter::new(self.payload_version);
+        for message in compressed {
+            let start = bytes.len();
+            writer.write(message, &mut bytes)?;
+            let size = bytes.len() - start;
+            stats.message_bytes.push(size);
+            stats.naive_bytes += match message.as_non_empty_block_numbers() {
+                Some((accelerations, _)) => {
+                    // The message on its own has a preamble of its own, which we don't count.
+                    let mut alone = vec![];
+                    MessageWriter::new(self.payload_version).write(message, &mut alone)?;
+                    size - (alone.len() - 1) + accelerations.len() * (8 + 32)
+                }
+                None => size,
+            };
+        }
+        writer.finish(&mut bytes);
+        stats.total_bytes = bytes.len();
+        Ok((bytes, stats))
+    }
+
+    /// Compresses `message` and writes it into `bytes`, which must be the same buffer for every
+    /// message of the payload. Once all messages are pushed, [`Encoder::finish`] completes the
+    /// payload. Until then, the length of `bytes` is the running payload size, except for the last
+    /// message, which is held back because the next one might get merged into it.
+    ///
+    /// This writes the same payload as [`Encoder::compress`] and [`Encoder::encode`] would for all
+    /// messages at once, without keeping them around. Mixing the two APIs in the same payload
+    /// isn't supported.
+    pub fn push_message(
+        &mut self,
+        message: &Message,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        let version = self.encoding_version;
+        self.compress_message(message)?;
+        let writer = self
+            .writer
+            .get_or_insert_with(|| MessageWriter::new(version));
+        let held_back = self.compressed.len().saturating_sub(1);
+        for compressed in self.compressed.drain(..held_back) {
+            writer.write(&compressed, bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the messages that [`Encoder::push_message`] held back, completing the payload. After
+    /// this call, the [`Encoder`] is ready to write another payload.
+    pub fn finish(&mut self, bytes: &mut Vec<u8>) -> Result<(), EncodeError> {
+        let Some(mut writer) = self.writer.take() else {
+            return Ok(());
+        };
+        for compressed in self.compressed.drain(..) {
+            writer.write(&compressed, bytes)?;
+        }
+        writer.finish(bytes);
+        Ok(())
+    }
+
+    /// Compresses and encodes `messages` into as few payloads as possible, each at most
+    /// `max_bytes` long, e.g. to respect calldata limits. Every payload decodes on its own, once
+    /// the ones before it have been decoded. An `UpdateVersion` message always ends a payload.
+    ///
+    /// Fails with [`EncodeError::PayloadTooLarge`] if a message doesn't fit in a payload by itself.
+    /// The [`Encoder`] is only updated with the messages of complete payloads.
+    pub fn split_into_payloads(
+        &mut self,
+        messages: &[Message],
+        max_bytes: usize,
+    ) -> Result<Vec<Vec<u8>>, EncodeError> {
+        let payloads = self.split(messages, max_bytes)?;
+        Ok(payloads.into_iter().map(|payload| payload.bytes).collect())
+    }
+
+    /// Like [`Encoder::split_into_payloads`], but also returns where each payload ends and how
+    /// many networks it's decoded with.
+    pub(crate) fn split(
+        &mut self,
+        messages: &[Message],
+        max_bytes: usize,
+    ) -> Result<Vec<SplitPayload>, EncodeError> {
+        let mut payloads = vec![];
+        let mut start = 0;
+        while start < messages.len() {
+            // Grow the payload one message at a time, until the next one doesn't fit. Encoding
+            // the whole payload every time keeps merged messages and preambles accurate.
+            let mut fitting = None;
+            for end in start + 1..=messages.len() {
+                let mut encoder = self.clone();
+                let payload = match encoder
+                    .compress(&messages[start..end])
+                    .and_then(|compressed| encoder.encode(&compressed))
+                {
+                    Err(EncodeError::MessageAfterEncodingVersionChange) if fitting.is_some() => {
+                        break
+                    }
+                    result => result?,
+                };
+                if payload.len() > max_bytes {
+                    if fitting.is_none() {
+                        return Err(EncodeError::PayloadTooLarge {
+                            message: messages[start].name(),
+                            size: payload.len(),
+                            max_bytes,
+                        });
+                    }
+                    break;
+                }
+                fitting = Some((encoder, payload, end));
+            }
+            // Unwrap: the first message either fits or returns early.
+            let (encoder, bytes, end) = fitting.unwrap();
+            payloads.push(SplitPayload {
+                bytes,
+                end,
+                network_count: self.networks.len(),
+            });
+            *self = encoder;
+            start = end;
+        }
+        Ok(payloads)
     }
 
-    fn compress_message(&mut self, message: &Message) -> Result<(), Error> {
+    fn compress_message(&mut self, message: &Message) -> Result<(), EncodeError> {
         // After updating the encoding version, no more messages can be encoded
         // in the same batch.
         if let Some(CompressedMessage::UpdateVersion { .. }) = self.compressed.last() {
-            return Err(Error::MessageAfterEncodingVersionChange);
+            return Err(EncodeError::MessageAfterEncodingVersionChange);
         }
 
         match message {
             Message::SetBlockNumbersForNextEpoch(block_ptrs) => {
-                // There are separate cases for empty sets and non-empty sets.
-                if block_ptrs.is_empty() {
+                // Once there are networks, the subgraph expects an acceleration for each of them,
+                // even if none of them got a new block.
+                if self.networks.is_empty() {
                     self.compress_empty_block_ptrs();
                 } else {
-                    self.compress_block_ptrs(block_ptrs.clone())?;
+                    let block_numbers = self.compress_block_ptrs(block_ptrs.clone())?;
+                    self.compressed
+                        .push(CompressedMessage::SetBlockNumbersForNextEpoch(
+                            block_numbers,
+                        ));
                 }
             }
             Message::RegisterNetworks { remove, add } => {
-                for index in remove {
-                    self.remove_network(*index);
-                }
+                self.remove_networks(remove)?;
                 for id in add {
-                    self.add_network(id);
+                    self.add_network(id)?;
                 }
 
                 self.compressed.push(CompressedMessage::RegisterNetworks {
@@ -132,13 +507,22 @@ impl Encoder {
                 });
             }
             Message::CorrectEpochs { data_by_network_id } => {
+                if let Some(index) = data_by_network_id
+                    .keys()
+                    .find(|index| **index >= self.networks.len() as NetworkIndex)
+                {
+                    return Err(EncodeError::UnregisteredNetwork(*index));
+                }
                 self.compressed.push(CompressedMessage::CorrectEpochs {
                     data_by_network_id: data_by_network_id.clone(),
                 });
             }
             Message::UpdateVersion { version_number } => {
-                if *version_number != CURRENT_ENCODING_VERSION {
-                    return Err(Error::UnsupportedEncodingVersion(*version_number));
+                // Downgrades aren't allowed, and neither are versions we can't encode.
+                if *version_number < self.encoding_version
+                    || !SUPPORTED_ENCODING_VERSIONS.contains(version_number)
+                {
+                    return Err(EncodeError::UnsupportedEncodingVersion(*version_number));
                 }
 
                 self.encoding_version = *version_number;
@@ -147,15 +531,15 @@ impl Encoder {
                 });
             }
             Message::Reset => {
+                // Like the subgraph's `resetGlobalState`, which also goes back to version 0.
                 self.networks.clear();
+                self.encoding_version = 0;
                 self.compressed.push(CompressedMessage::Reset);
             }
             Message::RegisterNetworksAndAliases { remove, add } => {
-                for index in remove {
-                    self.remove_network(*index);
-                }
+                self.remove_networks(remove)?;
                 for (id, _) in add {
-                    self.add_network(id);
+                    self.add_network(id)?;
                 }
 
                 self.compressed
@@ -169,35 +553,87 @@ impl Encoder {
                 valid_through,
                 permissions,
             } => {
+                let permissions = permissions
+                    .iter()
+                    .map(|name| match Message::str_to_u64(name) {
+                        UNKNOWN_MESSAGE_TAG => Err(EncodeError::UnsupportedMessage(name.clone())),
+                        tag => Ok(tag),
+                    })
+                    .collect::<Result<_, _>>()?;
                 self.compressed.push(CompressedMessage::ChangePermissions {
                     address: *address,
                     valid_through: *valid_through,
-                    permissions: permissions
-                        .iter()
-                        .map(|x| Message::str_to_u64(x.as_str()))
-                        .collect(),
+                    permissions,
                 });
             }
+            Message::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_ptrs,
+            } => {
+                self.remove_networks(remove)?;
+                for id in add {
+                    self.add_network(id)?;
+                }
+                // Block numbers can't be set without networks, so leave them out and only
+                // register the networks.
+                if self.networks.is_empty() {
+                    self.compressed.push(CompressedMessage::RegisterNetworks {
+                        remove: remove.clone(),
+                        add: add.clone(),
+                    });
+                } else {
+                    let block_numbers = self.compress_block_ptrs(block_ptrs.clone())?;
+                    self.compressed
+                        .push(CompressedMessage::RegisterNetworksAndSetBlockNumbers {
+                            remove: remove.clone(),
+                            add: add.clone(),
+                            block_numbers,
+                        });
+                }
+            }
         };
         Ok(())
     }
 
-    fn add_network(&mut self, id: &str) {
-        self.networks.push((id.to_string(), Network::default()));
+    fn add_network(&mut self, id: &str) -> Result<(), EncodeError> {
+        if self.network_index(id).is_some() {
+            return Err(EncodeError::DuplicateNetworkId(id.to_string()));
+        }
+        let array_index = self.networks.len() as NetworkIndex;
+        self.networks
+            .push((id.to_string(), Network::new(0, 0, array_index)));
+        Ok(())
+    }
+
+    /// Removals all refer to indices from before the message, so none of them can repeat.
+    fn remove_networks(&mut self, remove: &[NetworkIndex]) -> Result<(), EncodeError> {
+        for (i, index) in remove.iter().enumerate() {
+            if remove[..i].contains(index) {
+                return Err(EncodeError::DuplicateNetworkIndex(*index));
+            }
+        }
+        for index in remove {
+            self.remove_network(*index)?;
+        }
+        // Like the subgraph, the remaining networks keep their order.
+        for (i, (_, network)) in self.networks.iter_mut().enumerate() {
+            network.array_index = i as NetworkIndex;
+        }
+        Ok(())
     }
 
     /// Remove a network from [`Encoder.networks`].
     ///
     /// Removal occurs by position, based on the `array_index` field of the target element.
-    fn remove_network(&mut self, network_index: NetworkIndex) {
+    fn remove_network(&mut self, network_index: NetworkIndex) -> Result<(), EncodeError> {
         let position = self
             .networks
             .iter()
             .position(|(_, network)| network.array_index == network_index)
-            .unwrap_or_else(|| {
-                panic!("Failed to find the a network with array_index equal to {network_index}")
-            });
+            .ok_or(EncodeError::UnregisteredNetwork(network_index))?;
         self.networks.remove(position);
+        Ok(())
     }
 
     /// Takes in some network data by network ID and turns it into a [`Vec`] with the correct
@@ -205,7 +641,7 @@ impl Encoder {
     fn sort_network_data_by_index<T>(
         &self,
         chain_data: &BTreeMap<String, T>,
-    ) -> Result<Vec<T>, Error>
+    ) -> Result<Vec<T>, EncodeError>
     where
         T: Clone,
     {
@@ -214,13 +650,13 @@ impl Encoder {
             .map(|(id, data)| {
                 Ok((
                     self.network_index(id)
-                        .ok_or_else(|| Error::InvalidNetworkId(id.to_string()))?,
+                        .ok_or_else(|| EncodeError::InvalidNetworkId(id.to_string()))?,
                     data.clone(),
                 ))
             })
-            .collect::<Result<Vec<(NetworkIndex, T)>, Error>>()?;
+            .collect::<Result<Vec<(NetworkIndex, T)>, EncodeError>>()?;
         // Sort by network index.
-        sorted.sort_by(|(i, _), (j, _)| i.cmp(j));
+        sorted.sort_by_key(|(i, _)| *i);
         // Now remove the network index, which is implied by element positioning within the vector.
         Ok(sorted.into_iter().map(|(_, x)| x).collect())
     }
@@ -228,7 +664,7 @@ impl Encoder {
     fn compress_block_ptrs(
         &mut self,
         mut block_ptrs: BTreeMap<String, BlockPtr>,
-    ) -> Result<(), Error> {
+    ) -> Result<CompressedSetBlockNumbersForNextEpoch, EncodeError> {
         for network in &self.networks {
             if !block_ptrs.contains_key(&network.0) {
                 block_ptrs.insert(
@@ -246,13 +682,34 @@ impl Encoder {
         let sorted_block_ptrs = self.sort_network_data_by_index(&block_ptrs)?;
 
         for (i, ptr) in sorted_block_ptrs.into_iter().enumerate() {
-            let network_data = &self.networks[i].1;
+            let (network_id, network_data) = &self.networks[i];
 
-            let delta = ptr.number as i64 - network_data.block_number as i64;
-            let acceleration = delta - network_data.block_delta;
+            let exact_acceleration = network_data.exact_acceleration(ptr.number);
+            let acceleration = match (i64::try_from(exact_acceleration), self.overflow_policy) {
+                (Ok(acceleration), _) => acceleration,
+                (Err(_), OverflowPolicy::Strict) => {
+                    return Err(EncodeError::AccelerationOverflow {
+                        network_id: network_id.clone(),
+                        acceleration: exact_acceleration,
+                    })
+                }
+                (Err(_), OverflowPolicy::Lossy) => {
+                    exact_acceleration.clamp(i64::MIN.into(), i64::MAX.into()) as i64
+                }
+            };
+            // What the subgraph computes out of the acceleration, which must fit in what it
+            // decodes the next payload with.
+            let delta = i128::from(network_data.block_delta) + i128::from(acceleration);
+            let block_number = i128::from(network_data.block_number) + delta;
+            let (Ok(delta), Ok(block_number)) = (i64::try_from(delta), u64::try_from(block_number))
+            else {
+                return Err(EncodeError::DeltaOverflow {
+                    network_id: network_id.clone(),
+                });
+            };
 
             let current_network = &mut self.networks[i].1;
-            current_network.block_number = ptr.number;
+            current_network.block_number = block_number;
             current_network.block_delta = delta;
 
             accelerations.push(acceleration);
@@ -263,15 +720,10 @@ impl Encoder {
             });
         }
 
-        self.compressed
-            .push(CompressedMessage::SetBlockNumbersForNextEpoch(
-                CompressedSetBlockNumbersForNextEpoch::NonEmpty {
-                    accelerations,
-                    root: merkle_root(&merkle_leaves),
-                },
-            ));
-
-        Ok(())
+        Ok(CompressedSetBlockNumbersForNextEpoch::NonEmpty {
+            accelerations,
+            root: merkle_root(&merkle_leaves),
+        })
     }
 
     fn compress_empty_block_ptrs(&mut self) {
@@ -347,6 +799,29 @@ mod tests {
         assert_eq!(accelerations, [1, 150]);
     }
 
+    #[test]
+    fn no_new_blocks_with_networks() {
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+        let compressed = encoder
+            .compress(&[
+                Message::RegisterNetworks {
+                    remove: vec![],
+                    add: vec!["A:1".to_string()],
+                },
+                Message::SetBlockNumbersForNextEpoch(BTreeMap::new()),
+            ])
+            .unwrap();
+
+        // The subgraph reads an acceleration for every registered network.
+        let accelerations = compressed
+            .last()
+            .unwrap()
+            .as_non_empty_block_numbers()
+            .unwrap()
+            .0;
+        assert_eq!(accelerations, [0]);
+    }
+
     #[test]
     fn pipeline() {
         let mut messages = Vec::new();
@@ -428,6 +903,50 @@ mod tests {
         assert_ne!(networks_before, networks_after);
     }
 
+    #[test]
+    fn register_networks_keeps_array_indices_in_step() {
+        let networks = ["A:1", "B:2", "C:3"]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.to_string(), Network::new(0, 0, i as u64)))
+            .collect();
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, networks).unwrap();
+        let array_indices = |encoder: &Encoder| {
+            encoder
+                .networks
+                .iter()
+                .map(|(id, network)| (id.clone(), network.array_index))
+                .collect::<Vec<_>>()
+        };
+
+        encoder
+            .compress(&[Message::RegisterNetworks {
+                remove: vec![0],
+                add: vec!["D:4".to_string()],
+            }])
+            .unwrap();
+        assert_eq!(
+            array_indices(&encoder),
+            [
+                ("B:2".to_string(), 0),
+                ("C:3".to_string(), 1),
+                ("D:4".to_string(), 2)
+            ]
+        );
+
+        // Later removals refer to the new indices.
+        encoder
+            .compress(&[Message::RegisterNetworks {
+                remove: vec![2],
+                add: vec![],
+            }])
+            .unwrap();
+        assert_eq!(
+            array_indices(&encoder),
+            [("B:2".to_string(), 0), ("C:3".to_string(), 1)]
+        );
+    }
+
     #[test]
     fn set_block_numbers_changes_state() {
         let mut encoder = Encoder::new(
@@ -495,4 +1014,550 @@ mod tests {
             _ => panic!("Expected ChangePermissions message"),
         }
     }
+
+    #[test]
+    fn reset_clears_networks() {
+        let mut encoder = Encoder::new(
+            CURRENT_ENCODING_VERSION,
+            vec![("foo:bar".to_string(), Network::new(42, 1, 0))],
+        )
+        .unwrap();
+        let compressed = encoder
+            .compress(&[
+                Message::Reset,
+                Message::SetBlockNumbersForNextEpoch(BTreeMap::new()),
+            ])
+            .unwrap();
+        assert!(encoder.network_deltas().is_empty());
+
+        // Block numbers after a reset are for no networks at all.
+        let encoded = encoder.encode(&compressed).unwrap();
+        assert_eq!(encoded, [0x05, 0x01, 0x03]);
+        assert_eq!(
+            decode_messages_with_networks(&encoded, 1).unwrap(),
+            compressed
+        );
+    }
+
+    #[test]
+    fn errors_instead_of_panics() {
+        let mut encoder = Encoder::new(
+            CURRENT_ENCODING_VERSION,
+            vec![("foo:bar".to_string(), Network::new(42, 0, 0))],
+        )
+        .unwrap();
+        assert!(matches!(
+            encoder.compress(&[Message::RegisterNetworks {
+                remove: vec![1],
+                add: vec![],
+            }]),
+            Err(EncodeError::UnregisteredNetwork(1))
+        ));
+        assert!(matches!(
+            encoder.clone().compress(&[Message::SetBlockNumbersForNextEpoch(
+                [("foo:bar".to_string(), BlockPtr::new(u64::MAX, [0; 32]))].into()
+            )]),
+            Err(EncodeError::AccelerationOverflow { network_id, .. }) if network_id == "foo:bar"
+        ));
+        assert!(matches!(
+            encoder.clone().compress(&[Message::ChangePermissions {
+                address: [1; 20],
+                valid_through: 0,
+                permissions: vec!["FooMessage".to_string()],
+            }]),
+            Err(EncodeError::UnsupportedMessage(name)) if name == "FooMessage"
+        ));
+        assert!(matches!(
+            encoder.encode(&[CompressedMessage::ChangePermissions {
+                address: [1; 20],
+                valid_through: 0,
+                permissions: vec![UNKNOWN_MESSAGE_TAG],
+            }]),
+            Err(EncodeError::UnsupportedMessage(_))
+        ));
+    }
+
+    #[test]
+    fn update_version() {
+        for version in SUPPORTED_ENCODING_VERSIONS {
+            assert_eq!(
+                Encoder::new(version, vec![]).unwrap().encoding_version(),
+                version
+            );
+        }
+        assert!(matches!(
+            Encoder::new(SUPPORTED_ENCODING_VERSIONS.end() + 1, vec![]),
+            Err(EncodeError::UnsupportedEncodingVersion(_))
+        ));
+
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+        assert!(encoder.update_version_message().is_none());
+        assert!(matches!(
+            encoder.compress(&[Message::UpdateVersion {
+                version_number: SUPPORTED_ENCODING_VERSIONS.end() + 1
+            }]),
+            Err(EncodeError::UnsupportedEncodingVersion(_))
+        ));
+
+        let messages = [
+            Message::SetBlockNumbersForNextEpoch(BTreeMap::new()),
+            Message::UpdateVersion {
+                version_number: CURRENT_ENCODING_VERSION,
+            },
+        ];
+        let compressed = encoder.compress(&messages).unwrap();
+        let encoded = encoder.encode(&compressed).unwrap();
+        assert_eq!(decode_messages(&encoded).unwrap(), compressed);
+
+        // Nothing can follow a version change in the same batch.
+        assert!(matches!(
+            encoder.compress(&[messages[1].clone(), messages[0].clone()]),
+            Err(EncodeError::MessageAfterEncodingVersionChange)
+        ));
+    }
+
+    #[test]
+    fn push_messages() {
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+        let empty = Message::SetBlockNumbersForNextEpoch(BTreeMap::new());
+        let mut bytes = vec![];
+        encoder.push_message(&empty, &mut bytes).unwrap();
+        encoder.push_message(&empty, &mut bytes).unwrap();
+        // Both are merged into one message, which is only written once the payload is finished.
+        assert!(bytes.is_empty());
+        encoder.finish(&mut bytes).unwrap();
+        assert_eq!(
+            decode_messages(&bytes).unwrap(),
+            vec![CompressedMessage::SetBlockNumbersForNextEpoch(
+                CompressedSetBlockNumbersForNextEpoch::Empty { count: 2 }
+            )]
+        );
+
+        // Finishing starts a new payload, with its own preamble.
+        let mut bytes = vec![];
+        encoder.push_message(&Message::Reset, &mut bytes).unwrap();
+        encoder.push_message(&empty, &mut bytes).unwrap();
+        encoder.finish(&mut bytes).unwrap();
+        assert_eq!(bytes[0], 0x05);
+        assert_eq!(decode_messages(&bytes).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn split_into_payloads() {
+        let register = |id: &str| Message::RegisterNetworks {
+            remove: vec![],
+            add: vec![id.to_string()],
+        };
+        let messages = [
+            register("eip155:1"),
+            register("eip155:100"),
+            register("eip155:42161"),
+            Message::UpdateVersion {
+                version_number: CURRENT_ENCODING_VERSION,
+            },
+            Message::Reset,
+        ];
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+        let payloads = encoder.split_into_payloads(&messages, 30).unwrap();
+        assert_eq!(encoder.network_deltas().len(), 0);
+
+        // The first two registrations fit together, and nothing can follow `UpdateVersion`.
+        let decoded: Vec<Vec<CompressedMessage>> = payloads
+            .iter()
+            .zip([0, 2, 3])
+            .map(|(payload, network_count)| {
+                assert!(payload.len() <= 30);
+                decode_messages_with_networks(payload, network_count).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            decoded.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert_eq!(
+            decoded.concat(),
+            Encoder::new(CURRENT_ENCODING_VERSION, vec![])
+                .unwrap()
+                .compress(&messages[..4])
+                .unwrap()
+                .into_iter()
+                .chain([CompressedMessage::Reset])
+                .collect::<Vec<_>>()
+        );
+
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+        assert!(matches!(
+            encoder.split_into_payloads(&messages, 10),
+            Err(EncodeError::PayloadTooLarge {
+                message: "RegisterNetworks",
+                max_bytes: 10,
+                ..
+            })
+        ));
+        assert!(encoder.network_deltas().is_empty());
+    }
+
+    #[test]
+    fn compression_stats() {
+        let networks = vec![
+            ("A:1".to_string(), Network::new(100, 10, 0)),
+            ("B:2".to_string(), Network::new(200, 10, 1)),
+        ];
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, networks).unwrap();
+        let compressed = encoder
+            .compress(&[
+                Message::SetBlockNumbersForNextEpoch(
+                    [("A:1".to_string(), BlockPtr::new(115, [1; 32]))]
+                        .into_iter()
+                        .collect(),
+                ),
+                Message::Reset,
+            ])
+            .unwrap();
+        let (payload, stats) = encoder.encode_with_stats(&compressed).unwrap();
+
+        assert_eq!(payload, encoder.encode(&compressed).unwrap());
+        // A preamble, the root and two single-byte accelerations, then a zero.
+        assert_eq!(stats.message_bytes, [1 + 32 + 2, 1]);
+        assert_eq!(stats.total_bytes, payload.len());
+        assert_eq!(stats.naive_bytes, 1 + 2 * 40 + 1);
+        assert!(stats.compression_ratio() < 0.5);
+    }
+
+    #[test]
+    fn acceleration_overflow() {
+        let networks = vec![
+            ("A:1".to_string(), Network::new(10, 0, 0)),
+            ("B:2".to_string(), Network::new(u64::MAX - 1, 0, 1)),
+        ];
+        let message = Message::SetBlockNumbersForNextEpoch(
+            [("A:1".to_string(), BlockPtr::new(u64::MAX, [1; 32]))]
+                .into_iter()
+                .collect(),
+        );
+
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, networks.clone()).unwrap();
+        assert!(matches!(
+            encoder.compress(std::slice::from_ref(&message)),
+            Err(EncodeError::AccelerationOverflow { network_id, acceleration })
+                if network_id == "A:1" && acceleration == i128::from(u64::MAX - 10)
+        ));
+
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, networks)
+            .unwrap()
+            .with_overflow_policy(OverflowPolicy::Lossy);
+        let compressed = encoder.compress(&[message]).unwrap();
+        assert_eq!(
+            compressed[0].as_non_empty_block_numbers().unwrap().0,
+            [i64::MAX, 0]
+        );
+        assert_eq!(
+            encoder.network_deltas()[0].1,
+            Network::new(10 + i64::MAX as u64, i64::MAX, 0)
+        );
+    }
+
+    #[test]
+    fn wide_accelerations() {
+        let network = Network::new(u64::MAX, i64::MAX, 0);
+        assert_eq!(
+            network.exact_acceleration(0),
+            -i128::from(u64::MAX) - i128::from(i64::MAX)
+        );
+
+        // The acceleration fits, but the delta the subgraph would compute doesn't.
+        let mut encoder = Encoder::new(
+            CURRENT_ENCODING_VERSION,
+            vec![("A:1".to_string(), Network::new(0, 10, 0))],
+        )
+        .unwrap();
+        assert!(matches!(
+            encoder.compress(&[Message::SetBlockNumbersForNextEpoch(
+                [("A:1".to_string(), BlockPtr::new(i64::MAX as u64 + 1, [0; 32]))].into()
+            )]),
+            Err(EncodeError::DeltaOverflow { network_id }) if network_id == "A:1"
+        ));
+    }
+
+    #[test]
+    fn network_table() {
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![]).unwrap();
+        encoder
+            .compress(&[
+                Message::RegisterNetworks {
+                    remove: vec![],
+                    add: vec!["A:1".to_string(), "B:2".to_string(), "C:3".to_string()],
+                },
+                Message::SetBlockNumbersForNextEpoch(
+                    [("C:3".to_string(), BlockPtr::new(30, [3; 32]))]
+                        .into_iter()
+                        .collect(),
+                ),
+                Message::RegisterNetworks {
+                    remove: vec![0],
+                    add: vec![],
+                },
+            ])
+            .unwrap();
+        assert_eq!(
+            encoder.network_table(),
+            [
+                NetworkTableEntry {
+                    chain_id: "B:2",
+                    index: 0,
+                    block_number: 0,
+                    block_delta: 0,
+                },
+                NetworkTableEntry {
+                    chain_id: "C:3",
+                    index: 1,
+                    block_number: 30,
+                    block_delta: 30,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn register_networks_by_id() {
+        let networks = vec![
+            ("A:1".to_string(), Network::new(100, 10, 0)),
+            ("B:2".to_string(), Network::new(200, 10, 1)),
+            ("C:3".to_string(), Network::new(300, 10, 2)),
+        ];
+        let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, networks).unwrap();
+
+        let message = encoder
+            .register_networks_message(
+                &["C:3".to_string(), "A:1".to_string()],
+                vec!["D:4".to_string()],
+            )
+            .unwrap();
+        assert!(matches!(
+            &message,
+            Message::RegisterNetworks { remove, add } if remove == &[2, 0] && add == &["D:4"]
+        ));
+        encoder.compress(&[message]).unwrap();
+        let ids: Vec<_> = encoder.network_deltas().iter().map(|(id, _)| id).collect();
+        assert_eq!(ids, ["B:2", "D:4"]);
+
+        assert!(matches!(
+            encoder.register_networks_message(&["A:1".to_string()], vec![]),
+            Err(EncodeError::UnregisteredNetworkId(id)) if id == "A:1"
+        ));
+        assert!(matches!(
+            encoder.register_networks_message(&["B:2".to_string(), "B:2".to_string()], vec![]),
+            Err(EncodeError::DuplicateNetworkId(id)) if id == "B:2"
+        ));
+    }
+
+    #[test]
+    fn delta_accelerations() {
+        let networks = vec![
+            ("A:1".to_string(), Network::new(100, 10, 0)),
+            ("B:2".to_string(), Network::new(200, 10, 1)),
+            ("C:3".to_string(), Network::new(300, 10, 2)),
+        ];
+        let message = Message::SetBlockNumbersForNextEpoch(
+            [
+                ("A:1".to_string(), BlockPtr::new(115, [1; 32])),
+                ("B:2".to_string(), BlockPtr::new(216, [2; 32])),
+                ("C:3".to_string(), BlockPtr::new(300, [3; 32])),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let encode = |version| {
+            let mut encoder = Encoder::new(version, networks.clone()).unwrap();
+            let compressed = encoder.compress(std::slice::from_ref(&message)).unwrap();
+            (compressed.clone(), encoder.encode(&compressed).unwrap())
+        };
+
+        let (compressed, payload) = encode(DELTA_ACCELERATIONS_ENCODING_VERSION);
+        assert_eq!(
+            compressed[0].as_non_empty_block_numbers().unwrap().0,
+            [5, 6, -10]
+        );
+        assert_eq!(
+            decode_messages_with_version(&payload, 3, DELTA_ACCELERATIONS_ENCODING_VERSION)
+                .unwrap(),
+            compressed
+        );
+        // The same payload in version 0 would carry the differences instead.
+        assert_eq!(
+            decode_messages_with_networks(&payload, 3).unwrap()[0]
+                .as_non_empty_block_numbers()
+                .unwrap()
+                .0,
+            [5, 1, -16]
+        );
+        assert_eq!(encode(0).0, compressed);
+    }
+
+    #[test]
+    fn reset_goes_back_to_version_0() {
+        let networks = vec![("A:1".to_string(), Network::new(100, 10, 0))];
+        let messages = [
+            Message::Reset,
+            Message::RegisterNetworks {
+                remove: vec![],
+                add: vec!["B:2".to_string(), "C:3".to_string()],
+            },
+            Message::SetBlockNumbersForNextEpoch(
+                [
+                    ("B:2".to_string(), BlockPtr::new(100, [2; 32])),
+                    ("C:3".to_string(), BlockPtr::new(300, [3; 32])),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        ];
+        let mut encoder = Encoder::new(DELTA_ACCELERATIONS_ENCODING_VERSION, networks).unwrap();
+        let compressed = encoder.compress(&messages).unwrap();
+        assert_eq!(encoder.encoding_version(), 0);
+        let payload = encoder.encode(&compressed).unwrap();
+
+        // The accelerations after the reset aren't delta-encoded anymore.
+        assert_eq!(
+            decode_messages_with_version(&payload, 1, DELTA_ACCELERATIONS_ENCODING_VERSION)
+                .unwrap(),
+            compressed
+        );
+        let mut encoder = Encoder::new(0, vec![]).unwrap();
+        let compressed_in_version_0 = encoder.compress(&messages).unwrap();
+        assert_eq!(compressed_in_version_0, compressed);
+        assert_eq!(encoder.encode(&compressed).unwrap(), payload);
+    }
+
+    #[test]
+    fn checksum_trailer() {
+        let mut encoder = Encoder::new(0, vec![]).unwrap();
+        let update = Message::UpdateVersion {
+            version_number: CHECKSUM_ENCODING_VERSION,
+        };
+        let compressed = encoder.compress(&[update]).unwrap();
+        // The switch itself is still written in the previous version.
+        assert_eq!(encoder.encode(&compressed).unwrap(), [0x02, 0x05]);
+
+        let message = Message::RegisterNetworks {
+            remove: vec![],
+            add: vec!["A:1".to_string()],
+        };
+        let compressed = encoder.compress(std::slice::from_ref(&message)).unwrap();
+        let (payload, stats) = encoder.encode_with_stats(&compressed).unwrap();
+        assert_eq!(stats.total_bytes, payload.len());
+        assert_eq!(
+            payload.len(),
+            stats.message_bytes.iter().sum::<usize>() + CHECKSUM_BYTES
+        );
+        assert_eq!(
+            decode_messages_with_version(&payload, 0, CHECKSUM_ENCODING_VERSION).unwrap(),
+            compressed
+        );
+
+        let mut streamed = vec![];
+        let mut encoder = Encoder::new(CHECKSUM_ENCODING_VERSION, vec![]).unwrap();
+        encoder.push_message(&message, &mut streamed).unwrap();
+        encoder.finish(&mut streamed).unwrap();
+        assert_eq!(streamed, payload);
+
+        let mut corrupted = payload.clone();
+        corrupted[2] ^= 1;
+        assert!(matches!(
+            decode_messages_with_version(&corrupted, 0, CHECKSUM_ENCODING_VERSION),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            decode_messages_with_version(
+                &payload[..payload.len() - 1],
+                0,
+                CHECKSUM_ENCODING_VERSION
+            ),
+            Err(DecodeError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            decode_messages_with_version(&payload[..2], 0, CHECKSUM_ENCODING_VERSION),
+            Err(DecodeError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn register_and_set_block_numbers() {
+        let networks = vec![("A:1".to_string(), Network::new(100, 10, 0))];
+        let block_ptrs: BTreeMap<_, _> = [
+            ("A:1".to_string(), BlockPtr::new(115, [1; 32])),
+            ("B:2".to_string(), BlockPtr::new(500, [2; 32])),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut separate = Encoder::new(0, networks.clone()).unwrap();
+        let compressed = separate
+            .compress(&[
+                Message::RegisterNetworks {
+                    remove: vec![],
+                    add: vec!["B:2".to_string()],
+                },
+                Message::SetBlockNumbersForNextEpoch(block_ptrs.clone()),
+            ])
+            .unwrap();
+        let separate_payload = separate.encode(&compressed).unwrap();
+
+        let mut combined = Encoder::new(0, networks).unwrap();
+        let compressed = combined
+            .compress(&[Message::RegisterNetworksAndSetBlockNumbers {
+                remove: vec![],
+                add: vec!["B:2".to_string()],
+                block_ptrs,
+            }])
+            .unwrap();
+        let payload = combined.encode(&compressed).unwrap();
+
+        assert_eq!(combined.network_table(), separate.network_table());
+        // The only difference is a tag, which fits in the preamble of the other message here.
+        assert_eq!(payload.len(), separate_payload.len());
+        assert_eq!(
+            decode_messages_with_networks(&payload, 1).unwrap(),
+            compressed
+        );
+        let CompressedMessage::RegisterNetworksAndSetBlockNumbers { block_numbers, .. } =
+            &compressed[0]
+        else {
+            panic!("unexpected messages: {compressed:?}");
+        };
+        assert!(matches!(
+            block_numbers,
+            CompressedSetBlockNumbersForNextEpoch::NonEmpty { accelerations, .. }
+                if accelerations == &[5, 500]
+        ));
+    }
+
+    #[test]
+    fn register_and_set_block_numbers_without_networks() {
+        let networks = vec![("A:1".to_string(), Network::new(100, 10, 0))];
+        let mut encoder = Encoder::new(0, networks).unwrap();
+        let compressed = encoder
+            .compress(&[Message::RegisterNetworksAndSetBlockNumbers {
+                remove: vec![0],
+                add: vec![],
+                block_ptrs: [("A:1".to_string(), BlockPtr::new(115, [1; 32]))]
+                    .into_iter()
+                    .collect(),
+            }])
+            .unwrap();
+        let payload = encoder.encode(&compressed).unwrap();
+
+        assert_eq!(
+            compressed,
+            vec![CompressedMessage::RegisterNetworks {
+                remove: vec![0],
+                add: vec![],
+            }]
+        );
+        assert!(encoder.network_table().is_empty());
+        assert_eq!(
+            decode_messages_with_networks(&payload, 1).unwrap(),
+            compressed
+        );
+    }
 }
@@ -1,7 +1,17 @@
+//! The merkle root that `SetBlockNumbersForNextEpoch` messages commit to, over the block of every
+//! registered network.
+//!
+//! Each leaf is the Keccak-256 hash of the network index and block number, both as little-endian
+//! `u64`s, followed by the block hash. Leaves are ordered by network index and hashed pairwise, with
+//! the smaller hash first; an odd node out is carried up to the next level as-is. The root of no
+//! leaves is all zeroes.
+
 use super::Bytes32;
 use crate::NetworkIndex;
+use alloc::vec::Vec;
 use tiny_keccak::{Hasher, Keccak};
 
+/// The block of a single network.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MerkleLeaf {
     pub network_index: NetworkIndex,
@@ -19,6 +29,7 @@ impl MerkleLeaf {
     }
 }
 
+/// `data` must be ordered by network index.
 pub fn merkle_root(data: &[MerkleLeaf]) -> Bytes32 {
     let mut scratch: Vec<Bytes32> = data.iter().map(MerkleLeaf::hash).collect();
 
@@ -37,13 +48,13 @@ pub fn merkle_root(data: &[MerkleLeaf]) -> Bytes32 {
             write += 1;
         }
 
-        scratch.truncate(write - 1);
+        scratch.truncate(write);
     }
 
     scratch.first().cloned().unwrap_or_default()
 }
 
-fn keccak<const N: usize>(data: [&[u8]; N]) -> Bytes32 {
+pub(crate) fn keccak<const N: usize>(data: [&[u8]; N]) -> Bytes32 {
     let mut hasher = Keccak::v256();
     for elem in data {
         hasher.update(elem);
@@ -76,4 +87,40 @@ mod tests {
 
         assert_eq!(leaf.hash(), merkle_root(&[leaf]));
     }
+
+    fn leaves() -> Vec<MerkleLeaf> {
+        (0..3)
+            .map(|i| MerkleLeaf {
+                network_index: i,
+                block_number: (i + 1) * 100,
+                block_hash: [i as u8 + 1; 32],
+            })
+            .collect()
+    }
+
+    // These were computed with an independent Keccak-256 implementation.
+
+    #[test]
+    fn leaf_hash() {
+        assert_eq!(
+            hex::encode(leaves()[0].hash()),
+            "26abf868ca81a994afa99386bcfbd9445f4dd281825603bdb9e919726dcbdc72"
+        );
+    }
+
+    #[test]
+    fn merkle_root_with_two_leaves() {
+        assert_eq!(
+            hex::encode(merkle_root(&leaves()[..2])),
+            "64f4fd4259dc6e3cae0d15ae5de1b8e192e201a7afc1f6d2b04d69ecd619b076"
+        );
+    }
+
+    #[test]
+    fn merkle_root_with_odd_leaves() {
+        assert_eq!(
+            hex::encode(merkle_root(&leaves())),
+            "59637fbeb3bf61de4b5f8914b178442297e25118f0a98cc8093856f48118df92"
+        );
+    }
 }
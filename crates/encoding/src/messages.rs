@@ -1,9 +1,14 @@
-use std::collections::BTreeMap;
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
 
 pub type NetworkIndex = u64;
 pub type Bytes32 = [u8; 32];
 
+/// Displays and parses as `<number>:0x<hash>`, e.g. in config files and JSON fixtures.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_with::DeserializeFromStr, serde_with::SerializeDisplay)
+)]
 pub struct BlockPtr {
     pub number: u64,
     pub hash: Bytes32,
@@ -15,8 +20,32 @@ impl BlockPtr {
     }
 }
 
-impl std::fmt::Debug for BlockPtr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for BlockPtr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}:0x{}", self.number, hex::encode(self.hash))
+    }
+}
+
+impl core::str::FromStr for BlockPtr {
+    type Err = String;
+
+    /// The `0x` prefix of the hash is optional.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, hash) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected <number>:<hash>, got {s}"))?;
+        let number = number
+            .parse()
+            .map_err(|e| format!("Invalid block number {number}: {e}"))?;
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(hash.trim_start_matches("0x"), &mut bytes)
+            .map_err(|e| format!("Invalid block hash {hash}: {e}"))?;
+        Ok(Self::new(number, bytes))
+    }
+}
+
+impl core::fmt::Debug for BlockPtr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("BlockPtr")
             .field("number", &self.number)
             .field("hash", &format!("0x{}", hex::encode(self.hash)))
@@ -24,7 +53,7 @@ impl std::fmt::Debug for BlockPtr {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Message {
     // TODO: Consider specifying epoch number here?
     SetBlockNumbersForNextEpoch(BTreeMap<String, BlockPtr>),
@@ -34,8 +63,8 @@ pub enum Message {
         // Add is by name
         add: Vec<String>,
     },
+    /// Corrects the merkle roots that earlier payloads committed to, by network index.
     CorrectEpochs {
-        // TODO: include hash, count, and (if count is nonzero) merkle root
         data_by_network_id: BTreeMap<NetworkIndex, EpochDetails>,
     },
     UpdateVersion {
@@ -53,19 +82,61 @@ pub enum Message {
         valid_through: u64,
         permissions: Vec<String>,
     },
+    /// A `RegisterNetworks` message and a `SetBlockNumbersForNextEpoch` message in one, for
+    /// onboarding networks along with their first block numbers. The block numbers are for the
+    /// networks as registered after the message. Unlike the two separate messages, it takes a
+    /// single tag, and [`Encoder::split_into_payloads`](crate::Encoder::split_into_payloads) can't
+    /// put the registration and the block numbers in different payloads.
+    RegisterNetworksAndSetBlockNumbers {
+        // Remove is by index
+        remove: Vec<NetworkIndex>,
+        // Add is by name
+        add: Vec<String>,
+        block_ptrs: BTreeMap<String, BlockPtr>,
+    },
 }
 
+/// What [`Message::str_to_u64`] returns for names that aren't messages.
+pub const UNKNOWN_MESSAGE_TAG: u64 = 8;
+
+/// Message names as used in permissions, by tag.
+pub(crate) const MESSAGE_NAMES: [&str; UNKNOWN_MESSAGE_TAG as usize] = [
+    "SetBlockNumbersForNextEpochMessage",
+    "CorrectEpochsMessage",
+    "UpdateVersionMessage",
+    "RegisterNetworksMessage",
+    "ChangePermissionsMessage",
+    "ResetStateMessage",
+    "RegisterNetworksAndAliasesMessage",
+    "RegisterNetworksAndSetBlockNumbersMessage",
+];
+
 impl Message {
     pub fn str_to_u64(str: &str) -> u64 {
-        match str {
-            "SetBlockNumbersForNextEpochMessage" => 0u64,
-            "CorrectEpochsMessage" => 1,
-            "UpdateVersionMessage" => 2,
-            "RegisterNetworksMessage" => 3,
-            "ChangePermissionsMessage" => 4,
-            "ResetStateMessage" => 5,
-            "RegisterNetworksAndAliasesMessage" => 6,
-            _ => 7,
+        MESSAGE_NAMES
+            .iter()
+            .position(|name| *name == str)
+            .map_or(UNKNOWN_MESSAGE_TAG, |tag| tag as u64)
+    }
+
+    /// The inverse of [`Message::str_to_u64`].
+    pub fn u64_to_str(tag: u64) -> Option<&'static str> {
+        MESSAGE_NAMES.get(usize::try_from(tag).ok()?).copied()
+    }
+
+    /// The name of the message in the JSON format, e.g. `"RegisterNetworks"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::SetBlockNumbersForNextEpoch(_) => "SetBlockNumbersForNextEpoch",
+            Message::RegisterNetworks { .. } => "RegisterNetworks",
+            Message::CorrectEpochs { .. } => "CorrectEpochs",
+            Message::UpdateVersion { .. } => "UpdateVersion",
+            Message::Reset => "Reset",
+            Message::RegisterNetworksAndAliases { .. } => "RegisterNetworksAndAliases",
+            Message::ChangePermissions { .. } => "ChangePermissions",
+            Message::RegisterNetworksAndSetBlockNumbers { .. } => {
+                "RegisterNetworksAndSetBlockNumbers"
+            }
         }
     }
 }
@@ -93,6 +164,11 @@ pub enum CompressedMessage {
         valid_through: u64,
         permissions: Vec<u64>,
     },
+    RegisterNetworksAndSetBlockNumbers {
+        remove: Vec<u64>,
+        add: Vec<String>,
+        block_numbers: CompressedSetBlockNumbersForNextEpoch,
+    },
 }
 
 impl CompressedMessage {
@@ -120,8 +196,181 @@ pub enum CompressedSetBlockNumbersForNextEpoch {
     },
 }
 
+/// A [`CompressedMessage`] that borrows its strings and hashes from the payload it was decoded
+/// from, see [`decode_message_refs`](crate::decode_message_refs).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompressedMessageRef<'a> {
+    SetBlockNumbersForNextEpoch(CompressedSetBlockNumbersForNextEpochRef<'a>),
+    CorrectEpochs {
+        data_by_network_id: BTreeMap<NetworkIndex, EpochDetailsRef<'a>>,
+    },
+    RegisterNetworks {
+        remove: Vec<u64>,
+        add: Vec<&'a str>,
+    },
+    UpdateVersion {
+        version_number: u64,
+    },
+    Reset,
+    RegisterNetworksAndAliases {
+        remove: Vec<u64>,
+        add: Vec<(&'a str, &'a str)>,
+    },
+    ChangePermissions {
+        address: &'a [u8; 20],
+        valid_through: u64,
+        permissions: Vec<u64>,
+    },
+    RegisterNetworksAndSetBlockNumbers {
+        remove: Vec<u64>,
+        add: Vec<&'a str>,
+        block_numbers: CompressedSetBlockNumbersForNextEpochRef<'a>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompressedSetBlockNumbersForNextEpochRef<'a> {
+    Empty {
+        count: u64,
+    },
+    NonEmpty {
+        accelerations: Vec<i64>,
+        root: &'a Bytes32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EpochDetailsRef<'a> {
+    pub tx_hash: &'a Bytes32,
+    pub merkle_root: &'a Bytes32,
+}
+
+impl From<CompressedMessageRef<'_>> for CompressedMessage {
+    fn from(message: CompressedMessageRef<'_>) -> Self {
+        match message {
+            CompressedMessageRef::SetBlockNumbersForNextEpoch(block_numbers) => {
+                Self::SetBlockNumbersForNextEpoch(block_numbers.into())
+            }
+            CompressedMessageRef::CorrectEpochs { data_by_network_id } => Self::CorrectEpochs {
+                data_by_network_id: data_by_network_id
+                    .into_iter()
+                    .map(|(index, details)| {
+                        (
+                            index,
+                            EpochDetails::new(*details.tx_hash, *details.merkle_root),
+                        )
+                    })
+                    .collect(),
+            },
+            CompressedMessageRef::RegisterNetworks { remove, add } => Self::RegisterNetworks {
+                remove,
+                add: add.into_iter().map(String::from).collect(),
+            },
+            CompressedMessageRef::UpdateVersion { version_number } => {
+                Self::UpdateVersion { version_number }
+            }
+            CompressedMessageRef::Reset => Self::Reset,
+            CompressedMessageRef::RegisterNetworksAndAliases { remove, add } => {
+                Self::RegisterNetworksAndAliases {
+                    remove,
+                    add: add
+                        .into_iter()
+                        .map(|(id, alias)| (id.into(), alias.into()))
+                        .collect(),
+                }
+            }
+            CompressedMessageRef::ChangePermissions {
+                address,
+                valid_through,
+                permissions,
+            } => Self::ChangePermissions {
+                address: *address,
+                valid_through,
+                permissions,
+            },
+            CompressedMessageRef::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add,
+                block_numbers,
+            } => Self::RegisterNetworksAndSetBlockNumbers {
+                remove,
+                add: add.into_iter().map(String::from).collect(),
+                block_numbers: block_numbers.into(),
+            },
+        }
+    }
+}
+
+impl From<CompressedSetBlockNumbersForNextEpochRef<'_>> for CompressedSetBlockNumbersForNextEpoch {
+    fn from(block_numbers: CompressedSetBlockNumbersForNextEpochRef<'_>) -> Self {
+        match block_numbers {
+            CompressedSetBlockNumbersForNextEpochRef::Empty { count } => Self::Empty { count },
+            CompressedSetBlockNumbersForNextEpochRef::NonEmpty {
+                accelerations,
+                root,
+            } => Self::NonEmpty {
+                accelerations,
+                root: *root,
+            },
+        }
+    }
+}
+
+/// Identifies the payload being corrected.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct EpochDetails {
-    tx_hash: Bytes32,
-    merkle_root: Bytes32,
+    /// The transaction that carried the payload.
+    pub tx_hash: Bytes32,
+    /// The corrected merkle root of the network's block.
+    pub merkle_root: Bytes32,
+}
+
+impl EpochDetails {
+    pub fn new(tx_hash: Bytes32, merkle_root: Bytes32) -> Self {
+        Self {
+            tx_hash,
+            merkle_root,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::str::FromStr;
+
+    #[test]
+    fn block_ptr_round_trips_through_strings() {
+        let block_ptr = BlockPtr::new(42, [0xab; 32]);
+        let s = block_ptr.to_string();
+        assert_eq!(s, format!("42:0x{}", "ab".repeat(32)));
+        assert_eq!(BlockPtr::from_str(&s), Ok(block_ptr));
+        assert_eq!(
+            BlockPtr::from_str(&format!("42:{}", "ab".repeat(32))),
+            Ok(block_ptr)
+        );
+    }
+
+    #[test]
+    fn invalid_block_ptrs() {
+        let hash = "ab".repeat(32);
+        for s in [
+            "".to_string(),
+            hash.clone(),
+            format!("-1:{hash}"),
+            format!("42:{}", "ab".repeat(31)),
+            "42:0xzz".to_string(),
+        ] {
+            assert!(BlockPtr::from_str(&s).is_err(), "{s}");
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn block_ptr_serde() {
+        let block_ptr = BlockPtr::new(42, [0xab; 32]);
+        let json = serde_json::to_value(block_ptr).unwrap();
+        assert_eq!(json, serde_json::json!(block_ptr.to_string()));
+        assert_eq!(serde_json::from_value::<BlockPtr>(json).unwrap(), block_ptr);
+    }
 }
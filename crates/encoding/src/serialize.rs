@@ -1,45 +1,152 @@
-use crate::{messages::*, NetworkIndex};
+//! A payload is a sequence of preambles, each followed by the messages whose tags it holds. A
+//! preamble is a single byte with room for two 4-bit tags, starting from the low bits. Any number
+//! of messages fits in a payload: once a preamble is full, the next message starts a new one. The
+//! last preamble is padded with zero tags, which the Epoch Subgraph ignores once there are no bytes
+//! left to decode.
+//!
+//! Payloads of [`CHECKSUM_ENCODING_VERSION`] end with a trailer, which [`MessageWriter::finish`]
+//! appends.
+
+use crate::varint::{encode_i64, encode_u64};
+use crate::{
+    messages::*, EncodeError, NetworkIndex, CHECKSUM_BYTES, CHECKSUM_ENCODING_VERSION,
+    CURRENT_ENCODING_VERSION, DELTA_ACCELERATIONS_ENCODING_VERSION,
+};
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use tiny_keccak::{Hasher, Keccak};
 
 const PREAMBLE_BIT_LENGTH: usize = 8;
 const TAG_BIT_LENGTH: usize = 4;
 const PREAMBLE_CAPACITY: usize = PREAMBLE_BIT_LENGTH / TAG_BIT_LENGTH;
 
-pub fn serialize_messages(messages: &[CompressedMessage], bytes: &mut Vec<u8>) {
-    let chunks = messages.chunks(PREAMBLE_CAPACITY);
-    for chunk in chunks {
-        serialize_preamble(chunk, bytes);
+/// Serializes messages in the layout of [`CURRENT_ENCODING_VERSION`].
+///
+/// Fails with [`EncodeError::UnsupportedMessage`] if a `ChangePermissions` message grants a
+/// permission for a message that doesn't exist.
+pub fn serialize_messages(
+    messages: &[CompressedMessage],
+    bytes: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    let mut writer = MessageWriter::new(CURRENT_ENCODING_VERSION);
+    for message in messages {
+        writer.write(message, bytes)?;
+    }
+    writer.finish(bytes);
+    Ok(())
+}
+
+/// Serializes messages one at a time, in the layout of a given encoding version. For the current
+/// version, the bytes are the same as [`serialize_messages`] writes.
+///
+/// Every preamble is written along with the first message after it, and the tags of later messages
+/// are filled in afterwards, so the same buffer must be passed to every call, and then to
+/// [`MessageWriter::finish`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MessageWriter {
+    /// The version of the payload, which decides its trailer.
+    encoding_version: u64,
+    /// The version that the subgraph decodes the next message with. A `Reset` sets it back to 0
+    /// within the same payload.
+    message_version: u64,
+    /// The offset of the last preamble, and how many tags it holds, if it has room for more.
+    preamble: Option<(usize, usize)>,
+    /// The offset of the first message.
+    start: Option<usize>,
+}
 
-        for message in chunk {
-            serialize_message(message, bytes)
+impl MessageWriter {
+    pub(crate) fn new(encoding_version: u64) -> Self {
+        Self {
+            encoding_version,
+            message_version: encoding_version,
+            preamble: None,
+            start: None,
         }
     }
-}
 
-fn serialize_preamble(messages: &[CompressedMessage], bytes: &mut Vec<u8>) {
-    assert!(messages.len() <= PREAMBLE_CAPACITY);
+    pub(crate) fn write(
+        &mut self,
+        message: &CompressedMessage,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), EncodeError> {
+        let delta_accelerations = match self.message_version {
+            0 => false,
+            DELTA_ACCELERATIONS_ENCODING_VERSION => true,
+            CHECKSUM_ENCODING_VERSION => false,
+            version => return Err(EncodeError::UnsupportedEncodingVersion(version)),
+        };
+        self.start.get_or_insert(bytes.len());
+
+        let (offset, tags) = self.preamble.take().unwrap_or_else(|| {
+            bytes.push(0);
+            (bytes.len() - 1, 0)
+        });
+        bytes[offset] |= message_tag(message) << (TAG_BIT_LENGTH * tags);
+        if tags + 1 < PREAMBLE_CAPACITY {
+            self.preamble = Some((offset, tags + 1));
+        }
 
-    let mut preamble = 0;
-    for (i, msg) in messages.iter().enumerate() {
-        preamble |= message_tag(msg) << (TAG_BIT_LENGTH * i);
+        if let CompressedMessage::Reset = message {
+            self.message_version = 0;
+        }
+        serialize_message(message, delta_accelerations, bytes)
     }
+}
 
-    bytes.push(preamble)
+impl MessageWriter {
+    /// Completes the payload, with a trailer if the encoding version has one.
+    pub(crate) fn finish(self, bytes: &mut Vec<u8>) {
+        if self.encoding_version == CHECKSUM_ENCODING_VERSION {
+            let start = self.start.unwrap_or(bytes.len());
+            let trailer = checksum(&bytes[start..]);
+            bytes.extend_from_slice(&trailer);
+        }
+    }
+}
+
+/// The trailer of a [`CHECKSUM_ENCODING_VERSION`] payload without it.
+pub(crate) fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let mut hasher = Keccak::v256();
+    hasher.update(bytes);
+    let mut hash = [0; 32];
+    hasher.finalize(&mut hash);
+    // Unwrap: the slice has exactly the right length.
+    hash[..CHECKSUM_BYTES].try_into().unwrap()
 }
 
-fn serialize_message(message: &CompressedMessage, bytes: &mut Vec<u8>) {
+/// Replaces every acceleration but the first with its difference from the previous one. The
+/// subgraph computes these with wrapping 64-bit arithmetic too.
+fn delta_encode(accelerations: &[i64]) -> Vec<i64> {
+    let previous = core::iter::once(0).chain(accelerations.iter().copied());
+    accelerations
+        .iter()
+        .zip(previous)
+        .map(|(acceleration, previous)| acceleration.wrapping_sub(previous))
+        .collect()
+}
+
+/// `delta_accelerations` is whether accelerations are written as in
+/// [`DELTA_ACCELERATIONS_ENCODING_VERSION`].
+fn serialize_message(
+    message: &CompressedMessage,
+    delta_accelerations: bool,
+    bytes: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
     match message {
         CompressedMessage::SetBlockNumbersForNextEpoch(compressed_block_numbers) => {
-            serialize_set_block_numbers_for_next_block(compressed_block_numbers, bytes)
+            serialize_set_block_numbers_for_next_block(
+                compressed_block_numbers,
+                delta_accelerations,
+                bytes,
+            )
         }
         CompressedMessage::RegisterNetworks { add, remove } => {
             serialize_register_networks(add, remove, bytes)
         }
-        CompressedMessage::UpdateVersion { version_number } => {
-            serialize_u64(*version_number, bytes);
-        }
-        CompressedMessage::Reset => serialize_u64(0, bytes),
-        CompressedMessage::CorrectEpochs { .. } => {
-            todo!()
+        CompressedMessage::UpdateVersion { version_number } => encode_u64(*version_number, bytes),
+        CompressedMessage::Reset => encode_u64(0, bytes),
+        CompressedMessage::CorrectEpochs { data_by_network_id } => {
+            serialize_correct_epochs(data_by_network_id, bytes)
         }
         CompressedMessage::RegisterNetworksAndAliases { add, remove } => {
             serialize_register_networks_and_aliases(add, remove, bytes)
@@ -48,36 +155,67 @@ fn serialize_message(message: &CompressedMessage, bytes: &mut Vec<u8>) {
             address,
             valid_through,
             permissions,
-        } => serialize_change_permissions(address, *valid_through, permissions, bytes),
+        } => serialize_change_permissions(address, *valid_through, permissions, bytes)?,
+        // The block numbers are decoded with the networks that are left after the registration.
+        CompressedMessage::RegisterNetworksAndSetBlockNumbers {
+            remove,
+            add,
+            block_numbers,
+        } => {
+            serialize_register_networks(add, remove, bytes);
+            serialize_set_block_numbers_for_next_block(block_numbers, delta_accelerations, bytes);
+        }
     }
+    Ok(())
 }
 
 fn serialize_set_block_numbers_for_next_block(
     block_numbers: &CompressedSetBlockNumbersForNextEpoch,
+    delta_accelerations: bool,
     bytes: &mut Vec<u8>,
 ) {
     match block_numbers {
-        CompressedSetBlockNumbersForNextEpoch::Empty { count } => serialize_u64(*count, bytes),
+        CompressedSetBlockNumbersForNextEpoch::Empty { count } => encode_u64(*count, bytes),
         CompressedSetBlockNumbersForNextEpoch::NonEmpty {
             accelerations,
             root,
         } => {
             bytes.extend_from_slice(root);
-            for acceleration in accelerations {
-                serialize_i64(*acceleration, bytes);
+            if delta_accelerations {
+                for acceleration in delta_encode(accelerations) {
+                    encode_i64(acceleration, bytes);
+                }
+            } else {
+                for acceleration in accelerations {
+                    encode_i64(*acceleration, bytes);
+                }
             }
         }
     }
 }
 
+/// The number of corrections, followed by the network index, transaction hash and merkle root
+/// of each, sorted by network index.
+fn serialize_correct_epochs(
+    data_by_network_id: &BTreeMap<NetworkIndex, EpochDetails>,
+    bytes: &mut Vec<u8>,
+) {
+    encode_u64(data_by_network_id.len() as u64, bytes);
+    for (index, details) in data_by_network_id {
+        encode_u64(*index, bytes);
+        bytes.extend_from_slice(&details.tx_hash);
+        bytes.extend_from_slice(&details.merkle_root);
+    }
+}
+
 fn serialize_register_networks(add: &[String], remove: &[NetworkIndex], bytes: &mut Vec<u8>) {
-    serialize_u64(remove.len() as u64, bytes);
+    encode_u64(remove.len() as u64, bytes);
     for id in remove {
         // TODO: Compression - could delta encode series here. Probably not worth it.
-        serialize_u64(*id, bytes);
+        encode_u64(*id, bytes);
     }
 
-    serialize_u64(add.len() as u64, bytes);
+    encode_u64(add.len() as u64, bytes);
     for add in add {
         serialize_str(add, bytes);
     }
@@ -88,13 +226,13 @@ fn serialize_register_networks_and_aliases(
     remove: &[NetworkIndex],
     bytes: &mut Vec<u8>,
 ) {
-    serialize_u64(remove.len() as u64, bytes);
+    encode_u64(remove.len() as u64, bytes);
     for id in remove {
         // TODO: Compression - could delta encode series here. Probably not worth it.
-        serialize_u64(*id, bytes);
+        encode_u64(*id, bytes);
     }
 
-    serialize_u64(add.len() as u64, bytes);
+    encode_u64(add.len() as u64, bytes);
     for (add0, add1) in add {
         serialize_str(add0, bytes);
         serialize_str(add1, bytes);
@@ -106,48 +244,26 @@ fn serialize_change_permissions(
     valid_through: u64,
     permissions: &[u64],
     bytes: &mut Vec<u8>,
-) {
+) -> Result<(), EncodeError> {
+    if let Some(permission) = permissions.iter().find(|p| **p >= UNKNOWN_MESSAGE_TAG) {
+        return Err(EncodeError::UnsupportedMessage(format!(
+            "permission tag {permission}"
+        )));
+    }
     bytes.extend_from_slice(address);
-    serialize_u64(valid_through, bytes);
-    serialize_u64(permissions.len() as u64, bytes);
+    encode_u64(valid_through, bytes);
+    encode_u64(permissions.len() as u64, bytes);
     for permission in permissions {
-        serialize_u64(*permission, bytes);
+        encode_u64(*permission, bytes);
     }
+    Ok(())
 }
 
 fn serialize_str(value: &str, bytes: &mut Vec<u8>) {
-    serialize_u64(value.len() as u64, bytes);
+    encode_u64(value.len() as u64, bytes);
     bytes.extend_from_slice(value.as_bytes());
 }
 
-fn serialize_i64(value: i64, bytes: &mut Vec<u8>) {
-    // Uses ZigZag encoding. See
-    // <https://developers.google.com/protocol-buffers/docs/encoding#signed-ints>.
-    let unsigned = (value << 1) ^ (value >> 63);
-
-    serialize_u64(unsigned as u64, bytes);
-}
-
-fn serialize_u64(mut value: u64, bytes: &mut Vec<u8>) {
-    // The number of meaningful bits in `value`.
-    let num_bits_to_encode = 64 - value.leading_zeros();
-    // The number of bytes that are needed to encode `value`. It is
-    // calculated by finding the next multiple of 7 after `num_bits_to_encode`.
-    // Range bounds are tricky and must be handled separately.
-    let num_bytes = (num_bits_to_encode.clamp(1, 63) - 1) / 7 + 1;
-
-    debug_assert!(num_bytes >= 1);
-    debug_assert!(num_bytes <= 9);
-
-    bytes.push((value << num_bytes) as u8 | (1 << (num_bytes - 1)) as u8);
-    value >>= 8u32.saturating_sub(num_bytes);
-
-    while value > 0 {
-        bytes.push(value as u8);
-        value >>= 8;
-    }
-}
-
 fn message_tag(m: &CompressedMessage) -> u8 {
     match m {
         CompressedMessage::SetBlockNumbersForNextEpoch { .. } => 0u8,
@@ -157,51 +273,6 @@ fn message_tag(m: &CompressedMessage) -> u8 {
         CompressedMessage::ChangePermissions { .. } => 4,
         CompressedMessage::Reset => 5,
         CompressedMessage::RegisterNetworksAndAliases { .. } => 6,
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    const U64_TESTS: &[(u64, &[u8])] = &[
-        (0, &[1]),
-        (23, &[47]),
-        (9000, &[162, 140]),
-        (1455594, &[84, 175, 177]),
-        (109771541, &[88, 177, 175, 104]),
-        (24345908991, &[240, 223, 34, 100, 181]),
-        (1903269233213, &[96, 143, 240, 235, 200, 110]),
-        (72057594037927935, &[128, 255, 255, 255, 255, 255, 255, 255]),
-        (u64::MAX, &[0, 255, 255, 255, 255, 255, 255, 255, 255]),
-    ];
-
-    const ZIGZAG_TESTS: &[(u64, i64)] = &[
-        (0, 0),
-        (1, -1),
-        (4294967294, 2147483647),
-        (u64::MAX, i64::MIN),
-    ];
-
-    #[test]
-    fn encode_u64() {
-        for (value, expected) in U64_TESTS.iter() {
-            let mut buf = Vec::new();
-            serialize_u64(*value, &mut buf);
-            assert_eq!(&buf[..], *expected);
-        }
-    }
-
-    #[test]
-    fn encode_i64() {
-        for (unsigned, signed) in ZIGZAG_TESTS.iter() {
-            let mut buf_u64 = Vec::new();
-            serialize_u64(*unsigned as u64, &mut buf_u64);
-
-            let mut buf_i64 = Vec::new();
-            serialize_i64(*signed, &mut buf_i64);
-
-            assert_eq!(&buf_i64[..], &buf_u64[..]);
-        }
+        CompressedMessage::RegisterNetworksAndSetBlockNumbers { .. } => 7,
     }
 }
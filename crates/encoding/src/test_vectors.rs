@@ -0,0 +1,309 @@
+//! A fixed corpus of payloads, for conformance tests of the encoder, the Epoch Subgraph and the
+//! DataEdge contracts against the same fixtures.
+//!
+//! Every [`TestVector`] is a sequence of payloads that starts from a fresh Epoch Subgraph, i.e.
+//! without networks and on encoding version 0. Payloads must be decoded in order, because the
+//! networks and the encoding version that each of them needs are left behind by the previous ones.
+//! [`test_vectors`] always generates the same vectors, so their bytes can be checked into other
+//! repositories; `cargo xtask test-vectors` prints them as JSON.
+
+use crate::messages::*;
+use crate::{
+    EncodeError, Encoder, CHECKSUM_ENCODING_VERSION, DELTA_ACCELERATIONS_ENCODING_VERSION,
+};
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+/// A named sequence of payloads, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub payloads: Vec<TestPayload>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestPayload {
+    /// The number of networks that were registered before the payload.
+    pub network_count: u64,
+    /// The encoding version that the payload is written in.
+    pub encoding_version: u64,
+    /// The messages that went into the [`Encoder`].
+    pub messages: Vec<Message>,
+    /// What the payload decodes to.
+    pub compressed: Vec<CompressedMessage>,
+    pub bytes: Vec<u8>,
+}
+
+impl TestPayload {
+    /// The payload as `0x`-prefixed hex, as it appears in calldata.
+    pub fn hex(&self) -> String {
+        format!("0x{}", hex::encode(&self.bytes))
+    }
+}
+
+/// Generates the corpus, which is the same on every call.
+pub fn test_vectors() -> Vec<TestVector> {
+    // Unwrap: all of these are valid.
+    cases()
+        .into_iter()
+        .map(|(name, payloads)| generate(name, payloads).unwrap())
+        .collect()
+}
+
+fn generate(name: &'static str, payloads: Vec<Vec<Message>>) -> Result<TestVector, EncodeError> {
+    let mut encoder = Encoder::new(0, vec![])?;
+    let payloads = payloads
+        .into_iter()
+        .map(|messages| {
+            let network_count = encoder.network_deltas().len() as u64;
+            let encoding_version = encoder.encoding_version();
+            let compressed = encoder.compress(&messages)?;
+            let bytes = encoder.encode(&compressed)?;
+            Ok(TestPayload {
+                network_count,
+                encoding_version,
+                messages,
+                compressed,
+                bytes,
+            })
+        })
+        .collect::<Result<_, EncodeError>>()?;
+    Ok(TestVector { name, payloads })
+}
+
+fn register(add: &[&str]) -> Message {
+    Message::RegisterNetworks {
+        remove: vec![],
+        add: add.iter().map(ToString::to_string).collect(),
+    }
+}
+
+/// Block numbers by network, each with a hash made of its first byte.
+fn block_numbers(blocks: &[(&str, u64)]) -> Message {
+    Message::SetBlockNumbersForNextEpoch(
+        blocks
+            .iter()
+            .map(|(id, number)| (id.to_string(), BlockPtr::new(*number, [*number as u8; 32])))
+            .collect(),
+    )
+}
+
+fn cases() -> Vec<(&'static str, Vec<Vec<Message>>)> {
+    const ETHEREUM: &str = "eip155:1";
+    const GNOSIS: &str = "eip155:100";
+    const ARBITRUM: &str = "eip155:42161";
+    const BITCOIN: &str = "bip122:000000000019d6689c085ae165831e93";
+
+    vec![
+        (
+            "empty_block_numbers",
+            vec![vec![
+                block_numbers(&[]),
+                block_numbers(&[]),
+                block_numbers(&[]),
+            ]],
+        ),
+        (
+            "register_networks",
+            vec![vec![register(&[ETHEREUM, GNOSIS])]],
+        ),
+        (
+            "block_numbers",
+            vec![
+                vec![register(&[ETHEREUM, GNOSIS, BITCOIN])],
+                vec![block_numbers(&[
+                    (ETHEREUM, 15_000_000),
+                    (GNOSIS, 24_000_000),
+                    (BITCOIN, 750_000),
+                ])],
+                // Bitcoin doesn't get a new block, and Gnosis slows down.
+                vec![block_numbers(&[
+                    (ETHEREUM, 15_006_646),
+                    (GNOSIS, 24_015_000),
+                ])],
+            ],
+        ),
+        (
+            "register_and_block_numbers",
+            vec![vec![
+                register(&[ETHEREUM]),
+                block_numbers(&[(ETHEREUM, 100)]),
+                block_numbers(&[(ETHEREUM, 250)]),
+            ]],
+        ),
+        (
+            "remove_networks",
+            vec![
+                vec![register(&[ETHEREUM, GNOSIS, ARBITRUM])],
+                vec![Message::RegisterNetworks {
+                    remove: vec![0, 2],
+                    add: vec![BITCOIN.to_string()],
+                }],
+                vec![block_numbers(&[(GNOSIS, 1_000), (BITCOIN, 2_000)])],
+            ],
+        ),
+        (
+            "register_networks_and_set_block_numbers",
+            vec![
+                vec![register(&[ETHEREUM])],
+                vec![Message::RegisterNetworksAndSetBlockNumbers {
+                    remove: vec![],
+                    add: vec![GNOSIS.to_string()],
+                    block_ptrs: BTreeMap::from([
+                        (ETHEREUM.to_string(), BlockPtr::new(100, [100; 32])),
+                        (GNOSIS.to_string(), BlockPtr::new(200, [200; 32])),
+                    ]),
+                }],
+            ],
+        ),
+        (
+            "register_networks_and_aliases",
+            vec![vec![Message::RegisterNetworksAndAliases {
+                remove: vec![],
+                add: vec![
+                    (ETHEREUM.to_string(), "mainnet".to_string()),
+                    (GNOSIS.to_string(), "gnosis".to_string()),
+                ],
+            }]],
+        ),
+        (
+            "correct_epochs",
+            vec![
+                vec![register(&[ETHEREUM, GNOSIS])],
+                vec![Message::CorrectEpochs {
+                    data_by_network_id: BTreeMap::from([(1, EpochDetails::new([1; 32], [2; 32]))]),
+                }],
+            ],
+        ),
+        (
+            "change_permissions",
+            vec![vec![Message::ChangePermissions {
+                address: [0x44; 20],
+                valid_through: 1_000_000,
+                permissions: vec![
+                    "SetBlockNumbersForNextEpochMessage".to_string(),
+                    "ResetStateMessage".to_string(),
+                ],
+            }]],
+        ),
+        (
+            "delta_accelerations",
+            vec![
+                vec![register(&[ETHEREUM, GNOSIS, ARBITRUM])],
+                vec![Message::UpdateVersion {
+                    version_number: DELTA_ACCELERATIONS_ENCODING_VERSION,
+                }],
+                vec![block_numbers(&[
+                    (ETHEREUM, 100),
+                    (GNOSIS, 90),
+                    (ARBITRUM, 120),
+                ])],
+            ],
+        ),
+        (
+            "checksum",
+            vec![
+                vec![Message::UpdateVersion {
+                    version_number: CHECKSUM_ENCODING_VERSION,
+                }],
+                vec![register(&[ETHEREUM, GNOSIS])],
+                vec![block_numbers(&[(ETHEREUM, 100), (GNOSIS, 200)])],
+            ],
+        ),
+        (
+            "reset",
+            vec![
+                vec![register(&[ETHEREUM, GNOSIS])],
+                vec![Message::Reset, register(&[ARBITRUM])],
+            ],
+        ),
+    ]
+}
+
+/// The corpus as JSON: an array of vectors, each with a `name` and its `payloads`. Every payload
+/// has the `networkCount` and `encodingVersion` it's decoded with, its bytes as `hex`, and the
+/// `messages` it decodes to, as in [`crate::json`].
+#[cfg(feature = "json")]
+pub fn test_vectors_json() -> Result<serde_json::Value, crate::json::JsonError> {
+    let vectors = test_vectors()
+        .into_iter()
+        .map(|vector| {
+            let payloads = vector
+                .payloads
+                .into_iter()
+                .map(|payload| {
+                    Ok(serde_json::json!({
+                        "networkCount": payload.network_count,
+                        "encodingVersion": payload.encoding_version,
+                        "hex": payload.hex(),
+                        "messages": crate::json::compressed_to_json(payload.compressed)?,
+                    }))
+                })
+                .collect::<Result<Vec<_>, crate::json::JsonError>>()?;
+            Ok(serde_json::json!({ "name": vector.name, "payloads": payloads }))
+        })
+        .collect::<Result<Vec<_>, crate::json::JsonError>>()?;
+    Ok(serde_json::Value::Array(vectors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode_messages_with_version;
+
+    #[test]
+    fn payloads_decode() {
+        for vector in test_vectors() {
+            for payload in vector.payloads {
+                let decoded = decode_messages_with_version(
+                    &payload.bytes,
+                    payload.network_count,
+                    payload.encoding_version,
+                )
+                .unwrap();
+                assert_eq!(decoded, payload.compressed, "{}", vector.name);
+            }
+        }
+    }
+
+    /// Other repositories check these bytes in, so they must only change on purpose.
+    #[test]
+    fn golden_payloads() {
+        let hex = |name: &str| -> Vec<String> {
+            test_vectors()
+                .into_iter()
+                .find(|vector| vector.name == name)
+                .unwrap()
+                .payloads
+                .iter()
+                .map(TestPayload::hex)
+                .collect()
+        };
+        assert_eq!(hex("empty_block_numbers"), ["0x0007"]);
+        assert_eq!(
+            hex("remove_networks"),
+            [
+                "0x030107116569703135353a31156569703135353a313030196569703135353a3432313631",
+                "0x03050105034f6269703132323a3030303030303030303031396436363839633038356165313635383331653933",
+                "0x0023818c28ef5c16154802f3570210790d338c74c53d39507789c5729e1bd7edb0421f823e",
+            ]
+        );
+        assert_eq!(
+            hex("change_permissions"),
+            ["0x04444444444444444444444444444444444444444404127a05010b"]
+        );
+        assert_eq!(
+            hex("delta_accelerations"),
+            [
+                "0x030107116569703135353a31156569703135353a313030196569703135353a3432313631",
+                "0x0203",
+                "0x007082421bddf780763deeb16b44bce002400d5c99d122daa79e5df899227970d522032779",
+            ]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! The prefix varint format used for all integers in encoded payloads.
+//!
+//! An integer is encoded in `n` bytes such that (as a little-endian number) the lowest `n` bits
+//! hold the length marker `1 << (n - 1)`, and the remaining `7 * n` bits hold the value itself.
+//! In other words, the number of trailing zeros of the first byte tells how many more bytes
+//! follow, so that decoders don't need to look at every byte to find the end of the integer.
+//!
+//! | Bytes | Value bits | First byte  |
+//! |-------|------------|-------------|
+//! | 1     | 7          | `xxxxxxx1`  |
+//! | 2     | 14         | `xxxxxx10`  |
+//! | ...   | ...        | ...         |
+//! | 8     | 56         | `10000000`  |
+//!
+//! # `u64` and `u128`
+//!
+//! [`encode_u64`] never uses more than 9 bytes: a first byte of `0` is followed by the 8 bytes of
+//! the value, without a length marker. This is the format that the subgraph understands.
+//!
+//! [`encode_u128`] instead keeps extending the length marker into the following bytes, up to 19
+//! bytes for [`u128::MAX`]. Both formats are identical for values below 2<sup>56</sup>, but
+//! **they differ for larger values**, so decoders must know in advance which one to expect.
+//!
+//! Signed integers are ZigZag-encoded first, see [`encode_i64`].
+
+use alloc::vec::Vec;
+
+/// The maximum length of an encoded [`u64`].
+pub const MAX_U64_LEN: usize = 9;
+/// The maximum length of an encoded [`u128`].
+pub const MAX_U128_LEN: usize = 19;
+
+/// Something that went wrong when decoding a varint.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VarintError {
+    #[error("Varint is {expected} bytes long, but only {available} are available")]
+    Truncated { expected: usize, available: usize },
+    #[error("Varint doesn't fit in {bits} bits")]
+    Overflow { bits: u32 },
+}
+
+/// Encodes `value` and appends it to `bytes`.
+pub fn encode_u64(value: u64, bytes: &mut Vec<u8>) {
+    // The number of meaningful bits in `value`, at least one.
+    let num_bits_to_encode = 64 - (value | 1).leading_zeros();
+    // The number of bytes that are needed to encode `value`, i.e. the next multiple of 7 after
+    // `num_bits_to_encode`. Values of more than 56 bits take 9 bytes, which the `min` covers.
+    let num_bytes = num_bits_to_encode.div_ceil(7).min(MAX_U64_LEN as u32);
+
+    debug_assert!(num_bytes >= 1);
+    debug_assert!(num_bytes <= 9);
+
+    if num_bytes == MAX_U64_LEN as u32 {
+        bytes.push(0);
+        bytes.extend_from_slice(&value.to_le_bytes());
+    } else {
+        // `value` has at most `7 * num_bytes` bits, so the length marker fits next to it.
+        let encoded = (value << num_bytes) | (1 << (num_bytes - 1));
+        // Writing a whole word and cutting it short is cheaper than writing a variable number of
+        // bytes.
+        let len = bytes.len() + num_bytes as usize;
+        bytes.extend_from_slice(&encoded.to_le_bytes());
+        bytes.truncate(len);
+    }
+}
+
+/// Encodes `value` and appends it to `bytes`. See the [module-level docs](self) for how this
+/// differs from [`encode_u64`].
+pub fn encode_u128(value: u128, bytes: &mut Vec<u8>) {
+    let num_bits_to_encode = (128 - value.leading_zeros()).max(1);
+    let num_bytes = num_bits_to_encode.div_ceil(7);
+    debug_assert!(num_bytes as usize <= MAX_U128_LEN);
+
+    // `value << num_bytes` can take more than 128 bits, so we split it in two halves.
+    let low = (value << num_bytes) | (1 << (num_bytes - 1));
+    let high = value >> (128 - num_bytes);
+    let mut encoded = [0; 32];
+    encoded[..16].copy_from_slice(&low.to_le_bytes());
+    encoded[16..].copy_from_slice(&high.to_le_bytes());
+    bytes.extend_from_slice(&encoded[..num_bytes as usize]);
+}
+
+/// Encodes `value` with ZigZag encoding, so that small negative numbers take few bytes too.
+/// See <https://developers.google.com/protocol-buffers/docs/encoding#signed-ints>.
+pub fn encode_i64(value: i64, bytes: &mut Vec<u8>) {
+    let unsigned = (value << 1) ^ (value >> 63);
+    encode_u64(unsigned as u64, bytes);
+}
+
+/// Decodes a [`u64`] from the beginning of `bytes`. Returns the value and the number of bytes
+/// it took.
+pub fn decode_u64(bytes: &[u8]) -> Result<(u64, usize), VarintError> {
+    let first = *bytes.first().ok_or(VarintError::Truncated {
+        expected: 1,
+        available: 0,
+    })?;
+    let num_bytes = if first == 0 {
+        MAX_U64_LEN
+    } else {
+        first.trailing_zeros() as usize + 1
+    };
+    let value = if let Some(word) = bytes.get(..8).filter(|_| first != 0) {
+        // Most varints are read from the middle of a payload, so a whole word is available and
+        // the bytes after the varint can be masked out instead of copied around.
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        (word & (u64::MAX >> (64 - 8 * num_bytes))) >> num_bytes
+    } else {
+        let encoded = take(bytes, num_bytes)?;
+        let mut le_bytes = [0; 8];
+        if first == 0 {
+            le_bytes.copy_from_slice(&encoded[1..]);
+            u64::from_le_bytes(le_bytes)
+        } else {
+            le_bytes[..num_bytes].copy_from_slice(encoded);
+            u64::from_le_bytes(le_bytes) >> num_bytes
+        }
+    };
+    Ok((value, num_bytes))
+}
+
+/// Decodes a [`u128`], as encoded by [`encode_u128`], from the beginning of `bytes`. Returns the
+/// value and the number of bytes it took.
+pub fn decode_u128(bytes: &[u8]) -> Result<(u128, usize), VarintError> {
+    // The length marker can span multiple bytes.
+    let zero_bytes = bytes
+        .iter()
+        .take(MAX_U128_LEN / 8 + 1)
+        .take_while(|b| **b == 0)
+        .count();
+    if zero_bytes * 8 >= MAX_U128_LEN {
+        return Err(VarintError::Overflow { bits: 128 });
+    }
+    let Some(marker_byte) = bytes.get(zero_bytes) else {
+        // We don't know how long the varint is, but it's at least one byte longer.
+        return Err(VarintError::Truncated {
+            expected: bytes.len() + 1,
+            available: bytes.len(),
+        });
+    };
+    let num_bytes = zero_bytes * 8 + marker_byte.trailing_zeros() as usize + 1;
+    if num_bytes > MAX_U128_LEN {
+        return Err(VarintError::Overflow { bits: 128 });
+    }
+    let encoded = take(bytes, num_bytes)?;
+
+    let mut le_bytes = [0; 32];
+    le_bytes[..num_bytes].copy_from_slice(encoded);
+    let low = u128::from_le_bytes(le_bytes[..16].try_into().unwrap());
+    let high = u128::from_le_bytes(le_bytes[16..].try_into().unwrap());
+    // Any bits that would be shifted out of a `u128` must be zero.
+    if high >> num_bytes != 0 {
+        return Err(VarintError::Overflow { bits: 128 });
+    }
+    let value = (low >> num_bytes) | (high << (128 - num_bytes));
+    Ok((value, num_bytes))
+}
+
+/// Decodes an [`i64`], as encoded by [`encode_i64`], from the beginning of `bytes`.
+pub fn decode_i64(bytes: &[u8]) -> Result<(i64, usize), VarintError> {
+    let (unsigned, len) = decode_u64(bytes)?;
+    let value = (unsigned >> 1) as i64 ^ -((unsigned & 1) as i64);
+    Ok((value, len))
+}
+
+fn take(bytes: &[u8], len: usize) -> Result<&[u8], VarintError> {
+    bytes.get(..len).ok_or(VarintError::Truncated {
+        expected: len,
+        available: bytes.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const U64_TESTS: &[(u64, &[u8])] = &[
+        (0, &[1]),
+        (23, &[47]),
+        (9000, &[162, 140]),
+        (1455594, &[84, 175, 177]),
+        (109771541, &[88, 177, 175, 104]),
+        (24345908991, &[240, 223, 34, 100, 181]),
+        (1903269233213, &[96, 143, 240, 235, 200, 110]),
+        (72057594037927935, &[128, 255, 255, 255, 255, 255, 255, 255]),
+        (u64::MAX, &[0, 255, 255, 255, 255, 255, 255, 255, 255]),
+    ];
+
+    const ZIGZAG_TESTS: &[(u64, i64)] = &[
+        (0, 0),
+        (1, -1),
+        (4294967294, 2147483647),
+        (u64::MAX, i64::MIN),
+    ];
+
+    fn encoded_u64(value: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        super::encode_u64(value, &mut buf);
+        buf
+    }
+
+    fn encoded_u128(value: u128) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode_u128(value, &mut buf);
+        buf
+    }
+
+    /// All powers of two within `bits`, plus their neighbours.
+    fn boundaries(bits: u32) -> impl Iterator<Item = u128> {
+        (0..bits).flat_map(|i| {
+            let power = 1u128 << i;
+            [power - 1, power, power + 1]
+        })
+    }
+
+    #[test]
+    fn encode_u64() {
+        for (value, expected) in U64_TESTS.iter() {
+            assert_eq!(&encoded_u64(*value)[..], *expected);
+        }
+    }
+
+    #[test]
+    fn encode_i64() {
+        for (unsigned, signed) in ZIGZAG_TESTS.iter() {
+            let mut buf_i64 = Vec::new();
+            super::encode_i64(*signed, &mut buf_i64);
+
+            assert_eq!(&buf_i64[..], &encoded_u64(*unsigned)[..]);
+            assert_eq!(
+                decode_i64(&buf_i64).unwrap(),
+                (*signed, encoded_u64(*unsigned).len())
+            );
+        }
+    }
+
+    #[test]
+    fn u64_roundtrip_at_boundaries() {
+        for value in boundaries(64).map(|v| v as u64).chain([u64::MAX]) {
+            let buf = encoded_u64(value);
+            let bits = 64 - value.leading_zeros();
+            let expected_len = if bits > 56 {
+                9
+            } else {
+                bits.max(1).div_ceil(7)
+            };
+            assert_eq!(buf.len(), expected_len as usize, "{value}");
+            assert_eq!(decode_u64(&buf).unwrap(), (value, buf.len()), "{value}");
+        }
+    }
+
+    #[test]
+    fn u128_roundtrip_at_boundaries() {
+        for value in boundaries(128).chain([u128::MAX]) {
+            let buf = encoded_u128(value);
+            let bits = (128 - value.leading_zeros()).max(1);
+            assert_eq!(buf.len(), bits.div_ceil(7) as usize, "{value}");
+            assert_eq!(decode_u128(&buf).unwrap(), (value, buf.len()), "{value}");
+        }
+    }
+
+    #[test]
+    fn max_length_encodings() {
+        assert_eq!(encoded_u64(u64::MAX).len(), MAX_U64_LEN);
+        assert_eq!(encoded_u128(u128::MAX).len(), MAX_U128_LEN);
+        assert_eq!(
+            encoded_u128(u128::MAX),
+            [&[0, 0, 0b1111_1100][..], &[0xff; 15], &[0b0000_0111]].concat()
+        );
+    }
+
+    #[test]
+    fn u64_and_u128_agree_below_2_pow_56() {
+        for value in boundaries(56).filter(|v| *v < 1 << 56) {
+            assert_eq!(encoded_u64(value as u64), encoded_u128(value), "{value}");
+        }
+        assert_ne!(encoded_u64(1 << 56), encoded_u128(1 << 56));
+    }
+
+    #[test]
+    fn decoding_ignores_trailing_bytes() {
+        let mut buf = encoded_u64(9000);
+        buf.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(decode_u64(&buf).unwrap(), (9000, 2));
+        assert_eq!(decode_u128(&buf).unwrap(), (9000, 2));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            decode_u64(&[]),
+            Err(VarintError::Truncated {
+                expected: 1,
+                available: 0
+            })
+        );
+        for value in [9000, 1 << 40, u64::MAX] {
+            let buf = encoded_u64(value);
+            for len in 1..buf.len() {
+                assert_eq!(
+                    decode_u64(&buf[..len]),
+                    Err(VarintError::Truncated {
+                        expected: buf.len(),
+                        available: len
+                    })
+                );
+            }
+        }
+        for value in [9000, 1 << 70, u128::MAX] {
+            let buf = encoded_u128(value);
+            for len in 0..buf.len() {
+                assert!(matches!(
+                    decode_u128(&buf[..len]),
+                    Err(VarintError::Truncated { .. })
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn u128_overflow() {
+        // A length marker beyond the maximum length.
+        assert_eq!(
+            decode_u128(&[0, 0, 0]),
+            Err(VarintError::Overflow { bits: 128 })
+        );
+        assert_eq!(
+            decode_u128(&[0, 0, 0b0000_1000]),
+            Err(VarintError::Overflow { bits: 128 })
+        );
+        // A maximum length varint with more than 128 bits of value.
+        assert_eq!(
+            decode_u128(&[&[0, 0, 0b1111_1100][..], &[0xff; 16]].concat()),
+            Err(VarintError::Overflow { bits: 128 })
+        );
+    }
+}
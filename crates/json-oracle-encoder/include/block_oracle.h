@@ -0,0 +1,67 @@
+#ifndef BLOCK_ORACLE_H
+#define BLOCK_ORACLE_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define BLOCK_ORACLE_OK 0
+
+#define BLOCK_ORACLE_ERROR 1
+
+// Bytes owned by this library. JSON isn't NUL-terminated.
+typedef struct BlockOracleBuffer {
+  uint8_t *data;
+  uintptr_t len;
+} BlockOracleBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Encodes a JSON list with a single message block into a payload.
+//
+// # Safety
+//
+// `json` must be a NUL-terminated string, and `out` must point to a writable
+// [`BlockOracleBuffer`].
+int block_oracle_encode(const char *json, struct BlockOracleBuffer *out);
+
+// Decodes a payload into a JSON list with a single message block. `network_count` is the number
+// of networks that were registered before the payload.
+//
+// # Safety
+//
+// `payload` must point to `payload_len` readable bytes, and `out` must point to a writable
+// [`BlockOracleBuffer`].
+int block_oracle_decode(const uint8_t *payload,
+                        uintptr_t payload_len,
+                        uint64_t network_count,
+                        struct BlockOracleBuffer *out);
+
+// Like [`block_oracle_decode`], but for the calldata of a DataEdge transaction.
+//
+// # Safety
+//
+// `calldata` must point to `calldata_len` readable bytes, and `out` must point to a writable
+// [`BlockOracleBuffer`].
+int block_oracle_decode_calldata(const uint8_t *calldata,
+                                 uintptr_t calldata_len,
+                                 uint64_t network_count,
+                                 struct BlockOracleBuffer *out);
+
+// Releases a buffer that one of the other functions wrote.
+//
+// # Safety
+//
+// `buffer` must have been written by this library, and not released yet.
+void block_oracle_buffer_free(struct BlockOracleBuffer buffer);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif // BLOCK_ORACLE_H
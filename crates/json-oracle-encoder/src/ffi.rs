@@ -0,0 +1,156 @@
+//! `extern "C"` bindings, so that tooling in other languages can parse DataEdge calldata with the
+//! oracle's codec. Messages are passed around as JSON, in the same format as for the
+//! `block-oracle encode` command. The C header is `include/block_oracle.h`, which is generated with
+//! `cbindgen --config cbindgen.toml --output include/block_oracle.h` from this crate's directory.
+//!
+//! Every function writes its output into a [`BlockOracleBuffer`] and returns
+//! [`BLOCK_ORACLE_OK`], or [`BLOCK_ORACLE_ERROR`] with a UTF-8 error message in the buffer instead.
+//! Either way, the buffer must be released with [`block_oracle_buffer_free`].
+
+use std::ffi::{c_char, c_int, CStr};
+
+pub const BLOCK_ORACLE_OK: c_int = 0;
+pub const BLOCK_ORACLE_ERROR: c_int = 1;
+
+/// Bytes owned by this library. JSON isn't NUL-terminated.
+#[repr(C)]
+pub struct BlockOracleBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl BlockOracleBuffer {
+    fn new(bytes: Vec<u8>) -> Self {
+        let bytes = Box::into_raw(bytes.into_boxed_slice());
+        Self {
+            data: bytes as *mut u8,
+            len: bytes.len(),
+        }
+    }
+}
+
+/// Encodes a JSON list with a single message block into a payload.
+///
+/// # Safety
+///
+/// `json` must be a NUL-terminated string, and `out` must point to a writable
+/// [`BlockOracleBuffer`].
+#[no_mangle]
+pub unsafe extern "C" fn block_oracle_encode(
+    json: *const c_char,
+    out: *mut BlockOracleBuffer,
+) -> c_int {
+    let result = CStr::from_ptr(json)
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(|json| Ok(serde_json::from_str(json)?))
+        .and_then(crate::messages_to_payload);
+    write_result(result, out)
+}
+
+/// Decodes a payload into a JSON list with a single message block. `network_count` is the number
+/// of networks that were registered before the payload.
+///
+/// # Safety
+///
+/// `payload` must point to `payload_len` readable bytes, and `out` must point to a writable
+/// [`BlockOracleBuffer`].
+#[no_mangle]
+pub unsafe extern "C" fn block_oracle_decode(
+    payload: *const u8,
+    payload_len: usize,
+    network_count: u64,
+    out: *mut BlockOracleBuffer,
+) -> c_int {
+    let payload = std::slice::from_raw_parts(payload, payload_len);
+    let result = crate::payload_to_messages(payload, network_count).map(|json| json.to_string());
+    write_result(result.map(String::into_bytes), out)
+}
+
+/// Like [`block_oracle_decode`], but for the calldata of a DataEdge transaction.
+///
+/// # Safety
+///
+/// `calldata` must point to `calldata_len` readable bytes, and `out` must point to a writable
+/// [`BlockOracleBuffer`].
+#[no_mangle]
+pub unsafe extern "C" fn block_oracle_decode_calldata(
+    calldata: *const u8,
+    calldata_len: usize,
+    network_count: u64,
+    out: *mut BlockOracleBuffer,
+) -> c_int {
+    let calldata = std::slice::from_raw_parts(calldata, calldata_len);
+    let result = crate::calldata_to_payload(calldata)
+        .and_then(|payload| crate::payload_to_messages(&payload, network_count))
+        .map(|json| json.to_string());
+    write_result(result.map(String::into_bytes), out)
+}
+
+/// Releases a buffer that one of the other functions wrote.
+///
+/// # Safety
+///
+/// `buffer` must have been written by this library, and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn block_oracle_buffer_free(buffer: BlockOracleBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+unsafe fn write_result(result: anyhow::Result<Vec<u8>>, out: *mut BlockOracleBuffer) -> c_int {
+    let (status, bytes) = match result {
+        Ok(bytes) => (BLOCK_ORACLE_OK, bytes),
+        Err(e) => (BLOCK_ORACLE_ERROR, format!("{e:#}").into_bytes()),
+    };
+    out.write(BlockOracleBuffer::new(bytes));
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    unsafe fn take(buffer: BlockOracleBuffer) -> Vec<u8> {
+        let bytes = std::slice::from_raw_parts(buffer.data, buffer.len).to_vec();
+        block_oracle_buffer_free(buffer);
+        bytes
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let json = r#"[{"message":"RegisterNetworks","add":["eip155:1"],"remove":[]}]"#;
+        let json = CString::new(json).unwrap();
+        let mut out = BlockOracleBuffer::new(vec![]);
+        unsafe {
+            assert_eq!(
+                block_oracle_encode(json.as_ptr(), &mut out),
+                BLOCK_ORACLE_OK
+            );
+            let payload = take(out);
+
+            let calldata = crate::calldata(payload);
+            let mut out = BlockOracleBuffer::new(vec![]);
+            let status =
+                block_oracle_decode_calldata(calldata.as_ptr(), calldata.len(), 0, &mut out);
+            assert_eq!(status, BLOCK_ORACLE_OK);
+            let decoded: serde_json::Value = serde_json::from_slice(&take(out)).unwrap();
+            assert_eq!(decoded[0][0]["add"][0], "eip155:1");
+        }
+    }
+
+    #[test]
+    fn errors_are_messages() {
+        let mut out = BlockOracleBuffer::new(vec![]);
+        unsafe {
+            let status = block_oracle_decode_calldata([0xff].as_ptr(), 1, 0, &mut out);
+            assert_eq!(status, BLOCK_ORACLE_ERROR);
+            assert!(!String::from_utf8(take(out)).unwrap().is_empty());
+        }
+    }
+}
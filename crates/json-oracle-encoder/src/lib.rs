@@ -1,8 +1,12 @@
 use anyhow::anyhow;
 use epoch_encoding as ee;
-use ethabi::{encode, short_signature, ParamType, Token};
-use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::BTreeMap;
+use ethabi::{decode, encode, short_signature, ParamType, Token};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 type EncodedMessageBlocks = Vec<(Vec<&'static str>, Vec<u8>)>;
 
@@ -68,17 +72,26 @@ fn messages_to_encoded_message_blocks(
             let message_type = message.message_type();
             let ready_to_encode = match message {
                 Message::Reset => ee::CompressedMessage::Reset,
-                Message::CorrectEpochs {} => ee::CompressedMessage::CorrectEpochs {
-                    data_by_network_id: BTreeMap::new(),
+                Message::CorrectEpochs { corrections } => ee::CompressedMessage::CorrectEpochs {
+                    data_by_network_id: corrections
+                        .into_iter()
+                        .map(Correction::into_epoch_details)
+                        .collect::<anyhow::Result<_>>()?,
                 },
                 Message::UpdateVersion { version_number } => {
                     ee::CompressedMessage::UpdateVersion { version_number }
                 }
                 Message::RegisterNetworks { remove, add } => {
-                    ee::CompressedMessage::RegisterNetworks { remove, add }
+                    ee::CompressedMessage::RegisterNetworks {
+                        remove: remove.iter().map(Removal::index).collect(),
+                        add,
+                    }
                 }
                 Message::RegisterNetworksAndAliases { remove, add } => {
-                    ee::CompressedMessage::RegisterNetworksAndAliases { remove, add }
+                    ee::CompressedMessage::RegisterNetworksAndAliases {
+                        remove: remove.iter().map(Removal::index).collect(),
+                        add,
+                    }
                 }
                 Message::ChangePermissions {
                     address,
@@ -94,34 +107,106 @@ fn messages_to_encoded_message_blocks(
                         .map(|x| ee::Message::str_to_u64(x.as_str()))
                         .collect(),
                 },
-                Message::SetBlockNumbersForNextEpoch(SetBlockNumbersForNextEpoch::Empty {
-                    count,
-                }) => ee::CompressedMessage::SetBlockNumbersForNextEpoch(
-                    ee::CompressedSetBlockNumbersForNextEpoch::Empty { count },
-                ),
-                Message::SetBlockNumbersForNextEpoch(SetBlockNumbersForNextEpoch::NonEmpty {
-                    merkle_root,
-                    accelerations,
-                }) => ee::CompressedMessage::SetBlockNumbersForNextEpoch(
-                    ee::CompressedSetBlockNumbersForNextEpoch::NonEmpty {
-                        root: merkle_root.try_into().map_err(|_| {
-                            anyhow!("Bad JSON: The Merkle root must have exactly 32 bytes.")
-                        })?,
-                        accelerations,
-                    },
-                ),
+                Message::SetBlockNumbersForNextEpoch(block_numbers) => {
+                    ee::CompressedMessage::SetBlockNumbersForNextEpoch(block_numbers.compress()?)
+                }
+                Message::RegisterNetworksAndSetBlockNumbers {
+                    remove,
+                    add,
+                    block_numbers,
+                } => ee::CompressedMessage::RegisterNetworksAndSetBlockNumbers {
+                    remove: remove.iter().map(Removal::index).collect(),
+                    add,
+                    block_numbers: block_numbers.compress()?,
+                },
             };
             message_types.push(message_type);
             compressed_contents.push(ready_to_encode);
         }
         let mut payload = Vec::new();
-        ee::serialize_messages(&compressed_contents[..], &mut payload);
+        ee::serialize_messages(&compressed_contents[..], &mut payload)?;
         encoded_message_blocks.push((message_types, payload));
     }
 
     Ok(encoded_message_blocks)
 }
 
+/// The inverse of [`messages_to_payload`]: decodes a payload into a list with a single message
+/// block. `network_count` is the number of networks that were registered before the payload.
+pub fn payload_to_messages(
+    payload: &[u8],
+    network_count: u64,
+) -> anyhow::Result<serde_json::Value> {
+    let messages = ee::json::to_json(payload, network_count)?;
+    Ok(serde_json::Value::Array(vec![messages]))
+}
+
+/// Checks that every network removal targets the intended network, given the networks that are
+/// currently registered in the Epoch Subgraph, ordered by their index.
+///
+/// Removals must name the network they are meant to remove. Message blocks are applied in order,
+/// so removals and additions shift the indices of later messages.
+pub fn verify_removals(json: &serde_json::Value, networks: &[String]) -> anyhow::Result<()> {
+    let message_blocks: Vec<MessageBlock> = serde_json::from_value(json.clone())?;
+    let mut networks = networks.to_vec();
+
+    for message in message_blocks.iter().flat_map(MessageBlock::messages) {
+        let (remove, add): (&[Removal], Vec<&String>) = match message {
+            Message::RegisterNetworks { remove, add } => (remove, add.iter().collect()),
+            Message::RegisterNetworksAndAliases { remove, add } => {
+                (remove, add.iter().map(|(chain_id, _)| chain_id).collect())
+            }
+            Message::RegisterNetworksAndSetBlockNumbers { remove, add, .. } => {
+                (remove, add.iter().collect())
+            }
+            _ => continue,
+        };
+
+        let mut indices = vec![];
+        for removal in remove {
+            let index = removal.index();
+            let Some(actual) = networks.get(index as usize) else {
+                return Err(anyhow!(
+                    "Can't remove network #{index}, as there are only {} networks",
+                    networks.len()
+                ));
+            };
+            match removal {
+                Removal::Index(_) => {
+                    return Err(anyhow!(
+                        "The removal of network #{index} doesn't name the network it's meant to \
+                         remove, which currently is '{actual}'"
+                    ))
+                }
+                Removal::Network { chain_id, .. } if chain_id != actual => {
+                    let intended = match networks.iter().position(|id| id == chain_id) {
+                        Some(i) => format!("#{i}"),
+                        None => "not registered".to_string(),
+                    };
+                    return Err(anyhow!(
+                        "Network #{index} is '{actual}', not '{chain_id}' ({intended})"
+                    ));
+                }
+                Removal::Network { .. } => {}
+            }
+            if indices.contains(&index) {
+                return Err(anyhow!("Network #{index} is removed twice"));
+            }
+            indices.push(index);
+        }
+
+        // Mirrors the subgraph, which keeps the order of the remaining networks.
+        let mut i = 0;
+        networks.retain(|_| {
+            i += 1;
+            !indices.contains(&(i - 1))
+        });
+        networks.extend(add.into_iter().cloned());
+    }
+
+    Ok(())
+}
+
 /// Whether the JSON encoder should output the payload of the compressed messages, or the full
 /// calldata.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -137,17 +222,47 @@ pub enum MessageBlock {
     MessageBlockWithOneMessage(Message),
 }
 
+impl MessageBlock {
+    fn messages(&self) -> &[Message] {
+        match self {
+            MessageBlock::MessageBlock(messages) => messages,
+            MessageBlock::MessageBlockWithOneMessage(message) => std::slice::from_ref(message),
+        }
+    }
+}
+
+/// A network to remove, referenced by its index in the Epoch Subgraph's list of networks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Removal {
+    Index(u64),
+    /// Also names the network, so that the index can be checked with [`verify_removals`].
+    #[serde(rename_all = "camelCase")]
+    Network {
+        index: u64,
+        chain_id: String,
+    },
+}
+
+impl Removal {
+    pub fn index(&self) -> u64 {
+        match self {
+            Removal::Index(index) | Removal::Network { index, .. } => *index,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "message")]
 #[serde(rename_all = "PascalCase")]
 pub enum Message {
     SetBlockNumbersForNextEpoch(SetBlockNumbersForNextEpoch),
     CorrectEpochs {
-        // TODO.
+        corrections: Vec<Correction>,
     },
     #[serde(rename_all = "camelCase")]
     RegisterNetworks {
-        remove: Vec<u64>,
+        remove: Vec<Removal>,
         add: Vec<String>,
     },
     #[serde(rename_all = "camelCase")]
@@ -156,15 +271,21 @@ pub enum Message {
     },
     Reset,
     RegisterNetworksAndAliases {
-        remove: Vec<u64>,
+        remove: Vec<Removal>,
         add: Vec<(String, String)>,
     },
     ChangePermissions {
-        #[serde(deserialize_with = "deserialize_hex")]
+        #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
         address: Vec<u8>,
         valid_through: u64,
         permissions: Vec<String>,
     },
+    #[serde(rename_all = "camelCase")]
+    RegisterNetworksAndSetBlockNumbers {
+        remove: Vec<Removal>,
+        add: Vec<String>,
+        block_numbers: SetBlockNumbersForNextEpoch,
+    },
 }
 
 impl Message {
@@ -177,10 +298,38 @@ impl Message {
             Message::Reset => "Reset",
             Message::RegisterNetworksAndAliases { .. } => "RegisterNetworksAndAliases",
             Message::ChangePermissions { .. } => "ChangePermissions",
+            Message::RegisterNetworksAndSetBlockNumbers { .. } => {
+                "RegisterNetworksAndSetBlockNumbers"
+            }
         }
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Correction {
+    network_index: u64,
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    tx_hash: Vec<u8>,
+    #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
+    merkle_root: Vec<u8>,
+}
+
+impl Correction {
+    fn into_epoch_details(self) -> anyhow::Result<(u64, ee::messages::EpochDetails)> {
+        let bytes32 = |bytes: Vec<u8>, field: &str| -> anyhow::Result<[u8; 32]> {
+            bytes
+                .try_into()
+                .map_err(|_| anyhow!("Bad JSON: The {field} must have exactly 32 bytes."))
+        };
+        let details = ee::messages::EpochDetails::new(
+            bytes32(self.tx_hash, "transaction hash")?,
+            bytes32(self.merkle_root, "Merkle root")?,
+        );
+        Ok((self.network_index, details))
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 #[serde(rename_all = "camelCase")]
@@ -189,12 +338,29 @@ pub enum SetBlockNumbersForNextEpoch {
     Empty { count: u64 },
     #[serde(rename_all = "camelCase")]
     NonEmpty {
-        #[serde(deserialize_with = "deserialize_hex")]
+        #[serde(serialize_with = "serialize_hex", deserialize_with = "deserialize_hex")]
         merkle_root: Vec<u8>,
         accelerations: Vec<i64>,
     },
 }
 
+impl SetBlockNumbersForNextEpoch {
+    fn compress(self) -> anyhow::Result<ee::CompressedSetBlockNumbersForNextEpoch> {
+        Ok(match self {
+            Self::Empty { count } => ee::CompressedSetBlockNumbersForNextEpoch::Empty { count },
+            Self::NonEmpty {
+                merkle_root,
+                accelerations,
+            } => ee::CompressedSetBlockNumbersForNextEpoch::NonEmpty {
+                root: merkle_root.try_into().map_err(|_| {
+                    anyhow!("Bad JSON: The Merkle root must have exactly 32 bytes.")
+                })?,
+                accelerations,
+            },
+        })
+    }
+}
+
 fn deserialize_hex<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
 where
     D: Deserializer<'de>,
@@ -203,9 +369,150 @@ where
     hex::decode(s.strip_prefix("0x").unwrap_or(s.as_str())).map_err(serde::de::Error::custom)
 }
 
+fn serialize_hex<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&format!("0x{}", hex::encode(bytes)))
+}
+
 pub fn calldata(payload: Vec<u8>) -> Vec<u8> {
     let signature = short_signature("crossChainEpochOracle", &[ParamType::Bytes]);
     let payload = Token::Bytes(payload);
     let encoded = encode(&[payload]);
     signature.into_iter().chain(encoded).collect()
 }
+
+/// The inverse of [`calldata`].
+pub fn calldata_to_payload(calldata: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let signature = short_signature("crossChainEpochOracle", &[ParamType::Bytes]);
+    let arguments = calldata
+        .strip_prefix(&signature[..])
+        .ok_or_else(|| anyhow!("The calldata doesn't call crossChainEpochOracle"))?;
+    match &decode(&[ParamType::Bytes], arguments)?[..] {
+        [Token::Bytes(payload)] => Ok(payload.clone()),
+        tokens => Err(anyhow!("Unexpected calldata arguments: {tokens:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn networks() -> Vec<String> {
+        ["eip155:1", "eip155:100", "eip155:137"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    #[test]
+    fn removals_by_index_and_chain_id_encode_the_same() {
+        let by_index = messages_to_payload(json!([{
+            "message": "RegisterNetworks", "add": [], "remove": [1]
+        }]))
+        .unwrap();
+        let by_chain_id = messages_to_payload(json!([{
+            "message": "RegisterNetworks",
+            "add": [],
+            "remove": [{ "index": 1, "chainId": "eip155:100" }]
+        }]))
+        .unwrap();
+        assert_eq!(by_index, by_chain_id);
+    }
+
+    #[test]
+    fn calldata_round_trip() {
+        let payload = messages_to_payload(json!([{ "message": "Reset" }])).unwrap();
+        assert_eq!(
+            calldata_to_payload(&calldata(payload.clone())).unwrap(),
+            payload
+        );
+        assert!(calldata_to_payload(&payload).is_err());
+    }
+
+    #[test]
+    fn correct_epochs() {
+        let payload = messages_to_payload(json!([{
+            "message": "CorrectEpochs",
+            "corrections": [{
+                "networkIndex": 1,
+                "txHash": format!("0x{}", "11".repeat(32)),
+                "merkleRoot": "22".repeat(32)
+            }]
+        }]))
+        .unwrap();
+        let [message] = &ee::decode_messages_with_networks(&payload, 2).unwrap()[..] else {
+            panic!("expected a single message");
+        };
+        let ee::CompressedMessage::CorrectEpochs { data_by_network_id } = message else {
+            panic!("unexpected message: {message:?}");
+        };
+        assert_eq!(
+            data_by_network_id[&1],
+            ee::messages::EpochDetails::new([0x11; 32], [0x22; 32])
+        );
+    }
+
+    #[test]
+    fn payloads_decode_to_json() {
+        let json = json!([[
+            { "message": "RegisterNetworks", "add": ["eip155:1", "eip155:100"], "remove": [] },
+            {
+                "message": "SetBlockNumbersForNextEpoch",
+                "merkleRoot": format!("0x{}", "33".repeat(32)),
+                "accelerations": [10, -2]
+            },
+            {
+                "message": "ChangePermissions",
+                "address": format!("0x{}", "44".repeat(20)),
+                "valid_through": 100,
+                "permissions": ["ResetStateMessage"]
+            },
+            { "message": "Reset" }
+        ]]);
+        let payload = messages_to_payload(json.clone()).unwrap();
+        assert_eq!(payload_to_messages(&payload, 0).unwrap(), json);
+    }
+
+    #[test]
+    fn register_networks_and_set_block_numbers() {
+        let json = json!([[{
+            "message": "RegisterNetworksAndSetBlockNumbers",
+            "remove": [],
+            "add": ["eip155:1"],
+            "blockNumbers": { "merkleRoot": format!("0x{}", "33".repeat(32)), "accelerations": [10] }
+        }]]);
+        let payload = messages_to_payload(json.clone()).unwrap();
+        assert_eq!(payload_to_messages(&payload, 0).unwrap(), json);
+    }
+
+    #[test]
+    fn verify_removals_follows_reordering() {
+        // After removing #0, `eip155:137` moves to #1.
+        let json = json!([
+            [{ "message": "RegisterNetworks", "add": [], "remove": [{ "index": 0, "chainId": "eip155:1" }] }],
+            [{ "message": "RegisterNetworks", "add": [], "remove": [{ "index": 1, "chainId": "eip155:137" }] }]
+        ]);
+        verify_removals(&json, &networks()).unwrap();
+
+        let json = json!([
+            [{ "message": "RegisterNetworks", "add": [], "remove": [{ "index": 0, "chainId": "eip155:1" }] }],
+            [{ "message": "RegisterNetworks", "add": [], "remove": [{ "index": 2, "chainId": "eip155:137" }] }]
+        ]);
+        assert!(verify_removals(&json, &networks()).is_err());
+    }
+
+    #[test]
+    fn verify_removals_rejects_mismatches() {
+        for remove in [
+            json!([1]),
+            json!([{ "index": 1, "chainId": "eip155:137" }]),
+            json!([{ "index": 3, "chainId": "eip155:137" }]),
+            json!([{ "index": 1, "chainId": "eip155:100" }, { "index": 1, "chainId": "eip155:100" }]),
+        ] {
+            let json = json!([{ "message": "RegisterNetworks", "add": [], "remove": remove }]);
+            assert!(verify_removals(&json, &networks()).is_err(), "{json}");
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! [`wasm_bindgen`] bindings, so that web tooling and the Epoch Subgraph's tests can share the
+//! oracle's codec instead of maintaining their own. Messages are passed around as JSON, in the same
+//! format as for the `block-oracle encode` command.
+
+use wasm_bindgen::prelude::*;
+
+/// Encodes a list with a single message block into a payload. See [`messages_to_payload`](crate::messages_to_payload).
+#[wasm_bindgen]
+pub fn encode_messages(json: &str) -> Result<Vec<u8>, String> {
+    let json_value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    crate::messages_to_payload(json_value).map_err(|e| e.to_string())
+}
+
+/// Decodes a payload into a list with a single JSON message block. `network_count` is the number of networks that were
+/// registered before the payload. See [`payload_to_messages`](crate::payload_to_messages).
+#[wasm_bindgen]
+pub fn decode_messages(payload: &[u8], network_count: u32) -> Result<String, String> {
+    let json_value =
+        crate::payload_to_messages(payload, network_count.into()).map_err(|e| e.to_string())?;
+    Ok(json_value.to_string())
+}
@@ -38,7 +38,51 @@ fn check_build_requirements() -> Result<(), String> {
     Ok(())
 }
 
+/// Return the commit the crate is being built from.
+///
+/// The `BLOCK_ORACLE_GIT_COMMIT` env variable takes precedence, which is useful when building
+/// outside of a git checkout (e.g. inside a Docker container).
+fn git_commit() -> String {
+    println!("cargo:rerun-if-env-changed=BLOCK_ORACLE_GIT_COMMIT");
+    if let Ok(commit) = env::var("BLOCK_ORACLE_GIT_COMMIT") {
+        return commit;
+    }
+    rerun_if_head_changed();
+    git(&["rev-parse", "--short=12", "HEAD"]).unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Make Cargo run the build script again when a commit is checked out or made, so that the
+/// embedded commit doesn't go stale on incremental builds.
+fn rerun_if_head_changed() {
+    let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]).map(PathBuf::from) else {
+        return;
+    };
+    // `HEAD` changes when switching branches, the ref it points to when committing. The ref may
+    // also only exist in `packed-refs`. Missing files would make Cargo rerun the script every time.
+    let mut paths = vec![git_dir.join("HEAD"), git_dir.join("packed-refs")];
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        paths.push(git_dir.join(head_ref));
+    }
+    for path in paths.into_iter().filter(|path| path.exists()) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+/// Runs a git command and returns its trimmed output, if it succeeded.
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+}
+
 fn main() {
+    // Expose build information to `build_info`.
+    println!("cargo:rustc-env=BLOCK_ORACLE_GIT_COMMIT={}", git_commit());
+
     // Run code generation only if 'proto-gen' feature is enabled.
     if env::var("CARGO_FEATURE_PROTO_GEN").is_ok() {
         // Check if all the build requirements are met.
@@ -0,0 +1,197 @@
+//! What the oracle's transactions cost, as recorded in the [`History`](crate::history::History).
+//!
+//! Reverted transactions count too, since their gas was spent all the same. Payloads that were
+//! never mined, e.g. dropped or cancelled ones, are skipped. The transactions that cancelled them
+//! aren't in the history, so their gas is missing from these numbers.
+
+use crate::history::PublishedPayload;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use web3::types::U256;
+
+const WEI_PER_ETH: u64 = 1_000_000_000_000_000_000;
+
+/// The total cost of some transactions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Costs {
+    pub transactions: u64,
+    pub gas_used: U256,
+    /// The gas used times the effective gas price.
+    pub wei: U256,
+    /// Transactions without a recorded gas price, e.g. from older history entries. Their gas is
+    /// included in `gas_used`, but missing from `wei`.
+    pub unpriced: u64,
+}
+
+impl Costs {
+    pub fn of<'a>(payloads: impl IntoIterator<Item = &'a PublishedPayload>) -> Self {
+        let mut costs = Self::default();
+        for payload in payloads {
+            costs.add(payload);
+        }
+        costs
+    }
+
+    pub fn add(&mut self, payload: &PublishedPayload) {
+        if !payload.status.is_mined() {
+            return;
+        }
+        let gas_used = payload.gas_used.unwrap_or_default();
+        self.transactions += 1;
+        self.gas_used = self.gas_used.saturating_add(gas_used);
+        match payload.effective_gas_price {
+            Some(gas_price) => {
+                self.wei = self.wei.saturating_add(gas_used.saturating_mul(gas_price))
+            }
+            None => self.unpriced += 1,
+        }
+    }
+
+    /// [`Costs::wei`] in ETH, for metrics.
+    pub fn eth(&self) -> f64 {
+        let (eth, wei) = self.wei.div_mod(WEI_PER_ETH.into());
+        eth.low_u128() as f64 + wei.low_u64() as f64 / WEI_PER_ETH as f64
+    }
+
+    /// [`Costs::wei`] in ETH, with all 18 decimals.
+    pub fn eth_string(&self) -> String {
+        let (eth, wei) = self.wei.div_mod(WEI_PER_ETH.into());
+        format!("{eth}.{:018}", wei.low_u64())
+    }
+}
+
+/// How to group costs in a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Epoch,
+    /// Calendar months in UTC, by confirmation time.
+    Month,
+}
+
+impl std::str::FromStr for Period {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoch" => Ok(Self::Epoch),
+            "month" => Ok(Self::Month),
+            _ => Err(format!("Unknown period '{s}', expected 'epoch' or 'month'")),
+        }
+    }
+}
+
+/// The costs of `payloads` per `period`, e.g. per epoch or per `2024-01`.
+pub fn costs_by<'a>(
+    period: Period,
+    payloads: impl IntoIterator<Item = &'a PublishedPayload>,
+) -> BTreeMap<PeriodKey, Costs> {
+    let mut costs: BTreeMap<PeriodKey, Costs> = BTreeMap::new();
+    for payload in payloads {
+        let key = match period {
+            Period::Epoch => PeriodKey::Epoch(payload.epoch),
+            Period::Month => PeriodKey::Month(month(payload.timestamp)),
+        };
+        costs.entry(key).or_default().add(payload);
+    }
+    costs
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PeriodKey {
+    Epoch(u64),
+    Month(String),
+}
+
+impl Display for PeriodKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeriodKey::Epoch(epoch) => write!(f, "{epoch}"),
+            PeriodKey::Month(month) => f.write_str(month),
+        }
+    }
+}
+
+fn month(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|time| time.format("%Y-%m").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::SubmissionStatus;
+
+    fn payload(
+        epoch: u64,
+        timestamp: u64,
+        gas_used: u64,
+        gas_price: Option<u64>,
+    ) -> PublishedPayload {
+        PublishedPayload {
+            epoch,
+            timestamp,
+            correlation_id: String::new(),
+            messages: vec![],
+            payload: String::new(),
+            tx_hash: Default::default(),
+            status: SubmissionStatus::Confirmed,
+            nonce: None,
+            block_number: None,
+            gas_used: Some(gas_used.into()),
+            effective_gas_price: gas_price.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn totals() {
+        let costs = Costs::of(&[
+            payload(1, 0, 100_000, Some(5_000_000_000_000)),
+            payload(1, 0, 50_000, Some(10_000_000_000_000)),
+            payload(2, 0, 21_000, None),
+        ]);
+        assert_eq!(costs.transactions, 3);
+        assert_eq!(costs.gas_used, 171_000.into());
+        assert_eq!(costs.wei, 1_000_000_000_000_000_000u64.into());
+        assert_eq!(costs.unpriced, 1);
+        assert_eq!(costs.eth_string(), "1.000000000000000000");
+        assert_eq!(costs.eth(), 1.0);
+
+        let costs = Costs::of(&[payload(1, 0, 21_000, Some(1_000_000_000))]);
+        assert_eq!(costs.eth_string(), "0.000021000000000000");
+
+        let dropped = PublishedPayload {
+            status: SubmissionStatus::Dropped,
+            gas_used: None,
+            ..payload(1, 0, 0, None)
+        };
+        assert_eq!(Costs::of(&[dropped]), Costs::default());
+    }
+
+    #[test]
+    fn periods() {
+        // 2024-01-31T23:59:59Z and 2024-02-01T00:00:00Z.
+        let payloads = [
+            payload(10, 1706745599, 100, Some(1)),
+            payload(10, 1706745600, 200, Some(1)),
+            payload(11, 1706745600, 300, Some(1)),
+        ];
+
+        let by_epoch = costs_by(Period::Epoch, &payloads);
+        let epochs: Vec<_> = by_epoch
+            .iter()
+            .map(|(key, costs)| (key.to_string(), costs.gas_used.as_u64()))
+            .collect();
+        assert_eq!(epochs, [("10".to_string(), 300), ("11".to_string(), 300)]);
+
+        let by_month = costs_by(Period::Month, &payloads);
+        let months: Vec<_> = by_month
+            .iter()
+            .map(|(key, costs)| (key.to_string(), costs.transactions))
+            .collect();
+        assert_eq!(
+            months,
+            [("2024-01".to_string(), 1), ("2024-02".to_string(), 2)]
+        );
+    }
+}
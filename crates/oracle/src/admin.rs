@@ -0,0 +1,242 @@
+//! An HTTP API for operators to inspect and tweak a running oracle.
+//!
+//! Unlike the metrics server, the admin API only listens on the loopback interface.
+
+use crate::runner::endpoints::{EndpointId, Endpoints, RotationError};
+use crate::Caip2ChainId;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Allows replacing the global log filter at runtime.
+pub type LogFilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Wakes up an oracle that was paused by an
+/// [`ErrorAction::Pause`](crate::runner::error_handling::ErrorAction::Pause) policy.
+pub type ResumeHandle = Arc<Notify>;
+
+pub async fn admin_server(
+    port: u16,
+    log_filter: LogFilterHandle,
+    resume: ResumeHandle,
+    endpoints: Endpoints,
+) {
+    info!("Starting admin server at 127.0.0.1:{port}");
+    warp::serve(routes(log_filter, resume, endpoints))
+        .run(([127, 0, 0, 1], port))
+        .await;
+}
+
+/// - `GET /log-filter` returns the current log filter.
+/// - `PUT /log-filter` replaces it with the directives in the request body, e.g.
+///   `block_oracle=debug,info`. See [`EnvFilter`] for the syntax.
+/// - `POST /resume` resumes polling if the oracle is paused.
+/// - `PUT /endpoints/protocol-chain` and `PUT /endpoints/<chain ID>/<index>` point a JSON RPC
+///   endpoint to the URL in the request body, e.g. after rotating its API key. `index` is the
+///   position of the URL among the chain's `jrpc` URLs in the configuration file.
+fn routes(
+    log_filter: LogFilterHandle,
+    resume: ResumeHandle,
+    endpoints: Endpoints,
+) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let get_log_filter = {
+        let log_filter = log_filter.clone();
+        warp::path("log-filter")
+            .and(warp::path::end())
+            .and(warp::get())
+            .map(move || {
+                let reply = log_filter
+                    .with_current(|filter| filter.to_string())
+                    .unwrap_or_else(|error| error.to_string());
+                warp::reply::with_status(reply, StatusCode::OK)
+            })
+    };
+    let put_log_filter = warp::path("log-filter")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::body::bytes())
+        .map(move |body: warp::hyper::body::Bytes| {
+            match set_log_filter(&log_filter, &String::from_utf8_lossy(&body)) {
+                Ok(()) => warp::reply::with_status("OK".to_string(), StatusCode::OK),
+                Err(error) => warp::reply::with_status(error, StatusCode::BAD_REQUEST),
+            }
+        });
+    let post_resume = warp::path("resume")
+        .and(warp::path::end())
+        .and(warp::post())
+        .map(move || {
+            resume.notify_waiters();
+            warp::reply::with_status("OK".to_string(), StatusCode::OK)
+        });
+    let put_protocol_chain_endpoint = {
+        let endpoints = endpoints.clone();
+        warp::path!("endpoints" / "protocol-chain")
+            .and(warp::put())
+            .and(warp::body::bytes())
+            .map(move |body: warp::hyper::body::Bytes| {
+                rotate_endpoint(&endpoints, EndpointId::ProtocolChain, &body)
+            })
+    };
+    let put_indexed_chain_endpoint = warp::path!("endpoints" / Caip2ChainId / usize)
+        .and(warp::put())
+        .and(warp::body::bytes())
+        .map(move |chain_id, index, body: warp::hyper::body::Bytes| {
+            rotate_endpoint(
+                &endpoints,
+                EndpointId::IndexedChain { chain_id, index },
+                &body,
+            )
+        });
+    get_log_filter
+        .or(put_log_filter)
+        .or(post_resume)
+        .or(put_protocol_chain_endpoint)
+        .or(put_indexed_chain_endpoint)
+}
+
+fn rotate_endpoint(
+    endpoints: &Endpoints,
+    endpoint: EndpointId,
+    body: &[u8],
+) -> warp::reply::WithStatus<String> {
+    let url = match String::from_utf8_lossy(body).trim().parse() {
+        Ok(url) => url,
+        Err(error) => {
+            let reply = format!("Invalid URL: {error}");
+            return warp::reply::with_status(reply, StatusCode::BAD_REQUEST);
+        }
+    };
+    match endpoints.rotate(&endpoint, &url) {
+        Ok(()) => warp::reply::with_status("OK".to_string(), StatusCode::OK),
+        Err(error @ RotationError::UnknownEndpoint(_)) => {
+            warp::reply::with_status(error.to_string(), StatusCode::NOT_FOUND)
+        }
+        Err(error) => warp::reply::with_status(error.to_string(), StatusCode::BAD_REQUEST),
+    }
+}
+
+fn set_log_filter(log_filter: &LogFilterHandle, directives: &str) -> Result<(), String> {
+    let directives = directives.trim();
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    log_filter.reload(filter).map_err(|e| e.to_string())?;
+    warn!(filter = directives, "The log filter was changed.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jrpc_utils::JrpcExpBackoff;
+    use crate::metrics::Metrics;
+    use std::time::Duration;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn endpoints() -> Endpoints {
+        let transport = |chain_id: Caip2ChainId| {
+            JrpcExpBackoff::http(
+                "http://127.0.0.1:8545".parse().unwrap(),
+                chain_id,
+                Duration::from_secs(1),
+                Metrics::new().unwrap(),
+            )
+        };
+        Endpoints::new(
+            transport("eip155:42161".parse().unwrap()),
+            vec![transport(Caip2ChainId::ethereum_mainnet())],
+        )
+    }
+
+    #[tokio::test]
+    async fn change_log_filter() {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(layer);
+        let routes = routes(handle, ResumeHandle::default(), endpoints());
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/log-filter")
+            .body("block_oracle=trace,warn")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = warp::test::request()
+            .path("/log-filter")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.body(), "block_oracle=trace,warn");
+    }
+
+    #[tokio::test]
+    async fn invalid_log_filter() {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(layer);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/log-filter")
+            .body("block_oracle=not-a-level")
+            .reply(&routes(handle, ResumeHandle::default(), endpoints()))
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(layer);
+        let resume = ResumeHandle::default();
+        let resumed = resume.notified();
+        tokio::pin!(resumed);
+        resumed.as_mut().enable();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/resume")
+            .reply(&routes(handle, resume.clone(), endpoints()))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        resumed.await;
+    }
+
+    #[tokio::test]
+    async fn rotate_endpoints() {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let _subscriber = Registry::default().with(layer);
+        let routes = routes(handle, ResumeHandle::default(), endpoints());
+
+        for (path, body, status) in [
+            (
+                "/endpoints/protocol-chain",
+                "http://127.0.0.1:8546",
+                StatusCode::OK,
+            ),
+            (
+                "/endpoints/eip155:1/0",
+                "http://127.0.0.1:8546",
+                StatusCode::OK,
+            ),
+            (
+                "/endpoints/eip155:1/1",
+                "http://127.0.0.1:8546",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                "/endpoints/eip155:1/0",
+                "not a URL",
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = warp::test::request()
+                .method("PUT")
+                .path(path)
+                .body(body)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), status, "{path}");
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Information about the oracle build, so that it's possible to tell which build produced a given
+//! payload.
+
+use serde::Serialize;
+use std::sync::OnceLock;
+
+/// The version of the `block-oracle` crate.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// The git commit the oracle was built from, or `unknown`.
+pub const GIT_COMMIT: &str = env!("BLOCK_ORACLE_GIT_COMMIT");
+/// The encoding version of the messages the oracle produces.
+pub const ENCODING_VERSION: u64 = epoch_encoding::CURRENT_ENCODING_VERSION;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub encoding_version: u64,
+}
+
+impl BuildInfo {
+    pub const CURRENT: Self = Self {
+        version: VERSION,
+        git_commit: GIT_COMMIT,
+        encoding_version: ENCODING_VERSION,
+    };
+}
+
+/// A human-readable summary of [`BuildInfo::CURRENT`], e.g. for `--version`.
+pub fn long_version() -> &'static str {
+    static LONG_VERSION: OnceLock<String> = OnceLock::new();
+    LONG_VERSION.get_or_init(|| {
+        format!("{VERSION} (commit {GIT_COMMIT}, encoding version {ENCODING_VERSION})")
+    })
+}
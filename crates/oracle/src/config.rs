@@ -1,10 +1,23 @@
+use crate::keystore;
 use crate::models::Caip2ChainId;
+use crate::runner::block_selection::BlockSelectionStrategy;
+use crate::runner::error_handling::{ErrorClass, ErrorPolicy};
 use anyhow::Context;
+use epoch_encoding::OverflowPolicy;
 use secp256k1::SecretKey;
-use serde::Deserialize;
-use serde_utils::{EitherLiteralOrEnvVar, FromStrWrapper};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::IntoDeserializer;
+use serde::{Deserialize, Serialize};
+use serde_utils::{FromStrWrapper, OneOrMany};
 use std::{
-    collections::HashMap, fmt::Display, fs::read_to_string, path::Path, str::FromStr,
+    collections::HashMap,
+    fmt::Display,
+    fs::read_to_string,
+    io::{BufRead, IsTerminal},
+    marker::PhantomData,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    str::FromStr,
     time::Duration,
 };
 use thiserror::Error;
@@ -14,16 +27,81 @@ use web3::types::H160;
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
+    #[error("Failed to read the config file {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// Names the offending key, and for values the expected format.
+    #[error("Invalid TOML config file {}", path.display())]
+    Toml {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("Invalid JSON config file {}", path.display())]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("Invalid YAML config file {}", path.display())]
+    Yaml {
+        path: PathBuf,
+        #[source]
+        source: serde_yaml::Error,
+    },
+    #[error("Failed to read the keystore password from stdin")]
+    PasswordPrompt(#[source] std::io::Error),
+    #[error("Failed to read the owner's private key from {0}")]
+    OwnerKeySource(OwnerKeySource, #[source] std::io::Error),
+    #[error("The owner's private key from {0} isn't a 32-byte hex private key")]
+    InvalidOwnerKey(OwnerKeySource),
+    #[error("{0}")]
+    OwnerKey(&'static str),
     #[error(transparent)]
-    Io(#[from] std::io::Error),
-    #[error("Error deserializing config file")]
-    Toml(#[from] toml::de::Error),
+    Keystore(#[from] crate::keystore::KeystoreError),
+    #[error("{0}")]
+    CoSigning(&'static str),
+    #[error("{0}")]
+    DataEdgeCall(&'static str),
+    #[error("Invalid Blockmeta URL for the indexed chain {chain_id}")]
+    BlockmetaUrl {
+        chain_id: Caip2ChainId,
+        #[source]
+        source: tonic::codegen::http::uri::InvalidUri,
+    },
+    #[error("Invalid settings for the indexed chain {chain_id}: {reason}")]
+    IndexedChain {
+        chain_id: Caip2ChainId,
+        reason: &'static str,
+    },
+    #[error("Failed to read the environment variable `{name}`: {source}")]
+    EnvVar {
+        name: String,
+        #[source]
+        source: std::env::VarError,
+    },
+    #[error("A `${{` placeholder is missing its closing `}}`")]
+    UnterminatedPlaceholder,
 }
 
 #[derive(Clone, Debug)]
 pub struct IndexedChain {
     pub id: Caip2ChainId,
-    pub jrpc_url: Url,
+    /// One or more JSON RPC providers for this chain. When there are multiple, they must agree on
+    /// the block that gets published, unless there's a `failover_cooldown`.
+    pub jrpc_urls: Vec<Url>,
+    pub block_selection: BlockSelectionStrategy,
+    /// How often the block time sampler polls this chain's head, instead of the adaptive interval
+    /// or the protocol chain's polling interval.
+    pub polling_interval: Option<Duration>,
+    /// The most requests per second to send to each of this chain's JSON RPC providers.
+    pub rate_limit: Option<NonZeroU32>,
+    /// With failover, the JSON RPC providers are tried in order rather than having to agree, and
+    /// one that fails is skipped for this long.
+    pub failover_cooldown: Option<Duration>,
 }
 
 #[derive(Clone, Debug)]
@@ -37,6 +115,17 @@ pub struct ProtocolChain {
     pub id: Caip2ChainId,
     pub jrpc_url: Url,
     pub polling_interval: Duration,
+    /// The most requests per second to send to `jrpc_url`, e.g. while scanning blocks for the
+    /// freshness check.
+    pub rate_limit: Option<NonZeroU32>,
+}
+
+/// Where and how often to push metrics, for environments that can't be scraped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PushgatewayOptions {
+    pub url: Url,
+    pub job: String,
+    pub interval: Duration,
 }
 
 #[derive(Clone, Deserialize, Debug, Copy)]
@@ -50,18 +139,226 @@ pub struct TransactionMonitoringOptions {
     /// Gas price percentual increase
     #[serde(default = "serde_defaults::transaction_monitoring_gas_percentual_increase")]
     pub gas_percentual_increase: u32,
+    /// Bumps never raise the gas price, or the max fee per gas with EIP-1559, above this many wei.
+    #[serde(default)]
+    pub max_bumped_gas_price: Option<u64>,
+    /// Transactions that aren't mined this many seconds after they were first sent are cancelled,
+    /// so that their payload can be submitted again with a fresh nonce.
+    #[serde(default)]
+    pub deadline_in_seconds: Option<u64>,
+    /// Submissions are postponed while the estimated gas price, or max fee per gas with EIP-1559,
+    /// is above this many gwei.
+    #[serde(default)]
+    pub max_gas_price_gwei: Option<u64>,
     /// How much time to wait between querying the JSON RPC provider for confirmations
     #[serde(default = "serde_defaults::transaction_monitoring_poll_interval_in_seconds")]
     pub poll_interval_in_seconds: u64,
-    /// How many confirmations to wait for
+    /// How many blocks must be on top of a transaction's block before it counts as confirmed
     #[serde(default = "serde_defaults::transaction_monitoring_confirmations")]
     pub confirmations: usize,
+    /// The gas limit of transactions whose gas can't be estimated.
     #[serde(default = "serde_defaults::transaction_monitoring_gas_limit")]
     pub gas_limit: u64,
+    /// The gas limit of transactions is this percentage of their estimated gas.
+    #[serde(default = "serde_defaults::transaction_monitoring_gas_limit_percentage")]
+    pub gas_limit_percentage: u32,
+    /// Payloads that are estimated to need more gas than this are refused. The gas limit never
+    /// exceeds it either.
+    #[serde(default)]
+    pub max_gas_limit: Option<u64>,
+    /// Payloads are refused if their estimated gas exceeds the protocol chain's block gas limit
+    /// minus this percentage of it.
+    #[serde(default = "serde_defaults::transaction_monitoring_block_gas_limit_margin")]
+    pub block_gas_limit_margin: u32,
+    /// Fixes the max fee of EIP-1559 transactions instead of estimating it.
     #[serde(default)]
     pub max_fee_per_gas: Option<u64>,
+    /// Fixes the priority fee of EIP-1559 transactions instead of estimating it. It never exceeds
+    /// the max fee.
     #[serde(default)]
     pub max_priority_fee_per_gas: Option<u64>,
+    /// Transactions are only sent if the owner's balance covers their maximum cost plus this many
+    /// wei, e.g. to leave room for fee bumps.
+    #[serde(default)]
+    pub balance_buffer: u64,
+    /// What to do at startup with DataEdge transactions that the owner sent but that aren't mined
+    /// yet.
+    #[serde(default)]
+    pub pending_transactions: PendingTransactionPolicy,
+    /// Payloads aren't submitted again if the owner sent an identical one that the Epoch Subgraph
+    /// hasn't indexed yet, e.g. right before the oracle crashed. At most this many of the latest
+    /// blocks are searched. `0` disables the check.
+    #[serde(default = "serde_defaults::transaction_monitoring_duplicate_check_blocks")]
+    pub duplicate_check_blocks: u64,
+    #[serde(default)]
+    pub gas: GasOptions,
+}
+
+/// How transaction fees are estimated. See [`gas`](crate::runner::gas).
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub struct GasOptions {
+    /// How many of the latest blocks the priority fee is estimated from.
+    #[serde(default = "serde_defaults::gas_fee_history_blocks")]
+    pub fee_history_blocks: u64,
+    /// Which percentile of the priority fees paid within each block is taken into account.
+    #[serde(default = "serde_defaults::gas_priority_fee_percentile")]
+    pub priority_fee_percentile: f64,
+    /// The max fee is this percentage of the next block's base fee, plus the priority fee.
+    #[serde(default = "serde_defaults::gas_base_fee_percentage")]
+    pub base_fee_percentage: u32,
+    /// The priority fee is this percentage of the estimate.
+    #[serde(default = "serde_defaults::gas_priority_fee_percentage")]
+    pub priority_fee_percentage: u32,
+    /// On chains without EIP-1559, the gas price is this percentage of `eth_gasPrice`.
+    #[serde(default = "serde_defaults::gas_legacy_gas_price_percentage")]
+    pub legacy_gas_price_percentage: u32,
+    #[serde(default)]
+    pub transaction_type: TransactionType,
+}
+
+/// See [`Contracts::resolve_pending_transactions`](crate::contracts::Contracts::resolve_pending_transactions).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PendingTransactionPolicy {
+    /// Waits for pending transactions to be confirmed, and only cancels those that aren't within
+    /// the confirmation timeout.
+    #[default]
+    Adopt,
+    /// Cancels pending transactions right away, so the next payload doesn't queue behind them.
+    Replace,
+}
+
+/// Which kind of transactions are sent to the protocol chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransactionType {
+    /// EIP-1559 transactions if the protocol chain reports base fees, legacy ones otherwise.
+    #[default]
+    Auto,
+    /// Legacy transactions with a gas price, e.g. for chains that reject EIP-1559 transactions
+    /// despite reporting base fees.
+    Legacy,
+    /// EIP-1559 transactions only. Fails if the protocol chain doesn't report base fees.
+    Eip1559,
+}
+
+impl Default for GasOptions {
+    fn default() -> Self {
+        use serde_defaults::*;
+        Self {
+            fee_history_blocks: gas_fee_history_blocks(),
+            priority_fee_percentile: gas_priority_fee_percentile(),
+            base_fee_percentage: gas_base_fee_percentage(),
+            priority_fee_percentage: gas_priority_fee_percentage(),
+            legacy_gas_price_percentage: gas_legacy_gas_price_percentage(),
+            transaction_type: TransactionType::default(),
+        }
+    }
+}
+
+/// How the oracle makes sure that the Epoch Subgraph has processed all relevant DataEdge
+/// transactions before acting on its state. See `docs/subgraph-freshness.org`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FreshnessCheckOptions {
+    /// Disabling the check is only meant for development environments, e.g. Hardhat, where
+    /// nothing but the oracle itself sends transactions to the DataEdge.
+    pub enabled: bool,
+    pub scan: FreshnessScan,
+    /// Number of blocks that the Epoch Subgraph may be away from the protocol chain's head. If
+    /// the block distance is lower than this, the blocks in between are scanned for relevant
+    /// transactions.
+    pub threshold: u64,
+    pub on_stale: StaleSubgraphAction,
+}
+
+/// How to look for relevant transactions between the subgraph's latest block and the chain head.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FreshnessScan {
+    /// Fetches every block and looks for transactions from the owner to the DataEdge. Works with
+    /// any JSON RPC provider, but doesn't see calls that go through other contracts.
+    #[default]
+    Transactions,
+    /// Uses `trace_filter` to also find internal calls to the DataEdge, e.g. from a multisig.
+    /// Requires a provider with the `trace` API enabled.
+    Traces,
+    /// Uses `eth_getLogs` to find the events emitted by an `EventfulDataEdge`. Cheap, but only
+    /// works for that kind of DataEdge.
+    Logs,
+}
+
+/// What to do when the Epoch Subgraph is not fresh.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StaleSubgraphAction {
+    /// Skip the epoch for now and check again on the next polling iteration.
+    #[default]
+    Defer,
+    /// Log a warning and act on the subgraph's state anyway.
+    Warn,
+}
+
+/// How many failures each dependency may have before it's considered degraded. See
+/// [`ErrorBudget`](crate::runner::error_budget::ErrorBudget).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct ErrorBudgetOptions {
+    /// How many of the most recent outcomes of each dependency are taken into account.
+    #[serde(default = "serde_defaults::error_budget_window")]
+    pub window: usize,
+    /// A dependency is degraded when more than this many of its most recent outcomes are failures.
+    #[serde(default = "serde_defaults::error_budget_max_failures")]
+    pub max_failures: usize,
+    /// Errors caused by a degraded dependency cool down for this many times longer than usual.
+    #[serde(default = "serde_defaults::error_budget_degraded_cooldown_multiplier")]
+    pub degraded_cooldown_multiplier: u32,
+}
+
+impl Default for ErrorBudgetOptions {
+    fn default() -> Self {
+        use serde_defaults::*;
+        Self {
+            window: error_budget_window(),
+            max_failures: error_budget_max_failures(),
+            degraded_cooldown_multiplier: error_budget_degraded_cooldown_multiplier(),
+        }
+    }
+}
+
+/// Which Tokio scheduler the oracle runs on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuntimeFlavor {
+    #[default]
+    MultiThread,
+    CurrentThread,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct RuntimeOptions {
+    #[serde(default)]
+    pub flavor: RuntimeFlavor,
+    /// Number of worker threads of the multi-threaded runtime. Defaults to the number of CPU
+    /// cores.
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+    /// Maximum number of threads used for blocking operations. Defaults to Tokio's default.
+    #[serde(default)]
+    pub max_blocking_threads: Option<usize>,
+}
+
+/// Sampling of indexed chain heads in the background, to learn their block times. See
+/// [`block_times`](crate::runner::block_times).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct AdaptivePollingOptions {
+    /// No chain is sampled more often than this.
+    #[serde(default = "serde_defaults::adaptive_polling_min_sampling_interval_in_seconds")]
+    pub min_sampling_interval_in_seconds: u64,
+    /// No chain is sampled less often than this.
+    #[serde(default = "serde_defaults::adaptive_polling_max_sampling_interval_in_seconds")]
+    pub max_sampling_interval_in_seconds: u64,
+    /// How many blocks a chain is expected to produce between two samples.
+    #[serde(default = "serde_defaults::adaptive_polling_blocks_per_sample")]
+    pub blocks_per_sample: u32,
 }
 
 impl Default for TransactionMonitoringOptions {
@@ -72,66 +369,352 @@ impl Default for TransactionMonitoringOptions {
             ),
             max_retries: transaction_monitoring_max_retries(),
             gas_percentual_increase: transaction_monitoring_gas_percentual_increase(),
+            max_bumped_gas_price: None,
+            deadline_in_seconds: None,
+            max_gas_price_gwei: None,
             poll_interval_in_seconds: transaction_monitoring_poll_interval_in_seconds(),
             confirmations: transaction_monitoring_confirmations(),
             gas_limit: transaction_monitoring_gas_limit(),
+            gas_limit_percentage: transaction_monitoring_gas_limit_percentage(),
+            max_gas_limit: None,
+            block_gas_limit_margin: transaction_monitoring_block_gas_limit_margin(),
             max_fee_per_gas: None,
             max_priority_fee_per_gas: None,
+            balance_buffer: 0,
+            pending_transactions: PendingTransactionPolicy::default(),
+            duplicate_check_blocks: transaction_monitoring_duplicate_check_blocks(),
+            gas: GasOptions::default(),
+        }
+    }
+}
+
+/// Where the owner's key lives, see [`signer`](crate::signer).
+#[derive(Clone, Debug)]
+pub enum OwnerKey {
+    /// From `owner_private_key`, `owner_keystore` or an [`OwnerKeySource`].
+    PrivateKey(SecretKey),
+    AwsKms(AwsKmsOptions),
+    Vault(VaultOptions),
+    Remote(RemoteSignerOptions),
+    /// An OpenZeppelin Defender relayer, which signs and sends transactions itself.
+    Defender(DefenderOptions),
+}
+
+/// Where to read the owner's private key from instead of the config file, so that it stays out of
+/// both the file and the command line. See [`Config::try_parse_with_owner_key`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnerKeySource {
+    /// A file with nothing but the key, e.g. a mounted secret.
+    File(PathBuf),
+    /// The name of an environment variable.
+    EnvVar(String),
+    /// The first line of stdin, e.g. piped from a password manager.
+    Stdin,
+    /// Asks for the key on the terminal, without echoing it.
+    Prompt,
+}
+
+impl OwnerKeySource {
+    /// Reads the hex key, with or without a `0x` prefix.
+    pub fn load(&self) -> Result<SecretKey, ConfigError> {
+        let io_error = |error| ConfigError::OwnerKeySource(self.clone(), error);
+        let hex = match self {
+            Self::File(path) => read_to_string(path).map_err(io_error)?,
+            Self::EnvVar(name) => std::env::var(name).map_err(|source| ConfigError::EnvVar {
+                name: name.clone(),
+                source,
+            })?,
+            Self::Stdin => read_line().map_err(io_error)?,
+            Self::Prompt => {
+                if !std::io::stdin().is_terminal() {
+                    return Err(io_error(std::io::Error::other("stdin isn't a terminal")));
+                }
+                crate::terminal::read_secret("Owner private key: ").map_err(io_error)?
+            }
+        };
+        let hex = hex.trim();
+        let hex = hex.strip_prefix("0x").unwrap_or(hex);
+        SecretKey::from_str(hex).map_err(|_| ConfigError::InvalidOwnerKey(self.clone()))
+    }
+}
+
+impl Display for OwnerKeySource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::EnvVar(name) => write!(f, "the environment variable `{name}`"),
+            Self::Stdin => write!(f, "stdin"),
+            Self::Prompt => write!(f, "the prompt"),
+        }
+    }
+}
+
+/// Reads a line from stdin.
+fn read_line() -> std::io::Result<String> {
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    Ok(line)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AwsKmsOptions {
+    /// The key ID, ARN, alias name or alias ARN.
+    pub key_id: String,
+    pub region: String,
+    pub endpoint: Url,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VaultOptions {
+    pub address: Url,
+    pub token: String,
+    /// Only for Vault Enterprise.
+    pub namespace: Option<String>,
+    /// Where the secrets engine is mounted. The stock transit engine has no secp256k1 keys, so this
+    /// is a plugin that has them, with the same API.
+    pub mount: String,
+    /// The name of the key.
+    pub key: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteSignerOptions {
+    /// The JSON-RPC endpoint that serves `eth_signTransaction`.
+    pub url: Url,
+    /// The `owner_address`.
+    pub address: H160,
+}
+
+/// See [`defender`](crate::runner::defender).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefenderOptions {
+    pub api_key: String,
+    pub api_secret: String,
+    /// The Relay API.
+    pub api_url: Url,
+    /// The Cognito user pool that API keys authenticate against.
+    pub user_pool_id: String,
+    pub client_id: String,
+    pub speed: DefenderSpeed,
+    /// The `owner_address`, which must be the relayer's.
+    pub address: H160,
+}
+
+/// How much the relayer pays for gas, see
+/// <https://docs.openzeppelin.com/defender/v1/relay#speed>.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DefenderSpeed {
+    SafeLow,
+    Average,
+    #[default]
+    Fast,
+    Fastest,
+}
+
+/// A private relay, like Flashbots Protect, that transactions are sent to instead of the public
+/// mempool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrivateRelayOptions {
+    /// The JSON-RPC endpoint that serves `eth_sendRawTransaction`.
+    pub url: Url,
+    /// How long to wait for the relay before sending through the protocol chain's provider.
+    pub timeout: Duration,
+}
+
+/// How payloads are passed to the DataEdge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataEdgeCall {
+    /// As the only argument of the function `name`, which must take `bytes`.
+    Function {
+        name: String,
+        /// The DataEdge's ABI. The bundled one if `None`.
+        abi_path: Option<PathBuf>,
+    },
+    /// As the raw calldata, which the DataEdge handles in its fallback function.
+    Fallback,
+}
+
+impl Default for DataEdgeCall {
+    fn default() -> Self {
+        Self::Function {
+            name: serde_defaults::data_edge_function(),
+            abi_path: None,
         }
     }
 }
 
+/// Payloads are only submitted once enough operators co-signed them, see
+/// [`co_signing`](crate::runner::co_signing).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoSigningOptions {
+    /// The port of the co-signing API, which listens on all interfaces.
+    pub port: u16,
+    /// The contract that owns the DataEdge and checks the signatures. Payloads are sent to it
+    /// instead of the DataEdge.
+    pub contract_address: H160,
+    /// The operators whose signatures count.
+    pub signers: Vec<H160>,
+    /// How many of the `signers` must sign a payload.
+    pub threshold: usize,
+    /// How long to wait for signatures before giving up on the polling iteration.
+    pub timeout: Duration,
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     pub log_level: LevelFilter,
-    pub owner_private_key: SecretKey,
+    pub owner_key: OwnerKey,
     pub data_edge_address: H160,
+    pub data_edge_call: DataEdgeCall,
     pub epoch_manager_address: H160,
+    /// If set, protocol chain reads are batched through this Multicall3 contract.
+    pub multicall_address: Option<H160>,
     pub subgraph_url: Url,
     pub bearer_token: String,
     pub owner_address: H160,
     pub indexed_chains: Vec<IndexedChain>,
     pub blockmeta_indexed_chains: Vec<BlockmetaIndexedChain>,
-    pub freshness_threshold: u64,
+    pub freshness_check: FreshnessCheckOptions,
+    pub error_budget: ErrorBudgetOptions,
+    /// Overrides the default behavior of the main loop after errors of each class.
+    pub error_policies: HashMap<ErrorClass, ErrorPolicy>,
     pub protocol_chain: ProtocolChain,
     pub retry_strategy_max_wait_time: Duration,
     pub metrics_port: u16,
+    pub admin_port: Option<u16>,
     pub transaction_monitoring_options: TransactionMonitoringOptions,
+    /// If set, transactions are sent through this relay first. Disabled if `None`.
+    pub private_relay: Option<PrivateRelayOptions>,
+    /// Disabled if `None`.
+    pub co_signing: Option<CoSigningOptions>,
     pub blockmeta_auth_token: String,
+    pub history_file: Option<PathBuf>,
+    pub runtime_options: RuntimeOptions,
+    pub pushgateway: Option<PushgatewayOptions>,
+    /// How often to re-resolve the hostnames of JSON RPC endpoints that failed. Disabled if `None`.
+    pub dns_refresh_interval: Option<Duration>,
+    /// Disabled if `None`.
+    pub adaptive_polling: Option<AdaptivePollingOptions>,
+    /// How many epochs the subgraph may fall behind the Epoch Manager before the oracle attempts
+    /// a recovery. See [`missed_epochs`](crate::runner::missed_epochs). Disabled if `None`.
+    pub max_missed_epochs: Option<u64>,
+    /// Payloads are split so that none is longer than this. Disabled if `None`.
+    pub max_payload_bytes: Option<usize>,
+    pub acceleration_overflow: OverflowPolicy,
+    /// Indexed chains that the subgraph doesn't know yet are registered along with the block
+    /// numbers of the next epoch, instead of being left out.
+    pub register_new_networks: bool,
+    /// Payloads are encoded and logged, but never submitted.
+    pub dry_run: bool,
+    /// The keys whose values are read from an environment variable with the deprecated `"$NAME"`
+    /// form rather than `"${NAME}"`, to warn about once logging is set up.
+    pub bare_env_vars: Vec<String>,
 }
 
 impl Config {
-    /// Loads all configuration options the provided TOML configuration file and environment
-    /// variables.
+    /// Like [`Config::try_parse`], but panics on invalid configs. Mostly useful in tests.
     pub fn parse(config_file: impl AsRef<Path>) -> Self {
-        let config_file = ConfigFile::from_file(config_file.as_ref())
-            .context("Failed to read config file as valid TOML")
-            .unwrap();
+        Self::try_parse(config_file).unwrap()
+    }
+
+    /// Loads all configuration options the provided TOML or JSON configuration file and
+    /// environment variables. Errors name the file and the offending entry, so that they can be
+    /// shown to users as they are.
+    pub fn try_parse(config_file: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let config_file = ConfigFile::from_file(config_file.as_ref())?;
+        let owner_key = config_file
+            .owner_key()
+            .context("Failed to load the owner's key")?;
+        Self::resolve(config_file, owner_key)
+    }
+
+    /// Like [`Config::try_parse`], but with the owner's private key from `owner_key` instead of
+    /// the config file, whose own owner key settings are ignored.
+    pub fn try_parse_with_owner_key(
+        config_file: impl AsRef<Path>,
+        owner_key: &OwnerKeySource,
+    ) -> anyhow::Result<Self> {
+        let config_file = ConfigFile::from_file(config_file.as_ref())?;
+        let owner_key = owner_key.load().context("Failed to load the owner's key")?;
+        Self::resolve(config_file, OwnerKey::PrivateKey(owner_key))
+    }
+
+    /// Loads `config_file` again, e.g. after it changed while the oracle runs. The owner's key is
+    /// kept as it is, so that keystores don't ask for their password again.
+    pub fn reload(&self, config_file: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let config_file = ConfigFile::from_file(config_file.as_ref())?;
+        Self::resolve(config_file, self.owner_key.clone())
+    }
+
+    fn resolve(mut config_file: ConfigFile, owner_key: OwnerKey) -> anyhow::Result<Self> {
+        let indexed_chains = std::mem::take(&mut config_file.indexed_chains)
+            .into_iter()
+            .filter_map(|(id, chain)| chain.resolve(id).transpose())
+            .collect::<Result<Vec<IndexedChain>, ConfigError>>()?;
+        for (chain_id, url) in config_file.blockmeta_indexed_chains.iter().flatten() {
+            url.0
+                .parse::<tonic::transport::Uri>()
+                .map_err(|source| ConfigError::BlockmetaUrl {
+                    chain_id: chain_id.clone(),
+                    source,
+                })?;
+        }
+        let co_signing = config_file
+            .co_signing
+            .as_ref()
+            .map(SerdeCoSigning::resolve)
+            .transpose()
+            .context("Invalid `co_signing` options")?;
+        let data_edge_call = config_file
+            .data_edge_call
+            .as_ref()
+            .map(SerdeDataEdgeCall::resolve)
+            .transpose()
+            .context("Invalid `data_edge_call` options")?
+            .unwrap_or_default();
 
-        Self::from_config_file(config_file)
+        Ok(Self::from_config_file(
+            config_file,
+            owner_key,
+            indexed_chains,
+            co_signing,
+            data_edge_call,
+        ))
     }
 
-    fn from_config_file(config_file: ConfigFile) -> Self {
+    fn from_config_file(
+        config_file: ConfigFile,
+        owner_key: OwnerKey,
+        indexed_chains: Vec<IndexedChain>,
+        co_signing: Option<CoSigningOptions>,
+        data_edge_call: DataEdgeCall,
+    ) -> Self {
         Self {
             log_level: config_file.log_level.0,
-            owner_private_key: config_file.owner_private_key.0,
+            owner_key,
             data_edge_address: config_file.data_edge_address.0,
+            data_edge_call,
             epoch_manager_address: config_file.epoch_manager_address.0,
+            multicall_address: config_file.multicall_address.map(|address| address.0),
             subgraph_url: config_file.subgraph_url.0,
             bearer_token: config_file.bearer_token.0,
-            freshness_threshold: config_file.freshness_threshold,
+            freshness_check: {
+                let options = config_file.freshness_check.unwrap_or_default();
+                FreshnessCheckOptions {
+                    enabled: options.enabled,
+                    scan: options.scan,
+                    threshold: options.threshold.unwrap_or(config_file.freshness_threshold),
+                    on_stale: options.on_stale,
+                }
+            },
+            error_budget: config_file.error_budget,
+            error_policies: config_file.error_policies,
             owner_address: config_file.owner_address.0,
             retry_strategy_max_wait_time: Duration::from_secs(
                 config_file.web3_transport_retry_max_wait_time_in_seconds,
             ),
-            indexed_chains: config_file
-                .indexed_chains
-                .into_iter()
-                .map(|(id, provider)| IndexedChain {
-                    id,
-                    jrpc_url: provider.0,
-                })
-                .collect::<Vec<IndexedChain>>(),
+            indexed_chains,
             blockmeta_indexed_chains: config_file
                 .blockmeta_indexed_chains
                 .unwrap_or_default()
@@ -147,91 +730,675 @@ impl Config {
                 polling_interval: Duration::from_secs(
                     config_file.protocol_chain.polling_interval_in_seconds,
                 ),
+                rate_limit: config_file.protocol_chain.rate_limit,
             },
             metrics_port: config_file.metrics_port,
+            admin_port: config_file.admin_port,
             transaction_monitoring_options: config_file.transaction_monitoring_options,
+            private_relay: config_file
+                .private_relay
+                .map(|private_relay| PrivateRelayOptions {
+                    url: private_relay.url.0,
+                    timeout: Duration::from_secs(private_relay.timeout_in_seconds),
+                }),
+            co_signing,
             blockmeta_auth_token: config_file.blockmeta_auth_token.0,
+            history_file: config_file.history_file,
+            runtime_options: config_file.runtime_options,
+            pushgateway: config_file
+                .pushgateway
+                .map(|pushgateway| PushgatewayOptions {
+                    url: pushgateway.url.0,
+                    job: pushgateway.job,
+                    interval: Duration::from_secs(pushgateway.interval_in_seconds),
+                }),
+            dns_refresh_interval: Some(config_file.dns_refresh_interval_in_seconds)
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs),
+            adaptive_polling: config_file.adaptive_polling,
+            max_missed_epochs: Some(config_file.max_missed_epochs).filter(|epochs| *epochs > 0),
+            max_payload_bytes: Some(config_file.max_payload_bytes).filter(|bytes| *bytes > 0),
+            acceleration_overflow: config_file.acceleration_overflow,
+            register_new_networks: config_file.register_new_networks,
+            dry_run: config_file.dry_run,
+            bare_env_vars: config_file.bare_env_vars,
+        }
+    }
+}
+
+/// The formats that config files can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigFormat {
+    Toml,
+    /// Mostly for configs that are generated, e.g. by Helm's `toJson`.
+    Json,
+    /// Mostly for configs that are templated, e.g. in Helm values.
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Files without a known extension are assumed to be TOML, as they always were.
+    fn of(file_path: &Path) -> Self {
+        let extension = file_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("json") => Self::Json,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Toml,
         }
     }
 }
 
-/// Represents the TOML config file
+/// Represents the config file, in any [`ConfigFormat`].
 #[derive(Deserialize)]
 #[serde(rename_all = "snake_case")]
 struct ConfigFile {
     owner_address: FromStrWrapper<H160>,
-    owner_private_key: EitherLiteralOrEnvVar<SecretKey>,
-    data_edge_address: EitherLiteralOrEnvVar<H160>,
-    epoch_manager_address: EitherLiteralOrEnvVar<H160>,
-    subgraph_url: EitherLiteralOrEnvVar<Url>,
-    bearer_token: EitherLiteralOrEnvVar<String>,
-    /// Number of blocks that the Epoch Subgraph may be away from the protocol chain's head. If the
-    /// block distance is lower than this, a `trace_filter` JSON RPC call will be used to infer if
-    /// any relevant transaction happened within that treshold.
+    /// Exactly one of `owner_private_key`, `owner_keystore`, `owner_aws_kms`, `owner_vault`,
+    /// `owner_remote_signer` and `owner_defender` must be set.
+    owner_private_key: Option<FromStrWrapper<SecretKey>>,
+    owner_keystore: Option<SerdeKeystore>,
+    owner_aws_kms: Option<SerdeAwsKms>,
+    owner_vault: Option<SerdeVault>,
+    owner_remote_signer: Option<SerdeRemoteSigner>,
+    owner_defender: Option<SerdeDefender>,
+    data_edge_address: FromStrWrapper<H160>,
+    /// Defaults to calling `crossChainEpochOracle` of the bundled DataEdge ABI.
+    data_edge_call: Option<SerdeDataEdgeCall>,
+    epoch_manager_address: FromStrWrapper<H160>,
+    multicall_address: Option<FromStrWrapper<H160>>,
+    subgraph_url: FromStrWrapper<Url>,
+    bearer_token: FromStrWrapper<String>,
+    /// Kept for backwards compatibility, prefer `freshness_check.threshold`.
     #[serde(default = "serde_defaults::freshness_threshold")]
     freshness_threshold: u64,
+    freshness_check: Option<SerdeFreshnessCheck>,
+    #[serde(default)]
+    error_budget: ErrorBudgetOptions,
+    #[serde(default)]
+    error_policies: HashMap<ErrorClass, ErrorPolicy>,
     #[serde(default = "serde_defaults::web3_transport_retry_max_wait_time_in_seconds")]
     web3_transport_retry_max_wait_time_in_seconds: u64,
     #[serde(default = "serde_defaults::log_level")]
     log_level: FromStrWrapper<LevelFilter>,
     protocol_chain: SerdeProtocolChain,
-    indexed_chains: HashMap<Caip2ChainId, EitherLiteralOrEnvVar<Url>>,
-    blockmeta_indexed_chains: Option<HashMap<Caip2ChainId, EitherLiteralOrEnvVar<String>>>,
+    indexed_chains: HashMap<Caip2ChainId, SerdeIndexedChain>,
+    blockmeta_indexed_chains: Option<HashMap<Caip2ChainId, FromStrWrapper<String>>>,
     #[serde(default = "serde_defaults::metrics_port")]
     metrics_port: u16,
+    /// The port of the admin API, which only listens on localhost. Disabled if unset.
+    admin_port: Option<u16>,
     #[serde(default, rename = "transaction_monitoring")]
     transaction_monitoring_options: TransactionMonitoringOptions,
-    blockmeta_auth_token: EitherLiteralOrEnvVar<String>,
+    private_relay: Option<SerdePrivateRelay>,
+    co_signing: Option<SerdeCoSigning>,
+    blockmeta_auth_token: FromStrWrapper<String>,
+    /// Where to keep the history of published blocks, which is compared against the Epoch
+    /// Subgraph on every polling iteration. If unset, the history only lives in memory.
+    history_file: Option<PathBuf>,
+    #[serde(default, rename = "runtime")]
+    runtime_options: RuntimeOptions,
+    pushgateway: Option<SerdePushgateway>,
+    /// Transports are rebuilt when the addresses behind their hostnames change, which is only
+    /// checked after requests to them failed. `0` disables these checks.
+    #[serde(default = "serde_defaults::dns_refresh_interval_in_seconds")]
+    dns_refresh_interval_in_seconds: u64,
+    adaptive_polling: Option<AdaptivePollingOptions>,
+    #[serde(default = "serde_defaults::max_missed_epochs")]
+    max_missed_epochs: u64,
+    /// `0` disables payload splitting.
+    #[serde(default = "serde_defaults::max_payload_bytes")]
+    max_payload_bytes: usize,
+    /// Either "strict" or "lossy". See [`OverflowPolicy`].
+    #[serde(default)]
+    acceleration_overflow: OverflowPolicy,
+    #[serde(default)]
+    register_new_networks: bool,
+    /// Runs the whole pipeline without ever sending a transaction, e.g. to try a new
+    /// configuration against mainnet data.
+    #[serde(default)]
+    dry_run: bool,
+    /// Filled in by [`interpolate_env_vars`] rather than read.
+    #[serde(skip)]
+    bare_env_vars: Vec<String>,
 }
 
 impl ConfigFile {
-    /// Tries to Create a [`ConfigFile`] from a TOML file.
+    /// Tries to Create a [`ConfigFile`] from a file, in the format of its extension.
     fn from_file(file_path: &Path) -> Result<Self, ConfigError> {
-        let string = read_to_string(file_path)?;
-        toml::from_str(&string).map_err(ConfigError::Toml)
+        let format = ConfigFormat::of(file_path);
+        let path = || file_path.to_path_buf();
+        let string = read_to_string(file_path).map_err(|source| ConfigError::Io {
+            path: path(),
+            source,
+        })?;
+        match format {
+            ConfigFormat::Toml => {
+                let toml = |source| ConfigError::Toml {
+                    path: path(),
+                    source,
+                };
+                let mut value: toml::Value = toml::from_str(&string).map_err(toml)?;
+                let mut bare_env_vars = vec![];
+                interpolate_env_vars(&mut value, "", &mut bare_env_vars)?;
+                let config_file: Self = value.try_into().map_err(toml)?;
+                Ok(Self {
+                    bare_env_vars,
+                    ..config_file
+                })
+            }
+            ConfigFormat::Json => {
+                let json = |source| ConfigError::Json {
+                    path: path(),
+                    source,
+                };
+                let mut value: serde_json::Value = serde_json::from_str(&string).map_err(json)?;
+                let mut bare_env_vars = vec![];
+                interpolate_json_env_vars(&mut value, "", &mut bare_env_vars)?;
+                let config_file: Self = serde_json::from_value(value).map_err(json)?;
+                Ok(Self {
+                    bare_env_vars,
+                    ..config_file
+                })
+            }
+            ConfigFormat::Yaml => {
+                let yaml = |source| ConfigError::Yaml {
+                    path: path(),
+                    source,
+                };
+                let mut value: serde_yaml::Value = serde_yaml::from_str(&string).map_err(yaml)?;
+                let mut bare_env_vars = vec![];
+                interpolate_yaml_env_vars(&mut value, "", &mut bare_env_vars)?;
+                let config_file: Self = serde_yaml::from_value(value).map_err(yaml)?;
+                Ok(Self {
+                    bare_env_vars,
+                    ..config_file
+                })
+            }
+        }
+    }
+
+    fn owner_key(&self) -> Result<OwnerKey, ConfigError> {
+        let sources = [
+            self.owner_private_key.is_some(),
+            self.owner_keystore.is_some(),
+            self.owner_aws_kms.is_some(),
+            self.owner_vault.is_some(),
+            self.owner_remote_signer.is_some(),
+            self.owner_defender.is_some(),
+        ];
+        if sources.into_iter().filter(|is_set| *is_set).count() != 1 {
+            return Err(ConfigError::OwnerKey(
+                "exactly one of `owner_private_key`, `owner_keystore`, `owner_aws_kms`, \
+                 `owner_vault`, `owner_remote_signer` and `owner_defender` must be set",
+            ));
+        }
+
+        if let Some(private_key) = &self.owner_private_key {
+            return Ok(OwnerKey::PrivateKey(private_key.0));
+        }
+        if let Some(keystore) = &self.owner_keystore {
+            let password = match &keystore.password {
+                Some(password) => password.0.clone(),
+                None => keystore::prompt_password(&keystore.path)
+                    .map_err(ConfigError::PasswordPrompt)?,
+            };
+            return Ok(OwnerKey::PrivateKey(keystore::decrypt_file(
+                &keystore.path,
+                &password,
+            )?));
+        }
+        if let Some(aws_kms) = &self.owner_aws_kms {
+            return Ok(OwnerKey::AwsKms(aws_kms.resolve()?));
+        }
+        if let Some(vault) = &self.owner_vault {
+            return Ok(OwnerKey::Vault(VaultOptions {
+                address: vault.address.0.clone(),
+                token: vault.token.0.clone(),
+                namespace: vault.namespace.clone(),
+                mount: vault.mount.clone(),
+                key: vault.key.clone(),
+            }));
+        }
+        if let Some(defender) = &self.owner_defender {
+            return Ok(OwnerKey::Defender(DefenderOptions {
+                api_key: defender.api_key.0.clone(),
+                api_secret: defender.api_secret.0.clone(),
+                api_url: defender.api_url.clone(),
+                user_pool_id: defender.user_pool_id.clone(),
+                client_id: defender.client_id.clone(),
+                speed: defender.speed,
+                address: self.owner_address.0,
+            }));
+        }
+        // Unwrap: exactly one of the sources is set.
+        let remote_signer = self.owner_remote_signer.as_ref().unwrap();
+        Ok(OwnerKey::Remote(RemoteSignerOptions {
+            url: remote_signer.url.0.clone(),
+            address: self.owner_address.0,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct SerdeRemoteSigner {
+    url: FromStrWrapper<Url>,
+}
+
+#[derive(Deserialize)]
+struct SerdeDefender {
+    api_key: FromStrWrapper<String>,
+    api_secret: FromStrWrapper<String>,
+    #[serde(default = "serde_defaults::defender_api_url")]
+    api_url: Url,
+    #[serde(default = "serde_defaults::defender_user_pool_id")]
+    user_pool_id: String,
+    #[serde(default = "serde_defaults::defender_client_id")]
+    client_id: String,
+    #[serde(default)]
+    speed: DefenderSpeed,
+}
+
+#[derive(Deserialize)]
+struct SerdeVault {
+    address: FromStrWrapper<Url>,
+    token: FromStrWrapper<String>,
+    namespace: Option<String>,
+    mount: String,
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct SerdeAwsKms {
+    key_id: FromStrWrapper<String>,
+    /// Defaults to the region of the key ARN.
+    region: Option<String>,
+    /// Defaults to the public endpoint of the region.
+    endpoint: Option<FromStrWrapper<Url>>,
+}
+
+impl SerdeAwsKms {
+    fn resolve(&self) -> Result<AwsKmsOptions, ConfigError> {
+        let key_id = self.key_id.0.clone();
+        // ARNs look like `arn:aws:kms:<region>:<account>:key/<id>`.
+        let region = match (&self.region, key_id.strip_prefix("arn:")) {
+            (Some(region), _) => region.clone(),
+            (None, Some(arn)) => arn
+                .split(':')
+                .nth(2)
+                .filter(|region| !region.is_empty())
+                .ok_or(ConfigError::OwnerKey("invalid AWS KMS key ARN"))?
+                .to_string(),
+            (None, None) => {
+                return Err(ConfigError::OwnerKey(
+                    "`owner_aws_kms.region` must be set unless `key_id` is an ARN",
+                ))
+            }
+        };
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.0.clone(),
+            None => format!("https://kms.{region}.amazonaws.com/")
+                .parse()
+                .map_err(|_| ConfigError::OwnerKey("invalid AWS region"))?,
+        };
+        Ok(AwsKmsOptions {
+            key_id,
+            region,
+            endpoint,
+        })
+    }
+}
+
+/// An encrypted JSON keystore with the owner's private key, see [`keystore`].
+#[derive(Deserialize)]
+struct SerdeKeystore {
+    path: PathBuf,
+    /// Usually an environment variable. If unset, the password is read from stdin.
+    password: Option<FromStrWrapper<String>>,
+}
+
+/// An entry of the `indexed_chains` table. It's either just the JSON RPC URL(s), or a table with
+/// additional per-chain settings.
+///
+/// Unlike `#[serde(untagged)]`, deserialization keeps the error of the offending entry.
+enum SerdeIndexedChain {
+    Url(OneOrMany<FromStrWrapper<Url>>),
+    Detailed(SerdeIndexedChainTable),
+}
+
+#[derive(Deserialize)]
+struct SerdeIndexedChainTable {
+    jrpc: OneOrMany<FromStrWrapper<Url>>,
+    block_selection: Option<BlockSelectionStrategy>,
+    /// Shorthand for a `depth-offset` block selection.
+    confirmations: Option<u64>,
+    polling_interval_in_seconds: Option<u64>,
+    #[serde(default = "serde_defaults::indexed_chain_enabled")]
+    enabled: bool,
+    rate_limit: Option<NonZeroU32>,
+    #[serde(default)]
+    failover: bool,
+    #[serde(default = "serde_defaults::indexed_chain_failover_cooldown_in_seconds")]
+    failover_cooldown_in_seconds: u64,
+}
+
+impl<'de> Deserialize<'de> for SerdeIndexedChain {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = SerdeIndexedChain;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON RPC URL, a list of them, or a table with `jrpc` URLs")
+            }
+
+            fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                OneOrMany::deserialize(s.into_deserializer()).map(SerdeIndexedChain::Url)
+            }
+
+            fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                OneOrMany::deserialize(SeqAccessDeserializer::new(seq)).map(SerdeIndexedChain::Url)
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                SerdeIndexedChainTable::deserialize(MapAccessDeserializer::new(map))
+                    .map(SerdeIndexedChain::Detailed)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl SerdeIndexedChain {
+    /// `None` if the chain is disabled.
+    fn resolve(self, id: Caip2ChainId) -> Result<Option<IndexedChain>, ConfigError> {
+        let jrpc_urls = |jrpc: OneOrMany<FromStrWrapper<Url>>| {
+            jrpc.into_vec().into_iter().map(|url| url.0).collect()
+        };
+        match self {
+            SerdeIndexedChain::Url(jrpc) => Ok(Some(IndexedChain {
+                id,
+                jrpc_urls: jrpc_urls(jrpc),
+                block_selection: BlockSelectionStrategy::default(),
+                polling_interval: None,
+                rate_limit: None,
+                failover_cooldown: None,
+            })),
+            SerdeIndexedChain::Detailed(SerdeIndexedChainTable { enabled: false, .. }) => Ok(None),
+            SerdeIndexedChain::Detailed(SerdeIndexedChainTable {
+                jrpc,
+                block_selection,
+                confirmations,
+                polling_interval_in_seconds,
+                enabled: true,
+                rate_limit,
+                failover,
+                failover_cooldown_in_seconds,
+            }) => {
+                let block_selection = match (block_selection, confirmations) {
+                    (Some(_), Some(_)) => {
+                        return Err(ConfigError::IndexedChain {
+                            chain_id: id,
+                            reason: "`confirmations` can't be combined with `block_selection`",
+                        })
+                    }
+                    (Some(block_selection), None) => block_selection,
+                    (None, Some(depth)) => BlockSelectionStrategy::DepthOffset { depth },
+                    (None, None) => BlockSelectionStrategy::default(),
+                };
+                if polling_interval_in_seconds == Some(0) {
+                    return Err(ConfigError::IndexedChain {
+                        chain_id: id,
+                        reason: "`polling_interval_in_seconds` must be at least 1",
+                    });
+                }
+                Ok(Some(IndexedChain {
+                    id,
+                    jrpc_urls: jrpc_urls(jrpc),
+                    block_selection,
+                    polling_interval: polling_interval_in_seconds.map(Duration::from_secs),
+                    rate_limit,
+                    failover_cooldown: failover
+                        .then(|| Duration::from_secs(failover_cooldown_in_seconds)),
+                }))
+            }
+        }
     }
 }
 
 #[derive(Deserialize, Debug)]
 struct SerdeProtocolChain {
     name: Caip2ChainId,
-    jrpc: EitherLiteralOrEnvVar<Url>,
+    jrpc: FromStrWrapper<Url>,
     #[serde(default = "serde_defaults::protocol_chain_polling_interval_in_seconds")]
     polling_interval_in_seconds: u64,
+    rate_limit: Option<NonZeroU32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SerdeFreshnessCheck {
+    #[serde(default = "serde_defaults::freshness_check_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    scan: FreshnessScan,
+    /// Overrides the top-level `freshness_threshold`.
+    threshold: Option<u64>,
+    #[serde(default)]
+    on_stale: StaleSubgraphAction,
+}
+
+impl Default for SerdeFreshnessCheck {
+    fn default() -> Self {
+        Self {
+            enabled: serde_defaults::freshness_check_enabled(),
+            scan: FreshnessScan::default(),
+            threshold: None,
+            on_stale: StaleSubgraphAction::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SerdePushgateway {
+    url: FromStrWrapper<Url>,
+    #[serde(default = "serde_defaults::pushgateway_job")]
+    job: String,
+    #[serde(default = "serde_defaults::pushgateway_interval_in_seconds")]
+    interval_in_seconds: u64,
+}
+
+#[derive(Deserialize)]
+struct SerdePrivateRelay {
+    url: FromStrWrapper<Url>,
+    #[serde(default = "serde_defaults::private_relay_timeout_in_seconds")]
+    timeout_in_seconds: u64,
+}
+
+#[derive(Deserialize)]
+struct SerdeDataEdgeCall {
+    function: Option<String>,
+    abi_path: Option<PathBuf>,
+    #[serde(default)]
+    raw_calldata: bool,
+}
+
+impl SerdeDataEdgeCall {
+    fn resolve(&self) -> Result<DataEdgeCall, ConfigError> {
+        if self.raw_calldata {
+            if self.function.is_some() || self.abi_path.is_some() {
+                return Err(ConfigError::DataEdgeCall(
+                    "`raw_calldata` can't be combined with `function` or `abi_path`",
+                ));
+            }
+            return Ok(DataEdgeCall::Fallback);
+        }
+        Ok(DataEdgeCall::Function {
+            name: self
+                .function
+                .clone()
+                .unwrap_or_else(serde_defaults::data_edge_function),
+            abi_path: self.abi_path.clone(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SerdeCoSigning {
+    port: u16,
+    contract_address: FromStrWrapper<H160>,
+    signers: Vec<FromStrWrapper<H160>>,
+    threshold: usize,
+    #[serde(default = "serde_defaults::co_signing_timeout_in_seconds")]
+    timeout_in_seconds: u64,
+}
+
+impl SerdeCoSigning {
+    fn resolve(&self) -> Result<CoSigningOptions, ConfigError> {
+        let mut signers: Vec<H160> = self.signers.iter().map(|signer| signer.0).collect();
+        signers.sort();
+        signers.dedup();
+        if signers.len() < self.signers.len() {
+            return Err(ConfigError::CoSigning("`signers` has duplicates"));
+        }
+        if self.threshold == 0 || self.threshold > signers.len() {
+            return Err(ConfigError::CoSigning(
+                "`threshold` must be between 1 and the number of `signers`",
+            ));
+        }
+        Ok(CoSigningOptions {
+            port: self.port,
+            contract_address: self.contract_address.0,
+            signers,
+            threshold: self.threshold,
+            timeout: Duration::from_secs(self.timeout_in_seconds),
+        })
+    }
 }
 
 mod serde_utils {
     use super::*;
 
-    #[derive(Clone, Debug, PartialEq, Eq)]
-    pub struct EitherLiteralOrEnvVar<T>(pub T);
+    /// Describes the format of values that are parsed from strings, for error messages.
+    pub trait Expected {
+        const EXPECTED: &'static str;
+    }
+
+    impl Expected for String {
+        const EXPECTED: &'static str = "a string";
+    }
+
+    impl Expected for H160 {
+        const EXPECTED: &'static str =
+            "a 20-byte hex address, e.g. `0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1`";
+    }
+
+    impl Expected for SecretKey {
+        const EXPECTED: &'static str = "a 32-byte hex private key";
+    }
+
+    impl Expected for Url {
+        const EXPECTED: &'static str = "an absolute URL, e.g. `https://example.com`";
+    }
 
-    impl<'de, T> serde::Deserialize<'de> for EitherLiteralOrEnvVar<T>
+    impl Expected for LevelFilter {
+        const EXPECTED: &'static str = "a log level, e.g. `info`";
+    }
+
+    /// Never includes `s` itself, as it might be a secret.
+    fn from_str<T, E>(s: &str) -> Result<T, E>
     where
-        T: FromStr,
+        T: FromStr + Expected,
         T::Err: Display,
+        E: serde::de::Error,
+    {
+        T::from_str(s).map_err(|error| E::custom(format!("{error}, expected {}", T::EXPECTED)))
+    }
+
+    /// Either a single value or a list of values.
+    pub enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    /// Like `#[serde(untagged)]`, but keeps the errors of the values. Single values must be
+    /// strings or tables.
+    impl<'de, T> Deserialize<'de> for OneOrMany<T>
+    where
+        T: Deserialize<'de>,
     {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
             D: serde::Deserializer<'de>,
         {
-            let raw_s = String::deserialize(deserializer)?;
-            let value_s = if raw_s.starts_with('$') {
-                std::env::var(raw_s.strip_prefix('$').unwrap()).map_err(serde::de::Error::custom)?
-            } else {
-                raw_s
-            };
-            Ok(EitherLiteralOrEnvVar(
-                T::from_str(&value_s).map_err(serde::de::Error::custom)?,
-            ))
+            struct Visitor<T>(PhantomData<T>);
+
+            impl<'de, T> serde::de::Visitor<'de> for Visitor<T>
+            where
+                T: Deserialize<'de>,
+            {
+                type Value = OneOrMany<T>;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a value or a list of values")
+                }
+
+                fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+                where
+                    E: serde::de::Error,
+                {
+                    T::deserialize(s.into_deserializer()).map(OneOrMany::One)
+                }
+
+                fn visit_seq<A>(self, seq: A) -> Result<Self::Value, A::Error>
+                where
+                    A: serde::de::SeqAccess<'de>,
+                {
+                    Vec::deserialize(SeqAccessDeserializer::new(seq)).map(OneOrMany::Many)
+                }
+
+                fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+                where
+                    A: serde::de::MapAccess<'de>,
+                {
+                    T::deserialize(MapAccessDeserializer::new(map)).map(OneOrMany::One)
+                }
+            }
+
+            deserializer.deserialize_any(Visitor(PhantomData))
+        }
+    }
+
+    impl<T> OneOrMany<T> {
+        pub fn into_vec(self) -> Vec<T> {
+            match self {
+                OneOrMany::One(value) => vec![value],
+                OneOrMany::Many(values) => values,
+            }
         }
     }
 
+    #[derive(Clone, Debug, PartialEq, Eq)]
     pub struct FromStrWrapper<T>(pub T);
 
     impl<'de, T> Deserialize<'de> for FromStrWrapper<T>
     where
-        T: FromStr,
+        T: FromStr + Expected,
         T::Err: Display,
     {
         fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -239,11 +1406,133 @@ mod serde_utils {
             D: serde::Deserializer<'de>,
         {
             let s = String::deserialize(deserializer)?;
-            FromStr::from_str(&s)
-                .map(Self)
-                .map_err(serde::de::Error::custom)
+            from_str(&s).map(Self)
+        }
+    }
+}
+
+/// Replaces `${NAME}` placeholders in every string of the config file with the value of the
+/// environment variable `NAME`, e.g. for API keys within JSON RPC URLs, and `$$` with a literal
+/// `$`. Keys are left as they are. `key` is the path of `value`, and the paths of values in the
+/// deprecated `$NAME` form are added to `bare_env_vars`.
+fn interpolate_env_vars(
+    value: &mut toml::Value,
+    key: &str,
+    bare_env_vars: &mut Vec<String>,
+) -> Result<(), ConfigError> {
+    match value {
+        toml::Value::String(string) => *string = interpolate(string, key, bare_env_vars)?,
+        toml::Value::Array(values) => {
+            values.iter_mut().enumerate().try_for_each(|(i, value)| {
+                interpolate_env_vars(value, &format!("{key}[{i}]"), bare_env_vars)
+            })?
+        }
+        toml::Value::Table(table) => table.iter_mut().try_for_each(|(name, value)| {
+            interpolate_env_vars(value, &join_key(key, name), bare_env_vars)
+        })?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_json_env_vars(
+    value: &mut serde_json::Value,
+    key: &str,
+    bare_env_vars: &mut Vec<String>,
+) -> Result<(), ConfigError> {
+    match value {
+        serde_json::Value::String(string) => *string = interpolate(string, key, bare_env_vars)?,
+        serde_json::Value::Array(values) => {
+            values.iter_mut().enumerate().try_for_each(|(i, value)| {
+                interpolate_json_env_vars(value, &format!("{key}[{i}]"), bare_env_vars)
+            })?
+        }
+        serde_json::Value::Object(object) => object.iter_mut().try_for_each(|(name, value)| {
+            interpolate_json_env_vars(value, &join_key(key, name), bare_env_vars)
+        })?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_yaml_env_vars(
+    value: &mut serde_yaml::Value,
+    key: &str,
+    bare_env_vars: &mut Vec<String>,
+) -> Result<(), ConfigError> {
+    match value {
+        serde_yaml::Value::String(string) => *string = interpolate(string, key, bare_env_vars)?,
+        serde_yaml::Value::Sequence(values) => {
+            values.iter_mut().enumerate().try_for_each(|(i, value)| {
+                interpolate_yaml_env_vars(value, &format!("{key}[{i}]"), bare_env_vars)
+            })?
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            mapping.iter_mut().try_for_each(|(name, value)| {
+                let name = name.as_str().unwrap_or_default();
+                interpolate_yaml_env_vars(value, &join_key(key, name), bare_env_vars)
+            })?
         }
+        serde_yaml::Value::Tagged(tagged) => {
+            interpolate_yaml_env_vars(&mut tagged.value, key, bare_env_vars)?
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn join_key(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{parent}.{name}")
+    }
+}
+
+/// Every environment variable is looked up exactly once, so values that start with `$` are never
+/// mistaken for another placeholder. Whole values of the form `$NAME` are still read from the
+/// environment variable `NAME`, as they were before placeholders, but `key` is added to
+/// `bare_env_vars`.
+fn interpolate(
+    string: &str,
+    key: &str,
+    bare_env_vars: &mut Vec<String>,
+) -> Result<String, ConfigError> {
+    if let Some(name) = string
+        .strip_prefix('$')
+        .filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_'))
+    {
+        bare_env_vars.push(key.to_string());
+        return std::env::var(name).map_err(|source| ConfigError::EnvVar {
+            name: name.to_string(),
+            source,
+        });
     }
+    let mut interpolated = String::with_capacity(string.len());
+    let mut rest = string;
+    while let Some(start) = rest.find('$') {
+        interpolated.push_str(&rest[..start]);
+        let after_dollar = &rest[start + 1..];
+        if let Some(after) = after_dollar.strip_prefix('$') {
+            interpolated.push('$');
+            rest = after;
+        } else if let Some(placeholder) = after_dollar.strip_prefix('{') {
+            let (name, after) = placeholder
+                .split_once('}')
+                .ok_or(ConfigError::UnterminatedPlaceholder)?;
+            let value = std::env::var(name).map_err(|source| ConfigError::EnvVar {
+                name: name.to_string(),
+                source,
+            })?;
+            interpolated.push_str(&value);
+            rest = after;
+        } else {
+            interpolated.push('$');
+            rest = after_dollar;
+        }
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
 }
 
 /// These should be expressed as constants once
@@ -260,16 +1549,32 @@ mod serde_defaults {
         10
     }
 
-    pub fn protocol_chain_polling_interval_in_seconds() -> u64 {
-        120
+    pub fn freshness_check_enabled() -> bool {
+        true
     }
 
-    pub fn web3_transport_retry_max_wait_time_in_seconds() -> u64 {
-        60
+    pub fn error_budget_window() -> usize {
+        20
     }
 
-    pub fn transaction_monitoring_confirmation_timeout_in_seconds() -> u64 {
-        120
+    pub fn error_budget_max_failures() -> usize {
+        10
+    }
+
+    pub fn error_budget_degraded_cooldown_multiplier() -> u32 {
+        4
+    }
+
+    pub fn protocol_chain_polling_interval_in_seconds() -> u64 {
+        120
+    }
+
+    pub fn web3_transport_retry_max_wait_time_in_seconds() -> u64 {
+        60
+    }
+
+    pub fn transaction_monitoring_confirmation_timeout_in_seconds() -> u64 {
+        120
     }
 
     pub fn transaction_monitoring_max_retries() -> u32 {
@@ -292,14 +1597,113 @@ mod serde_defaults {
         100_000
     }
 
+    pub fn transaction_monitoring_gas_limit_percentage() -> u32 {
+        120
+    }
+
+    pub fn transaction_monitoring_block_gas_limit_margin() -> u32 {
+        10
+    }
+
+    pub fn transaction_monitoring_duplicate_check_blocks() -> u64 {
+        10_000
+    }
+
+    pub fn gas_fee_history_blocks() -> u64 {
+        10
+    }
+
+    pub fn gas_priority_fee_percentile() -> f64 {
+        50.0
+    }
+
+    pub fn gas_base_fee_percentage() -> u32 {
+        200
+    }
+
+    pub fn gas_priority_fee_percentage() -> u32 {
+        100
+    }
+
+    pub fn gas_legacy_gas_price_percentage() -> u32 {
+        100
+    }
+
+    pub fn dns_refresh_interval_in_seconds() -> u64 {
+        60
+    }
+
+    pub fn adaptive_polling_min_sampling_interval_in_seconds() -> u64 {
+        1
+    }
+
+    pub fn adaptive_polling_max_sampling_interval_in_seconds() -> u64 {
+        300
+    }
+
+    pub fn adaptive_polling_blocks_per_sample() -> u32 {
+        10
+    }
+
+    pub fn max_missed_epochs() -> u64 {
+        2
+    }
+
+    /// Leaves room for the calldata's ABI encoding below the 128 KiB that nodes accept for a
+    /// transaction by default.
+    pub fn max_payload_bytes() -> usize {
+        120_000
+    }
+
+    pub fn indexed_chain_enabled() -> bool {
+        true
+    }
+
+    pub fn indexed_chain_failover_cooldown_in_seconds() -> u64 {
+        60
+    }
+
+    pub fn defender_api_url() -> url::Url {
+        "https://api.defender.openzeppelin.com/".parse().unwrap()
+    }
+
+    pub fn defender_user_pool_id() -> String {
+        "us-west-2_iLmIggsiy".to_string()
+    }
+
+    pub fn defender_client_id() -> String {
+        "1bpd19lcr33qvg5cr3oi79rdap".to_string()
+    }
+
     pub fn metrics_port() -> u16 {
         9090
     }
+
+    pub fn pushgateway_job() -> String {
+        "block-oracle".to_string()
+    }
+
+    pub fn pushgateway_interval_in_seconds() -> u64 {
+        15
+    }
+
+    pub fn private_relay_timeout_in_seconds() -> u64 {
+        10
+    }
+
+    pub fn data_edge_function() -> String {
+        "crossChainEpochOracle".to_string()
+    }
+
+    pub fn co_signing_timeout_in_seconds() -> u64 {
+        600
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runner::error_handling::ErrorAction;
 
     fn indexed_chain(config: &Config, id: &str) -> IndexedChain {
         config
@@ -323,6 +1727,65 @@ mod tests {
         format!("{}/test/config/{}", env!("CARGO_MANIFEST_DIR"), filename)
     }
 
+    /// The entries that every config needs, except for the owner's key. Tests only spell out the
+    /// entries they're about, see [`config_with`].
+    const BASE_CONFIG: &str = r#"
+        owner_address = "90F8bf6A479f320ead074411a4B0e7944Ea8c9C1"
+        subgraph_url = "http://localhost:8080"
+        bearer_token = "token"
+        data_edge_address = "e78a0f7e598cc8b0bb87894b0f60dd2a88d6a8ab"
+        epoch_manager_address = "e78a0f7e598cc8b0bb87894b0f60dd2a88d6a8ab"
+        blockmeta_auth_token = "token"
+
+        [protocol_chain]
+        name = "eip155:1"
+        jrpc = "http://127.0.0.1:8545/"
+
+        [indexed_chains]
+        "eip155:1" = "http://127.0.0.1:8545/"
+    "#;
+
+    /// [`BASE_CONFIG`] with an `owner_private_key` and the entries of `extra` on top.
+    fn config_with(extra: &str) -> Config {
+        let owner_private_key = r#"owner_private_key = "4f3edf983ac636a65a842ce7c78d9aa706d3b113bce9c46f30d7d21715b23b1d""#;
+        try_config(&[owner_private_key, extra]).unwrap()
+    }
+
+    /// Like [`config_with`], but `extra` configures the owner's key itself.
+    fn config_with_owner(extra: &str) -> Config {
+        try_config(&[extra]).unwrap()
+    }
+
+    /// Parses [`BASE_CONFIG`] with the entries of every snippet added, or replaced if they're in
+    /// both, the same way as a config file.
+    fn try_config(snippets: &[&str]) -> anyhow::Result<Config> {
+        let mut value: toml::Value = toml::from_str(BASE_CONFIG)?;
+        for snippet in snippets {
+            merge(&mut value, toml::from_str(snippet)?);
+        }
+        interpolate_env_vars(&mut value, "", &mut vec![])?;
+        let config_file: ConfigFile = value.try_into()?;
+        let owner_key = config_file.owner_key()?;
+        Config::resolve(config_file, owner_key)
+    }
+
+    /// Tables are merged entry by entry, any other value is replaced.
+    fn merge(base: &mut toml::Value, extra: toml::Value) {
+        match (base, extra) {
+            (toml::Value::Table(base), toml::Value::Table(extra)) => {
+                for (key, value) in extra {
+                    match base.get_mut(&key) {
+                        Some(existing) => merge(existing, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, extra) => *base = extra,
+        }
+    }
+
     #[test]
     #[should_panic]
     fn invalid_jrpc_provider_url() {
@@ -332,7 +1795,12 @@ mod tests {
     #[test]
     #[should_panic]
     fn invalid_blockmeta_provider_url() {
-        Config::parse(config_file_path("invalid_blockmeta_provider_url.toml"));
+        config_with(
+            r#"
+            [blockmeta_indexed_chains]
+            "eip155:56" = "not a URL"
+            "#,
+        );
     }
 
     #[test]
@@ -340,6 +1808,709 @@ mod tests {
         Config::parse(config_file_path("config.sample.toml"));
     }
 
+    #[test]
+    fn indexed_chain_block_selection() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(
+            indexed_chain(&config, "spam:42").block_selection,
+            BlockSelectionStrategy::Latest
+        );
+        assert_eq!(
+            indexed_chain(&config, "eip155:100").block_selection,
+            BlockSelectionStrategy::DepthOffset { depth: 12 }
+        );
+    }
+
+    #[test]
+    fn indexed_chain_settings() {
+        let config = config_with(
+            r#"
+            [indexed_chains."eip155:1"]
+            jrpc = "http://127.0.0.1:8545/"
+            confirmations = 64
+            polling_interval_in_seconds = 30
+            rate_limit = 5
+
+            [indexed_chains."eip155:100"]
+            jrpc = ["http://127.0.0.1:8546/", "http://127.0.0.1:8547/"]
+            block_selection = { strategy = "finalized" }
+            failover = true
+
+            [indexed_chains."eip155:137"]
+            jrpc = "http://127.0.0.1:8548/"
+            enabled = false
+            "#,
+        );
+        let chain = indexed_chain(&config, "eip155:1");
+        assert_eq!(
+            chain.block_selection,
+            BlockSelectionStrategy::DepthOffset { depth: 64 }
+        );
+        assert_eq!(chain.polling_interval, Some(Duration::from_secs(30)));
+        assert_eq!(chain.rate_limit, NonZeroU32::new(5));
+        assert_eq!(chain.failover_cooldown, None);
+        let chain = indexed_chain(&config, "eip155:100");
+        assert_eq!(chain.block_selection, BlockSelectionStrategy::Finalized);
+        assert_eq!(chain.polling_interval, None);
+        assert_eq!(chain.rate_limit, None);
+        assert_eq!(chain.failover_cooldown, Some(Duration::from_secs(60)));
+        // Disabled chains are left out.
+        assert_eq!(
+            config
+                .indexed_chains
+                .iter()
+                .filter(|chain| chain.id.as_str() == "eip155:137")
+                .count(),
+            0
+        );
+
+        let conflicting: SerdeIndexedChain = toml::from_str(
+            r#"
+            jrpc = "http://127.0.0.1:8545/"
+            block_selection = { strategy = "latest" }
+            confirmations = 12
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            conflicting.resolve(Caip2ChainId::ethereum_mainnet()),
+            Err(ConfigError::IndexedChain { .. })
+        ));
+    }
+
+    #[test]
+    fn actionable_errors() {
+        let path = config_file_path("invalid_jrpc_provider_url.toml");
+        let error = format!("{:#}", Config::try_parse(&path).unwrap_err());
+        assert!(error.contains(&path), "{error}");
+        assert!(
+            error.contains("for key `indexed_chains.eip155:77`"),
+            "{error}"
+        );
+        assert!(error.contains("expected an absolute URL"), "{error}");
+
+        let error = format!(
+            "{:#}",
+            Config::try_parse(config_file_path("missing.toml")).unwrap_err()
+        );
+        assert!(
+            error.starts_with("Failed to read the config file"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn json_config() {
+        let config = Config::parse(config_file_path("json_config.json"));
+        assert_eq!(config.protocol_chain.polling_interval.as_secs(), 30);
+        assert_eq!(indexed_chain(&config, "eip155:1").jrpc_urls.len(), 1);
+        let chain = indexed_chain(&config, "eip155:100");
+        assert_eq!(chain.jrpc_urls.len(), 2);
+        assert_eq!(
+            chain.block_selection,
+            BlockSelectionStrategy::DepthOffset { depth: 12 }
+        );
+
+        assert_eq!(ConfigFormat::of(Path::new("config")), ConfigFormat::Toml);
+    }
+
+    #[test]
+    fn yaml_config() {
+        std::env::set_var("BLOCK_ORACLE_TEST_YAML_API_KEY", "secret");
+        let config = Config::parse(config_file_path("yaml_config.yaml"));
+        assert_eq!(config.protocol_chain.polling_interval.as_secs(), 30);
+        assert_eq!(indexed_chain(&config, "eip155:1").jrpc_urls.len(), 1);
+        let chain = indexed_chain(&config, "eip155:100");
+        assert_eq!(
+            chain.jrpc_urls[1].as_str(),
+            "https://gnosis.example.com/secret/rpc"
+        );
+        assert_eq!(
+            chain.block_selection,
+            BlockSelectionStrategy::DepthOffset { depth: 12 }
+        );
+
+        assert_eq!(
+            ConfigFormat::of(Path::new("config.yml")),
+            ConfigFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn rate_limits() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(config.protocol_chain.rate_limit, None);
+
+        let config = config_with(
+            r#"
+            protocol_chain = { rate_limit = 25 }
+            indexed_chains = { "eip155:1" = { jrpc = "http://127.0.0.1:8545/", rate_limit = 5 } }
+            "#,
+        );
+        assert_eq!(config.protocol_chain.rate_limit, NonZeroU32::new(25));
+        assert_eq!(
+            indexed_chain(&config, "eip155:1").rate_limit,
+            NonZeroU32::new(5)
+        );
+    }
+
+    #[test]
+    fn indexed_chain_multiple_providers() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(indexed_chain(&config, "spam:42").jrpc_urls.len(), 1);
+        assert_eq!(
+            indexed_chain(&config, "eip155:137")
+                .jrpc_urls
+                .iter()
+                .map(Url::as_str)
+                .collect::<Vec<_>>(),
+            vec!["https://example.com/", "https://example.org/"]
+        );
+    }
+
+    #[test]
+    fn runtime_options() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(
+            config.runtime_options,
+            RuntimeOptions {
+                flavor: RuntimeFlavor::MultiThread,
+                worker_threads: Some(2),
+                max_blocking_threads: None,
+            }
+        );
+    }
+
+    #[test]
+    fn adaptive_polling() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(config.adaptive_polling, None);
+
+        let config = config_with("[adaptive_polling]\nmax_sampling_interval_in_seconds = 600");
+        assert_eq!(
+            config.adaptive_polling,
+            Some(AdaptivePollingOptions {
+                min_sampling_interval_in_seconds: 1,
+                max_sampling_interval_in_seconds: 600,
+                blocks_per_sample: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn pushgateway() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(config.pushgateway, None);
+        assert_eq!(config.dns_refresh_interval, Some(Duration::from_secs(60)));
+        assert_eq!(config.max_missed_epochs, Some(2));
+
+        let config = config_with(
+            r#"
+            [pushgateway]
+            url = "http://pushgateway.example.com:9091"
+            interval_in_seconds = 30
+            "#,
+        );
+        assert_eq!(
+            config.pushgateway,
+            Some(PushgatewayOptions {
+                url: "http://pushgateway.example.com:9091".parse().unwrap(),
+                job: "block-oracle".to_string(),
+                interval: Duration::from_secs(30),
+            })
+        );
+    }
+
+    #[test]
+    fn private_relay() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(config.private_relay, None);
+
+        let config = config_with(
+            r#"
+            [private_relay]
+            url = "https://rpc.flashbots.net/fast"
+            "#,
+        );
+        assert_eq!(
+            config.private_relay,
+            Some(PrivateRelayOptions {
+                url: "https://rpc.flashbots.net/fast".parse().unwrap(),
+                timeout: Duration::from_secs(10),
+            })
+        );
+    }
+
+    #[test]
+    fn data_edge_call() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(config.data_edge_call, DataEdgeCall::default());
+
+        let config = config_with(
+            r#"
+            [data_edge_call]
+            function = "submitPayload"
+            abi_path = "test/config/data_edge_abi.json"
+            "#,
+        );
+        assert_eq!(
+            config.data_edge_call,
+            DataEdgeCall::Function {
+                name: "submitPayload".to_string(),
+                abi_path: Some("test/config/data_edge_abi.json".into()),
+            }
+        );
+
+        let data_edge_call = |function: Option<&str>, raw_calldata| SerdeDataEdgeCall {
+            function: function.map(ToString::to_string),
+            abi_path: None,
+            raw_calldata,
+        };
+        assert_eq!(
+            data_edge_call(None, false).resolve().unwrap(),
+            DataEdgeCall::default()
+        );
+        assert_eq!(
+            data_edge_call(None, true).resolve().unwrap(),
+            DataEdgeCall::Fallback
+        );
+        assert!(data_edge_call(Some("submitPayload"), true)
+            .resolve()
+            .is_err());
+    }
+
+    #[test]
+    fn co_signing() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(config.co_signing, None);
+
+        let config = config_with(
+            r#"
+            [co_signing]
+            port = 9092
+            contract_address = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+            signers = [
+                "0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1",
+                "0x70997970C51812dc3A010C7d01b50e0d17dc79C8",
+                "0x3C44CdDdB6a900fa2b585dd299e03d12FA4293BC",
+            ]
+            threshold = 2
+            "#,
+        );
+        assert_eq!(
+            config.co_signing,
+            Some(CoSigningOptions {
+                port: 9092,
+                contract_address: "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+                    .parse()
+                    .unwrap(),
+                signers: vec![
+                    "0x3C44CdDdB6a900fa2b585dd299e03d12FA4293BC"
+                        .parse()
+                        .unwrap(),
+                    "0x70997970C51812dc3A010C7d01b50e0d17dc79C8"
+                        .parse()
+                        .unwrap(),
+                    "0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1"
+                        .parse()
+                        .unwrap(),
+                ],
+                threshold: 2,
+                timeout: Duration::from_secs(600),
+            })
+        );
+
+        let co_signing = |threshold, signers: &[&str]| SerdeCoSigning {
+            port: 9092,
+            contract_address: FromStrWrapper(H160::zero()),
+            signers: signers
+                .iter()
+                .map(|signer| FromStrWrapper(signer.parse().unwrap()))
+                .collect(),
+            threshold,
+            timeout_in_seconds: 600,
+        };
+        let signer = "0x90F8bf6A479f320ead074411a4B0e7944Ea8c9C1";
+        assert!(co_signing(1, &[signer]).resolve().is_ok());
+        assert!(co_signing(0, &[signer]).resolve().is_err());
+        assert!(co_signing(2, &[signer]).resolve().is_err());
+        assert!(co_signing(2, &[signer, signer]).resolve().is_err());
+    }
+
+    #[test]
+    fn transaction_type() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(
+            config.transaction_monitoring_options.gas.transaction_type,
+            TransactionType::Auto
+        );
+
+        let config = config_with(
+            r#"
+            [transaction_monitoring.gas]
+            transaction_type = "legacy"
+            "#,
+        );
+        assert_eq!(
+            config.transaction_monitoring_options.gas.transaction_type,
+            TransactionType::Legacy
+        );
+    }
+
+    #[test]
+    fn pending_transactions() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(
+            config.transaction_monitoring_options.pending_transactions,
+            PendingTransactionPolicy::Adopt
+        );
+
+        let config = config_with(
+            r#"
+            [transaction_monitoring]
+            pending_transactions = "replace"
+            "#,
+        );
+        assert_eq!(
+            config.transaction_monitoring_options.pending_transactions,
+            PendingTransactionPolicy::Replace
+        );
+    }
+
+    #[test]
+    fn register_new_networks() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert!(!config.register_new_networks);
+
+        let config = config_with("register_new_networks = true");
+        assert!(config.register_new_networks);
+    }
+
+    #[test]
+    fn dry_run() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert!(!config.dry_run);
+
+        let config = config_with("dry_run = true");
+        assert!(config.dry_run);
+    }
+
+    #[test]
+    fn freshness_check() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert_eq!(
+            config.freshness_check,
+            FreshnessCheckOptions {
+                enabled: true,
+                scan: FreshnessScan::Transactions,
+                threshold: 10,
+                on_stale: StaleSubgraphAction::Defer,
+            }
+        );
+
+        let config = config_with(
+            r#"
+            # The top-level setting is still honored, unless the table overrides it.
+            freshness_threshold = 20
+
+            [freshness_check]
+            scan = "logs"
+            threshold = 500
+            on_stale = "warn"
+            "#,
+        );
+        assert_eq!(
+            config.freshness_check,
+            FreshnessCheckOptions {
+                enabled: true,
+                scan: FreshnessScan::Logs,
+                threshold: 500,
+                on_stale: StaleSubgraphAction::Warn,
+            }
+        );
+    }
+
+    #[test]
+    fn error_policies() {
+        let config = Config::parse(config_file_path("config.sample.toml"));
+        assert!(config.error_policies.is_empty());
+
+        let config = config_with(
+            r#"
+            [error_policies]
+            subgraph-not-fresh = { action = "retry", cooldown = 1, max_consecutive = 100 }
+            cant-submit-tx = { action = "pause" }
+            "#,
+        );
+        assert_eq!(
+            config.error_policies[&ErrorClass::SubgraphNotFresh],
+            ErrorPolicy {
+                action: ErrorAction::Retry { cooldown: 1 },
+                max_consecutive: Some(100),
+            }
+        );
+        assert_eq!(
+            config.error_policies[&ErrorClass::CantSubmitTx],
+            ErrorPolicy {
+                action: ErrorAction::Pause,
+                max_consecutive: None,
+            }
+        );
+    }
+
+    #[test]
+    fn owner_key_source() {
+        let expected: SecretKey =
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+                .parse()
+                .unwrap();
+        let source = OwnerKeySource::File(config_file_path("owner_private_key.txt").into());
+        let config =
+            Config::try_parse_with_owner_key(config_file_path("config.sample.toml"), &source)
+                .unwrap();
+        let OwnerKey::PrivateKey(private_key) = config.owner_key else {
+            panic!("expected a private key");
+        };
+        assert_eq!(private_key, expected);
+
+        std::env::set_var(
+            "BLOCK_ORACLE_TEST_OWNER_KEY",
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d",
+        );
+        let source = OwnerKeySource::EnvVar("BLOCK_ORACLE_TEST_OWNER_KEY".to_string());
+        assert_eq!(source.load().unwrap(), expected);
+
+        // The key itself is never part of the error.
+        let source = OwnerKeySource::File(config_file_path("config.sample.toml").into());
+        let error = source.load().unwrap_err().to_string();
+        assert!(error.ends_with("config.sample.toml isn't a 32-byte hex private key"));
+    }
+
+    #[test]
+    fn owner_keystore() {
+        let config = config_with_owner(
+            r#"
+            owner_address = "008aeeda4d805471df9b2a5b0f38a0c3bcba786b"
+
+            [owner_keystore]
+            path = "test/config/keystore.json"
+            password = "testpassword"
+            "#,
+        );
+        let OwnerKey::PrivateKey(private_key) = config.owner_key else {
+            panic!("expected a private key");
+        };
+        assert_eq!(
+            private_key,
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+                .parse()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn owner_aws_kms() {
+        let config = config_with_owner(
+            r#"
+            [owner_aws_kms]
+            key_id = "arn:aws:kms:eu-west-1:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab"
+            "#,
+        );
+        let OwnerKey::AwsKms(options) = config.owner_key else {
+            panic!("expected an AWS KMS key");
+        };
+        assert_eq!(
+            options,
+            AwsKmsOptions {
+                key_id:
+                    "arn:aws:kms:eu-west-1:111122223333:key/1234abcd-12ab-34cd-56ef-1234567890ab"
+                        .to_string(),
+                region: "eu-west-1".to_string(),
+                endpoint: "https://kms.eu-west-1.amazonaws.com/".parse().unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn owner_vault() {
+        std::env::set_var("BLOCK_ORACLE_TEST_VAULT_TOKEN", "hvs.token");
+        let config = config_with_owner(
+            r#"
+            [owner_vault]
+            address = "https://vault.example.com:8200"
+            token = "${BLOCK_ORACLE_TEST_VAULT_TOKEN}"
+            mount = "secp256k1-transit"
+            key = "block-oracle"
+            "#,
+        );
+        let OwnerKey::Vault(options) = config.owner_key else {
+            panic!("expected a Vault key");
+        };
+        assert_eq!(
+            options,
+            VaultOptions {
+                address: "https://vault.example.com:8200".parse().unwrap(),
+                token: "hvs.token".to_string(),
+                namespace: None,
+                mount: "secp256k1-transit".to_string(),
+                key: "block-oracle".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn owner_remote_signer() {
+        let config = config_with_owner(
+            r#"
+            [owner_remote_signer]
+            url = "http://127.0.0.1:9000"
+            "#,
+        );
+        let OwnerKey::Remote(options) = config.owner_key else {
+            panic!("expected a remote signer");
+        };
+        assert_eq!(
+            options,
+            RemoteSignerOptions {
+                url: "http://127.0.0.1:9000".parse().unwrap(),
+                address: config.owner_address,
+            }
+        );
+    }
+
+    #[test]
+    fn owner_defender() {
+        std::env::set_var("BLOCK_ORACLE_TEST_DEFENDER_API_SECRET", "secret");
+        let config = config_with_owner(
+            r#"
+            [owner_defender]
+            api_key = "key"
+            api_secret = "${BLOCK_ORACLE_TEST_DEFENDER_API_SECRET}"
+            speed = "fastest"
+            "#,
+        );
+        let OwnerKey::Defender(options) = config.owner_key else {
+            panic!("expected a Defender relayer");
+        };
+        assert_eq!(
+            options,
+            DefenderOptions {
+                api_key: "key".to_string(),
+                api_secret: "secret".to_string(),
+                api_url: "https://api.defender.openzeppelin.com/".parse().unwrap(),
+                user_pool_id: "us-west-2_iLmIggsiy".to_string(),
+                client_id: "1bpd19lcr33qvg5cr3oi79rdap".to_string(),
+                speed: DefenderSpeed::Fastest,
+                address: config.owner_address,
+            }
+        );
+    }
+
+    #[test]
+    fn env_var_interpolation() {
+        std::env::set_var("BLOCK_ORACLE_TEST_API_KEY", "secret");
+        std::env::set_var(
+            "BLOCK_ORACLE_TEST_DATA_EDGE",
+            "0xe78a0f7e598cc8b0bb87894b0f60dd2a88d6a8ab",
+        );
+        let config = config_with(
+            r#"
+            data_edge_address = "${BLOCK_ORACLE_TEST_DATA_EDGE}"
+
+            [protocol_chain]
+            name = "eip155:42161"
+            jrpc = "https://arb-mainnet.example.com/v2/${BLOCK_ORACLE_TEST_API_KEY}"
+
+            [indexed_chains]
+            "eip155:1" = [
+                "https://example.com",
+                "https://eth.example.com/${BLOCK_ORACLE_TEST_API_KEY}/rpc",
+            ]
+            "#,
+        );
+        assert_eq!(
+            config.protocol_chain.jrpc_url.as_str(),
+            "https://arb-mainnet.example.com/v2/secret"
+        );
+        assert_eq!(
+            indexed_chain(&config, "eip155:1").jrpc_urls[1].as_str(),
+            "https://eth.example.com/secret/rpc"
+        );
+        assert_eq!(
+            config.data_edge_address,
+            "e78a0f7e598cc8b0bb87894b0f60dd2a88d6a8ab".parse().unwrap()
+        );
+
+        let interpolate = |string| interpolate(string, "key", &mut vec![]);
+        assert_eq!(interpolate("no placeholders").unwrap(), "no placeholders");
+        assert_eq!(
+            interpolate("${BLOCK_ORACLE_TEST_API_KEY}${BLOCK_ORACLE_TEST_API_KEY}").unwrap(),
+            "secretsecret"
+        );
+        assert!(matches!(
+            interpolate("https://example.com/${BLOCK_ORACLE_TEST_UNSET}"),
+            Err(ConfigError::EnvVar { name, .. }) if name == "BLOCK_ORACLE_TEST_UNSET"
+        ));
+        assert!(matches!(
+            interpolate("https://example.com/${BLOCK_ORACLE_TEST_API_KEY"),
+            Err(ConfigError::UnterminatedPlaceholder)
+        ));
+
+        // Interpolated values are taken as they are, even if they look like placeholders.
+        std::env::set_var("BLOCK_ORACLE_TEST_DOLLARS", "$BLOCK_ORACLE_TEST_API_KEY");
+        assert_eq!(
+            interpolate("${BLOCK_ORACLE_TEST_DOLLARS}").unwrap(),
+            "$BLOCK_ORACLE_TEST_API_KEY"
+        );
+        assert_eq!(
+            interpolate("$${BLOCK_ORACLE_TEST_API_KEY} costs 5$").unwrap(),
+            "${BLOCK_ORACLE_TEST_API_KEY} costs 5$"
+        );
+        assert_eq!(interpolate("$$ecret").unwrap(), "$ecret");
+
+        // The whole value is read from the environment in the deprecated form, as it used to be.
+        let mut bare_env_vars = vec![];
+        assert_eq!(
+            super::interpolate("$BLOCK_ORACLE_TEST_API_KEY", "key", &mut bare_env_vars).unwrap(),
+            "secret"
+        );
+        assert_eq!(bare_env_vars, ["key"]);
+    }
+
+    #[test]
+    fn reload() {
+        let config = config_with_owner(
+            r#"
+            owner_address = "008aeeda4d805471df9b2a5b0f38a0c3bcba786b"
+
+            [owner_keystore]
+            path = "test/config/keystore.json"
+            password = "testpassword"
+            "#,
+        );
+        let reloaded = config
+            .reload(config_file_path("config.sample.toml"))
+            .unwrap();
+        // The keystore's key is kept, instead of the sample's `owner_private_key`.
+        let (OwnerKey::PrivateKey(key), OwnerKey::PrivateKey(reloaded_key)) =
+            (&config.owner_key, &reloaded.owner_key)
+        else {
+            panic!("expected private keys");
+        };
+        assert_eq!(key, reloaded_key);
+        assert_eq!(reloaded.protocol_chain.polling_interval.as_secs(), 5);
+        assert!(indexed_chain(&reloaded, "eip155:100")
+            .jrpc_urls
+            .iter()
+            .any(|url| url.as_str() == "https://example.com/"));
+
+        // Broken files are an error, rather than a panic.
+        assert!(config
+            .reload(config_file_path("invalid_jrpc_provider_url.toml"))
+            .is_err());
+        assert!(config.reload(config_file_path("missing.toml")).is_err());
+    }
+
     #[test]
     fn set_provider_via_env_var() {
         let jrpc_url = "https://sokol-archive.blockscout.com/";
@@ -351,7 +2522,7 @@ mod tests {
         let config = Config::parse(config_file_path("indexed_chain_provider_via_env_var.toml"));
 
         assert_eq!(
-            indexed_chain(&config, "eip155:77").jrpc_url.as_str(),
+            indexed_chain(&config, "eip155:77").jrpc_urls[0].as_str(),
             jrpc_url
         );
 
@@ -359,5 +2530,12 @@ mod tests {
             blockmeta_indexed_chain(&config, "bip122:77").url.as_str(),
             url
         );
+        assert_eq!(
+            config.bare_env_vars,
+            [
+                "blockmeta_indexed_chains.bip122:77",
+                "indexed_chains.eip155:77"
+            ]
+        );
     }
 }
@@ -0,0 +1,248 @@
+//! Live validation of a configuration, for `block-oracle config check`.
+//!
+//! Every check talks to the service it's about, so that a wrong URL, chain or key shows up
+//! before the oracle is started rather than in its first polling iterations. Plain HTTP
+//! transports are used, without the oracle's retries, so that failures are reported right away.
+
+use crate::config::OwnerKey;
+use crate::defender::DefenderRelayer;
+use crate::metrics::Metrics;
+use crate::models::eip155_chain_id;
+use crate::{query_subgraph, signer, Caip2ChainId, Config};
+use std::fmt::Display;
+use url::Url;
+use web3::types::Address;
+use web3::{transports::Http, Web3};
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// What was checked, e.g. `eip155:1 JSON RPC #0`.
+    pub subject: String,
+    /// What was found if the check passed, or why it failed.
+    pub outcome: Result<String, String>,
+}
+
+impl Check {
+    fn new(subject: impl Display, outcome: Result<String, String>) -> Self {
+        Self {
+            subject: subject.to_string(),
+            outcome,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome.is_ok()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Ok(details) => write!(f, "PASS  {}: {details}", self.subject),
+            Err(reason) => write!(f, "FAIL  {}: {reason}", self.subject),
+        }
+    }
+}
+
+/// Runs all checks of `config` one after the other, in the order they're reported in.
+pub async fn check(config: &Config) -> Vec<Check> {
+    let mut checks = vec![];
+    let protocol_chain = &config.protocol_chain;
+    checks.push(Check::new(
+        format!("protocol chain {} JSON RPC", protocol_chain.id),
+        check_protocol_chain_id(&protocol_chain.jrpc_url, &protocol_chain.id).await,
+    ));
+    for chain in &config.indexed_chains {
+        for (index, jrpc_url) in chain.jrpc_urls.iter().enumerate() {
+            checks.push(Check::new(
+                format!("indexed chain {} JSON RPC #{index}", chain.id),
+                check_chain_id(jrpc_url, &chain.id).await,
+            ));
+        }
+    }
+    checks.push(Check::new(
+        "DataEdge contract",
+        check_code(&protocol_chain.jrpc_url, config.data_edge_address).await,
+    ));
+    checks.push(Check::new(
+        "Epoch Manager contract",
+        check_code(&protocol_chain.jrpc_url, config.epoch_manager_address).await,
+    ));
+    checks.push(Check::new("owner key", check_owner_key(config).await));
+    checks.push(Check::new("Epoch Subgraph", check_subgraph(config).await));
+    checks
+}
+
+/// Transactions are signed for the protocol chain's ID, so unlike indexed chains, it must be an
+/// eip155 chain whose ID can be verified. The oracle refuses to sign otherwise.
+async fn check_protocol_chain_id(
+    jrpc_url: &Url,
+    expected: &Caip2ChainId,
+) -> Result<String, String> {
+    if eip155_chain_id(expected).is_none() {
+        return Err(format!(
+            "{expected} isn't an eip155 chain, so transactions can't be signed for it"
+        ));
+    }
+    check_chain_id(jrpc_url, expected).await
+}
+
+async fn check_chain_id(jrpc_url: &Url, expected: &Caip2ChainId) -> Result<String, String> {
+    let web3 = web3(jrpc_url)?;
+    let chain_id = web3
+        .eth()
+        .chain_id()
+        .await
+        .map_err(|error| format!("eth_chainId failed: {error}"))?;
+    match eip155_chain_id(expected) {
+        Some(expected) if expected == chain_id => Ok(format!("chain ID {chain_id}")),
+        Some(expected) => Err(format!("chain ID {chain_id}, but {expected} was expected")),
+        None => Ok(format!(
+            "chain ID {chain_id}, which can't be verified for a non-eip155 chain"
+        )),
+    }
+}
+
+async fn check_code(jrpc_url: &Url, address: Address) -> Result<String, String> {
+    let code = web3(jrpc_url)?
+        .eth()
+        .code(address, None)
+        .await
+        .map_err(|error| format!("eth_getCode failed: {error}"))?;
+    if code.0.is_empty() {
+        return Err(format!("there's no contract at {address:?}"));
+    }
+    Ok(format!("{} bytes of code at {address:?}", code.0.len()))
+}
+
+async fn check_owner_key(config: &Config) -> Result<String, String> {
+    let address = match &config.owner_key {
+        // The relayer's address is configured, so only Defender can tell whether it's right.
+        OwnerKey::Defender(options) => DefenderRelayer::new(options.clone())
+            .address()
+            .await
+            .map_err(|error| format!("failed to query the Defender relayer: {error}"))?,
+        owner_key => signer::from_owner_key(owner_key)
+            .address()
+            .await
+            .map_err(|error| format!("failed to derive the owner address: {error}"))?,
+    };
+    if address != config.owner_address {
+        return Err(format!(
+            "the key belongs to {address:?}, but `owner_address` is {:?}",
+            config.owner_address
+        ));
+    }
+    Ok(format!("belongs to {address:?}"))
+}
+
+async fn check_subgraph(config: &Config) -> Result<String, String> {
+    let metrics = Metrics::new().map_err(|error| error.to_string())?;
+    let state = query_subgraph(&config.subgraph_url, &config.bearer_token, &metrics)
+        .await
+        .map_err(|error| error.to_string())?;
+    Ok(match state.latest_epoch_number() {
+        Some(epoch) => format!(
+            "at epoch {epoch}, indexed up to block #{}",
+            state.last_indexed_block_number
+        ),
+        None => "not initialized yet".to_string(),
+    })
+}
+
+fn web3(jrpc_url: &Url) -> Result<Web3<Http>, String> {
+    let transport = Http::new(jrpc_url.as_str()).map_err(|error| error.to_string())?;
+    Ok(Web3::new(transport))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DefenderOptions;
+    use crate::test_utils::jrpc_provider;
+    use serde_json::json;
+
+    /// A JSON RPC provider on chain 1, with code everywhere.
+    async fn provider() -> Url {
+        jrpc_provider(|method, _| match method {
+            "eth_chainId" => json!("0x1"),
+            "eth_getCode" => json!("0x6080"),
+            method => panic!("unexpected method {method}"),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn checks() {
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        let url = provider().await;
+        config.protocol_chain.jrpc_url = url.clone();
+        config
+            .indexed_chains
+            .retain(|chain| chain.id.as_str() == "eip155:100");
+        config.indexed_chains[0].jrpc_urls = vec![url];
+        // Nothing listens there.
+        config.subgraph_url = "http://127.0.0.1:1".parse().unwrap();
+
+        let checks = check(&config).await;
+        let outcomes: Vec<_> = checks
+            .iter()
+            .map(|check| (check.subject.as_str(), check.passed()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("protocol chain eip155:1 JSON RPC", true),
+                ("indexed chain eip155:100 JSON RPC #0", false),
+                ("DataEdge contract", true),
+                ("Epoch Manager contract", true),
+                ("owner key", false),
+                ("Epoch Subgraph", false),
+            ]
+        );
+        assert_eq!(
+            checks[1].to_string(),
+            "FAIL  indexed chain eip155:100 JSON RPC #0: chain ID 1, but 100 was expected"
+        );
+    }
+
+    #[tokio::test]
+    async fn protocol_chain_must_be_eip155() {
+        let bitcoin = "bip122:000000000019d6689c085ae165831e93".parse().unwrap();
+        let url = provider().await;
+        assert!(check_chain_id(&url, &bitcoin).await.is_ok());
+        assert_eq!(
+            check_protocol_chain_id(&url, &bitcoin).await,
+            Err(format!(
+                "{bitcoin} isn't an eip155 chain, so transactions can't be signed for it"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn defender_keys_are_checked_with_defender() {
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        config.owner_key = OwnerKey::Defender(DefenderOptions {
+            api_key: "key".to_string(),
+            api_secret: "secret".to_string(),
+            api_url: "http://127.0.0.1:1".parse().unwrap(),
+            // Fails before anything is sent.
+            user_pool_id: "invalid".to_string(),
+            client_id: "client".to_string(),
+            speed: Default::default(),
+            address: config.owner_address,
+        });
+        let error = check_owner_key(&config).await.unwrap_err();
+        assert!(
+            error.starts_with("failed to query the Defender relayer"),
+            "{error}"
+        );
+    }
+}
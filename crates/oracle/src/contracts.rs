@@ -1,24 +1,39 @@
 use crate::{
-    config::TransactionMonitoringOptions,
-    metrics::METRICS,
+    config::{DataEdgeCall, PendingTransactionPolicy, TransactionMonitoringOptions},
+    confirmations::{ConfirmationError, ConfirmationTracker},
+    defender::DefenderError,
+    gas::GasEstimator,
+    metrics::Metrics,
+    nonce_manager::NonceManager,
+    private_relay::PrivateRelay,
+    signer::Signer,
+    submitter::SentTransaction,
     transaction_monitor::{TransactionMonitor, TransactionMonitorError},
+    Config,
 };
 use anyhow::Context;
-use secp256k1::SecretKey;
-use tracing::{debug, info, trace};
+use futures::stream::StreamExt;
+use std::ops::RangeInclusive;
+use tracing::{debug, info, trace, warn};
 use web3::{
     api::Eth,
-    contract::{tokens::Tokenize, Contract},
-    ethabi::Address,
-    signing::SecretKeyRef,
-    types::{TransactionReceipt, U256},
+    contract::Contract,
+    ethabi::{Address, Function, ParamType, Token},
+    types::{
+        BlockId, BlockNumber, Bytes, CallRequest, Transaction, TransactionReceipt,
+        TxpoolContentInfo, H256, U256,
+    },
     Transport, Web3,
 };
 
 static EPOCH_MANAGER_ABI: &[u8] = include_bytes!("abi/EpochManager.json");
 static EPOCH_MANAGER_FUNCTION_NAME: &str = "currentEpoch";
 static DATA_EDGE_ABI: &[u8] = include_bytes!("abi/DataEdge.json");
-static DATA_EDGE_FUNCTION_NAME: &str = "crossChainEpochOracle";
+pub(crate) static DATA_EDGE_FUNCTION_NAME: &str = "crossChainEpochOracle";
+static MULTICALL_ABI: &[u8] = include_bytes!("abi/Multicall3.json");
+const GWEI: u64 = 1_000_000_000;
+/// How many blocks are fetched at once when looking for duplicate payloads.
+const DUPLICATE_CHECK_CONCURRENCY: usize = 10;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ContractError {
@@ -26,6 +41,59 @@ pub enum ContractError {
     TransactionMonitor(#[from] TransactionMonitorError),
     #[error(transparent)]
     AbiEncode(#[from] web3::ethabi::Error),
+    #[error(
+        "The payload needs an estimated {estimated_gas} gas, but only {usable_gas} gas of the \
+         protocol chain's block gas limit may be used. It must be split into smaller payloads"
+    )]
+    ExceedsBlockGasLimit {
+        estimated_gas: U256,
+        usable_gas: U256,
+    },
+    #[error(
+        "The payload needs an estimated {estimated_gas} gas, but the gas limit may be at most \
+         {max_gas_limit}. It must be split into smaller payloads"
+    )]
+    ExceedsMaxGasLimit {
+        estimated_gas: U256,
+        max_gas_limit: U256,
+    },
+    #[error(
+        "The payload has {size} bytes, but payloads may have at most {max_bytes}. It must be \
+         split into smaller payloads"
+    )]
+    PayloadTooLarge { size: usize, max_bytes: usize },
+    #[error(
+        "The estimated gas price of {gas_price} wei is above the configured maximum of \
+         {max_gas_price} wei. Postponing the submission"
+    )]
+    GasPriceTooHigh {
+        gas_price: U256,
+        max_gas_price: U256,
+    },
+    #[error(
+        "The owner's balance of {balance} wei doesn't cover the transaction's maximum cost plus \
+         the configured buffer, {required} wei"
+    )]
+    InsufficientFunds { balance: U256, required: U256 },
+    #[error("Failed to look for identical payloads in recent blocks: {0}")]
+    DuplicateCheck(#[source] web3::Error),
+    #[error("Failed to query the owner's balance: {0}")]
+    Balance(#[source] web3::Error),
+    #[error("Failed to relay the transaction through Defender: {0}")]
+    Defender(#[from] DefenderError),
+    #[error("There is no contract at {0:?}, where payloads are sent")]
+    NoDataEdgeContract(Address),
+    #[error("The DataEdge call would revert: {reason}")]
+    SimulationReverted { reason: String },
+    #[error("Failed to simulate the DataEdge call: {0}")]
+    Simulation(#[source] web3::Error),
+}
+
+/// A DataEdge transaction that was confirmed.
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub receipt: TransactionReceipt,
+    pub nonce: U256,
 }
 
 pub struct Contracts<T>
@@ -34,8 +102,15 @@ where
 {
     client: Web3<T>,
     data_edge: Contract<T>,
+    /// Payloads are passed to the fallback function if `None`.
+    data_edge_function: Option<String>,
     epoch_manager: Contract<T>,
+    multicall: Option<Contract<T>>,
     transaction_monitoring_options: TransactionMonitoringOptions,
+    nonces: NonceManager,
+    metrics: Option<Metrics>,
+    private_relay: Option<PrivateRelay>,
+    max_payload_bytes: Option<usize>,
 }
 
 impl<T> Contracts<T>
@@ -54,11 +129,105 @@ where
         Ok(Self {
             client,
             data_edge,
+            data_edge_function: Some(DATA_EDGE_FUNCTION_NAME.to_string()),
             epoch_manager,
+            multicall: None,
             transaction_monitoring_options,
+            nonces: NonceManager::new(),
+            metrics: None,
+            private_relay: None,
+            max_payload_bytes: None,
         })
     }
 
+    /// The contracts of `config` on the protocol chain that `client` talks to, along with the
+    /// configured DataEdge call, Multicall3 contract, private relay and payload size limit.
+    pub fn from_config(client: Web3<T>, config: &Config) -> anyhow::Result<Self> {
+        let mut contracts = Contracts::new(
+            client,
+            config.data_edge_address,
+            config.epoch_manager_address,
+            config.transaction_monitoring_options,
+        )
+        .context("Failed to initialize Block Oracle's required contracts")?
+        .with_data_edge_call(&config.data_edge_call)
+        .context("Failed to initialize the DataEdge call")?;
+        if let Some(multicall_address) = config.multicall_address {
+            contracts = contracts
+                .with_multicall(multicall_address)
+                .context("Failed to initialize the Multicall3 contract")?;
+        }
+        if let Some(options) = &config.private_relay {
+            contracts = contracts.with_private_relay(PrivateRelay::new(options));
+        }
+        if let Some(max_payload_bytes) = config.max_payload_bytes {
+            contracts = contracts.with_max_payload_bytes(max_payload_bytes);
+        }
+        Ok(contracts)
+    }
+
+    /// Passes payloads to the DataEdge as `call` says, e.g. to another function of a custom ABI.
+    pub fn with_data_edge_call(mut self, call: &DataEdgeCall) -> anyhow::Result<Self> {
+        let DataEdgeCall::Function { name, abi_path } = call else {
+            self.data_edge_function = None;
+            return Ok(self);
+        };
+        if let Some(abi_path) = abi_path {
+            let abi = std::fs::read(abi_path).with_context(|| {
+                format!("Failed to read the DataEdge ABI at {}", abi_path.display())
+            })?;
+            self.data_edge =
+                Contracts::new_contract(&abi, &self.client.eth(), self.data_edge.address())?;
+        }
+        let function = self
+            .data_edge
+            .abi()
+            .function(name)
+            .with_context(|| format!("The DataEdge ABI has no function `{name}`"))?;
+        anyhow::ensure!(
+            function.inputs.len() == 1 && function.inputs[0].kind == ParamType::Bytes,
+            "The DataEdge function `{name}` must take a single `bytes` argument"
+        );
+        self.data_edge_function = Some(name.clone());
+        Ok(self)
+    }
+
+    /// Batches protocol chain reads through the Multicall3 contract at `multicall_address`. See
+    /// [`Contracts::query_snapshot`].
+    pub fn with_multicall(mut self, multicall_address: Address) -> anyhow::Result<Self> {
+        let multicall =
+            Contracts::new_contract(MULTICALL_ABI, &self.client.eth(), multicall_address)?;
+        self.multicall = Some(multicall);
+        Ok(self)
+    }
+
+    /// Reports the estimated gas of payloads to `metrics`.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sends DataEdge transactions through `private_relay` first.
+    pub fn with_private_relay(mut self, private_relay: PrivateRelay) -> Self {
+        self.private_relay = Some(private_relay);
+        self
+    }
+
+    /// Refuses to submit payloads longer than `max_payload_bytes`, rather than letting their
+    /// transactions fail on chain.
+    pub fn with_max_payload_bytes(mut self, max_payload_bytes: usize) -> Self {
+        self.max_payload_bytes = Some(max_payload_bytes);
+        self
+    }
+
+    pub fn data_edge_address(&self) -> Address {
+        self.data_edge.address()
+    }
+
+    pub fn transaction_monitoring_options(&self) -> &TransactionMonitoringOptions {
+        &self.transaction_monitoring_options
+    }
+
     fn new_contract(abi: &[u8], eth: &Eth<T>, address: Address) -> anyhow::Result<Contract<T>> {
         Contract::from_json(eth.clone(), address, abi)
             .with_context(|| "Failed to create contract".to_string())
@@ -78,42 +247,1082 @@ where
             .await?;
         let current_epoch = epoch_number.as_u64();
         debug!("Epoch Manager is at epoch {current_epoch}");
-        METRICS.set_current_epoch("manager", current_epoch as i64);
         Ok(current_epoch)
     }
 
-    pub async fn submit_call(
+    /// The protocol chain block at which the Epoch Manager's next epoch starts, unless its epoch
+    /// length changes in the meantime.
+    pub async fn query_next_epoch_block(&self) -> Result<u64, web3::contract::Error> {
+        let query = |function| {
+            self.epoch_manager
+                .query::<U256, _, _, _>(function, (), None, Default::default(), None)
+        };
+        let (current_epoch_block, epoch_length) =
+            futures::future::try_join(query("currentEpochBlock"), query("epochLength")).await?;
+        let next_epoch_block = current_epoch_block.saturating_add(epoch_length).as_u64();
+        trace!(
+            next_epoch_block,
+            "Queried the Epoch Manager for the next epoch"
+        );
+        Ok(next_epoch_block)
+    }
+
+    /// Reads everything that the oracle needs from the protocol chain in a single `eth_call`, so
+    /// that all values refer to the same block. Returns `None` if no Multicall3 contract was
+    /// configured.
+    pub async fn query_snapshot(
+        &self,
+        owner_address: Address,
+    ) -> Result<Option<ProtocolChainSnapshot>, web3::contract::Error> {
+        let Some(multicall) = &self.multicall else {
+            return Ok(None);
+        };
+        trace!("Querying the protocol chain through Multicall3");
+        let get_block_number = multicall.abi().function("getBlockNumber")?;
+        let get_eth_balance = multicall.abi().function("getEthBalance")?;
+        let current_epoch = self
+            .epoch_manager
+            .abi()
+            .function(EPOCH_MANAGER_FUNCTION_NAME)?;
+        let calls = [
+            (multicall.address(), get_block_number.encode_input(&[])?),
+            (
+                multicall.address(),
+                get_eth_balance.encode_input(&[Token::Address(owner_address)])?,
+            ),
+            (
+                self.epoch_manager.address(),
+                current_epoch.encode_input(&[])?,
+            ),
+        ];
+
+        let aggregate3 = multicall.abi().function("aggregate3")?;
+        let calls = calls
+            .into_iter()
+            .map(|(target, calldata)| {
+                // If any call fails, the whole batch does.
+                Token::Tuple(vec![
+                    Token::Address(target),
+                    Token::Bool(false),
+                    Token::Bytes(calldata),
+                ])
+            })
+            .collect();
+        let call = CallRequest {
+            to: Some(multicall.address()),
+            data: Some(aggregate3.encode_input(&[Token::Array(calls)])?.into()),
+            ..Default::default()
+        };
+        let output = self.client.eth().call(call, None).await?;
+        let [block_number, owner_balance, current_epoch] = decode_aggregate3(
+            aggregate3,
+            &output.0,
+            [get_block_number, get_eth_balance, current_epoch],
+        )?;
+
+        let snapshot = ProtocolChainSnapshot {
+            block_number: block_number.as_u64(),
+            owner_balance,
+            current_epoch: current_epoch.as_u64(),
+        };
+        debug!(?snapshot, "Queried the protocol chain through Multicall3");
+        Ok(Some(snapshot))
+    }
+
+    /// The DataEdge call that submits `payload`, as the recipient and the calldata.
+    pub fn data_edge_call(&self, payload: &[u8]) -> Result<(Address, Bytes), ContractError> {
+        check_payload_size(payload, self.max_payload_bytes)?;
+        let calldata = self.encode_data_edge_payload(payload.to_vec())?;
+        Ok((self.data_edge.address(), calldata.into()))
+    }
+
+    /// Signs `calldata` for `to` with `signer` and sends it, unless it already landed after
+    /// `after_block`, see [`Contracts::prepare`]. The nonce stays reserved until the transaction
+    /// is confirmed, so submissions are serialized and concurrent calls never share a nonce.
+    pub async fn send<'a>(
+        &'a self,
+        to: Address,
+        calldata: Bytes,
+        after_block: Option<u64>,
+        signer: &'a dyn Signer,
+    ) -> Result<SentTransaction<'a>, ContractError> {
+        let owner_address = signer
+            .address()
+            .await
+            .map_err(TransactionMonitorError::Signing)?;
+        let gas_limit = match self
+            .prepare(owner_address, to, &calldata, after_block)
+            .await?
+        {
+            PreparedCall::Duplicate(submission) => {
+                return Ok(SentTransaction::already_landed(*submission))
+            }
+            PreparedCall::Ready { gas_limit } => gas_limit,
+        };
+
+        let nonce = self
+            .nonces
+            .reserve(&self.client.eth(), owner_address)
+            .await
+            .map_err(TransactionMonitorError::Nonce)?;
+        let transaction_monitor = TransactionMonitor::new(
+            self.client.clone(),
+            signer,
+            to,
+            calldata,
+            nonce.nonce(),
+            gas_limit,
+            self.transaction_monitoring_options,
+        )
+        .await?
+        .with_private_relay(self.private_relay.as_ref());
+        self.check_gas_price(transaction_monitor.gas_price().max_fee_per_gas())?;
+        let required = transaction_monitor
+            .max_cost()
+            .saturating_add(self.transaction_monitoring_options.balance_buffer.into());
+        self.check_balance(owner_address, required).await?;
+        let sent = transaction_monitor.send().await?;
+        let tx_hash = sent.hash();
+        Ok(SentTransaction::pending(
+            tx_hash,
+            nonce.nonce(),
+            async move {
+                let receipt = transaction_monitor.wait_for_confirmation(sent).await?;
+                let submission = Submission {
+                    receipt,
+                    nonce: nonce.nonce(),
+                };
+                nonce.consume();
+                Ok(submission)
+            },
+        ))
+    }
+
+    /// Checks everything that can be checked before `calldata` is sent from `from` to `to`, and
+    /// picks its gas limit. See [`PreparedCall`].
+    ///
+    /// Identical calls that landed after `after_block` are duplicates, e.g. one that was sent
+    /// right before the oracle crashed, or by an operator. This is usually the latest block that
+    /// the Epoch Subgraph indexed, as the payloads it has seen are accounted for already.
+    pub async fn prepare(
         &self,
-        payload: Vec<u8>,
-        owner_private_key: &SecretKey,
+        from: Address,
+        to: Address,
+        calldata: &Bytes,
+        after_block: Option<u64>,
+    ) -> Result<PreparedCall, ContractError> {
+        if let Some(submission) = self.find_duplicate(from, to, calldata, after_block).await? {
+            info!(
+                hash = ?submission.receipt.transaction_hash,
+                "An identical payload already landed. Skipping the submission."
+            );
+            return Ok(PreparedCall::Duplicate(Box::new(submission)));
+        }
+        self.simulate(from, to, calldata).await?;
+        let gas_limit = self.gas_limit(from, to, calldata).await?;
+        Ok(PreparedCall::Ready { gas_limit })
+    }
+
+    /// Waits until the transaction `hash` with `nonce` is confirmed.
+    pub async fn wait_for_receipt(
+        &self,
+        hash: H256,
+        nonce: U256,
     ) -> Result<TransactionReceipt, ContractError> {
-        info!("Sending transaction to DataEdge");
-
-        let transaction_receipt: TransactionReceipt = {
-            let calldata: web3::types::Bytes =
-                self.abi_encode_data_edge_payload((payload,))?.into();
-
-            let transaction_monitor = TransactionMonitor::new(
-                self.client.clone(),
-                SecretKeyRef::new(owner_private_key),
-                self.data_edge.address(),
-                calldata,
-                self.transaction_monitoring_options,
-            )
-            .await?;
-            transaction_monitor.execute_transaction().await?
+        let receipt =
+            ConfirmationTracker::new(self.client.eth(), &self.transaction_monitoring_options)
+                .wait(hash)
+                .await
+                .map_err(|error| TransactionMonitorError::from_confirmation(error, nonce))?;
+        Ok(receipt)
+    }
+
+    /// Looks for a successful transaction to `to` with `calldata` from `owner_address` among the
+    /// blocks after `after_block`, see [`duplicate_check_range`].
+    async fn find_duplicate(
+        &self,
+        owner_address: Address,
+        to: Address,
+        calldata: &Bytes,
+        after_block: Option<u64>,
+    ) -> Result<Option<Submission>, ContractError> {
+        let max_blocks = self.transaction_monitoring_options.duplicate_check_blocks;
+        if max_blocks == 0 {
+            return Ok(None);
+        }
+        let eth = self.client.eth();
+        let latest = eth
+            .block_number()
+            .await
+            .map_err(ContractError::DuplicateCheck)?
+            .as_u64();
+        let numbers = duplicate_check_range(latest, after_block, max_blocks);
+        if after_block.is_some_and(|after_block| after_block + 1 < *numbers.start()) {
+            warn!(
+                after_block,
+                latest,
+                max_blocks,
+                "The subgraph is too far behind to look for identical payloads in all blocks it \
+                 hasn't indexed. Only looking in the latest ones."
+            );
+        }
+        trace!(?numbers, "Looking for identical payloads in recent blocks");
+        let mut blocks = futures::stream::iter(numbers.rev())
+            .map(|number| eth.block_with_txs(BlockId::Number(number.into())))
+            .buffered(DUPLICATE_CHECK_CONCURRENCY);
+        while let Some(block) = blocks.next().await {
+            let Some(block) = block.map_err(ContractError::DuplicateCheck)? else {
+                continue;
+            };
+            let Some(transaction) =
+                find_transaction(&block.transactions, owner_address, to, calldata)
+            else {
+                continue;
+            };
+            let receipt = eth
+                .transaction_receipt(transaction.hash)
+                .await
+                .map_err(ContractError::DuplicateCheck)?;
+            // Reverted transactions don't count.
+            if let Some(receipt) = receipt.filter(|receipt| receipt.status == Some(1.into())) {
+                return Ok(Some(Submission {
+                    receipt,
+                    nonce: transaction.nonce,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Deals with the DataEdge transactions that the owner sent but that aren't mined yet, e.g.
+    /// because the oracle restarted while waiting for them. Otherwise, the next submission would
+    /// queue behind them. Depending on the [`PendingTransactionPolicy`], they're either waited for
+    /// and only cancelled if they aren't confirmed in time, or cancelled right away.
+    ///
+    /// Pending transactions are listed with `txpool_content`. If the provider doesn't support it,
+    /// every nonce between the mined and the pending transaction count is cancelled instead, as
+    /// there's no telling which transactions hold them.
+    pub async fn resolve_pending_transactions(
+        &self,
+        signer: &dyn Signer,
+        recipient: Address,
+    ) -> Result<(), ContractError> {
+        let owner_address = signer
+            .address()
+            .await
+            .map_err(TransactionMonitorError::Signing)?;
+        let eth = self.client.eth();
+        let (mined, pending) = futures::future::try_join(
+            eth.transaction_count(owner_address, Some(BlockNumber::Latest)),
+            eth.transaction_count(owner_address, Some(BlockNumber::Pending)),
+        )
+        .await
+        .map_err(TransactionMonitorError::Nonce)?;
+        if pending <= mined {
+            return Ok(());
+        }
+        let content = match self.client.txpool().content().await {
+            Ok(content) => content,
+            Err(error) => {
+                warn!(
+                    %error,
+                    count = %(pending - mined),
+                    "The owner has pending transactions, but the provider can't list them. \
+                     Cancelling all of them."
+                );
+                let mut nonce = mined;
+                while nonce < pending {
+                    self.cancel(signer, owner_address, nonce).await?;
+                    nonce += U256::one();
+                }
+                return Ok(());
+            }
         };
 
-        Ok(transaction_receipt)
+        let options = &self.transaction_monitoring_options;
+        for transaction in pending_transactions(&content, owner_address, recipient) {
+            let (hash, nonce) = (transaction.hash, transaction.nonce);
+            warn!(
+                ?hash,
+                %nonce,
+                policy = ?options.pending_transactions,
+                "Found a pending DataEdge transaction"
+            );
+            if options.pending_transactions == PendingTransactionPolicy::Adopt {
+                match ConfirmationTracker::new(eth.clone(), options)
+                    .wait(hash)
+                    .await
+                {
+                    Ok(receipt) => {
+                        info!(?hash, block_number = ?receipt.block_number, "The pending DataEdge transaction was confirmed");
+                        continue;
+                    }
+                    Err(error @ ConfirmationError::Reverted { .. }) => {
+                        warn!(%error, "The pending DataEdge transaction was reverted");
+                        continue;
+                    }
+                    Err(ConfirmationError::Timeout(_)) => {
+                        warn!(
+                            ?hash,
+                            "The pending DataEdge transaction wasn't confirmed in time"
+                        );
+                    }
+                    Err(error) => {
+                        return Err(TransactionMonitorError::from_confirmation(error, nonce).into())
+                    }
+                }
+            }
+            self.cancel(signer, owner_address, nonce).await?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the transaction that holds `nonce` with a zero-value transfer to the owner itself.
+    async fn cancel(
+        &self,
+        signer: &dyn Signer,
+        owner_address: Address,
+        nonce: U256,
+    ) -> Result<(), ContractError> {
+        info!(%nonce, "Cancelling the pending transaction");
+        let transaction_monitor = TransactionMonitor::cancellation(
+            self.client.clone(),
+            signer,
+            owner_address,
+            nonce,
+            self.transaction_monitoring_options,
+        )
+        .await?
+        .with_private_relay(self.private_relay.as_ref());
+        match transaction_monitor.execute_transaction().await {
+            Ok(receipt) => {
+                info!(hash = ?receipt.transaction_hash, %nonce, "The nonce of the pending transaction is free again")
+            }
+            // The nonce is free either way.
+            Err(error) if error.is_nonce_too_low() => {
+                info!(%nonce, "The pending transaction was mined in the meantime")
+            }
+            Err(
+                TransactionMonitorError::Cancelled { .. }
+                | TransactionMonitorError::Replaced { .. },
+            ) => {}
+            Err(error) => return Err(error.into()),
+        }
+        Ok(())
+    }
+
+    /// Runs the same fee checks that [`Contracts::send`] runs, for transactions that `from` sends
+    /// through someone else, e.g. a Defender relayer: the current gas price must be within
+    /// `max_gas_price_gwei`, and `from` must afford `gas_limit` at it plus the `balance_buffer`.
+    pub async fn check_fees(&self, from: Address, gas_limit: U256) -> Result<(), ContractError> {
+        let gas_price = GasEstimator::new(self.client.eth(), &self.transaction_monitoring_options)
+            .estimate()
+            .await
+            .map_err(TransactionMonitorError::Startup)?;
+        let max_fee_per_gas = gas_price.max_fee_per_gas();
+        self.check_gas_price(max_fee_per_gas)?;
+        let required = gas_limit
+            .saturating_mul(max_fee_per_gas)
+            .saturating_add(self.transaction_monitoring_options.balance_buffer.into());
+        self.check_balance(from, required).await
+    }
+
+    /// Fails with [`ContractError::GasPriceTooHigh`] if `gas_price` is above the configured
+    /// `max_gas_price_gwei`.
+    pub fn check_gas_price(&self, gas_price: U256) -> Result<(), ContractError> {
+        let result = check_gas_price(
+            gas_price,
+            self.transaction_monitoring_options.max_gas_price_gwei,
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.set_gas_price_too_high(result.is_err());
+        }
+        result
+    }
+
+    /// Fails with [`ContractError::InsufficientFunds`] unless `owner_address` holds at least
+    /// `required` wei.
+    pub async fn check_balance(
+        &self,
+        owner_address: Address,
+        required: U256,
+    ) -> Result<(), ContractError> {
+        let balance = self
+            .client
+            .eth()
+            .balance(owner_address, None)
+            .await
+            .map_err(ContractError::Balance)?;
+        let result = check_funds(balance, required);
+        if let Some(metrics) = &self.metrics {
+            metrics.set_insufficient_funds(result.is_err());
+        }
+        result
+    }
+
+    /// Runs the DataEdge call with `eth_call` against the latest block, so that wrong addresses or
+    /// ABIs, paused contracts and the like are caught before any gas is spent.
+    async fn simulate(
+        &self,
+        from: Address,
+        address: Address,
+        calldata: &Bytes,
+    ) -> Result<(), ContractError> {
+        let eth = self.client.eth();
+        // Calls to accounts without code always succeed.
+        let code = eth
+            .code(address, None)
+            .await
+            .map_err(ContractError::Simulation)?;
+        if code.0.is_empty() {
+            return Err(ContractError::NoDataEdgeContract(address));
+        }
+
+        let call = CallRequest {
+            from: Some(from),
+            to: Some(address),
+            data: Some(calldata.clone()),
+            ..Default::default()
+        };
+        match eth.call(call, None).await {
+            Ok(_) => {
+                debug!("Simulated the DataEdge call successfully");
+                Ok(())
+            }
+            Err(web3::Error::Rpc(error)) if is_revert(&error) => {
+                Err(ContractError::SimulationReverted {
+                    reason: revert_reason(&error),
+                })
+            }
+            Err(error) => Err(ContractError::Simulation(error)),
+        }
     }
 
-    fn abi_encode_data_edge_payload(
+    /// Estimates the gas of the DataEdge call and derives the transaction's gas limit from it.
+    ///
+    /// Refuses calldata whose transaction could never be mined, as it would need (almost) more gas
+    /// than a whole block of the protocol chain, or more than `max_gas_limit`. If the gas can't be
+    /// estimated, the transaction is sent anyway with the configured `gas_limit`.
+    async fn gas_limit(
         &self,
-        params: impl Tokenize,
-    ) -> Result<Vec<u8>, web3::ethabi::Error> {
+        from: Address,
+        to: Address,
+        calldata: &Bytes,
+    ) -> Result<U256, ContractError> {
+        let options = &self.transaction_monitoring_options;
+        let eth = self.client.eth();
+        let call = CallRequest {
+            from: Some(from),
+            to: Some(to),
+            data: Some(calldata.clone()),
+            ..Default::default()
+        };
+        let (estimated_gas, latest_block) = match futures::future::try_join(
+            eth.estimate_gas(call, None),
+            eth.block(BlockId::Number(BlockNumber::Latest)),
+        )
+        .await
+        {
+            Ok(estimate) => estimate,
+            Err(error) => {
+                warn!(%error, "Failed to estimate gas. Can't check the payload size.");
+                return Ok(options.gas_limit.into());
+            }
+        };
+        if let Some(metrics) = &self.metrics {
+            metrics.set_payload_estimated_gas(i64::try_from(estimated_gas).unwrap_or(i64::MAX));
+        }
+        match latest_block {
+            Some(latest_block) => {
+                debug!(
+                    %estimated_gas,
+                    block_gas_limit = %latest_block.gas_limit,
+                    "Estimated gas for the payload"
+                );
+                check_block_gas_limit(
+                    estimated_gas,
+                    latest_block.gas_limit,
+                    options.block_gas_limit_margin,
+                )?;
+            }
+            None => {
+                warn!("The latest block of the protocol chain is missing. Can't check the payload size.");
+            }
+        }
+        gas_limit_from_estimate(
+            estimated_gas,
+            options.gas_limit_percentage,
+            options.max_gas_limit.map(Into::into),
+        )
+    }
+
+    fn encode_data_edge_payload(&self, payload: Vec<u8>) -> Result<Vec<u8>, web3::ethabi::Error> {
+        let Some(function) = &self.data_edge_function else {
+            return Ok(payload);
+        };
         self.data_edge
             .abi()
-            .function(DATA_EDGE_FUNCTION_NAME)
-            .and_then(|function| function.encode_input(&params.into_tokens()))
+            .function(function)
+            .and_then(|function| function.encode_input(&[Token::Bytes(payload)]))
+    }
+}
+
+/// What [`Contracts::prepare`] found out about a call.
+#[derive(Debug)]
+pub enum PreparedCall {
+    /// An identical call was sent recently, and this is its transaction.
+    Duplicate(Box<Submission>),
+    /// The call may be sent with `gas_limit`.
+    Ready { gas_limit: U256 },
+}
+
+/// The blocks to look for duplicate payloads in, i.e. those after `after_block` up to `latest`,
+/// but at most the latest `max_blocks`.
+fn duplicate_check_range(
+    latest: u64,
+    after_block: Option<u64>,
+    max_blocks: u64,
+) -> RangeInclusive<u64> {
+    let oldest = latest.saturating_sub(max_blocks.saturating_sub(1));
+    let first = match after_block {
+        Some(after_block) => (after_block + 1).max(oldest),
+        None => oldest,
+    };
+    first..=latest
+}
+
+/// The transaction from `from` to `to` with `calldata`, if any.
+fn find_transaction<'a>(
+    transactions: &'a [Transaction],
+    from: Address,
+    to: Address,
+    calldata: &Bytes,
+) -> Option<&'a Transaction> {
+    transactions.iter().find(|transaction| {
+        transaction.from == Some(from)
+            && transaction.to == Some(to)
+            && &transaction.input == calldata
+    })
+}
+
+/// The pending transactions from `owner` to `to` in `content`, by nonce.
+fn pending_transactions(
+    content: &TxpoolContentInfo,
+    owner: Address,
+    to: Address,
+) -> Vec<&Transaction> {
+    let mut transactions: Vec<_> = content
+        .pending
+        .get(&owner)
+        .into_iter()
+        .flat_map(|transactions| transactions.values())
+        .filter(|transaction| transaction.to == Some(to))
+        .collect();
+    // The keys are decimal strings, which don't sort numerically.
+    transactions.sort_by_key(|transaction| transaction.nonce);
+    transactions
+}
+
+/// Values read from the protocol chain at the same block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolChainSnapshot {
+    pub block_number: u64,
+    pub owner_balance: U256,
+    pub current_epoch: u64,
+}
+
+/// Decodes the output of `aggregate3`, where the result of each call is a single `uint256`.
+fn decode_aggregate3<const N: usize>(
+    aggregate3: &Function,
+    output: &[u8],
+    functions: [&Function; N],
+) -> Result<[U256; N], web3::contract::Error> {
+    let invalid = |reason: &str| web3::contract::Error::InvalidOutputType(reason.to_string());
+    let results = match aggregate3.decode_output(output)?.pop() {
+        Some(Token::Array(results)) if results.len() == N => results,
+        _ => return Err(invalid("unexpected number of Multicall3 results")),
+    };
+
+    let mut values = [U256::zero(); N];
+    for ((value, result), function) in values.iter_mut().zip(results).zip(functions) {
+        let Token::Tuple(result) = result else {
+            return Err(invalid("Multicall3 results must be tuples"));
+        };
+        let return_data = match result.as_slice() {
+            [Token::Bool(true), Token::Bytes(return_data)] => return_data,
+            _ => return Err(invalid("a call within Multicall3 failed")),
+        };
+        *value = match function.decode_output(return_data)?.as_slice() {
+            [Token::Uint(uint)] => *uint,
+            _ => return Err(invalid("expected a single uint256")),
+        };
+    }
+    Ok(values)
+}
+
+/// Whether an `eth_call` failed because the call reverted, rather than because of the provider.
+/// Geth and most others use error code 3 if there's revert data.
+fn is_revert(error: &jsonrpc_core::Error) -> bool {
+    error.code == jsonrpc_core::ErrorCode::ServerError(3)
+        || error.message.to_lowercase().contains("revert")
+}
+
+/// Decodes `Error(string)` revert data, falling back to the error message.
+fn revert_reason(error: &jsonrpc_core::Error) -> String {
+    const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+    let reason = error
+        .data
+        .as_ref()
+        .and_then(|data| data.as_str())
+        .and_then(|data| hex::decode(data.trim_start_matches("0x")).ok())
+        .and_then(|data| {
+            let data = data.strip_prefix(&ERROR_SELECTOR)?;
+            match web3::ethabi::decode(&[web3::ethabi::ParamType::String], data).ok()?[..] {
+                [Token::String(ref reason)] => Some(reason.clone()),
+                _ => None,
+            }
+        });
+    reason.unwrap_or_else(|| error.message.clone())
+}
+
+/// The gas limit is `percentage` of `estimated_gas`, but at most `max_gas_limit`.
+fn gas_limit_from_estimate(
+    estimated_gas: U256,
+    percentage: u32,
+    max_gas_limit: Option<U256>,
+) -> Result<U256, ContractError> {
+    let gas_limit = estimated_gas.saturating_mul(percentage.into()) / 100;
+    let Some(max_gas_limit) = max_gas_limit else {
+        return Ok(gas_limit);
+    };
+    if estimated_gas > max_gas_limit {
+        return Err(ContractError::ExceedsMaxGasLimit {
+            estimated_gas,
+            max_gas_limit,
+        });
+    }
+    Ok(gas_limit.min(max_gas_limit))
+}
+
+pub(crate) fn check_payload_size(
+    payload: &[u8],
+    max_bytes: Option<usize>,
+) -> Result<(), ContractError> {
+    match max_bytes {
+        Some(max_bytes) if payload.len() > max_bytes => Err(ContractError::PayloadTooLarge {
+            size: payload.len(),
+            max_bytes,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn check_gas_price(gas_price: U256, max_gas_price_gwei: Option<u64>) -> Result<(), ContractError> {
+    let Some(max_gas_price_gwei) = max_gas_price_gwei else {
+        return Ok(());
+    };
+    let max_gas_price = U256::from(max_gas_price_gwei).saturating_mul(GWEI.into());
+    if gas_price > max_gas_price {
+        return Err(ContractError::GasPriceTooHigh {
+            gas_price,
+            max_gas_price,
+        });
+    }
+    Ok(())
+}
+
+fn check_funds(balance: U256, required: U256) -> Result<(), ContractError> {
+    if balance < required {
+        return Err(ContractError::InsufficientFunds { balance, required });
+    }
+    Ok(())
+}
+
+/// `margin` is the percentage of `block_gas_limit` that must be left unused.
+fn check_block_gas_limit(
+    estimated_gas: U256,
+    block_gas_limit: U256,
+    margin: u32,
+) -> Result<(), ContractError> {
+    let usable_gas = block_gas_limit / 100 * (100 - margin.min(100));
+    if estimated_gas > usable_gas {
+        return Err(ContractError::ExceedsBlockGasLimit {
+            estimated_gas,
+            usable_gas,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GasOptions, TransactionType};
+    use crate::signer::LocalSigner;
+    use crate::test_utils::{jrpc_provider, serve};
+    use secp256k1::SecretKey;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use web3::transports::Http;
+
+    #[test]
+    fn data_edge_calls() {
+        let client = Web3::new(Http::new("http://127.0.0.1:1").unwrap());
+        let contracts = || {
+            Contracts::new(
+                client.clone(),
+                Address::zero(),
+                Address::zero(),
+                Default::default(),
+            )
+            .unwrap()
+        };
+        let payload = vec![0xab; 3];
+        let selector = |calldata: &[u8]| calldata[..4].to_vec();
+
+        let bundled = contracts();
+        let calldata = bundled.encode_data_edge_payload(payload.clone()).unwrap();
+        assert_eq!(
+            selector(&calldata),
+            function_selector(b"crossChainEpochOracle(bytes)")
+        );
+
+        let abi_path = Some(
+            format!(
+                "{}/test/config/data_edge_abi.json",
+                env!("CARGO_MANIFEST_DIR")
+            )
+            .into(),
+        );
+        let custom = contracts()
+            .with_data_edge_call(&DataEdgeCall::Function {
+                name: "submitPayload".to_string(),
+                abi_path: abi_path.clone(),
+            })
+            .unwrap();
+        let calldata = custom.encode_data_edge_payload(payload.clone()).unwrap();
+        assert_eq!(
+            selector(&calldata),
+            function_selector(b"submitPayload(bytes)")
+        );
+
+        let fallback = contracts()
+            .with_data_edge_call(&DataEdgeCall::Fallback)
+            .unwrap();
+        assert_eq!(
+            fallback.encode_data_edge_payload(payload.clone()).unwrap(),
+            payload
+        );
+
+        for name in ["setEpoch", "crossChainEpochOracle"] {
+            assert!(contracts()
+                .with_data_edge_call(&DataEdgeCall::Function {
+                    name: name.to_string(),
+                    abi_path: abi_path.clone(),
+                })
+                .is_err());
+        }
+    }
+
+    fn function_selector(signature: &[u8]) -> Vec<u8> {
+        web3::signing::keccak256(signature)[..4].to_vec()
+    }
+
+    #[test]
+    fn block_gas_limit_margin() {
+        let block_gas_limit = U256::from(30_000_000);
+        assert!(check_block_gas_limit(27_000_000.into(), block_gas_limit, 10).is_ok());
+        assert!(matches!(
+            check_block_gas_limit(27_000_001.into(), block_gas_limit, 10),
+            Err(ContractError::ExceedsBlockGasLimit { usable_gas, .. }) if usable_gas == 27_000_000.into()
+        ));
+        assert!(check_block_gas_limit(30_000_000.into(), block_gas_limit, 0).is_ok());
+    }
+
+    #[test]
+    fn payload_size() {
+        assert!(check_payload_size(&[0; 100], None).is_ok());
+        assert!(check_payload_size(&[0; 100], Some(100)).is_ok());
+        assert!(matches!(
+            check_payload_size(&[0; 101], Some(100)),
+            Err(ContractError::PayloadTooLarge {
+                size: 101,
+                max_bytes: 100
+            })
+        ));
+    }
+
+    #[test]
+    fn gas_price_ceiling() {
+        assert!(check_gas_price(U256::MAX, None).is_ok());
+        assert!(check_gas_price(50_000_000_000u64.into(), Some(50)).is_ok());
+        assert!(matches!(
+            check_gas_price(50_000_000_001u64.into(), Some(50)),
+            Err(ContractError::GasPriceTooHigh { max_gas_price, .. })
+                if max_gas_price == 50_000_000_000u64.into()
+        ));
+    }
+
+    #[test]
+    fn funds() {
+        assert!(check_funds(1_000.into(), 1_000.into()).is_ok());
+        assert!(matches!(
+            check_funds(999.into(), 1_000.into()),
+            Err(ContractError::InsufficientFunds { balance, required })
+                if balance == 999.into() && required == 1_000.into()
+        ));
+    }
+
+    #[test]
+    fn gas_limit_margin_and_cap() {
+        assert_eq!(
+            gas_limit_from_estimate(100_000.into(), 120, None).unwrap(),
+            120_000.into()
+        );
+        assert_eq!(
+            gas_limit_from_estimate(100_000.into(), 120, Some(110_000.into())).unwrap(),
+            110_000.into()
+        );
+        assert!(matches!(
+            gas_limit_from_estimate(100_001.into(), 120, Some(100_000.into())),
+            Err(ContractError::ExceedsMaxGasLimit { .. })
+        ));
+    }
+
+    #[test]
+    fn revert_reasons() {
+        let revert_data = |reason: &str| {
+            let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+            data.extend(web3::ethabi::encode(&[Token::String(reason.to_string())]));
+            serde_json::Value::String(format!("0x{}", hex::encode(data)))
+        };
+        let error = jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(3),
+            message: "execution reverted: Pausable: paused".to_string(),
+            data: Some(revert_data("Pausable: paused")),
+        };
+        assert!(is_revert(&error));
+        assert_eq!(revert_reason(&error), "Pausable: paused");
+
+        let error = jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(-32000),
+            message: "execution reverted".to_string(),
+            data: None,
+        };
+        assert!(is_revert(&error));
+        assert_eq!(revert_reason(&error), "execution reverted");
+
+        let error = jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::InternalError,
+            message: "header not found".to_string(),
+            data: None,
+        };
+        assert!(!is_revert(&error));
+    }
+
+    #[test]
+    fn pending_data_edge_transactions() {
+        let owner = Address::repeat_byte(0x22);
+        let data_edge = Address::repeat_byte(0x11);
+        let transaction = |nonce: u64, to: Address| {
+            serde_json::json!({
+                "hash": H256::from_low_u64_be(nonce),
+                "nonce": U256::from(nonce),
+                "blockHash": null,
+                "blockNumber": null,
+                "transactionIndex": null,
+                "from": owner,
+                "to": to,
+                "value": "0x0",
+                "gasPrice": "0x1",
+                "gas": "0x5208",
+                "input": "0x",
+            })
+        };
+        let content: TxpoolContentInfo = serde_json::from_value(serde_json::json!({
+            "pending": {
+                format!("{owner:?}"): {
+                    "10": transaction(10, data_edge),
+                    "9": transaction(9, data_edge),
+                    "11": transaction(11, Address::repeat_byte(0x33)),
+                },
+                format!("{:?}", Address::repeat_byte(0x44)): {
+                    "3": transaction(3, data_edge),
+                },
+            },
+            "queued": {},
+        }))
+        .unwrap();
+        let nonces: Vec<_> = pending_transactions(&content, owner, data_edge)
+            .iter()
+            .map(|transaction| transaction.nonce.as_u64())
+            .collect();
+        assert_eq!(nonces, [9, 10]);
+        assert!(pending_transactions(&content, data_edge, data_edge).is_empty());
+    }
+
+    #[test]
+    fn duplicates() {
+        let (owner, data_edge) = (Address::repeat_byte(0x22), Address::repeat_byte(0x11));
+        let calldata = Bytes(vec![1, 2, 3]);
+        let transaction = |from, to, input: &[u8]| Transaction {
+            from: Some(from),
+            to: Some(to),
+            input: Bytes(input.to_vec()),
+            ..Default::default()
+        };
+        let transactions = [
+            transaction(owner, data_edge, &[1, 2]),
+            transaction(Address::repeat_byte(0x33), data_edge, &[1, 2, 3]),
+            transaction(owner, Address::repeat_byte(0x33), &[1, 2, 3]),
+        ];
+        assert!(find_transaction(&transactions, owner, data_edge, &calldata).is_none());
+
+        let mut transactions = transactions.to_vec();
+        transactions.push(transaction(owner, data_edge, &[1, 2, 3]));
+        assert_eq!(
+            find_transaction(&transactions, owner, data_edge, &calldata),
+            transactions.last()
+        );
+    }
+
+    #[test]
+    fn duplicate_check_ranges() {
+        // Only the blocks that the subgraph hasn't indexed yet.
+        assert_eq!(duplicate_check_range(1_000, Some(990), 10_000), 991..=1_000);
+        // The subgraph is at the latest block.
+        assert!(duplicate_check_range(1_000, Some(1_000), 10_000).is_empty());
+        // At most `max_blocks`.
+        assert_eq!(duplicate_check_range(1_000, Some(0), 100), 901..=1_000);
+        assert_eq!(duplicate_check_range(1_000, None, 100), 901..=1_000);
+        assert_eq!(duplicate_check_range(50, None, 100), 0..=50);
+    }
+
+    #[test]
+    fn aggregate3_output() {
+        let abi = web3::ethabi::Contract::load(MULTICALL_ABI).unwrap();
+        let aggregate3 = abi.function("aggregate3").unwrap();
+        let get_block_number = abi.function("getBlockNumber").unwrap();
+        let result = |success, value: u64| {
+            Token::Tuple(vec![
+                Token::Bool(success),
+                Token::Bytes(web3::ethabi::encode(&[Token::Uint(value.into())])),
+            ])
+        };
+
+        let output = web3::ethabi::encode(&[Token::Array(vec![result(true, 1), result(true, 2)])]);
+        let values =
+            decode_aggregate3(aggregate3, &output, [get_block_number, get_block_number]).unwrap();
+        assert_eq!(values, [U256::from(1), U256::from(2)]);
+
+        let output = web3::ethabi::encode(&[Token::Array(vec![result(true, 1), result(false, 2)])]);
+        assert!(
+            decode_aggregate3(aggregate3, &output, [get_block_number, get_block_number]).is_err()
+        );
+        assert!(decode_aggregate3(aggregate3, &output, [get_block_number]).is_err());
+    }
+
+    #[tokio::test]
+    async fn pending_transactions_are_cancelled_without_txpool() {
+        let sent_nonces = Arc::new(Mutex::new(vec![]));
+        let url = {
+            let sent_nonces = sent_nonces.clone();
+            serve(move |request| {
+                let params = &request["params"];
+                let result = match request["method"].as_str().unwrap() {
+                    "eth_getTransactionCount" if params[1] == "latest" => json!("0x5"),
+                    "eth_getTransactionCount" => json!("0x7"),
+                    "txpool_content" => {
+                        return Some(json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "error": { "code": -32601, "message": "method not found" },
+                        }))
+                    }
+                    "eth_chainId" => json!("0x1"),
+                    "eth_feeHistory" => json!({
+                        "oldestBlock": "0x1",
+                        "baseFeePerGas": ["0x0"],
+                        "gasUsedRatio": [],
+                    }),
+                    "eth_gasPrice" => json!("0x3b9aca00"),
+                    "eth_sendRawTransaction" => {
+                        let raw = hex::decode(params[0].as_str().unwrap().trim_start_matches("0x"))
+                            .unwrap();
+                        let transaction = rlp::Rlp::new(&raw);
+                        sent_nonces
+                            .lock()
+                            .unwrap()
+                            .push(transaction.val_at::<U256>(0).unwrap());
+                        json!(H256::from(web3::signing::keccak256(&raw)))
+                    }
+                    "eth_getTransactionReceipt" => json!({
+                        "transactionHash": params[0],
+                        "transactionIndex": "0x0",
+                        "blockNumber": "0x10",
+                        "from": Address::zero(),
+                        "cumulativeGasUsed": "0x5208",
+                        "gasUsed": "0x5208",
+                        "logs": [],
+                        "logsBloom": format!("0x{}", "0".repeat(512)),
+                        "status": "0x1",
+                    }),
+                    "eth_blockNumber" => json!("0x20"),
+                    method => panic!("unexpected method {method}"),
+                };
+                Some(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+            })
+            .await
+        };
+        let client = Web3::new(Http::new(url.as_str()).unwrap());
+        let contracts =
+            Contracts::new(client, Address::zero(), Address::zero(), Default::default()).unwrap();
+        let signer = LocalSigner::new(SecretKey::from_slice(&[0x11; 32]).unwrap());
+
+        contracts
+            .resolve_pending_transactions(&signer, Address::zero())
+            .await
+            .unwrap();
+        assert_eq!(*sent_nonces.lock().unwrap(), [U256::from(5), U256::from(6)]);
+    }
+
+    #[tokio::test]
+    async fn fees_of_relayed_transactions() {
+        let url = jrpc_provider(|method, _| match method {
+            "eth_gasPrice" => json!("0x174876e800"),
+            "eth_getBalance" => json!("0x3b9aca00"),
+            method => panic!("unexpected method {method}"),
+        })
+        .await;
+        let contracts = |max_gas_price_gwei| {
+            let options = TransactionMonitoringOptions {
+                max_gas_price_gwei,
+                balance_buffer: 1,
+                gas: GasOptions {
+                    transaction_type: TransactionType::Legacy,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let client = Web3::new(Http::new(url.as_str()).unwrap());
+            Contracts::new(client, Address::zero(), Address::zero(), options).unwrap()
+        };
+
+        assert!(matches!(
+            contracts(Some(50))
+                .check_fees(Address::zero(), 1.into())
+                .await,
+            Err(ContractError::GasPriceTooHigh { .. })
+        ));
+        // 10 gas at 100 gwei, plus the buffer.
+        assert!(matches!(
+            contracts(Some(100)).check_fees(Address::zero(), 10.into()).await,
+            Err(ContractError::InsufficientFunds { required, .. })
+                if required == 1_000_000_000_001u64.into()
+        ));
+        assert!(contracts(None)
+            .check_fees(Address::zero(), 0.into())
+            .await
+            .is_ok());
     }
 }
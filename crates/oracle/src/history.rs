@@ -0,0 +1,755 @@
+//! A local, persistent record of the blocks and payloads that the oracle published.
+//!
+//! The Epoch Subgraph is the source of truth for the rest of the protocol, but it's also the
+//! product of decoding our own messages (and of anyone else's messages, if the DataEdge contract
+//! ever accepts them). Keeping track of what we actually sent allows us to notice when the
+//! subgraph disagrees with us.
+//!
+//! The history is stored as a JSON Lines file, with one [`PublishedBlock`] or [`PublishedPayload`]
+//! per line. It can be exported for reporting purposes with [`History::export`].
+
+use crate::metrics::Metrics;
+use crate::status::CorrelationId;
+use crate::subgraph::SubgraphState;
+use crate::Caip2ChainId;
+use epoch_encoding::BlockPtr;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+use tracing::{debug, error};
+use web3::types::{H256, U256};
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Bad entry at line {line} of the history file: {error}")]
+    BadEntry {
+        line: usize,
+        error: serde_json::Error,
+    },
+}
+
+/// A block that the oracle published for an indexed chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishedBlock {
+    pub epoch: u64,
+    pub chain_id: Caip2ChainId,
+    pub number: u64,
+    pub hash: H256,
+    /// The polling iteration that published the block. Missing from older entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+/// How the transaction of a [`PublishedPayload`] ended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    /// Older entries only recorded confirmed transactions.
+    #[default]
+    Confirmed,
+    Reverted,
+    /// Never mined, and the oracle stopped waiting for it.
+    Dropped,
+    /// Never mined, because another transaction used its nonce.
+    Replaced,
+    /// Never mined, because it was cancelled after `deadline_in_seconds`.
+    Cancelled,
+}
+
+impl SubmissionStatus {
+    /// Whether the transaction made it into a block, and so spent gas.
+    pub fn is_mined(&self) -> bool {
+        matches!(
+            self,
+            SubmissionStatus::Confirmed | SubmissionStatus::Reverted
+        )
+    }
+}
+
+impl Display for SubmissionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SubmissionStatus::Confirmed => "confirmed",
+            SubmissionStatus::Reverted => "reverted",
+            SubmissionStatus::Dropped => "dropped",
+            SubmissionStatus::Replaced => "replaced",
+            SubmissionStatus::Cancelled => "cancelled",
+        })
+    }
+}
+
+/// A payload that the oracle submitted to the DataEdge contract.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishedPayload {
+    pub epoch: u64,
+    /// When the transaction was confirmed, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub correlation_id: String,
+    /// The names of the encoded messages, e.g. `SetBlockNumbersForNextEpoch`.
+    pub messages: Vec<String>,
+    /// The hex-encoded payload.
+    pub payload: String,
+    pub tx_hash: H256,
+    #[serde(default)]
+    pub status: SubmissionStatus,
+    /// Missing from older entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<U256>,
+    /// Missing from older entries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    pub gas_used: Option<U256>,
+    pub effective_gas_price: Option<U256>,
+}
+
+/// A line of the history file.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Payload(PublishedPayload),
+    Block(PublishedBlock),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown export format '{s}', expected 'csv' or 'json'"
+            )),
+        }
+    }
+}
+
+/// A difference between what the oracle published and what the Epoch Subgraph recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discrepancy {
+    /// The subgraph recorded a different block number than the one we published.
+    Mismatch {
+        published: PublishedBlock,
+        recorded_number: u64,
+    },
+    /// The subgraph recorded an epoch for which we didn't publish anything.
+    Unknown {
+        epoch: u64,
+        chain_id: Caip2ChainId,
+        recorded_number: u64,
+    },
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Discrepancy::Mismatch {
+                published,
+                recorded_number,
+            } => write!(
+                f,
+                "the oracle published block #{} for chain '{}' at epoch {}, but the subgraph recorded block #{}",
+                published.number, published.chain_id, published.epoch, recorded_number
+            ),
+            Discrepancy::Unknown {
+                epoch,
+                chain_id,
+                recorded_number,
+            } => write!(
+                f,
+                "the subgraph recorded block #{recorded_number} for chain '{chain_id}' at epoch {epoch}, but the oracle didn't publish anything for that epoch"
+            ),
+        }
+    }
+}
+
+/// The Epoch Subgraph has forgotten about epochs that the oracle already published, which
+/// usually means that it was redeployed from scratch or wiped.
+///
+/// Publishing on top of such a subgraph would re-register all networks and encode deltas against
+/// a zeroed state, so the oracle refuses to do so until an operator either waits for the subgraph
+/// to resync or resets the history (see [`History::archive`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discontinuity {
+    /// The latest epoch in the history.
+    pub published_epoch: u64,
+    /// The latest epoch in the subgraph, if any.
+    pub subgraph_epoch: Option<u64>,
+}
+
+impl Display for Discontinuity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.subgraph_epoch {
+            Some(subgraph_epoch) => write!(
+                f,
+                "the oracle published blocks up to epoch {}, but the Epoch Subgraph is at epoch {subgraph_epoch}",
+                self.published_epoch
+            ),
+            None => write!(
+                f,
+                "the oracle published blocks up to epoch {}, but the Epoch Subgraph has no epochs at all",
+                self.published_epoch
+            ),
+        }
+    }
+}
+
+/// The per-chain history of published blocks and payloads, optionally backed by a file.
+#[derive(Debug, Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    entries: BTreeMap<u64, BTreeMap<Caip2ChainId, PublishedBlock>>,
+    payloads: Vec<PublishedPayload>,
+}
+
+impl History {
+    /// A history that is lost when the process exits.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Loads the history from `path`, which is created on the first write if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, HistoryError> {
+        let path = path.as_ref().to_path_buf();
+        let mut history = Self {
+            path: Some(path.clone()),
+            ..Default::default()
+        };
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e.into()),
+        };
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line)
+                .map_err(|error| HistoryError::BadEntry { line: i + 1, error })?;
+            match entry {
+                Entry::Payload(payload) => history.payloads.push(payload),
+                Entry::Block(block) => history.insert(block),
+            }
+        }
+        debug!(
+            path = %path.display(),
+            epochs = history.entries.len(),
+            "Loaded the history of published blocks."
+        );
+        Ok(history)
+    }
+
+    /// Records the `blocks` that were published at `epoch`.
+    pub fn record(
+        &mut self,
+        epoch: u64,
+        blocks: &BTreeMap<Caip2ChainId, BlockPtr>,
+        correlation_id: &CorrelationId,
+    ) -> Result<(), HistoryError> {
+        let published: Vec<PublishedBlock> = blocks
+            .iter()
+            .map(|(chain_id, block)| PublishedBlock {
+                epoch,
+                chain_id: chain_id.clone(),
+                number: block.number,
+                hash: H256(block.hash),
+                correlation_id: Some(correlation_id.to_string()),
+            })
+            .collect();
+
+        self.append(published.iter().cloned().map(Entry::Block))?;
+        for block in published {
+            self.insert(block);
+        }
+        Ok(())
+    }
+
+    /// Records a `payload` that was submitted to the DataEdge contract.
+    pub fn record_payload(&mut self, payload: PublishedPayload) -> Result<(), HistoryError> {
+        self.append([Entry::Payload(payload.clone())])?;
+        self.payloads.push(payload);
+        Ok(())
+    }
+
+    fn append(&self, entries: impl IntoIterator<Item = Entry>) -> Result<(), HistoryError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut lines = String::new();
+        for entry in entries {
+            // Unwrap: serializing plain data into JSON can't fail.
+            lines.push_str(&serde_json::to_string(&entry).unwrap());
+            lines.push('\n');
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Returns the block published for `chain_id` at `epoch`, if any.
+    pub fn get(&self, epoch: u64, chain_id: &Caip2ChainId) -> Option<&PublishedBlock> {
+        self.entries
+            .get(&epoch)
+            .and_then(|blocks| blocks.get(chain_id))
+    }
+
+    /// The latest payload that was submitted, if any.
+    pub fn latest_payload(&self) -> Option<&PublishedPayload> {
+        self.payloads.last()
+    }
+
+    /// All payloads that were submitted, oldest first.
+    pub fn payloads(&self) -> &[PublishedPayload] {
+        &self.payloads
+    }
+
+    /// The latest `count` payloads that were submitted, oldest first.
+    pub fn latest_payloads(&self, count: usize) -> &[PublishedPayload] {
+        &self.payloads[self.payloads.len().saturating_sub(count)..]
+    }
+
+    /// The latest epoch at which blocks were published, if any.
+    pub fn latest_epoch(&self) -> Option<u64> {
+        self.entries.keys().next_back().copied()
+    }
+
+    /// Checks that the Epoch Subgraph is not behind the history by more than one epoch. Being one
+    /// epoch behind is normal right after publishing, while our transaction gets indexed.
+    pub fn check_continuity(&self, subgraph_state: &SubgraphState) -> Result<(), Discontinuity> {
+        let Some(published_epoch) = self.latest_epoch() else {
+            return Ok(());
+        };
+        let subgraph_epoch = subgraph_state.latest_epoch_number();
+        if subgraph_epoch.is_some_and(|epoch| epoch + 1 >= published_epoch) {
+            return Ok(());
+        }
+        Err(Discontinuity {
+            published_epoch,
+            subgraph_epoch,
+        })
+    }
+
+    /// Moves the history file at `path` aside, so that the oracle starts over with an empty
+    /// history. Returns where the file was moved to, or `None` if there was no file.
+    pub fn archive(path: impl AsRef<Path>) -> Result<Option<PathBuf>, HistoryError> {
+        let path = path.as_ref();
+        let mut archived = path.as_os_str().to_owned();
+        archived.push(format!(".{}.bak", UNIX_EPOCH.elapsed().unwrap().as_secs()));
+        let archived = PathBuf::from(archived);
+        match std::fs::rename(path, &archived) {
+            Ok(()) => Ok(Some(archived)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes all payloads with a timestamp within `from..to` to `writer`. JSON exports also
+    /// include the blocks that were published with each payload.
+    pub fn export(
+        &self,
+        from: Option<u64>,
+        to: Option<u64>,
+        format: ExportFormat,
+        mut writer: impl Write,
+    ) -> std::io::Result<()> {
+        let payloads = self.payloads.iter().filter(|payload| {
+            from.is_none_or(|from| payload.timestamp >= from)
+                && to.is_none_or(|to| payload.timestamp < to)
+        });
+        match format {
+            ExportFormat::Csv => {
+                writeln!(
+                    writer,
+                    "epoch,timestamp,correlation_id,messages,tx_hash,status,nonce,block_number,gas_used,effective_gas_price,payload"
+                )?;
+                fn optional(value: Option<impl ToString>) -> String {
+                    value.map(|v| v.to_string()).unwrap_or_default()
+                }
+                for payload in payloads {
+                    writeln!(
+                        writer,
+                        "{},{},{},{},{:?},{},{},{},{},{},{}",
+                        payload.epoch,
+                        payload.timestamp,
+                        payload.correlation_id,
+                        payload.messages.join(";"),
+                        payload.tx_hash,
+                        payload.status,
+                        optional(payload.nonce),
+                        optional(payload.block_number),
+                        optional(payload.gas_used),
+                        optional(payload.effective_gas_price),
+                        payload.payload
+                    )?;
+                }
+            }
+            ExportFormat::Json => {
+                #[derive(Serialize)]
+                struct Row<'a> {
+                    #[serde(flatten)]
+                    payload: &'a PublishedPayload,
+                    blocks: Vec<&'a PublishedBlock>,
+                }
+                let rows: Vec<Row> = payloads
+                    .map(|payload| Row {
+                        payload,
+                        blocks: self
+                            .entries
+                            .get(&payload.epoch)
+                            .map(|blocks| blocks.values().collect())
+                            .unwrap_or_default(),
+                    })
+                    .collect();
+                serde_json::to_writer_pretty(&mut writer, &rows)?;
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the latest block update of every network in the subgraph against the history.
+    ///
+    /// Only epochs that are covered by the history are checked, i.e. anything before the
+    /// first recorded epoch is ignored.
+    ///
+    /// The subgraph doesn't keep block hashes around, so only block numbers are compared. Hashes
+    /// are still recorded for manual auditing.
+    pub fn cross_check(&self, subgraph_state: &SubgraphState) -> Vec<Discrepancy> {
+        let first_epoch = match self.entries.keys().next() {
+            Some(epoch) => *epoch,
+            None => return vec![],
+        };
+        let networks = match &subgraph_state.global_state {
+            Some(global_state) => &global_state.networks,
+            None => return vec![],
+        };
+
+        let mut discrepancies = vec![];
+        for network in networks {
+            let update = match &network.latest_block_update {
+                Some(update) if update.updated_at_epoch_number >= first_epoch => update,
+                _ => continue,
+            };
+            let epoch = update.updated_at_epoch_number;
+            match (self.entries.get(&epoch), self.get(epoch, &network.id)) {
+                (Some(_), Some(published)) if published.number != update.block_number => {
+                    discrepancies.push(Discrepancy::Mismatch {
+                        published: published.clone(),
+                        recorded_number: update.block_number,
+                    })
+                }
+                // We published something for this epoch, but not for this chain (e.g. because
+                // its providers were down). The subgraph then extrapolates the block number, so
+                // there's nothing to compare against.
+                (Some(_), _) => {}
+                (None, _) => discrepancies.push(Discrepancy::Unknown {
+                    epoch,
+                    chain_id: network.id.clone(),
+                    recorded_number: update.block_number,
+                }),
+            }
+        }
+        discrepancies
+    }
+
+    /// Runs [`History::cross_check`] and reports its findings via logs and metrics.
+    pub fn report_discrepancies(&self, subgraph_state: &SubgraphState, metrics: &Metrics) {
+        let discrepancies = self.cross_check(subgraph_state);
+        for discrepancy in &discrepancies {
+            error!(%discrepancy, "The Epoch Subgraph disagrees with the oracle's history.");
+        }
+        metrics.set_history_discrepancies(discrepancies.len() as i64);
+    }
+
+    fn insert(&mut self, block: PublishedBlock) {
+        self.entries
+            .entry(block.epoch)
+            .or_default()
+            .insert(block.chain_id.clone(), block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subgraph::{BlockUpdate, GlobalState, Network};
+    use std::str::FromStr;
+
+    fn chain(id: &str) -> Caip2ChainId {
+        Caip2ChainId::from_str(id).unwrap()
+    }
+
+    fn blocks(numbers: &[(&str, u64)]) -> BTreeMap<Caip2ChainId, BlockPtr> {
+        numbers
+            .iter()
+            .map(|(id, number)| (chain(id), BlockPtr::new(*number, [*number as u8; 32])))
+            .collect()
+    }
+
+    fn correlation_id() -> CorrelationId {
+        CorrelationId::new(0, 1)
+    }
+
+    fn subgraph_state(updates: &[(&str, u64, u64)]) -> SubgraphState {
+        let networks = updates
+            .iter()
+            .enumerate()
+            .map(|(i, (id, epoch, number))| Network {
+                id: chain(id),
+                array_index: i as u64,
+                latest_block_update: Some(BlockUpdate {
+                    block_number: *number,
+                    acceleration: 0,
+                    delta: 0,
+                    updated_at_epoch_number: *epoch,
+                }),
+            })
+            .collect();
+        SubgraphState {
+            last_indexed_block_number: 0,
+            global_state: Some(GlobalState {
+                networks,
+                encoding_version: 0,
+                latest_epoch_number: None,
+            }),
+            last_payload: None,
+        }
+    }
+
+    #[test]
+    fn persists_across_restarts() {
+        let path =
+            std::env::temp_dir().join(format!("block-oracle-history-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = History::open(&path).unwrap();
+        history
+            .record(
+                10,
+                &blocks(&[("eip155:1", 100), ("eip155:100", 200)]),
+                &correlation_id(),
+            )
+            .unwrap();
+        history
+            .record(11, &blocks(&[("eip155:1", 110)]), &correlation_id())
+            .unwrap();
+        history.record_payload(payload(11, 1000)).unwrap();
+
+        let reopened = History::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.entries, history.entries);
+        assert_eq!(reopened.payloads, history.payloads);
+        assert_eq!(reopened.get(10, &chain("eip155:100")).unwrap().number, 200);
+    }
+
+    fn payload(epoch: u64, timestamp: u64) -> PublishedPayload {
+        PublishedPayload {
+            epoch,
+            timestamp,
+            correlation_id: correlation_id().to_string(),
+            messages: vec!["SetBlockNumbersForNextEpoch".to_string()],
+            payload: "0x00".to_string(),
+            tx_hash: H256::repeat_byte(epoch as u8),
+            status: SubmissionStatus::Confirmed,
+            nonce: Some(epoch.into()),
+            block_number: Some(timestamp),
+            gas_used: Some(21_000.into()),
+            effective_gas_price: None,
+        }
+    }
+
+    #[test]
+    fn latest_payloads() {
+        let mut history = History::in_memory();
+        assert!(history.latest_payloads(2).is_empty());
+        for epoch in 10..13 {
+            history.record_payload(payload(epoch, epoch * 100)).unwrap();
+        }
+        let epochs = |payloads: &[PublishedPayload]| -> Vec<u64> {
+            payloads.iter().map(|payload| payload.epoch).collect()
+        };
+        assert_eq!(epochs(history.latest_payloads(2)), [11, 12]);
+        assert_eq!(epochs(history.latest_payloads(5)), [10, 11, 12]);
+    }
+
+    /// Entries from before statuses and nonces were recorded.
+    #[test]
+    fn older_payload_entries() {
+        let entry = format!(
+            r#"{{"epoch":10,"timestamp":1000,"correlation_id":"0-1","messages":[],"payload":"0x00","tx_hash":"0x{}","gas_used":null,"effective_gas_price":null}}"#,
+            "0a".repeat(32)
+        );
+        let Entry::Payload(payload) = serde_json::from_str(&entry).unwrap() else {
+            panic!("expected a payload");
+        };
+        assert_eq!(payload.status, SubmissionStatus::Confirmed);
+        assert_eq!(payload.nonce, None);
+    }
+
+    #[test]
+    fn export() {
+        let mut history = History::in_memory();
+        for (epoch, timestamp) in [(10, 1000), (11, 2000), (12, 3000)] {
+            history
+                .record(epoch, &blocks(&[("eip155:1", epoch)]), &correlation_id())
+                .unwrap();
+            history.record_payload(payload(epoch, timestamp)).unwrap();
+        }
+
+        let mut csv = vec![];
+        history
+            .export(Some(2000), Some(3000), ExportFormat::Csv, &mut csv)
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("11,2000,0-1,SetBlockNumbersForNextEpoch,0x0b0b"));
+        assert!(lines[1].contains(",confirmed,11,2000,"));
+        assert!(lines[1].ends_with(",21000,,0x00"));
+
+        let mut json = vec![];
+        history
+            .export(None, Some(2000), ExportFormat::Json, &mut json)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["epoch"], 10);
+        assert_eq!(json[0]["blocks"][0]["chain_id"], "eip155:1");
+    }
+
+    fn with_latest_epoch(mut state: SubgraphState, epoch: Option<u64>) -> SubgraphState {
+        state.global_state.as_mut().unwrap().latest_epoch_number = epoch;
+        state
+    }
+
+    #[test]
+    fn continuity() {
+        let mut history = History::in_memory();
+        let state = subgraph_state(&[("eip155:1", 10, 100)]);
+        assert_eq!(history.check_continuity(&state), Ok(()));
+
+        history
+            .record(10, &blocks(&[("eip155:1", 100)]), &correlation_id())
+            .unwrap();
+        assert_eq!(history.latest_epoch(), Some(10));
+        for epoch in [9, 10, 11] {
+            let state = with_latest_epoch(state.clone(), Some(epoch));
+            assert_eq!(history.check_continuity(&state), Ok(()));
+        }
+    }
+
+    #[test]
+    fn wiped_subgraph() {
+        let mut history = History::in_memory();
+        history
+            .record(10, &blocks(&[("eip155:1", 100)]), &correlation_id())
+            .unwrap();
+
+        let redeployed = with_latest_epoch(subgraph_state(&[]), Some(2));
+        assert_eq!(
+            history.check_continuity(&redeployed),
+            Err(Discontinuity {
+                published_epoch: 10,
+                subgraph_epoch: Some(2)
+            })
+        );
+        let wiped = SubgraphState {
+            global_state: None,
+            ..subgraph_state(&[])
+        };
+        assert_eq!(
+            history.check_continuity(&wiped),
+            Err(Discontinuity {
+                published_epoch: 10,
+                subgraph_epoch: None
+            })
+        );
+    }
+
+    #[test]
+    fn archive() {
+        let path = std::env::temp_dir().join(format!(
+            "block-oracle-history-archive-{}.jsonl",
+            std::process::id()
+        ));
+        assert_eq!(History::archive(&path).unwrap(), None);
+
+        let mut history = History::open(&path).unwrap();
+        history
+            .record(10, &blocks(&[("eip155:1", 100)]), &correlation_id())
+            .unwrap();
+        let archived = History::archive(&path).unwrap().unwrap();
+        assert_eq!(History::open(&path).unwrap().latest_epoch(), None);
+        assert_eq!(History::open(&archived).unwrap().latest_epoch(), Some(10));
+        std::fs::remove_file(archived).unwrap();
+    }
+
+    #[test]
+    fn agreeing_subgraph() {
+        let mut history = History::in_memory();
+        history
+            .record(
+                10,
+                &blocks(&[("eip155:1", 100), ("eip155:100", 200)]),
+                &correlation_id(),
+            )
+            .unwrap();
+        let state = subgraph_state(&[("eip155:1", 10, 100), ("eip155:100", 10, 200)]);
+        assert!(history.cross_check(&state).is_empty());
+    }
+
+    #[test]
+    fn mismatching_block_number() {
+        let mut history = History::in_memory();
+        history
+            .record(10, &blocks(&[("eip155:1", 100)]), &correlation_id())
+            .unwrap();
+        let state = subgraph_state(&[("eip155:1", 10, 101)]);
+        assert!(matches!(
+            history.cross_check(&state).as_slice(),
+            [Discrepancy::Mismatch {
+                recorded_number: 101,
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn unknown_submission() {
+        let mut history = History::in_memory();
+        history
+            .record(10, &blocks(&[("eip155:1", 100)]), &correlation_id())
+            .unwrap();
+        let state = subgraph_state(&[("eip155:1", 11, 110)]);
+        assert!(matches!(
+            history.cross_check(&state).as_slice(),
+            [Discrepancy::Unknown { epoch: 11, .. }]
+        ));
+    }
+
+    #[test]
+    fn epochs_before_history_are_ignored() {
+        let mut history = History::in_memory();
+        history
+            .record(10, &blocks(&[("eip155:1", 100)]), &correlation_id())
+            .unwrap();
+        let state = subgraph_state(&[("eip155:1", 10, 100), ("eip155:100", 9, 90)]);
+        assert!(history.cross_check(&state).is_empty());
+    }
+}
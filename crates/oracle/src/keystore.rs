@@ -0,0 +1,207 @@
+//! Decrypts the owner's private key from an encrypted JSON keystore, i.e. the [Web3 Secret Storage]
+//! files that `geth account new`, `cast wallet new` and most wallets export.
+//!
+//! Only version 3 keystores are supported, with the `aes-128-ctr` cipher and either the `scrypt` or
+//! the `pbkdf2` (HMAC-SHA256) key derivation function.
+//!
+//! [Web3 Secret Storage]: https://ethereum.org/en/developers/docs/data-structures-and-encoding/web3-secret-storage/
+
+use openssl::{hash::MessageDigest, pkcs5, symm};
+use secp256k1::SecretKey;
+use serde::Deserialize;
+use std::path::Path;
+use web3::signing::keccak256;
+
+/// Upper bound of the memory that scrypt may use, which is about 128 * `n` * `r` bytes. Keystores
+/// made with the "light" or "standard" parameters of geth need 8 MiB and 256 MiB respectively.
+const SCRYPT_MAX_MEMORY: u64 = 1 << 30;
+
+#[derive(thiserror::Error, Debug)]
+pub enum KeystoreError {
+    #[error("failed to read the keystore file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid keystore file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported keystore: {0}")]
+    Unsupported(String),
+    #[error("invalid hex in the keystore file: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("failed to decrypt the keystore: {0}")]
+    Crypto(#[from] openssl::error::ErrorStack),
+    #[error("wrong keystore password")]
+    WrongPassword,
+    #[error("the keystore doesn't contain a valid private key: {0}")]
+    InvalidKey(#[from] secp256k1::Error),
+}
+
+#[derive(Deserialize, Debug)]
+struct Keystore {
+    version: u64,
+    // Some wallets capitalize this field.
+    #[serde(alias = "Crypto")]
+    crypto: Crypto,
+}
+
+#[derive(Deserialize, Debug)]
+struct Crypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    #[serde(flatten)]
+    kdf: Kdf,
+    mac: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CipherParams {
+    iv: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "kdf", content = "kdfparams", rename_all = "lowercase")]
+enum Kdf {
+    Scrypt {
+        dklen: usize,
+        n: u64,
+        r: u64,
+        p: u64,
+        salt: String,
+    },
+    Pbkdf2 {
+        c: usize,
+        dklen: usize,
+        prf: String,
+        salt: String,
+    },
+}
+
+/// Reads the keystore at `path` and decrypts it with `password`.
+pub fn decrypt_file(path: impl AsRef<Path>, password: &str) -> Result<SecretKey, KeystoreError> {
+    let json = std::fs::read_to_string(path)?;
+    decrypt(&json, password)
+}
+
+pub fn decrypt(json: &str, password: &str) -> Result<SecretKey, KeystoreError> {
+    let keystore: Keystore = serde_json::from_str(json)?;
+    if keystore.version != 3 {
+        return Err(KeystoreError::Unsupported(format!(
+            "version {}",
+            keystore.version
+        )));
+    }
+    let crypto = keystore.crypto;
+    if crypto.cipher != "aes-128-ctr" {
+        return Err(KeystoreError::Unsupported(format!(
+            "cipher {}",
+            crypto.cipher
+        )));
+    }
+
+    let derived_key = derive_key(&crypto.kdf, password.as_bytes())?;
+    let ciphertext = hex::decode(&crypto.ciphertext)?;
+    let mac_preimage = [&derived_key[16..32], &ciphertext].concat();
+    if keccak256(&mac_preimage)[..] != hex::decode(&crypto.mac)?[..] {
+        return Err(KeystoreError::WrongPassword);
+    }
+
+    let iv = hex::decode(&crypto.cipherparams.iv)?;
+    let private_key = symm::decrypt(
+        symm::Cipher::aes_128_ctr(),
+        &derived_key[..16],
+        Some(&iv),
+        &ciphertext,
+    )?;
+    Ok(SecretKey::from_slice(&private_key)?)
+}
+
+fn derive_key(kdf: &Kdf, password: &[u8]) -> Result<Vec<u8>, KeystoreError> {
+    let (dklen, salt) = match kdf {
+        Kdf::Scrypt { dklen, salt, .. } | Kdf::Pbkdf2 { dklen, salt, .. } => (*dklen, salt),
+    };
+    // The MAC is computed over the second half of the first 32 bytes.
+    if dklen < 32 {
+        return Err(KeystoreError::Unsupported(format!("dklen {dklen}")));
+    }
+    let salt = hex::decode(salt)?;
+    let mut key = vec![0; dklen];
+    match kdf {
+        Kdf::Scrypt { n, r, p, .. } => {
+            pkcs5::scrypt(password, &salt, *n, *r, *p, SCRYPT_MAX_MEMORY, &mut key)?;
+        }
+        Kdf::Pbkdf2 { c, prf, .. } => {
+            if prf != "hmac-sha256" {
+                return Err(KeystoreError::Unsupported(format!("prf {prf}")));
+            }
+            pkcs5::pbkdf2_hmac(password, &salt, *c, MessageDigest::sha256(), &mut key)?;
+        }
+    }
+    Ok(key)
+}
+
+/// Asks for the keystore password on the terminal, and reads it from stdin without echoing it.
+pub fn prompt_password(path: &Path) -> std::io::Result<String> {
+    crate::terminal::read_secret(&format!("Password for {}: ", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The PBKDF2 keystore is the test vector of the Web3 Secret Storage Definition. Its scrypt
+    // vector uses parameters that OpenSSL rejects (n >= 2^(16 * r)), so this one uses the "light"
+    // parameters of geth instead.
+    const PRIVATE_KEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+    const PASSWORD: &str = "testpassword";
+
+    const PBKDF2_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    const SCRYPT_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext": "91c23c03a8b5117a6e3d78dbb5dfc46f037aff9351dff59c2879586f51756502",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 8192,
+                "p": 1,
+                "r": 8,
+                "salt": "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac": "8f8639fe08fe1f43d6c13006d66ad2d993c6674ee2ba03f025596797a73614b9"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    #[test]
+    fn decrypts_keystores() {
+        let expected: SecretKey = PRIVATE_KEY.parse().unwrap();
+        assert_eq!(decrypt(PBKDF2_KEYSTORE, PASSWORD).unwrap(), expected);
+        assert_eq!(decrypt(SCRYPT_KEYSTORE, PASSWORD).unwrap(), expected);
+    }
+
+    #[test]
+    fn wrong_password() {
+        assert!(matches!(
+            decrypt(PBKDF2_KEYSTORE, "wrongpassword"),
+            Err(KeystoreError::WrongPassword)
+        ));
+    }
+}
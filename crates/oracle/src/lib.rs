@@ -0,0 +1,58 @@
+//! The Epoch Block Oracle.
+//!
+//! Every time the Epoch Manager enters a new epoch, the oracle collects the latest block of every
+//! indexed chain and sends them to the DataEdge contract on the protocol chain, where the Epoch
+//! Subgraph picks them up.
+//!
+//! The `block-oracle` binary is a thin wrapper around this library, which can also be embedded in
+//! other services. The main entry points are:
+//!
+//! - [`Config`], which is usually loaded from a TOML file with [`Config::try_parse`].
+//! - [`Oracle`](runner::oracle::Oracle), which runs one polling iteration at a time via
+//!   [`Oracle::run`](runner::oracle::Oracle::run).
+//! - [`Metrics`](metrics::Metrics), the Prometheus metrics that the oracle reports to. Every
+//!   component gets them injected, so you can register them alongside your own.
+//! - [`runner::run`], which runs the oracle the same way the binary does, i.e. with logging, a
+//!   metrics server and CTRL+C handling. [`runner::run_reloadable`] also reloads the
+//!   configuration file on `SIGHUP`.
+//!
+//! ```no_run
+//! use block_oracle::{metrics::Metrics, runner::oracle::Oracle, Config};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config = Config::try_parse("config.toml")?;
+//! let metrics = Metrics::new()?;
+//! let mut oracle = Oracle::new(config, metrics)?;
+//! loop {
+//!     oracle.run().await?;
+//! }
+//! # }
+//! ```
+
+pub mod accounting;
+pub mod admin;
+pub mod build_info;
+pub mod config;
+pub mod config_check;
+pub mod contracts;
+pub mod history;
+pub mod keystore;
+pub mod metrics;
+pub mod models;
+pub mod runner;
+pub mod service;
+pub mod signer;
+pub mod status;
+pub mod subgraph;
+pub mod terminal;
+#[cfg(test)]
+mod test_utils;
+
+pub use config::Config;
+pub use models::{BlockmetaProviderForChain, Caip2ChainId, JrpcProviderForChain};
+pub use runner::*;
+pub use subgraph::{query_subgraph, SubgraphQuery, SubgraphQueryError};
+
+pub mod blockmeta {
+    pub mod blockmeta_client;
+}
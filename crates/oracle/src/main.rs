@@ -1,35 +1,70 @@
-pub mod config;
-pub mod contracts;
-pub mod metrics;
-pub mod models;
-pub mod runner;
-pub mod subgraph;
-
+use anyhow::Context;
+use block_oracle::accounting::{costs_by, Costs, Period};
+use block_oracle::config::{OwnerKeySource, RuntimeFlavor, RuntimeOptions};
+use block_oracle::config_check;
+use block_oracle::history::{ExportFormat, History};
+use block_oracle::metrics::Metrics;
+use block_oracle::signer::{
+    self,
+    ledger::{self, DerivationPath, LedgerSigner},
+    Signer,
+};
+use block_oracle::submitter::{self, Payload, SubmissionOutcome, TransactionSubmitter};
+use block_oracle::{build_info, contracts::Contracts, runner, Config, JrpcProviderForChain};
 use clap::Parser;
-use contracts::Contracts;
+use epoch_encoding::{
+    decode_messages_with_version, json::compressed_to_json, Encoder, Message,
+    CURRENT_ENCODING_VERSION,
+};
 use json_oracle_encoder::{print_encoded_json_messages, OutputKind};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::runtime::{Builder, Runtime};
 use web3::transports::Http;
 
-pub use config::Config;
-pub use models::{BlockmetaProviderForChain, Caip2ChainId, JrpcProviderForChain};
-pub use runner::*;
-pub use subgraph::{query_subgraph, SubgraphQueryError};
+fn main() -> anyhow::Result<()> {
+    let Cli {
+        command,
+        owner_key_args,
+    } = Cli::parse();
+    match command {
+        Clap::Run {
+            config_file,
+            runtime_args,
+            dry_run,
+        } => {
+            let mut config = owner_key_args.load_config(&config_file)?;
+            config.dry_run |= dry_run;
+            let runtime = build_runtime(runtime_args.apply(config.runtime_options))?;
+            runtime.block_on(runner::run_reloadable(config_file, config))?;
+        }
+        // One-shot commands do very little work, so a single thread is plenty.
+        one_shot => {
+            let options = RuntimeOptions {
+                flavor: RuntimeFlavor::CurrentThread,
+                ..Default::default()
+            };
+            build_runtime(options)?.block_on(run_one_shot(one_shot, owner_key_args))?;
+        }
+    }
 
-pub mod blockmeta {
-    pub mod blockmeta_client;
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    match Clap::parse() {
-        Clap::Run { config_file } => runner::run(config_file).await?,
+async fn run_one_shot(command: Clap, owner_key_args: OwnerKeyArgs) -> anyhow::Result<()> {
+    match command {
+        Clap::Run { .. } => unreachable!("`run` is not a one-shot command"),
         Clap::Encode {
             json_path,
             calldata,
+            config_file,
         } => {
             let file_contents = std::fs::read_to_string(json_path)?;
             let json = serde_json::from_str(&file_contents)?;
+            if let Some(config_file) = config_file {
+                let config = owner_key_args.load_config(config_file)?;
+                verify_removals(config, &json).await?;
+            }
             let output_kind = if calldata {
                 OutputKind::Calldata
             } else {
@@ -38,16 +73,60 @@ async fn main() -> anyhow::Result<()> {
             print_encoded_json_messages(output_kind, json)?;
         }
         Clap::CurrentEpoch { config_file } => {
-            let config = Config::parse(config_file);
+            let config = owner_key_args.load_config(config_file)?;
             print_current_epoch(config).await?;
         }
         Clap::SendMessage {
             config_file,
             payload,
+            signing_args,
         } => {
-            let config = Config::parse(config_file);
+            let config = owner_key_args.load_config(config_file)?;
             let payload = hex::decode(payload)?;
-            send_message(config, payload).await?;
+            send_message(config, payload, signing_args).await?;
+        }
+        Clap::ResetSubgraph {
+            config_file,
+            yes,
+            signing_args,
+        } => {
+            anyhow::ensure!(
+                yes,
+                "This wipes all networks and block numbers from the Epoch Subgraph. Pass `--yes` \
+                 if that's what you want"
+            );
+            let config = owner_key_args.load_config(config_file)?;
+            send_message(config, reset_payload()?, signing_args).await?;
+        }
+        Clap::ExportHistory {
+            config_file,
+            format,
+            from,
+            to,
+            output,
+        } => {
+            let config = owner_key_args.load_config(config_file)?;
+            export_history(config, format, from, to, output)?;
+        }
+        Clap::Submissions { config_file, last } => {
+            let config = owner_key_args.load_config(config_file)?;
+            print_submissions(config, last)?;
+        }
+        Clap::Costs {
+            config_file,
+            by,
+            from,
+            to,
+        } => {
+            let config = owner_key_args.load_config(config_file)?;
+            print_costs(config, by, from, to)?;
+        }
+        Clap::ResetHistory { config_file } => {
+            let config = owner_key_args.load_config(config_file)?;
+            reset_history(config)?;
+        }
+        Clap::Config(ConfigCommand::Check { config_file }) => {
+            check_config(config_file, owner_key_args).await?;
         }
     }
 
@@ -57,13 +136,27 @@ async fn main() -> anyhow::Result<()> {
 #[derive(Parser, Debug, Clone)]
 #[clap(name = "block-oracle")]
 #[clap(bin_name = "block-oracle")]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version = build_info::long_version(), about, long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Clap,
+    #[clap(flatten)]
+    owner_key_args: OwnerKeyArgs,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
 enum Clap {
     /// Run the block oracle and regularly sends block number updates.
     Run {
-        /// The path of the TOML configuration file.
+        /// The path of the TOML or JSON configuration file.
         #[clap(parse(from_os_str))]
         config_file: PathBuf,
+        #[clap(flatten)]
+        runtime_args: RuntimeArgs,
+        /// Encode and log payloads without ever submitting them. Overrides `dry_run` in the
+        /// configuration file.
+        #[clap(long)]
+        dry_run: bool,
     },
     /// Compile block oracle messages from JSON to calldata.
     Encode {
@@ -72,44 +165,452 @@ enum Clap {
         /// Whether to output the full calldata instead of just the payload.
         #[clap(short, long, action)]
         calldata: bool,
+        /// If given, network removals are checked against the networks in the Epoch Subgraph,
+        /// and must name the networks they're meant to remove.
+        #[clap(long)]
+        config_file: Option<PathBuf>,
     },
     /// Query the Epoch Manager for the current epoch.
     CurrentEpoch {
-        /// The path of the TOML configuration file.
+        /// The path of the TOML or JSON configuration file.
         #[clap(short, long)]
         config_file: PathBuf,
     },
     /// Send a message to the DataEdge contract.
     SendMessage {
-        /// The path of the TOML configuration file.
+        /// The path of the TOML or JSON configuration file.
         #[clap(short, long)]
         config_file: PathBuf,
         payload: String,
+        #[clap(flatten)]
+        signing_args: SigningArgs,
+    },
+    /// Send a `Reset` message to the DataEdge contract, which wipes the Epoch Subgraph's state,
+    /// e.g. after a testnet subgraph got corrupted.
+    ResetSubgraph {
+        /// The path of the TOML or JSON configuration file.
+        #[clap(short, long)]
+        config_file: PathBuf,
+        /// Confirms that the subgraph's state should be wiped.
+        #[clap(long)]
+        yes: bool,
+        #[clap(flatten)]
+        signing_args: SigningArgs,
+    },
+    /// Export the history of published payloads, e.g. for reporting purposes.
+    ExportHistory {
+        /// The path of the TOML or JSON configuration file.
+        #[clap(short, long)]
+        config_file: PathBuf,
+        /// Either `csv` or `json`.
+        #[clap(long, default_value = "csv")]
+        format: ExportFormat,
+        /// Only export payloads submitted at or after this date (e.g. `2024-01-01` or
+        /// `2024-01-01T12:00:00Z`).
+        #[clap(long, parse(try_from_str = parse_date))]
+        from: Option<u64>,
+        /// Only export payloads submitted before this date.
+        #[clap(long, parse(try_from_str = parse_date))]
+        to: Option<u64>,
+        /// Where to write the export. Defaults to stdout.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show the latest payloads submitted to the DataEdge contract, and how their transactions
+    /// ended.
+    Submissions {
+        /// The path of the TOML or JSON configuration file.
+        #[clap(short, long)]
+        config_file: PathBuf,
+        /// How many submissions to show.
+        #[clap(short = 'n', long, default_value = "10")]
+        last: usize,
+    },
+    /// Report what the submitted transactions cost, per month or per epoch.
+    Costs {
+        /// The path of the TOML or JSON configuration file.
+        #[clap(short, long)]
+        config_file: PathBuf,
+        /// Either `month` or `epoch`.
+        #[clap(long, default_value = "month")]
+        by: Period,
+        /// Only count transactions confirmed at or after this date (e.g. `2024-01-01` or
+        /// `2024-01-01T12:00:00Z`).
+        #[clap(long, parse(try_from_str = parse_date))]
+        from: Option<u64>,
+        /// Only count transactions confirmed before this date.
+        #[clap(long, parse(try_from_str = parse_date))]
+        to: Option<u64>,
+    },
+    /// Move the history of published payloads aside, e.g. after the Epoch Subgraph was
+    /// deliberately redeployed from scratch. The oracle must not be running.
+    ResetHistory {
+        /// The path of the TOML or JSON configuration file.
+        #[clap(short, long)]
+        config_file: PathBuf,
+    },
+    /// Inspect a configuration file.
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum ConfigCommand {
+    /// Parse the configuration file and check it against the services it points to: every JSON
+    /// RPC provider must be on its chain, the DataEdge and Epoch Manager must have code, the owner
+    /// key must match `owner_address` and the Epoch Subgraph must answer.
+    Check {
+        /// The path of the TOML or JSON configuration file.
+        #[clap(short, long)]
+        config_file: PathBuf,
     },
 }
 
-async fn send_message(config: Config, payload: Vec<u8>) -> anyhow::Result<()> {
-    let private_key = config.owner_private_key;
-    let contracts = init_contracts(config)?;
-    let tx = contracts.submit_call(payload, &private_key).await?;
-    println!("Sent message.\nTransaction hash: {tx:?}");
+/// Parses a date, with an optional time, into seconds since the UNIX epoch.
+fn parse_date(s: &str) -> anyhow::Result<u64> {
+    let timestamp = match chrono::DateTime::parse_from_rfc3339(s) {
+        Ok(datetime) => datetime.timestamp(),
+        Err(_) => chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .with_context(|| format!("Invalid date '{s}'"))?
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp(),
+    };
+    u64::try_from(timestamp).with_context(|| format!("Date '{s}' is before 1970"))
+}
+
+/// Command line overrides for the `[runtime]` section of the configuration file.
+#[derive(clap::Args, Debug, Clone)]
+struct RuntimeArgs {
+    /// Run everything on a single thread.
+    #[clap(long)]
+    current_thread: bool,
+    /// Number of worker threads of the multi-threaded runtime.
+    #[clap(long)]
+    worker_threads: Option<usize>,
+    /// Maximum number of threads used for blocking operations.
+    #[clap(long)]
+    max_blocking_threads: Option<usize>,
+}
+
+impl RuntimeArgs {
+    fn apply(&self, mut options: RuntimeOptions) -> RuntimeOptions {
+        if self.current_thread {
+            options.flavor = RuntimeFlavor::CurrentThread;
+        }
+        options.worker_threads = self.worker_threads.or(options.worker_threads);
+        options.max_blocking_threads = self.max_blocking_threads.or(options.max_blocking_threads);
+        options
+    }
+}
+
+// Where to read the owner's private key from instead of the configuration file. Unlike a key on
+// the command line, none of these end up in the shell history or the process list. (Not a doc
+// comment, which clap would show as the description of `block-oracle` itself.)
+#[derive(clap::Args, Debug, Clone)]
+struct OwnerKeyArgs {
+    /// Read the owner's private key from this file, e.g. a mounted secret.
+    #[clap(long, global = true, group = "owner-key", value_name = "PATH")]
+    owner_private_key_file: Option<PathBuf>,
+    /// Read the owner's private key from this environment variable.
+    #[clap(long, global = true, group = "owner-key", value_name = "NAME")]
+    owner_private_key_env: Option<String>,
+    /// Read the owner's private key from the first line of stdin.
+    #[clap(long, global = true, group = "owner-key")]
+    owner_private_key_stdin: bool,
+    /// Ask for the owner's private key on the terminal, without echoing it.
+    #[clap(long, global = true, group = "owner-key")]
+    prompt_owner_private_key: bool,
+}
+
+impl OwnerKeyArgs {
+    fn source(&self) -> Option<OwnerKeySource> {
+        if let Some(path) = &self.owner_private_key_file {
+            Some(OwnerKeySource::File(path.clone()))
+        } else if let Some(name) = &self.owner_private_key_env {
+            Some(OwnerKeySource::EnvVar(name.clone()))
+        } else if self.owner_private_key_stdin {
+            Some(OwnerKeySource::Stdin)
+        } else if self.prompt_owner_private_key {
+            Some(OwnerKeySource::Prompt)
+        } else {
+            None
+        }
+    }
+
+    /// Loads the configuration file, with the owner's key from the command line if one was given.
+    fn load_config(&self, config_file: impl AsRef<Path>) -> anyhow::Result<Config> {
+        match self.source() {
+            Some(source) => Config::try_parse_with_owner_key(config_file, &source),
+            None => Config::try_parse(config_file),
+        }
+    }
+}
+
+/// How one-shot commands that send a transaction sign it.
+#[derive(clap::Args, Debug, Clone)]
+struct SigningArgs {
+    /// Sign with a Ledger device instead of the configured owner key. The payload is decoded and
+    /// printed first, so that it can be checked before approving the transaction on the device.
+    #[clap(long)]
+    ledger: bool,
+    /// The derivation path of the owner account on the Ledger device.
+    #[clap(long, default_value = ledger::DEFAULT_DERIVATION_PATH, requires = "ledger")]
+    derivation_path: DerivationPath,
+}
+
+fn build_runtime(options: RuntimeOptions) -> std::io::Result<Runtime> {
+    let mut builder = match options.flavor {
+        RuntimeFlavor::MultiThread => {
+            let mut builder = Builder::new_multi_thread();
+            if let Some(worker_threads) = options.worker_threads {
+                builder.worker_threads(worker_threads);
+            }
+            builder
+        }
+        RuntimeFlavor::CurrentThread => Builder::new_current_thread(),
+    };
+    if let Some(max_blocking_threads) = options.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+    builder.enable_all().build()
+}
+
+fn export_history(
+    config: Config,
+    format: ExportFormat,
+    from: Option<u64>,
+    to: Option<u64>,
+    output: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let path = config
+        .history_file
+        .context("There's no `history_file` in the configuration file")?;
+    let history = History::open(path)?;
+    match output {
+        Some(output) => history.export(from, to, format, std::fs::File::create(output)?)?,
+        None => history.export(from, to, format, std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn print_submissions(config: Config, last: usize) -> anyhow::Result<()> {
+    let path = config
+        .history_file
+        .context("There's no `history_file` in the configuration file")?;
+    let history = History::open(path)?;
+    println!(
+        "{:<8} {:<20} {:<9} {:>8} {:>10} {:>10}  {:<66}  MESSAGES",
+        "EPOCH", "TIME", "STATUS", "NONCE", "BLOCK", "GAS USED", "TRANSACTION"
+    );
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    for payload in history.latest_payloads(last) {
+        let time = chrono::DateTime::from_timestamp(payload.timestamp as i64, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+        println!(
+            "{:<8} {:<20} {:<9} {:>8} {:>10} {:>10}  {:<66?}  {}",
+            payload.epoch,
+            optional(time),
+            payload.status.to_string(),
+            optional(payload.nonce.map(|nonce| nonce.to_string())),
+            optional(payload.block_number.map(|number| number.to_string())),
+            optional(payload.gas_used.map(|gas| gas.to_string())),
+            payload.tx_hash,
+            payload.messages.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn print_costs(
+    config: Config,
+    by: Period,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> anyhow::Result<()> {
+    let path = config
+        .history_file
+        .context("There's no `history_file` in the configuration file")?;
+    let history = History::open(path)?;
+    let payloads: Vec<_> = history
+        .payloads()
+        .iter()
+        .filter(|payload| from.is_none_or(|from| payload.timestamp >= from))
+        .filter(|payload| to.is_none_or(|to| payload.timestamp < to))
+        .collect();
+    println!(
+        "{:<10} {:>12} {:>14} {:>24}",
+        "PERIOD", "TRANSACTIONS", "GAS USED", "ETH"
+    );
+    let print = |period: &str, costs: &Costs| {
+        println!(
+            "{:<10} {:>12} {:>14} {:>24}",
+            period,
+            costs.transactions,
+            costs.gas_used,
+            costs.eth_string()
+        );
+    };
+    for (period, costs) in costs_by(by, payloads.iter().copied()) {
+        print(&period.to_string(), &costs);
+    }
+    let total = Costs::of(payloads);
+    print("TOTAL", &total);
+    if total.unpriced > 0 {
+        println!(
+            "\n{} transactions have no recorded gas price, so their ETH cost is missing.",
+            total.unpriced
+        );
+    }
+    Ok(())
+}
+
+async fn send_message(
+    mut config: Config,
+    payload: Vec<u8>,
+    signing_args: SigningArgs,
+) -> anyhow::Result<()> {
+    let signer: Box<dyn Signer> = if signing_args.ledger {
+        print_payload_summary(&config, &payload).await?;
+        let signer = LedgerSigner::open(signing_args.derivation_path)?;
+        let address = signer.address().await?;
+        anyhow::ensure!(
+            address == config.owner_address,
+            "The Ledger account {address:?} isn't the owner {:?}",
+            config.owner_address
+        );
+        eprintln!("Approve the transaction on the Ledger device.");
+        Box::new(signer)
+    } else {
+        signer::from_owner_key(&config.owner_key)
+    };
+    // The message is sent on request, so dry runs don't apply.
+    config.dry_run = false;
+    let contracts = Arc::new(init_contracts(&config)?);
+    let submitter = submitter::from_config(&config, contracts, signer, None)?;
+    let payload = Payload {
+        epoch: 0,
+        messages: vec![],
+        bytes: payload,
+        network_count: 0,
+        encoding_version: CURRENT_ENCODING_VERSION,
+        subgraph_block: None,
+    };
+    let sent = submitter.send(&payload).await?;
+    if let Some(tx_hash) = sent.tx_hash() {
+        println!("Transaction hash: {tx_hash:?}");
+    }
+    match submitter.wait_for_confirmation(sent).await? {
+        SubmissionOutcome::Confirmed(submission) => println!(
+            "Confirmed in block {}.\nNonce: {}",
+            submission.receipt.block_number.unwrap_or_default(),
+            submission.nonce
+        ),
+        SubmissionOutcome::AlreadyLanded(_) => {
+            println!("An identical message already landed, so it wasn't sent again.")
+        }
+        SubmissionOutcome::NotSent => {}
+    }
+    Ok(())
+}
+
+fn reset_payload() -> anyhow::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(CURRENT_ENCODING_VERSION, vec![])?;
+    let compressed = encoder.compress(&[Message::Reset])?;
+    Ok(encoder.encode(&compressed)?)
+}
+
+/// Prints the messages in `payload` the way the Epoch Subgraph will decode them, since the Ledger
+/// device can't show them.
+async fn print_payload_summary(config: &Config, payload: &[u8]) -> anyhow::Result<()> {
+    let metrics = Metrics::new()?;
+    let subgraph_state =
+        block_oracle::query_subgraph(&config.subgraph_url, &config.bearer_token, &metrics)
+            .await
+            .context("Failed to query the Epoch Subgraph, which is needed to decode the payload")?;
+    let (network_count, encoding_version) = match subgraph_state.global_state {
+        Some(global_state) => (
+            global_state.networks.len() as u64,
+            u64::try_from(global_state.encoding_version)?,
+        ),
+        None => (0, CURRENT_ENCODING_VERSION),
+    };
+    let messages = decode_messages_with_version(payload, network_count, encoding_version)
+        .context("The Epoch Subgraph can't decode the payload")?;
+    println!("DataEdge contract: {:?}", config.data_edge_address);
+    println!("Owner: {:?}", config.owner_address);
+    println!(
+        "Payload ({} bytes, encoding version {encoding_version}):",
+        payload.len()
+    );
+    println!("{:#}", compressed_to_json(messages)?);
     Ok(())
 }
 
 async fn print_current_epoch(config: Config) -> anyhow::Result<()> {
-    let contracts = init_contracts(config)?;
+    let contracts = init_contracts(&config)?;
     let current_epoch = contracts.query_current_epoch().await?;
     println!("{current_epoch}");
     Ok(())
 }
 
-fn init_contracts(config: Config) -> anyhow::Result<Contracts<Http>> {
+async fn verify_removals(config: Config, json: &serde_json::Value) -> anyhow::Result<()> {
+    let metrics = Metrics::new()?;
+    let subgraph_state =
+        block_oracle::query_subgraph(&config.subgraph_url, &config.bearer_token, &metrics).await?;
+    // Networks are sorted by their index.
+    let networks: Vec<String> = subgraph_state
+        .global_state
+        .map(|global_state| global_state.networks)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|network| network.id.as_str().to_owned())
+        .collect();
+    json_oracle_encoder::verify_removals(json, &networks)
+        .context("Network removals don't match the Epoch Subgraph")
+}
+
+fn init_contracts(config: &Config) -> anyhow::Result<Contracts<Http>> {
     let transport = Http::new(config.protocol_chain.jrpc_url.as_str())?;
-    let protocol_chain = JrpcProviderForChain::new(config.protocol_chain.id, transport);
-    Contracts::new(
-        protocol_chain.web3,
-        config.data_edge_address,
-        config.epoch_manager_address,
-        config.transaction_monitoring_options,
-    )
+    let protocol_chain = JrpcProviderForChain::new(config.protocol_chain.id.clone(), transport);
+    Contracts::from_config(protocol_chain.web3, config)
+}
+
+async fn check_config(config_file: PathBuf, owner_key_args: OwnerKeyArgs) -> anyhow::Result<()> {
+    let config = match owner_key_args.load_config(&config_file) {
+        Ok(config) => config,
+        Err(error) => {
+            println!("FAIL  configuration file: {error:#}");
+            anyhow::bail!("The configuration file is invalid");
+        }
+    };
+    println!("PASS  configuration file: {}", config_file.display());
+    let checks = config_check::check(&config).await;
+    for check in &checks {
+        println!("{check}");
+    }
+    let failed = checks.iter().filter(|check| !check.passed()).count();
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} of {} checks failed",
+        checks.len() + 1
+    );
+    println!("All checks passed.");
+    Ok(())
+}
+
+fn reset_history(config: Config) -> anyhow::Result<()> {
+    let path = config
+        .history_file
+        .context("There's no `history_file` in the configuration file")?;
+    match History::archive(&path)? {
+        Some(archived) => println!(
+            "Moved {} to {}. The oracle will start over with an empty history.",
+            path.display(),
+            archived.display()
+        ),
+        None => println!("There's no history at {} yet.", path.display()),
+    }
+    Ok(())
 }
@@ -1,30 +1,53 @@
-use lazy_static::lazy_static;
+use crate::accounting::Costs;
+use crate::build_info::BuildInfo;
+use crate::config::PushgatewayOptions;
+use crate::status::SharedStatus;
 use prometheus::{
-    register_gauge_with_registry, register_histogram_vec_with_registry,
-    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry,
-    register_int_gauge_with_registry, Encoder, Gauge, HistogramVec, IntCounterVec, IntGauge,
-    IntGaugeVec, Registry, TextEncoder,
+    register_counter_vec_with_registry, register_counter_with_registry,
+    register_gauge_vec_with_registry, register_gauge_with_registry,
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry, Counter, CounterVec, Encoder, Gauge, GaugeVec, HistogramVec,
+    IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
 };
-use std::time::UNIX_EPOCH;
-use tracing::{debug, error, info};
+use std::time::{Instant, UNIX_EPOCH};
+use tracing::{debug, error, info, warn};
+use url::Url;
 use warp::{http::Response, Filter};
 
-lazy_static! {
-    pub static ref METRICS: Metrics = Metrics::new().expect("failed to create Metrics");
-}
-
+/// The oracle's Prometheus metrics.
+///
+/// Cloning is cheap and all clones report to the same [`Registry`].
 #[derive(Debug, Clone)]
 pub struct Metrics {
     registry: Registry,
     jrpc_request_duration_seconds: HistogramVec,
     jrpc_failure: IntCounterVec,
+    jrpc_provider_requests: IntCounterVec,
+    jrpc_rate_limit_wait_seconds: CounterVec,
     current_epoch: IntGaugeVec,
     last_sent_message: Gauge,
     latest_block_number: IntGaugeVec,
     wallet_balance: IntGauge,
+    insufficient_funds: IntGauge,
+    gas_price_too_high: IntGauge,
     subgraph_indexing_errors: IntGauge,
     subgraph_last_payload_health: IntGauge,
     subgraph_last_payload_block_number: IntGauge,
+    history_discrepancies: IntGauge,
+    subgraph_epochs_behind: IntGauge,
+    dependency_degraded: IntGaugeVec,
+    block_time_seconds: GaugeVec,
+    payload_bytes: IntGauge,
+    payload_compression_ratio: Gauge,
+    payload_estimated_gas: IntGauge,
+    gas_spent: IntCounter,
+    eth_spent: Counter,
+    epoch_cost_eth: Gauge,
+    started_at: Instant,
+    uptime_seconds: Gauge,
+    tokio_alive_tasks: IntGauge,
+    tokio_workers: IntGauge,
 }
 
 impl Metrics {
@@ -45,6 +68,20 @@ impl Metrics {
             registry
         )?;
 
+        let jrpc_provider_requests = register_int_counter_vec_with_registry!(
+            "epoch_block_oracle_jrpc_provider_requests_total",
+            "JSON RPC requests served per provider of chains with failover",
+            &["network", "provider"],
+            registry
+        )?;
+
+        let jrpc_rate_limit_wait_seconds = register_counter_vec_with_registry!(
+            "epoch_block_oracle_jrpc_rate_limit_wait_seconds_total",
+            "Time JSON RPC Requests Waited for Their Rate Limit",
+            &["network"],
+            registry
+        )?;
+
         let current_epoch = register_int_gauge_vec_with_registry!(
             "epoch_block_oracle_current_epoch",
             "Current Epoch",
@@ -71,6 +108,18 @@ impl Metrics {
             registry
         )?;
 
+        let insufficient_funds = register_int_gauge_with_registry!(
+            "epoch_block_oracle_insufficient_funds",
+            "Owner's ETH Balance Doesn't Cover the Latest Transaction",
+            registry
+        )?;
+
+        let gas_price_too_high = register_int_gauge_with_registry!(
+            "epoch_block_oracle_gas_price_too_high",
+            "Submissions Postponed Because of the Maximum Gas Price",
+            registry
+        )?;
+
         let subgraph_indexing_errors = register_int_gauge_with_registry!(
             "epoch_block_oracle_subgraph_health",
             "Epoch Subgraph Indexing Errors",
@@ -89,21 +138,148 @@ impl Metrics {
             registry
         )?;
 
+        let history_discrepancies = register_int_gauge_with_registry!(
+            "epoch_block_oracle_history_discrepancies",
+            "Epoch Subgraph Block Numbers Not Matching the Oracle's History",
+            registry
+        )?;
+
+        let subgraph_epochs_behind = register_int_gauge_with_registry!(
+            "epoch_block_oracle_subgraph_epochs_behind",
+            "Epochs the Epoch Subgraph Is Behind the Epoch Manager",
+            registry
+        )?;
+
+        let dependency_degraded = register_int_gauge_vec_with_registry!(
+            "epoch_block_oracle_dependency_degraded",
+            "Dependencies That Exhausted Their Error Budget",
+            &["dependency"],
+            registry
+        )?;
+
+        let block_time_seconds = register_gauge_vec_with_registry!(
+            "epoch_block_oracle_block_time_seconds",
+            "Learned Average Block Time of Indexed Chains",
+            &["network"],
+            registry
+        )?;
+
+        let payload_bytes = register_int_gauge_with_registry!(
+            "epoch_block_oracle_payload_bytes",
+            "Size of the Latest Block Numbers Payload",
+            registry
+        )?;
+
+        let payload_compression_ratio = register_gauge_with_registry!(
+            "epoch_block_oracle_payload_compression_ratio",
+            "Size of the Latest Block Numbers Payload Relative to Uncompressed Block Numbers",
+            registry
+        )?;
+
+        let payload_estimated_gas = register_int_gauge_with_registry!(
+            "epoch_block_oracle_payload_estimated_gas",
+            "Estimated Gas of the Latest Payload",
+            registry
+        )?;
+
+        let gas_spent = register_int_counter_with_registry!(
+            "epoch_block_oracle_gas_spent_total",
+            "Gas Used by Submitted Transactions",
+            registry
+        )?;
+
+        let eth_spent = register_counter_with_registry!(
+            "epoch_block_oracle_eth_spent_total",
+            "ETH Spent on Submitted Transactions",
+            registry
+        )?;
+
+        let epoch_cost_eth = register_gauge_with_registry!(
+            "epoch_block_oracle_epoch_cost_eth",
+            "ETH Spent on the Latest Epoch's Transactions",
+            registry
+        )?;
+
+        // Process telemetry: RSS, open file descriptors, CPU time, etc. It's only available
+        // on Linux, which is what we run on in production anyway.
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(
+            prometheus::process_collector::ProcessCollector::for_self(),
+        ))?;
+
+        let uptime_seconds = register_gauge_with_registry!(
+            "epoch_block_oracle_uptime_seconds",
+            "Block Oracle Uptime",
+            registry
+        )?;
+
+        let tokio_alive_tasks = register_int_gauge_with_registry!(
+            "epoch_block_oracle_tokio_alive_tasks",
+            "Outstanding Tokio Tasks",
+            registry
+        )?;
+
+        let tokio_workers = register_int_gauge_with_registry!(
+            "epoch_block_oracle_tokio_workers",
+            "Tokio Worker Threads",
+            registry
+        )?;
+
+        // Constant 1, the interesting bits are in the labels.
+        let build_info = register_int_gauge_vec_with_registry!(
+            "epoch_block_oracle_build_info",
+            "Block Oracle Build Information",
+            &["version", "git_commit", "encoding_version"],
+            registry
+        )?;
+        let current = BuildInfo::CURRENT;
+        build_info
+            .get_metric_with_label_values(&[
+                current.version,
+                current.git_commit,
+                &current.encoding_version.to_string(),
+            ])?
+            .set(1);
+
         Ok(Self {
             registry,
             jrpc_request_duration_seconds,
             jrpc_failure,
+            jrpc_provider_requests,
+            jrpc_rate_limit_wait_seconds,
             current_epoch,
             last_sent_message,
             latest_block_number,
             wallet_balance,
+            insufficient_funds,
+            gas_price_too_high,
             subgraph_indexing_errors,
             subgraph_last_payload_health,
             subgraph_last_payload_block_number,
+            history_discrepancies,
+            subgraph_epochs_behind,
+            dependency_degraded,
+            block_time_seconds,
+            payload_bytes,
+            payload_compression_ratio,
+            payload_estimated_gas,
+            gas_spent,
+            eth_spent,
+            epoch_cost_eth,
+            started_at: Instant::now(),
+            uptime_seconds,
+            tokio_alive_tasks,
+            tokio_workers,
         })
     }
 
+    /// The registry that all metrics are registered with.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
     pub fn encode(&self) -> Vec<u8> {
+        self.update_self_telemetry();
         let mut buffer = vec![];
         TextEncoder::new()
             .encode(&self.registry.gather(), &mut buffer)
@@ -111,6 +287,20 @@ impl Metrics {
         buffer
     }
 
+    /// Refreshes the metrics that describe the oracle process itself. These are cheap to compute,
+    /// so we do it right before every scrape.
+    fn update_self_telemetry(&self) {
+        self.uptime_seconds
+            .set(self.started_at.elapsed().as_secs_f64());
+        // There's no runtime when encoding outside of the metrics server, e.g. in tests.
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let runtime_metrics = handle.metrics();
+            self.tokio_alive_tasks
+                .set(runtime_metrics.num_alive_tasks() as i64);
+            self.tokio_workers.set(runtime_metrics.num_workers() as i64);
+        }
+    }
+
     pub fn set_current_epoch(&self, label: &str, current_epoch: i64) {
         self.current_epoch
             .get_metric_with_label_values(&[label])
@@ -142,6 +332,14 @@ impl Metrics {
         self.wallet_balance.set(balance)
     }
 
+    pub fn set_insufficient_funds(&self, insufficient: bool) {
+        self.insufficient_funds.set(insufficient as i64)
+    }
+
+    pub fn set_gas_price_too_high(&self, too_high: bool) {
+        self.gas_price_too_high.set(too_high as i64)
+    }
+
     pub fn set_subgraph_indexing_errors(&self, error: bool) {
         self.subgraph_indexing_errors.set(error as i64)
     }
@@ -156,20 +354,164 @@ impl Metrics {
         self.subgraph_last_payload_block_number.set(block_number)
     }
 
+    pub fn set_history_discrepancies(&self, count: i64) {
+        self.history_discrepancies.set(count)
+    }
+
+    pub fn set_subgraph_epochs_behind(&self, epochs: i64) {
+        self.subgraph_epochs_behind.set(epochs)
+    }
+
+    pub fn set_dependency_degraded(&self, dependency: &str, degraded: bool) {
+        self.dependency_degraded
+            .get_metric_with_label_values(&[dependency])
+            .unwrap()
+            .set(degraded as i64)
+    }
+
+    pub fn set_block_time(&self, network: &str, block_time: std::time::Duration) {
+        self.block_time_seconds
+            .get_metric_with_label_values(&[network])
+            .unwrap()
+            .set(block_time.as_secs_f64())
+    }
+
+    pub fn set_payload_estimated_gas(&self, gas: i64) {
+        self.payload_estimated_gas.set(gas)
+    }
+
+    pub fn set_compression_stats(&self, stats: &epoch_encoding::CompressionStats) {
+        self.payload_bytes.set(stats.total_bytes as i64);
+        self.payload_compression_ratio
+            .set(stats.compression_ratio());
+    }
+
+    /// Adds the cost of newly submitted transactions to the running totals.
+    pub fn record_costs(&self, costs: &Costs) {
+        self.gas_spent
+            .inc_by(u64::try_from(costs.gas_used).unwrap_or(u64::MAX));
+        self.eth_spent.inc_by(costs.eth());
+    }
+
+    pub fn set_epoch_cost(&self, costs: &Costs) {
+        self.epoch_cost_eth.set(costs.eth())
+    }
+
     pub fn track_jrpc_failure(&self, network: &str) {
         self.jrpc_failure
             .get_metric_with_label_values(&[network])
             .unwrap()
             .inc();
     }
+
+    pub fn track_jrpc_rate_limit_wait(&self, network: &str, waited: std::time::Duration) {
+        self.jrpc_rate_limit_wait_seconds
+            .get_metric_with_label_values(&[network])
+            .unwrap()
+            .inc_by(waited.as_secs_f64());
+    }
+
+    /// `provider` is the position of the provider's URL among the chain's `jrpc` URLs.
+    pub fn track_jrpc_provider_request(&self, network: &str, provider: usize) {
+        self.jrpc_provider_requests
+            .get_metric_with_label_values(&[network, &provider.to_string()])
+            .unwrap()
+            .inc();
+    }
 }
 
-pub async fn metrics_server(metrics: &'static Metrics, port: u16) {
-    info!("Starting metrics server at port {port}/metrics");
-    let endpoint = warp::path("metrics").map(|| {
+/// Regularly pushes `metrics` to a Prometheus Pushgateway, replacing whatever was previously pushed
+/// for the same job.
+pub async fn pushgateway_task(metrics: Metrics, options: PushgatewayOptions) {
+    info!(url = %options.url, job = options.job.as_str(), "Pushing metrics to the Pushgateway");
+    let Some(endpoint) = pushgateway_endpoint(&options.url, &options.job) else {
+        error!(url = %options.url, "The Pushgateway URL must be an HTTP URL. Not pushing metrics.");
+        return;
+    };
+    let client = reqwest::Client::builder()
+        .user_agent("block-oracle")
+        .build()
+        .unwrap();
+
+    let mut interval = tokio::time::interval(options.interval);
+    loop {
+        interval.tick().await;
+        let result = client
+            // `PUT` replaces all metrics of the job, while `POST` would only replace the ones with
+            // the same name.
+            .put(endpoint.clone())
+            .header("Content-Type", TextEncoder::new().format_type())
+            .body(metrics.encode())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => debug!("Pushed metrics to the Pushgateway"),
+            Err(error) => warn!(%error, "Failed to push metrics to the Pushgateway"),
+        }
+    }
+}
+
+/// `<url>/metrics/job/<job>`, with `job` percent-encoded.
+fn pushgateway_endpoint(url: &Url, job: &str) -> Option<Url> {
+    let mut endpoint = url.clone();
+    endpoint
+        .path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(["metrics", "job", job]);
+    Some(endpoint)
+}
+
+pub async fn metrics_server(metrics: Metrics, status: SharedStatus, port: u16) {
+    info!("Starting metrics server at port {port}/metrics and {port}/status");
+    let endpoint = warp::path("metrics").map(move || {
         Response::builder()
             .header("Content-Type", "text/plain")
             .body(metrics.encode())
     });
-    warp::serve(endpoint).run(([0, 0, 0, 0], port)).await;
+    let status = warp::path("status").map(move || warp::reply::json(&status.get()));
+    warp::serve(endpoint.or(status))
+        .run(([0, 0, 0, 0], port))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn self_telemetry() {
+        let metrics = Metrics::new().unwrap();
+        let encoded = String::from_utf8(metrics.encode()).unwrap();
+        assert!(encoded.contains("epoch_block_oracle_uptime_seconds"));
+        assert!(encoded.contains("epoch_block_oracle_tokio_alive_tasks"));
+        #[cfg(target_os = "linux")]
+        assert!(encoded.contains("process_resident_memory_bytes"));
+    }
+
+    #[test]
+    fn block_times() {
+        let metrics = Metrics::new().unwrap();
+        metrics.set_block_time("eip155:100", std::time::Duration::from_millis(5_200));
+        let encoded = String::from_utf8(metrics.encode()).unwrap();
+        assert!(
+            encoded.contains(r#"epoch_block_oracle_block_time_seconds{network="eip155:100"} 5.2"#)
+        );
+    }
+
+    #[test]
+    fn pushgateway_endpoints() {
+        let url = Url::parse("http://pushgateway:9091/").unwrap();
+        assert_eq!(
+            pushgateway_endpoint(&url, "block-oracle").unwrap().as_str(),
+            "http://pushgateway:9091/metrics/job/block-oracle"
+        );
+        assert_eq!(
+            pushgateway_endpoint(&url, "oracle/arbitrum one")
+                .unwrap()
+                .as_str(),
+            "http://pushgateway:9091/metrics/job/oracle%2Farbitrum%20one"
+        );
+    }
 }
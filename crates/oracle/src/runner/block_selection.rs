@@ -0,0 +1,406 @@
+//! Policies for choosing which block of an indexed chain gets published at the start of a new
+//! epoch.
+//!
+//! Historically, the Block Oracle implicitly published whatever the JSON-RPC provider reported as
+//! the chain head at the time of the query. The [`BlockSelection`] trait makes that decision
+//! explicit and configurable on a per-chain basis.
+
+use crate::runner::jrpc_utils::{get_block, BlockHeader};
+use async_trait::async_trait;
+use epoch_encoding::BlockPtr;
+use jsonrpc_core::Value;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::trace;
+use web3::{Transport, Web3};
+
+/// Upper bound on the number of exponential probing steps used by [`TimestampAligned`] while
+/// looking for a block that is older than the target timestamp. `2^32` blocks is more history than
+/// any chain we care about has.
+const MAX_TIMESTAMP_PROBES: u32 = 32;
+
+/// Picks the block that should represent an indexed chain for the next epoch.
+#[async_trait(?Send)]
+pub trait BlockSelection<T: Transport> {
+    async fn select_block(&self, web3: &Web3<T>) -> web3::Result<BlockPtr>;
+}
+
+/// Selects the chain head, as reported by `eth_getBlockByNumber("latest")`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latest;
+
+/// Selects the latest block that the provider considers final, as reported by
+/// `eth_getBlockByNumber("finalized")`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Finalized;
+
+/// Selects the block that is `depth` blocks behind the chain head.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DepthOffset {
+    pub depth: u64,
+}
+
+/// Selects the most recent block whose timestamp is not later than the current time rounded down
+/// to a multiple of `interval_in_seconds`.
+///
+/// This makes the blocks of different chains refer to the same instant in time (as long as their
+/// block timestamps are accurate), regardless of when exactly each chain was queried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampAligned {
+    pub interval_in_seconds: u64,
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> BlockSelection<T> for Latest {
+    async fn select_block(&self, web3: &Web3<T>) -> web3::Result<BlockPtr> {
+        Ok(required_block(web3, tag("latest")).await?.ptr())
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> BlockSelection<T> for Finalized {
+    async fn select_block(&self, web3: &Web3<T>) -> web3::Result<BlockPtr> {
+        Ok(required_block(web3, tag("finalized")).await?.ptr())
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> BlockSelection<T> for DepthOffset {
+    async fn select_block(&self, web3: &Web3<T>) -> web3::Result<BlockPtr> {
+        let head = required_block(web3, tag("latest")).await?;
+        let target = head.number.as_u64().saturating_sub(self.depth);
+        if target == head.number.as_u64() {
+            return Ok(head.ptr());
+        }
+        Ok(required_block(web3, number(target)).await?.ptr())
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> BlockSelection<T> for TimestampAligned {
+    async fn select_block(&self, web3: &Web3<T>) -> web3::Result<BlockPtr> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("the system clock is set after the UNIX epoch")
+            .as_secs();
+        self.select_block_at(web3, now).await
+    }
+}
+
+impl TimestampAligned {
+    /// The instant that blocks should be aligned to, given the current UNIX time.
+    fn target_timestamp(&self, now: u64) -> u64 {
+        let interval = self.interval_in_seconds.max(1);
+        now - now % interval
+    }
+
+    async fn select_block_at<T: Transport>(
+        &self,
+        web3: &Web3<T>,
+        now: u64,
+    ) -> web3::Result<BlockPtr> {
+        let target = self.target_timestamp(now);
+        let head = required_block(web3, tag("latest")).await?;
+        if head.timestamp.as_u64() <= target {
+            return Ok(head.ptr());
+        }
+
+        // Probe exponentially further into the past until we find a block that is old enough.
+        // `newer` always points to a block produced after the target timestamp, and `older` to a
+        // block produced at or before it.
+        let mut newer = head;
+        let mut older = None;
+        let mut step = 1u64;
+        for _ in 0..MAX_TIMESTAMP_PROBES {
+            let candidate_number = newer.number.as_u64().saturating_sub(step);
+            let candidate = required_block(web3, number(candidate_number)).await?;
+            if candidate.timestamp.as_u64() <= target || candidate_number == 0 {
+                older = Some(candidate);
+                break;
+            }
+            newer = candidate;
+            step = step.saturating_mul(2);
+        }
+        let mut older = older.ok_or_else(|| {
+            web3::Error::InvalidResponse(format!(
+                "Couldn't find a block with a timestamp before {target}"
+            ))
+        })?;
+
+        // Binary search between the two.
+        while newer.number.as_u64() - older.number.as_u64() > 1 {
+            let middle =
+                older.number.as_u64() + (newer.number.as_u64() - older.number.as_u64()) / 2;
+            let candidate = required_block(web3, number(middle)).await?;
+            if candidate.timestamp.as_u64() <= target {
+                older = candidate;
+            } else {
+                newer = candidate;
+            }
+        }
+        trace!(
+            target_timestamp = target,
+            number = older.number.as_u64(),
+            "Selected a timestamp-aligned block"
+        );
+        Ok(older.ptr())
+    }
+}
+
+/// All block selection policies supported by the configuration file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum BlockSelectionStrategy {
+    #[default]
+    Latest,
+    Finalized,
+    DepthOffset {
+        depth: u64,
+    },
+    TimestampAligned {
+        interval_in_seconds: u64,
+    },
+}
+
+#[async_trait(?Send)]
+impl<T: Transport> BlockSelection<T> for BlockSelectionStrategy {
+    async fn select_block(&self, web3: &Web3<T>) -> web3::Result<BlockPtr> {
+        match *self {
+            Self::Latest => Latest.select_block(web3).await,
+            Self::Finalized => Finalized.select_block(web3).await,
+            Self::DepthOffset { depth } => DepthOffset { depth }.select_block(web3).await,
+            Self::TimestampAligned {
+                interval_in_seconds,
+            } => {
+                TimestampAligned {
+                    interval_in_seconds,
+                }
+                .select_block(web3)
+                .await
+            }
+        }
+    }
+}
+
+fn tag(name: &str) -> Value {
+    Value::String(name.to_string())
+}
+
+fn number(block_number: u64) -> Value {
+    web3::helpers::serialize(&web3::types::BlockNumber::Number(block_number.into()))
+}
+
+/// Like [`get_block`], but a missing block is an error.
+async fn required_block<T: Transport>(web3: &Web3<T>, block: Value) -> web3::Result<BlockHeader> {
+    get_block(web3, block.clone())
+        .await?
+        .ok_or_else(|| web3::Error::InvalidResponse(format!("Block {block} not found")))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use futures::future::{ready, Ready};
+    use jsonrpc_core::{Call, MethodCall, Params};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use web3::RequestId;
+
+    /// A fake JSON-RPC provider for a chain with blocks `0..=head`, where block `n` has timestamp
+    /// `n * block_time` and a hash derived from `n` and `fork`. Providers with different `fork`
+    /// values disagree on all block hashes, e.g. because they serve a different network.
+    #[derive(Debug, Clone)]
+    pub(crate) struct FakeChain {
+        pub head: u64,
+        pub finalized: u64,
+        pub block_time: u64,
+        pub fork: u8,
+        /// Fails all requests, after recording them.
+        pub down: bool,
+        pub requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeChain {
+        pub fn new(head: u64) -> Self {
+            Self {
+                head,
+                finalized: head.saturating_sub(64),
+                block_time: 12,
+                fork: 0,
+                down: false,
+                requests: Default::default(),
+            }
+        }
+
+        pub fn on_fork(mut self, fork: u8) -> Self {
+            self.fork = fork;
+            self
+        }
+
+        pub fn down(mut self) -> Self {
+            self.down = true;
+            self
+        }
+
+        pub fn hash(number: u64) -> [u8; 32] {
+            Self::hash_on_fork(number, 0)
+        }
+
+        pub fn hash_on_fork(number: u64, fork: u8) -> [u8; 32] {
+            let mut hash = [0; 32];
+            hash[0] = fork;
+            hash[24..].copy_from_slice(&number.to_be_bytes());
+            hash
+        }
+
+        fn block(&self, number: u64) -> serde_json::Value {
+            if number > self.head {
+                return serde_json::Value::Null;
+            }
+            json!({
+                "hash": format!("0x{}", hex::encode(Self::hash_on_fork(number, self.fork))),
+                "number": format!("{number:#x}"),
+                "timestamp": format!("{:#x}", number * self.block_time),
+            })
+        }
+    }
+
+    impl Transport for FakeChain {
+        type Out = Ready<web3::Result<Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            let call = Call::MethodCall(MethodCall {
+                jsonrpc: None,
+                method: method.to_string(),
+                params: Params::Array(params),
+                id: jsonrpc_core::Id::Num(0),
+            });
+            (0, call)
+        }
+
+        fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+            let Call::MethodCall(call) = request else {
+                panic!("unexpected request");
+            };
+            let Params::Array(params) = call.params else {
+                panic!("unexpected params");
+            };
+            assert_eq!(call.method, "eth_getBlockByNumber");
+            let tag = params[0].as_str().unwrap().to_string();
+            self.requests.lock().unwrap().push(tag.clone());
+            if self.down {
+                return ready(Err(web3::Error::Unreachable));
+            }
+            let number = match tag.as_str() {
+                "latest" => self.head,
+                "finalized" => self.finalized,
+                hex => u64::from_str_radix(hex.trim_start_matches("0x"), 16).unwrap(),
+            };
+            ready(Ok(self.block(number)))
+        }
+    }
+
+    fn web3(chain: &FakeChain) -> Web3<FakeChain> {
+        Web3::new(chain.clone())
+    }
+
+    #[tokio::test]
+    async fn latest() {
+        let chain = FakeChain::new(1000);
+        let block = Latest.select_block(&web3(&chain)).await.unwrap();
+        assert_eq!(block, BlockPtr::new(1000, FakeChain::hash(1000)));
+    }
+
+    #[tokio::test]
+    async fn finalized() {
+        let chain = FakeChain::new(1000);
+        let block = Finalized.select_block(&web3(&chain)).await.unwrap();
+        assert_eq!(block.number, 936);
+    }
+
+    #[tokio::test]
+    async fn depth_offset() {
+        let chain = FakeChain::new(1000);
+        let block = DepthOffset { depth: 10 }
+            .select_block(&web3(&chain))
+            .await
+            .unwrap();
+        assert_eq!(block, BlockPtr::new(990, FakeChain::hash(990)));
+
+        // Never goes below genesis.
+        let block = DepthOffset { depth: 5000 }
+            .select_block(&web3(&chain))
+            .await
+            .unwrap();
+        assert_eq!(block.number, 0);
+    }
+
+    #[tokio::test]
+    async fn timestamp_aligned() {
+        // Block `n` has timestamp `12 * n`, so the head (1000) was produced at `12000`. Aligned to
+        // 3600 seconds, the target is `10800`, i.e. exactly block 900.
+        let chain = FakeChain::new(1000);
+        let strategy = TimestampAligned {
+            interval_in_seconds: 3600,
+        };
+        let block = strategy
+            .select_block_at(&web3(&chain), 12_005)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 900);
+
+        // When no boundary falls exactly on a block, pick the last one before it.
+        let strategy = TimestampAligned {
+            interval_in_seconds: 1000,
+        };
+        let block = strategy
+            .select_block_at(&web3(&chain), 11_999)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 916);
+    }
+
+    #[tokio::test]
+    async fn timestamp_aligned_head_is_old_enough() {
+        let chain = FakeChain::new(1000);
+        let strategy = TimestampAligned {
+            interval_in_seconds: 60,
+        };
+        let block = strategy
+            .select_block_at(&web3(&chain), 1_000_000)
+            .await
+            .unwrap();
+        assert_eq!(block.number, 1000);
+        assert_eq!(chain.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn deserialize_strategies() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            block_selection: BlockSelectionStrategy,
+        }
+        let parse = |s: &str| toml::from_str::<Wrapper>(s).unwrap().block_selection;
+
+        assert_eq!(
+            parse(r#"block_selection = { strategy = "latest" }"#),
+            BlockSelectionStrategy::Latest
+        );
+        assert_eq!(
+            parse(r#"block_selection = { strategy = "finalized" }"#),
+            BlockSelectionStrategy::Finalized
+        );
+        assert_eq!(
+            parse(r#"block_selection = { strategy = "depth-offset", depth = 12 }"#),
+            BlockSelectionStrategy::DepthOffset { depth: 12 }
+        );
+        assert_eq!(
+            parse(
+                r#"block_selection = { strategy = "timestamp-aligned", interval_in_seconds = 60 }"#
+            ),
+            BlockSelectionStrategy::TimestampAligned {
+                interval_in_seconds: 60
+            }
+        );
+    }
+}
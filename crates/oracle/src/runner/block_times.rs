@@ -0,0 +1,383 @@
+//! Learns the block time of every indexed chain by sampling its head in the background.
+//!
+//! Chains produce blocks at wildly different rates, from several per second to one every ten
+//! minutes, so a single cadence would either hammer the fast ones or under-sample the slow ones.
+//! Each chain is instead sampled every `blocks_per_sample` of its own block times, within
+//! configurable bounds. Block times are learned as exponentially weighted moving averages.
+//!
+//! Chains are also sampled one of their block times before the next epoch starts, so that their
+//! last sample before the epoch boundary is at most a block behind. The boundary is estimated from
+//! the Epoch Manager's next epoch block and the learned block time of the protocol chain.
+//!
+//! Chains with a `polling_interval` of their own are sampled at that interval instead. They're
+//! sampled even without adaptive polling, in which case the others are sampled at the protocol
+//! chain's polling interval.
+//!
+//! Learned block times are exposed through the `epoch_block_oracle_block_time_seconds` metric, and
+//! sampled heads through `epoch_block_oracle_latest_block_number` with the `sampler` source.
+//!
+//! Sampling doesn't affect which blocks are published: those are still selected at the start of
+//! each epoch, according to each chain's
+//! [`BlockSelectionStrategy`](crate::runner::block_selection::BlockSelectionStrategy).
+
+use super::jrpc_utils::{get_latest_block, JrpcExpBackoff};
+use crate::config::AdaptivePollingOptions;
+use crate::contracts::Contracts;
+use crate::metrics::Metrics;
+use crate::{Caip2ChainId, JrpcProviderForChain};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// How much weight the newest observation gets in the moving average of block times.
+const SMOOTHING: f64 = 0.2;
+
+/// The moving average of a single chain's block time.
+#[derive(Debug, Default)]
+struct BlockTime {
+    /// The block number that was last seen to advance, and when.
+    last_advance: Option<(u64, Instant)>,
+    seconds_per_block: Option<f64>,
+}
+
+impl BlockTime {
+    /// Records that `number` is the chain head at `at`.
+    fn observe(&mut self, number: u64, at: Instant) {
+        match self.last_advance {
+            Some((last, _)) if number == last => {}
+            Some((last, since)) if number > last => {
+                let seconds = at.duration_since(since).as_secs_f64() / (number - last) as f64;
+                self.seconds_per_block = Some(match self.seconds_per_block {
+                    Some(average) => average + SMOOTHING * (seconds - average),
+                    None => seconds,
+                });
+                self.last_advance = Some((number, at));
+            }
+            // The head went backwards, e.g. because of a reorg or a different provider behind a
+            // load balancer. Start over from there.
+            _ => self.last_advance = Some((number, at)),
+        }
+    }
+
+    fn get(&self) -> Option<Duration> {
+        self.seconds_per_block.map(Duration::from_secs_f64)
+    }
+}
+
+/// How long to wait before sampling a chain again. Chains whose block time isn't known yet are
+/// sampled as often as allowed, so that it's learned quickly.
+fn sampling_interval(block_time: Option<Duration>, options: &AdaptivePollingOptions) -> Duration {
+    let min = Duration::from_secs(options.min_sampling_interval_in_seconds);
+    let max = Duration::from_secs(options.max_sampling_interval_in_seconds).max(min);
+    match block_time {
+        Some(block_time) => (block_time * options.blocks_per_sample).clamp(min, max),
+        None => min,
+    }
+}
+
+/// How often a chain is sampled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cadence {
+    Fixed(Duration),
+    Adaptive(AdaptivePollingOptions),
+}
+
+impl Cadence {
+    /// The chain's own `polling_interval` if it has one, or else the adaptive interval if
+    /// `options` are set, or else `default_interval`.
+    fn of(
+        polling_interval: Option<Duration>,
+        options: Option<AdaptivePollingOptions>,
+        default_interval: Duration,
+    ) -> Self {
+        match (polling_interval, options) {
+            (Some(interval), _) => Cadence::Fixed(interval),
+            (None, Some(options)) => Cadence::Adaptive(options),
+            (None, None) => Cadence::Fixed(default_interval),
+        }
+    }
+}
+
+/// When a single chain is sampled.
+#[derive(Debug)]
+struct Schedule {
+    block_time: BlockTime,
+    cadence: Cadence,
+}
+
+impl Schedule {
+    fn new(cadence: Cadence) -> Self {
+        Self {
+            block_time: BlockTime::default(),
+            cadence,
+        }
+    }
+
+    /// When to sample the chain again after a sample at `now`, if the next epoch starts at
+    /// `next_epoch`. With an adaptive cadence, that's one [`sampling_interval`] later, unless one
+    /// block time before the epoch boundary comes first.
+    fn next_sample(&self, now: Instant, next_epoch: Option<Instant>) -> Instant {
+        let options = match self.cadence {
+            Cadence::Fixed(interval) => return now + interval,
+            Cadence::Adaptive(options) => options,
+        };
+        let next = now + sampling_interval(self.block_time.get(), &options);
+        let before_boundary = self
+            .block_time
+            .get()
+            .zip(next_epoch)
+            .and_then(|(block_time, next_epoch)| next_epoch.checked_sub(block_time));
+        match before_boundary {
+            Some(before_boundary) if before_boundary > now => {
+                let min = now + Duration::from_secs(options.min_sampling_interval_in_seconds);
+                before_boundary.max(min).min(next)
+            }
+            _ => next,
+        }
+    }
+}
+
+/// When the next epoch starts, if the protocol chain is at `head` at `now` and keeps producing a
+/// block every `block_time`.
+fn estimate_next_epoch(
+    head: u64,
+    next_epoch_block: u64,
+    block_time: Duration,
+    now: Instant,
+) -> Instant {
+    now + block_time.mul_f64(next_epoch_block.saturating_sub(head) as f64)
+}
+
+/// Samples every chain in `indexed_chains` forever, each at its own [`Cadence`]. With adaptive
+/// polling, the protocol chain is sampled too, to estimate when the next epoch starts. Chains with
+/// multiple providers are sampled through the first one.
+///
+/// `default_interval` is the protocol chain's polling interval.
+///
+/// JSON RPC futures aren't [`Send`], so this must run on the same task as the main loop rather than
+/// be spawned.
+pub async fn block_times_task(
+    protocol_chain: JrpcProviderForChain<JrpcExpBackoff>,
+    contracts: &Contracts<JrpcExpBackoff>,
+    indexed_chains: Vec<JrpcProviderForChain<JrpcExpBackoff>>,
+    options: Option<AdaptivePollingOptions>,
+    default_interval: Duration,
+    metrics: Metrics,
+) {
+    let mut first_provider_per_chain = BTreeMap::new();
+    for chain in indexed_chains {
+        first_provider_per_chain
+            .entry(chain.chain_id.clone())
+            .or_insert(chain);
+    }
+    info!(
+        chains = first_provider_per_chain.len(),
+        "Learning the block times of indexed chains."
+    );
+    let next_epoch = Cell::new(None);
+    let epochs = async {
+        if let Some(options) = options {
+            sample_protocol_chain(protocol_chain, contracts, options, &next_epoch).await;
+        }
+    };
+    let tasks = first_provider_per_chain.into_values().map(|chain| {
+        let cadence = Cadence::of(chain.polling_interval, options, default_interval);
+        sample_chain(chain, cadence, &next_epoch, metrics.clone())
+    });
+    futures::future::join(epochs, futures::future::join_all(tasks)).await;
+}
+
+/// Keeps `next_epoch` up to date.
+async fn sample_protocol_chain(
+    chain: JrpcProviderForChain<JrpcExpBackoff>,
+    contracts: &Contracts<JrpcExpBackoff>,
+    options: AdaptivePollingOptions,
+    next_epoch: &Cell<Option<Instant>>,
+) {
+    let mut schedule = Schedule::new(Cadence::Adaptive(options));
+    loop {
+        match get_latest_block(chain.web3.clone()).await {
+            Ok(head) => {
+                let now = Instant::now();
+                schedule.block_time.observe(head.number, now);
+                match contracts.query_next_epoch_block().await {
+                    Ok(next_epoch_block) => {
+                        let estimate = schedule.block_time.get().map(|block_time| {
+                            estimate_next_epoch(head.number, next_epoch_block, block_time, now)
+                        });
+                        next_epoch.set(estimate);
+                        debug!(
+                            head = head.number,
+                            next_epoch_block,
+                            next_epoch_in_seconds = ?estimate
+                                .map(|estimate| estimate.saturating_duration_since(now).as_secs()),
+                            "Estimated the start of the next epoch."
+                        );
+                    }
+                    Err(error) => {
+                        warn!(%error, "Failed to query when the next epoch starts.");
+                    }
+                }
+            }
+            Err(error) => {
+                warn!(chain_id = %chain.chain_id, %error, "Failed to sample the chain head.");
+            }
+        }
+        let at = schedule.next_sample(Instant::now(), None);
+        tokio::time::sleep_until(at.into()).await;
+    }
+}
+
+async fn sample_chain(
+    chain: JrpcProviderForChain<JrpcExpBackoff>,
+    cadence: Cadence,
+    next_epoch: &Cell<Option<Instant>>,
+    metrics: Metrics,
+) {
+    let chain_id: &Caip2ChainId = &chain.chain_id;
+    let mut schedule = Schedule::new(cadence);
+    loop {
+        match get_latest_block(chain.web3.clone()).await {
+            Ok(head) => {
+                schedule.block_time.observe(head.number, Instant::now());
+                metrics.set_latest_block_number(chain_id.as_str(), "sampler", head.number as i64);
+                if let Some(block_time) = schedule.block_time.get() {
+                    metrics.set_block_time(chain_id.as_str(), block_time);
+                }
+            }
+            Err(error) => {
+                warn!(%chain_id, %error, "Failed to sample the chain head.");
+            }
+        }
+        let now = Instant::now();
+        let at = schedule.next_sample(now, next_epoch.get());
+        debug!(
+            %chain_id,
+            interval_in_ms = at.duration_since(now).as_millis() as u64,
+            "Sampled the chain head."
+        );
+        tokio::time::sleep_until(at.into()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPTIONS: AdaptivePollingOptions = AdaptivePollingOptions {
+        min_sampling_interval_in_seconds: 5,
+        max_sampling_interval_in_seconds: 300,
+        blocks_per_sample: 10,
+    };
+
+    #[test]
+    fn learn_block_time() {
+        let start = Instant::now();
+        let mut block_time = BlockTime::default();
+        block_time.observe(100, start);
+        assert_eq!(block_time.get(), None);
+
+        // Samples without a new block don't count.
+        block_time.observe(100, start + Duration::from_secs(1));
+        block_time.observe(102, start + Duration::from_secs(4));
+        assert_eq!(block_time.get(), Some(Duration::from_secs(2)));
+
+        // Later samples are averaged in.
+        block_time.observe(103, start + Duration::from_secs(11));
+        assert_eq!(block_time.get(), Some(Duration::from_secs(3)));
+
+        // Going backwards keeps the average, but restarts measuring.
+        block_time.observe(50, start + Duration::from_secs(12));
+        block_time.observe(51, start + Duration::from_secs(14));
+        let seconds = block_time.get().unwrap().as_secs_f64();
+        assert!((seconds - 2.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interval_is_bounded() {
+        assert_eq!(sampling_interval(None, &OPTIONS), Duration::from_secs(5));
+        let interval = |millis| sampling_interval(Some(Duration::from_millis(millis)), &OPTIONS);
+        assert_eq!(interval(250), Duration::from_secs(5));
+        assert_eq!(interval(12_000), Duration::from_secs(120));
+        assert_eq!(interval(600_000), Duration::from_secs(300));
+    }
+
+    #[test]
+    fn samples_before_the_epoch_boundary() {
+        let now = Instant::now();
+        let mut schedule = Schedule::new(Cadence::Adaptive(OPTIONS));
+        schedule
+            .block_time
+            .observe(100, now - Duration::from_secs(12));
+        schedule.block_time.observe(101, now);
+        let seconds = |secs| now + Duration::from_secs(secs);
+
+        // Far from the boundary, or without knowing it, every 10 blocks.
+        assert_eq!(schedule.next_sample(now, None), seconds(120));
+        assert_eq!(schedule.next_sample(now, Some(seconds(600))), seconds(120));
+        // A block before the boundary, unless that's too soon.
+        assert_eq!(schedule.next_sample(now, Some(seconds(60))), seconds(48));
+        assert_eq!(schedule.next_sample(now, Some(seconds(14))), seconds(5));
+        // Within the last block, there's nothing to catch up with.
+        assert_eq!(schedule.next_sample(now, Some(seconds(6))), seconds(120));
+    }
+
+    #[test]
+    fn cadences() {
+        let default_interval = Duration::from_secs(5);
+        let minute = Some(Duration::from_secs(60));
+        assert_eq!(
+            Cadence::of(minute, Some(OPTIONS), default_interval),
+            Cadence::Fixed(Duration::from_secs(60))
+        );
+        assert_eq!(
+            Cadence::of(None, Some(OPTIONS), default_interval),
+            Cadence::Adaptive(OPTIONS)
+        );
+        assert_eq!(
+            Cadence::of(None, None, default_interval),
+            Cadence::Fixed(default_interval)
+        );
+    }
+
+    #[test]
+    fn slow_chains_are_sampled_less_often() {
+        let start = Instant::now();
+        let default_interval = Duration::from_secs(5);
+        let mut fast = Schedule::new(Cadence::of(None, None, default_interval));
+        let mut slow = Schedule::new(Cadence::of(
+            Some(Duration::from_secs(60)),
+            None,
+            default_interval,
+        ));
+        // Sample both chains over ten minutes, whenever they're due.
+        let end = start + Duration::from_secs(600);
+        let mut samples = [0, 0];
+        let mut due = [start, start];
+        while let Some((chain, at)) = due
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, at)| *at < end)
+            .min_by_key(|(_, at)| *at)
+        {
+            samples[chain] += 1;
+            let schedule = if chain == 0 { &mut fast } else { &mut slow };
+            due[chain] = schedule.next_sample(at, Some(start + Duration::from_secs(300)));
+        }
+        assert_eq!(samples, [120, 10]);
+    }
+
+    #[test]
+    fn next_epoch_estimates() {
+        let now = Instant::now();
+        let block_time = Duration::from_millis(250);
+        assert_eq!(
+            estimate_next_epoch(1_000, 1_240, block_time, now),
+            now + Duration::from_secs(60)
+        );
+        // The Epoch Manager may lag behind the head, if nobody ran the epoch yet.
+        assert_eq!(estimate_next_epoch(1_300, 1_240, block_time, now), now);
+    }
+}
@@ -0,0 +1,437 @@
+//! Collects signatures from other oracle operators before a payload is submitted, so that the
+//! DataEdge can be owned by a contract that only accepts payloads enough operators agree on.
+//!
+//! The oracle proposes every payload it's about to submit, and operators sign its [`digest`] with
+//! `personal_sign` (EIP-191), e.g. after checking that their own oracle produced the same payload.
+//! Proposals and signatures go through a small HTTP API:
+//!
+//! - `GET /proposal` returns the pending proposal as JSON, or 404 if there's none.
+//! - `POST /signatures` adds a signature to it. The body is a JSON object with the signed
+//!   `digest` and the 65-byte `signature`, both hex-encoded.
+//!
+//! Once `threshold` of the configured signers signed, the payload is sent to the co-signing
+//! contract as `crossChainEpochOracle(bytes payload, bytes[] signatures)`, with the signatures
+//! ordered by signer address.
+//!
+//! `packages/contracts/DataEdgeCoSigner.sol` is the contract this module is written against.
+
+use crate::config::CoSigningOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+use web3::ethabi::{self, Token};
+use web3::signing::{hash_message, keccak256, recover};
+use web3::types::{Address, Bytes, H256};
+
+#[derive(thiserror::Error, Debug)]
+pub enum CoSigningError {
+    #[error(
+        "Only {collected} of the {threshold} required co-signatures for the payload arrived \
+         within {timeout:?}"
+    )]
+    Timeout {
+        collected: usize,
+        threshold: usize,
+        timeout: Duration,
+    },
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("there is no proposal to sign")]
+    NoProposal,
+    #[error("the digest doesn't match the pending proposal")]
+    StaleDigest,
+    #[error("the signature is invalid")]
+    Invalid,
+    #[error("{0:?} is not a co-signer")]
+    UnknownSigner(Address),
+}
+
+/// A payload that waits for co-signatures, as served by `GET /proposal`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Proposal {
+    pub epoch: u64,
+    pub payload: Bytes,
+    pub digest: H256,
+    /// The co-signers that signed it so far.
+    pub signed_by: Vec<Address>,
+    pub threshold: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignatureRequest {
+    digest: H256,
+    signature: Bytes,
+}
+
+#[derive(Default)]
+struct State {
+    proposal: Option<(u64, Bytes, H256)>,
+    signatures: BTreeMap<Address, Bytes>,
+}
+
+/// Shared by the oracle, which proposes payloads, and the co-signing API.
+#[derive(Clone)]
+pub struct CoSigning {
+    options: CoSigningOptions,
+    state: Arc<Mutex<State>>,
+    signed: Arc<Notify>,
+}
+
+impl CoSigning {
+    pub fn new(options: CoSigningOptions) -> Self {
+        Self {
+            options,
+            state: Default::default(),
+            signed: Default::default(),
+        }
+    }
+
+    /// Proposes `payload` and waits until enough co-signers signed it. Signatures are kept if the
+    /// same payload is proposed again, e.g. after a timeout.
+    pub async fn collect(
+        &self,
+        chain_id: u64,
+        epoch: u64,
+        payload: &[u8],
+    ) -> Result<Vec<Bytes>, CoSigningError> {
+        let digest = digest(chain_id, self.options.contract_address, payload);
+        self.propose(epoch, payload, digest);
+        info!(
+            epoch,
+            ?digest,
+            threshold = self.options.threshold,
+            "Waiting for co-signatures"
+        );
+        let enough_signatures = async {
+            loop {
+                let signed = self.signed.notified();
+                if let Some(signatures) = self.signatures() {
+                    return signatures;
+                }
+                signed.await;
+            }
+        };
+        match tokio::time::timeout(self.options.timeout, enough_signatures).await {
+            Ok(signatures) => {
+                *self.state.lock().unwrap() = State::default();
+                Ok(signatures)
+            }
+            Err(_) => Err(CoSigningError::Timeout {
+                collected: self.state.lock().unwrap().signatures.len(),
+                threshold: self.options.threshold,
+                timeout: self.options.timeout,
+            }),
+        }
+    }
+
+    /// The co-signing contract, where co-signed payloads are sent.
+    pub fn contract_address(&self) -> Address {
+        self.options.contract_address
+    }
+
+    pub fn proposal(&self) -> Option<Proposal> {
+        let state = self.state.lock().unwrap();
+        let (epoch, payload, digest) = state.proposal.clone()?;
+        Some(Proposal {
+            epoch,
+            payload,
+            digest,
+            signed_by: state.signatures.keys().copied().collect(),
+            threshold: self.options.threshold,
+        })
+    }
+
+    /// Adds a co-signer's signature of `digest` to the pending proposal, and returns how many it
+    /// has now.
+    pub fn add_signature(&self, digest: H256, signature: &[u8]) -> Result<usize, SignatureError> {
+        let mut state = self.state.lock().unwrap();
+        match &state.proposal {
+            None => return Err(SignatureError::NoProposal),
+            Some((_, _, proposed)) if *proposed != digest => {
+                return Err(SignatureError::StaleDigest)
+            }
+            Some(_) => {}
+        }
+        let (signer, signature) = recover_signer(digest, signature)?;
+        if !self.options.signers.contains(&signer) {
+            return Err(SignatureError::UnknownSigner(signer));
+        }
+        info!(?signer, "Received a co-signature");
+        state.signatures.insert(signer, signature);
+        self.signed.notify_waiters();
+        Ok(state.signatures.len())
+    }
+
+    fn propose(&self, epoch: u64, payload: &[u8], digest: H256) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(&state.proposal, Some((_, _, proposed)) if *proposed == digest) {
+            state.signatures.clear();
+        }
+        state.proposal = Some((epoch, payload.to_vec().into(), digest));
+    }
+
+    /// The signatures ordered by signer address, once there are enough of them.
+    fn signatures(&self) -> Option<Vec<Bytes>> {
+        let state = self.state.lock().unwrap();
+        (state.signatures.len() >= self.options.threshold)
+            .then(|| state.signatures.values().cloned().collect())
+    }
+}
+
+/// What co-signers sign: `keccak256(abi.encode(chainId, contract, keccak256(payload)))`, so that
+/// signatures can't be replayed on other chains or contracts.
+pub fn digest(chain_id: u64, contract: Address, payload: &[u8]) -> H256 {
+    let encoded = ethabi::encode(&[
+        Token::Uint(chain_id.into()),
+        Token::Address(contract),
+        Token::FixedBytes(keccak256(payload).to_vec()),
+    ]);
+    keccak256(&encoded).into()
+}
+
+/// Recovers the address that signed `digest` as an EIP-191 message, and normalizes the signature
+/// so that its `v` is 27 or 28, like `ecrecover` expects.
+fn recover_signer(digest: H256, signature: &[u8]) -> Result<(Address, Bytes), SignatureError> {
+    let [rs @ .., v] = signature else {
+        return Err(SignatureError::Invalid);
+    };
+    if rs.len() != 64 {
+        return Err(SignatureError::Invalid);
+    }
+    let recovery_id = match v {
+        0 | 1 => *v,
+        27 | 28 => v - 27,
+        _ => return Err(SignatureError::Invalid),
+    };
+    let message = hash_message(digest.as_bytes());
+    let signer =
+        recover(message.as_bytes(), rs, recovery_id.into()).map_err(|_| SignatureError::Invalid)?;
+    let mut normalized = rs.to_vec();
+    normalized.push(recovery_id + 27);
+    Ok((signer, normalized.into()))
+}
+
+pub async fn co_signing_server(co_signing: CoSigning) {
+    let port = co_signing.options.port;
+    info!("Starting co-signing server at 0.0.0.0:{port}");
+    warp::serve(routes(co_signing))
+        .run(([0, 0, 0, 0], port))
+        .await;
+}
+
+fn routes(co_signing: CoSigning) -> impl Filter<Extract = impl Reply, Error = Rejection> + Clone {
+    let get_proposal = {
+        let co_signing = co_signing.clone();
+        warp::path("proposal")
+            .and(warp::path::end())
+            .and(warp::get())
+            .map(move || match co_signing.proposal() {
+                Some(proposal) => {
+                    warp::reply::with_status(warp::reply::json(&proposal), StatusCode::OK)
+                }
+                None => warp::reply::with_status(
+                    warp::reply::json(&"there is no proposal to sign"),
+                    StatusCode::NOT_FOUND,
+                ),
+            })
+    };
+    let post_signature = warp::path("signatures")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::body::json())
+        .map(move |request: SignatureRequest| {
+            match co_signing.add_signature(request.digest, &request.signature.0) {
+                Ok(signatures) => {
+                    warp::reply::with_status(warp::reply::json(&signatures), StatusCode::OK)
+                }
+                Err(error) => {
+                    warn!(%error, "Rejected a co-signature");
+                    let status = match error {
+                        SignatureError::NoProposal => StatusCode::NOT_FOUND,
+                        SignatureError::StaleDigest => StatusCode::CONFLICT,
+                        SignatureError::Invalid => StatusCode::BAD_REQUEST,
+                        SignatureError::UnknownSigner(_) => StatusCode::FORBIDDEN,
+                    };
+                    warp::reply::with_status(warp::reply::json(&error.to_string()), status)
+                }
+            }
+        });
+    get_proposal.or(post_signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::SecretKey;
+    use serde_json::json;
+    use std::str::FromStr;
+    use web3::signing::{Key, SecretKeyRef};
+
+    const KEYS: [&str; 3] = [
+        "4f3edf983ac636a65a842ce7c78d9aa706d3b113bce9c46f30d7d21715b23b1d",
+        "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+        "5de4111afa1a4b94908f83103eb1f1706367c2e68ca870fc3fb9a804cdab365a",
+    ];
+
+    fn key(index: usize) -> SecretKey {
+        SecretKey::from_str(KEYS[index]).unwrap()
+    }
+
+    fn sign(key: &SecretKey, digest: H256) -> Vec<u8> {
+        let signature = SecretKeyRef::new(key)
+            .sign_message(hash_message(digest.as_bytes()).as_bytes())
+            .unwrap();
+        let mut bytes = signature.r.as_bytes().to_vec();
+        bytes.extend_from_slice(signature.s.as_bytes());
+        bytes.push(signature.v as u8);
+        bytes
+    }
+
+    fn co_signing(threshold: usize) -> CoSigning {
+        let mut signers: Vec<Address> = (0..2)
+            .map(|i| SecretKeyRef::new(&key(i)).address())
+            .collect();
+        signers.sort();
+        CoSigning::new(CoSigningOptions {
+            port: 0,
+            contract_address: Address::repeat_byte(0xcc),
+            signers,
+            threshold,
+            timeout: Duration::from_millis(200),
+        })
+    }
+
+    #[test]
+    fn digests() {
+        let payload = [1, 2, 3];
+        let contract = Address::repeat_byte(0xcc);
+        let digest = digest(42161, contract, &payload);
+        assert_ne!(digest, super::digest(1, contract, &payload));
+        assert_ne!(digest, super::digest(42161, Address::zero(), &payload));
+        assert_ne!(digest, super::digest(42161, contract, &[1, 2]));
+    }
+
+    #[test]
+    fn signature_recovery() {
+        let digest = H256::repeat_byte(1);
+        let mut signature = sign(&key(0), digest);
+        let address = SecretKeyRef::new(&key(0)).address();
+
+        let (signer, normalized) = recover_signer(digest, &signature).unwrap();
+        assert_eq!(signer, address);
+        assert!(matches!(normalized.0[64], 27 | 28));
+        assert_eq!(recover_signer(digest, &normalized.0).unwrap().0, address);
+
+        assert_ne!(
+            recover_signer(H256::zero(), &signature).map(|(signer, _)| signer),
+            Ok(address)
+        );
+        assert_eq!(
+            recover_signer(digest, &signature[..64]),
+            Err(SignatureError::Invalid)
+        );
+        signature[64] = 2;
+        assert_eq!(
+            recover_signer(digest, &signature),
+            Err(SignatureError::Invalid)
+        );
+    }
+
+    #[tokio::test]
+    async fn threshold() {
+        let co_signing = co_signing(2);
+        let payload = [1, 2, 3];
+        let digest = digest(1, co_signing.options.contract_address, &payload);
+        assert_eq!(
+            co_signing.add_signature(digest, &sign(&key(0), digest)),
+            Err(SignatureError::NoProposal)
+        );
+
+        // Nobody signs in time.
+        assert!(matches!(
+            co_signing.collect(1, 7, &payload).await,
+            Err(CoSigningError::Timeout { collected: 0, .. })
+        ));
+        assert_eq!(co_signing.proposal().unwrap().digest, digest);
+
+        assert_eq!(
+            co_signing.add_signature(digest, &sign(&key(0), digest)),
+            Ok(1)
+        );
+        // Signing twice doesn't count twice.
+        assert_eq!(
+            co_signing.add_signature(digest, &sign(&key(0), digest)),
+            Ok(1)
+        );
+        assert_eq!(
+            co_signing.add_signature(H256::zero(), &sign(&key(1), H256::zero())),
+            Err(SignatureError::StaleDigest)
+        );
+        assert_eq!(
+            co_signing.add_signature(digest, &sign(&key(2), digest)),
+            Err(SignatureError::UnknownSigner(
+                SecretKeyRef::new(&key(2)).address()
+            ))
+        );
+
+        // The signature from before the timeout still counts.
+        let collect = tokio::spawn({
+            let co_signing = co_signing.clone();
+            async move { co_signing.collect(1, 7, &payload).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            co_signing.add_signature(digest, &sign(&key(1), digest)),
+            Ok(2)
+        );
+        let signatures = collect.await.unwrap().unwrap();
+
+        let signers: Vec<Address> = signatures
+            .iter()
+            .map(|signature| recover_signer(digest, &signature.0).unwrap().0)
+            .collect();
+        assert_eq!(signers, co_signing.options.signers);
+        assert_eq!(co_signing.proposal(), None);
+    }
+
+    #[tokio::test]
+    async fn api() {
+        let co_signing = co_signing(1);
+        let routes = routes(co_signing.clone());
+
+        let response = warp::test::request().path("/proposal").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let payload = [1, 2, 3];
+        let digest = digest(1, co_signing.options.contract_address, &payload);
+        co_signing.propose(7, &payload, digest);
+        let response = warp::test::request().path("/proposal").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let proposal: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(proposal["epoch"], 7);
+        assert_eq!(proposal["payload"], "0x010203");
+        assert_eq!(proposal["digest"], format!("{digest:?}"));
+
+        for (signature, status) in [
+            (sign(&key(2), digest), StatusCode::FORBIDDEN),
+            (vec![0; 65], StatusCode::BAD_REQUEST),
+            (sign(&key(0), digest), StatusCode::OK),
+        ] {
+            let response = warp::test::request()
+                .method("POST")
+                .path("/signatures")
+                .json(&json!({ "digest": digest, "signature": Bytes(signature) }))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), status);
+        }
+        assert_eq!(co_signing.proposal().unwrap().signed_by.len(), 1);
+    }
+}
@@ -0,0 +1,51 @@
+//! Reloading the configuration file while the oracle runs.
+//!
+//! On `SIGHUP`, the configuration file is parsed again and handed to the main loop, which applies
+//! it with [`Oracle::reload`](super::oracle::Oracle::reload) between polling iterations. In-flight
+//! transactions are thus never interrupted. A file that fails to parse is logged and ignored, so a
+//! typo can't take the oracle down.
+
+use crate::Config;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+
+/// Listens for `SIGHUP` and sends the reloaded configuration for every signal. Only `SIGHUP` is
+/// supported, so nothing is ever sent on other platforms.
+pub fn config_reloads(config_file: PathBuf, config: Config) -> mpsc::UnboundedReceiver<Config> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        use tracing::{error, info, warn};
+
+        let mut sighup = match signal(SignalKind::hangup()) {
+            Ok(sighup) => sighup,
+            Err(error) => {
+                warn!(%error, "Failed to listen for SIGHUP. The configuration can't be reloaded.");
+                return receiver;
+            }
+        };
+        tokio::spawn(async move {
+            let mut config = config;
+            while sighup.recv().await.is_some() {
+                info!(config_file = %config_file.display(), "SIGHUP received. Reloading the configuration.");
+                match config.reload(&config_file) {
+                    Ok(reloaded) => {
+                        super::warn_about_bare_env_vars(&reloaded);
+                        config = reloaded.clone();
+                        if sender.send(reloaded).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => error!(
+                        error = format!("{error:#}"),
+                        "Failed to reload the configuration. Keeping the current one."
+                    ),
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    let _ = (sender, config_file, config);
+    receiver
+}
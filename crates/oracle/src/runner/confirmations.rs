@@ -0,0 +1,175 @@
+//! Watches a transaction until it's buried under enough blocks of the protocol chain.
+//!
+//! Unlike `web3::confirm`, this doesn't rely on block filters, which providers behind load
+//! balancers tend to lose, and the receipt is fetched again on every poll, so a transaction that
+//! gets reorged out goes back to being pending instead of counting as confirmed.
+
+use crate::config::TransactionMonitoringOptions;
+use tokio::time::{sleep, timeout, Duration};
+use tracing::{debug, trace};
+use web3::{
+    api::Eth,
+    types::{TransactionReceipt, H256, U256, U64},
+    Transport,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfirmationError {
+    #[error("the transaction {0:?} wasn't confirmed in time")]
+    Timeout(H256),
+    #[error("the transaction {tx_hash:?} was reverted in block #{block_number}")]
+    Reverted {
+        tx_hash: H256,
+        block_number: U64,
+        gas_used: Option<U256>,
+        effective_gas_price: Option<U256>,
+    },
+    #[error("failed to poll for the transaction receipt: {0}")]
+    Provider(#[from] web3::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfirmationStatus {
+    /// Not in a block, or not anymore.
+    Pending,
+    /// In a block, with `depth` blocks on top of it.
+    Confirming {
+        depth: u64,
+    },
+    Confirmed,
+}
+
+pub struct ConfirmationTracker<T: Transport> {
+    eth: Eth<T>,
+    poll_interval: Duration,
+    confirmations: u64,
+    timeout: Duration,
+}
+
+impl<T: Transport> ConfirmationTracker<T> {
+    pub fn new(eth: Eth<T>, options: &TransactionMonitoringOptions) -> Self {
+        Self {
+            eth,
+            poll_interval: Duration::from_secs(options.poll_interval_in_seconds),
+            confirmations: options.confirmations as u64,
+            timeout: Duration::from_secs(options.confirmation_timeout_in_seconds),
+        }
+    }
+
+    /// Polls until the transaction has the configured number of confirmations, and returns its
+    /// receipt. Fails if it takes longer than the confirmation timeout, or if the transaction was
+    /// reverted.
+    pub async fn wait(&self, tx_hash: H256) -> Result<TransactionReceipt, ConfirmationError> {
+        timeout(self.timeout, self.poll(tx_hash))
+            .await
+            .map_err(|_elapsed| ConfirmationError::Timeout(tx_hash))?
+    }
+
+    async fn poll(&self, tx_hash: H256) -> Result<TransactionReceipt, ConfirmationError> {
+        loop {
+            let (receipt, latest_block) = futures::future::try_join(
+                self.eth.transaction_receipt(tx_hash),
+                self.eth.block_number(),
+            )
+            .await?;
+            match confirmation_status(receipt.as_ref(), latest_block, self.confirmations) {
+                ConfirmationStatus::Pending => {
+                    trace!(?tx_hash, "The transaction is pending");
+                }
+                ConfirmationStatus::Confirming { depth } => {
+                    trace!(
+                        ?tx_hash,
+                        depth,
+                        confirmations = self.confirmations,
+                        "Waiting for more confirmations"
+                    );
+                }
+                ConfirmationStatus::Confirmed => {
+                    // Unwrap: only receipts are confirmed.
+                    let receipt = receipt.unwrap();
+                    debug!(?tx_hash, block_number = ?receipt.block_number, "Transaction confirmed");
+                    return check_status(receipt);
+                }
+            }
+            sleep(self.poll_interval).await;
+        }
+    }
+}
+
+fn confirmation_status(
+    receipt: Option<&TransactionReceipt>,
+    latest_block: U64,
+    confirmations: u64,
+) -> ConfirmationStatus {
+    let Some(block_number) = receipt.and_then(|receipt| receipt.block_number) else {
+        return ConfirmationStatus::Pending;
+    };
+    // A lagging provider may not have seen the receipt's block yet.
+    let depth = latest_block.saturating_sub(block_number).as_u64();
+    if depth >= confirmations {
+        ConfirmationStatus::Confirmed
+    } else {
+        ConfirmationStatus::Confirming { depth }
+    }
+}
+
+fn check_status(receipt: TransactionReceipt) -> Result<TransactionReceipt, ConfirmationError> {
+    if receipt.status == Some(U64::zero()) {
+        return Err(ConfirmationError::Reverted {
+            tx_hash: receipt.transaction_hash,
+            block_number: receipt.block_number.unwrap_or_default(),
+            gas_used: receipt.gas_used,
+            effective_gas_price: receipt.effective_gas_price,
+        });
+    }
+    Ok(receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(block_number: Option<u64>, status: u64) -> TransactionReceipt {
+        TransactionReceipt {
+            block_number: block_number.map(Into::into),
+            status: Some(status.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn confirmations_count_blocks_on_top() {
+        use ConfirmationStatus::*;
+        let included = receipt(Some(100), 1);
+        assert_eq!(confirmation_status(None, 100.into(), 2), Pending);
+        assert_eq!(
+            confirmation_status(Some(&receipt(None, 1)), 100.into(), 2),
+            Pending
+        );
+        assert_eq!(
+            confirmation_status(Some(&included), 99.into(), 2),
+            Confirming { depth: 0 }
+        );
+        assert_eq!(
+            confirmation_status(Some(&included), 101.into(), 2),
+            Confirming { depth: 1 }
+        );
+        assert_eq!(
+            confirmation_status(Some(&included), 102.into(), 2),
+            Confirmed
+        );
+        assert_eq!(
+            confirmation_status(Some(&included), 100.into(), 0),
+            Confirmed
+        );
+    }
+
+    #[test]
+    fn reverted_receipts_are_errors() {
+        assert!(check_status(receipt(Some(100), 1)).is_ok());
+        assert!(matches!(
+            check_status(receipt(Some(100), 0)),
+            Err(ConfirmationError::Reverted { block_number, .. }) if block_number == 100.into()
+        ));
+    }
+}
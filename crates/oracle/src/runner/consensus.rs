@@ -0,0 +1,217 @@
+//! Agreement between multiple JSON RPC providers of the same indexed chain.
+//!
+//! Trusting a single provider means that a lagging provider (or worse, one that is connected to the
+//! wrong network) gets its blocks published verbatim. When multiple providers are configured for a
+//! chain, we instead pick the median block among the ones they select, and only publish it if a
+//! strict majority of all configured providers (not just the ones that responded) agrees on its
+//! hash. Chains with failover skip all of this, as their
+//! providers are only used one at a time.
+
+use crate::runner::block_selection::BlockSelection;
+use crate::runner::jrpc_utils::get_block;
+use crate::JrpcProviderForChain;
+use epoch_encoding::BlockPtr;
+use futures::future::join_all;
+use std::collections::HashMap;
+use tracing::{debug, warn};
+use web3::Transport;
+
+/// Selects a block for a chain that is served by one or more `providers`.
+///
+/// All providers are expected to belong to the same chain.
+pub async fn median_of_providers<T>(providers: &[JrpcProviderForChain<T>]) -> web3::Result<BlockPtr>
+where
+    T: Transport,
+{
+    match providers {
+        [] => Err(web3::Error::InvalidResponse(
+            "No JSON RPC providers available".to_string(),
+        )),
+        [provider] => provider.block_selection.select_block(&provider.web3).await,
+        // The first provider's transport fails over to the others.
+        [provider, ..] if provider.failover => {
+            provider.block_selection.select_block(&provider.web3).await
+        }
+        _ => {
+            let selected = join_all(
+                providers
+                    .iter()
+                    .map(|p| async move { p.block_selection.select_block(&p.web3).await }),
+            )
+            .await;
+            consensus(providers, selected).await
+        }
+    }
+}
+
+async fn consensus<T>(
+    providers: &[JrpcProviderForChain<T>],
+    selected: Vec<web3::Result<BlockPtr>>,
+) -> web3::Result<BlockPtr>
+where
+    T: Transport,
+{
+    let chain_id = &providers[0].chain_id;
+    let mut candidates = vec![];
+    let mut first_error = None;
+    for (provider, result) in providers.iter().zip(selected) {
+        match result {
+            Ok(block) => candidates.push((provider, block)),
+            Err(error) => {
+                warn!(
+                    chain_id = chain_id.as_str(),
+                    %error,
+                    "A JSON RPC provider failed to select a block. Excluding it from the quorum."
+                );
+                first_error.get_or_insert(error);
+            }
+        }
+    }
+    if candidates.is_empty() {
+        // Unwrap: there's at least one provider, so if there's no candidate there must be an error.
+        return Err(first_error.unwrap());
+    }
+
+    // We use the lower median, so that the block was seen by at least half of the providers.
+    let mut numbers: Vec<u64> = candidates.iter().map(|(_, block)| block.number).collect();
+    numbers.sort_unstable();
+    let median = numbers[(numbers.len() - 1) / 2];
+
+    // Now ask every provider what they think the hash of the median block is. Providers that
+    // selected the median block already told us.
+    let hashes = join_all(candidates.iter().map(|(provider, block)| async move {
+        if block.number == median {
+            return Some(block.hash);
+        }
+        let number = web3::helpers::serialize(&web3::types::BlockNumber::Number(median.into()));
+        match get_block(&provider.web3, number).await {
+            Ok(Some(header)) => Some(header.hash.0),
+            Ok(None) => None,
+            Err(error) => {
+                warn!(
+                    chain_id = chain_id.as_str(),
+                    block_number = median,
+                    %error,
+                    "A JSON RPC provider failed to return the median block."
+                );
+                None
+            }
+        }
+    }))
+    .await;
+
+    let mut votes: HashMap<[u8; 32], usize> = HashMap::new();
+    for hash in hashes.iter().flatten() {
+        *votes.entry(*hash).or_default() += 1;
+    }
+    // Providers that are down count against the quorum, so that a single provider can't reach
+    // consensus on its own when the others are unreachable.
+    let quorum = providers.len() / 2 + 1;
+    match votes.into_iter().max_by_key(|(_, count)| *count) {
+        Some((hash, count)) if count >= quorum => {
+            if count < providers.len() {
+                warn!(
+                    chain_id = chain_id.as_str(),
+                    block_number = median,
+                    agreeing = count,
+                    providers = providers.len(),
+                    "Not all JSON RPC providers agree on the median block."
+                );
+            }
+            debug!(
+                chain_id = chain_id.as_str(),
+                block_number = median,
+                hash = hex::encode(hash).as_str(),
+                "JSON RPC providers reached consensus."
+            );
+            Ok(BlockPtr::new(median, hash))
+        }
+        best => Err(web3::Error::InvalidResponse(format!(
+            "JSON RPC providers for chain {} disagree on block {}: at most {} out of {} agree on its hash",
+            chain_id,
+            median,
+            best.map(|(_, count)| count).unwrap_or(0),
+            providers.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::block_selection::tests::FakeChain;
+    use crate::Caip2ChainId;
+
+    fn providers(chains: &[FakeChain]) -> Vec<JrpcProviderForChain<FakeChain>> {
+        chains
+            .iter()
+            .map(|c| JrpcProviderForChain::new(Caip2ChainId::ethereum_mainnet(), c.clone()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn single_provider() {
+        let block = median_of_providers(&providers(&[FakeChain::new(100)]))
+            .await
+            .unwrap();
+        assert_eq!(block, BlockPtr::new(100, FakeChain::hash(100)));
+    }
+
+    #[tokio::test]
+    async fn lagging_provider_is_ignored() {
+        let chains = [FakeChain::new(100), FakeChain::new(10), FakeChain::new(101)];
+        let block = median_of_providers(&providers(&chains)).await.unwrap();
+        assert_eq!(block, BlockPtr::new(100, FakeChain::hash(100)));
+    }
+
+    #[tokio::test]
+    async fn wrong_network_provider_is_ignored() {
+        let chains = [
+            FakeChain::new(100),
+            FakeChain::new(100).on_fork(1),
+            FakeChain::new(102),
+        ];
+        let block = median_of_providers(&providers(&chains)).await.unwrap();
+        assert_eq!(block, BlockPtr::new(100, FakeChain::hash(100)));
+    }
+
+    #[tokio::test]
+    async fn no_quorum() {
+        let chains = [
+            FakeChain::new(100),
+            FakeChain::new(100).on_fork(1),
+            FakeChain::new(100).on_fork(2),
+        ];
+        assert!(median_of_providers(&providers(&chains)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unreachable_providers_count_against_the_quorum() {
+        let chains = [
+            FakeChain::new(100),
+            FakeChain::new(100).down(),
+            FakeChain::new(100).down(),
+        ];
+        assert!(median_of_providers(&providers(&chains)).await.is_err());
+
+        let chains = [
+            FakeChain::new(100),
+            FakeChain::new(100),
+            FakeChain::new(100).down(),
+        ];
+        let block = median_of_providers(&providers(&chains)).await.unwrap();
+        assert_eq!(block, BlockPtr::new(100, FakeChain::hash(100)));
+    }
+
+    #[tokio::test]
+    async fn failover_uses_the_first_provider() {
+        let chains = [FakeChain::new(100), FakeChain::new(100).on_fork(1)];
+        let providers: Vec<_> = providers(&chains)
+            .into_iter()
+            .map(|provider| provider.with_failover(true))
+            .collect();
+        let block = median_of_providers(&providers).await.unwrap();
+        assert_eq!(block, BlockPtr::new(100, FakeChain::hash(100)));
+        assert!(chains[1].requests.lock().unwrap().is_empty());
+    }
+}
@@ -3,7 +3,8 @@ use std::sync::Arc;
 use tracing::{error, warn};
 
 /// Gracefully handles interrupts and returns `true` from [`CtrlcHandler::poll_ctrlc`] if CTRL+C
-/// was detected.
+/// was detected. `SIGTERM` is treated the same way, e.g. when stopped by systemd. `SIGHUP`
+/// reloads the configuration instead, see [`config_reload`](super::config_reload).
 pub struct CtrlcHandler {
     ctrlc_received: Arc<AtomicBool>,
 }
@@ -11,24 +12,25 @@ pub struct CtrlcHandler {
 impl CtrlcHandler {
     const ORDERING: Ordering = Ordering::Relaxed;
 
+    /// Must be called within a Tokio runtime, which listens for `SIGTERM`.
     pub fn init() -> Self {
         let ctrlc = Arc::new(AtomicBool::new(false));
         let ctrlc_clone = ctrlc.clone();
-        ctrlc::set_handler(move || {
-            let pressed_already = ctrlc_clone.load(Self::ORDERING);
-            if pressed_already {
-                error!(
-                    "CTRL+C was pressed a second time. Exiting immediately."
-                );
-                std::process::exit(0);
-            } else {
-                warn!(
-                    "CTRL+C detected. Stopping... please wait. Press CTRL+C again to exit immediately."
-                );
-                ctrlc_clone.store(true, Self::ORDERING);
-            }
-        })
-        .expect("Error setting the CTRL+C handler.");
+        ctrlc::set_handler(move || Self::interrupt(&ctrlc_clone))
+            .expect("Error setting the CTRL+C handler.");
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut sigterm =
+                signal(SignalKind::terminate()).expect("Error setting the SIGTERM handler.");
+            let ctrlc = ctrlc.clone();
+            tokio::spawn(async move {
+                while sigterm.recv().await.is_some() {
+                    Self::interrupt(&ctrlc);
+                }
+            });
+        }
         Self {
             ctrlc_received: ctrlc,
         }
@@ -37,4 +39,17 @@ impl CtrlcHandler {
     pub fn poll_ctrlc(&self) -> bool {
         self.ctrlc_received.load(Self::ORDERING)
     }
+
+    fn interrupt(ctrlc: &AtomicBool) {
+        let pressed_already = ctrlc.load(Self::ORDERING);
+        if pressed_already {
+            error!("CTRL+C was pressed a second time. Exiting immediately.");
+            std::process::exit(0);
+        } else {
+            warn!(
+                "CTRL+C detected. Stopping... please wait. Press CTRL+C again to exit immediately."
+            );
+            ctrlc.store(true, Self::ORDERING);
+        }
+    }
 }
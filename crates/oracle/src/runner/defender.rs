@@ -0,0 +1,604 @@
+//! Sends DataEdge transactions through an OpenZeppelin Defender relayer, which keeps the owner key
+//! and takes care of nonces, fees and resubmissions itself. The oracle only hands over the
+//! calldata and gas limit, and then follows the transaction until it's mined. The fee options of
+//! `[transaction_monitoring]` don't apply, besides `deadline_in_seconds`, which becomes the
+//! transaction's `validUntil`.
+//!
+//! The Relay API authenticates API keys against an AWS Cognito user pool with the Secure Remote
+//! Password protocol (SRP), where the API key is the username and the API secret the password.
+//! Access tokens are reused until shortly before they expire.
+
+use crate::config::{DefenderOptions, DefenderSpeed};
+use chrono::{DateTime, SecondsFormat, Utc};
+use openssl::{
+    base64,
+    bn::{BigNum, BigNumContext, BigNumRef, MsbOption},
+    hash::MessageDigest,
+    pkey::PKey,
+    sha::sha256,
+    sign,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{debug, trace};
+use web3::types::{Address, Bytes, H256, U256};
+
+/// Access tokens are refreshed this long before they expire, so they don't expire in flight.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum DefenderError {
+    #[error("request to Defender failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Defender responded with {status}: {body}")]
+    Api {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("invalid response from Defender: {0}")]
+    InvalidResponse(String),
+    #[error("failed to authenticate with Defender: {0}")]
+    Authentication(&'static str),
+    #[error("Defender failed to send the transaction {0}")]
+    Failed(String),
+    #[error("the transaction {transaction_id} wasn't mined within {timeout:?}")]
+    Timeout {
+        transaction_id: String,
+        timeout: Duration,
+    },
+    #[error(transparent)]
+    Crypto(#[from] openssl::error::ErrorStack),
+}
+
+impl From<serde_json::Error> for DefenderError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::InvalidResponse(error.to_string())
+    }
+}
+
+/// A transaction that the relayer accepted.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayedTransaction {
+    pub transaction_id: String,
+    /// Changes whenever the relayer resubmits the transaction with higher fees.
+    pub hash: H256,
+    pub nonce: u64,
+    pub status: TransactionStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+    Pending,
+    Sent,
+    Submitted,
+    InMempool,
+    Mined,
+    Confirmed,
+    Failed,
+}
+
+pub struct DefenderRelayer {
+    options: DefenderOptions,
+    http: reqwest::Client,
+    /// The access token, and when it must be refreshed.
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl DefenderRelayer {
+    pub fn new(options: DefenderOptions) -> Self {
+        Self {
+            options,
+            http: reqwest::Client::new(),
+            token: Mutex::new(None),
+        }
+    }
+
+    /// The address of the relayer, which also proves that the API key works.
+    pub async fn address(&self) -> Result<Address, DefenderError> {
+        #[derive(Deserialize)]
+        struct Relayer {
+            address: Address,
+        }
+        let relayer: Relayer = self.request(reqwest::Method::GET, "relayer", None).await?;
+        Ok(relayer.address)
+    }
+
+    /// Hands a transaction to the relayer. If it isn't mined within `valid_for`, the relayer
+    /// replaces it.
+    pub async fn send_transaction(
+        &self,
+        to: Address,
+        data: &Bytes,
+        gas_limit: U256,
+        valid_for: Option<Duration>,
+    ) -> Result<RelayedTransaction, DefenderError> {
+        let valid_until =
+            valid_for.map(|valid_for| DateTime::<Utc>::from(SystemTime::now() + valid_for));
+        let request = transaction_request(to, data, gas_limit, self.options.speed, valid_until);
+        let transaction: RelayedTransaction = self
+            .request(reqwest::Method::POST, "txs", Some(request))
+            .await?;
+        debug!(
+            transaction_id = %transaction.transaction_id,
+            hash = ?transaction.hash,
+            nonce = transaction.nonce,
+            "Defender accepted the transaction"
+        );
+        Ok(transaction)
+    }
+
+    /// Polls the relayer until it reports `transaction` as mined, and returns its final hash.
+    pub async fn wait_until_mined(
+        &self,
+        mut transaction: RelayedTransaction,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<H256, DefenderError> {
+        let started = Instant::now();
+        loop {
+            match transaction.status {
+                TransactionStatus::Mined | TransactionStatus::Confirmed => {
+                    return Ok(transaction.hash)
+                }
+                TransactionStatus::Failed => {
+                    return Err(DefenderError::Failed(transaction.transaction_id))
+                }
+                status => trace!(
+                    transaction_id = %transaction.transaction_id,
+                    ?status,
+                    "The relayed transaction isn't mined yet"
+                ),
+            }
+            if started.elapsed() >= timeout {
+                return Err(DefenderError::Timeout {
+                    transaction_id: transaction.transaction_id,
+                    timeout,
+                });
+            }
+            tokio::time::sleep(poll_interval).await;
+            let path = format!("txs/{}", transaction.transaction_id);
+            transaction = self.request(reqwest::Method::GET, &path, None).await?;
+        }
+    }
+
+    async fn request<R: for<'de> Deserialize<'de>>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<R, DefenderError> {
+        let token = self.access_token().await?;
+        let url = self
+            .options
+            .api_url
+            .join(path)
+            .map_err(|error| DefenderError::InvalidResponse(error.to_string()))?;
+        let mut builder = self
+            .http
+            .request(method, url)
+            .header("x-api-key", &self.options.api_key)
+            .bearer_auth(token);
+        if let Some(body) = body {
+            builder = builder.json(&body);
+        }
+        parse_response(builder.send().await?).await
+    }
+
+    async fn access_token(&self) -> Result<String, DefenderError> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, refresh_at)) = &*token {
+            if Instant::now() < *refresh_at {
+                return Ok(access_token.clone());
+            }
+        }
+        let (access_token, expires_in) = self.authenticate().await?;
+        let refresh_at = Instant::now() + expires_in.saturating_sub(TOKEN_EXPIRY_MARGIN);
+        *token = Some((access_token.clone(), refresh_at));
+        Ok(access_token)
+    }
+
+    /// Logs in with the `USER_SRP_AUTH` flow of Cognito, and returns the access token along with
+    /// how long it's valid.
+    async fn authenticate(&self) -> Result<(String, Duration), DefenderError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct InitiateAuthResponse {
+            challenge_parameters: ChallengeParameters,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+        struct ChallengeParameters {
+            salt: String,
+            srp_b: String,
+            secret_block: String,
+            user_id_for_srp: String,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct RespondToAuthChallengeResponse {
+            authentication_result: AuthenticationResult,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct AuthenticationResult {
+            access_token: String,
+            expires_in: u64,
+        }
+
+        debug!("Authenticating with Defender");
+        let (region, pool_name) = self
+            .options
+            .user_pool_id
+            .split_once('_')
+            .ok_or(DefenderError::Authentication("invalid user pool ID"))?;
+        let endpoint = format!("https://cognito-idp.{region}.amazonaws.com/");
+        let srp = Srp::new()?;
+
+        let response: InitiateAuthResponse = self
+            .cognito(
+                &endpoint,
+                "InitiateAuth",
+                json!({
+                    "AuthFlow": "USER_SRP_AUTH",
+                    "ClientId": self.options.client_id,
+                    "AuthParameters": {
+                        "USERNAME": self.options.api_key,
+                        "SRP_A": srp.public_key_hex()?,
+                    },
+                }),
+            )
+            .await?;
+        let challenge = response.challenge_parameters;
+        let timestamp = cognito_timestamp(SystemTime::now().into());
+        let key = srp.session_key(
+            pool_name,
+            &challenge.user_id_for_srp,
+            &self.options.api_secret,
+            &challenge.salt,
+            &challenge.srp_b,
+        )?;
+        let message = [
+            pool_name.as_bytes(),
+            challenge.user_id_for_srp.as_bytes(),
+            &base64::decode_block(&challenge.secret_block)?,
+            timestamp.as_bytes(),
+        ]
+        .concat();
+        let signature = base64::encode_block(&hmac(&key, &message)?);
+
+        let response: RespondToAuthChallengeResponse = self
+            .cognito(
+                &endpoint,
+                "RespondToAuthChallenge",
+                json!({
+                    "ChallengeName": "PASSWORD_VERIFIER",
+                    "ClientId": self.options.client_id,
+                    "ChallengeResponses": {
+                        "USERNAME": challenge.user_id_for_srp,
+                        "PASSWORD_CLAIM_SECRET_BLOCK": challenge.secret_block,
+                        "PASSWORD_CLAIM_SIGNATURE": signature,
+                        "TIMESTAMP": timestamp,
+                    },
+                }),
+            )
+            .await?;
+        let result = response.authentication_result;
+        Ok((result.access_token, Duration::from_secs(result.expires_in)))
+    }
+
+    async fn cognito<R: for<'de> Deserialize<'de>>(
+        &self,
+        endpoint: &str,
+        action: &str,
+        body: Value,
+    ) -> Result<R, DefenderError> {
+        let response = self
+            .http
+            .post(endpoint)
+            .header("content-type", "application/x-amz-json-1.1")
+            .header(
+                "x-amz-target",
+                format!("AWSCognitoIdentityProviderService.{action}"),
+            )
+            .body(serde_json::to_vec(&body)?)
+            .send()
+            .await?;
+        parse_response(response).await
+    }
+}
+
+async fn parse_response<R: for<'de> Deserialize<'de>>(
+    response: reqwest::Response,
+) -> Result<R, DefenderError> {
+    let status = response.status();
+    let body = response.bytes().await?;
+    if !status.is_success() {
+        return Err(DefenderError::Api {
+            status,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        });
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// The body of a `POST /txs` request.
+fn transaction_request(
+    to: Address,
+    data: &Bytes,
+    gas_limit: U256,
+    speed: DefenderSpeed,
+    valid_until: Option<DateTime<Utc>>,
+) -> Value {
+    let mut request = json!({
+        "to": to,
+        "data": data,
+        // In decimal, as the API takes numbers or decimal strings.
+        "gasLimit": gas_limit.to_string(),
+        "speed": speed,
+    });
+    if let Some(valid_until) = valid_until {
+        request["validUntil"] = json!(valid_until.to_rfc3339_opts(SecondsFormat::Millis, true));
+    }
+    request
+}
+
+/// The `TIMESTAMP` of a password verifier challenge response, e.g. `Tue Jan 2 03:04:05 UTC 2024`.
+fn cognito_timestamp(now: DateTime<Utc>) -> String {
+    now.format("%a %b %-d %H:%M:%S UTC %Y").to_string()
+}
+
+/// The client side of the SRP-6a variant that Cognito uses, with the 3072-bit group of RFC 3526
+/// and SHA-256.
+struct Srp {
+    n: BigNum,
+    g: BigNum,
+    /// The multiplier parameter.
+    k: BigNum,
+    /// The ephemeral private key, and its public key.
+    a: BigNum,
+    public_key: BigNum,
+}
+
+impl Srp {
+    fn new() -> Result<Self, openssl::error::ErrorStack> {
+        let mut a = BigNum::new()?;
+        a.rand(1024, MsbOption::MAYBE_ZERO, false)?;
+        Self::with_private_key(a)
+    }
+
+    fn with_private_key(a: BigNum) -> Result<Self, openssl::error::ErrorStack> {
+        let n = BigNum::get_rfc3526_prime_3072()?;
+        let g = BigNum::from_u32(2)?;
+        let k = hash_to_number(&[pad(&n)?, pad(&g)?].concat())?;
+        let mut context = BigNumContext::new()?;
+        let mut public_key = BigNum::new()?;
+        public_key.mod_exp(&g, &a, &n, &mut context)?;
+        Ok(Self {
+            n,
+            g,
+            k,
+            a,
+            public_key,
+        })
+    }
+
+    fn public_key_hex(&self) -> Result<String, openssl::error::ErrorStack> {
+        Ok(self.public_key.to_hex_str()?.to_lowercase())
+    }
+
+    /// The key that the password claim is signed with, derived from the server's public key `b`.
+    fn session_key(
+        &self,
+        pool_name: &str,
+        user_id: &str,
+        password: &str,
+        salt: &str,
+        b: &str,
+    ) -> Result<Vec<u8>, DefenderError> {
+        let mut context = BigNumContext::new()?;
+        let b = BigNum::from_hex_str(b)?;
+        let mut b_mod_n = BigNum::new()?;
+        b_mod_n.nnmod(&b, &self.n, &mut context)?;
+        if b_mod_n.num_bits() == 0 {
+            return Err(DefenderError::Authentication("invalid SRP_B"));
+        }
+        let u = hash_to_number(&[pad(&self.public_key)?, pad(&b)?].concat())?;
+        if u.num_bits() == 0 {
+            return Err(DefenderError::Authentication("invalid SRP_B"));
+        }
+        let salt = BigNum::from_hex_str(salt)?;
+        let x = private_value(pool_name, user_id, password, &salt)?;
+
+        // S = (B - k * g^x) ^ (a + u * x) mod N
+        let mut g_x = BigNum::new()?;
+        g_x.mod_exp(&self.g, &x, &self.n, &mut context)?;
+        let mut k_g_x = BigNum::new()?;
+        k_g_x.mod_mul(&self.k, &g_x, &self.n, &mut context)?;
+        let mut base = BigNum::new()?;
+        base.mod_sub(&b_mod_n, &k_g_x, &self.n, &mut context)?;
+        let mut u_x = BigNum::new()?;
+        u_x.checked_mul(&u, &x, &mut context)?;
+        let mut exponent = BigNum::new()?;
+        exponent.checked_add(&self.a, &u_x)?;
+        let mut s = BigNum::new()?;
+        s.mod_exp(&base, &exponent, &self.n, &mut context)?;
+
+        Ok(hkdf(&pad(&s)?, &pad(&u)?)?)
+    }
+}
+
+/// `x`, which is derived from the password.
+fn private_value(
+    pool_name: &str,
+    user_id: &str,
+    password: &str,
+    salt: &BigNumRef,
+) -> Result<BigNum, openssl::error::ErrorStack> {
+    let identity = sha256(format!("{pool_name}{user_id}:{password}").as_bytes());
+    hash_to_number(&[pad(salt)?, identity.to_vec()].concat())
+}
+
+/// The big-endian bytes of `number`, with a leading zero byte if the highest bit is set, as if it
+/// was signed.
+fn pad(number: &BigNumRef) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let bytes = number.to_vec();
+    Ok(match bytes.first() {
+        Some(byte) if *byte < 0x80 => bytes,
+        _ => [&[0], &bytes[..]].concat(),
+    })
+}
+
+fn hash_to_number(data: &[u8]) -> Result<BigNum, openssl::error::ErrorStack> {
+    BigNum::from_slice(&sha256(data))
+}
+
+/// HKDF with SHA-256, for the 16 bytes of key that Cognito derives.
+fn hkdf(ikm: &[u8], salt: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let prk = hmac(salt, ikm)?;
+    let mut okm = hmac(&prk, b"Caldera Derived Key\x01")?;
+    okm.truncate(16);
+    Ok(okm)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let key = PKey::hmac(key)?;
+    let mut signer = sign::Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    signer.sign_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The client must derive the same key as a server that only knows the password verifier.
+    #[test]
+    fn srp_session_key() {
+        let mut context = BigNumContext::new().unwrap();
+        let random = |bits| {
+            let mut number = BigNum::new().unwrap();
+            number.rand(bits, MsbOption::MAYBE_ZERO, false).unwrap();
+            number
+        };
+        let (pool_name, user_id, password) = ("iLmIggsiy", "user-id", "secret");
+        let salt = random(128);
+        let client = Srp::new().unwrap();
+
+        // The server stores the verifier `v = g^x`, and its public key is `B = k * v + g^b`.
+        let x = private_value(pool_name, user_id, password, &salt).unwrap();
+        let mut v = BigNum::new().unwrap();
+        v.mod_exp(&client.g, &x, &client.n, &mut context).unwrap();
+        let b = random(1024);
+        let mut g_b = BigNum::new().unwrap();
+        g_b.mod_exp(&client.g, &b, &client.n, &mut context).unwrap();
+        let mut k_v = BigNum::new().unwrap();
+        k_v.mod_mul(&client.k, &v, &client.n, &mut context).unwrap();
+        let mut server_public_key = BigNum::new().unwrap();
+        server_public_key
+            .mod_add(&k_v, &g_b, &client.n, &mut context)
+            .unwrap();
+
+        // The server computes `S = (A * v^u)^b`.
+        let u = hash_to_number(
+            &[
+                pad(&client.public_key).unwrap(),
+                pad(&server_public_key).unwrap(),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        let mut v_u = BigNum::new().unwrap();
+        v_u.mod_exp(&v, &u, &client.n, &mut context).unwrap();
+        let mut base = BigNum::new().unwrap();
+        base.mod_mul(&client.public_key, &v_u, &client.n, &mut context)
+            .unwrap();
+        let mut s = BigNum::new().unwrap();
+        s.mod_exp(&base, &b, &client.n, &mut context).unwrap();
+        let server_key = hkdf(&pad(&s).unwrap(), &pad(&u).unwrap()).unwrap();
+
+        let client_key = client
+            .session_key(
+                pool_name,
+                user_id,
+                password,
+                &salt.to_hex_str().unwrap(),
+                &server_public_key.to_hex_str().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(client_key, server_key);
+        assert_eq!(client_key.len(), 16);
+
+        assert!(client
+            .session_key(
+                pool_name,
+                user_id,
+                password,
+                &salt.to_hex_str().unwrap(),
+                "00"
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn padding() {
+        let pad_hex = |hex: &str| hex::encode(pad(&BigNum::from_hex_str(hex).unwrap()).unwrap());
+        assert_eq!(pad_hex("0"), "00");
+        assert_eq!(pad_hex("7f"), "7f");
+        assert_eq!(pad_hex("80"), "0080");
+        assert_eq!(pad_hex("102"), "0102");
+    }
+
+    #[test]
+    fn timestamps() {
+        let now = "2024-01-02T03:04:05Z".parse().unwrap();
+        assert_eq!(cognito_timestamp(now), "Tue Jan 2 03:04:05 UTC 2024");
+        let now = "2024-11-23T13:14:15Z".parse().unwrap();
+        assert_eq!(cognito_timestamp(now), "Sat Nov 23 13:14:15 UTC 2024");
+    }
+
+    #[test]
+    fn requests() {
+        let to = Address::repeat_byte(0x11);
+        let data = Bytes(vec![1, 2, 3]);
+        assert_eq!(
+            transaction_request(to, &data, 100_000.into(), DefenderSpeed::Fast, None),
+            json!({
+                "to": "0x1111111111111111111111111111111111111111",
+                "data": "0x010203",
+                "gasLimit": "100000",
+                "speed": "fast",
+            })
+        );
+        let valid_until = "2024-01-02T03:04:05Z".parse().unwrap();
+        let request = transaction_request(
+            to,
+            &data,
+            100_000.into(),
+            DefenderSpeed::SafeLow,
+            Some(valid_until),
+        );
+        assert_eq!(request["speed"], "safeLow");
+        assert_eq!(request["validUntil"], "2024-01-02T03:04:05.000Z");
+    }
+
+    #[test]
+    fn responses() {
+        let transaction: RelayedTransaction = serde_json::from_value(json!({
+            "transactionId": "5fcb8a6d-8d3e-403a-b33d-ade27ce0f85a",
+            "hash": format!("0x{}", "ab".repeat(32)),
+            "nonce": 7,
+            "status": "inmempool",
+            "speed": "fast",
+            "gasLimit": 100000,
+            "chainId": 42161,
+        }))
+        .unwrap();
+        assert_eq!(transaction.nonce, 7);
+        assert_eq!(transaction.hash, H256::repeat_byte(0xab));
+        assert_eq!(transaction.status, TransactionStatus::InMempool);
+    }
+}
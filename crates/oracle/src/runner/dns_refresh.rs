@@ -0,0 +1,138 @@
+//! Rebuilds JSON RPC transports when the IP addresses behind their hostnames change.
+//!
+//! HTTP transports keep pooled connections to whatever address the hostname resolved to when
+//! they were opened. Providers that fail over by updating DNS records would otherwise only be
+//! picked up once enough requests to the dead address failed.
+//!
+//! Hostnames are only re-resolved after a request to them failed in the transport, so that
+//! healthy endpoints don't cause any DNS traffic.
+
+use super::endpoints::Endpoints;
+use std::collections::{BTreeSet, HashMap};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Resolved addresses by `host:port`.
+type KnownAddresses = HashMap<String, BTreeSet<IpAddr>>;
+
+pub async fn dns_refresh_task(endpoints: Endpoints, interval: Duration) {
+    info!(
+        interval_in_seconds = interval.as_secs(),
+        "Watching JSON RPC endpoints for DNS changes."
+    );
+    let mut known = KnownAddresses::new();
+    loop {
+        refresh(&endpoints, &mut known).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Re-resolves the hostnames of endpoints that failed since the last refresh, and returns how many
+/// transports were rebuilt. Transports are rebuilt if their addresses changed, or if they were never
+/// resolved before, since their pooled connections might be to an outdated address.
+async fn refresh(endpoints: &Endpoints, known: &mut KnownAddresses) -> usize {
+    let mut current = KnownAddresses::new();
+    let mut rebuilt = 0;
+    for (endpoint, transport) in endpoints.all() {
+        if !transport.take_transport_failure() {
+            continue;
+        }
+        let Some(url) = transport.url() else {
+            continue;
+        };
+        let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+            continue;
+        };
+        if host.parse::<IpAddr>().is_ok() {
+            continue;
+        }
+
+        let host_and_port = format!("{host}:{port}");
+        if !current.contains_key(&host_and_port) {
+            match tokio::net::lookup_host(&host_and_port).await {
+                Ok(addresses) => {
+                    let addresses = addresses.map(|address| address.ip()).collect();
+                    current.insert(host_and_port.clone(), addresses);
+                }
+                Err(error) => {
+                    // Keep the transport as it is, it might still work.
+                    warn!(%endpoint, %error, "Failed to resolve the JSON RPC endpoint's hostname.");
+                    continue;
+                }
+            }
+        }
+
+        let addresses = &current[&host_and_port];
+        let previous = known.get(&host_and_port);
+        if previous == Some(addresses) {
+            debug!(%endpoint, "The JSON RPC endpoint's addresses didn't change.");
+            continue;
+        }
+        info!(
+            %endpoint,
+            ?previous,
+            current = ?addresses,
+            "The JSON RPC endpoint failed and its addresses may have changed. Reconnecting."
+        );
+        match transport.rotate_url(&url) {
+            Ok(()) => rebuilt += 1,
+            Err(error) => warn!(%endpoint, %error, "Failed to rebuild the transport."),
+        }
+    }
+    known.extend(current);
+    rebuilt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jrpc_utils::JrpcExpBackoff;
+    use crate::metrics::Metrics;
+    use crate::Caip2ChainId;
+    use web3::Web3;
+
+    /// Doesn't retry failed requests.
+    fn transport(url: &str) -> JrpcExpBackoff {
+        JrpcExpBackoff::http(
+            url.parse().unwrap(),
+            Caip2ChainId::ethereum_mainnet(),
+            Duration::ZERO,
+            Metrics::new().unwrap(),
+        )
+    }
+
+    /// Nothing listens on port 1.
+    async fn fail_request(transport: &JrpcExpBackoff) {
+        assert!(Web3::new(transport.clone()).eth().chain_id().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rebuild_after_failures() {
+        let hostname = transport("http://localhost:1");
+        let ip = transport("http://127.0.0.1:1");
+        let endpoints = Endpoints::new(hostname.clone(), vec![ip.clone()]);
+        let mut known = KnownAddresses::new();
+
+        // Nothing is resolved without failures.
+        assert_eq!(refresh(&endpoints, &mut known).await, 0);
+        assert!(known.is_empty());
+
+        // IP literals are skipped, and hostnames are rebuilt after their first failure.
+        fail_request(&hostname).await;
+        fail_request(&ip).await;
+        assert_eq!(refresh(&endpoints, &mut known).await, 1);
+        assert_eq!(known.len(), 1);
+        assert_eq!(refresh(&endpoints, &mut known).await, 0);
+
+        // Later failures only rebuild the transport if the addresses changed.
+        fail_request(&hostname).await;
+        assert_eq!(refresh(&endpoints, &mut known).await, 0);
+        known.insert(
+            "localhost:1".to_string(),
+            BTreeSet::from(["10.0.0.1".parse().unwrap()]),
+        );
+        fail_request(&hostname).await;
+        assert_eq!(refresh(&endpoints, &mut known).await, 1);
+    }
+}
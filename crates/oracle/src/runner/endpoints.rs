@@ -0,0 +1,154 @@
+//! Runtime rotation of JSON RPC endpoints.
+//!
+//! Managed providers usually embed API keys in their URLs, and those keys get rotated on a
+//! schedule. Rotating an endpoint swaps the transport underneath the oracle's providers, so
+//! everything that is keyed by chain (error budgets, metrics, etc.) carries over.
+
+use super::jrpc_utils::JrpcExpBackoff;
+use crate::Caip2ChainId;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+use url::Url;
+
+/// Identifies a JSON RPC endpoint from the configuration file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EndpointId {
+    ProtocolChain,
+    /// `index` is the position of the URL among the chain's `jrpc` URLs.
+    IndexedChain {
+        chain_id: Caip2ChainId,
+        index: usize,
+    },
+}
+
+impl Display for EndpointId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EndpointId::ProtocolChain => f.write_str("protocol chain"),
+            EndpointId::IndexedChain { chain_id, index } => write!(f, "{chain_id} #{index}"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RotationError {
+    #[error("There's no endpoint for the {0}")]
+    UnknownEndpoint(EndpointId),
+    #[error("Failed to create a transport for the new URL: {0}")]
+    Transport(#[from] web3::Error),
+}
+
+/// A handle to the JSON RPC transports of an [`Oracle`](super::oracle::Oracle).
+///
+/// Clones share the indexed chains, which change when the configuration is reloaded.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    protocol_chain: JrpcExpBackoff,
+    indexed_chains: Arc<RwLock<Vec<JrpcExpBackoff>>>,
+}
+
+impl Endpoints {
+    pub fn new(protocol_chain: JrpcExpBackoff, indexed_chains: Vec<JrpcExpBackoff>) -> Self {
+        Self {
+            protocol_chain,
+            indexed_chains: Arc::new(RwLock::new(indexed_chains)),
+        }
+    }
+
+    /// All endpoints along with their transports.
+    pub fn all(&self) -> Vec<(EndpointId, JrpcExpBackoff)> {
+        let mut endpoints = vec![(EndpointId::ProtocolChain, self.protocol_chain.clone())];
+        let mut per_chain: HashMap<Caip2ChainId, usize> = HashMap::new();
+        // Unwrap: the lock is never held across panics.
+        for transport in self.indexed_chains.read().unwrap().iter() {
+            let index = per_chain.entry(transport.network().clone()).or_default();
+            endpoints.push((
+                EndpointId::IndexedChain {
+                    chain_id: transport.network().clone(),
+                    index: *index,
+                },
+                transport.clone(),
+            ));
+            *index += 1;
+        }
+        endpoints
+    }
+
+    /// Replaces the transports of all indexed chains, for this and all cloned handles.
+    pub(crate) fn set_indexed_chains(&self, indexed_chains: Vec<JrpcExpBackoff>) {
+        *self.indexed_chains.write().unwrap() = indexed_chains;
+    }
+
+    /// Points an endpoint to a new URL. The URL isn't logged, as it likely contains secrets.
+    pub fn rotate(&self, endpoint: &EndpointId, jrpc_url: &Url) -> Result<(), RotationError> {
+        let transport = match endpoint {
+            EndpointId::ProtocolChain => Some(self.protocol_chain.clone()),
+            EndpointId::IndexedChain { chain_id, index } => self
+                .indexed_chains
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|transport| transport.network() == chain_id)
+                .nth(*index)
+                .cloned(),
+        };
+        let transport =
+            transport.ok_or_else(|| RotationError::UnknownEndpoint(endpoint.clone()))?;
+        transport.rotate_url(jrpc_url)?;
+        warn!(%endpoint, "The JSON RPC endpoint was rotated.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    fn transport(chain_id: &str) -> JrpcExpBackoff {
+        JrpcExpBackoff::http(
+            "http://127.0.0.1:8545".parse().unwrap(),
+            Caip2ChainId::from_str(chain_id).unwrap(),
+            Duration::from_secs(1),
+            Metrics::new().unwrap(),
+        )
+    }
+
+    #[test]
+    fn rotate() {
+        let endpoints = Endpoints::new(
+            transport("eip155:42161"),
+            vec![
+                transport("eip155:1"),
+                transport("eip155:100"),
+                transport("eip155:1"),
+            ],
+        );
+        let url: Url = "http://127.0.0.1:8546".parse().unwrap();
+        let mainnet = |index| EndpointId::IndexedChain {
+            chain_id: Caip2ChainId::ethereum_mainnet(),
+            index,
+        };
+
+        endpoints.rotate(&EndpointId::ProtocolChain, &url).unwrap();
+        endpoints.rotate(&mainnet(1), &url).unwrap();
+        assert!(matches!(
+            endpoints.rotate(&mainnet(2), &url),
+            Err(RotationError::UnknownEndpoint(_))
+        ));
+
+        // Clones see the indexed chains of a reloaded configuration.
+        endpoints
+            .clone()
+            .set_indexed_chains(vec![transport("eip155:1")]);
+        assert_eq!(endpoints.all().len(), 2);
+        assert!(matches!(
+            endpoints.rotate(&mainnet(1), &url),
+            Err(RotationError::UnknownEndpoint(_))
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+//! Per-dependency error budgets.
+//!
+//! A single failure of a JSON RPC provider or of the subgraph is business as usual, but a
+//! dependency that keeps failing deserves different treatment than one that hiccups once a day.
+//! Every dependency gets a budget of failures within its most recent outcomes. Once the budget is
+//! exhausted, the dependency is considered *degraded* until enough of its calls succeed again:
+//!
+//! - Degraded indexed chains are left out of payloads, so they keep their previous block number.
+//! - Errors caused by a degraded protocol chain or subgraph cool down for longer.
+//! - The `epoch_block_oracle_dependency_degraded` metric is set, so that it can be alerted on.
+
+use crate::config::ErrorBudgetOptions;
+use crate::metrics::Metrics;
+use crate::Caip2ChainId;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use tracing::{error, info};
+
+/// Something the oracle depends on, whose failures are tracked separately.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Dependency {
+    ProtocolChain,
+    Subgraph,
+    IndexedChain(Caip2ChainId),
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dependency::ProtocolChain => f.write_str("protocol-chain"),
+            Dependency::Subgraph => f.write_str("subgraph"),
+            Dependency::IndexedChain(chain_id) => write!(f, "{chain_id}"),
+        }
+    }
+}
+
+pub struct ErrorBudget {
+    options: ErrorBudgetOptions,
+    dependencies: HashMap<Dependency, Outcomes>,
+    metrics: Metrics,
+}
+
+#[derive(Default)]
+struct Outcomes {
+    /// The most recent outcomes, `true` being a failure.
+    recent: VecDeque<bool>,
+    degraded: bool,
+}
+
+impl Outcomes {
+    fn failures(&self) -> usize {
+        self.recent.iter().filter(|failed| **failed).count()
+    }
+}
+
+impl ErrorBudget {
+    pub fn new(options: ErrorBudgetOptions, metrics: Metrics) -> Self {
+        Self {
+            options,
+            dependencies: HashMap::new(),
+            metrics,
+        }
+    }
+
+    pub fn record_success(&mut self, dependency: Dependency) {
+        self.record(dependency, false)
+    }
+
+    pub fn record_failure(&mut self, dependency: Dependency) {
+        self.record(dependency, true)
+    }
+
+    pub fn is_degraded(&self, dependency: &Dependency) -> bool {
+        self.dependencies
+            .get(dependency)
+            .is_some_and(|outcomes| outcomes.degraded)
+    }
+
+    /// How much longer than usual to cool down after an error caused by `dependency`.
+    pub fn cooldown_multiplier(&self, dependency: &Dependency) -> u32 {
+        if self.is_degraded(dependency) {
+            self.options.degraded_cooldown_multiplier
+        } else {
+            1
+        }
+    }
+
+    fn record(&mut self, dependency: Dependency, failed: bool) {
+        let outcomes = self.dependencies.entry(dependency.clone()).or_default();
+        outcomes.recent.push_back(failed);
+        while outcomes.recent.len() > self.options.window {
+            outcomes.recent.pop_front();
+        }
+
+        let failures = outcomes.failures();
+        let exhausted = failures > self.options.max_failures;
+        if exhausted == outcomes.degraded {
+            return;
+        }
+        outcomes.degraded = exhausted;
+        if exhausted {
+            error!(
+                %dependency,
+                failures,
+                window = self.options.window,
+                "Dependency exhausted its error budget. Entering degraded mode."
+            );
+        } else {
+            info!(%dependency, "Dependency recovered from degraded mode.");
+        }
+        self.metrics
+            .set_dependency_degraded(&dependency.to_string(), exhausted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_budget() -> ErrorBudget {
+        let options = ErrorBudgetOptions {
+            window: 4,
+            max_failures: 2,
+            degraded_cooldown_multiplier: 5,
+        };
+        ErrorBudget::new(options, Metrics::new().unwrap())
+    }
+
+    #[test]
+    fn exhaust_and_recover() {
+        let mut budget = error_budget();
+        let chain = Dependency::IndexedChain(Caip2ChainId::ethereum_mainnet());
+
+        budget.record_failure(chain.clone());
+        budget.record_success(chain.clone());
+        budget.record_failure(chain.clone());
+        assert!(!budget.is_degraded(&chain));
+        budget.record_failure(chain.clone());
+        assert!(budget.is_degraded(&chain));
+        assert_eq!(budget.cooldown_multiplier(&chain), 5);
+
+        // Other dependencies have their own budget.
+        assert!(!budget.is_degraded(&Dependency::Subgraph));
+        assert_eq!(budget.cooldown_multiplier(&Dependency::Subgraph), 1);
+
+        // Recovering takes as many successes as needed for failures to leave the window.
+        budget.record_failure(chain.clone());
+        budget.record_success(chain.clone());
+        assert!(budget.is_degraded(&chain));
+        budget.record_success(chain.clone());
+        assert!(!budget.is_degraded(&chain));
+    }
+}
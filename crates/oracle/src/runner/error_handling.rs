@@ -1,4 +1,8 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::ops::ControlFlow;
+use std::str::FromStr;
 
 /// Tells us whether an application error is recoverable or not. If recoverable,
 /// the cooldown should last for the given amount of polling cycles before
@@ -10,7 +14,221 @@ pub type OracleControlFlow = ControlFlow<(), u32>;
 /// Sends instructions to control the Oracle main loop flow.
 ///
 /// When continuing, the implementor can opt to define a different duration for
-/// the sleep cycle.
+/// the sleep cycle. [`MainLoopFlow::instruction`] is only the default behavior,
+/// which operators can override per [`ErrorClass`] with [`ErrorPolicies`].
 pub trait MainLoopFlow {
     fn instruction(&self) -> OracleControlFlow;
+
+    fn class(&self) -> ErrorClass;
+}
+
+/// Groups of errors that operators can configure an [`ErrorPolicy`] for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde_with::DeserializeFromStr)]
+pub enum ErrorClass {
+    ProtocolChainJrpc,
+    IndexedChainJrpc,
+    SubgraphTransport,
+    SubgraphFailed,
+    SubgraphBadData,
+    CantSubmitTx,
+    SigningFailed,
+    NonceTooLow,
+    ReplacementUnderpriced,
+    TransactionReverted,
+    SimulationFailed,
+    InsufficientFunds,
+    GasPriceTooHigh,
+    PayloadTooLarge,
+    EpochManagerCallFailed,
+    EpochManagerBehindSubgraph,
+    SubgraphNotFresh,
+    SubgraphNotInitialized,
+    SubgraphDiscontinuity,
+    MissedEpochs,
+    Encoding,
+    CoSigningTimeout,
+    ChainIdMismatch,
+}
+
+impl ErrorClass {
+    const NAMES: [(ErrorClass, &'static str); 23] = [
+        (ErrorClass::ProtocolChainJrpc, "protocol-chain-jrpc"),
+        (ErrorClass::IndexedChainJrpc, "indexed-chain-jrpc"),
+        (ErrorClass::SubgraphTransport, "subgraph-transport"),
+        (ErrorClass::SubgraphFailed, "subgraph-failed"),
+        (ErrorClass::SubgraphBadData, "subgraph-bad-data"),
+        (ErrorClass::CantSubmitTx, "cant-submit-tx"),
+        (ErrorClass::SigningFailed, "signing-failed"),
+        (ErrorClass::NonceTooLow, "nonce-too-low"),
+        (
+            ErrorClass::ReplacementUnderpriced,
+            "replacement-underpriced",
+        ),
+        (ErrorClass::TransactionReverted, "transaction-reverted"),
+        (ErrorClass::SimulationFailed, "simulation-failed"),
+        (ErrorClass::InsufficientFunds, "insufficient-funds"),
+        (ErrorClass::GasPriceTooHigh, "gas-price-too-high"),
+        (ErrorClass::PayloadTooLarge, "payload-too-large"),
+        (
+            ErrorClass::EpochManagerCallFailed,
+            "epoch-manager-call-failed",
+        ),
+        (
+            ErrorClass::EpochManagerBehindSubgraph,
+            "epoch-manager-behind-subgraph",
+        ),
+        (ErrorClass::SubgraphNotFresh, "subgraph-not-fresh"),
+        (
+            ErrorClass::SubgraphNotInitialized,
+            "subgraph-not-initialized",
+        ),
+        (ErrorClass::SubgraphDiscontinuity, "subgraph-discontinuity"),
+        (ErrorClass::MissedEpochs, "missed-epochs"),
+        (ErrorClass::Encoding, "encoding"),
+        (ErrorClass::CoSigningTimeout, "co-signing-timeout"),
+        (ErrorClass::ChainIdMismatch, "chain-id-mismatch"),
+    ];
+}
+
+impl Display for ErrorClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (_, name) = Self::NAMES
+            .iter()
+            .find(|(class, _)| class == self)
+            .expect("every error class has a name");
+        f.write_str(name)
+    }
+}
+
+// Not derived with serde, because `toml` can't deserialize enums as table keys.
+impl FromStr for ErrorClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(class, _)| *class)
+            .ok_or_else(|| format!("unknown error class '{s}'"))
+    }
+}
+
+/// What the main loop does after an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum ErrorAction {
+    /// Cool down for `cooldown` polling intervals, then try again.
+    Retry {
+        #[serde(default)]
+        cooldown: u32,
+    },
+    /// Carry on as if the iteration had succeeded, i.e. wait for the usual polling interval.
+    Skip,
+    /// Stop polling until the oracle is resumed through the admin API, or restarted.
+    Pause,
+    /// Exit the oracle.
+    Exit,
+}
+
+impl From<OracleControlFlow> for ErrorAction {
+    fn from(instruction: OracleControlFlow) -> Self {
+        match instruction {
+            ControlFlow::Continue(cooldown) => ErrorAction::Retry { cooldown },
+            ControlFlow::Break(()) => ErrorAction::Exit,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct ErrorPolicy {
+    #[serde(flatten)]
+    pub action: ErrorAction,
+    /// Exit once an error of this class happens in this many polling iterations in a row.
+    #[serde(default)]
+    pub max_consecutive: Option<u32>,
+}
+
+/// Decides what the main loop does after each error, based on the configured [`ErrorPolicy`] of
+/// its class, falling back to [`MainLoopFlow::instruction`].
+#[derive(Debug, Default)]
+pub struct ErrorPolicies {
+    policies: HashMap<ErrorClass, ErrorPolicy>,
+    /// The class of the errors of the latest polling iterations, and how many there were.
+    consecutive: Option<(ErrorClass, u32)>,
+}
+
+impl ErrorPolicies {
+    pub fn new(policies: HashMap<ErrorClass, ErrorPolicy>) -> Self {
+        Self {
+            policies,
+            consecutive: None,
+        }
+    }
+
+    /// Must be called after every successful polling iteration.
+    pub fn record_success(&mut self) {
+        self.consecutive = None;
+    }
+
+    pub fn action(&mut self, err: &impl MainLoopFlow) -> ErrorAction {
+        let class = err.class();
+        let count = match self.consecutive {
+            Some((previous, count)) if previous == class => count + 1,
+            _ => 1,
+        };
+        self.consecutive = Some((class, count));
+
+        match self.policies.get(&class) {
+            Some(policy) if policy.max_consecutive.is_some_and(|max| count >= max) => {
+                ErrorAction::Exit
+            }
+            Some(policy) => policy.action,
+            None => err.instruction().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NotFresh;
+
+    impl MainLoopFlow for NotFresh {
+        fn instruction(&self) -> OracleControlFlow {
+            OracleControlFlow::Continue(2)
+        }
+
+        fn class(&self) -> ErrorClass {
+            ErrorClass::SubgraphNotFresh
+        }
+    }
+
+    #[test]
+    fn defaults_and_max_consecutive() {
+        let mut policies = ErrorPolicies::default();
+        assert_eq!(
+            policies.action(&NotFresh),
+            ErrorAction::Retry { cooldown: 2 }
+        );
+
+        let policy = ErrorPolicy {
+            action: ErrorAction::Skip,
+            max_consecutive: Some(3),
+        };
+        let mut policies = ErrorPolicies::new([(ErrorClass::SubgraphNotFresh, policy)].into());
+        assert_eq!(policies.action(&NotFresh), ErrorAction::Skip);
+        assert_eq!(policies.action(&NotFresh), ErrorAction::Skip);
+        policies.record_success();
+        assert_eq!(policies.action(&NotFresh), ErrorAction::Skip);
+        assert_eq!(policies.action(&NotFresh), ErrorAction::Skip);
+        assert_eq!(policies.action(&NotFresh), ErrorAction::Exit);
+    }
+
+    #[test]
+    fn class_names_roundtrip() {
+        for (class, _) in ErrorClass::NAMES {
+            assert_eq!(class.to_string().parse::<ErrorClass>(), Ok(class));
+        }
+    }
 }
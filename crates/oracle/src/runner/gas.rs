@@ -0,0 +1,374 @@
+//! Prices the oracle's transactions on the protocol chain.
+//!
+//! On chains with EIP-1559, fees are derived from `eth_feeHistory`: the priority fee is the median
+//! of what recent blocks paid at a configurable percentile, and the max fee leaves room for the
+//! base fee to grow before the transaction is mined. Chains without EIP-1559, and providers
+//! without `eth_feeHistory`, fall back to legacy pricing with `eth_gasPrice`, unless the
+//! [`TransactionType`] is fixed in the configuration. See [`GasOptions`].
+
+use crate::config::{GasOptions, TransactionMonitoringOptions, TransactionType};
+use tracing::{debug, warn};
+use web3::{
+    api::Eth,
+    types::{BlockNumber, FeeHistory, TransactionParameters, U256, U64},
+    Transport,
+};
+
+const EIP1559_TRANSACTION_TYPE: u64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasPrice {
+    Legacy {
+        gas_price: U256,
+    },
+    Eip1559 {
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+}
+
+impl GasPrice {
+    /// Sets the fields of `transaction` that determine its price, and its type accordingly.
+    pub fn apply(self, transaction: &mut TransactionParameters) {
+        match self {
+            GasPrice::Legacy { gas_price } => {
+                transaction.transaction_type = None;
+                transaction.gas_price = Some(gas_price);
+                transaction.max_fee_per_gas = None;
+                transaction.max_priority_fee_per_gas = None;
+            }
+            GasPrice::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                transaction.transaction_type = Some(U64::from(EIP1559_TRANSACTION_TYPE));
+                transaction.gas_price = None;
+                transaction.max_fee_per_gas = Some(max_fee_per_gas);
+                transaction.max_priority_fee_per_gas = Some(max_priority_fee_per_gas);
+            }
+        }
+    }
+
+    /// The most that a unit of gas may cost.
+    pub fn max_fee_per_gas(self) -> U256 {
+        match self {
+            GasPrice::Legacy { gas_price } => gas_price,
+            GasPrice::Eip1559 {
+                max_fee_per_gas, ..
+            } => max_fee_per_gas,
+        }
+    }
+
+    /// Raises every fee by `percentual_increase`, e.g. to replace a transaction that got stuck.
+    /// Nodes only accept replacements that pay more on all fees. Neither the gas price nor the
+    /// max fee per gas are raised above `cap`, so the result may be the same as before.
+    pub fn bump(self, percentual_increase: u32, cap: Option<U256>) -> Self {
+        let cap = cap.unwrap_or(U256::MAX);
+        match self {
+            GasPrice::Legacy { gas_price } => GasPrice::Legacy {
+                gas_price: bump_gas(gas_price, percentual_increase).min(cap.max(gas_price)),
+            },
+            GasPrice::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let max_fee_per_gas =
+                    bump_gas(max_fee_per_gas, percentual_increase).min(cap.max(max_fee_per_gas));
+                GasPrice::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas: bump_gas(
+                        max_priority_fee_per_gas,
+                        percentual_increase,
+                    )
+                    .min(max_fee_per_gas),
+                }
+            }
+        }
+    }
+}
+
+pub struct GasEstimator<T: Transport> {
+    eth: Eth<T>,
+    options: GasOptions,
+    max_fee_per_gas: Option<U256>,
+    max_priority_fee_per_gas: Option<U256>,
+}
+
+impl<T: Transport> GasEstimator<T> {
+    pub fn new(eth: Eth<T>, options: &TransactionMonitoringOptions) -> Self {
+        Self {
+            eth,
+            options: options.gas,
+            max_fee_per_gas: options.max_fee_per_gas.map(Into::into),
+            max_priority_fee_per_gas: options.max_priority_fee_per_gas.map(Into::into),
+        }
+    }
+
+    /// Estimates fees for the configured [`TransactionType`], which also determines the type of
+    /// the transaction, see [`GasPrice::apply`].
+    pub async fn estimate(&self) -> Result<GasPrice, web3::Error> {
+        match self.options.transaction_type {
+            TransactionType::Legacy => self.estimate_legacy().await,
+            TransactionType::Eip1559 => self.estimate_eip1559().await?.ok_or_else(|| {
+                web3::Error::InvalidResponse(
+                    "the protocol chain doesn't report base fees, so it doesn't support EIP-1559 \
+                     transactions"
+                        .to_string(),
+                )
+            }),
+            TransactionType::Auto => {
+                match self.estimate_eip1559().await {
+                    Ok(Some(gas_price)) => return Ok(gas_price),
+                    Ok(None) => debug!(
+                        "The protocol chain doesn't support EIP-1559. Using legacy gas pricing."
+                    ),
+                    Err(error) => {
+                        warn!(%error, "Failed to fetch the fee history. Using legacy gas pricing.")
+                    }
+                }
+                self.estimate_legacy().await
+            }
+        }
+    }
+
+    /// Returns `None` if the protocol chain doesn't support EIP-1559.
+    async fn estimate_eip1559(&self) -> Result<Option<GasPrice>, web3::Error> {
+        let fee_history = self
+            .eth
+            .fee_history(
+                self.options.fee_history_blocks.into(),
+                BlockNumber::Latest,
+                Some(vec![self.options.priority_fee_percentile]),
+            )
+            .await?;
+        let Some(gas_price) = eip1559_fees(&fee_history, &self.options) else {
+            return Ok(None);
+        };
+        let gas_price = self.with_overrides(gas_price);
+        debug!(?gas_price, "Estimated EIP-1559 fees");
+        Ok(Some(gas_price))
+    }
+
+    async fn estimate_legacy(&self) -> Result<GasPrice, web3::Error> {
+        let gas_price = percentage(
+            self.eth.gas_price().await?,
+            self.options.legacy_gas_price_percentage,
+        );
+        debug!(%gas_price, "Estimated legacy gas price");
+        Ok(GasPrice::Legacy { gas_price })
+    }
+
+    /// Fees that are fixed in the configuration take precedence over the estimates. Nodes reject
+    /// priority fees above the max fee, so the priority fee is clamped to it.
+    fn with_overrides(&self, gas_price: GasPrice) -> GasPrice {
+        match gas_price {
+            GasPrice::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => {
+                let max_fee_per_gas = self.max_fee_per_gas.unwrap_or(max_fee_per_gas);
+                GasPrice::Eip1559 {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas: self
+                        .max_priority_fee_per_gas
+                        .unwrap_or(max_priority_fee_per_gas)
+                        .min(max_fee_per_gas),
+                }
+            }
+            legacy => legacy,
+        }
+    }
+}
+
+/// Returns `None` if the fee history has no base fee, i.e. the chain doesn't support EIP-1559.
+fn eip1559_fees(fee_history: &FeeHistory, options: &GasOptions) -> Option<GasPrice> {
+    // The last base fee is the one of the next block.
+    let base_fee = *fee_history.base_fee_per_gas.last()?;
+    if base_fee.is_zero() {
+        return None;
+    }
+
+    let mut rewards: Vec<U256> = fee_history
+        .reward
+        .iter()
+        .flatten()
+        .filter_map(|rewards| rewards.first().copied())
+        .collect();
+    rewards.sort();
+    let median_reward = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+
+    let max_priority_fee_per_gas = percentage(median_reward, options.priority_fee_percentage);
+    let max_fee_per_gas =
+        percentage(base_fee, options.base_fee_percentage).saturating_add(max_priority_fee_per_gas);
+    Some(GasPrice::Eip1559 {
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+    })
+}
+
+fn percentage(value: U256, percentage: u32) -> U256 {
+    value.saturating_mul(percentage.into()) / 100
+}
+
+fn bump_gas(gas_price: U256, percentual_increase: u32) -> U256 {
+    percentage(gas_price, 100 + percentual_increase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fee_history(base_fees: &[u64], rewards: &[u64]) -> FeeHistory {
+        FeeHistory {
+            oldest_block: BlockNumber::Number(100.into()),
+            base_fee_per_gas: base_fees.iter().copied().map(Into::into).collect(),
+            gas_used_ratio: vec![0.5; rewards.len()],
+            reward: Some(
+                rewards
+                    .iter()
+                    .map(|reward| vec![(*reward).into()])
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_bump_gas() {
+        let input: U256 = 1000.into();
+        let percentual_increase: u32 = 25;
+        let expected: U256 = 1250.into();
+        let output = bump_gas(input, percentual_increase);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn fees_from_fee_history() {
+        let options = GasOptions::default();
+        assert_eq!(
+            eip1559_fees(&fee_history(&[90, 100, 110], &[3, 1, 2]), &options),
+            Some(GasPrice::Eip1559 {
+                // Twice the next base fee, plus the median reward.
+                max_fee_per_gas: 222.into(),
+                max_priority_fee_per_gas: 2.into(),
+            })
+        );
+
+        let options = GasOptions {
+            base_fee_percentage: 150,
+            priority_fee_percentage: 200,
+            ..options
+        };
+        assert_eq!(
+            eip1559_fees(&fee_history(&[100], &[]), &options),
+            Some(GasPrice::Eip1559 {
+                max_fee_per_gas: 150.into(),
+                max_priority_fee_per_gas: 0.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn chains_without_eip1559() {
+        let options = GasOptions::default();
+        assert_eq!(eip1559_fees(&fee_history(&[0, 0], &[1]), &options), None);
+        assert_eq!(eip1559_fees(&fee_history(&[], &[]), &options), None);
+    }
+
+    #[test]
+    fn bump_and_apply() {
+        let gas_price = GasPrice::Eip1559 {
+            max_fee_per_gas: 200.into(),
+            max_priority_fee_per_gas: 10.into(),
+        };
+        let bumped = gas_price.bump(50, None);
+        assert_eq!(
+            bumped,
+            GasPrice::Eip1559 {
+                max_fee_per_gas: 300.into(),
+                max_priority_fee_per_gas: 15.into(),
+            }
+        );
+
+        let mut transaction = TransactionParameters::default();
+        bumped.apply(&mut transaction);
+        assert_eq!(transaction.transaction_type, Some(2.into()));
+        assert_eq!(transaction.gas_price, None);
+        assert_eq!(transaction.max_fee_per_gas, Some(300.into()));
+
+        GasPrice::Legacy {
+            gas_price: 100.into(),
+        }
+        .apply(&mut transaction);
+        assert_eq!(transaction.transaction_type, None);
+        assert_eq!(transaction.gas_price, Some(100.into()));
+        assert_eq!(transaction.max_fee_per_gas, None);
+    }
+
+    #[test]
+    fn bumps_are_capped() {
+        let legacy = GasPrice::Legacy {
+            gas_price: 100.into(),
+        };
+        assert_eq!(
+            legacy.bump(50, Some(120.into())),
+            GasPrice::Legacy {
+                gas_price: 120.into()
+            }
+        );
+        // Fees that are above the cap already are left alone.
+        assert_eq!(legacy.bump(50, Some(80.into())), legacy);
+
+        let eip1559 = GasPrice::Eip1559 {
+            max_fee_per_gas: 100.into(),
+            max_priority_fee_per_gas: 90.into(),
+        };
+        assert_eq!(
+            eip1559.bump(50, Some(110.into())),
+            GasPrice::Eip1559 {
+                max_fee_per_gas: 110.into(),
+                max_priority_fee_per_gas: 110.into(),
+            }
+        );
+    }
+
+    #[test]
+    fn overrides_keep_the_priority_fee_below_the_max_fee() {
+        let estimator = |max_fee_per_gas, max_priority_fee_per_gas| {
+            let transport = web3::transports::Http::new("http://localhost:1").unwrap();
+            GasEstimator::new(
+                web3::Web3::new(transport).eth(),
+                &TransactionMonitoringOptions {
+                    max_fee_per_gas,
+                    max_priority_fee_per_gas,
+                    ..Default::default()
+                },
+            )
+        };
+        let estimate = GasPrice::Eip1559 {
+            max_fee_per_gas: 200.into(),
+            max_priority_fee_per_gas: 50.into(),
+        };
+
+        assert_eq!(
+            estimator(Some(40), None).with_overrides(estimate),
+            GasPrice::Eip1559 {
+                max_fee_per_gas: 40.into(),
+                max_priority_fee_per_gas: 40.into(),
+            }
+        );
+        assert_eq!(
+            estimator(None, Some(300)).with_overrides(estimate),
+            GasPrice::Eip1559 {
+                max_fee_per_gas: 200.into(),
+                max_priority_fee_per_gas: 200.into(),
+            }
+        );
+        assert_eq!(
+            estimator(Some(100), Some(10)).with_overrides(estimate),
+            GasPrice::Eip1559 {
+                max_fee_per_gas: 100.into(),
+                max_priority_fee_per_gas: 10.into(),
+            }
+        );
+    }
+}
@@ -1,51 +1,146 @@
-use super::METRICS;
+use super::consensus::median_of_providers;
+use super::rate_limit::RateLimiter;
+use crate::metrics::Metrics;
 use crate::{Caip2ChainId, JrpcProviderForChain};
 use backoff::{future::retry, ExponentialBackoff, ExponentialBackoffBuilder};
 use epoch_encoding::BlockPtr;
-use futures::{future::try_join_all, TryFutureExt};
-use futures::{
-    stream::{FuturesUnordered, StreamExt},
-    FutureExt,
-};
+use futures::future::try_join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
 use jsonrpc_core::{Call, Value};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::num::NonZeroU32;
 use std::ops::RangeInclusive;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use std::{future::Future, pin::Pin, time::Duration};
-use tracing::trace;
+use tracing::{trace, warn};
 use url::Url;
 use web3::helpers::CallFuture;
-use web3::types::{BlockNumber, Transaction, H160, H256, U64};
+use web3::types::{
+    BlockNumber, FilterBuilder, Log, Trace, TraceFilterBuilder, Transaction, H160, H256, U256, U64,
+};
 use web3::{transports::Http, RequestId, Transport, Web3};
 
 /// A wrapper around [`web3::Transport`] that retries JSON-RPC calls on failure.
+///
+/// Clones share the underlying transport, which can be replaced at runtime with
+/// [`JrpcExpBackoff::rotate`], e.g. when the API key in a provider's URL is rotated.
+///
+/// With [`JrpcExpBackoff::set_failover`], every attempt goes through the first healthy one of
+/// this and the fallback transports, in order.
 #[derive(Debug, Clone)]
 pub struct JrpcExpBackoff<T = Http> {
-    inner: T,
+    inner: Arc<RwLock<T>>,
+    /// Only known for HTTP transports.
+    url: Arc<RwLock<Option<Url>>>,
     strategy: ExponentialBackoff,
     network: Arc<Caip2ChainId>,
+    metrics: Metrics,
+    /// Applies to every attempt, retries included.
+    rate_limiter: Arc<RateLimiter>,
+    failover: Arc<RwLock<Option<Failover<T>>>>,
+    /// Until when failover skips this transport, after it failed.
+    unhealthy_until: Arc<Mutex<Option<Instant>>>,
+    /// Whether a request failed in the transport since the last
+    /// [`JrpcExpBackoff::take_transport_failure`], as opposed to a JSON RPC error.
+    transport_failed: Arc<AtomicBool>,
+}
+
+/// The transports to fail over to, after the one that has them.
+#[derive(Debug, Clone)]
+pub struct Failover<T> {
+    pub fallbacks: Vec<JrpcExpBackoff<T>>,
+    /// How long a transport is skipped after it failed.
+    pub cooldown: Duration,
 }
 
 impl<T> JrpcExpBackoff<T> {
-    pub fn new(transport: T, network: Caip2ChainId, max_wait: Duration) -> Self {
+    pub fn new(transport: T, network: Caip2ChainId, max_wait: Duration, metrics: Metrics) -> Self {
         let strategy = ExponentialBackoffBuilder::new()
             .with_max_elapsed_time(Some(max_wait))
             .build();
 
         Self {
-            inner: transport,
+            inner: Arc::new(RwLock::new(transport)),
+            url: Arc::new(RwLock::new(None)),
             strategy,
             network: Arc::new(network),
+            metrics,
+            rate_limiter: Arc::default(),
+            failover: Arc::default(),
+            unhealthy_until: Arc::default(),
+            transport_failed: Arc::default(),
         }
     }
+
+    /// Sends at most `requests_per_second` through this and all cloned instances.
+    pub fn with_rate_limit(self, requests_per_second: Option<NonZeroU32>) -> Self {
+        self.set_rate_limit(requests_per_second);
+        self
+    }
+
+    /// Like [`JrpcExpBackoff::with_rate_limit`], but for transports that are already shared.
+    pub fn set_rate_limit(&self, requests_per_second: Option<NonZeroU32>) {
+        self.rate_limiter.set_limit(requests_per_second);
+    }
+
+    /// Sets the transports to fail over to, for this and all cloned instances. Fallbacks shouldn't
+    /// have fallbacks of their own.
+    pub fn set_failover(&self, failover: Option<Failover<T>>) {
+        *self.failover.write().unwrap() = failover;
+    }
+
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until
+            .lock()
+            .unwrap()
+            .is_none_or(|until| until <= now)
+    }
+
+    /// Replaces the underlying transport of this and all cloned instances. Calls that are being
+    /// retried switch over with their next attempt.
+    pub fn rotate(&self, transport: T) {
+        // Unwrap: the lock is never held across panics.
+        *self.inner.write().unwrap() = transport;
+    }
+
+    pub fn network(&self) -> &Caip2ChainId {
+        &self.network
+    }
+
+    /// Whether a request failed to reach the provider since the last call, e.g. because the
+    /// connection was refused.
+    pub fn take_transport_failure(&self) -> bool {
+        self.transport_failed.swap(false, Ordering::Relaxed)
+    }
 }
 
 impl JrpcExpBackoff {
-    pub fn http(jrpc_url: Url, network: Caip2ChainId, max_wait: Duration) -> Self {
+    pub fn http(
+        jrpc_url: Url,
+        network: Caip2ChainId,
+        max_wait: Duration,
+        metrics: Metrics,
+    ) -> Self {
         // Unwrap: URLs were already parsed and are valid.
         let client = Http::new(jrpc_url.as_str()).expect("failed to create HTTP transport");
-        Self::new(client, network, max_wait)
+        let transport = Self::new(client, network, max_wait, metrics);
+        *transport.url.write().unwrap() = Some(jrpc_url);
+        transport
+    }
+
+    /// Replaces the underlying transport with a new one for `jrpc_url`, which also drops all
+    /// pooled connections.
+    pub fn rotate_url(&self, jrpc_url: &Url) -> web3::Result<()> {
+        self.rotate(Http::new(jrpc_url.as_str())?);
+        *self.url.write().unwrap() = Some(jrpc_url.clone());
+        Ok(())
+    }
+
+    pub fn url(&self) -> Option<Url> {
+        self.url.read().unwrap().clone()
     }
 }
 
@@ -56,29 +151,139 @@ where
     type Out = Pin<Box<dyn Future<Output = web3::error::Result<Value>>>>;
 
     fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
-        self.inner.prepare(method, params)
+        self.inner.read().unwrap().prepare(method, params)
     }
 
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
         let strategy = self.strategy.clone();
-        let transport = self.inner.clone();
-        let network = self.network.clone();
+        let transport = self.clone();
         let op = move || {
-            trace!(?id, ?request, %network, "Sending JRPC call");
-            let start = std::time::Instant::now();
-            let network2 = network.clone();
-            let result = transport.send(id, request.clone()).map_err(move |e| {
-                METRICS.track_jrpc_failure(network2.as_str());
-                backoff::Error::transient(e)
-            });
-            let elapsed = start.elapsed();
-            METRICS.set_jrpc_request_duration(network.as_str(), elapsed);
-            result
+            let transport = transport.clone();
+            let request = request.clone();
+            async move {
+                transport
+                    .attempt(id, request)
+                    .await
+                    .map_err(backoff::Error::transient)
+            }
         };
         Box::pin(retry(strategy, op))
     }
 }
 
+impl<T> JrpcExpBackoff<T>
+where
+    T: web3::Transport + 'static,
+{
+    /// A single attempt at `request`. With failover, transports that failed within their cooldown
+    /// are skipped, unless all of them did.
+    async fn attempt(&self, id: RequestId, request: Call) -> web3::Result<Value> {
+        let failover = self.failover.read().unwrap().clone();
+        let Some(Failover {
+            fallbacks,
+            cooldown,
+        }) = failover
+        else {
+            return self.send_once(id, request).await;
+        };
+        let now = Instant::now();
+        let transports: Vec<(usize, JrpcExpBackoff<T>)> = std::iter::once(self.clone())
+            .chain(fallbacks)
+            .enumerate()
+            .collect();
+        let healthy: Vec<_> = transports
+            .iter()
+            .filter(|(_, transport)| transport.is_healthy(now))
+            .cloned()
+            .collect();
+        let candidates = if healthy.is_empty() {
+            transports
+        } else {
+            healthy
+        };
+        let mut last_error = None;
+        for (provider, transport) in candidates {
+            match transport.send_once(id, request.clone()).await {
+                Ok(value) => {
+                    trace!(%self.network, provider, "JRPC call served");
+                    self.metrics
+                        .track_jrpc_provider_request(self.network.as_str(), provider);
+                    return Ok(value);
+                }
+                Err(error) => {
+                    warn!(
+                        %self.network,
+                        provider,
+                        %error,
+                        cooldown_in_seconds = cooldown.as_secs(),
+                        "A JSON RPC provider failed. Failing over to the next one."
+                    );
+                    *transport.unhealthy_until.lock().unwrap() = Some(Instant::now() + cooldown);
+                    last_error = Some(error);
+                }
+            }
+        }
+        // Unwrap: there's always at least this transport.
+        Err(last_error.unwrap())
+    }
+
+    async fn send_once(&self, id: RequestId, request: Call) -> web3::Result<Value> {
+        let waited = self.rate_limiter.acquire().await;
+        if !waited.is_zero() {
+            self.metrics
+                .track_jrpc_rate_limit_wait(self.network.as_str(), waited);
+        }
+        let transport = self.inner.read().unwrap().clone();
+        trace!(?id, ?request, %self.network, "Sending JRPC call");
+        let start = Instant::now();
+        let result = transport.send(id, request).await;
+        self.metrics
+            .set_jrpc_request_duration(self.network.as_str(), start.elapsed());
+        if let Err(error) = &result {
+            self.metrics.track_jrpc_failure(self.network.as_str());
+            if matches!(error, web3::Error::Transport(_)) {
+                self.transport_failed.store(true, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+}
+
+/// A subset of [`web3::types::Block`] that is compatible with Celo. Should only be used for mined
+/// blocks, i.e. with a block number. You can add fields as necessary, but you MUST make sure
+/// they're widely available across all supported indexed chains.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BlockHeader {
+    pub hash: H256,
+    pub number: U64,
+    pub timestamp: U256,
+}
+
+impl BlockHeader {
+    pub fn ptr(&self) -> BlockPtr {
+        BlockPtr {
+            number: self.number.as_u64(),
+            hash: self.hash.0,
+        }
+    }
+}
+
+/// Fetches a block header via `eth_getBlockByNumber`. `block` is either a block tag (e.g.
+/// `"latest"`, `"finalized"`) or a hex-encoded block number.
+pub async fn get_block<T>(web3: &Web3<T>, block: Value) -> web3::Result<Option<BlockHeader>>
+where
+    T: Transport,
+{
+    // We don't care about the transactions in the block.
+    let include_txs = web3::helpers::serialize(&false);
+
+    let fut = web3
+        .transport()
+        .execute("eth_getBlockByNumber", vec![block, include_txs]);
+    let call_fut: CallFuture<Option<BlockHeader>, T::Out> = CallFuture::new(fut);
+    call_fut.await
+}
+
 /// It'd pretty weird if a provider wouldn't respond with a valid latest block; in that case,
 /// we'll raise a [`web3::Error`].
 ///
@@ -87,43 +292,41 @@ pub async fn get_latest_block<T>(web3: Web3<T>) -> web3::Result<BlockPtr>
 where
     T: Transport,
 {
-    /// A subset of [`web3::types::Block`] that is compatible with Celo. Should only be used for mined
-    /// blocks, i.e. with a block number. You can add fields as necessary, but you MUST make sure
-    /// they're widely available across all supported indexed chains.
-    #[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
-    struct LatestBlockCeloCompatible {
-        hash: H256,
-        number: U64,
-    }
-
     // We're asking for the chain head.
     let block_num = web3::helpers::serialize(&BlockNumber::Latest);
-    // We don't care about the transactions in the block.
-    let include_txs = web3::helpers::serialize(&false);
-
-    let fut = web3
-        .transport()
-        .execute("eth_getBlockByNumber", vec![block_num, include_txs]);
-    let call_fut: CallFuture<LatestBlockCeloCompatible, T::Out> = CallFuture::new(fut);
-    let latest_block = call_fut.await?;
-
-    Ok(BlockPtr {
-        number: latest_block.number.as_u64(),
-        hash: latest_block.hash.0,
-    })
+    let latest_block = get_block(&web3, block_num)
+        .await?
+        .ok_or_else(|| web3::Error::InvalidResponse("Latest block not found".to_string()))?;
+    Ok(latest_block.ptr())
 }
 
-/// Fetches the latest available block number and hash from all `chains`.
+/// Fetches the block number and hash from all `chains`, as chosen by each chain's
+/// [`BlockSelection`](super::block_selection::BlockSelection) policy.
+///
+/// `chains` may contain multiple providers for the same chain, in which case they must reach
+/// consensus on the selected block. See [`median_of_providers`].
 pub async fn get_latest_blocks<T>(
     chains: &[JrpcProviderForChain<T>],
 ) -> BTreeMap<Caip2ChainId, web3::Result<BlockPtr>>
 where
     T: web3::Transport,
 {
-    let mut tasks = chains
-        .iter()
-        .cloned()
-        .map(|chain| get_latest_block(chain.web3).map(|block| (chain.chain_id, block)))
+    let mut providers_per_chain: BTreeMap<Caip2ChainId, Vec<JrpcProviderForChain<T>>> =
+        BTreeMap::new();
+    for chain in chains {
+        providers_per_chain
+            .entry(chain.chain_id.clone())
+            .or_default()
+            .push(chain.clone());
+    }
+    let chain_count = providers_per_chain.len();
+
+    let mut tasks = providers_per_chain
+        .into_iter()
+        .map(|(chain_id, providers)| async move {
+            let block = median_of_providers(&providers).await;
+            (chain_id, block)
+        })
         .collect::<FuturesUnordered<_>>();
 
     let mut block_ptr_per_chain = BTreeMap::new();
@@ -131,7 +334,7 @@ where
         block_ptr_per_chain.insert(chain_id, jrpc_call_result);
     }
 
-    assert!(block_ptr_per_chain.len() == chains.len());
+    assert!(block_ptr_per_chain.len() == chain_count);
     block_ptr_per_chain
 }
 
@@ -170,3 +373,106 @@ where
     txs.retain(|tx| tx.from == Some(from_address) && tx.to == Some(to_address));
     Ok(txs)
 }
+
+/// Like [`calls_in_block_range`], but also finds internal calls, via `trace_filter`.
+pub async fn traces_in_block_range<T>(
+    web3: Web3<T>,
+    block_range: RangeInclusive<u64>,
+    from_address: H160,
+    to_address: H160,
+) -> web3::Result<Vec<Trace>>
+where
+    T: Transport,
+{
+    let filter = TraceFilterBuilder::default()
+        .from_block(BlockNumber::Number((*block_range.start()).into()))
+        .to_block(BlockNumber::Number((*block_range.end()).into()))
+        .from_address(vec![from_address])
+        .to_address(vec![to_address])
+        .build();
+    web3.trace().filter(filter).await
+}
+
+/// Finds the logs emitted by `address` within a block range.
+pub async fn logs_in_block_range<T>(
+    web3: Web3<T>,
+    block_range: RangeInclusive<u64>,
+    address: H160,
+) -> web3::Result<Vec<Log>>
+where
+    T: Transport,
+{
+    let filter = FilterBuilder::default()
+        .from_block(BlockNumber::Number((*block_range.start()).into()))
+        .to_block(BlockNumber::Number((*block_range.end()).into()))
+        .address(vec![address])
+        .build();
+    web3.eth().logs(filter).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::block_selection::tests::FakeChain;
+
+    fn transport(chain: &FakeChain, metrics: &Metrics) -> JrpcExpBackoff<FakeChain> {
+        JrpcExpBackoff::new(
+            chain.clone(),
+            Caip2ChainId::ethereum_mainnet(),
+            Duration::ZERO,
+            metrics.clone(),
+        )
+    }
+
+    #[tokio::test]
+    async fn rate_limit() {
+        let metrics = Metrics::new().unwrap();
+        let chain = FakeChain::new(100);
+        let web3 = Web3::new(transport(&chain, &metrics).with_rate_limit(NonZeroU32::new(20)));
+        let start = Instant::now();
+        let blocks =
+            futures::future::join_all((0..3).map(|_| get_latest_block(web3.clone()))).await;
+        assert!(blocks.iter().all(Result::is_ok));
+        // The first request goes out right away, the others 50ms apart.
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(chain.requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn failover() {
+        let metrics = Metrics::new().unwrap();
+        let (down, up) = (FakeChain::new(100).down(), FakeChain::new(200));
+        let primary = transport(&down, &metrics);
+        let web3 = Web3::new(primary.clone());
+        let requests = |chain: &FakeChain| chain.requests.lock().unwrap().len();
+
+        // Without a cooldown, the failed provider is tried first every time.
+        primary.set_failover(Some(Failover {
+            fallbacks: vec![transport(&up, &metrics)],
+            cooldown: Duration::ZERO,
+        }));
+        assert_eq!(get_latest_block(web3.clone()).await.unwrap().number, 200);
+        assert_eq!(get_latest_block(web3.clone()).await.unwrap().number, 200);
+        assert_eq!((requests(&down), requests(&up)), (2, 2));
+
+        // With one, it's skipped until the cooldown is over.
+        primary.set_failover(Some(Failover {
+            fallbacks: vec![transport(&up, &metrics)],
+            cooldown: Duration::from_secs(60),
+        }));
+        assert_eq!(get_latest_block(web3.clone()).await.unwrap().number, 200);
+        assert_eq!(get_latest_block(web3.clone()).await.unwrap().number, 200);
+        assert_eq!((requests(&down), requests(&up)), (3, 4));
+
+        // When all providers are unhealthy, all of them are tried.
+        let also_down = FakeChain::new(200).down();
+        primary.set_failover(Some(Failover {
+            fallbacks: vec![transport(&also_down, &metrics)],
+            cooldown: Duration::from_secs(60),
+        }));
+        assert!(get_latest_block(web3.clone()).await.is_err());
+        assert_eq!((requests(&down), requests(&also_down)), (3, 1));
+        assert!(get_latest_block(web3).await.is_err());
+        assert_eq!((requests(&down), requests(&also_down)), (4, 2));
+    }
+}
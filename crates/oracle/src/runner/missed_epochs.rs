@@ -0,0 +1,164 @@
+//! Notices when the Epoch Subgraph stops advancing even though every polling iteration succeeds.
+//!
+//! Payloads can get lost in ways that the main loop doesn't notice by itself: a transaction that is
+//! dropped from the mempool, a payload that the subgraph rejects, or a subgraph that stopped
+//! processing DataEdge calls altogether. Once the subgraph falls more than a configurable number
+//! of epochs behind the Epoch Manager, the oracle tries to find out why (see [`Diagnosis`]) and
+//! fails the iteration with [`Error::MissedEpochs`](super::Error::MissedEpochs), so that operators
+//! get alerted and [error policies](super::error_handling) can escalate. This happens at most once
+//! per epoch; the iterations in between carry on as usual, re-submitting payloads if needed.
+
+use crate::history::PublishedPayload;
+use crate::subgraph::SubgraphState;
+use std::fmt::Display;
+use web3::types::{TransactionReceipt, H256, U64};
+
+/// Decides when to attempt a recovery.
+#[derive(Debug)]
+pub struct MissedEpochWatchdog {
+    max_missed_epochs: u64,
+    /// The Epoch Manager's epoch at the time of the last recovery attempt.
+    last_attempt: Option<u64>,
+}
+
+impl MissedEpochWatchdog {
+    pub fn new(max_missed_epochs: u64) -> Self {
+        Self {
+            max_missed_epochs,
+            last_attempt: None,
+        }
+    }
+
+    /// Returns `true` if a recovery attempt is due.
+    pub fn check(&mut self, subgraph_epoch: u64, manager_epoch: u64) -> bool {
+        let missed = manager_epoch.saturating_sub(subgraph_epoch);
+        if missed <= self.max_missed_epochs {
+            self.last_attempt = None;
+            return false;
+        }
+        if self.last_attempt == Some(manager_epoch) {
+            return false;
+        }
+        self.last_attempt = Some(manager_epoch);
+        true
+    }
+}
+
+/// The likely reason why the subgraph didn't advance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnosis {
+    /// The oracle hasn't recorded any payload since the subgraph's latest epoch.
+    NothingSubmitted,
+    /// The latest payload's transaction is unknown to the protocol chain, so it was probably
+    /// dropped. It will be re-submitted.
+    TransactionDropped(H256),
+    TransactionReverted(H256),
+    /// The transaction succeeded, but the subgraph marked the payload as invalid.
+    PayloadRejected(H256),
+    /// The transaction succeeded, but the subgraph didn't act on it.
+    PayloadIgnored(H256),
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnosis::NothingSubmitted => {
+                f.write_str("no payload was submitted since the subgraph's latest epoch")
+            }
+            Diagnosis::TransactionDropped(tx) => {
+                write!(f, "transaction {tx:?} was dropped and will be re-submitted")
+            }
+            Diagnosis::TransactionReverted(tx) => write!(f, "transaction {tx:?} reverted"),
+            Diagnosis::PayloadRejected(tx) => {
+                write!(f, "the subgraph rejected the payload of transaction {tx:?}")
+            }
+            Diagnosis::PayloadIgnored(tx) => {
+                write!(f, "the subgraph didn't process transaction {tx:?}")
+            }
+        }
+    }
+}
+
+/// `latest_payload` is the latest payload in the history, and `receipt` its transaction receipt
+/// as currently seen by the protocol chain.
+pub fn diagnose(
+    subgraph_state: &SubgraphState,
+    latest_payload: Option<&PublishedPayload>,
+    receipt: Option<&TransactionReceipt>,
+) -> Diagnosis {
+    let subgraph_epoch = subgraph_state.latest_epoch_number();
+    let Some(payload) = latest_payload.filter(|p| subgraph_epoch.is_none_or(|e| p.epoch > e))
+    else {
+        return Diagnosis::NothingSubmitted;
+    };
+    let tx = payload.tx_hash;
+    match receipt {
+        None => Diagnosis::TransactionDropped(tx),
+        Some(receipt) if receipt.status == Some(U64::zero()) => Diagnosis::TransactionReverted(tx),
+        Some(_) => match &subgraph_state.last_payload {
+            Some(last_payload) if !last_payload.is_valid() => Diagnosis::PayloadRejected(tx),
+            _ => Diagnosis::PayloadIgnored(tx),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::SubmissionStatus;
+
+    #[test]
+    fn at_most_once_per_epoch() {
+        let mut watchdog = MissedEpochWatchdog::new(2);
+        assert!(!watchdog.check(10, 12));
+        assert!(watchdog.check(10, 13));
+        assert!(!watchdog.check(10, 13));
+        assert!(watchdog.check(10, 14));
+        // Catching up resets the watchdog.
+        assert!(!watchdog.check(14, 14));
+        assert!(watchdog.check(14, 17));
+    }
+
+    #[test]
+    fn diagnoses() {
+        let subgraph_state = SubgraphState {
+            last_indexed_block_number: 0,
+            global_state: None,
+            last_payload: None,
+        };
+        let payload = PublishedPayload {
+            epoch: 3,
+            timestamp: 0,
+            correlation_id: String::new(),
+            messages: vec![],
+            payload: String::new(),
+            tx_hash: H256::repeat_byte(1),
+            status: SubmissionStatus::Confirmed,
+            nonce: None,
+            block_number: None,
+            gas_used: None,
+            effective_gas_price: None,
+        };
+        assert_eq!(
+            diagnose(&subgraph_state, None, None),
+            Diagnosis::NothingSubmitted
+        );
+        assert_eq!(
+            diagnose(&subgraph_state, Some(&payload), None),
+            Diagnosis::TransactionDropped(payload.tx_hash)
+        );
+        let mut receipt = TransactionReceipt {
+            status: Some(U64::zero()),
+            ..Default::default()
+        };
+        assert_eq!(
+            diagnose(&subgraph_state, Some(&payload), Some(&receipt)),
+            Diagnosis::TransactionReverted(payload.tx_hash)
+        );
+        receipt.status = Some(U64::one());
+        assert_eq!(
+            diagnose(&subgraph_state, Some(&payload), Some(&receipt)),
+            Diagnosis::PayloadIgnored(payload.tx_hash)
+        );
+    }
+}
@@ -1,23 +1,47 @@
+pub mod block_selection;
+pub mod block_times;
+pub mod co_signing;
+pub mod config_reload;
+pub mod confirmations;
+pub mod consensus;
 pub mod ctrlc;
+pub mod defender;
+pub mod dns_refresh;
+pub mod endpoints;
+pub mod error_budget;
 pub mod error_handling;
+pub mod gas;
 pub mod jrpc_utils;
+pub mod missed_epochs;
+pub mod nonce_manager;
 pub mod oracle;
+pub mod private_relay;
+pub mod rate_limit;
+pub mod submitter;
 pub mod transaction_monitor;
 
 use self::ctrlc::CtrlcHandler;
+use crate::admin::{admin_server, LogFilterHandle, ResumeHandle};
+use crate::build_info;
+use crate::co_signing::{co_signing_server, CoSigningError};
 use crate::contracts::ContractError;
-use crate::metrics::{metrics_server, METRICS};
+use crate::history::Discontinuity;
+use crate::metrics::{metrics_server, pushgateway_task, Metrics};
+use crate::service::ServiceNotifier;
+use crate::transaction_monitor::{Rejection, TransactionMonitorError};
 use crate::{Caip2ChainId, Config, SubgraphQueryError};
-use error_handling::{MainLoopFlow, OracleControlFlow};
-use lazy_static::lazy_static;
+use epoch_encoding::EncodeError;
+use error_budget::Dependency;
+use error_handling::{ErrorAction, ErrorClass, ErrorPolicies, MainLoopFlow, OracleControlFlow};
+use missed_epochs::Diagnosis;
 use oracle::Oracle;
-use std::{env::set_var, path::Path, time::Duration};
-use tracing::{error, info, metadata::LevelFilter};
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-
-lazy_static! {
-    static ref CTRLC_HANDLER: CtrlcHandler = CtrlcHandler::init();
-}
+use std::path::PathBuf;
+use std::{env::set_var, time::Duration};
+use submitter::SubmitterError;
+use tokio::sync::mpsc;
+use tracing::{error, info, info_span, metadata::LevelFilter, warn, Instrument};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter};
+use web3::types::U256;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -34,12 +58,82 @@ pub enum Error {
     ContractError(#[from] ContractError),
     #[error("Failed to call Epoch Manager")]
     EpochManagerCallFailed(#[from] web3::contract::Error),
+    #[error("Failed to query the protocol chain through Multicall3: {0}")]
+    MulticallFailed(web3::contract::Error),
     #[error("Epoch Manager latest epoch ({manager}) is behind Epoch Subgraph's ({subgraph})")]
     EpochManagerBehindSubgraph { manager: u64, subgraph: u64 },
     #[error("The subgraph hasn't indexed all relevant transactions yet")]
     SubgraphNotFresh,
     #[error("The subgraph has not been initialized yet")]
     SubgraphNotInitialized,
+    #[error(
+        "The subgraph was probably redeployed or wiped, as {0}. Either wait for it to resync, or \
+         reset the oracle's history with `block-oracle reset-history`"
+    )]
+    SubgraphDiscontinuity(Discontinuity),
+    #[error(
+        "The subgraph is at epoch {subgraph}, but the Epoch Manager is already at epoch {manager}: \
+         {diagnosis}"
+    )]
+    MissedEpochs {
+        subgraph: u64,
+        manager: u64,
+        diagnosis: Diagnosis,
+    },
+    #[error("Failed to encode the payload: {0}")]
+    Encoding(#[from] EncodeError),
+    #[error(
+        "Encoding a {message} message left the encoder's state unchanged, so its payload \
+         wouldn't publish anything"
+    )]
+    EncoderUnchanged { message: &'static str },
+    #[error(transparent)]
+    CoSigning(#[from] CoSigningError),
+    #[error(
+        "The protocol chain's JSON-RPC provider is on chain ID {actual}, but the protocol chain \
+         is configured as '{configured}'. Nothing is signed until its `jrpc` URL is fixed"
+    )]
+    ChainIdMismatch {
+        configured: Caip2ChainId,
+        actual: U256,
+    },
+}
+
+impl From<SubmitterError> for Error {
+    fn from(error: SubmitterError) -> Self {
+        match error {
+            SubmitterError::Contract(error) => Self::ContractError(error),
+            SubmitterError::CoSigning(error) => Self::CoSigning(error),
+        }
+    }
+}
+
+impl Error {
+    /// The dependency whose failure caused this error, if any. See [`ErrorBudget`].
+    ///
+    /// [`ErrorBudget`]: error_budget::ErrorBudget
+    pub fn dependency(&self) -> Option<Dependency> {
+        use Error::*;
+        match self {
+            Subgraph(_) => Some(Dependency::Subgraph),
+            BadJrpcProtocolChain(_)
+            | ContractError(_)
+            | EpochManagerCallFailed(_)
+            | MulticallFailed(_) => Some(Dependency::ProtocolChain),
+            BadJrpcIndexedChain { chain_id, .. } => {
+                Some(Dependency::IndexedChain(chain_id.clone()))
+            }
+            EpochManagerBehindSubgraph { .. }
+            | SubgraphNotFresh
+            | SubgraphNotInitialized
+            | SubgraphDiscontinuity(_)
+            | MissedEpochs { .. }
+            | Encoding(_)
+            | EncoderUnchanged { .. }
+            | CoSigning(_)
+            | ChainIdMismatch { .. } => None,
+        }
+    }
 }
 
 impl MainLoopFlow for Error {
@@ -51,82 +145,375 @@ impl MainLoopFlow for Error {
             BadJrpcIndexedChain { .. } => OracleControlFlow::Continue(0),
 
             // TODO: Put those variants under a new `contracts::Error` enum
+            // Retrying won't make the payload any smaller, so we poll less frequently.
+            ContractError(
+                crate::contracts::ContractError::ExceedsBlockGasLimit { .. }
+                | crate::contracts::ContractError::ExceedsMaxGasLimit { .. }
+                | crate::contracts::ContractError::PayloadTooLarge { .. },
+            ) => OracleControlFlow::Continue(40),
+            // Most likely out of gas, which retrying won't fix either.
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Reverted(_),
+            )) => OracleControlFlow::Continue(40),
+            // The next submission reserves a fresh nonce.
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Rejected {
+                    rejection: Rejection::NonceTooLow,
+                    ..
+                },
+            )) => OracleControlFlow::Continue(0),
+            // Another transaction holds the nonce, and it must be mined or dropped first.
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Rejected {
+                    rejection: Rejection::ReplacementUnderpriced | Rejection::AlreadyKnown,
+                    ..
+                },
+            )) => OracleControlFlow::Continue(2),
+            // Requires external intervention, e.g. a paused contract, a wrong address or topping
+            // up the owner's balance.
+            ContractError(
+                crate::contracts::ContractError::SimulationReverted { .. }
+                | crate::contracts::ContractError::NoDataEdgeContract(_)
+                | crate::contracts::ContractError::TransactionMonitor(
+                    TransactionMonitorError::Rejected {
+                        rejection: Rejection::ExecutionReverted | Rejection::InsufficientFunds,
+                        ..
+                    },
+                ),
+            ) => OracleControlFlow::Continue(40),
             ContractError(_) => OracleControlFlow::Continue(0),
             EpochManagerCallFailed(_) => OracleControlFlow::Continue(0),
+            MulticallFailed(_) => OracleControlFlow::Continue(0),
             EpochManagerBehindSubgraph { .. } => OracleControlFlow::Continue(0),
 
             // TODO: Put those variants under the `SubgraphQueryError` enum
             SubgraphNotFresh => OracleControlFlow::Continue(2),
             SubgraphNotInitialized => OracleControlFlow::Continue(2),
+            // Requires external intervention, so we poll less frequently.
+            SubgraphDiscontinuity(_) => OracleControlFlow::Continue(40),
+            // The next iteration re-submits if needed.
+            MissedEpochs { .. } => OracleControlFlow::Continue(0),
+            // The subgraph's state must change first, so we poll less frequently.
+            Encoding(_) | EncoderUnchanged { .. } => OracleControlFlow::Continue(40),
+            // The payload stays proposed, so signatures keep arriving in the meantime.
+            CoSigning(_) => OracleControlFlow::Continue(0),
+            // Requires external intervention, e.g. rotating the endpoint through the admin API.
+            ChainIdMismatch { .. } => OracleControlFlow::Continue(40),
+        }
+    }
+
+    fn class(&self) -> ErrorClass {
+        use Error::*;
+        match self {
+            Subgraph(err) => err.class(),
+            BadJrpcProtocolChain(_) | MulticallFailed(_) => ErrorClass::ProtocolChainJrpc,
+            BadJrpcIndexedChain { .. } => ErrorClass::IndexedChainJrpc,
+            ContractError(
+                crate::contracts::ContractError::ExceedsBlockGasLimit { .. }
+                | crate::contracts::ContractError::ExceedsMaxGasLimit { .. }
+                | crate::contracts::ContractError::PayloadTooLarge { .. },
+            ) => ErrorClass::PayloadTooLarge,
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Reverted(_),
+            )) => ErrorClass::TransactionReverted,
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Signing(_),
+            )) => ErrorClass::SigningFailed,
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Rejected {
+                    rejection: Rejection::NonceTooLow,
+                    ..
+                },
+            )) => ErrorClass::NonceTooLow,
+            ContractError(crate::contracts::ContractError::TransactionMonitor(
+                TransactionMonitorError::Rejected {
+                    rejection: Rejection::ReplacementUnderpriced | Rejection::AlreadyKnown,
+                    ..
+                },
+            )) => ErrorClass::ReplacementUnderpriced,
+            ContractError(
+                crate::contracts::ContractError::SimulationReverted { .. }
+                | crate::contracts::ContractError::NoDataEdgeContract(_)
+                | crate::contracts::ContractError::TransactionMonitor(
+                    TransactionMonitorError::Rejected {
+                        rejection: Rejection::ExecutionReverted,
+                        ..
+                    },
+                ),
+            ) => ErrorClass::SimulationFailed,
+            ContractError(
+                crate::contracts::ContractError::InsufficientFunds { .. }
+                | crate::contracts::ContractError::TransactionMonitor(
+                    TransactionMonitorError::Rejected {
+                        rejection: Rejection::InsufficientFunds,
+                        ..
+                    },
+                ),
+            ) => ErrorClass::InsufficientFunds,
+            ContractError(crate::contracts::ContractError::GasPriceTooHigh { .. }) => {
+                ErrorClass::GasPriceTooHigh
+            }
+            ContractError(_) => ErrorClass::CantSubmitTx,
+            EpochManagerCallFailed(_) => ErrorClass::EpochManagerCallFailed,
+            EpochManagerBehindSubgraph { .. } => ErrorClass::EpochManagerBehindSubgraph,
+            SubgraphNotFresh => ErrorClass::SubgraphNotFresh,
+            SubgraphNotInitialized => ErrorClass::SubgraphNotInitialized,
+            SubgraphDiscontinuity(_) => ErrorClass::SubgraphDiscontinuity,
+            MissedEpochs { .. } => ErrorClass::MissedEpochs,
+            Encoding(EncodeError::PayloadTooLarge { .. }) => ErrorClass::PayloadTooLarge,
+            Encoding(_) | EncoderUnchanged { .. } => ErrorClass::Encoding,
+            CoSigning(_) => ErrorClass::CoSigningTimeout,
+            ChainIdMismatch { .. } => ErrorClass::ChainIdMismatch,
         }
     }
 }
 
-pub async fn run(config_file: impl AsRef<Path>) -> Result<(), Error> {
-    // Immediately dereference some constants to trigger `lazy_static`
-    // initialization.
-    let config = Config::parse(config_file);
-    let _ = &*METRICS;
+/// Runs the oracle until CTRL+C is pressed, the same way the `block-oracle` binary does.
+///
+/// This installs a global logger and a CTRL+C handler, so it should be called at most once per
+/// process. Services that embed the oracle will usually want to drive an [`Oracle`] themselves.
+pub async fn run(config: Config) -> anyhow::Result<()> {
+    run_with_reloads(config, mpsc::unbounded_channel().1).await
+}
+
+/// Like [`run`], but reloads the configuration from `config_file` on `SIGHUP`, see [`config_reload`].
+pub async fn run_reloadable(config_file: PathBuf, config: Config) -> anyhow::Result<()> {
+    let reloads = config_reload::config_reloads(config_file, config.clone());
+    run_with_reloads(config, reloads).await
+}
+
+async fn run_with_reloads(
+    config: Config,
+    reloads: mpsc::UnboundedReceiver<Config>,
+) -> anyhow::Result<()> {
+    let metrics = Metrics::new()?;
+    let ctrlc = CtrlcHandler::init();
 
-    init_logging(config.log_level);
-    info!(log_level = %config.log_level, "The block oracle is starting.");
+    let log_filter = init_logging(config.log_level);
+    info!(
+        log_level = %config.log_level,
+        version = build_info::long_version(),
+        "The block oracle is starting."
+    );
+    if config.dry_run {
+        warn!("Running in dry-run mode. Payloads are logged, but never submitted.");
+    }
+    warn_about_bare_env_vars(&config);
+
+    let oracle = Oracle::new(config.clone(), metrics.clone())?;
+    // Nothing must be signed for the wrong chain. The check is repeated before every submission,
+    // so an unreachable provider is only fatal there.
+    match oracle.verify_chain_id().await {
+        Ok(_) => {}
+        Err(error @ Error::ChainIdMismatch { .. }) => {
+            error!(%error, "The protocol chain is misconfigured.");
+            return Err(error.into());
+        }
+        Err(error) => warn!(%error, "Failed to verify the protocol chain's ID."),
+    }
+    let resume = ResumeHandle::default();
+    let endpoints = oracle.endpoints();
 
     // Spawn the metrics server
-    tokio::spawn(metrics_server(&METRICS, config.metrics_port));
+    tokio::spawn(metrics_server(
+        metrics.clone(),
+        oracle.status(),
+        config.metrics_port,
+    ));
+    if let Some(port) = config.admin_port {
+        tokio::spawn(admin_server(
+            port,
+            log_filter,
+            resume.clone(),
+            endpoints.clone(),
+        ));
+    }
+    if let Some(co_signing) = oracle.co_signing() {
+        tokio::spawn(co_signing_server(co_signing));
+    }
+    if let Some(interval) = config.dns_refresh_interval {
+        tokio::spawn(dns_refresh::dns_refresh_task(endpoints.clone(), interval));
+    }
+    if let Some(pushgateway) = config.pushgateway.clone() {
+        tokio::spawn(pushgateway_task(metrics.clone(), pushgateway));
+    }
 
     // Start the Epoch Block Oracle
-    oracle_task(config).await
+    let notifier = ServiceNotifier::from_env();
+    notifier.ready();
+    let protocol_chain = oracle.protocol_chain();
+    let contracts = oracle.contracts();
+    let indexed_chains = oracle.indexed_chains().to_vec();
+    let block_times = async {
+        let sampled = config.adaptive_polling.is_some()
+            || indexed_chains
+                .iter()
+                .any(|chain| chain.polling_interval.is_some());
+        if sampled {
+            block_times::block_times_task(
+                protocol_chain,
+                &contracts,
+                indexed_chains,
+                config.adaptive_polling,
+                config.protocol_chain.polling_interval,
+                metrics.clone(),
+            )
+            .await;
+        }
+        std::future::pending::<()>().await
+    };
+    let result = tokio::select! {
+        result = oracle_task(oracle, config.clone(), reloads, ctrlc, resume, &notifier) => result,
+        () = block_times => unreachable!(),
+    };
+    notifier.stopping();
+    Ok(result?)
 }
 
-async fn oracle_task(config: Config) -> Result<(), Error> {
-    let mut oracle = Oracle::new(config.clone());
+async fn oracle_task(
+    mut oracle: Oracle,
+    config: Config,
+    mut reloads: mpsc::UnboundedReceiver<Config>,
+    ctrlc: CtrlcHandler,
+    resume: ResumeHandle,
+    notifier: &ServiceNotifier,
+) -> Result<(), Error> {
     info!("Entering the main polling loop. Press CTRL+C to stop.");
+    let mut error_policies = ErrorPolicies::new(config.error_policies.clone());
 
-    while !CTRLC_HANDLER.poll_ctrlc() {
+    while !ctrlc.poll_ctrlc() {
+        // Reloads only apply between polling iterations, so that they never interrupt a
+        // transaction.
+        while let Ok(reloaded) = reloads.try_recv() {
+            oracle.reload(reloaded);
+        }
+        let polling_interval = oracle.config().protocol_chain.polling_interval;
         if let Err(err) = oracle.run().await {
-            handle_error(err, config.protocol_chain.polling_interval).await?;
+            let config = oracle.config();
+            // Errors are logged within the span of the iteration that caused them.
+            let span = info_span!(
+                "iteration",
+                correlation_id = oracle.correlation_id().as_str()
+            );
+            let degraded_multiplier = err
+                .dependency()
+                .map_or(1, |d| oracle.error_budget().cooldown_multiplier(&d));
+            let action = error_policies.action(&err);
+            handle_error(
+                err,
+                action,
+                config,
+                degraded_multiplier,
+                &ctrlc,
+                &resume,
+                notifier,
+            )
+            .instrument(span)
+            .await?;
             continue;
         }
+        error_policies.record_success();
 
         // After every polling iteration, we go to sleep for a bit. Wouldn't
         // want to DDoS our data providers, wouldn't we?
         info!(
-            seconds = config.protocol_chain.polling_interval.as_secs(),
+            seconds = polling_interval.as_secs(),
             "Going to sleep before next polling iteration."
         );
-        tokio::time::sleep(config.protocol_chain.polling_interval).await;
+        notifier.sleep(polling_interval).await;
     }
     Ok(())
 }
 
-async fn handle_error(err: Error, polling_interval: Duration) -> Result<(), Error> {
-    error!(
-        error = err.to_string().as_str(),
-        "An error occurred and interrupted the last polling iteration."
-    );
-    match err.instruction() {
-        OracleControlFlow::Break(()) => {
+/// `degraded_multiplier` stretches the cooldown when the error was caused by a degraded
+/// dependency.
+async fn handle_error(
+    err: Error,
+    action: ErrorAction,
+    config: &Config,
+    degraded_multiplier: u32,
+    ctrlc: &CtrlcHandler,
+    resume: &ResumeHandle,
+    notifier: &ServiceNotifier,
+) -> Result<(), Error> {
+    let polling_interval = config.protocol_chain.polling_interval;
+    if action == ErrorAction::Skip {
+        warn!(
+            error = err.to_string().as_str(),
+            "An error occurred and interrupted the last polling iteration. Skipping it."
+        );
+    } else {
+        error!(
+            error = err.to_string().as_str(),
+            "An error occurred and interrupted the last polling iteration."
+        );
+    }
+    match action {
+        ErrorAction::Skip => {
+            notifier.sleep(polling_interval).await;
+            Ok(())
+        }
+        ErrorAction::Exit => {
             error!("This error is non-recoverable. Exiting now.");
             Err(err)
         }
-        OracleControlFlow::Continue(cooldown_multiplier) => {
-            let wait = polling_interval * cooldown_multiplier;
+        ErrorAction::Pause => {
+            match config.admin_port {
+                Some(port) => error!(
+                    "Pausing the oracle. Resume it with `curl -X POST http://127.0.0.1:{port}/resume`, \
+                     or restart it."
+                ),
+                None => error!(
+                    "Pausing the oracle. Restart it to resume, or set `admin_port` to be able to \
+                     resume it over HTTP."
+                ),
+            }
+            let resumed = resume.notified();
+            tokio::pin!(resumed);
+            resumed.as_mut().enable();
+            while !ctrlc.poll_ctrlc() {
+                tokio::select! {
+                    _ = &mut resumed => break,
+                    _ = notifier.sleep(Duration::from_secs(1)) => {}
+                }
+            }
+            info!("Resuming the oracle.");
+            Ok(())
+        }
+        ErrorAction::Retry {
+            cooldown: cooldown_multiplier,
+        } => {
+            let mut wait = polling_interval * cooldown_multiplier;
+            if degraded_multiplier > 1 {
+                wait = wait.max(polling_interval) * degraded_multiplier;
+            }
             error!(
                 cooling_off_seconds = wait.as_secs(),
                 "This error is recoverable.",
             );
-            tokio::time::sleep(wait).await;
+            notifier.sleep(wait).await;
             Ok(())
         }
     }
 }
 
-fn init_logging(log_level: LevelFilter) {
+/// The `$NAME` form is still read, but only `${NAME}` placeholders can be part of a longer value.
+fn warn_about_bare_env_vars(config: &Config) {
+    for key in &config.bare_env_vars {
+        warn!(
+            key,
+            "Reading environment variables with \"$NAME\" is deprecated. Write \"${{NAME}}\" instead."
+        );
+    }
+}
+
+fn init_logging(log_level: LevelFilter) -> LogFilterHandle {
     set_var("RUST_LOG", "block_oracle=trace");
 
     let filter = EnvFilter::builder()
         .with_default_directive(log_level.into())
         .from_env_lossy();
+    let (filter, handle) = reload::Layer::new(filter);
 
     let stdout = fmt::layer()
         .with_ansi(false)
@@ -138,6 +525,7 @@ fn init_logging(log_level: LevelFilter) {
         .with(filter)
         .with(stdout)
         .init();
+    handle
 }
 
 pub fn hex_string(bytes: &[u8]) -> String {
@@ -0,0 +1,157 @@
+//! Hands out the nonces of the owner account, one transaction at a time.
+//!
+//! Fetching the nonce right before every transaction is racy: a provider behind a load balancer
+//! may not have seen our last transaction yet, and a retried submission may overlap with the
+//! previous one. Either way, two transactions end up with the same nonce and one of them gets
+//! stuck. The [`NonceManager`] remembers the next nonce locally, reconciles it with the
+//! provider's pending transaction count, and only lets a single submission hold a nonce at once.
+
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{debug, warn};
+use web3::{
+    api::Eth,
+    types::{Address, BlockNumber, U256},
+    Transport,
+};
+
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    /// The account and its next nonce, as far as we know.
+    next: Mutex<Option<(Address, U256)>>,
+}
+
+impl NonceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reserves the next nonce of `address`. Other reservations wait until this one is dropped,
+    /// so submissions are serialized.
+    pub async fn reserve<T: Transport>(
+        &self,
+        eth: &Eth<T>,
+        address: Address,
+    ) -> Result<NonceReservation<'_>, web3::Error> {
+        let mut next = self.next.lock().await;
+        let pending = eth
+            .transaction_count(address, Some(BlockNumber::Pending))
+            .await?;
+        let nonce = reconcile(&mut next, address, pending);
+        debug!(%nonce, %pending, "Reserved a nonce");
+        Ok(NonceReservation {
+            next,
+            nonce,
+            consumed: false,
+        })
+    }
+}
+
+/// A nonce that only this submission may use. If the transaction is sent, the reservation must
+/// be [consumed](NonceReservation::consume). Otherwise, the next reservation starts over from the
+/// provider's pending transaction count.
+#[derive(Debug)]
+pub struct NonceReservation<'a> {
+    next: MutexGuard<'a, Option<(Address, U256)>>,
+    nonce: U256,
+    consumed: bool,
+}
+
+impl NonceReservation<'_> {
+    pub fn nonce(&self) -> U256 {
+        self.nonce
+    }
+
+    /// Marks the nonce as used, so the next reservation gets the one after it.
+    pub fn consume(mut self) {
+        if let Some((_, next)) = self.next.as_mut() {
+            *next = self.nonce + 1;
+        }
+        self.consumed = true;
+    }
+}
+
+impl Drop for NonceReservation<'_> {
+    fn drop(&mut self) {
+        if !self.consumed {
+            *self.next = None;
+        }
+    }
+}
+
+/// Picks the nonce of the next transaction from what we know locally and the provider's
+/// `pending` transaction count, whichever is higher.
+fn reconcile(next: &mut Option<(Address, U256)>, address: Address, pending: U256) -> U256 {
+    let nonce = match *next {
+        Some((known, local)) if known == address => {
+            if local > pending {
+                // Most likely, the provider hasn't seen our last transaction yet.
+                warn!(%local, %pending, "The provider is behind our local nonce");
+            } else if local < pending {
+                warn!(
+                    %local,
+                    %pending,
+                    "The owner account sent transactions that the oracle didn't"
+                );
+            }
+            local.max(pending)
+        }
+        _ => pending,
+    };
+    *next = Some((address, nonce));
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reservation(manager: &NonceManager, pending: u64) -> NonceReservation<'_> {
+        let mut next = manager.next.try_lock().unwrap();
+        let nonce = reconcile(&mut next, Address::zero(), pending.into());
+        NonceReservation {
+            next,
+            nonce,
+            consumed: false,
+        }
+    }
+
+    #[test]
+    fn consumed_nonces_stay_ahead_of_a_lagging_provider() {
+        let manager = NonceManager::new();
+        let first = reservation(&manager, 5);
+        assert_eq!(first.nonce(), 5.into());
+        first.consume();
+
+        // The provider hasn't seen the first transaction yet.
+        let second = reservation(&manager, 5);
+        assert_eq!(second.nonce(), 6.into());
+        second.consume();
+
+        // Someone else used the account in the meantime.
+        assert_eq!(reservation(&manager, 9).nonce(), 9.into());
+    }
+
+    #[test]
+    fn unused_nonces_are_reconciled_again() {
+        let manager = NonceManager::new();
+        reservation(&manager, 5).consume();
+        drop(reservation(&manager, 5));
+        assert_eq!(*manager.next.try_lock().unwrap(), None);
+        assert_eq!(reservation(&manager, 5).nonce(), 5.into());
+    }
+
+    #[test]
+    fn reservations_are_exclusive() {
+        let manager = NonceManager::new();
+        let reservation = reservation(&manager, 0);
+        assert!(manager.next.try_lock().is_err());
+        drop(reservation);
+        assert!(manager.next.try_lock().is_ok());
+    }
+
+    #[test]
+    fn other_accounts_start_over() {
+        let mut next = Some((Address::repeat_byte(1), U256::from(10)));
+        assert_eq!(reconcile(&mut next, Address::zero(), 3.into()), 3.into());
+    }
+}
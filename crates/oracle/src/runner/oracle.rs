@@ -1,64 +1,295 @@
 use crate::{
+    accounting::Costs,
     blockmeta::blockmeta_client::{get_latest_blockmeta_blocks, AuthInterceptor},
-    contracts::Contracts,
+    co_signing::CoSigning,
+    config::{IndexedChain, StaleSubgraphAction},
+    contracts::{ContractError, Contracts, ProtocolChainSnapshot},
+    defender::DefenderError,
+    endpoints::Endpoints,
+    error_budget::{Dependency, ErrorBudget},
     hex_string,
-    jrpc_utils::{get_latest_block, get_latest_blocks, JrpcExpBackoff},
-    metrics::METRICS,
-    subgraph::{query_subgraph, SubgraphState},
+    history::{History, PublishedPayload, SubmissionStatus},
+    jrpc_utils::{get_latest_block, get_latest_blocks, Failover, JrpcExpBackoff},
+    metrics::Metrics,
+    missed_epochs::{diagnose, MissedEpochWatchdog},
+    models::eip155_chain_id,
+    signer,
+    status::{CorrelationId, SharedStatus},
+    subgraph::{query_subgraph, GlobalState, SubgraphQueryError, SubgraphState},
+    submitter::{self, Payload, SubmissionOutcome, SubmitterError, TransactionSubmitter},
+    transaction_monitor::TransactionMonitorError,
     BlockmetaProviderForChain, Caip2ChainId, Config, Error, JrpcProviderForChain,
 };
-use alloy_primitives::BlockHash;
-use epoch_encoding::{BlockPtr, Encoder, Message, CURRENT_ENCODING_VERSION};
+use anyhow::Context;
+use epoch_encoding::{
+    batch, inspect, normalize_block_hash, Batch, BlockPtr, CompressionStats, Encoder, Message,
+    OverflowPolicy, SetBlockNumbersBuilder, CURRENT_ENCODING_VERSION,
+};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 use std::{cmp::Ordering, collections::BTreeMap};
 use tonic::codegen::InterceptedService;
 use tonic::transport::Channel;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use url::Url;
+use web3::types::U256;
 
 /// The main application in-memory state.
-pub struct Oracle {
+pub struct Oracle<S = Box<dyn TransactionSubmitter>> {
     config: Config,
     protocol_chain: JrpcProviderForChain<JrpcExpBackoff>,
     indexed_chains: Vec<JrpcProviderForChain<JrpcExpBackoff>>,
+    endpoints: Endpoints,
     blockmeta_indexed_chains:
         Vec<BlockmetaProviderForChain<InterceptedService<Channel, AuthInterceptor>>>,
-    contracts: Contracts<JrpcExpBackoff>,
+    /// Only used for reads, payloads go through the `submitter`.
+    contracts: Arc<Contracts<JrpcExpBackoff>>,
+    submitter: S,
+    /// Handed to the co-signing API. Payloads are co-signed by the `submitter`.
+    co_signing: Option<CoSigning>,
+    history: History,
+    error_budget: ErrorBudget,
+    missed_epochs: Option<MissedEpochWatchdog>,
+    metrics: Metrics,
+    status: SharedStatus,
+    started_at_unix_secs: u64,
+    iteration: u64,
+    correlation_id: CorrelationId,
+    /// Whether the owner's pending transactions were dealt with since startup.
+    pending_transactions_resolved: bool,
 }
 
 impl Oracle {
-    pub fn new(config: Config) -> Self {
-        let protocol_chain = protocol_chain(&config);
-        let indexed_chains = indexed_chains(&config);
-        let blockmeta_indexed_chains = blockmeta_indexed_chains(&config);
-        let contracts = Contracts::new(
-            protocol_chain.web3.clone(),
-            config.data_edge_address,
-            config.epoch_manager_address,
-            config.transaction_monitoring_options,
+    /// Fails if the configured contracts or the history file can't be loaded.
+    pub fn new(config: Config, metrics: Metrics) -> anyhow::Result<Self> {
+        let protocol_chain = protocol_chain(&config, &metrics);
+        let contracts = Contracts::from_config(protocol_chain.web3.clone(), &config)?
+            .with_metrics(metrics.clone());
+        let contracts = Arc::new(contracts);
+        let signer = signer::from_owner_key(&config.owner_key);
+        let co_signing = config.co_signing.clone().map(CoSigning::new);
+        let submitter =
+            submitter::from_config(&config, contracts.clone(), signer, co_signing.clone())?;
+        Self::from_parts(
+            config,
+            metrics,
+            protocol_chain,
+            contracts,
+            submitter,
+            co_signing,
         )
-        .expect("Failed to initialize Block Oracle's required contracts");
+    }
+}
+
+impl<S: TransactionSubmitter> Oracle<S> {
+    /// Like [`Oracle::new`], but submits payloads through `submitter`, e.g. a backend that was
+    /// picked at runtime as a `Box<dyn TransactionSubmitter>`, or a mock. The protocol chain is
+    /// still read through the configured JSON RPC provider, and `submitter` is in charge of
+    /// dry runs and co-signing.
+    pub fn with_submitter(config: Config, metrics: Metrics, submitter: S) -> anyhow::Result<Self> {
+        let protocol_chain = protocol_chain(&config, &metrics);
+        let contracts = Contracts::from_config(protocol_chain.web3.clone(), &config)?
+            .with_metrics(metrics.clone());
+        let contracts = Arc::new(contracts);
+        Self::from_parts(config, metrics, protocol_chain, contracts, submitter, None)
+    }
+
+    fn from_parts(
+        config: Config,
+        metrics: Metrics,
+        protocol_chain: JrpcProviderForChain<JrpcExpBackoff>,
+        contracts: Arc<Contracts<JrpcExpBackoff>>,
+        submitter: S,
+        co_signing: Option<CoSigning>,
+    ) -> anyhow::Result<Self> {
+        let indexed_chains = indexed_chains(&config, &metrics);
+        let endpoints = Endpoints::new(
+            protocol_chain.web3.transport().clone(),
+            transports(&indexed_chains),
+        );
+        let blockmeta_indexed_chains = blockmeta_indexed_chains(&config);
+        let history = match &config.history_file {
+            Some(path) => History::open(path)
+                .with_context(|| format!("Failed to load the history file {}", path.display()))?,
+            None => History::in_memory(),
+        };
+        let error_budget = ErrorBudget::new(config.error_budget, metrics.clone());
+        let missed_epochs = config.max_missed_epochs.map(MissedEpochWatchdog::new);
+        let started_at_unix_secs = UNIX_EPOCH.elapsed().unwrap().as_secs();
 
-        Self {
+        Ok(Self {
             config,
             protocol_chain,
             indexed_chains,
+            endpoints,
             blockmeta_indexed_chains,
             contracts,
+            submitter,
+            co_signing,
+            history,
+            error_budget,
+            missed_epochs,
+            metrics,
+            status: SharedStatus::default(),
+            started_at_unix_secs,
+            iteration: 0,
+            correlation_id: CorrelationId::new(started_at_unix_secs, 0),
+            pending_transactions_resolved: false,
+        })
+    }
+
+    /// The [`CorrelationId`] of the current (or last) polling iteration.
+    pub fn correlation_id(&self) -> &CorrelationId {
+        &self.correlation_id
+    }
+
+    /// How the oracle's dependencies have been doing lately.
+    pub fn error_budget(&self) -> &ErrorBudget {
+        &self.error_budget
+    }
+
+    /// A handle to rotate the oracle's JSON RPC endpoints while it runs.
+    pub fn endpoints(&self) -> Endpoints {
+        self.endpoints.clone()
+    }
+
+    /// The configuration that the oracle currently runs with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Applies a reloaded configuration between polling iterations, see
+    /// [`config_reload`](super::config_reload).
+    ///
+    /// Only the indexed chains, the protocol chain's JSON RPC URL and the polling interval are
+    /// taken from `config`. Indexed chains that were added get new providers, and changed URLs
+    /// are rotated, see [`Endpoints`]. Everything else is built once at startup, so changing it
+    /// takes a restart.
+    pub fn reload(&mut self, config: Config) {
+        if config.protocol_chain.id != self.config.protocol_chain.id {
+            warn!(
+                current = %self.config.protocol_chain.id,
+                reloaded = %config.protocol_chain.id,
+                "The protocol chain can't change without a restart. Keeping the current one."
+            );
         }
+        let protocol_chain = self.protocol_chain.web3.transport();
+        if config.protocol_chain.jrpc_url != self.config.protocol_chain.jrpc_url {
+            match protocol_chain.rotate_url(&config.protocol_chain.jrpc_url) {
+                Ok(()) => {
+                    self.config.protocol_chain.jrpc_url = config.protocol_chain.jrpc_url.clone();
+                    info!("The protocol chain's JSON RPC endpoint was reloaded.");
+                }
+                Err(error) => warn!(
+                    %error,
+                    "Failed to create a transport for the protocol chain's new URL. Keeping the \
+                     current one."
+                ),
+            }
+        }
+        self.config.protocol_chain.polling_interval = config.protocol_chain.polling_interval;
+        protocol_chain.set_rate_limit(config.protocol_chain.rate_limit);
+        self.config.protocol_chain.rate_limit = config.protocol_chain.rate_limit;
+
+        self.indexed_chains = reload_indexed_chains(&self.indexed_chains, &config, &self.metrics);
+        self.endpoints
+            .set_indexed_chains(transports(&self.indexed_chains));
+        log_indexed_chain_changes(&self.config.indexed_chains, &config.indexed_chains);
+        self.config.indexed_chains = config.indexed_chains;
+        info!(
+            polling_interval_in_seconds = self.config.protocol_chain.polling_interval.as_secs(),
+            indexed_chains = self.config.indexed_chains.len(),
+            "The configuration was reloaded."
+        );
+    }
+
+    /// A handle to the payloads that wait for co-signatures, if co-signing is enabled.
+    pub fn co_signing(&self) -> Option<CoSigning> {
+        self.co_signing.clone()
+    }
+
+    /// The JSON RPC provider of the protocol chain, sharing its transport with the oracle.
+    pub fn protocol_chain(&self) -> JrpcProviderForChain<JrpcExpBackoff> {
+        self.protocol_chain.clone()
+    }
+
+    /// The contracts that the oracle reads from.
+    pub fn contracts(&self) -> Arc<Contracts<JrpcExpBackoff>> {
+        self.contracts.clone()
+    }
+
+    /// The JSON RPC providers of indexed chains, sharing their transports with the oracle.
+    pub fn indexed_chains(&self) -> &[JrpcProviderForChain<JrpcExpBackoff>] {
+        &self.indexed_chains
+    }
+
+    /// A handle to the oracle's status, which is updated as it runs.
+    pub fn status(&self) -> SharedStatus {
+        self.status.clone()
     }
 
     /// Runs a new polling iteration and submits new messages to the subgraph,
     /// if necessary.
+    ///
+    /// Everything that happens during the iteration is logged within a span that carries the
+    /// iteration's [`CorrelationId`].
     pub async fn run(&mut self) -> Result<(), Error> {
+        self.iteration += 1;
+        self.correlation_id = CorrelationId::new(self.started_at_unix_secs, self.iteration);
+        self.status.start_iteration(self.correlation_id.clone());
+        let span = info_span!("iteration", correlation_id = self.correlation_id.as_str());
+        let result = self.run_iteration().instrument(span).await;
+        match &result {
+            // Both the protocol chain and the subgraph are used in every successful iteration.
+            Ok(()) => {
+                self.error_budget.record_success(Dependency::ProtocolChain);
+                self.error_budget.record_success(Dependency::Subgraph);
+            }
+            Err(error) => {
+                if let Some(dependency) = error.dependency() {
+                    self.error_budget.record_failure(dependency);
+                }
+            }
+        }
+        result
+    }
+
+    async fn run_iteration(&mut self) -> Result<(), Error> {
         info!("New polling iteration.");
 
-        self.query_owner_eth_balance().await?;
+        // Transactions left behind by a previous run would hold back our next submission.
+        if !self.pending_transactions_resolved {
+            // Pending transactions may be cancelled, which means signing.
+            self.verify_chain_id().await?;
+            self.submitter.resolve_pending_transactions().await?;
+            self.pending_transactions_resolved = true;
+        }
+
+        let snapshot = self
+            .contracts
+            .query_snapshot(self.config.owner_address)
+            .await
+            .map_err(Error::MulticallFailed)?;
+        self.query_owner_eth_balance(snapshot.as_ref()).await?;
 
         // Before anything else, we must get the latest subgraph state
         debug!("Querying the subgraph state...");
-        let subgraph_state =
-            query_subgraph(&self.config.subgraph_url, &self.config.bearer_token).await?;
+        let subgraph_state = query_subgraph(
+            &self.config.subgraph_url,
+            &self.config.bearer_token,
+            &self.metrics,
+        )
+        .await?;
+        self.history
+            .check_continuity(&subgraph_state)
+            .map_err(Error::SubgraphDiscontinuity)?;
+        self.history
+            .report_discrepancies(&subgraph_state, &self.metrics);
 
-        if self.detect_new_epoch(&subgraph_state).await? {
+        if self
+            .detect_new_epoch(&subgraph_state, snapshot.as_ref())
+            .await?
+        {
             self.handle_new_epoch(&subgraph_state).await?;
         } else {
             debug!("No epoch change detected.");
@@ -68,57 +299,94 @@ impl Oracle {
 
     /// Checks if the Subgraph should consider that the Subgraph is at a previous epoch compared to
     /// the Epoch Manager.
-    async fn detect_new_epoch(&self, subgraph_state: &SubgraphState) -> Result<bool, Error> {
+    ///
+    /// Values from `snapshot` are preferred over querying the protocol chain again.
+    async fn detect_new_epoch(
+        &mut self,
+        subgraph_state: &SubgraphState,
+        snapshot: Option<&ProtocolChainSnapshot>,
+    ) -> Result<bool, Error> {
         // Then we check if there is a new epoch by looking at the current Subgraph state.
-        let last_block_number_indexed_by_subgraph = match self.is_new_epoch(subgraph_state).await {
-            // The Subgraph is at the same epoch as the Epoch Manager.
-            Ok(NewEpochCheck::SameEpoch) => return Ok(false),
+        let last_block_number_indexed_by_subgraph =
+            match self.is_new_epoch(subgraph_state, snapshot).await {
+                // The Subgraph is at the same epoch as the Epoch Manager.
+                Ok(NewEpochCheck::SameEpoch) => return Ok(false),
 
-            // The Subgraph is at a previous epoch than the Epoch Manager, but we still need to
-            // check if the former is fresh.
-            Ok(NewEpochCheck::PreviousEpoch {
-                subgraph_latest_indexed_block,
-            }) => subgraph_latest_indexed_block,
+                // The Subgraph is at a previous epoch than the Epoch Manager, but we still need to
+                // check if the former is fresh.
+                Ok(NewEpochCheck::PreviousEpoch {
+                    subgraph_latest_indexed_block,
+                    subgraph_epoch,
+                    manager_epoch,
+                }) => {
+                    self.watch_missed_epochs(subgraph_state, subgraph_epoch, manager_epoch)
+                        .await?;
+                    subgraph_latest_indexed_block
+                }
 
-            // The Subgraph was recently initialized and needs to receive its first
-            // SetBlockNumbersForNextEpoch message.
-            Ok(NewEpochCheck::RecentlyInitialized) => return Ok(true),
+                // The Subgraph was recently initialized and needs to receive its first
+                // SetBlockNumbersForNextEpoch message.
+                Ok(NewEpochCheck::RecentlyInitialized) => return Ok(true),
 
-            Err(other) => return Err(other),
-        };
+                Err(other) => return Err(other),
+            };
 
-        let protocol_chain_current_block = get_latest_block(self.protocol_chain.web3.clone())
-            .await
-            .map_err(Error::BadJrpcProtocolChain)?;
-        debug!(
-            number = protocol_chain_current_block.number,
-            hash = hex::encode(protocol_chain_current_block.hash).as_str(),
-            "Got the latest block from the protocol chain."
-        );
+        let freshness_check = self.config.freshness_check;
+        if !freshness_check.enabled {
+            debug!("The freshness check is disabled, assuming the subgraph is fresh.");
+            return Ok(true);
+        }
+
+        let protocol_chain_current_block_number = match snapshot {
+            Some(snapshot) => snapshot.block_number,
+            None => {
+                let block = get_latest_block(self.protocol_chain.web3.clone())
+                    .await
+                    .map_err(Error::BadJrpcProtocolChain)?;
+                debug!(
+                    number = block.number,
+                    hash = hex::encode(block.hash).as_str(),
+                    "Got the latest block from the protocol chain."
+                );
+                block.number
+            }
+        };
 
         let is_fresh = freshness::subgraph_is_fresh(
             last_block_number_indexed_by_subgraph.into(),
-            protocol_chain_current_block.number.into(),
+            protocol_chain_current_block_number.into(),
             self.protocol_chain.clone(),
             self.config.owner_address,
             self.config.data_edge_address,
-            self.config.freshness_threshold,
+            &freshness_check,
         )
         .await
         .map_err(Error::BadJrpcProtocolChain)?;
-        if !is_fresh {
-            error!("Subgraph is not fresh");
-            Err(Error::SubgraphNotFresh)
-        } else {
-            Ok(true)
+        match (is_fresh, freshness_check.on_stale) {
+            (true, _) => Ok(true),
+            (false, StaleSubgraphAction::Defer) => {
+                error!("Subgraph is not fresh");
+                Err(Error::SubgraphNotFresh)
+            }
+            (false, StaleSubgraphAction::Warn) => {
+                warn!("Subgraph is not fresh, but proceeding anyway as configured");
+                Ok(true)
+            }
         }
     }
 
     /// Checks if the Subgraph epoch is behind the Epoch Manager's current epoch.
     ///
-    /// Returns a pair of values indicating: 1) if there is a new epoch; and 2) the latest block
-    /// number indexed by the subgraph. Returns `None` if the Subgraph is not initialized.
-    async fn is_new_epoch(&self, subgraph_state: &SubgraphState) -> Result<NewEpochCheck, Error> {
+    /// The current epoch always comes from the Epoch Manager's `currentEpoch()` (possibly through
+    /// a Multicall3 `snapshot`), rather than being derived from block numbers and the epoch
+    /// length, so the oracle can't drift from the protocol's notion of epochs.
+    ///
+    /// Fails with [`Error::SubgraphNotInitialized`] if the Subgraph has no networks yet.
+    async fn is_new_epoch(
+        &self,
+        subgraph_state: &SubgraphState,
+        snapshot: Option<&ProtocolChainSnapshot>,
+    ) -> Result<NewEpochCheck, Error> {
         use NewEpochCheck::*;
         let (subgraph_latest_indexed_block, subgraph_latest_epoch) = {
             match subgraph_state.latest_epoch_number() {
@@ -136,11 +404,24 @@ impl Oracle {
         };
 
         debug!("Subgraph is at epoch {subgraph_latest_epoch}");
-        METRICS.set_current_epoch("subgraph", subgraph_latest_epoch as i64);
-        let manager_current_epoch = self.contracts.query_current_epoch().await?;
+        self.metrics
+            .set_current_epoch("subgraph", subgraph_latest_epoch as i64);
+        let manager_current_epoch = match snapshot {
+            Some(snapshot) => {
+                self.metrics
+                    .set_current_epoch("manager", snapshot.current_epoch as i64);
+                snapshot.current_epoch
+            }
+            None => self.query_current_epoch().await?,
+        };
+        self.metrics.set_subgraph_epochs_behind(
+            manager_current_epoch.saturating_sub(subgraph_latest_epoch) as i64,
+        );
         match subgraph_latest_epoch.cmp(&manager_current_epoch) {
             Ordering::Less => Ok(PreviousEpoch {
                 subgraph_latest_indexed_block,
+                subgraph_epoch: subgraph_latest_epoch,
+                manager_epoch: manager_current_epoch,
             }),
             Ordering::Equal => Ok(SameEpoch),
             Ordering::Greater => Err(Error::EpochManagerBehindSubgraph {
@@ -150,17 +431,76 @@ impl Oracle {
         }
     }
 
+    /// Attempts a recovery if the subgraph fell too far behind the Epoch Manager. See
+    /// [`missed_epochs`](crate::runner::missed_epochs).
+    async fn watch_missed_epochs(
+        &mut self,
+        subgraph_state: &SubgraphState,
+        subgraph_epoch: u64,
+        manager_epoch: u64,
+    ) -> Result<(), Error> {
+        let Some(watchdog) = &mut self.missed_epochs else {
+            return Ok(());
+        };
+        if !watchdog.check(subgraph_epoch, manager_epoch) {
+            return Ok(());
+        }
+        warn!(
+            subgraph_epoch,
+            manager_epoch, "The subgraph stopped advancing. Attempting a recovery."
+        );
+
+        // Reconcile with the subgraph first, in case our own records are off too.
+        self.history
+            .report_discrepancies(subgraph_state, &self.metrics);
+        let latest_payload = self.history.latest_payload();
+        let receipt = match latest_payload {
+            Some(payload) => self
+                .protocol_chain
+                .web3
+                .eth()
+                .transaction_receipt(payload.tx_hash)
+                .await
+                .map_err(Error::BadJrpcProtocolChain)?,
+            None => None,
+        };
+        Err(Error::MissedEpochs {
+            subgraph: subgraph_epoch,
+            manager: manager_epoch,
+            diagnosis: diagnose(subgraph_state, latest_payload, receipt.as_ref()),
+        })
+    }
+
     async fn handle_new_epoch(&mut self, subgraph_state: &SubgraphState) -> Result<(), Error> {
         info!("Entering a new epoch.");
         info!("Collecting latest block information from all indexed chains.");
 
         let latest_jrpc_blocks_res = get_latest_blocks(&self.indexed_chains).await;
+        let latest_blockmeta_blocks_res =
+            get_latest_blockmeta_blocks(&self.blockmeta_indexed_chains).await;
+        let outcomes = latest_jrpc_blocks_res
+            .iter()
+            .map(|(chain_id, res)| (chain_id, res.is_ok()))
+            .chain(
+                latest_blockmeta_blocks_res
+                    .iter()
+                    .map(|(chain_id, res)| (chain_id, res.is_ok())),
+            );
+        for (chain_id, ok) in outcomes {
+            let dependency = Dependency::IndexedChain(chain_id.clone());
+            if ok {
+                self.error_budget.record_success(dependency);
+            } else {
+                self.error_budget.record_failure(dependency);
+            }
+        }
+
         let latest_jrpc_blocks: BTreeMap<Caip2ChainId, BlockPtr> = latest_jrpc_blocks_res
             .iter()
             .filter_map(|(chain_id, res)| -> Option<(Caip2ChainId, BlockPtr)> {
                 match res {
                     Ok(block) => {
-                        METRICS.set_latest_block_number(
+                        self.metrics.set_latest_block_number(
                             chain_id.as_str(),
                             "jrpc",
                             block.number as i64,
@@ -179,24 +519,23 @@ impl Oracle {
             })
             .collect();
 
-        let latest_blockmeta_blocks_res =
-            get_latest_blockmeta_blocks(&self.blockmeta_indexed_chains).await;
         let latest_blockmeta_blocks: BTreeMap<Caip2ChainId, BlockPtr> = latest_blockmeta_blocks_res
             .iter()
             .filter_map(|(chain_id, res)| -> Option<(Caip2ChainId, BlockPtr)> {
                 match res {
                     Ok(block) => {
-                        METRICS.set_latest_block_number(
+                        self.metrics.set_latest_block_number(
                             chain_id.as_str(),
                             "blockmeta",
                             block.num as i64,
                         );
 
-                        match block.id.clone().parse::<BlockHash>() {
+                        // Blockmeta serves non-EVM chains too, whose hashes aren't necessarily hex.
+                        match normalize_block_hash(chain_id, &block.id) {
                             Ok(hash) => {
                                 let block_ptr = BlockPtr {
                                     number: block.num,
-                                    hash: hash.0,
+                                    hash,
                                 };
                                 Some((chain_id.clone(), block_ptr))
                             }
@@ -222,153 +561,629 @@ impl Oracle {
             })
             .collect();
 
-        let latest_blocks: BTreeMap<Caip2ChainId, BlockPtr> = latest_jrpc_blocks
+        let mut latest_blocks: BTreeMap<Caip2ChainId, BlockPtr> = latest_jrpc_blocks
             .into_iter()
-            .chain(latest_blockmeta_blocks.into_iter())
+            .chain(latest_blockmeta_blocks)
             .collect();
-        let payload = set_block_numbers_for_next_epoch(subgraph_state, latest_blocks);
-        let transaction_receipt = self
-            .contracts
-            .submit_call(payload, &self.config.owner_private_key)
-            .await
-            .map_err(Error::ContractError)?;
-        METRICS.set_last_sent_message();
-        info!(
-            tx_hash = ?transaction_receipt.transaction_hash,
-            "Contract call submitted successfully."
+        latest_blocks.retain(|chain_id, _| {
+            let degraded = self
+                .error_budget
+                .is_degraded(&Dependency::IndexedChain(chain_id.clone()));
+            if degraded {
+                warn!(
+                    chain_id = chain_id.as_str(),
+                    "Leaving a degraded chain out of the payload."
+                );
+            }
+            !degraded
+        });
+        let new_networks = unregistered_networks(
+            subgraph_state,
+            &mut latest_blocks,
+            self.config.register_new_networks,
         );
+        // The subgraph attributes the new blocks to the Epoch Manager's current epoch at the time
+        // it processes our transaction.
+        let epoch = self.query_current_epoch().await?;
+        let encoding_version = self.update_encoding_version(subgraph_state, epoch).await?;
+        let (batches, stats) = encode_new_epoch(
+            subgraph_state,
+            encoding_version,
+            new_networks,
+            latest_blocks.clone(),
+            self.config.max_payload_bytes.unwrap_or(usize::MAX),
+            self.config.acceleration_overflow,
+        )?;
+        self.metrics.set_compression_stats(&stats);
+        let payloads = batches.into_iter().map(|batch| Payload {
+            epoch,
+            messages: batch.messages.iter().map(ToString::to_string).collect(),
+            bytes: batch.payload,
+            network_count: batch.network_count as u64,
+            encoding_version,
+            subgraph_block: Some(subgraph_state.last_indexed_block_number),
+        });
+        self.submit_payloads(epoch, payloads, &latest_blocks).await
+    }
 
-        // TODO: After broadcasting a transaction to the protocol chain and getting a transaction
-        // receipt, we should monitor it until it get enough confirmations. It's unclear which
-        // component should do this task.
-
+    /// Submits `payloads` in order, as every one only decodes after the previous one, so a failure
+    /// stops the rest. Each payload is recorded as soon as it's confirmed, so that the ones that
+    /// landed stay in the history and accounting even if a later one fails. `latest_blocks` are
+    /// recorded along with the payload that sets them.
+    async fn submit_payloads(
+        &mut self,
+        epoch: u64,
+        payloads: impl IntoIterator<Item = Payload>,
+        latest_blocks: &BTreeMap<Caip2ChainId, BlockPtr>,
+    ) -> Result<(), Error> {
+        for payload in payloads {
+            // Nothing new was published, e.g. in dry runs.
+            let Some(published_payload) = self.submit_payload(payload).await? else {
+                continue;
+            };
+            let sets_block_numbers = published_payload.messages.iter().any(|message| {
+                matches!(
+                    message.as_str(),
+                    "SetBlockNumbersForNextEpoch" | "RegisterNetworksAndSetBlockNumbers"
+                )
+            });
+            let recorded = if sets_block_numbers {
+                self.history
+                    .record(epoch, latest_blocks, &self.correlation_id)
+            } else {
+                Ok(())
+            };
+            let recorded = recorded.and_then(|()| self.history.record_payload(published_payload));
+            if let Err(error) = recorded {
+                error!(
+                    %error,
+                    "Failed to record the published blocks. The history is now incomplete."
+                );
+            }
+            let payloads = self.history.payloads().iter();
+            let costs = Costs::of(payloads.filter(|payload| payload.epoch == epoch));
+            self.metrics.set_epoch_cost(&costs);
+        }
         Ok(())
     }
 
-    /// Queries the Protocol Chain for the current balance of the Owner's account.
+    /// Brings the subgraph's encoding version up to [`CURRENT_ENCODING_VERSION`]. This needs a
+    /// payload of its own, as the subgraph stops decoding a payload after an `UpdateVersion`
+    /// message.
     ///
-    /// Used for monitoring and logging.
-    async fn query_owner_eth_balance(&self) -> Result<(), Error> {
-        let balance = self
+    /// Returns the encoding version that the subgraph decodes the next payload with.
+    async fn update_encoding_version(
+        &mut self,
+        subgraph_state: &SubgraphState,
+        epoch: u64,
+    ) -> Result<u64, Error> {
+        let Some(global_state) = &subgraph_state.global_state else {
+            // An uninitialized subgraph decodes any supported version.
+            return Ok(CURRENT_ENCODING_VERSION);
+        };
+        let mut encoder = subgraph_encoder(global_state)?;
+        let encoding_version = encoder.encoding_version();
+        let Some(message) = encoder.update_version_message() else {
+            return Ok(encoding_version);
+        };
+
+        info!(
+            from = encoding_version,
+            to = CURRENT_ENCODING_VERSION,
+            "Updating the encoding version."
+        );
+        // Unwrap: `update_version_message` only returns supported versions.
+        let compressed = encoder.compress(&[message]).unwrap();
+        let payload = Payload {
+            epoch,
+            messages: vec!["UpdateVersion".to_string()],
+            bytes: encoder.encode(&compressed)?,
+            network_count: global_state.networks.len() as u64,
+            encoding_version,
+            subgraph_block: Some(subgraph_state.last_indexed_block_number),
+        };
+        let Some(published_payload) = self.submit_payload(payload).await? else {
+            return Ok(encoder.encoding_version());
+        };
+        if let Err(error) = self.history.record_payload(published_payload) {
+            error!(
+                %error,
+                "Failed to record the published payload. The history is now incomplete."
+            );
+        }
+        Ok(encoder.encoding_version())
+    }
+
+    /// Submits `payload` and returns its record for the history, unless it wasn't sent, or an
+    /// identical payload landed earlier, which was already accounted for. Reverted transactions are
+    /// recorded right away, since their payload isn't published.
+    async fn submit_payload(
+        &mut self,
+        payload: Payload,
+    ) -> Result<Option<PublishedPayload>, Error> {
+        self.verify_chain_id().await?;
+        // The hash and nonce of the transaction, once it's sent.
+        let mut sent_as = None;
+        let result = match self.submitter.send(&payload).await {
+            Ok(sent) => {
+                sent_as = sent.tx_hash().zip(sent.nonce());
+                if let Some((tx_hash, nonce)) = sent_as {
+                    info!(?tx_hash, %nonce, "Waiting for the transaction to be confirmed.");
+                }
+                self.submitter.wait_for_confirmation(sent).await
+            }
+            Err(error) => Err(error),
+        };
+        let mut published_payload = PublishedPayload {
+            epoch: payload.epoch,
+            timestamp: UNIX_EPOCH.elapsed().unwrap().as_secs(),
+            correlation_id: self.correlation_id.to_string(),
+            messages: payload.messages,
+            payload: hex_string(&payload.bytes),
+            tx_hash: Default::default(),
+            status: SubmissionStatus::Confirmed,
+            nonce: None,
+            block_number: None,
+            gas_used: None,
+            effective_gas_price: None,
+        };
+        let submission = match result {
+            Ok(SubmissionOutcome::Confirmed(submission)) => submission,
+            Ok(SubmissionOutcome::AlreadyLanded(submission)) => {
+                info!(
+                    tx_hash = ?submission.receipt.transaction_hash,
+                    nonce = %submission.nonce,
+                    "The payload already landed."
+                );
+                return Ok(None);
+            }
+            Ok(SubmissionOutcome::NotSent) => return Ok(None),
+            Err(error) => {
+                let failed = match &error {
+                    SubmitterError::Contract(ContractError::TransactionMonitor(
+                        TransactionMonitorError::Reverted(reverted),
+                    )) => {
+                        published_payload.tx_hash = reverted.tx_hash;
+                        published_payload.status = SubmissionStatus::Reverted;
+                        published_payload.nonce = Some(reverted.nonce);
+                        published_payload.block_number = Some(reverted.block_number.as_u64());
+                        published_payload.gas_used = reverted.gas_used;
+                        published_payload.effective_gas_price = reverted.effective_gas_price;
+                        true
+                    }
+                    error => match (sent_as, unmined_status(error)) {
+                        (Some((tx_hash, nonce)), Some(status)) => {
+                            published_payload.tx_hash = tx_hash;
+                            published_payload.status = status;
+                            published_payload.nonce = Some(nonce);
+                            true
+                        }
+                        _ => false,
+                    },
+                };
+                if failed {
+                    let status = published_payload.status;
+                    self.metrics.record_costs(&Costs::of([&published_payload]));
+                    if let Err(error) = self.history.record_payload(published_payload) {
+                        error!(
+                            %error,
+                            %status,
+                            "Failed to record the failed payload. The history is now incomplete."
+                        );
+                    }
+                }
+                return Err(error.into());
+            }
+        };
+        let receipt = submission.receipt;
+        self.metrics.set_last_sent_message();
+        info!(
+            tx_hash = ?receipt.transaction_hash,
+            nonce = %submission.nonce,
+            "Contract call submitted successfully."
+        );
+        published_payload.tx_hash = receipt.transaction_hash;
+        published_payload.nonce = Some(submission.nonce);
+        published_payload.block_number = receipt.block_number.map(|number| number.as_u64());
+        published_payload.gas_used = receipt.gas_used;
+        published_payload.effective_gas_price = receipt.effective_gas_price;
+        self.metrics.record_costs(&Costs::of([&published_payload]));
+        Ok(Some(published_payload))
+    }
+
+    /// Makes sure that the protocol chain's provider is on the configured chain, since
+    /// transactions are signed for whatever `eth_chainId` returns. Endpoints may be rotated at
+    /// runtime, so this is checked before every submission.
+    pub async fn verify_chain_id(&self) -> Result<U256, Error> {
+        let chain_id = self
             .protocol_chain
             .web3
             .eth()
-            .balance(self.config.owner_address, None)
+            .chain_id()
             .await
             .map_err(Error::BadJrpcProtocolChain)?;
+        check_chain_id(&self.protocol_chain.chain_id, chain_id)?;
+        Ok(chain_id)
+    }
+
+    async fn query_current_epoch(&self) -> Result<u64, Error> {
+        let current_epoch = self.contracts.query_current_epoch().await?;
+        self.metrics
+            .set_current_epoch("manager", current_epoch as i64);
+        Ok(current_epoch)
+    }
+
+    /// Queries the Protocol Chain for the current balance of the Owner's account.
+    ///
+    /// Used for monitoring and logging.
+    async fn query_owner_eth_balance(
+        &self,
+        snapshot: Option<&ProtocolChainSnapshot>,
+    ) -> Result<(), Error> {
+        let balance = match snapshot {
+            Some(snapshot) => snapshot.owner_balance,
+            None => self
+                .protocol_chain
+                .web3
+                .eth()
+                .balance(self.config.owner_address, None)
+                .await
+                .map_err(Error::BadJrpcProtocolChain)?,
+        };
 
         info!("Owner ETH Balance is {} gwei", balance);
 
         // overflow check
-        METRICS.set_wallet_balance(i64::try_from(balance).unwrap_or(i64::MAX));
+        self.metrics
+            .set_wallet_balance(i64::try_from(balance).unwrap_or(i64::MAX));
 
         Ok(())
     }
 }
 
-fn set_block_numbers_for_next_epoch(
+/// An encoder for the subgraph's current encoding version. Fails if the subgraph reports a version
+/// that this oracle can't write, e.g. because a newer oracle already updated it.
+fn subgraph_encoder(global_state: &GlobalState) -> Result<Encoder, Error> {
+    let encoding_version = u64::try_from(global_state.encoding_version).map_err(|_| {
+        SubgraphQueryError::BadData(anyhow::anyhow!(
+            "negative encoding version {}",
+            global_state.encoding_version
+        ))
+    })?;
+    Ok(Encoder::new(encoding_version, vec![])?)
+}
+
+/// Returns the networks in `latest_blocks` that the subgraph hasn't registered, to `register`.
+/// Otherwise, we're not interested in them, so they're logged and discarded.
+fn unregistered_networks(
     subgraph_state: &SubgraphState,
-    mut latest_blocks: BTreeMap<Caip2ChainId, BlockPtr>,
-) -> Vec<u8> {
+    latest_blocks: &mut BTreeMap<Caip2ChainId, BlockPtr>,
+    register: bool,
+) -> Vec<Caip2ChainId> {
     let registered_networks = subgraph_state
         .global_state
         .as_ref()
-        .map(|gs| gs.networks.clone())
+        .map(|gs| gs.networks.as_slice())
         // In case the subgraph is uninitialized, there's effectively no registered networks at all.
         .unwrap_or_default();
 
-    // We're not interested in unregistered networks. So we isolate them into a separate
-    // collection, log them, and finally discard them.
-    let mut ignored_networks = Vec::new();
+    let mut unregistered_networks = Vec::new();
     for chain_id in latest_blocks.keys().cloned() {
         if !registered_networks
             .iter()
             .any(|network| network.id == chain_id)
         {
-            ignored_networks.push(chain_id);
+            unregistered_networks.push(chain_id);
         }
     }
-    if !ignored_networks.is_empty() {
-        warn!(
-            ignored_networks = ?ignored_networks,
-            "Multiple networks present in the configuration file are not registered"
-        );
+    if unregistered_networks.is_empty() {
+        return unregistered_networks;
+    }
+    if register {
+        info!(new_networks = ?unregistered_networks, "Registering new networks.");
+        return unregistered_networks;
     }
-    for chain_id in ignored_networks {
+    warn!(
+        ignored_networks = ?unregistered_networks,
+        "Multiple networks present in the configuration file are not registered"
+    );
+    for chain_id in unregistered_networks {
         latest_blocks.remove(&chain_id);
     }
+    vec![]
+}
 
-    let message = Message::SetBlockNumbersForNextEpoch(
-        latest_blocks
-            .into_iter()
-            .map(|(chain_id, block_ptr)| (chain_id.as_str().to_owned(), block_ptr))
-            .collect(),
-    );
+/// Encodes the messages of a new epoch in the layout of `encoding_version`, which must be the one
+/// the subgraph currently decodes, into payloads of at most `max_payload_bytes`: a
+/// `RegisterNetworks` message for `new_networks`, if there are any, and a
+/// `SetBlockNumbersForNextEpoch` message for `latest_blocks`, which must only contain registered
+/// networks and `new_networks`; see [`unregistered_networks`]. The two are merged into a single
+/// message if it fits in a payload, see [`batch`].
+///
+/// Also measures the messages as if they were a single payload.
+fn encode_new_epoch(
+    subgraph_state: &SubgraphState,
+    encoding_version: u64,
+    new_networks: Vec<Caip2ChainId>,
+    latest_blocks: BTreeMap<Caip2ChainId, BlockPtr>,
+    max_payload_bytes: usize,
+    overflow_policy: OverflowPolicy,
+) -> Result<(Vec<Batch>, CompressionStats), Error> {
+    let registered_networks = subgraph_state
+        .global_state
+        .as_ref()
+        .map(|gs| gs.networks.clone())
+        // In case the subgraph is uninitialized, there's effectively no registered networks at all.
+        .unwrap_or_default();
+
+    // Unwrap: `latest_blocks` is keyed by chain ID, so there can't be duplicates.
+    let message = SetBlockNumbersBuilder::new()
+        .block_ptrs(latest_blocks)
+        .unwrap()
+        .build();
+    let mut messages = vec![];
+    if !new_networks.is_empty() {
+        messages.push(Message::RegisterNetworks {
+            remove: vec![],
+            add: new_networks
+                .iter()
+                .map(|chain_id| chain_id.as_str().to_owned())
+                .collect(),
+        });
+    }
+    messages.push(message.clone());
     let available_networks: Vec<(String, epoch_encoding::Network)> = {
         registered_networks
-            .into_iter()
+            .iter()
+            .cloned()
             .map(|network| (network.id.as_str().to_owned(), network.into()))
             .collect()
     };
 
     debug!(
-        message = ?message,
+        messages = ?messages,
         networks = ?available_networks,
         networks_count = available_networks.len(),
-        "Compressing 'SetBlockNumbersForNextEpoch'"
+        "Compressing the messages of the new epoch"
     );
 
-    let mut compression_engine = Encoder::new(CURRENT_ENCODING_VERSION, available_networks)
-        .expect("Can't prepare for encoding because something went wrong.");
+    let mut compression_engine =
+        Encoder::new(encoding_version, available_networks)?.with_overflow_policy(overflow_policy);
     let compression_engine_initially = compression_engine.clone();
 
-    let compressed = compression_engine
-        .compress(&[message])
-        .unwrap_or_else(|error| panic!("Encoding failed. Error: {error}"));
-    debug!(
-        compressed = ?compressed,
-        networks = ?compression_engine.network_deltas(),
-        "Successfully compressed 'SetBlockNumbersForNextEpoch'"
-    );
-    let encoded = compression_engine.encode(&compressed);
+    let stats = {
+        let mut encoder = compression_engine.clone();
+        let compressed = encoder.compress(&messages)?;
+        encoder.encode_with_stats(&compressed)?.1
+    };
     debug!(
-        encoded = hex_string(&encoded).as_str(),
-        "Successfully encoded 'SetBlockNumbersForNextEpoch'"
+        bytes = stats.total_bytes,
+        compression_ratio = stats.compression_ratio(),
+        "Measured the messages of the new epoch"
     );
 
-    assert_ne!(
-        compression_engine, compression_engine_initially,
-        "The encoder has identical internal state compared to what \
-            it had before these new messages. This is a bug!"
-    );
+    let batches = batch(&mut compression_engine, messages, max_payload_bytes)?;
+    for Batch {
+        messages,
+        payload,
+        network_count,
+    } in &batches
+    {
+        debug!(
+            ?messages,
+            encoded = hex_string(payload).as_str(),
+            "Successfully encoded the messages of the new epoch"
+        );
+        match inspect(payload, *network_count as u64, encoding_version) {
+            Ok(segments) => {
+                for segment in segments {
+                    debug!(%segment, "Payload segment");
+                }
+            }
+            Err(e) => error!(error = %e, "Failed to decode our own payload. This is a bug!"),
+        }
+    }
+    for entry in compression_engine.network_table() {
+        debug!(
+            chain_id = entry.chain_id,
+            index = entry.index,
+            block_number = entry.block_number,
+            block_delta = entry.block_delta,
+            "Network after the new epoch"
+        );
+        // New networks aren't in the subgraph yet.
+        let Some(subgraph_index) = registered_networks
+            .iter()
+            .find(|network| network.id.as_str() == entry.chain_id)
+            .map(|network| network.array_index)
+        else {
+            continue;
+        };
+        if subgraph_index != entry.index {
+            error!(
+                chain_id = entry.chain_id,
+                index = entry.index,
+                subgraph_index,
+                "The encoder and the Epoch Subgraph disagree on a network's index. This is a bug!"
+            );
+        }
+    }
+
+    if compression_engine == compression_engine_initially {
+        return Err(Error::EncoderUnchanged {
+            message: message.name(),
+        });
+    }
 
-    encoded
+    Ok((batches, stats))
 }
 
-fn protocol_chain(config: &Config) -> JrpcProviderForChain<JrpcExpBackoff> {
+/// How a transaction that was sent, but never mined, ended according to `error`, if it did.
+fn unmined_status(error: &SubmitterError) -> Option<SubmissionStatus> {
+    match error {
+        SubmitterError::Contract(ContractError::TransactionMonitor(error)) => match error {
+            TransactionMonitorError::BroadcastFailure => Some(SubmissionStatus::Dropped),
+            TransactionMonitorError::Replaced { .. } => Some(SubmissionStatus::Replaced),
+            TransactionMonitorError::Cancelled { .. } => Some(SubmissionStatus::Cancelled),
+            _ => None,
+        },
+        SubmitterError::Contract(ContractError::Defender(
+            DefenderError::Failed(_) | DefenderError::Timeout { .. },
+        )) => Some(SubmissionStatus::Dropped),
+        _ => None,
+    }
+}
+
+/// Only `eip155` chains have an `eth_chainId`, so any other configured ID is a mismatch.
+fn check_chain_id(configured: &Caip2ChainId, actual: U256) -> Result<(), Error> {
+    if eip155_chain_id(configured) == Some(actual) {
+        return Ok(());
+    }
+    Err(Error::ChainIdMismatch {
+        configured: configured.clone(),
+        actual,
+    })
+}
+
+fn protocol_chain(config: &Config, metrics: &Metrics) -> JrpcProviderForChain<JrpcExpBackoff> {
     let transport = JrpcExpBackoff::http(
         config.protocol_chain.jrpc_url.clone(),
         config.protocol_chain.id.clone(),
         config.retry_strategy_max_wait_time,
-    );
+        metrics.clone(),
+    )
+    .with_rate_limit(config.protocol_chain.rate_limit);
     JrpcProviderForChain::new(config.protocol_chain.id.clone(), transport)
 }
 
-fn indexed_chains(config: &Config) -> Vec<JrpcProviderForChain<JrpcExpBackoff>> {
+fn indexed_chains(config: &Config, metrics: &Metrics) -> Vec<JrpcProviderForChain<JrpcExpBackoff>> {
     config
         .indexed_chains
         .iter()
-        .map(|chain| {
-            let transport = JrpcExpBackoff::http(
-                chain.jrpc_url.clone(),
-                chain.id.clone(),
-                config.retry_strategy_max_wait_time,
-            );
-            JrpcProviderForChain::new(chain.id.clone(), transport)
+        .flat_map(|chain| {
+            let providers = chain
+                .jrpc_urls
+                .iter()
+                .map(|jrpc_url| {
+                    let transport = JrpcExpBackoff::http(
+                        jrpc_url.clone(),
+                        chain.id.clone(),
+                        config.retry_strategy_max_wait_time,
+                        metrics.clone(),
+                    )
+                    .with_rate_limit(chain.rate_limit);
+                    JrpcProviderForChain::new(chain.id.clone(), transport)
+                        .with_block_selection(chain.block_selection)
+                        .with_polling_interval(chain.polling_interval)
+                })
+                .collect();
+            with_failover(chain, providers)
+        })
+        .collect()
+}
+
+/// The providers for the indexed chains of `config`, reusing the `current` ones where possible.
+/// Their transports are shared, e.g. with the block time sampler, so changed URLs are rotated
+/// rather than replaced.
+fn reload_indexed_chains(
+    current: &[JrpcProviderForChain<JrpcExpBackoff>],
+    config: &Config,
+    metrics: &Metrics,
+) -> Vec<JrpcProviderForChain<JrpcExpBackoff>> {
+    config
+        .indexed_chains
+        .iter()
+        .flat_map(|chain| {
+            let mut existing = current
+                .iter()
+                .filter(|provider| provider.chain_id == chain.id);
+            let providers = chain
+                .jrpc_urls
+                .iter()
+                .map(|jrpc_url| {
+                    let Some(provider) = existing.next() else {
+                        let transport = JrpcExpBackoff::http(
+                            jrpc_url.clone(),
+                            chain.id.clone(),
+                            config.retry_strategy_max_wait_time,
+                            metrics.clone(),
+                        )
+                        .with_rate_limit(chain.rate_limit);
+                        return JrpcProviderForChain::new(chain.id.clone(), transport);
+                    };
+                    let transport = provider.web3.transport();
+                    transport.set_rate_limit(chain.rate_limit);
+                    if transport.url().as_ref() != Some(jrpc_url) {
+                        if let Err(error) = transport.rotate_url(jrpc_url) {
+                            warn!(
+                                chain_id = %chain.id,
+                                %error,
+                                "Failed to create a transport for the new URL. Keeping the \
+                                 current one."
+                            );
+                        }
+                    }
+                    provider.clone()
+                })
+                .map(|provider| {
+                    provider
+                        .with_block_selection(chain.block_selection)
+                        .with_polling_interval(chain.polling_interval)
+                })
+                .collect::<Vec<_>>();
+            with_failover(chain, providers)
         })
         .collect()
 }
 
+/// Lets the first of a `chain`'s providers fail over to the others, or undoes that if the chain
+/// doesn't have failover (anymore).
+fn with_failover(
+    chain: &IndexedChain,
+    providers: Vec<JrpcProviderForChain<JrpcExpBackoff>>,
+) -> Vec<JrpcProviderForChain<JrpcExpBackoff>> {
+    let transports = transports(&providers);
+    if let Some((first, fallbacks)) = transports.split_first() {
+        first.set_failover(chain.failover_cooldown.map(|cooldown| Failover {
+            fallbacks: fallbacks.to_vec(),
+            cooldown,
+        }));
+    }
+    providers
+        .into_iter()
+        .map(|provider| provider.with_failover(chain.failover_cooldown.is_some()))
+        .collect()
+}
+
+fn transports(providers: &[JrpcProviderForChain<JrpcExpBackoff>]) -> Vec<JrpcExpBackoff> {
+    providers
+        .iter()
+        .map(|provider| provider.web3.transport().clone())
+        .collect()
+}
+
+fn log_indexed_chain_changes(previous: &[IndexedChain], reloaded: &[IndexedChain]) {
+    let urls = |chains: &[IndexedChain]| -> BTreeMap<Caip2ChainId, Vec<Url>> {
+        chains
+            .iter()
+            .map(|chain| (chain.id.clone(), chain.jrpc_urls.clone()))
+            .collect()
+    };
+    let (previous, reloaded) = (urls(previous), urls(reloaded));
+    for chain_id in previous.keys().filter(|id| !reloaded.contains_key(*id)) {
+        info!(%chain_id, "The indexed chain was removed.");
+    }
+    for (chain_id, urls) in &reloaded {
+        match previous.get(chain_id) {
+            None => info!(%chain_id, "The indexed chain was added."),
+            Some(previous) if previous != urls => {
+                info!(%chain_id, "The indexed chain's JSON RPC endpoints were reloaded.")
+            }
+            Some(_) => {}
+        }
+    }
+}
+
 fn blockmeta_indexed_chains(
     config: &Config,
 ) -> Vec<BlockmetaProviderForChain<InterceptedService<Channel, AuthInterceptor>>> {
@@ -386,8 +1201,11 @@ fn blockmeta_indexed_chains(
 }
 
 mod freshness {
+    use crate::config::{FreshnessCheckOptions, FreshnessScan};
     use crate::models::JrpcProviderForChain;
-    use crate::runner::jrpc_utils::calls_in_block_range;
+    use crate::runner::jrpc_utils::{
+        calls_in_block_range, logs_in_block_range, traces_in_block_range,
+    };
     use tracing::{debug, trace};
     use web3::types::{H160, U64};
 
@@ -408,7 +1226,7 @@ mod freshness {
         protocol_chain: JrpcProviderForChain<T>,
         owner_address: H160,
         contract_address: H160,
-        freshness_threshold: u64,
+        options: &FreshnessCheckOptions,
     ) -> web3::Result<bool>
     where
         T: web3::Transport,
@@ -420,7 +1238,7 @@ mod freshness {
         let block_distance = (current_block - subgraph_latest_block).as_u64();
         if block_distance == 0 {
             return Ok(true);
-        } else if block_distance > freshness_threshold {
+        } else if block_distance > options.threshold {
             debug!(
                 %subgraph_latest_block,
                 %current_block,
@@ -431,15 +1249,32 @@ mod freshness {
             return Ok(false);
         }
         // Scan the blocks in betwenn for transactions from the Owner to the Data Edge contract
-        let calls = calls_in_block_range(
-            protocol_chain.web3,
-            subgraph_latest_block.as_u64()..=current_block.as_u64(),
-            owner_address,
-            contract_address,
-        )
-        .await?;
+        let block_range = subgraph_latest_block.as_u64()..=current_block.as_u64();
+        let calls = match options.scan {
+            FreshnessScan::Transactions => calls_in_block_range(
+                protocol_chain.web3,
+                block_range,
+                owner_address,
+                contract_address,
+            )
+            .await?
+            .len(),
+            FreshnessScan::Traces => traces_in_block_range(
+                protocol_chain.web3,
+                block_range,
+                owner_address,
+                contract_address,
+            )
+            .await?
+            .len(),
+            FreshnessScan::Logs => {
+                logs_in_block_range(protocol_chain.web3, block_range, contract_address)
+                    .await?
+                    .len()
+            }
+        };
 
-        if calls.is_empty() {
+        if calls == 0 {
             trace!(
                 %subgraph_latest_block,
                 %current_block,
@@ -453,7 +1288,7 @@ mod freshness {
                 %current_block,
                 "Epoch Subgraph is not fresh. \
                  Found {} calls between the last synced block and the protocol chain's head",
-                calls.len()
+                calls
             );
             Ok(false)
         }
@@ -467,7 +1302,511 @@ enum NewEpochCheck {
     /// message.
     RecentlyInitialized,
     /// The Epoch Subgraph is at a previous epoch than the Epoch Manager.
-    PreviousEpoch { subgraph_latest_indexed_block: u64 },
+    PreviousEpoch {
+        subgraph_latest_indexed_block: u64,
+        subgraph_epoch: u64,
+        manager_epoch: u64,
+    },
     /// The Epoch Subgraph is at the same epoch as the Epoch Manager.
     SameEpoch,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::Submission;
+    use crate::runner::error_handling::{ErrorClass, MainLoopFlow};
+    use crate::submitter::SentTransaction;
+    use crate::test_utils::jrpc_provider;
+    use crate::transaction_monitor::RevertedTransaction;
+    use async_trait::async_trait;
+    use epoch_encoding::EncodeError;
+    use serde_json::json;
+    use std::cell::RefCell;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use url::Url;
+    use web3::types::{TransactionReceipt, H256, U64};
+
+    /// Records payloads and answers with `results`, in order.
+    #[derive(Default)]
+    struct MockSubmitter {
+        payloads: RefCell<Vec<Vec<u8>>>,
+        results: RefCell<Vec<Result<SubmissionOutcome, ContractError>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl TransactionSubmitter for &MockSubmitter {
+        async fn send<'a>(
+            &'a self,
+            payload: &Payload,
+        ) -> Result<SentTransaction<'a>, SubmitterError> {
+            self.payloads.borrow_mut().push(payload.bytes.clone());
+            let pending = |result| {
+                let confirmation = futures::future::ready(result);
+                SentTransaction::pending(H256::repeat_byte(9), U256::from(9), confirmation)
+            };
+            Ok(match self.results.borrow_mut().remove(0) {
+                Ok(SubmissionOutcome::Confirmed(submission)) => pending(Ok(*submission)),
+                Ok(SubmissionOutcome::AlreadyLanded(submission)) => {
+                    SentTransaction::already_landed(*submission)
+                }
+                Ok(SubmissionOutcome::NotSent) => SentTransaction::not_sent(),
+                Err(error) => pending(Err(error)),
+            })
+        }
+
+        async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError> {
+            Ok(())
+        }
+    }
+
+    /// A JSON RPC provider whose `eth_chainId` is `chain_id`.
+    async fn provider(chain_id: u64) -> Url {
+        jrpc_provider(move |method, _| match method {
+            "eth_chainId" => json!(format!("{chain_id:#x}")),
+            method => panic!("unexpected method {method}"),
+        })
+        .await
+    }
+
+    fn payload(bytes: Vec<u8>) -> Payload {
+        Payload {
+            epoch: 5,
+            messages: vec!["SetBlockNumbersForNextEpoch".to_string()],
+            bytes,
+            network_count: 0,
+            encoding_version: CURRENT_ENCODING_VERSION,
+            subgraph_block: None,
+        }
+    }
+
+    fn submission(tx_hash: H256, nonce: u64) -> Box<Submission> {
+        Box::new(Submission {
+            receipt: TransactionReceipt {
+                transaction_hash: tx_hash,
+                block_number: Some(100.into()),
+                gas_used: Some(50_000.into()),
+                status: Some(1.into()),
+                ..Default::default()
+            },
+            nonce: nonce.into(),
+        })
+    }
+
+    #[tokio::test]
+    async fn submissions() {
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        config.protocol_chain.jrpc_url = provider(1).await;
+        let submitter = MockSubmitter::default();
+        let reverted = TransactionMonitorError::Reverted(Box::new(RevertedTransaction {
+            tx_hash: H256::repeat_byte(2),
+            block_number: U64::from(101),
+            nonce: U256::from(8),
+            gas_used: Some(60_000.into()),
+            effective_gas_price: Some(2_000_000_000u64.into()),
+        }));
+        *submitter.results.borrow_mut() = vec![
+            Ok(SubmissionOutcome::Confirmed(submission(
+                H256::repeat_byte(1),
+                7,
+            ))),
+            Err(ContractError::TransactionMonitor(reverted)),
+            Ok(SubmissionOutcome::AlreadyLanded(submission(
+                H256::repeat_byte(1),
+                7,
+            ))),
+            Err(ContractError::TransactionMonitor(
+                TransactionMonitorError::Cancelled {
+                    tx_hash: H256::repeat_byte(3),
+                    nonce: U256::from(9),
+                },
+            )),
+            Err(ContractError::TransactionMonitor(
+                TransactionMonitorError::BroadcastFailure,
+            )),
+        ];
+        let mut oracle =
+            Oracle::with_submitter(config, Metrics::new().unwrap(), &submitter).unwrap();
+
+        let published = oracle
+            .submit_payload(payload(vec![1, 2]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(published.tx_hash, H256::repeat_byte(1));
+        assert_eq!(published.nonce, Some(7.into()));
+        assert_eq!(published.block_number, Some(100));
+        assert_eq!(published.status, SubmissionStatus::Confirmed);
+
+        // Reverted payloads are recorded right away.
+        assert!(oracle.submit_payload(payload(vec![3])).await.is_err());
+        let recorded = oracle.history.latest_payload().unwrap();
+        assert_eq!(recorded.tx_hash, H256::repeat_byte(2));
+        assert_eq!(recorded.status, SubmissionStatus::Reverted);
+
+        // Payloads that landed before were recorded back then.
+        let published = oracle.submit_payload(payload(vec![1, 2])).await.unwrap();
+        assert_eq!(published, None);
+        assert_eq!(oracle.history.payloads().len(), 1);
+
+        // So are transactions that were sent, but never mined.
+        assert!(oracle.submit_payload(payload(vec![4])).await.is_err());
+        let recorded = oracle.history.latest_payload().unwrap();
+        assert_eq!(recorded.tx_hash, H256::repeat_byte(9));
+        assert_eq!(recorded.nonce, Some(9.into()));
+        assert_eq!(recorded.status, SubmissionStatus::Cancelled);
+        assert!(oracle.submit_payload(payload(vec![5])).await.is_err());
+        let recorded = oracle.history.latest_payload().unwrap();
+        assert_eq!(recorded.status, SubmissionStatus::Dropped);
+
+        assert_eq!(
+            *submitter.payloads.borrow(),
+            [vec![1, 2], vec![3], vec![1, 2], vec![4], vec![5]]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_batches() {
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        config.protocol_chain.jrpc_url = provider(1).await;
+        let submitter = MockSubmitter::default();
+        *submitter.results.borrow_mut() = vec![
+            Ok(SubmissionOutcome::Confirmed(submission(
+                H256::repeat_byte(1),
+                7,
+            ))),
+            Err(ContractError::TransactionMonitor(
+                TransactionMonitorError::BroadcastFailure,
+            )),
+        ];
+        let mut oracle =
+            Oracle::with_submitter(config, Metrics::new().unwrap(), &submitter).unwrap();
+        let ethereum = Caip2ChainId::ethereum_mainnet();
+        let latest_blocks = BTreeMap::from([(ethereum.clone(), BlockPtr::new(100, [1; 32]))]);
+        let payloads = vec![payload(vec![1]), payload(vec![2])];
+
+        assert!(oracle
+            .submit_payloads(5, payloads, &latest_blocks)
+            .await
+            .is_err());
+        // The first batch landed, so it's recorded along with its blocks.
+        let recorded = oracle.history.payloads();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[0].tx_hash, H256::repeat_byte(1));
+        assert_eq!(recorded[0].status, SubmissionStatus::Confirmed);
+        assert_eq!(recorded[1].status, SubmissionStatus::Dropped);
+        assert_eq!(oracle.history.get(5, &ethereum).unwrap().number, 100);
+        assert_eq!(*submitter.payloads.borrow(), [vec![1], vec![2]]);
+    }
+
+    #[tokio::test]
+    async fn wrong_chain() {
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        config.protocol_chain.jrpc_url = provider(42161).await;
+        let submitter = MockSubmitter::default();
+        let mut oracle =
+            Oracle::with_submitter(config, Metrics::new().unwrap(), &submitter).unwrap();
+
+        assert!(matches!(
+            oracle.verify_chain_id().await,
+            Err(Error::ChainIdMismatch { .. })
+        ));
+        assert!(matches!(
+            oracle.submit_payload(payload(vec![1, 2])).await,
+            Err(Error::ChainIdMismatch { .. })
+        ));
+        assert!(submitter.payloads.borrow().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dry_runs() {
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        config.protocol_chain.jrpc_url = provider(1).await;
+        config.dry_run = true;
+        let mut oracle = Oracle::new(config, Metrics::new().unwrap()).unwrap();
+
+        let published = oracle.submit_payload(payload(vec![1, 2])).await.unwrap();
+        assert_eq!(published, None);
+        assert!(oracle.history.latest_payload().is_none());
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        let mut oracle = Oracle::new(config.clone(), Metrics::new().unwrap()).unwrap();
+        let endpoints = oracle.endpoints();
+        let polygon = Caip2ChainId::from_str("eip155:137").unwrap();
+        let polygon_transport = oracle
+            .indexed_chains()
+            .iter()
+            .find(|provider| provider.chain_id == polygon)
+            .unwrap()
+            .web3
+            .transport()
+            .clone();
+
+        let mut reloaded = config.clone();
+        let url: Url = "https://example.net".parse().unwrap();
+        reloaded.protocol_chain.polling_interval = Duration::from_secs(30);
+        reloaded.protocol_chain.jrpc_url = url.clone();
+        reloaded
+            .indexed_chains
+            .retain(|chain| chain.id.as_str() != "spam:42");
+        for chain in &mut reloaded.indexed_chains {
+            if chain.id == polygon {
+                chain.jrpc_urls[0] = url.clone();
+            }
+        }
+        reloaded.indexed_chains.push(IndexedChain {
+            id: Caip2ChainId::ethereum_mainnet(),
+            jrpc_urls: vec![url.clone()],
+            block_selection: Default::default(),
+            polling_interval: Some(Duration::from_secs(60)),
+            rate_limit: None,
+            failover_cooldown: None,
+        });
+        reloaded.metrics_port += 1;
+        oracle.reload(reloaded);
+
+        assert_eq!(
+            oracle.config().protocol_chain.polling_interval,
+            Duration::from_secs(30)
+        );
+        assert_eq!(oracle.config().metrics_port, config.metrics_port);
+        assert_eq!(
+            oracle.protocol_chain.web3.transport().url(),
+            Some(url.clone())
+        );
+        let mut chains: Vec<_> = oracle
+            .indexed_chains()
+            .iter()
+            .map(|provider| provider.chain_id.as_str())
+            .collect();
+        chains.sort();
+        assert_eq!(
+            chains,
+            ["eip155:1", "eip155:100", "eip155:137", "eip155:137"]
+        );
+        let mainnet = oracle
+            .indexed_chains()
+            .iter()
+            .find(|provider| provider.chain_id == Caip2ChainId::ethereum_mainnet())
+            .unwrap();
+        assert_eq!(mainnet.polling_interval, Some(Duration::from_secs(60)));
+        // Changed URLs are rotated, so that clones of the transport follow along.
+        assert_eq!(polygon_transport.url(), Some(url));
+        assert_eq!(endpoints.all().len(), 5);
+    }
+
+    #[test]
+    fn chain_ids() {
+        let arbitrum = Caip2ChainId::from_str("eip155:42161").unwrap();
+        assert!(check_chain_id(&arbitrum, 42161.into()).is_ok());
+        assert!(matches!(
+            check_chain_id(&arbitrum, 1.into()),
+            Err(Error::ChainIdMismatch { actual, .. }) if actual == 1.into()
+        ));
+        let bitcoin = Caip2ChainId::from_str("bip122:000000000019d6689c085ae165831e93").unwrap();
+        assert!(check_chain_id(&bitcoin, 1.into()).is_err());
+    }
+
+    #[test]
+    fn oversized_messages() {
+        let ethereum = Caip2ChainId::ethereum_mainnet();
+        let subgraph_state = SubgraphState {
+            last_indexed_block_number: 1,
+            global_state: Some(GlobalState {
+                networks: vec![crate::subgraph::Network {
+                    id: ethereum.clone(),
+                    array_index: 0,
+                    latest_block_update: None,
+                }],
+                encoding_version: CURRENT_ENCODING_VERSION as i64,
+                latest_epoch_number: Some(1),
+            }),
+            last_payload: None,
+        };
+        let latest_blocks = BTreeMap::from([(ethereum, BlockPtr::new(1_000, [1; 32]))]);
+        let encode = |max_payload_bytes| {
+            encode_new_epoch(
+                &subgraph_state,
+                CURRENT_ENCODING_VERSION,
+                vec![],
+                latest_blocks.clone(),
+                max_payload_bytes,
+                OverflowPolicy::default(),
+            )
+        };
+
+        let (batches, _) = encode(usize::MAX).unwrap();
+        assert_eq!(batches.len(), 1);
+        let error = encode(4).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Encoding(EncodeError::PayloadTooLarge {
+                message: "SetBlockNumbersForNextEpoch",
+                max_bytes: 4,
+                ..
+            })
+        ));
+        assert_eq!(error.class(), ErrorClass::PayloadTooLarge);
+    }
+
+    #[tokio::test]
+    async fn new_networks_in_split_batches() {
+        let sent_nonces = Arc::new(Mutex::new(vec![]));
+        let url = {
+            let sent_nonces = sent_nonces.clone();
+            // The provider doesn't count the confirmed transactions yet.
+            jrpc_provider(move |method, params| match method {
+                "eth_chainId" => json!("0x1"),
+                "eth_getTransactionCount" => json!("0x5"),
+                "eth_getCode" => json!("0x00"),
+                "eth_call" => json!("0x"),
+                "eth_estimateGas" => json!("0x5208"),
+                "eth_getBlockByNumber" => json!(null),
+                "eth_feeHistory" => json!({
+                    "oldestBlock": "0x1",
+                    "baseFeePerGas": ["0x0"],
+                    "gasUsedRatio": [],
+                }),
+                "eth_gasPrice" => json!("0x3b9aca00"),
+                "eth_getBalance" => json!("0xde0b6b3a7640000"),
+                "eth_sendRawTransaction" => {
+                    let raw =
+                        hex::decode(params[0].as_str().unwrap().trim_start_matches("0x")).unwrap();
+                    let transaction = rlp::Rlp::new(&raw);
+                    sent_nonces
+                        .lock()
+                        .unwrap()
+                        .push(transaction.val_at::<U256>(0).unwrap());
+                    json!(H256::from(web3::signing::keccak256(&raw)))
+                }
+                "eth_getTransactionReceipt" => json!({
+                    "transactionHash": params[0],
+                    "transactionIndex": "0x0",
+                    "blockNumber": "0x10",
+                    "from": web3::types::Address::zero(),
+                    "cumulativeGasUsed": "0x5208",
+                    "gasUsed": "0x5208",
+                    "logs": [],
+                    "logsBloom": format!("0x{}", "0".repeat(512)),
+                    "status": "0x1",
+                }),
+                "eth_blockNumber" => json!("0x20"),
+                method => panic!("unexpected method {method}"),
+            })
+            .await
+        };
+        let mut config = Config::parse(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/test/config/config.sample.toml"
+        ));
+        config.protocol_chain.jrpc_url = url;
+        config.transaction_monitoring_options.duplicate_check_blocks = 0;
+        let mut oracle = Oracle::new(config, Metrics::new().unwrap()).unwrap();
+
+        let ethereum = Caip2ChainId::ethereum_mainnet();
+        let gnosis = Caip2ChainId::from_str("eip155:100").unwrap();
+        let subgraph_state = SubgraphState {
+            last_indexed_block_number: 1,
+            global_state: Some(GlobalState {
+                networks: vec![crate::subgraph::Network {
+                    id: ethereum.clone(),
+                    array_index: 0,
+                    latest_block_update: None,
+                }],
+                encoding_version: CURRENT_ENCODING_VERSION as i64,
+                latest_epoch_number: Some(1),
+            }),
+            last_payload: None,
+        };
+        let latest_blocks = BTreeMap::from([
+            (ethereum, BlockPtr::new(1_000, [1; 32])),
+            (gnosis.clone(), BlockPtr::new(2_000, [2; 32])),
+        ]);
+        let encode = |max_payload_bytes| {
+            encode_new_epoch(
+                &subgraph_state,
+                CURRENT_ENCODING_VERSION,
+                vec![gnosis.clone()],
+                latest_blocks.clone(),
+                max_payload_bytes,
+                OverflowPolicy::default(),
+            )
+            .unwrap()
+            .0
+        };
+
+        let batches = encode(usize::MAX);
+        assert_eq!(batches[0].messages, ["RegisterNetworksAndSetBlockNumbers"]);
+        let batches = encode(batches[0].payload.len() - 1);
+        let messages: Vec<_> = batches.iter().map(|batch| batch.messages.clone()).collect();
+        assert_eq!(
+            messages,
+            [["RegisterNetworks"], ["SetBlockNumbersForNextEpoch"]]
+        );
+        assert_eq!(batches[1].network_count, 2);
+
+        let payloads = batches.into_iter().map(|batch| Payload {
+            epoch: 5,
+            messages: batch.messages.iter().map(ToString::to_string).collect(),
+            bytes: batch.payload,
+            network_count: batch.network_count as u64,
+            encoding_version: CURRENT_ENCODING_VERSION,
+            subgraph_block: Some(1),
+        });
+        oracle
+            .submit_payloads(5, payloads, &latest_blocks)
+            .await
+            .unwrap();
+        // Every batch takes the nonce after the previous one's.
+        assert_eq!(*sent_nonces.lock().unwrap(), [U256::from(5), U256::from(6)]);
+        let nonces: Vec<_> = oracle
+            .history
+            .payloads()
+            .iter()
+            .map(|payload| payload.nonce)
+            .collect();
+        assert_eq!(nonces, [Some(U256::from(5)), Some(U256::from(6))]);
+        assert_eq!(oracle.history.get(5, &gnosis).unwrap().number, 2_000);
+    }
+
+    #[test]
+    fn subgraph_encoding_versions() {
+        let global_state = |encoding_version| GlobalState {
+            networks: vec![],
+            encoding_version,
+            latest_epoch_number: None,
+        };
+        let encoder = subgraph_encoder(&global_state(0)).unwrap();
+        assert_eq!(encoder.encoding_version(), 0);
+        assert!(matches!(
+            subgraph_encoder(&global_state(-1)),
+            Err(Error::Subgraph(SubgraphQueryError::BadData(_)))
+        ));
+        assert!(matches!(
+            subgraph_encoder(&global_state(1_000)),
+            Err(Error::Encoding(EncodeError::UnsupportedEncodingVersion(
+                1_000
+            )))
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Sends transactions to a private relay, like Flashbots Protect, instead of the public mempool,
+//! where oracle updates could be frontrun or censored.
+//!
+//! The relay is only a shortcut: if it rejects a transaction or doesn't answer in time, the
+//! [`TransactionMonitor`](crate::transaction_monitor::TransactionMonitor) sends it through the
+//! protocol chain's provider as usual. Receipts are always fetched from that provider.
+
+use crate::config::PrivateRelayOptions;
+use std::time::Duration;
+use tracing::trace;
+use web3::{transports::Http, types::Bytes, Web3};
+
+#[derive(thiserror::Error, Debug)]
+pub enum PrivateRelayError {
+    #[error("the private relay didn't respond within {0:?}")]
+    Timeout(Duration),
+    #[error("the private relay rejected the transaction: {0}")]
+    Rpc(#[from] web3::Error),
+}
+
+pub struct PrivateRelay {
+    client: Web3<Http>,
+    timeout: Duration,
+}
+
+impl PrivateRelay {
+    pub fn new(options: &PrivateRelayOptions) -> Self {
+        let transport = Http::with_client(reqwest::Client::new(), options.url.clone());
+        Self {
+            client: Web3::new(transport),
+            timeout: options.timeout,
+        }
+    }
+
+    pub async fn send_raw_transaction(
+        &self,
+        raw_transaction: Bytes,
+    ) -> Result<(), PrivateRelayError> {
+        trace!("Sending the transaction to the private relay");
+        let send = self.client.eth().send_raw_transaction(raw_transaction);
+        match tokio::time::timeout(self.timeout, send).await {
+            Ok(result) => result.map(|_| ()).map_err(PrivateRelayError::from),
+            Err(_) => Err(PrivateRelayError::Timeout(self.timeout)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::serve;
+    use serde_json::json;
+
+    /// A relay that answers every request with `response`, or never answers if it's `None`.
+    async fn relay(response: Option<serde_json::Value>) -> PrivateRelay {
+        PrivateRelay::new(&PrivateRelayOptions {
+            url: serve(move |_| response.clone()).await,
+            timeout: Duration::from_millis(200),
+        })
+    }
+
+    #[tokio::test]
+    async fn relay_responses() {
+        let hash = format!("0x{}", "ab".repeat(32));
+        let accepted = relay(Some(json!({ "jsonrpc": "2.0", "id": 0, "result": hash }))).await;
+        accepted
+            .send_raw_transaction(vec![0xf8].into())
+            .await
+            .unwrap();
+
+        let rejected = relay(Some(json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "error": { "code": -32000, "message": "nonce too low" },
+        })))
+        .await;
+        assert!(matches!(
+            rejected.send_raw_transaction(vec![0xf8].into()).await,
+            Err(PrivateRelayError::Rpc(web3::Error::Rpc(_)))
+        ));
+
+        let unresponsive = relay(None).await;
+        assert!(matches!(
+            unresponsive.send_raw_transaction(vec![0xf8].into()).await,
+            Err(PrivateRelayError::Timeout(_))
+        ));
+    }
+}
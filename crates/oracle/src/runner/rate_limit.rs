@@ -0,0 +1,89 @@
+//! Client-side rate limiting of JSON RPC requests, for providers with a requests-per-second quota.
+//!
+//! Requests are spaced out evenly rather than sent in bursts, so that retries and block scans
+//! stay within the quota at any point in time.
+
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The time between two requests, or `None` without a limit.
+    interval: Option<Duration>,
+    /// The earliest time the next request may be sent.
+    next: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: Option<NonZeroU32>) -> Self {
+        let limiter = Self::default();
+        limiter.set_limit(requests_per_second);
+        limiter
+    }
+
+    pub fn set_limit(&self, requests_per_second: Option<NonZeroU32>) {
+        let mut state = self.state.lock().unwrap();
+        state.interval = requests_per_second.map(|limit| Duration::from_secs(1) / limit.get());
+    }
+
+    /// Waits until the next request may be sent, and returns for how long.
+    pub async fn acquire(&self) -> Duration {
+        let now = Instant::now();
+        match self.reserve(now) {
+            Some(slot) if slot > now => {
+                tokio::time::sleep_until(slot.into()).await;
+                slot - now
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Reserves the next free slot at or after `now`, if there's a limit.
+    fn reserve(&self, now: Instant) -> Option<Instant> {
+        // Unwrap: the lock is never held across panics.
+        let mut state = self.state.lock().unwrap();
+        let interval = state.interval?;
+        let slot = state.next.map_or(now, |next| next.max(now));
+        state.next = Some(slot + interval);
+        Some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_spaced_out() {
+        let limiter = RateLimiter::new(NonZeroU32::new(4));
+        let start = Instant::now();
+        let slots: Vec<_> = (0..3)
+            .map(|_| limiter.reserve(start).unwrap() - start)
+            .collect();
+        assert_eq!(
+            slots,
+            [
+                Duration::ZERO,
+                Duration::from_millis(250),
+                Duration::from_millis(500)
+            ]
+        );
+
+        // Idle time isn't saved up for bursts.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(later), Some(later));
+        assert_eq!(
+            limiter.reserve(later),
+            Some(later + Duration::from_millis(250))
+        );
+
+        limiter.set_limit(None);
+        assert_eq!(limiter.reserve(later), None);
+    }
+}
@@ -0,0 +1,499 @@
+//! How the [`Oracle`](crate::oracle::Oracle) gets its payloads on chain.
+//!
+//! The oracle only decides what to publish and hands every [`Payload`] to a
+//! [`TransactionSubmitter`], which sends it and then waits for its confirmation. The
+//! `block-oracle` binary picks one with [`from_config`]:
+//!
+//! - [`ContractSubmitter`] signs transactions itself, with whichever [`Signer`] the owner key
+//!   calls for.
+//! - [`DefenderSubmitter`] hands them to an OpenZeppelin Defender relayer instead.
+//! - [`CoSigningSubmitter`] collects co-signatures before one of the above sends the payload to
+//!   the co-signing contract.
+//! - [`DryRunSubmitter`] only logs payloads.
+//!
+//! Other backends, or mocks in tests, only need to implement the trait. Like the oracle itself,
+//! submitters don't have to be `Send`, as the JSON RPC transports aren't.
+
+use crate::co_signing::{CoSigning, CoSigningError};
+use crate::config::OwnerKey;
+use crate::contracts::{
+    check_payload_size, ContractError, Contracts, PreparedCall, Submission, DATA_EDGE_FUNCTION_NAME,
+};
+use crate::defender::DefenderRelayer;
+use crate::models::eip155_chain_id;
+use crate::signer::Signer;
+use crate::{hex_string, Config};
+use anyhow::Context;
+use async_trait::async_trait;
+use epoch_encoding::{decode_messages_with_version, json::compressed_to_json};
+use futures::future::{self, LocalBoxFuture};
+use futures::{Future, FutureExt, TryFutureExt};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+use web3::ethabi::{self, Function, Token};
+use web3::types::{Address, Bytes, H256, U256};
+use web3::Transport;
+
+static DATA_EDGE_CO_SIGNER_ABI: &[u8] = include_bytes!("../abi/DataEdgeCoSigner.json");
+
+#[derive(thiserror::Error, Debug)]
+pub enum SubmitterError {
+    #[error(transparent)]
+    Contract(#[from] ContractError),
+    #[error(transparent)]
+    CoSigning(#[from] CoSigningError),
+}
+
+/// A payload for the DataEdge, along with what the subgraph needs to decode it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Payload {
+    pub epoch: u64,
+    /// The names of the messages in the payload.
+    pub messages: Vec<String>,
+    pub bytes: Vec<u8>,
+    /// How many networks the subgraph knows about before it decodes the payload.
+    pub network_count: u64,
+    pub encoding_version: u64,
+    /// The latest protocol chain block that the subgraph indexed, if known. Identical payloads
+    /// that landed after it count as duplicates, see [`Contracts::prepare`].
+    pub subgraph_block: Option<u64>,
+}
+
+/// What became of a payload that was handed to a [`TransactionSubmitter`].
+#[derive(Debug)]
+pub enum SubmissionOutcome {
+    Confirmed(Box<Submission>),
+    /// An identical payload landed earlier, e.g. right before the oracle crashed. It isn't sent
+    /// again, and this is its transaction.
+    AlreadyLanded(Box<Submission>),
+    /// Nothing was sent, e.g. in dry runs.
+    NotSent,
+}
+
+/// A transaction that was sent, but isn't necessarily confirmed yet. See
+/// [`TransactionSubmitter::wait_for_confirmation`].
+pub struct SentTransaction<'a> {
+    /// The hash and nonce of the transaction, if one was sent.
+    sent: Option<(H256, U256)>,
+    confirmation: LocalBoxFuture<'a, Result<SubmissionOutcome, ContractError>>,
+}
+
+impl<'a> SentTransaction<'a> {
+    /// A transaction that was sent as `tx_hash`, and whose `confirmation` resolves once it's
+    /// confirmed. It may be replaced with higher fees in the meantime.
+    pub fn pending(
+        tx_hash: H256,
+        nonce: U256,
+        confirmation: impl Future<Output = Result<Submission, ContractError>> + 'a,
+    ) -> Self {
+        Self {
+            sent: Some((tx_hash, nonce)),
+            confirmation: confirmation
+                .map_ok(|submission| SubmissionOutcome::Confirmed(Box::new(submission)))
+                .boxed_local(),
+        }
+    }
+
+    /// Stands in for an identical payload that landed earlier, see
+    /// [`SubmissionOutcome::AlreadyLanded`].
+    pub fn already_landed(submission: Submission) -> Self {
+        let (tx_hash, nonce) = (submission.receipt.transaction_hash, submission.nonce);
+        let outcome = SubmissionOutcome::AlreadyLanded(Box::new(submission));
+        Self {
+            sent: Some((tx_hash, nonce)),
+            confirmation: future::ready(Ok(outcome)).boxed_local(),
+        }
+    }
+
+    /// Stands in for a payload that wasn't sent, e.g. in dry runs.
+    pub fn not_sent() -> Self {
+        Self {
+            sent: None,
+            confirmation: future::ready(Ok(SubmissionOutcome::NotSent)).boxed_local(),
+        }
+    }
+
+    pub fn tx_hash(&self) -> Option<H256> {
+        self.sent.map(|(tx_hash, _)| tx_hash)
+    }
+
+    pub fn nonce(&self) -> Option<U256> {
+        self.sent.map(|(_, nonce)| nonce)
+    }
+
+    /// Resolves once the transaction is confirmed.
+    pub async fn confirmation(self) -> Result<SubmissionOutcome, ContractError> {
+        self.confirmation.await
+    }
+}
+
+#[async_trait(?Send)]
+pub trait TransactionSubmitter {
+    /// Sends a transaction with `payload` to the DataEdge, without waiting for its confirmation.
+    async fn send<'a>(&'a self, payload: &Payload) -> Result<SentTransaction<'a>, SubmitterError>;
+
+    /// Waits until `sent` is confirmed. Stuck transactions may be replaced with higher fees while
+    /// waiting.
+    async fn wait_for_confirmation<'a>(
+        &'a self,
+        sent: SentTransaction<'a>,
+    ) -> Result<SubmissionOutcome, SubmitterError> {
+        Ok(sent.confirmation().await?)
+    }
+
+    /// Deals with the transactions that a previous run left pending, before the first payload is
+    /// submitted.
+    async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError>;
+}
+
+#[async_trait(?Send)]
+impl<S> TransactionSubmitter for Box<S>
+where
+    S: TransactionSubmitter + ?Sized,
+{
+    async fn send<'a>(&'a self, payload: &Payload) -> Result<SentTransaction<'a>, SubmitterError> {
+        (**self).send(payload).await
+    }
+
+    async fn wait_for_confirmation<'a>(
+        &'a self,
+        sent: SentTransaction<'a>,
+    ) -> Result<SubmissionOutcome, SubmitterError> {
+        (**self).wait_for_confirmation(sent).await
+    }
+
+    async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError> {
+        (**self).resolve_pending_transactions().await
+    }
+}
+
+/// Submitters that can send payloads to other contracts than the DataEdge, like the co-signing
+/// contract. See [`CoSigningSubmitter`].
+#[async_trait(?Send)]
+pub trait CallSubmitter {
+    /// Sends a transaction with `calldata` to `to`, without waiting for its confirmation.
+    /// See [`Payload::subgraph_block`] for `after_block`.
+    async fn send_call<'a>(
+        &'a self,
+        to: Address,
+        calldata: Bytes,
+        after_block: Option<u64>,
+    ) -> Result<SentTransaction<'a>, ContractError>;
+
+    /// Like [`TransactionSubmitter::resolve_pending_transactions`], for transactions to `to`.
+    async fn resolve_pending_calls(&self, to: Address) -> Result<(), ContractError>;
+}
+
+/// Builds the submitter that `config` asks for: a [`DryRunSubmitter`] for dry runs, a
+/// [`DefenderSubmitter`] for Defender owner keys, and a [`ContractSubmitter`] that signs with
+/// `signer` otherwise. With `co_signing`, payloads are co-signed first.
+pub fn from_config<T>(
+    config: &Config,
+    contracts: Arc<Contracts<T>>,
+    signer: Box<dyn Signer>,
+    co_signing: Option<CoSigning>,
+) -> anyhow::Result<Box<dyn TransactionSubmitter>>
+where
+    T: Clone + Transport + 'static,
+{
+    if config.dry_run {
+        return Ok(Box::new(DryRunSubmitter));
+    }
+    match &config.owner_key {
+        OwnerKey::Defender(options) => {
+            let relayer = DefenderRelayer::new(options.clone());
+            let submitter = DefenderSubmitter::new(contracts, relayer, options.address);
+            with_co_signing(config, submitter, co_signing)
+        }
+        _ => with_co_signing(
+            config,
+            ContractSubmitter::new(contracts, signer),
+            co_signing,
+        ),
+    }
+}
+
+fn with_co_signing<S>(
+    config: &Config,
+    submitter: S,
+    co_signing: Option<CoSigning>,
+) -> anyhow::Result<Box<dyn TransactionSubmitter>>
+where
+    S: TransactionSubmitter + CallSubmitter + 'static,
+{
+    let Some(co_signing) = co_signing else {
+        return Ok(Box::new(submitter));
+    };
+    let chain_id = eip155_chain_id(&config.protocol_chain.id)
+        .context("Co-signing needs an eip155 protocol chain")?;
+    let submitter = CoSigningSubmitter::new(
+        submitter,
+        co_signing,
+        chain_id.low_u64(),
+        config.max_payload_bytes,
+    )
+    .context("Failed to initialize the co-signing contract")?;
+    Ok(Box::new(submitter))
+}
+
+/// Submits payloads through [`Contracts`], signing with `signer`.
+pub struct ContractSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    contracts: Arc<Contracts<T>>,
+    signer: Box<dyn Signer>,
+}
+
+impl<T> ContractSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    pub fn new(contracts: Arc<Contracts<T>>, signer: Box<dyn Signer>) -> Self {
+        Self { contracts, signer }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T> TransactionSubmitter for ContractSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    async fn send<'a>(&'a self, payload: &Payload) -> Result<SentTransaction<'a>, SubmitterError> {
+        info!("Sending transaction to DataEdge");
+        let (to, calldata) = self.contracts.data_edge_call(&payload.bytes)?;
+        Ok(self.send_call(to, calldata, payload.subgraph_block).await?)
+    }
+
+    async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError> {
+        let data_edge = self.contracts.data_edge_address();
+        Ok(self.resolve_pending_calls(data_edge).await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<T> CallSubmitter for ContractSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    async fn send_call<'a>(
+        &'a self,
+        to: Address,
+        calldata: Bytes,
+        after_block: Option<u64>,
+    ) -> Result<SentTransaction<'a>, ContractError> {
+        self.contracts
+            .send(to, calldata, after_block, self.signer.as_ref())
+            .await
+    }
+
+    async fn resolve_pending_calls(&self, to: Address) -> Result<(), ContractError> {
+        self.contracts
+            .resolve_pending_transactions(self.signer.as_ref(), to)
+            .await
+    }
+}
+
+/// Hands payloads to a Defender relayer, which takes care of their nonces and fees. See
+/// [`defender`](crate::defender).
+pub struct DefenderSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    contracts: Arc<Contracts<T>>,
+    relayer: DefenderRelayer,
+    /// The relayer's address.
+    address: Address,
+}
+
+impl<T> DefenderSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    pub fn new(contracts: Arc<Contracts<T>>, relayer: DefenderRelayer, address: Address) -> Self {
+        Self {
+            contracts,
+            relayer,
+            address,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<T> TransactionSubmitter for DefenderSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    async fn send<'a>(&'a self, payload: &Payload) -> Result<SentTransaction<'a>, SubmitterError> {
+        info!("Relaying transaction to DataEdge");
+        let (to, calldata) = self.contracts.data_edge_call(&payload.bytes)?;
+        Ok(self.send_call(to, calldata, payload.subgraph_block).await?)
+    }
+
+    async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError> {
+        let data_edge = self.contracts.data_edge_address();
+        Ok(self.resolve_pending_calls(data_edge).await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl<T> CallSubmitter for DefenderSubmitter<T>
+where
+    T: Clone + Transport,
+{
+    async fn send_call<'a>(
+        &'a self,
+        to: Address,
+        calldata: Bytes,
+        after_block: Option<u64>,
+    ) -> Result<SentTransaction<'a>, ContractError> {
+        let prepared = self
+            .contracts
+            .prepare(self.address, to, &calldata, after_block)
+            .await?;
+        let gas_limit = match prepared {
+            PreparedCall::Duplicate(submission) => {
+                return Ok(SentTransaction::already_landed(*submission))
+            }
+            PreparedCall::Ready { gas_limit } => gas_limit,
+        };
+        // The relayer picks its own fees, but it shouldn't pay more than we would either.
+        self.contracts.check_fees(self.address, gas_limit).await?;
+        let options = self.contracts.transaction_monitoring_options();
+        let valid_for = options.deadline_in_seconds.map(Duration::from_secs);
+        let transaction = self
+            .relayer
+            .send_transaction(to, &calldata, gas_limit, valid_for)
+            .await?;
+        let (tx_hash, nonce) = (transaction.hash, transaction.nonce.into());
+        // The relayer bumps fees on its own, so it gets as long as all of our retries would take.
+        let timeout = valid_for.unwrap_or(Duration::from_secs(
+            options.confirmation_timeout_in_seconds * u64::from(options.max_retries),
+        ));
+        let poll_interval = Duration::from_secs(options.poll_interval_in_seconds);
+        Ok(SentTransaction::pending(tx_hash, nonce, async move {
+            let hash = self
+                .relayer
+                .wait_until_mined(transaction, poll_interval, timeout)
+                .await?;
+            let receipt = self.contracts.wait_for_receipt(hash, nonce).await?;
+            Ok(Submission { receipt, nonce })
+        }))
+    }
+
+    /// The relayer takes care of its own transactions.
+    async fn resolve_pending_calls(&self, _to: Address) -> Result<(), ContractError> {
+        Ok(())
+    }
+}
+
+/// Collects co-signatures for every payload, and then has `inner` send it to the co-signing
+/// contract instead of the DataEdge. See [`co_signing`](crate::co_signing).
+pub struct CoSigningSubmitter<S> {
+    inner: S,
+    co_signing: CoSigning,
+    /// `crossChainEpochOracle(bytes payload, bytes[] signatures)` of the co-signing contract.
+    function: Function,
+    chain_id: u64,
+    max_payload_bytes: Option<usize>,
+}
+
+impl<S: CallSubmitter> CoSigningSubmitter<S> {
+    /// Signatures are only valid on `chain_id`, the protocol chain.
+    pub fn new(
+        inner: S,
+        co_signing: CoSigning,
+        chain_id: u64,
+        max_payload_bytes: Option<usize>,
+    ) -> Result<Self, ethabi::Error> {
+        let abi = ethabi::Contract::load(DATA_EDGE_CO_SIGNER_ABI)?;
+        let function = abi.function(DATA_EDGE_FUNCTION_NAME)?.clone();
+        Ok(Self {
+            inner,
+            co_signing,
+            function,
+            chain_id,
+            max_payload_bytes,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: CallSubmitter> TransactionSubmitter for CoSigningSubmitter<S> {
+    async fn send<'a>(&'a self, payload: &Payload) -> Result<SentTransaction<'a>, SubmitterError> {
+        check_payload_size(&payload.bytes, self.max_payload_bytes)?;
+        let signatures = self
+            .co_signing
+            .collect(self.chain_id, payload.epoch, &payload.bytes)
+            .await?;
+        info!("Sending co-signed transaction to the DataEdge owner");
+        let signatures = signatures
+            .into_iter()
+            .map(|signature| Token::Bytes(signature.0))
+            .collect();
+        let calldata = self
+            .function
+            .encode_input(&[
+                Token::Bytes(payload.bytes.clone()),
+                Token::Array(signatures),
+            ])
+            .map_err(ContractError::AbiEncode)?;
+        let co_signer = self.co_signing.contract_address();
+        let sent = self
+            .inner
+            .send_call(co_signer, calldata.into(), payload.subgraph_block)
+            .await?;
+        Ok(sent)
+    }
+
+    async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError> {
+        let co_signer = self.co_signing.contract_address();
+        Ok(self.inner.resolve_pending_calls(co_signer).await?)
+    }
+}
+
+/// Logs payloads and their decoded messages in place of submitting them.
+pub struct DryRunSubmitter;
+
+#[async_trait(?Send)]
+impl TransactionSubmitter for DryRunSubmitter {
+    async fn send<'a>(&'a self, payload: &Payload) -> Result<SentTransaction<'a>, SubmitterError> {
+        log_dry_run(payload);
+        Ok(SentTransaction::not_sent())
+    }
+
+    async fn resolve_pending_transactions(&self) -> Result<(), SubmitterError> {
+        Ok(())
+    }
+}
+
+fn log_dry_run(payload: &Payload) {
+    let Payload {
+        epoch,
+        messages,
+        bytes,
+        network_count,
+        encoding_version,
+        ..
+    } = payload;
+    let decoded = decode_messages_with_version(bytes, *network_count, *encoding_version)
+        .map_err(|e| e.to_string())
+        .and_then(|messages| compressed_to_json(messages).map_err(|e| e.to_string()));
+    match decoded {
+        Ok(decoded) => info!(
+            epoch,
+            ?messages,
+            payload = hex_string(bytes).as_str(),
+            %decoded,
+            "Dry run, not submitting the payload."
+        ),
+        Err(error) => error!(
+            epoch,
+            ?messages,
+            payload = hex_string(bytes).as_str(),
+            %error,
+            "Dry run, failed to decode our own payload. This is a bug!"
+        ),
+    }
+}
@@ -1,14 +1,17 @@
 use crate::config::TransactionMonitoringOptions;
-use either::Either;
+use crate::confirmations::{ConfirmationError, ConfirmationTracker};
+use crate::gas::{GasEstimator, GasPrice};
+use crate::private_relay::{PrivateRelay, PrivateRelayError};
+use crate::signer::{self, Signer, SignerError};
 use futures::stream::{FuturesUnordered, StreamExt};
 use std::collections::HashSet;
-use tokio::time::{timeout, Duration};
-use tracing::{debug, error, trace, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, trace, warn};
 use web3::{
-    api::{Accounts, Namespace},
     error::Error as Web3Error,
-    signing::{Key, SecretKeyRef},
-    types::{Address, Bytes, TransactionParameters, TransactionReceipt, H256, U256},
+    types::{
+        Address, BlockNumber, Bytes, TransactionParameters, TransactionReceipt, H256, U256, U64,
+    },
     Transport, Web3,
 };
 
@@ -16,125 +19,216 @@ use web3::{
 pub enum TransactionMonitorError {
     #[error("failed to determine default values for crafting the transaction: {0}")]
     Startup(#[source] Web3Error),
+    #[error("failed to reserve a nonce for the transaction: {0}")]
+    Nonce(#[source] Web3Error),
     #[error("failed to sign the transaction parameters: {0}")]
-    Signing(#[source] Web3Error),
+    Signing(#[source] SignerError),
     #[error("failed to send a signed transaction: {0}")]
     Provider(#[source] Web3Error),
+    #[error("the provider rejected the transaction, as {rejection}: {error}")]
+    Rejected {
+        rejection: Rejection,
+        #[source]
+        error: Web3Error,
+    },
     #[error("failed to send transaction after exhausting all retries")]
     BroadcastFailure,
+    #[error("the transaction {:?} was reverted in block #{}", .0.tx_hash, .0.block_number)]
+    Reverted(Box<RevertedTransaction>),
+    #[error("the transaction wasn't mined in time and was cancelled by {tx_hash:?}, freeing nonce {nonce}")]
+    Cancelled { tx_hash: H256, nonce: U256 },
+    #[error(
+        "none of the transactions was mined, but another transaction used their nonce {nonce}"
+    )]
+    Replaced { nonce: U256 },
 }
 
+/// A transaction that was mined, but reverted. Its gas was spent all the same.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevertedTransaction {
+    pub tx_hash: H256,
+    pub block_number: U64,
+    pub nonce: U256,
+    pub gas_used: Option<U256>,
+    pub effective_gas_price: Option<U256>,
+}
+
+impl TransactionMonitorError {
+    /// Whether a transaction with the same nonce was mined before ours could be sent.
+    pub(crate) fn is_nonce_too_low(&self) -> bool {
+        self.rejection() == Some(Rejection::NonceTooLow)
+    }
+
+    /// Why the provider refused to accept the transaction, if it did.
+    pub fn rejection(&self) -> Option<Rejection> {
+        match self {
+            Self::Rejected { rejection, .. } => Some(*rejection),
+            _ => None,
+        }
+    }
+
+    /// The error of a transaction that the provider refused to accept.
+    fn sending(error: Web3Error) -> Self {
+        match rejection(&error) {
+            Some(rejection) => Self::Rejected { rejection, error },
+            None => Self::Provider(error),
+        }
+    }
+
+    /// The error of a transaction with `nonce` that failed to be confirmed.
+    pub(crate) fn from_confirmation(error: ConfirmationError, nonce: U256) -> Self {
+        match error {
+            // This means that we failed handling the transaction and got a provider error before
+            // the timeout.
+            ConfirmationError::Provider(web3_error) => Self::Provider(web3_error),
+            ConfirmationError::Reverted {
+                tx_hash,
+                block_number,
+                gas_used,
+                effective_gas_price,
+            } => Self::Reverted(Box::new(RevertedTransaction {
+                tx_hash,
+                block_number,
+                nonce,
+                gas_used,
+                effective_gas_price,
+            })),
+            ConfirmationError::Timeout(_) => Self::BroadcastFailure,
+        }
+    }
+}
+
+/// The transactions that were sent for the same nonce so far, with ever higher fees. See
+/// [`TransactionMonitor::send`].
+#[derive(Debug)]
+pub struct SentTransactions {
+    hashes: HashSet<H256>,
+    latest: H256,
+    transaction_parameters: TransactionParameters,
+    gas_price: GasPrice,
+    retries: u32,
+    started: Instant,
+}
+
+impl SentTransactions {
+    /// The hash of the latest transaction that was sent.
+    pub fn hash(&self) -> H256 {
+        self.latest
+    }
+}
+
+/// The gas of a plain transfer.
+const CANCELLATION_GAS: u64 = 21_000;
+
 pub struct TransactionMonitor<'a, T: Transport> {
     client: Web3<T>,
-    signing_key: SecretKeyRef<'a>,
+    signer: &'a dyn Signer,
+    private_relay: Option<&'a PrivateRelay>,
 
     /// The unsingned transaction that we want to broadcast.
     /// We keep it around so we can control its `nonce` and `gas_price` values.
     transaction_parameters: TransactionParameters,
+    gas_price: GasPrice,
 
     options: TransactionMonitoringOptions,
+    confirmations: ConfirmationTracker<T>,
 }
 
 impl<'a, T: Transport> TransactionMonitor<'a, T> {
+    /// `nonce` must be reserved for this transaction, see
+    /// [`NonceManager`](crate::nonce_manager::NonceManager).
     pub async fn new(
         client: Web3<T>,
-        signing_key: SecretKeyRef<'a>,
+        signer: &'a dyn Signer,
         contract_address: Address,
         calldata: Bytes,
+        nonce: U256,
+        gas_limit: U256,
         options: TransactionMonitoringOptions,
     ) -> Result<TransactionMonitor<'a, T>, TransactionMonitorError> {
         trace!(?options, "Starting Transaction Monitor");
 
-        let from = signing_key.address();
-
-        let (nonce, gas_price) = futures::future::try_join(
-            client.eth().transaction_count(from, None),
-            client.eth().gas_price(),
-        )
-        .await
-        .map_err(TransactionMonitorError::Startup)?;
-        debug!(
-            %nonce,
-            %gas_price, "Fetched current nonce and gas price from provider"
-        );
+        let gas_price = GasEstimator::new(client.eth(), &options)
+            .estimate()
+            .await
+            .map_err(TransactionMonitorError::Startup)?;
+        debug!(%nonce, ?gas_price, "Estimated the transaction fees");
 
-        let transaction_parameters = TransactionParameters {
+        let mut transaction_parameters = TransactionParameters {
             to: Some(contract_address),
-            gas: options.gas_limit.into(),
-            gas_price: Some(gas_price),
+            gas: gas_limit,
             data: calldata,
             nonce: Some(nonce),
-            max_fee_per_gas: options.max_fee_per_gas.map(Into::into),
-            max_priority_fee_per_gas: options.max_priority_fee_per_gas.map(Into::into),
-
             ..Default::default()
         };
+        gas_price.apply(&mut transaction_parameters);
 
+        let confirmations = ConfirmationTracker::new(client.eth(), &options);
         Ok(Self {
             client,
             transaction_parameters,
-            signing_key,
+            gas_price,
+            signer,
+            private_relay: None,
             options,
+            confirmations,
         })
     }
 
-    /// It is possible that previously sent transactions are included in a block while we are trying
-    /// to rebroadcast the original transaction.
-    ///
-    /// If this function detects any confirmation the TransactionManager should abort its ongoing
-    /// operations and return the transaction hash of the confirmed transaction to the Oracle.
-    async fn check_previously_sent_transactions(
-        &self,
-        sent_transaction_hashes: HashSet<H256>,
-    ) -> Result<Option<TransactionReceipt>, Web3Error> {
-        // Create a task list with every transaction hash we have
-        let mut futures = FuturesUnordered::new();
-
-        for hash in &sent_transaction_hashes {
-            let eth = self.client.eth();
-            let future = async move {
-                trace!(
-                    ?hash,
-                    "Checking for previously sent transaction confirmations"
-                );
-                eth.transaction_receipt(*hash).await
-            };
-            futures.push(future)
-        }
+    /// A zero-value transfer from `owner` to itself, which frees `nonce` by replacing whichever
+    /// transaction holds it.
+    pub async fn cancellation(
+        client: Web3<T>,
+        signer: &'a dyn Signer,
+        owner: Address,
+        nonce: U256,
+        options: TransactionMonitoringOptions,
+    ) -> Result<TransactionMonitor<'a, T>, TransactionMonitorError> {
+        Self::new(
+            client,
+            signer,
+            owner,
+            Bytes::default(),
+            nonce,
+            CANCELLATION_GAS.into(),
+            options,
+        )
+        .await
+    }
 
-        // Await and check if any of those transactions has a receipt
-        while let Some(result) = futures.next().await {
-            match result {
-                Ok(None) => {}
-                Ok(Some(receipt)) => {
-                    return Ok(Some(receipt));
-                }
-                Err(error) => {
-                    warn!(%error, "Provider failure while attempting to check confirmations for \
-                                   previously sent transactions")
-                }
-            }
-        }
-        Ok(None)
+    /// Sends transactions through `private_relay` first, see
+    /// [`private_relay`](crate::private_relay).
+    pub fn with_private_relay(mut self, private_relay: Option<&'a PrivateRelay>) -> Self {
+        self.private_relay = private_relay;
+        self
+    }
+
+    /// The estimated fees, before any bumps.
+    pub fn gas_price(&self) -> GasPrice {
+        self.gas_price
+    }
+
+    /// The most that the transaction may cost before any fee bumps, i.e. its gas limit at its
+    /// maximum fee per gas.
+    pub fn max_cost(&self) -> U256 {
+        self.transaction_parameters
+            .gas
+            .saturating_mul(self.gas_price.max_fee_per_gas())
     }
 
-    /// Attempts to sign and broadcast a transaction, returing its receipt on success.
-    /// This function has two error types:
-    /// - the generalist `web3::error:Error, and
-    /// - the hash of the transaction that we given up waiting for it to be confirmed.
-    async fn send_transaction_and_wait_for_confirmation(
+    /// Signs and broadcasts a transaction, returning its hash. A transaction that the provider
+    /// already knows counts as broadcast.
+    async fn broadcast(
         &self,
         transaction_parameters: TransactionParameters,
-    ) -> Result<TransactionReceipt, Either<Web3Error, H256>> {
+    ) -> Result<H256, TransactionMonitorError> {
         // we will log this later
         let gas = transaction_parameters.gas;
 
-        // Sign the transaction
-        let signed_transaction = Accounts::new(self.client.transport().clone())
-            .sign_transaction(transaction_parameters, &*self.signing_key)
-            .await
-            .map_err(Either::Left)?;
-
+        let signed_transaction =
+            signer::sign_transaction(&self.client, transaction_parameters, self.signer)
+                .await
+                .map_err(TransactionMonitorError::Signing)?;
         let transaction_hash = signed_transaction.transaction_hash;
 
         trace!(
@@ -143,86 +237,442 @@ impl<'a, T: Transport> TransactionMonitor<'a, T> {
             timeout = self.options.confirmation_timeout_in_seconds,
             "Broadcasting transaction with timeout"
         );
+        if let Some(private_relay) = self.private_relay {
+            match private_relay
+                .send_raw_transaction(signed_transaction.raw_transaction.clone())
+                .await
+            {
+                Ok(()) => return Ok(transaction_hash),
+                Err(PrivateRelayError::Rpc(error))
+                    if rejection(&error) == Some(Rejection::AlreadyKnown) =>
+                {
+                    debug!(hash = ?transaction_hash, "The private relay already knows the transaction");
+                    return Ok(transaction_hash);
+                }
+                Err(error) => warn!(
+                    %error,
+                    hash = ?transaction_hash,
+                    "Falling back to the protocol chain's provider"
+                ),
+            }
+        }
+        match self
+            .client
+            .eth()
+            .send_raw_transaction(signed_transaction.raw_transaction)
+            .await
+        {
+            Ok(_) => Ok(transaction_hash),
+            Err(error) if rejection(&error) == Some(Rejection::AlreadyKnown) => {
+                debug!(hash = ?transaction_hash, "The provider already knows the transaction");
+                Ok(transaction_hash)
+            }
+            Err(error) => Err(TransactionMonitorError::sending(error)),
+        }
+    }
 
-        // Wrap the transaction broadcast in a tokio::timeout future
-        let send_transaction_future = web3::confirm::send_raw_transaction_with_confirmation(
-            self.client.transport().clone(),
-            signed_transaction.raw_transaction,
-            Duration::from_secs(self.options.poll_interval_in_seconds),
-            self.options.confirmations,
-        );
-        let with_timeout = timeout(
-            Duration::from_secs(self.options.confirmation_timeout_in_seconds),
-            send_transaction_future,
-        );
+    /// Waits for whichever of the `sent_transactions` gets confirmed first. As they all share the
+    /// same nonce, at most one of them can be mined.
+    async fn wait_for_any(
+        &self,
+        sent_transactions: &HashSet<H256>,
+    ) -> Result<TransactionReceipt, ConfirmationError> {
+        let mut futures: FuturesUnordered<_> = sent_transactions
+            .iter()
+            .map(|hash| self.confirmations.wait(*hash))
+            .collect();
+        let mut timeout = None;
+        while let Some(result) = futures.next().await {
+            match result {
+                Err(ConfirmationError::Timeout(hash)) => timeout = Some(hash),
+                result => return result,
+            }
+        }
+        // Unwrap: we only get here if there was at least one timeout.
+        Err(ConfirmationError::Timeout(timeout.unwrap()))
+    }
 
-        match with_timeout.await {
-            Ok(Ok(receipt)) => Ok(receipt),
-            Ok(Err(web3_error)) => Err(Either::Left(web3_error)),
-            Err(_timed_out) => Err(Either::Right(transaction_hash)),
+    /// Broadcasts the transaction and waits for its confirmation, see [`Self::send`] and
+    /// [`Self::wait_for_confirmation`].
+    pub async fn execute_transaction(&self) -> Result<TransactionReceipt, TransactionMonitorError> {
+        let sent = self.send().await?;
+        self.wait_for_confirmation(sent).await
+    }
+
+    /// Broadcasts the transaction for the first time. If the provider finds a transaction with
+    /// the same nonce that pays more, the fees are bumped right away.
+    pub async fn send(&self) -> Result<SentTransactions, TransactionMonitorError> {
+        let mut sent = SentTransactions {
+            hashes: HashSet::new(),
+            latest: H256::zero(),
+            transaction_parameters: self.transaction_parameters.clone(),
+            gas_price: self.gas_price,
+            retries: self.options.max_retries,
+            started: Instant::now(),
+        };
+        self.rebroadcast(&mut sent).await?;
+        if sent.hashes.is_empty() {
+            return Err(TransactionMonitorError::BroadcastFailure);
         }
+        Ok(sent)
     }
 
-    /// Broadcasts the transaction and waits for its confirmation.
+    /// Waits until one of the `sent` transactions is confirmed.
     ///
-    /// It will bump the gas price and retry if the transaction takes too long to confirm.
-    /// While doing so, it will also check if previously sent transactions were confirmed.
+    /// If the transaction isn't mined within the confirmation timeout, it's replaced by one with
+    /// the same nonce and higher fees, up to `max_bumped_gas_price`. While waiting, the
+    /// transactions that were replaced may still be mined, in which case their receipt is
+    /// returned instead.
+    ///
+    /// With `deadline_in_seconds`, the transaction is cancelled once that much time has passed
+    /// since it was first sent, or once the retries are exhausted, see [`Self::cancel`].
     ///
     /// This function will return an error if we exhaust its maximum retries attempts.
-    pub async fn execute_transaction(&self) -> Result<TransactionReceipt, TransactionMonitorError> {
-        let mut retries = self.options.max_retries;
-
-        let mut sent_transactions = HashSet::new();
-        let mut transaction_parameters = self.transaction_parameters.clone();
-
-        while retries > 0 {
-            // While we broadcast the current transaction, also check if any previously sent
-            // transaction was confirmed.
-            let (current_transaction_receipt, previous_transactions_receipt) = tokio::join!(
-                self.send_transaction_and_wait_for_confirmation(transaction_parameters.clone()),
-                self.check_previously_sent_transactions(sent_transactions.clone()),
-            );
+    pub async fn wait_for_confirmation(
+        &self,
+        mut sent: SentTransactions,
+    ) -> Result<TransactionReceipt, TransactionMonitorError> {
+        let deadline = self.options.deadline_in_seconds.map(Duration::from_secs);
+        let max_bumped_gas_price = self.options.max_bumped_gas_price.map(U256::from);
+        // Whether the current `gas_price` still needs to be broadcast.
+        let mut rebroadcast = false;
 
-            if let Ok(Some(receipt)) = previous_transactions_receipt {
-                return Ok(receipt);
+        while sent.retries > 0 {
+            if rebroadcast {
+                self.rebroadcast(&mut sent).await?;
+                if sent.retries == 0 {
+                    break;
+                }
             }
 
-            match current_transaction_receipt {
+            match self.wait_for_any(&sent.hashes).await {
                 Ok(receipt) => return Ok(receipt),
-                Err(Either::Left(web3_error)) => {
-                    // This means that we failed handling the transaction and got a provider error
-                    // before the timeout.
-                    return Err(TransactionMonitorError::Provider(web3_error));
-                }
-                Err(Either::Right(transaction_hash)) => {
+                Err(ConfirmationError::Timeout(transaction_hash)) => {
                     // This means that we timed out waiting for the transaction to be confirmed.
-                    sent_transactions.insert(transaction_hash);
-                    if let Some(gas) = transaction_parameters.gas_price.as_mut() {
-                        *gas = bump_gas(*gas, self.options.gas_percentual_increase)
-                            .expect("gas_price calculation won't overflow a 256-bit number")
+                    if deadline.is_some_and(|deadline| sent.started.elapsed() >= deadline) {
+                        warn!(
+                            ?transaction_hash,
+                            "The transaction wasn't mined before the deadline. Cancelling it."
+                        );
+                        return self.cancel(sent.gas_price, sent.hashes).await;
                     }
-                    retries -= 1;
-                    debug!(?transaction_hash, retries_left = %retries, "Timed out waiting for the transaction confirmation");
+                    sent.retries -= 1;
+                    let bumped = sent
+                        .gas_price
+                        .bump(self.options.gas_percentual_increase, max_bumped_gas_price);
+                    rebroadcast = bumped != sent.gas_price;
+                    if !rebroadcast {
+                        warn!(
+                            gas_price = ?sent.gas_price,
+                            "The transaction fees can't be bumped any further. Waiting for the \
+                             sent transactions instead."
+                        );
+                    }
+                    sent.gas_price = bumped;
+                    sent.gas_price.apply(&mut sent.transaction_parameters);
+                    debug!(?transaction_hash, retries_left = %sent.retries, "Timed out waiting for the transaction confirmation");
                 }
+                Err(error) => return Err(self.confirmation_error(error)),
             };
         }
 
         // At this point, we have exhausted all retry attempts
+        if deadline.is_some() && !sent.hashes.is_empty() {
+            warn!("The transaction wasn't mined after all retries. Cancelling it.");
+            return self.cancel(sent.gas_price, sent.hashes).await;
+        }
+        if self.is_replaced(&sent.hashes).await? {
+            return Err(TransactionMonitorError::Replaced {
+                // Unwrap: the nonce is always set, see `new`.
+                nonce: self.transaction_parameters.nonce.unwrap(),
+            });
+        }
         Err(TransactionMonitorError::BroadcastFailure)
     }
+
+    /// Whether the nonce was used by a transaction other than the `sent_transactions`, e.g. one
+    /// that an operator sent by hand.
+    async fn is_replaced(
+        &self,
+        sent_transactions: &HashSet<H256>,
+    ) -> Result<bool, TransactionMonitorError> {
+        let eth = self.client.eth();
+        for hash in sent_transactions {
+            // Ours was mined, it just didn't get enough confirmations in time.
+            let receipt = eth
+                .transaction_receipt(*hash)
+                .await
+                .map_err(TransactionMonitorError::Provider)?;
+            if receipt.is_some() {
+                return Ok(false);
+            }
+        }
+        let owner = self
+            .signer
+            .address()
+            .await
+            .map_err(TransactionMonitorError::Signing)?;
+        let mined = eth
+            .transaction_count(owner, Some(BlockNumber::Latest))
+            .await
+            .map_err(TransactionMonitorError::Provider)?;
+        // Unwrap: the nonce is always set, see `new`.
+        Ok(mined > self.transaction_parameters.nonce.unwrap())
+    }
+
+    /// Broadcasts the transaction with the current fees of `sent`. Underpriced replacements are
+    /// bumped and broadcast again until they're accepted or the retries run out.
+    async fn rebroadcast(
+        &self,
+        sent: &mut SentTransactions,
+    ) -> Result<(), TransactionMonitorError> {
+        let max_bumped_gas_price = self.options.max_bumped_gas_price.map(U256::from);
+        while sent.retries > 0 {
+            match self.broadcast(sent.transaction_parameters.clone()).await {
+                Ok(hash) => {
+                    sent.hashes.insert(hash);
+                    sent.latest = hash;
+                    return Ok(());
+                }
+                Err(TransactionMonitorError::Rejected {
+                    rejection: Rejection::ReplacementUnderpriced,
+                    ..
+                }) => {
+                    // The replacement needs to pay more, so bump again right away.
+                    sent.retries -= 1;
+                    debug!(gas_price = ?sent.gas_price, retries_left = %sent.retries, "The replacement transaction was underpriced");
+                    sent.gas_price = sent
+                        .gas_price
+                        .bump(self.options.gas_percentual_increase, max_bumped_gas_price);
+                    sent.gas_price.apply(&mut sent.transaction_parameters);
+                }
+                // A transaction we sent before was mined in the meantime.
+                Err(error) if error.is_nonce_too_low() && !sent.hashes.is_empty() => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the sent transactions with a zero-value transfer from the owner to itself, paying
+    /// more than `gas_price`, so that their nonce doesn't hold back later transactions. Once the
+    /// cancellation is mined, this fails with [`TransactionMonitorError::Cancelled`] and the payload
+    /// can be submitted again. If one of the `sent_transactions` is mined instead, its receipt is
+    /// returned.
+    async fn cancel(
+        &self,
+        gas_price: GasPrice,
+        mut sent_transactions: HashSet<H256>,
+    ) -> Result<TransactionReceipt, TransactionMonitorError> {
+        let owner = self
+            .signer
+            .address()
+            .await
+            .map_err(TransactionMonitorError::Signing)?;
+        let cancellation = cancellation(
+            &self.transaction_parameters,
+            owner,
+            // A cancellation must outbid the transactions it replaces, so bumps aren't capped.
+            gas_price.bump(self.options.gas_percentual_increase, None),
+        );
+        let cancellation_hash = match self.broadcast(cancellation).await {
+            Ok(hash) => {
+                sent_transactions.insert(hash);
+                Some(hash)
+            }
+            // A transaction we sent before was mined in the meantime.
+            Err(error) if error.is_nonce_too_low() => None,
+            Err(error) => return Err(error),
+        };
+
+        match self.wait_for_any(&sent_transactions).await {
+            Ok(receipt) if Some(receipt.transaction_hash) == cancellation_hash => {
+                Err(TransactionMonitorError::Cancelled {
+                    tx_hash: receipt.transaction_hash,
+                    // Unwrap: the nonce is always set, see `new`.
+                    nonce: self.transaction_parameters.nonce.unwrap(),
+                })
+            }
+            Ok(receipt) => Ok(receipt),
+            Err(ConfirmationError::Timeout(_)) => Err(TransactionMonitorError::BroadcastFailure),
+            Err(error) => Err(self.confirmation_error(error)),
+        }
+    }
+
+    fn confirmation_error(&self, error: ConfirmationError) -> TransactionMonitorError {
+        // Unwrap: the nonce is always set, see `new`.
+        TransactionMonitorError::from_confirmation(
+            error,
+            self.transaction_parameters.nonce.unwrap(),
+        )
+    }
 }
 
-fn bump_gas(gas_price: U256, percentual_increase: u32) -> Option<U256> {
-    let factor = U256::from(100 + percentual_increase);
-    let denominator = U256::from(100);
-    gas_price.checked_mul(factor)?.checked_div(denominator)
+/// A zero-value transfer from `owner` to itself that replaces `transaction`.
+fn cancellation(
+    transaction: &TransactionParameters,
+    owner: Address,
+    gas_price: GasPrice,
+) -> TransactionParameters {
+    let mut cancellation = TransactionParameters {
+        to: Some(owner),
+        gas: CANCELLATION_GAS.into(),
+        nonce: transaction.nonce,
+        chain_id: transaction.chain_id,
+        ..Default::default()
+    };
+    gas_price.apply(&mut cancellation);
+    cancellation
 }
 
-#[test]
-fn test_bump_gas() {
-    let input: U256 = 1000.into();
-    let percentual_increase: u32 = 25;
-    let expected: U256 = 1250.into();
-    let output = bump_gas(input, percentual_increase);
-    assert_eq!(output, Some(expected));
+/// Why a provider refused a transaction, if it's a reason that we know how to handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// A transaction with the same nonce is pending, and it pays at least as much.
+    ReplacementUnderpriced,
+    /// The very same transaction is pending already.
+    AlreadyKnown,
+    /// A transaction with the same nonce was mined already.
+    NonceTooLow,
+    /// The owner can't pay for the transaction's gas.
+    InsufficientFunds,
+    /// The transaction would revert, for providers that check before accepting it.
+    ExecutionReverted,
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Rejection::ReplacementUnderpriced => "a pending transaction with its nonce pays more",
+            Rejection::AlreadyKnown => "it's pending already",
+            Rejection::NonceTooLow => "its nonce was used already",
+            Rejection::InsufficientFunds => "the owner can't pay for it",
+            Rejection::ExecutionReverted => "it would revert",
+        })
+    }
+}
+
+fn rejection(error: &Web3Error) -> Option<Rejection> {
+    let Web3Error::Rpc(error) = error else {
+        return None;
+    };
+    // Reverts have their own code, see EIP-1474.
+    if error.code == jsonrpc_core::ErrorCode::ServerError(3) {
+        return Some(Rejection::ExecutionReverted);
+    }
+    // Clients word these differently, e.g. Geth and Erigon, OpenEthereum ("Transaction nonce is
+    // too low") and Nethermind ("OldNonce").
+    let message = error.message.to_lowercase();
+    let contains = |patterns: &[&str]| patterns.iter().any(|pattern| message.contains(pattern));
+    if contains(&["underpriced", "feetoolow"]) {
+        Some(Rejection::ReplacementUnderpriced)
+    } else if contains(&["already known", "known transaction", "already imported"]) {
+        Some(Rejection::AlreadyKnown)
+    } else if contains(&["nonce too low", "nonce is too low", "oldnonce"]) {
+        Some(Rejection::NonceTooLow)
+    } else if contains(&["insufficient funds", "insufficientfunds"]) {
+        Some(Rejection::InsufficientFunds)
+    } else if contains(&["execution reverted"]) {
+        Some(Rejection::ExecutionReverted)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rpc_error(message: &str) -> Web3Error {
+        Web3Error::Rpc(jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(-32000),
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    #[test]
+    fn rejections() {
+        assert_eq!(
+            rejection(&rpc_error("replacement transaction underpriced")),
+            Some(Rejection::ReplacementUnderpriced)
+        );
+        assert_eq!(
+            rejection(&rpc_error("already known")),
+            Some(Rejection::AlreadyKnown)
+        );
+        assert_eq!(
+            rejection(&rpc_error("Known transaction: 0x1234")),
+            Some(Rejection::AlreadyKnown)
+        );
+        assert_eq!(
+            rejection(&rpc_error("nonce too low")),
+            Some(Rejection::NonceTooLow)
+        );
+        assert_eq!(
+            rejection(&rpc_error(
+                "Transaction nonce is too low. Try incrementing the nonce."
+            )),
+            Some(Rejection::NonceTooLow)
+        );
+        assert_eq!(
+            rejection(&rpc_error("OldNonce")),
+            Some(Rejection::NonceTooLow)
+        );
+        assert_eq!(
+            rejection(&rpc_error("insufficient funds for gas * price + value")),
+            Some(Rejection::InsufficientFunds)
+        );
+        assert_eq!(
+            rejection(&rpc_error("execution reverted")),
+            Some(Rejection::ExecutionReverted)
+        );
+        let mut reverted = jsonrpc_core::Error::new(jsonrpc_core::ErrorCode::ServerError(3));
+        reverted.message = "Pausable: paused".to_string();
+        assert_eq!(
+            rejection(&Web3Error::Rpc(reverted)),
+            Some(Rejection::ExecutionReverted)
+        );
+        assert_eq!(rejection(&rpc_error("header not found")), None);
+        assert_eq!(rejection(&Web3Error::Unreachable), None);
+    }
+
+    #[test]
+    fn sending_errors() {
+        assert!(TransactionMonitorError::sending(rpc_error("nonce too low")).is_nonce_too_low());
+        assert_eq!(
+            TransactionMonitorError::sending(rpc_error("insufficient funds")).rejection(),
+            Some(Rejection::InsufficientFunds)
+        );
+        assert!(matches!(
+            TransactionMonitorError::sending(rpc_error("header not found")),
+            TransactionMonitorError::Provider(_)
+        ));
+    }
+
+    #[test]
+    fn cancellations() {
+        let owner = Address::repeat_byte(0x22);
+        let transaction = TransactionParameters {
+            nonce: Some(7.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            gas: 100_000.into(),
+            data: vec![1, 2, 3].into(),
+            chain_id: Some(42161),
+            ..Default::default()
+        };
+        let gas_price = GasPrice::Eip1559 {
+            max_fee_per_gas: 2_000.into(),
+            max_priority_fee_per_gas: 10.into(),
+        };
+        let cancellation = cancellation(&transaction, owner, gas_price);
+        assert_eq!(cancellation.to, Some(owner));
+        assert_eq!(cancellation.nonce, transaction.nonce);
+        assert_eq!(cancellation.chain_id, transaction.chain_id);
+        assert_eq!(cancellation.value, U256::zero());
+        assert_eq!(cancellation.gas, CANCELLATION_GAS.into());
+        assert!(cancellation.data.0.is_empty());
+        assert_eq!(cancellation.max_fee_per_gas, Some(2_000.into()));
+        assert_eq!(cancellation.max_priority_fee_per_gas, Some(10.into()));
+    }
 }
@@ -0,0 +1,187 @@
+//! Integration with service managers outside of Kubernetes.
+//!
+//! Under systemd (`Type=notify`), the oracle reports `READY=1` once it enters the main polling
+//! loop and `STOPPING=1` when it shuts down, following the `sd_notify(3)` protocol. `SIGTERM` is
+//! handled like CTRL+C, so `systemctl stop` waits for the current polling iteration to finish.
+//!
+//! With `WatchdogSec` set, the main loop sends `WATCHDOG=1` between polling iterations, including
+//! while it sleeps or cools down after errors, but never during an iteration. A wedged iteration
+//! (e.g. a hung RPC call) thus gets the oracle restarted. `WatchdogSec` must be longer than any
+//! single polling iteration.
+//!
+//! Outside of systemd, i.e. if `NOTIFY_SOCKET` is unset, notifications are no-ops.
+
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Sends notifications to the service manager that started the oracle, if any.
+#[derive(Debug, Default)]
+pub struct ServiceNotifier {
+    #[cfg(unix)]
+    socket: Option<(
+        std::os::unix::net::UnixDatagram,
+        std::os::unix::net::SocketAddr,
+    )>,
+    /// How often to send `WATCHDOG=1`, i.e. half of `WatchdogSec`.
+    watchdog_interval: Option<Duration>,
+}
+
+impl ServiceNotifier {
+    /// Connects to the socket in `NOTIFY_SOCKET`, if set.
+    pub fn from_env() -> Self {
+        let mut notifier = match std::env::var_os("NOTIFY_SOCKET") {
+            Some(path) => Self::new(&path.to_string_lossy()),
+            None => return Self::default(),
+        };
+        notifier.watchdog_interval = watchdog_interval(
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+        );
+        if let Some(interval) = notifier.watchdog_interval {
+            info!(
+                interval_in_ms = interval.as_millis() as u64,
+                "The systemd watchdog is enabled."
+            );
+        }
+        notifier
+    }
+
+    #[cfg(unix)]
+    fn new(path: &str) -> Self {
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let address = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name.as_bytes())
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => Err(std::io::Error::from(std::io::ErrorKind::Unsupported)),
+            None => SocketAddr::from_pathname(path),
+        };
+        match address.and_then(|address| Ok((UnixDatagram::unbound()?, address))) {
+            Ok(socket) => Self {
+                socket: Some(socket),
+                watchdog_interval: None,
+            },
+            Err(error) => {
+                warn!(%error, path, "Failed to open the service manager's notification socket.");
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn new(_path: &str) -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(unix)]
+        return self.socket.is_some();
+        #[cfg(not(unix))]
+        return false;
+    }
+
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// Tells the watchdog that the main loop is alive.
+    pub fn watchdog(&self) {
+        if self.watchdog_interval.is_some() {
+            self.notify("WATCHDOG=1");
+        }
+    }
+
+    /// Sleeps for `duration`, while keeping the watchdog happy.
+    pub async fn sleep(&self, duration: Duration) {
+        let Some(interval) = self.watchdog_interval else {
+            return tokio::time::sleep(duration).await;
+        };
+        let deadline = tokio::time::Instant::now() + duration;
+        loop {
+            self.watchdog();
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                return;
+            }
+            tokio::time::sleep_until(deadline.min(now + interval)).await;
+        }
+    }
+
+    /// A short, human-readable status, shown by `systemctl status`.
+    pub fn status(&self, status: &str) {
+        self.notify(&format!("STATUS={}", status.replace('\n', " ")));
+    }
+
+    #[cfg(unix)]
+    fn notify(&self, state: &str) {
+        let Some((socket, address)) = &self.socket else {
+            return;
+        };
+        debug!(state, "Notifying the service manager.");
+        if let Err(error) = socket.send_to_addr(state.as_bytes(), address) {
+            warn!(%error, state, "Failed to notify the service manager.");
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn notify(&self, _state: &str) {}
+}
+
+/// Follows `sd_watchdog_enabled(3)`: the watchdog is meant for us if `WATCHDOG_PID` is either
+/// unset or our PID.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Option<Duration> {
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    if pid.is_some_and(|pid| pid.parse() != Ok(std::process::id())) {
+        return None;
+    }
+    Some(Duration::from_micros(usec) / 2)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn watchdog_env() {
+        let pid = std::process::id().to_string();
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some(&pid)),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("1")), None);
+        assert_eq!(watchdog_interval(Some("0"), None), None);
+        assert_eq!(watchdog_interval(None, None), None);
+    }
+
+    #[test]
+    fn notify() {
+        let dir = std::env::temp_dir().join(format!("block-oracle-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&dir);
+        let receiver = UnixDatagram::bind(&dir).unwrap();
+
+        let notifier = ServiceNotifier::new(dir.to_str().unwrap());
+        assert!(notifier.is_enabled());
+        notifier.ready();
+        notifier.status("Polling\nagain");
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STATUS=Polling again");
+        std::fs::remove_file(&dir).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Signs with an asymmetric `ECC_SECG_P256K1` key in AWS KMS, so the owner key never exists on the
+//! oracle host.
+//!
+//! Requests are authenticated with the credentials in the `AWS_ACCESS_KEY_ID`,
+//! `AWS_SECRET_ACCESS_KEY` and (optionally) `AWS_SESSION_TOKEN` environment variables. The IAM
+//! principal needs the `kms:GetPublicKey` and `kms:Sign` permissions on the key.
+
+use super::{address_from_public_key, signature_from_der, Signer, SignerError};
+use crate::config::AwsKmsOptions;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use openssl::{base64, hash::MessageDigest, pkey::PKey, sha::sha256, sign};
+use serde::Deserialize;
+use std::time::SystemTime;
+use tokio::sync::OnceCell;
+use tracing::debug;
+use web3::{
+    signing::Signature,
+    types::{Address, H256},
+};
+
+const SERVICE: &str = "kms";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AwsKmsError {
+    #[error("missing AWS credentials, {0} isn't set")]
+    MissingCredentials(&'static str),
+    #[error("request to AWS KMS failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("AWS KMS responded with {status}: {body}")]
+    Api {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    #[error("invalid response from AWS KMS: {0}")]
+    InvalidResponse(String),
+    #[error(transparent)]
+    Crypto(#[from] openssl::error::ErrorStack),
+}
+
+impl From<serde_json::Error> for AwsKmsError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::InvalidResponse(error.to_string())
+    }
+}
+
+pub struct AwsKmsSigner {
+    options: AwsKmsOptions,
+    http: reqwest::Client,
+    /// Derived from the public key, which is fetched once.
+    address: OnceCell<Address>,
+}
+
+impl AwsKmsSigner {
+    pub fn new(options: AwsKmsOptions) -> Self {
+        Self {
+            options,
+            http: reqwest::Client::new(),
+            address: OnceCell::new(),
+        }
+    }
+
+    async fn request<R: for<'de> Deserialize<'de>>(
+        &self,
+        action: &str,
+        body: serde_json::Value,
+    ) -> Result<R, AwsKmsError> {
+        let credentials = Credentials::from_env()?;
+        let body = serde_json::to_vec(&body)?;
+        let host =
+            self.options.endpoint.host_str().ok_or_else(|| {
+                AwsKmsError::InvalidResponse("the endpoint has no host".to_string())
+            })?;
+        let host = match self.options.endpoint.port() {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let target = format!("TrentService.{action}");
+        let request = SignableRequest {
+            method: "POST",
+            path: "/",
+            query: "",
+            headers: vec![
+                ("content-type", "application/x-amz-json-1.1".to_string()),
+                ("host", host),
+                ("x-amz-target", target.clone()),
+            ],
+            body: &body,
+        };
+        let headers = sign_v4(
+            &request,
+            &credentials,
+            &self.options.region,
+            SERVICE,
+            SystemTime::now().into(),
+        )?;
+
+        let mut builder = self
+            .http
+            .post(self.options.endpoint.clone())
+            .header("content-type", "application/x-amz-json-1.1")
+            .header("x-amz-target", target);
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder.body(body).send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            return Err(AwsKmsError::Api {
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn fetch_address(&self) -> Result<Address, SignerError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct GetPublicKeyResponse {
+            public_key: String,
+        }
+        let response: GetPublicKeyResponse = self
+            .request(
+                "GetPublicKey",
+                serde_json::json!({ "KeyId": self.options.key_id }),
+            )
+            .await?;
+        let public_key = PKey::public_key_from_der(&base64::decode_block(&response.public_key)?)?;
+        let address = address_from_public_key(&public_key)?;
+        debug!(?address, key_id = %self.options.key_id, "Fetched the address of the AWS KMS key");
+        Ok(address)
+    }
+}
+
+#[async_trait]
+impl Signer for AwsKmsSigner {
+    async fn address(&self) -> Result<Address, SignerError> {
+        let address = self
+            .address
+            .get_or_try_init(|| self.fetch_address())
+            .await?;
+        Ok(*address)
+    }
+
+    async fn sign_hash(&self, hash: H256) -> Result<Signature, SignerError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct SignResponse {
+            signature: String,
+        }
+        let address = self.address().await?;
+        let response: SignResponse = self
+            .request(
+                "Sign",
+                serde_json::json!({
+                    "KeyId": self.options.key_id,
+                    "Message": base64::encode_block(hash.as_bytes()),
+                    "MessageType": "DIGEST",
+                    "SigningAlgorithm": "ECDSA_SHA_256",
+                }),
+            )
+            .await?;
+        let der = base64::decode_block(&response.signature)?;
+        signature_from_der(&der, hash, address)
+    }
+}
+
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    fn from_env() -> Result<Self, AwsKmsError> {
+        let var = |name| std::env::var(name).map_err(|_| AwsKmsError::MissingCredentials(name));
+        Ok(Self {
+            access_key_id: var("AWS_ACCESS_KEY_ID")?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+}
+
+struct SignableRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    /// Already in canonical form, i.e. sorted and URI-encoded.
+    query: &'a str,
+    /// Lowercase names. `x-amz-date` and `x-amz-security-token` are added when signing.
+    headers: Vec<(&'static str, String)>,
+    body: &'a [u8],
+}
+
+/// Signs `request` with AWS Signature Version 4, and returns the headers that must be added to it.
+fn sign_v4(
+    request: &SignableRequest,
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    now: DateTime<Utc>,
+) -> Result<Vec<(&'static str, String)>, AwsKmsError> {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+
+    let mut added = vec![("x-amz-date", amz_date.clone())];
+    if let Some(session_token) = &credentials.session_token {
+        added.push(("x-amz-security-token", session_token.clone()));
+    }
+    let mut headers: Vec<_> = request.headers.iter().chain(&added).collect();
+    headers.sort_by_key(|(name, _)| *name);
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{}",
+        request.method,
+        request.path,
+        request.query,
+        hex::encode(sha256(request.body)),
+    );
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex::encode(sha256(canonical_request.as_bytes()))
+    );
+
+    let mut key = hmac(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(),
+        date.as_bytes(),
+    )?;
+    for part in [region, service, "aws4_request"] {
+        key = hmac(&key, part.as_bytes())?;
+    }
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes())?);
+
+    added.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            credentials.access_key_id
+        ),
+    ));
+    Ok(added)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, AwsKmsError> {
+    let key = PKey::hmac(key)?;
+    let mut signer = sign::Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example of the AWS documentation, see
+    /// <https://docs.aws.amazon.com/IAM/latest/UserGuide/create-signed-request.html>.
+    #[test]
+    fn signature_v4() {
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let request = SignableRequest {
+            method: "GET",
+            path: "/",
+            query: "Action=ListUsers&Version=2010-05-08",
+            headers: vec![
+                (
+                    "content-type",
+                    "application/x-www-form-urlencoded; charset=utf-8".to_string(),
+                ),
+                ("host", "iam.amazonaws.com".to_string()),
+            ],
+            body: b"",
+        };
+        let now = "2015-08-30T12:36:00Z".parse().unwrap();
+        let headers = sign_v4(&request, &credentials, "us-east-1", "iam", now).unwrap();
+        assert_eq!(
+            headers,
+            [
+                ("x-amz-date", "20150830T123600Z".to_string()),
+                (
+                    "authorization",
+                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+                     SignedHeaders=content-type;host;x-amz-date, \
+                     Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+                        .to_string()
+                )
+            ]
+        );
+    }
+}
@@ -0,0 +1,668 @@
+//! Signs with the Ethereum app of a Ledger hardware wallet, for manual operations that someone
+//! approves on the device, like `send-message` and `reset-subgraph`.
+//!
+//! The device is found among the Linux `hidraw` devices, which must be readable and writable by
+//! the user, e.g. with the udev rules of Ledger Live. The Ethereum app can't decode DataEdge
+//! calldata, so blind signing must be enabled in its settings.
+
+use super::{recovery_id, Signer, SignerError};
+use async_trait::async_trait;
+use rlp::RlpStream;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+use tracing::debug;
+use web3::{
+    signing::{keccak256, Signature},
+    types::{Address, Bytes, TransactionParameters, H256, U256},
+};
+
+/// The account that Ledger Live and most wallets use first.
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0/0";
+
+const LEDGER_VENDOR_ID: u32 = 0x2c97;
+/// Report descriptors of the interface that speaks APDUs start with its vendor-defined usage page,
+/// 0xffa0. The other interfaces are for U2F and the like.
+const APDU_USAGE_PAGE: [u8; 3] = [0x06, 0xa0, 0xff];
+
+const PACKET_SIZE: usize = 64;
+const PACKET_HEADER_SIZE: usize = 5;
+const CHANNEL: [u8; 2] = [0x01, 0x01];
+const TAG_APDU: u8 = 0x05;
+
+const CLA: u8 = 0xe0;
+const INS_GET_ADDRESS: u8 = 0x02;
+const INS_SIGN_TRANSACTION: u8 = 0x04;
+const P1_FIRST_CHUNK: u8 = 0x00;
+const P1_MORE_CHUNKS: u8 = 0x80;
+/// Transactions are sent in chunks of this size, which leaves room for the EIP-155 fields of
+/// legacy transactions (see [`sign_transaction_apdus`]).
+const CHUNK_SIZE: usize = 150;
+const MAX_DERIVATION_DEPTH: usize = 10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum LedgerError {
+    #[error("no Ledger device found, is it connected and readable by this user?")]
+    NoDevice,
+    #[error("failed to talk to the Ledger device: {0}")]
+    Io(#[from] io::Error),
+    #[error("the Ledger device is locked")]
+    Locked,
+    #[error("the Ethereum app isn't open on the Ledger device")]
+    AppNotOpen,
+    #[error("the transaction was rejected on the Ledger device")]
+    Rejected,
+    #[error("the Ethereum app refused the transaction, is blind signing enabled?")]
+    InvalidData,
+    #[error("the Ledger device responded with status {0:#06x}")]
+    Status(u16),
+    #[error("invalid response from the Ledger device: {0}")]
+    InvalidResponse(String),
+    #[error("invalid derivation path '{0}'")]
+    InvalidDerivationPath(String),
+    #[error("the transaction has no {0}")]
+    IncompleteTransaction(&'static str),
+    #[error("unsupported transaction type {0}")]
+    UnsupportedTransactionType(u64),
+    #[error("Ledger devices only sign whole transactions")]
+    HashesNotSupported,
+}
+
+/// A BIP 32 derivation path like `m/44'/60'/0'/0/0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    /// The number of indices, followed by every index.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.0.len() as u8];
+        for index in &self.0 {
+            bytes.extend(index.to_be_bytes());
+        }
+        bytes
+    }
+}
+
+impl Default for DerivationPath {
+    fn default() -> Self {
+        // Unwrap: the default path is valid.
+        DEFAULT_DERIVATION_PATH.parse().unwrap()
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = LedgerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LedgerError::InvalidDerivationPath(s.to_string());
+        let indices = s
+            .strip_prefix("m/")
+            .unwrap_or(s)
+            .split('/')
+            .map(|index| {
+                let (index, hardened) = match index.strip_suffix(['\'', 'h']) {
+                    Some(index) => (index, true),
+                    None => (index, false),
+                };
+                let index: u32 = index.parse().map_err(|_| invalid())?;
+                if index & 0x8000_0000 != 0 {
+                    return Err(invalid());
+                }
+                Ok(if hardened { index | 0x8000_0000 } else { index })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if indices.len() > MAX_DERIVATION_DEPTH {
+            return Err(invalid());
+        }
+        Ok(Self(indices))
+    }
+}
+
+pub struct LedgerSigner {
+    /// Exchanges block until the user reacts, so they run on the blocking thread pool.
+    device: Arc<Mutex<HidDevice>>,
+    path: DerivationPath,
+    /// Read from the device once.
+    address: OnceCell<Address>,
+}
+
+impl LedgerSigner {
+    /// Connects to the first Ledger device.
+    pub fn open(path: DerivationPath) -> Result<Self, LedgerError> {
+        Ok(Self {
+            device: Arc::new(Mutex::new(HidDevice::open()?)),
+            path,
+            address: OnceCell::new(),
+        })
+    }
+
+    /// Sends `apdus` in order, and returns the response to the last one.
+    async fn exchange(&self, apdus: Vec<Vec<u8>>) -> Result<Vec<u8>, LedgerError> {
+        let device = self.device.clone();
+        tokio::task::spawn_blocking(move || {
+            // Unwrap: exchanges don't panic while holding the lock.
+            let mut device = device.lock().unwrap();
+            let mut response = vec![];
+            for apdu in apdus {
+                response = device.exchange(&apdu)?;
+            }
+            Ok(response)
+        })
+        .await
+        .map_err(|error| LedgerError::Io(io::Error::other(error)))?
+    }
+
+    async fn fetch_address(&self) -> Result<Address, LedgerError> {
+        let apdu = apdu(INS_GET_ADDRESS, 0, 0, &self.path.to_bytes());
+        let address = address_from_response(&self.exchange(vec![apdu]).await?)?;
+        debug!(?address, path = ?self.path, "Read the address of the Ledger account");
+        Ok(address)
+    }
+
+    async fn sign(&self, transaction: &TransactionParameters) -> Result<Bytes, SignerError> {
+        let address = self.address().await?;
+        let chain_id = transaction
+            .chain_id
+            .ok_or(LedgerError::IncompleteTransaction("chain ID"))?;
+        let unsigned = encode(transaction, chain_id, None)?;
+        let legacy = is_legacy(transaction);
+        // The EIP-155 fields of unsigned legacy transactions are the chain ID and two zeros.
+        let eip155_fields_size = if legacy {
+            rlp::encode(&chain_id).len() + 2
+        } else {
+            0
+        };
+        let apdus = sign_transaction_apdus(&self.path, &unsigned, eip155_fields_size);
+        let response = self.exchange(apdus).await?;
+
+        // The response is `v`, `r` and `s`. `v` is truncated to a byte, so it's useless for most
+        // chain IDs, and the recovery id is found again instead.
+        if response.len() != 65 {
+            return Err(LedgerError::InvalidResponse(format!(
+                "expected a 65-byte signature, got {} bytes",
+                response.len()
+            ))
+            .into());
+        }
+        let r = H256::from_slice(&response[1..33]);
+        let s = H256::from_slice(&response[33..65]);
+        let recovery_id = recovery_id(keccak256(&unsigned).into(), r, s, address)?;
+        // See EIP-155.
+        let v = if legacy {
+            recovery_id + 35 + chain_id * 2
+        } else {
+            recovery_id
+        };
+        Ok(encode(transaction, chain_id, Some(&Signature { v, r, s }))?.into())
+    }
+}
+
+#[async_trait]
+impl Signer for LedgerSigner {
+    async fn address(&self) -> Result<Address, SignerError> {
+        let address = self
+            .address
+            .get_or_try_init(|| self.fetch_address())
+            .await?;
+        Ok(*address)
+    }
+
+    async fn sign_hash(&self, _hash: H256) -> Result<Signature, SignerError> {
+        Err(LedgerError::HashesNotSupported.into())
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: &TransactionParameters,
+    ) -> Option<Result<Bytes, SignerError>> {
+        Some(self.sign(transaction).await)
+    }
+}
+
+struct HidDevice {
+    file: File,
+}
+
+impl HidDevice {
+    fn open() -> Result<Self, LedgerError> {
+        let sys_class = Path::new("/sys/class/hidraw");
+        let mut names: Vec<_> = match fs::read_dir(sys_class) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.file_name()))
+                .collect(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => vec![],
+            Err(error) => return Err(error.into()),
+        };
+        names.sort();
+        for name in names {
+            let device = sys_class.join(&name).join("device");
+            let Ok(uevent) = fs::read_to_string(device.join("uevent")) else {
+                continue;
+            };
+            let Ok(descriptor) = fs::read(device.join("report_descriptor")) else {
+                continue;
+            };
+            if vendor_id(&uevent) != Some(LEDGER_VENDOR_ID)
+                || !descriptor.starts_with(&APDU_USAGE_PAGE)
+            {
+                continue;
+            }
+            let path = Path::new("/dev").join(&name);
+            debug!(path = %path.display(), "Found a Ledger device");
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            return Ok(Self { file });
+        }
+        Err(LedgerError::NoDevice)
+    }
+
+    fn exchange(&mut self, apdu: &[u8]) -> Result<Vec<u8>, LedgerError> {
+        for packet in packets(apdu) {
+            // Writes to hidraw start with the report number, which is 0 for devices without
+            // numbered reports.
+            let mut report = [0; PACKET_SIZE + 1];
+            report[1..].copy_from_slice(&packet);
+            self.file.write_all(&report)?;
+        }
+        let response = read_packets(|| {
+            let mut packet = [0; PACKET_SIZE];
+            self.file.read_exact(&mut packet)?;
+            Ok(packet)
+        })?;
+        check_status(response)
+    }
+}
+
+/// The vendor ID in the `HID_ID` of a `uevent` file, e.g. `HID_ID=0003:00002C97:00004015`.
+fn vendor_id(uevent: &str) -> Option<u32> {
+    let id = uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("HID_ID="))?;
+    u32::from_str_radix(id.split(':').nth(1)?, 16).ok()
+}
+
+fn apdu(ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![CLA, ins, p1, p2, data.len() as u8];
+    apdu.extend(data);
+    apdu
+}
+
+/// Splits an APDU into HID packets. Every packet starts with the channel, the tag and a sequence
+/// number, and the first one also has the length of the APDU.
+fn packets(apdu: &[u8]) -> Vec<[u8; PACKET_SIZE]> {
+    let data = [&(apdu.len() as u16).to_be_bytes(), apdu].concat();
+    data.chunks(PACKET_SIZE - PACKET_HEADER_SIZE)
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut packet = [0; PACKET_SIZE];
+            packet[..2].copy_from_slice(&CHANNEL);
+            packet[2] = TAG_APDU;
+            packet[3..5].copy_from_slice(&(sequence as u16).to_be_bytes());
+            packet[PACKET_HEADER_SIZE..][..chunk.len()].copy_from_slice(chunk);
+            packet
+        })
+        .collect()
+}
+
+/// The inverse of [`packets`], which reads packets with `next_packet` until the response is
+/// complete.
+fn read_packets(
+    mut next_packet: impl FnMut() -> io::Result<[u8; PACKET_SIZE]>,
+) -> Result<Vec<u8>, LedgerError> {
+    let mut data = vec![];
+    for sequence in 0u16.. {
+        let packet = next_packet()?;
+        if packet[..2] != CHANNEL || packet[2] != TAG_APDU || packet[3..5] != sequence.to_be_bytes()
+        {
+            return Err(LedgerError::InvalidResponse(format!(
+                "unexpected packet header {}",
+                hex::encode(&packet[..PACKET_HEADER_SIZE])
+            )));
+        }
+        data.extend(&packet[PACKET_HEADER_SIZE..]);
+        let length = u16::from_be_bytes([data[0], data[1]]) as usize;
+        if data.len() >= length + 2 {
+            data.truncate(length + 2);
+            return Ok(data.split_off(2));
+        }
+    }
+    Err(LedgerError::InvalidResponse(
+        "the response is too long".to_string(),
+    ))
+}
+
+/// Strips the status word from the end of `response`, and turns failures into errors.
+fn check_status(mut response: Vec<u8>) -> Result<Vec<u8>, LedgerError> {
+    let Some(data_length) = response.len().checked_sub(2) else {
+        return Err(LedgerError::InvalidResponse(
+            "the status word is missing".to_string(),
+        ));
+    };
+    let status = u16::from_be_bytes([response[data_length], response[data_length + 1]]);
+    response.truncate(data_length);
+    match status {
+        0x9000 => Ok(response),
+        0x5515 => Err(LedgerError::Locked),
+        0x6985 => Err(LedgerError::Rejected),
+        0x6a80 => Err(LedgerError::InvalidData),
+        0x6511 | 0x6d00 | 0x6e00 | 0x6e01 => Err(LedgerError::AppNotOpen),
+        status => Err(LedgerError::Status(status)),
+    }
+}
+
+/// The response to `GET ETH PUBLIC ADDRESS` is the length of the public key, the public key, the
+/// length of the address and the address in hex.
+fn address_from_response(response: &[u8]) -> Result<Address, LedgerError> {
+    let invalid = || LedgerError::InvalidResponse("malformed address".to_string());
+    let public_key_length = *response.first().ok_or_else(invalid)? as usize;
+    let address = response.get(1 + public_key_length..).ok_or_else(invalid)?;
+    let address_length = *address.first().ok_or_else(invalid)? as usize;
+    let address = address.get(1..1 + address_length).ok_or_else(invalid)?;
+    let address = hex::decode(address).map_err(|_| invalid())?;
+    if address.len() != Address::len_bytes() {
+        return Err(invalid());
+    }
+    Ok(Address::from_slice(&address))
+}
+
+/// The `SIGN ETH TRANSACTION` APDUs for an unsigned transaction. The first chunk starts with the
+/// derivation path.
+///
+/// The Ethereum app fails to parse legacy transactions if a chunk ends right before or within their
+/// EIP-155 fields, i.e. their last `eip155_fields_size` bytes, so those are never split off.
+fn sign_transaction_apdus(
+    path: &DerivationPath,
+    unsigned: &[u8],
+    eip155_fields_size: usize,
+) -> Vec<Vec<u8>> {
+    let data = [path.to_bytes().as_slice(), unsigned].concat();
+    let eip155_fields = data.len() - eip155_fields_size;
+    let mut apdus = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        let mut end = data.len().min(offset + CHUNK_SIZE);
+        if end >= eip155_fields {
+            end = data.len();
+        }
+        let p1 = if offset == 0 {
+            P1_FIRST_CHUNK
+        } else {
+            P1_MORE_CHUNKS
+        };
+        apdus.push(apdu(INS_SIGN_TRANSACTION, p1, 0, &data[offset..end]));
+        offset = end;
+    }
+    apdus
+}
+
+fn is_legacy(transaction: &TransactionParameters) -> bool {
+    transaction
+        .transaction_type
+        .is_none_or(|transaction_type| transaction_type.is_zero())
+}
+
+/// RLP-encodes `transaction` like web3 does, i.e. with the same defaults for missing fees. Without
+/// a signature, this is what the Ledger device signs.
+fn encode(
+    transaction: &TransactionParameters,
+    chain_id: u64,
+    signature: Option<&Signature>,
+) -> Result<Vec<u8>, LedgerError> {
+    let transaction_type = transaction
+        .transaction_type
+        .map_or(0, |transaction_type| transaction_type.as_u64());
+    let nonce = transaction
+        .nonce
+        .ok_or(LedgerError::IncompleteTransaction("nonce"))?;
+    let gas_price = match transaction_type {
+        2 => transaction.max_fee_per_gas.or(transaction.gas_price),
+        _ => transaction.gas_price,
+    }
+    .ok_or(LedgerError::IncompleteTransaction("gas price"))?;
+
+    let mut stream = RlpStream::new();
+    match transaction_type {
+        0 => {
+            stream.begin_list(9);
+            append_legacy_fields(&mut stream, transaction, nonce, gas_price);
+            match signature {
+                Some(signature) => append_signature(&mut stream, signature),
+                None => {
+                    stream.append(&chain_id);
+                    stream.append(&0u8);
+                    stream.append(&0u8);
+                }
+            }
+            Ok(stream.out().to_vec())
+        }
+        1 => {
+            stream.begin_list(if signature.is_some() { 11 } else { 8 });
+            stream.append(&chain_id);
+            append_legacy_fields(&mut stream, transaction, nonce, gas_price);
+            append_access_list(&mut stream, transaction);
+            if let Some(signature) = signature {
+                append_signature(&mut stream, signature);
+            }
+            Ok([&[1], stream.as_raw()].concat())
+        }
+        2 => {
+            stream.begin_list(if signature.is_some() { 12 } else { 9 });
+            stream.append(&chain_id);
+            stream.append(&nonce);
+            stream.append(&transaction.max_priority_fee_per_gas.unwrap_or(gas_price));
+            stream.append(&gas_price);
+            stream.append(&transaction.gas);
+            append_call(&mut stream, transaction);
+            append_access_list(&mut stream, transaction);
+            if let Some(signature) = signature {
+                append_signature(&mut stream, signature);
+            }
+            Ok([&[2], stream.as_raw()].concat())
+        }
+        transaction_type => Err(LedgerError::UnsupportedTransactionType(transaction_type)),
+    }
+}
+
+fn append_legacy_fields(
+    stream: &mut RlpStream,
+    transaction: &TransactionParameters,
+    nonce: U256,
+    gas_price: U256,
+) {
+    stream.append(&nonce);
+    stream.append(&gas_price);
+    stream.append(&transaction.gas);
+    append_call(stream, transaction);
+}
+
+fn append_call(stream: &mut RlpStream, transaction: &TransactionParameters) {
+    match transaction.to {
+        Some(to) => stream.append(&to),
+        None => stream.append(&""),
+    };
+    stream.append(&transaction.value);
+    stream.append(&transaction.data.0);
+}
+
+fn append_access_list(stream: &mut RlpStream, transaction: &TransactionParameters) {
+    let access_list = transaction.access_list.as_deref().unwrap_or_default();
+    stream.begin_list(access_list.len());
+    for item in access_list {
+        stream.begin_list(2);
+        stream.append(&item.address);
+        stream.begin_list(item.storage_keys.len());
+        for storage_key in &item.storage_keys {
+            stream.append(storage_key);
+        }
+    }
+}
+
+fn append_signature(stream: &mut RlpStream, signature: &Signature) {
+    stream.append(&signature.v);
+    stream.append(&U256::from_big_endian(signature.r.as_bytes()));
+    stream.append(&U256::from_big_endian(signature.s.as_bytes()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secp256k1::SecretKey;
+    use web3::{
+        transports::Http,
+        types::{AccessListItem, U64},
+        Web3,
+    };
+
+    #[test]
+    fn derivation_paths() {
+        assert_eq!(
+            DerivationPath::default(),
+            DerivationPath(vec![0x8000_002c, 0x8000_003c, 0x8000_0000, 0, 0])
+        );
+        assert_eq!(
+            "44h/60h/1h".parse::<DerivationPath>().unwrap(),
+            DerivationPath(vec![0x8000_002c, 0x8000_003c, 0x8000_0001])
+        );
+        assert_eq!(
+            DerivationPath::default().to_bytes(),
+            hex::decode("058000002c8000003c800000000000000000000000").unwrap()
+        );
+        for invalid in ["m/44'/x", "m/2147483648", "m//0", "0/0/0/0/0/0/0/0/0/0/0"] {
+            assert!(invalid.parse::<DerivationPath>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn framing() {
+        let apdu: Vec<u8> = (0..=200).collect();
+        let frames = packets(&apdu);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0][..7], [0x01, 0x01, 0x05, 0x00, 0x00, 0x00, 201]);
+        assert_eq!(frames[3][..5], [0x01, 0x01, 0x05, 0x00, 0x03]);
+
+        let mut responses = frames.into_iter();
+        assert_eq!(
+            read_packets(|| Ok(responses.next().unwrap())).unwrap(),
+            apdu
+        );
+
+        let mut packet = packets(&[0x90, 0x00])[0];
+        packet[1] = 0x02;
+        assert!(matches!(
+            read_packets(|| Ok(packet)),
+            Err(LedgerError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn statuses() {
+        assert_eq!(check_status(vec![1, 2, 0x90, 0x00]).unwrap(), [1, 2]);
+        assert!(matches!(
+            check_status(vec![0x69, 0x85]),
+            Err(LedgerError::Rejected)
+        ));
+        assert!(matches!(
+            check_status(vec![0x6e, 0x01]),
+            Err(LedgerError::AppNotOpen)
+        ));
+        assert!(matches!(
+            check_status(vec![0x6b, 0x00]),
+            Err(LedgerError::Status(0x6b00))
+        ));
+        assert!(matches!(
+            check_status(vec![0x90]),
+            Err(LedgerError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn addresses() {
+        let address = Address::repeat_byte(0xab);
+        let mut response = vec![65];
+        response.extend([4; 65]);
+        response.push(40);
+        response.extend(hex::encode(address).into_bytes());
+        assert_eq!(address_from_response(&response).unwrap(), address);
+        assert!(address_from_response(&response[..100]).is_err());
+
+        assert_eq!(
+            vendor_id("DRIVER=hid-generic\nHID_ID=0003:00002C97:00004015\n"),
+            Some(0x2c97)
+        );
+        assert_eq!(vendor_id("DRIVER=hid-generic\n"), None);
+    }
+
+    /// Chunks never end within the EIP-155 fields of legacy transactions.
+    #[test]
+    fn chunks() {
+        let path = DerivationPath::default();
+        for length in [10, 128, 129, 130, 140, 400] {
+            let unsigned: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            let apdus = sign_transaction_apdus(&path, &unsigned, 5);
+            let data: Vec<u8> = apdus.iter().flat_map(|apdu| apdu[5..].to_vec()).collect();
+            assert_eq!(data, [path.to_bytes(), unsigned].concat());
+            assert_eq!(apdus[0][..3], [CLA, INS_SIGN_TRANSACTION, P1_FIRST_CHUNK]);
+            assert!(apdus[1..].iter().all(|apdu| apdu[2] == P1_MORE_CHUNKS));
+            assert!(apdus.iter().all(|apdu| apdu[4] as usize == apdu.len() - 5));
+            let mut end = 0;
+            for apdu in &apdus[..apdus.len() - 1] {
+                end += apdu.len() - 5;
+                assert!(end < data.len() - 5);
+            }
+        }
+    }
+
+    fn transaction(transaction_type: Option<u64>) -> TransactionParameters {
+        TransactionParameters {
+            nonce: Some(7.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            gas: 100_000.into(),
+            gas_price: Some(1_000.into()),
+            max_fee_per_gas: Some(2_000.into()),
+            max_priority_fee_per_gas: Some(10.into()),
+            transaction_type: transaction_type.map(U64::from),
+            data: vec![1, 2, 3].into(),
+            chain_id: Some(42161),
+            value: U256::zero(),
+            access_list: Some(vec![AccessListItem {
+                address: Address::repeat_byte(0x22),
+                storage_keys: vec![H256::repeat_byte(0x33)],
+            }]),
+        }
+    }
+
+    /// The encoding must match web3's, or the device would sign something else than what's sent.
+    #[tokio::test]
+    async fn matches_web3_encoding() {
+        let private_key: SecretKey =
+            "4f3edf983ac636a65a842ce7c78d9aa706d3b113bce9c46f30d7d21715b23b1d"
+                .parse()
+                .unwrap();
+        let client = Web3::new(Http::new("http://127.0.0.1:1").unwrap());
+        for transaction_type in [None, Some(1), Some(2)] {
+            let transaction = transaction(transaction_type);
+            let signed = client
+                .accounts()
+                .sign_transaction(transaction.clone(), &private_key)
+                .await
+                .unwrap();
+            let unsigned = encode(&transaction, 42161, None).unwrap();
+            assert_eq!(H256::from(keccak256(&unsigned)), signed.message_hash);
+            let signature = Signature {
+                v: signed.v,
+                r: signed.r,
+                s: signed.s,
+            };
+            assert_eq!(
+                encode(&transaction, 42161, Some(&signature)).unwrap(),
+                signed.raw_transaction.0
+            );
+        }
+        assert!(matches!(
+            encode(&transaction(Some(3)), 42161, None),
+            Err(LedgerError::UnsupportedTransactionType(3))
+        ));
+    }
+}
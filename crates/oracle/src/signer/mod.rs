@@ -0,0 +1,386 @@
+//! Signs the oracle's transactions with the owner key, wherever that key lives.
+//!
+//! A [`Signer`] only needs to sign 32-byte hashes. [`sign_transaction`] takes care of encoding the
+//! transaction, so that backends which keep the key off the oracle host, like
+//! [AWS KMS](aws_kms) and [Vault](vault), work the same way as a private key from the configuration
+//! file. [Remote signers](remote) and [Ledger devices](ledger), which only sign whole transactions,
+//! are supported as well. Relayers like [Defender](crate::runner::defender) sign and send
+//! transactions on their own, so the oracle only knows the address of their [`RelayerAccount`].
+
+pub mod aws_kms;
+pub mod ledger;
+pub mod remote;
+pub mod vault;
+
+use crate::config::OwnerKey;
+use async_trait::async_trait;
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::PointConversionForm,
+    ecdsa::EcdsaSig,
+    pkey::{PKeyRef, Public},
+};
+use secp256k1::SecretKey;
+use std::sync::Mutex;
+use web3::{
+    signing::{keccak256, recover, Key, SecretKeyRef, Signature, SigningError},
+    types::{Address, Bytes, TransactionParameters, H256},
+    Transport, Web3,
+};
+
+/// The order of the secp256k1 curve.
+const SECP256K1_ORDER: &str = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignerError {
+    #[error("failed to sign with AWS KMS: {0}")]
+    AwsKms(#[from] aws_kms::AwsKmsError),
+    #[error("failed to sign with Vault: {0}")]
+    Vault(#[from] vault::VaultError),
+    #[error("failed to sign with the Ledger device: {0}")]
+    Ledger(#[from] ledger::LedgerError),
+    #[error("failed to sign with the remote signer: {0}")]
+    Remote(#[from] remote::RemoteSignerError),
+    #[error("failed to sign the transaction: {0}")]
+    Signing(#[from] SigningError),
+    #[error("{0:?} belongs to a relayer, which signs its transactions itself")]
+    Relayer(Address),
+    #[error("the signature wasn't made by {0:?}")]
+    UnexpectedSignature(Address),
+    #[error(transparent)]
+    Crypto(#[from] openssl::error::ErrorStack),
+    #[error(transparent)]
+    Provider(#[from] web3::Error),
+}
+
+#[async_trait]
+pub trait Signer: Send + Sync {
+    async fn address(&self) -> Result<Address, SignerError>;
+
+    /// Signs `hash` as is, i.e. without any envelope. The `v` of the signature is the recovery
+    /// id, either 0 or 1.
+    async fn sign_hash(&self, hash: H256) -> Result<Signature, SignerError>;
+
+    /// Signs a whole transaction and returns it RLP-encoded, for signers that don't sign bare
+    /// hashes. Its chain ID is always set. Returns `None` if the transaction hash should be signed
+    /// with [`Signer::sign_hash`] instead, which is the default.
+    async fn sign_transaction(
+        &self,
+        _transaction: &TransactionParameters,
+    ) -> Option<Result<Bytes, SignerError>> {
+        None
+    }
+}
+
+/// A transaction that's ready to be broadcast.
+#[derive(Debug, Clone)]
+pub struct SignedTransaction {
+    pub raw_transaction: Bytes,
+    pub transaction_hash: H256,
+}
+
+/// Creates the signer for the configured owner key.
+pub fn from_owner_key(owner_key: &OwnerKey) -> Box<dyn Signer> {
+    match owner_key {
+        OwnerKey::PrivateKey(private_key) => Box::new(LocalSigner::new(*private_key)),
+        OwnerKey::AwsKms(options) => Box::new(aws_kms::AwsKmsSigner::new(options.clone())),
+        OwnerKey::Vault(options) => Box::new(vault::VaultSigner::new(options.clone())),
+        OwnerKey::Remote(options) => Box::new(remote::RemoteSigner::new(options.clone())),
+        OwnerKey::Defender(options) => Box::new(RelayerAccount::new(options.address)),
+    }
+}
+
+/// Signs with a private key that's kept in memory.
+pub struct LocalSigner {
+    private_key: SecretKey,
+}
+
+impl LocalSigner {
+    pub fn new(private_key: SecretKey) -> Self {
+        Self { private_key }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    async fn address(&self) -> Result<Address, SignerError> {
+        Ok(SecretKeyRef::new(&self.private_key).address())
+    }
+
+    async fn sign_hash(&self, hash: H256) -> Result<Signature, SignerError> {
+        Ok(SecretKeyRef::new(&self.private_key).sign_message(hash.as_bytes())?)
+    }
+}
+
+/// The account of a relayer that signs and sends transactions itself. Only its address is known.
+pub struct RelayerAccount {
+    address: Address,
+}
+
+impl RelayerAccount {
+    pub fn new(address: Address) -> Self {
+        Self { address }
+    }
+}
+
+#[async_trait]
+impl Signer for RelayerAccount {
+    async fn address(&self) -> Result<Address, SignerError> {
+        Ok(self.address)
+    }
+
+    async fn sign_hash(&self, _hash: H256) -> Result<Signature, SignerError> {
+        Err(SignerError::Relayer(self.address))
+    }
+}
+
+/// Signs `transaction` with `signer`. Its nonce and fees must be set already, or they would be
+/// fetched twice and might not match.
+///
+/// web3 can only sign with keys that sign synchronously, so the transaction is encoded twice: once
+/// to learn the hash that must be signed, and then again with the signature of that hash.
+pub async fn sign_transaction<T: Transport>(
+    client: &Web3<T>,
+    mut transaction: TransactionParameters,
+    signer: &dyn Signer,
+) -> Result<SignedTransaction, SignerError> {
+    let address = signer.address().await?;
+    if transaction.chain_id.is_none() {
+        transaction.chain_id = Some(client.eth().chain_id().await?.as_u64());
+    }
+    if let Some(raw_transaction) = signer.sign_transaction(&transaction).await {
+        let raw_transaction = raw_transaction?;
+        return Ok(SignedTransaction {
+            transaction_hash: keccak256(&raw_transaction.0).into(),
+            raw_transaction,
+        });
+    }
+
+    let hash = Mutex::new(None);
+    client
+        .accounts()
+        .sign_transaction(
+            transaction.clone(),
+            HashRecorder {
+                address,
+                hash: &hash,
+            },
+        )
+        .await?;
+    // Unwrap: the mutex can't be poisoned, and web3 always signs the transaction hash.
+    let hash = hash.into_inner().unwrap().unwrap();
+
+    let signature = signer.sign_hash(hash).await?;
+    let presigned = Presigned {
+        address,
+        hash,
+        recovery_id: signature.v,
+        r: signature.r,
+        s: signature.s,
+    };
+    let signed = client
+        .accounts()
+        .sign_transaction(transaction, presigned)
+        .await?;
+    Ok(SignedTransaction {
+        raw_transaction: signed.raw_transaction,
+        transaction_hash: signed.transaction_hash,
+    })
+}
+
+/// The Ethereum address of a secp256k1 public key, as remote signers return them.
+fn address_from_public_key(public_key: &PKeyRef<Public>) -> Result<Address, SignerError> {
+    let public_key = public_key.ec_key()?;
+    let mut context = BigNumContext::new()?;
+    let point = public_key.public_key().to_bytes(
+        public_key.group(),
+        PointConversionForm::UNCOMPRESSED,
+        &mut context,
+    )?;
+    // Skip the 0x04 prefix of uncompressed points.
+    let hash = keccak256(&point[1..]);
+    Ok(Address::from_slice(&hash[12..]))
+}
+
+/// Turns a DER-encoded ECDSA signature, as remote signers return them, into an Ethereum signature,
+/// which must have a low `s` and the recovery id of `address`.
+fn signature_from_der(der: &[u8], hash: H256, address: Address) -> Result<Signature, SignerError> {
+    let signature = EcdsaSig::from_der(der)?;
+    let order = BigNum::from_hex_str(SECP256K1_ORDER)?;
+    let mut half_order = BigNum::new()?;
+    half_order.rshift1(&order)?;
+    let s = if signature.s() > &half_order {
+        let mut s = BigNum::new()?;
+        s.checked_sub(&order, signature.s())?;
+        s
+    } else {
+        signature.s().to_owned()?
+    };
+    let r = H256::from_slice(&signature.r().to_vec_padded(32)?);
+    let s = H256::from_slice(&s.to_vec_padded(32)?);
+    Ok(Signature {
+        v: recovery_id(hash, r, s, address)?,
+        r,
+        s,
+    })
+}
+
+/// The recovery id, 0 or 1, for which the signature `(r, s)` of `hash` recovers `address`.
+fn recovery_id(hash: H256, r: H256, s: H256, address: Address) -> Result<u64, SignerError> {
+    let compact = [r.as_bytes(), s.as_bytes()].concat();
+    (0..2)
+        .find(|recovery_id| recover(hash.as_bytes(), &compact, *recovery_id) == Ok(address))
+        .map(|recovery_id| recovery_id as u64)
+        .ok_or(SignerError::UnexpectedSignature(address))
+}
+
+/// Records the hash that web3 wants signed, and returns a meaningless signature.
+struct HashRecorder<'a> {
+    address: Address,
+    hash: &'a Mutex<Option<H256>>,
+}
+
+impl Key for HashRecorder<'_> {
+    fn sign(&self, message: &[u8], _chain_id: Option<u64>) -> Result<Signature, SigningError> {
+        self.sign_message(message)
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Result<Signature, SigningError> {
+        if message.len() != 32 {
+            return Err(SigningError::InvalidMessage);
+        }
+        *self.hash.lock().unwrap() = Some(H256::from_slice(message));
+        Ok(Signature {
+            v: 0,
+            r: H256::zero(),
+            s: H256::zero(),
+        })
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+}
+
+/// Returns a signature that was made beforehand, by a [`Signer`].
+struct Presigned {
+    address: Address,
+    hash: H256,
+    recovery_id: u64,
+    r: H256,
+    s: H256,
+}
+
+impl Key for Presigned {
+    fn sign(&self, message: &[u8], chain_id: Option<u64>) -> Result<Signature, SigningError> {
+        let mut signature = self.sign_message(message)?;
+        // See EIP-155.
+        signature.v = match chain_id {
+            Some(chain_id) => signature.v + 35 + chain_id * 2,
+            None => signature.v + 27,
+        };
+        Ok(signature)
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Result<Signature, SigningError> {
+        if message != self.hash.as_bytes() {
+            return Err(SigningError::InvalidMessage);
+        }
+        Ok(Signature {
+            v: self.recovery_id,
+            r: self.r,
+            s: self.s,
+        })
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        ec::{EcGroup, EcKey},
+        nid::Nid,
+        pkey::PKey,
+    };
+    use web3::types::U256;
+
+    fn transaction(transaction_type: Option<u64>) -> TransactionParameters {
+        TransactionParameters {
+            nonce: Some(7.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            gas: 100_000.into(),
+            gas_price: Some(1_000.into()),
+            max_fee_per_gas: Some(1_000.into()),
+            max_priority_fee_per_gas: Some(10.into()),
+            transaction_type: transaction_type.map(Into::into),
+            data: vec![1, 2, 3].into(),
+            chain_id: Some(42161),
+            value: U256::zero(),
+            access_list: None,
+        }
+    }
+
+    /// Signing through a [`Signer`] must give the same result as signing with the key directly.
+    #[tokio::test]
+    async fn matches_web3_signing() {
+        let private_key: SecretKey =
+            "4f3edf983ac636a65a842ce7c78d9aa706d3b113bce9c46f30d7d21715b23b1d"
+                .parse()
+                .unwrap();
+        let client = Web3::new(web3::transports::Http::new("http://127.0.0.1:1").unwrap());
+        let signer = LocalSigner::new(private_key);
+        for transaction_type in [None, Some(2)] {
+            let expected = client
+                .accounts()
+                .sign_transaction(transaction(transaction_type), &private_key)
+                .await
+                .unwrap();
+            let signed = sign_transaction(&client, transaction(transaction_type), &signer)
+                .await
+                .unwrap();
+            assert_eq!(signed.raw_transaction, expected.raw_transaction);
+            assert_eq!(signed.transaction_hash, expected.transaction_hash);
+        }
+    }
+
+    /// Remote signers return DER signatures that may have a high `s`.
+    #[test]
+    fn der_signatures() {
+        let group = EcGroup::from_curve_name(Nid::SECP256K1).unwrap();
+        let key = EcKey::generate(&group).unwrap();
+        let address = address_from_public_key(
+            &PKey::public_key_from_der(&key.public_key_to_der().unwrap()).unwrap(),
+        )
+        .unwrap();
+        let private_key =
+            SecretKey::from_slice(&key.private_key().to_vec_padded(32).unwrap()).unwrap();
+        assert_eq!(address, SecretKeyRef::new(&private_key).address());
+
+        for i in 0..8u8 {
+            let hash = H256::repeat_byte(i + 1);
+            let der = EcdsaSig::sign(hash.as_bytes(), &key)
+                .unwrap()
+                .to_der()
+                .unwrap();
+            let signature = signature_from_der(&der, hash, address).unwrap();
+            assert!(signature.s.as_bytes()[0] < 0x80);
+            let compact = [signature.r.as_bytes(), signature.s.as_bytes()].concat();
+            assert_eq!(
+                recover(hash.as_bytes(), &compact, signature.v as i32),
+                Ok(address)
+            );
+        }
+        assert!(matches!(
+            signature_from_der(
+                &EcdsaSig::sign(&[1; 32], &key).unwrap().to_der().unwrap(),
+                H256::repeat_byte(1),
+                Address::zero(),
+            ),
+            Err(SignerError::UnexpectedSignature(_))
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+//! Delegates signing to an external JSON-RPC signer with `eth_signTransaction`, such as Consensys
+//! Web3Signer, or a node with an unlocked owner account. The oracle never handles key material.
+//!
+//! Web3Signer answers with the raw transaction, and nodes with a `{ raw, tx }` object. Both are
+//! supported.
+
+use super::{Signer, SignerError};
+use crate::config::RemoteSignerOptions;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use web3::{
+    helpers,
+    signing::Signature,
+    transports::Http,
+    types::{Address, Bytes, TransactionParameters, H256, U64},
+    Transport,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum RemoteSignerError {
+    #[error(transparent)]
+    Rpc(#[from] web3::Error),
+    #[error("invalid response to eth_signTransaction: {0}")]
+    InvalidResponse(String),
+    #[error("remote signers only sign whole transactions")]
+    HashesNotSupported,
+}
+
+pub struct RemoteSigner {
+    options: RemoteSignerOptions,
+    transport: Http,
+}
+
+impl RemoteSigner {
+    pub fn new(options: RemoteSignerOptions) -> Self {
+        let transport = Http::with_client(reqwest::Client::new(), options.url.clone());
+        Self { options, transport }
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    async fn address(&self) -> Result<Address, SignerError> {
+        Ok(self.options.address)
+    }
+
+    async fn sign_hash(&self, _hash: H256) -> Result<Signature, SignerError> {
+        Err(RemoteSignerError::HashesNotSupported.into())
+    }
+
+    async fn sign_transaction(
+        &self,
+        transaction: &TransactionParameters,
+    ) -> Option<Result<Bytes, SignerError>> {
+        let request = sign_transaction_request(transaction, self.options.address);
+        let response = self
+            .transport
+            .execute("eth_signTransaction", vec![request])
+            .await
+            .map_err(RemoteSignerError::from);
+        Some(
+            response
+                .and_then(raw_transaction)
+                .map_err(SignerError::from),
+        )
+    }
+}
+
+/// The `eth_signTransaction` parameter for `transaction`, sent from `from`.
+fn sign_transaction_request(transaction: &TransactionParameters, from: Address) -> Value {
+    let mut request = json!({
+        "from": from,
+        "to": transaction.to,
+        "gas": transaction.gas,
+        "value": transaction.value,
+        "data": transaction.data,
+        "nonce": transaction.nonce,
+        "chainId": transaction.chain_id.map(U64::from),
+    });
+    match transaction.transaction_type {
+        Some(transaction_type) => {
+            request["type"] = json!(transaction_type);
+            request["maxFeePerGas"] = json!(transaction.max_fee_per_gas);
+            request["maxPriorityFeePerGas"] = json!(transaction.max_priority_fee_per_gas);
+        }
+        None => request["gasPrice"] = json!(transaction.gas_price),
+    }
+    request
+}
+
+fn raw_transaction(response: Value) -> Result<Bytes, RemoteSignerError> {
+    let raw = match response {
+        Value::Object(mut object) => object.remove("raw").unwrap_or(Value::Null),
+        raw => raw,
+    };
+    helpers::decode(raw).map_err(|error| RemoteSignerError::InvalidResponse(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        let from = Address::repeat_byte(0x22);
+        let mut transaction = TransactionParameters {
+            nonce: Some(7.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            gas: 100_000.into(),
+            gas_price: Some(1_000.into()),
+            data: vec![1, 2, 3].into(),
+            chain_id: Some(42161),
+            ..Default::default()
+        };
+        assert_eq!(
+            sign_transaction_request(&transaction, from),
+            json!({
+                "from": "0x2222222222222222222222222222222222222222",
+                "to": "0x1111111111111111111111111111111111111111",
+                "gas": "0x186a0",
+                "gasPrice": "0x3e8",
+                "value": "0x0",
+                "data": "0x010203",
+                "nonce": "0x7",
+                "chainId": "0xa4b1",
+            })
+        );
+
+        transaction.transaction_type = Some(2.into());
+        transaction.gas_price = None;
+        transaction.max_fee_per_gas = Some(2_000.into());
+        transaction.max_priority_fee_per_gas = Some(10.into());
+        let request = sign_transaction_request(&transaction, from);
+        assert_eq!(request["type"], "0x2");
+        assert_eq!(request["maxFeePerGas"], "0x7d0");
+        assert_eq!(request["maxPriorityFeePerGas"], "0xa");
+        assert_eq!(request.get("gasPrice"), None);
+    }
+
+    #[test]
+    fn responses() {
+        let expected = Bytes(vec![0xf8, 0x01]);
+        assert_eq!(raw_transaction(json!("0xf801")).unwrap(), expected);
+        assert_eq!(
+            raw_transaction(json!({ "raw": "0xf801", "tx": {} })).unwrap(),
+            expected
+        );
+        assert!(raw_transaction(json!({ "tx": {} })).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+//! Signs with a secp256k1 key in a HashiCorp Vault secrets engine that speaks the API of the
+//! transit engine, i.e. `GET <mount>/keys/<key>` for the public key and `POST <mount>/sign/<key>`
+//! for signatures. The stock transit engine has no secp256k1 keys, so in practice that's a plugin.
+//!
+//! The token is renewed whenever half of its lease has passed, so a periodic token keeps working for
+//! as long as the oracle runs.
+
+use super::{address_from_public_key, signature_from_der, Signer, SignerError};
+use crate::config::VaultOptions;
+use async_trait::async_trait;
+use openssl::{base64, pkey::PKey};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::{Mutex, OnceCell};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, warn};
+use web3::{
+    signing::Signature,
+    types::{Address, H256},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum VaultError {
+    #[error("request to Vault failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("Vault denied the request, the token may have expired: {0}")]
+    PermissionDenied(String),
+    #[error("Vault is sealed or unavailable: {0}")]
+    Unavailable(String),
+    #[error("Vault responded with {status}: {message}")]
+    Api { status: StatusCode, message: String },
+    #[error("invalid response from Vault: {0}")]
+    InvalidResponse(String),
+}
+
+impl From<serde_json::Error> for VaultError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::InvalidResponse(error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Renewal {
+    /// The token hasn't been renewed yet.
+    Pending,
+    At(Instant),
+    /// The token isn't renewable, e.g. because it's a root token.
+    Never,
+}
+
+pub struct VaultSigner {
+    options: VaultOptions,
+    http: reqwest::Client,
+    /// Derived from the public key, which is fetched once.
+    address: OnceCell<Address>,
+    renewal: Mutex<Renewal>,
+}
+
+impl VaultSigner {
+    pub fn new(options: VaultOptions) -> Self {
+        Self {
+            options,
+            http: reqwest::Client::new(),
+            address: OnceCell::new(),
+            renewal: Mutex::new(Renewal::Pending),
+        }
+    }
+
+    async fn request<R: for<'de> Deserialize<'de>>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<R, VaultError> {
+        let url = format!(
+            "{}/v1/{path}",
+            self.options.address.as_str().trim_end_matches('/')
+        );
+        let mut builder = self
+            .http
+            .request(method, url)
+            .header("X-Vault-Token", &self.options.token);
+        if let Some(namespace) = &self.options.namespace {
+            builder = builder.header("X-Vault-Namespace", namespace);
+        }
+        if let Some(body) = body {
+            builder = builder.body(serde_json::to_vec(&body)?);
+        }
+        let response = builder.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        if !status.is_success() {
+            return Err(api_error(status, &body));
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Renews the token if half of its lease has passed. Failures are only logged, because the
+    /// token may still be valid for a while.
+    async fn renew_token(&self) {
+        #[derive(Deserialize)]
+        struct RenewResponse {
+            auth: Auth,
+        }
+        #[derive(Deserialize)]
+        struct Auth {
+            lease_duration: u64,
+            renewable: bool,
+        }
+
+        let mut renewal = self.renewal.lock().await;
+        match *renewal {
+            Renewal::At(at) if at > Instant::now() => return,
+            Renewal::Never => return,
+            _ => {}
+        }
+        let response: Result<RenewResponse, _> = self
+            .request(
+                Method::POST,
+                "auth/token/renew-self",
+                Some(serde_json::json!({})),
+            )
+            .await;
+        *renewal = match response {
+            Ok(RenewResponse { auth }) => {
+                debug!(
+                    lease_duration = auth.lease_duration,
+                    "Renewed the Vault token"
+                );
+                next_renewal(Instant::now(), auth.lease_duration, auth.renewable)
+            }
+            // Root tokens and the like can't be renewed, but they don't expire either.
+            Err(VaultError::Api { status, message }) if status == StatusCode::BAD_REQUEST => {
+                debug!(%message, "The Vault token can't be renewed");
+                Renewal::Never
+            }
+            Err(error) => {
+                warn!(%error, "Failed to renew the Vault token");
+                *renewal
+            }
+        };
+    }
+
+    async fn fetch_address(&self) -> Result<Address, SignerError> {
+        #[derive(Deserialize)]
+        struct KeyResponse {
+            data: KeyData,
+        }
+        #[derive(Deserialize)]
+        struct KeyData {
+            latest_version: u64,
+            keys: HashMap<String, KeyVersion>,
+        }
+        #[derive(Deserialize)]
+        struct KeyVersion {
+            public_key: String,
+        }
+
+        self.renew_token().await;
+        let response: KeyResponse = self
+            .request(
+                Method::GET,
+                &format!("{}/keys/{}", self.options.mount, self.options.key),
+                None,
+            )
+            .await?;
+        let version = response
+            .data
+            .keys
+            .get(&response.data.latest_version.to_string())
+            .ok_or_else(|| {
+                VaultError::InvalidResponse("the latest key version is missing".to_string())
+            })?;
+        let public_key = PKey::public_key_from_pem(version.public_key.as_bytes())?;
+        let address = address_from_public_key(&public_key)?;
+        debug!(?address, key = %self.options.key, "Fetched the address of the Vault key");
+        Ok(address)
+    }
+}
+
+#[async_trait]
+impl Signer for VaultSigner {
+    async fn address(&self) -> Result<Address, SignerError> {
+        let address = self
+            .address
+            .get_or_try_init(|| self.fetch_address())
+            .await?;
+        Ok(*address)
+    }
+
+    async fn sign_hash(&self, hash: H256) -> Result<Signature, SignerError> {
+        #[derive(Deserialize)]
+        struct SignResponse {
+            data: SignData,
+        }
+        #[derive(Deserialize)]
+        struct SignData {
+            signature: String,
+        }
+
+        let address = self.address().await?;
+        self.renew_token().await;
+        let response: SignResponse = self
+            .request(
+                Method::POST,
+                &format!("{}/sign/{}/sha2-256", self.options.mount, self.options.key),
+                Some(serde_json::json!({
+                    "input": base64::encode_block(hash.as_bytes()),
+                    "prehashed": true,
+                    "marshaling_algorithm": "asn1",
+                })),
+            )
+            .await?;
+        let der = base64::decode_block(signature_payload(&response.data.signature)?)?;
+        signature_from_der(&der, hash, address)
+    }
+}
+
+fn next_renewal(now: Instant, lease_duration: u64, renewable: bool) -> Renewal {
+    if !renewable || lease_duration == 0 {
+        return Renewal::Never;
+    }
+    Renewal::At(now + Duration::from_secs(lease_duration) / 2)
+}
+
+/// Vault signatures look like `vault:v<key version>:<base64>`.
+fn signature_payload(signature: &str) -> Result<&str, VaultError> {
+    match signature.splitn(3, ':').collect::<Vec<_>>()[..] {
+        ["vault", version, payload] if version.starts_with('v') => Ok(payload),
+        _ => Err(VaultError::InvalidResponse(format!(
+            "unexpected signature format: {signature}"
+        ))),
+    }
+}
+
+fn api_error(status: StatusCode, body: &[u8]) -> VaultError {
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        errors: Vec<String>,
+    }
+    let message = serde_json::from_slice::<ErrorResponse>(body)
+        .map(|response| response.errors.join("; "))
+        .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned());
+    match status {
+        StatusCode::FORBIDDEN => VaultError::PermissionDenied(message),
+        StatusCode::SERVICE_UNAVAILABLE => VaultError::Unavailable(message),
+        status => VaultError::Api { status, message },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renewals() {
+        let now = Instant::now();
+        assert_eq!(
+            next_renewal(now, 3600, true),
+            Renewal::At(now + Duration::from_secs(1800))
+        );
+        assert_eq!(next_renewal(now, 3600, false), Renewal::Never);
+        assert_eq!(next_renewal(now, 0, true), Renewal::Never);
+    }
+
+    #[test]
+    fn signature_payloads() {
+        assert_eq!(signature_payload("vault:v1:MEUCIQ==").unwrap(), "MEUCIQ==");
+        assert_eq!(signature_payload("vault:v12:MEU6").unwrap(), "MEU6");
+        assert!(signature_payload("MEUCIQ==").is_err());
+        assert!(signature_payload("vault:1:MEUCIQ==").is_err());
+    }
+
+    #[test]
+    fn api_errors() {
+        assert!(matches!(
+            api_error(StatusCode::FORBIDDEN, br#"{"errors":["permission denied"]}"#),
+            VaultError::PermissionDenied(message) if message == "permission denied"
+        ));
+        assert!(matches!(
+            api_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                br#"{"errors":["Vault is sealed"]}"#
+            ),
+            VaultError::Unavailable(_)
+        ));
+        assert!(matches!(
+            api_error(StatusCode::BAD_GATEWAY, b"upstream"),
+            VaultError::Api { message, .. } if message == "upstream"
+        ));
+    }
+}
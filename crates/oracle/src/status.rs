@@ -0,0 +1,70 @@
+//! What the oracle is up to, as served by the `/status` endpoint.
+
+use crate::build_info::BuildInfo;
+use serde::Serialize;
+use std::fmt::Display;
+use std::sync::{Arc, RwLock};
+use std::time::UNIX_EPOCH;
+
+/// Identifies a single polling iteration across logs, metrics and audit records.
+///
+/// It's made of the time at which the oracle started plus the iteration number, so that it's
+/// unique across restarts, e.g. `66f0b2c1-42`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct CorrelationId(String);
+
+impl CorrelationId {
+    pub fn new(started_at_unix_secs: u64, iteration: u64) -> Self {
+        Self(format!("{started_at_unix_secs:x}-{iteration}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The response body of the `/status` endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub build: BuildInfo,
+    pub current_iteration: Option<IterationStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IterationStatus {
+    pub correlation_id: CorrelationId,
+    pub started_at: u64,
+}
+
+/// A [`Status`] that is shared between the oracle and whoever reports about it. Cloning is cheap.
+#[derive(Debug, Clone)]
+pub struct SharedStatus(Arc<RwLock<Status>>);
+
+impl Default for SharedStatus {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(Status {
+            build: BuildInfo::CURRENT,
+            current_iteration: None,
+        })))
+    }
+}
+
+impl SharedStatus {
+    pub fn get(&self) -> Status {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn start_iteration(&self, correlation_id: CorrelationId) {
+        self.0.write().unwrap().current_iteration = Some(IterationStatus {
+            correlation_id,
+            started_at: UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        });
+    }
+}
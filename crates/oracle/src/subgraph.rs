@@ -1,11 +1,12 @@
-use super::metrics::METRICS;
+use super::metrics::Metrics;
 use crate::models::Caip2ChainId;
-use crate::runner::error_handling::{MainLoopFlow, OracleControlFlow};
+use crate::runner::error_handling::{ErrorClass, MainLoopFlow, OracleControlFlow};
 use anyhow::ensure;
 use graphql_client::{GraphQLQuery, Response};
 use itertools::Itertools;
 use reqwest::Url;
-use tracing::{error, info, warn};
+use std::ops::RangeInclusive;
+use tracing::{info, warn};
 
 #[derive(Debug, thiserror::Error)]
 pub enum SubgraphQueryError {
@@ -32,11 +33,22 @@ impl MainLoopFlow for SubgraphQueryError {
             _ => OracleControlFlow::Continue(40),
         }
     }
+
+    fn class(&self) -> ErrorClass {
+        match self {
+            SubgraphQueryError::Transport(_) => ErrorClass::SubgraphTransport,
+            SubgraphQueryError::IndexingError => ErrorClass::SubgraphFailed,
+            SubgraphQueryError::BadData(_) | SubgraphQueryError::Other(_) => {
+                ErrorClass::SubgraphBadData
+            }
+        }
+    }
 }
 
 pub async fn query_subgraph(
     url: &Url,
     bearer_token: &str,
+    metrics: &Metrics,
 ) -> Result<SubgraphState, SubgraphQueryError> {
     info!("Fetching latest subgraph state");
 
@@ -54,13 +66,13 @@ pub async fn query_subgraph(
 
     match response_body.errors.as_deref() {
         Some([]) | None => {
-            METRICS.set_subgraph_indexing_errors(false);
+            metrics.set_subgraph_indexing_errors(false);
         }
         Some(errors) => {
             // We only deal with the first error and ignore the rest.
             let e = &errors[0];
             if e.message == "indexing_error" {
-                METRICS.set_subgraph_indexing_errors(true);
+                metrics.set_subgraph_indexing_errors(true);
                 return Err(SubgraphQueryError::IndexingError);
             } else {
                 return Err(SubgraphQueryError::Other(anyhow::anyhow!("{}", e.message)));
@@ -82,6 +94,18 @@ pub async fn query_subgraph(
         .map(|gs| gs.try_into())
         .transpose()
         .map_err(SubgraphQueryError::BadData)?;
+    for network in global_state
+        .iter()
+        .flat_map(|gs: &GlobalState| &gs.networks)
+    {
+        if let Some(block_update) = &network.latest_block_update {
+            metrics.set_latest_block_number(
+                network.id.as_str(),
+                "subgraph",
+                block_update.block_number as i64,
+            );
+        }
+    }
     let last_payload: Option<Payload> = data
         .payloads
         .first()
@@ -91,7 +115,7 @@ pub async fn query_subgraph(
 
     // Check if the last payload indexed by the subgraph is valid.
     if let Some(payload) = &last_payload {
-        METRICS.set_subgraph_last_payload_health(payload.valid, payload.created_at);
+        metrics.set_subgraph_last_payload_health(payload.valid, payload.created_at);
     } else {
         warn!("Epoch Subgraph had no previous payload");
     };
@@ -103,6 +127,169 @@ pub async fn query_subgraph(
     })
 }
 
+/// The point in the subgraph's history at which to query entities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Snapshot {
+    #[default]
+    Latest,
+    /// The state of the subgraph right after indexing the given block of the protocol chain.
+    Block(u64),
+}
+
+impl Snapshot {
+    fn block_height<T>(self, block_height: impl FnOnce(Option<i64>) -> T) -> Option<T> {
+        match self {
+            Snapshot::Latest => None,
+            Snapshot::Block(number) => Some(block_height(Some(number as i64))),
+        }
+    }
+}
+
+/// Queries the history of the Epoch Subgraph, for tooling that needs more than the latest
+/// [`SubgraphState`]. Results are paginated transparently.
+#[derive(Debug, Clone)]
+pub struct SubgraphQuery {
+    client: reqwest::Client,
+    url: Url,
+    bearer_token: String,
+    page_size: usize,
+}
+
+impl SubgraphQuery {
+    /// The maximum page size that Graph Node allows.
+    pub const MAX_PAGE_SIZE: usize = 1000;
+
+    pub fn new(url: Url, bearer_token: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .user_agent("block-oracle")
+                .build()
+                .unwrap(),
+            url,
+            bearer_token: bearer_token.into(),
+            page_size: Self::MAX_PAGE_SIZE,
+        }
+    }
+
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.clamp(1, Self::MAX_PAGE_SIZE);
+        self
+    }
+
+    /// Fetches all epochs within `epochs` that the subgraph knows about as of `at`, in ascending
+    /// order.
+    pub async fn epochs(
+        &self,
+        epochs: RangeInclusive<u64>,
+        at: Snapshot,
+    ) -> Result<Vec<EpochSnapshot>, SubgraphQueryError> {
+        let mut snapshots: Vec<EpochSnapshot> = vec![];
+        let mut from = *epochs.start();
+        while from <= *epochs.end() {
+            let variables = graphql::epochs::Variables {
+                first: self.page_size as i64,
+                from: from.to_string(),
+                to: epochs.end().to_string(),
+                block: at.block_height(|number| graphql::epochs::Block_height { number }),
+            };
+            let data = self.post::<graphql::Epochs>(variables).await?;
+            let page_len = data.epoches.len();
+            for epoch in data.epoches {
+                snapshots.push(epoch.try_into().map_err(SubgraphQueryError::BadData)?);
+            }
+            match snapshots.last() {
+                Some(last) if page_len == self.page_size => from = last.epoch_number + 1,
+                _ => break,
+            }
+        }
+        Ok(snapshots)
+    }
+
+    pub async fn epoch(
+        &self,
+        epoch_number: u64,
+        at: Snapshot,
+    ) -> Result<Option<EpochSnapshot>, SubgraphQueryError> {
+        let mut epochs = self.epochs(epoch_number..=epoch_number, at).await?;
+        Ok(epochs.pop())
+    }
+
+    /// Fetches all networks along with their latest block update as of `epoch_number`.
+    ///
+    /// Array indices are the current ones, as the subgraph doesn't keep track of past
+    /// registrations. Use [`SubgraphQuery::networks_at_block`] if those matter.
+    pub async fn networks_at_epoch(
+        &self,
+        epoch_number: u64,
+    ) -> Result<Vec<NetworkSnapshot>, SubgraphQueryError> {
+        self.networks(epoch_number, Snapshot::Latest).await
+    }
+
+    /// Fetches all networks, including removed ones, exactly as they were right after indexing
+    /// `block_number`.
+    pub async fn networks_at_block(
+        &self,
+        block_number: u64,
+    ) -> Result<Vec<NetworkSnapshot>, SubgraphQueryError> {
+        self.networks(u64::MAX, Snapshot::Block(block_number)).await
+    }
+
+    async fn networks(
+        &self,
+        epoch_number: u64,
+        at: Snapshot,
+    ) -> Result<Vec<NetworkSnapshot>, SubgraphQueryError> {
+        let mut snapshots = vec![];
+        let mut after = String::new();
+        loop {
+            let variables = graphql::networks::Variables {
+                first: self.page_size as i64,
+                after: after.clone(),
+                epoch: epoch_number.to_string(),
+                block: at.block_height(|number| graphql::networks::Block_height { number }),
+            };
+            let data = self.post::<graphql::Networks>(variables).await?;
+            let page_len = data.networks.len();
+            if let Some(last) = data.networks.last() {
+                after = last.id.clone();
+            }
+            for network in data.networks {
+                snapshots.push(network.try_into().map_err(SubgraphQueryError::BadData)?);
+            }
+            if page_len < self.page_size {
+                return Ok(snapshots);
+            }
+        }
+    }
+
+    async fn post<Q: GraphQLQuery>(
+        &self,
+        variables: Q::Variables,
+    ) -> Result<Q::ResponseData, SubgraphQueryError> {
+        let response = self
+            .client
+            .post(self.url.clone())
+            .json(&Q::build_query(variables))
+            .bearer_auth(&self.bearer_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        let response_body: Response<Q::ResponseData> = response.json().await?;
+        match response_body.errors.as_deref() {
+            Some([]) | None => {}
+            Some([e, ..]) if e.message == "indexing_error" => {
+                return Err(SubgraphQueryError::IndexingError)
+            }
+            Some([e, ..]) => {
+                return Err(SubgraphQueryError::Other(anyhow::anyhow!("{}", e.message)))
+            }
+        }
+        response_body
+            .data
+            .ok_or_else(|| SubgraphQueryError::Other(anyhow::anyhow!("No response data")))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SubgraphState {
     pub last_indexed_block_number: u64,
@@ -194,11 +381,6 @@ impl TryFrom<graphql::subgraph_state::SubgraphStateGlobalStateNetworks> for Netw
                 delta: block_data.delta.parse()?,
                 updated_at_epoch_number: { block_data.epoch_number.parse()? },
             };
-            METRICS.set_latest_block_number(
-                id.as_str(),
-                "subgraph",
-                block_update.block_number as i64,
-            );
             Some(block_update)
         } else {
             info!("Network {} is uninitialized", id.as_str());
@@ -253,6 +435,13 @@ pub struct Payload {
     created_at: i64,
 }
 
+impl Payload {
+    /// Whether the subgraph could make sense of the payload.
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+}
+
 impl TryFrom<&graphql::subgraph_state::SubgraphStatePayloads> for Payload {
     type Error = anyhow::Error;
 
@@ -266,6 +455,84 @@ impl TryFrom<&graphql::subgraph_state::SubgraphStatePayloads> for Payload {
     }
 }
 
+/// The block numbers that were set for an epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpochSnapshot {
+    pub epoch_number: u64,
+    /// Sorted by network ID.
+    pub block_updates: Vec<(Caip2ChainId, BlockUpdate)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkSnapshot {
+    pub id: Caip2ChainId,
+    /// `None` for removed networks.
+    pub array_index: Option<u64>,
+    pub latest_block_update: Option<BlockUpdate>,
+}
+
+fn parse_network_id(id: &str) -> anyhow::Result<Caip2ChainId> {
+    id.parse()
+        .map_err(|s| anyhow::anyhow!("Invalid network name: {}", s))
+}
+
+impl TryFrom<graphql::epochs::EpochsEpoches> for EpochSnapshot {
+    type Error = anyhow::Error;
+
+    fn try_from(value: graphql::epochs::EpochsEpoches) -> Result<Self, Self::Error> {
+        let epoch_number = value.epoch_number.parse()?;
+        let mut block_updates = value
+            .block_numbers
+            .into_iter()
+            .map(|block_data| {
+                let block_update = BlockUpdate {
+                    block_number: block_data.block_number.parse()?,
+                    acceleration: block_data.acceleration.parse()?,
+                    delta: block_data.delta.parse()?,
+                    updated_at_epoch_number: epoch_number,
+                };
+                Ok((parse_network_id(&block_data.network.id)?, block_update))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        block_updates.sort_by(|(a, _), (b, _)| a.cmp(b));
+        ensure!(
+            block_updates.iter().map(|(id, _)| id).all_unique(),
+            "Epoch {} has multiple block numbers for the same network",
+            epoch_number
+        );
+
+        Ok(Self {
+            epoch_number,
+            block_updates,
+        })
+    }
+}
+
+impl TryFrom<graphql::networks::NetworksNetworks> for NetworkSnapshot {
+    type Error = anyhow::Error;
+
+    fn try_from(mut value: graphql::networks::NetworksNetworks) -> Result<Self, Self::Error> {
+        let latest_block_update = value
+            .block_numbers
+            .pop()
+            .map(|block_data| -> anyhow::Result<_> {
+                Ok(BlockUpdate {
+                    block_number: block_data.block_number.parse()?,
+                    acceleration: block_data.acceleration.parse()?,
+                    delta: block_data.delta.parse()?,
+                    updated_at_epoch_number: block_data.epoch_number.parse()?,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self {
+            id: parse_network_id(&value.id)?,
+            array_index: value.array_index.map(|i| i as u64),
+            latest_block_update,
+        })
+    }
+}
+
 mod graphql {
     use super::*;
 
@@ -276,16 +543,32 @@ mod graphql {
         deprecated = "warn"
     )]
     pub struct SubgraphState;
+
+    type BigInt = String;
+
+    #[derive(GraphQLQuery)]
+    #[graphql(
+        schema_path = "src/graphql/schema.graphql",
+        query_path = "src/graphql/history.graphql",
+        deprecated = "warn"
+    )]
+    pub struct Epochs;
+
+    #[derive(GraphQLQuery)]
+    #[graphql(
+        schema_path = "src/graphql/schema.graphql",
+        query_path = "src/graphql/history.graphql",
+        deprecated = "warn"
+    )]
+    pub struct Networks;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use hyper::server::conn::Http;
-    use hyper::{Body, Response};
+    use crate::test_utils::serve;
     use serde_json::json;
     use serde_json::Value as Json;
-    use tokio::net::TcpListener;
 
     struct FakeServer {
         value: serde_json::Value,
@@ -297,29 +580,7 @@ mod tests {
         }
 
         async fn serve(self) -> Url {
-            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-            let port = listener.local_addr().unwrap().port();
-
-            tokio::spawn(async move {
-                let service = hyper::service::service_fn({
-                    |_req| {
-                        let response = self.value.clone();
-                        async move {
-                            Ok::<_, hyper::Error>(Response::new(Body::from(response.to_string())))
-                        }
-                    }
-                });
-
-                loop {
-                    let (stream, _) = listener.accept().await.unwrap();
-
-                    Http::new().serve_connection(stream, service).await.unwrap();
-                }
-            });
-
-            let mut url = Url::parse("http://127.0.0.1").unwrap();
-            url.set_port(Some(port)).unwrap();
-            url
+            serve(move |_| Some(self.value.clone())).await
         }
     }
 
@@ -327,7 +588,7 @@ mod tests {
         let server = FakeServer::new(json);
         let url = &server.serve().await;
         let bearer_token = "foobar";
-        query_subgraph(url, bearer_token).await
+        query_subgraph(url, bearer_token, &Metrics::new().unwrap()).await
     }
 
     #[tokio::test]
@@ -455,4 +716,91 @@ mod tests {
         .unwrap();
         assert!(matches!(error, SubgraphQueryError::IndexingError));
     }
+
+    #[tokio::test]
+    async fn historical_epochs() {
+        let server = FakeServer::new(json!({
+            "data": {
+                "epoches": [
+                    {
+                        "epochNumber": "150",
+                        "blockNumbers": [
+                            {
+                                "blockNumber": "300",
+                                "acceleration": "-2",
+                                "delta": "8",
+                                "network": { "id": "eip155:100" }
+                            },
+                            {
+                                "blockNumber": "1000",
+                                "acceleration": "0",
+                                "delta": "10",
+                                "network": { "id": "eip155:1" }
+                            }
+                        ]
+                    }
+                ]
+            }
+        }));
+        let subgraph = SubgraphQuery::new(server.serve().await, "foobar");
+        let epoch = subgraph
+            .epoch(150, Snapshot::Block(42))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(epoch.epoch_number, 150);
+        assert_eq!(
+            epoch.block_updates[0],
+            (
+                Caip2ChainId::ethereum_mainnet(),
+                BlockUpdate {
+                    block_number: 1000,
+                    acceleration: 0,
+                    delta: 10,
+                    updated_at_epoch_number: 150,
+                }
+            )
+        );
+        assert_eq!(epoch.block_updates.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn historical_networks() {
+        let server = FakeServer::new(json!({
+            "data": {
+                "networks": [
+                    {
+                        "id": "eip155:1",
+                        "arrayIndex": 0,
+                        "blockNumbers": [
+                            {
+                                "blockNumber": "1000",
+                                "acceleration": "0",
+                                "delta": "10",
+                                "epochNumber": "149"
+                            }
+                        ]
+                    },
+                    {
+                        "id": "eip155:100",
+                        "arrayIndex": null,
+                        "blockNumbers": []
+                    }
+                ]
+            }
+        }));
+        let subgraph = SubgraphQuery::new(server.serve().await, "foobar");
+        let networks = subgraph.networks_at_epoch(150).await.unwrap();
+        assert_eq!(networks.len(), 2);
+        assert_eq!(networks[0].array_index, Some(0));
+        assert_eq!(
+            networks[0]
+                .latest_block_update
+                .as_ref()
+                .map(|block_update| block_update.updated_at_epoch_number),
+            Some(149)
+        );
+        assert_eq!(networks[1].array_index, None);
+        assert_eq!(networks[1].latest_block_update, None);
+    }
 }
@@ -0,0 +1,52 @@
+//! Fake HTTP services for tests, e.g. JSON RPC providers, relays and subgraphs.
+
+use hyper::server::conn::Http as HttpServer;
+use hyper::{Body, Request, Response};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use url::Url;
+
+/// Serves HTTP on a random local port, and answers every request with `respond(body)`, where
+/// `body` is the request's JSON body, or `null`. Requests are never answered if `respond` returns
+/// `None`, e.g. to test timeouts.
+pub async fn serve<F>(respond: F) -> Url
+where
+    F: Fn(Value) -> Option<Value> + Clone + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let respond = respond.clone();
+            let service = hyper::service::service_fn(move |request: Request<Body>| {
+                let respond = respond.clone();
+                async move {
+                    let body = hyper::body::to_bytes(request.into_body()).await?;
+                    let body = serde_json::from_slice(&body).unwrap_or_default();
+                    match respond(body) {
+                        Some(response) => {
+                            Ok::<_, hyper::Error>(Response::new(Body::from(response.to_string())))
+                        }
+                        None => futures::future::pending().await,
+                    }
+                }
+            });
+            tokio::spawn(HttpServer::new().serve_connection(stream, service));
+        }
+    });
+    Url::parse(&format!("http://127.0.0.1:{port}")).unwrap()
+}
+
+/// A JSON RPC provider that answers every call with the result `result(method, params)`.
+pub async fn jrpc_provider<F>(result: F) -> Url
+where
+    F: Fn(&str, &Value) -> Value + Clone + Send + Sync + 'static,
+{
+    serve(move |request| {
+        let method = request["method"].as_str().unwrap_or_default();
+        let result = result(method, &request["params"]);
+        Some(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }))
+    })
+    .await
+}
@@ -0,0 +1,320 @@
+//! Differential testing of the Rust encoder against the subgraph's AssemblyScript decoder.
+//!
+//! We generate random sequences of payloads with [`epoch_encoding::Encoder`] and record the state
+//! the encoder ends up in after each of them. Then we render the payloads and the expected state
+//! as a Matchstick test file, so that `graph test` can feed the very same bytes to the subgraph
+//! mappings and assert that the resulting entities match.
+//!
+//! Payloads mix every message that changes the state of indexed chains: network additions and
+//! removals, with or without aliases, block numbers on their own or merged with a registration,
+//! corrections, version updates and resets. Like the subgraph, every case starts at encoding
+//! version 0, and version updates move it through all the others.
+
+use epoch_encoding::messages::{EpochDetails, NetworkIndex};
+use epoch_encoding::{BlockPtr, Encoder, Message, CHECKSUM_ENCODING_VERSION};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use xshell::{cmd, Shell};
+
+const SUBGRAPH_DIRECTORY: &str = "packages/subgraph";
+const TEST_FILE: &str = "tests/differential.test.ts";
+/// Has every permission in `config/test.json`.
+const SUBMITTER: &str = "0x0000000000000000000000000000000000000000";
+const MAX_PAYLOADS_PER_CASE: usize = 8;
+
+/// A sequence of payloads, each submitted in its own epoch, starting from a clean store.
+struct Case {
+    payloads: Vec<Payload>,
+}
+
+struct Payload {
+    epoch: u64,
+    bytes: Vec<u8>,
+    /// The encoding version that the next payload is decoded with.
+    encoding_version: u64,
+    /// How many epochs got block numbers so far, including the ones the subgraph fills in.
+    epoch_count: u64,
+    /// Whether the payload sets the block numbers of `epoch`.
+    sets_block_numbers: bool,
+    networks: Vec<ExpectedNetwork>,
+}
+
+/// The state of an indexed chain after a payload, as seen by the encoder.
+struct ExpectedNetwork {
+    id: String,
+    /// Only for networks registered along with an alias.
+    alias: Option<String>,
+    block_number: u64,
+    delta: i64,
+    acceleration: i64,
+}
+
+pub fn run(seed: u64, cases: usize, no_run: bool) -> anyhow::Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let cases = (0..cases)
+        .map(|_| generate_case(&mut rng))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let test_file: PathBuf = [SUBGRAPH_DIRECTORY, TEST_FILE].iter().collect();
+    std::fs::write(&test_file, render(seed, &cases))?;
+    println!(
+        "Wrote {} differential test cases (seed {seed}) to {}",
+        cases.len(),
+        test_file.display()
+    );
+
+    if !no_run {
+        let sh = Shell::new()?;
+        sh.change_dir(SUBGRAPH_DIRECTORY);
+        cmd!(sh, "yarn test").run()?;
+    }
+    Ok(())
+}
+
+fn generate_case(rng: &mut impl Rng) -> anyhow::Result<Case> {
+    let mut encoder = Encoder::new(0, vec![])?;
+    let mut next_chain_id = 1;
+    let mut aliases = BTreeMap::new();
+    let mut epoch_count = 0;
+    let mut payloads = vec![];
+
+    for i in 0..rng.gen_range(1..=MAX_PAYLOADS_PER_CASE) {
+        let mut messages = vec![];
+
+        if i > 0 && rng.gen_bool(0.1) {
+            messages.push(Message::Reset);
+        }
+
+        // The first payload must register some networks, otherwise there's nothing to test.
+        // Removed networks are never added back, as the subgraph picks up where they left off.
+        let mut registration = None;
+        if i == 0 || rng.gen_bool(0.3) {
+            let network_count = after(&encoder, &messages)?.network_deltas().len();
+            let mut remove: Vec<NetworkIndex> = (0..network_count as NetworkIndex)
+                .filter(|_| rng.gen_bool(0.2))
+                .collect();
+            // Removals all refer to indices from before the message, in any order.
+            remove.shuffle(rng);
+            let add: Vec<String> = (0..rng.gen_range(usize::from(remove.is_empty())..=3))
+                .map(|_| {
+                    next_chain_id += 1;
+                    format!("eip155:{next_chain_id}")
+                })
+                .collect();
+            if rng.gen_bool(0.25) {
+                let add = add
+                    .into_iter()
+                    .map(|id| {
+                        let alias = format!("alias-{}", id.replace(':', "-"));
+                        aliases.insert(id.clone(), alias.clone());
+                        (id, alias)
+                    })
+                    .collect();
+                messages.push(Message::RegisterNetworksAndAliases { remove, add });
+            } else {
+                registration = Some(messages.len());
+                messages.push(Message::RegisterNetworks { remove, add });
+            }
+        }
+
+        // Networks that are left out keep their previous block number. An epoch without any
+        // networks can't have block numbers.
+        let registered = after(&encoder, &messages)?;
+        let sets_block_numbers = !registered.network_deltas().is_empty() && rng.gen_bool(0.9);
+        if sets_block_numbers {
+            let mut block_ptrs = BTreeMap::new();
+            for (id, network) in registered.network_deltas() {
+                if rng.gen_bool(0.8) {
+                    let number = network.block_number + rng.gen_range(0..=1_000);
+                    block_ptrs.insert(id.clone(), BlockPtr::new(number, rng.gen()));
+                }
+            }
+            match registration {
+                Some(index) if rng.gen_bool(0.5) => {
+                    let Message::RegisterNetworks { remove, add } = messages.remove(index) else {
+                        unreachable!()
+                    };
+                    messages.push(Message::RegisterNetworksAndSetBlockNumbers {
+                        remove,
+                        add,
+                        block_ptrs,
+                    });
+                }
+                _ => messages.push(Message::SetBlockNumbersForNextEpoch(block_ptrs)),
+            }
+        }
+
+        // Corrections don't change any state, but the subgraph must still skip over them.
+        let network_count = registered.network_deltas().len();
+        if network_count > 0 && rng.gen_bool(0.2) {
+            let mut data_by_network_id = BTreeMap::new();
+            for index in 0..network_count as NetworkIndex {
+                if rng.gen_bool(0.5) {
+                    data_by_network_id.insert(index, EpochDetails::new(rng.gen(), rng.gen()));
+                }
+            }
+            let position = match messages.last() {
+                Some(Message::SetBlockNumbersForNextEpoch(_)) if rng.gen_bool(0.5) => {
+                    messages.len() - 1
+                }
+                _ => messages.len(),
+            };
+            messages.insert(position, Message::CorrectEpochs { data_by_network_id });
+        }
+
+        // Nothing can follow a version update in the same payload, and versions only go up.
+        let version = registered.encoding_version();
+        if version < CHECKSUM_ENCODING_VERSION && rng.gen_bool(0.2) {
+            messages.push(Message::UpdateVersion {
+                version_number: rng.gen_range(version + 1..=CHECKSUM_ENCODING_VERSION),
+            });
+        }
+
+        if messages.is_empty() {
+            continue;
+        }
+
+        let previous: BTreeMap<String, i64> = encoder
+            .network_deltas()
+            .iter()
+            .map(|(id, n)| (id.clone(), n.block_delta))
+            .collect();
+        let compressed = encoder.compress(&messages)?;
+        let bytes = encoder.encode(&compressed)?;
+        let epoch = payloads.len() as u64 + 1;
+        if sets_block_numbers {
+            epoch_count = epoch;
+        }
+        let networks = encoder
+            .network_deltas()
+            .iter()
+            .map(|(id, network)| {
+                let previous_delta = previous.get(id).copied().unwrap_or(0);
+                ExpectedNetwork {
+                    id: id.clone(),
+                    alias: aliases.get(id).cloned(),
+                    block_number: network.block_number,
+                    delta: network.block_delta,
+                    acceleration: network.block_delta - previous_delta,
+                }
+            })
+            .collect();
+        payloads.push(Payload {
+            epoch,
+            bytes,
+            encoding_version: encoder.encoding_version(),
+            epoch_count,
+            sets_block_numbers,
+            networks,
+        });
+    }
+
+    Ok(Case { payloads })
+}
+
+/// The state of `encoder` after `messages`, which leaves `encoder` itself untouched.
+fn after(encoder: &Encoder, messages: &[Message]) -> anyhow::Result<Encoder> {
+    let mut encoder = encoder.clone();
+    encoder.compress(messages)?;
+    Ok(encoder)
+}
+
+fn render(seed: u64, cases: &[Case]) -> String {
+    let mut ts = format!(
+        r#"// Generated by `cargo xtask differential-test --seed {seed}`. Do not edit.
+import {{ clearStore, test, assert, afterEach, createMockedFunction }} from "matchstick-as/assembly/index";
+import {{ processPayload }} from "../src/mapping";
+import {{ EPOCH_MANAGER_ADDRESS, BIGINT_ONE }} from "../src/constants";
+import {{ Bytes, BigInt, Address, ethereum }} from "@graphprotocol/graph-ts";
+
+const SUBMITTER = "{SUBMITTER}";
+
+function mockEpochNumber(number: i32): void {{
+  createMockedFunction(
+    Address.fromString(EPOCH_MANAGER_ADDRESS),
+    "currentEpoch",
+    "currentEpoch():(uint256)"
+  )
+    .withArgs([])
+    .returns([ethereum.Value.fromSignedBigInt(BigInt.fromI32(number))]);
+}}
+
+afterEach(() => {{
+  clearStore();
+}});
+"#
+    );
+
+    // Unwraps: writing to a `String` can't fail.
+    for (i, case) in cases.iter().enumerate() {
+        writeln!(
+            ts,
+            "\ntest(\"Differential case {i} (seed {seed})\", () => {{"
+        )
+        .unwrap();
+        for (j, payload) in case.payloads.iter().enumerate() {
+            let epoch = payload.epoch;
+            writeln!(ts, "  mockEpochNumber({epoch});").unwrap();
+            writeln!(
+                ts,
+                "  processPayload(SUBMITTER, Bytes.fromHexString(\"0x{}\") as Bytes, \"0x{j:04x}\", BIGINT_ONE);",
+                hex::encode(&payload.bytes)
+            )
+            .unwrap();
+            writeln!(
+                ts,
+                "  assert.fieldEquals(\"Payload\", \"0x{j:04x}\", \"valid\", \"true\");"
+            )
+            .unwrap();
+            writeln!(
+                ts,
+                "  assert.entityCount(\"Epoch\", {});",
+                payload.epoch_count
+            )
+            .unwrap();
+            for (field, value) in [
+                ("activeNetworkCount", payload.networks.len() as u64),
+                ("encodingVersion", payload.encoding_version),
+            ] {
+                writeln!(
+                    ts,
+                    "  assert.fieldEquals(\"GlobalState\", \"0\", \"{field}\", \"{value}\");"
+                )
+                .unwrap();
+            }
+            for (index, network) in payload.networks.iter().enumerate() {
+                let mut fields = vec![("arrayIndex", index.to_string())];
+                fields.extend(network.alias.clone().map(|alias| ("alias", alias)));
+                for (field, value) in fields {
+                    writeln!(
+                        ts,
+                        "  assert.fieldEquals(\"Network\", \"{}\", \"{field}\", \"{value}\");",
+                        network.id
+                    )
+                    .unwrap();
+                }
+                if !payload.sets_block_numbers {
+                    continue;
+                }
+                let id = format!("{epoch}-{}", network.id);
+                for (field, value) in [
+                    ("blockNumber", network.block_number.to_string()),
+                    ("delta", network.delta.to_string()),
+                    ("acceleration", network.acceleration.to_string()),
+                ] {
+                    writeln!(
+                        ts,
+                        "  assert.fieldEquals(\"NetworkEpochBlockNumber\", \"{id}\", \"{field}\", \"{value}\");"
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        writeln!(ts, "}});").unwrap();
+    }
+    ts
+}
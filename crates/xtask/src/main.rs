@@ -1,6 +1,8 @@
 use clap::Parser as _;
 
+mod differential;
 mod message_samples;
+mod payload_sizes;
 
 /// Block Oracle automation scripts
 #[derive(clap::Parser)]
@@ -10,6 +12,36 @@ enum Tasks {
         #[clap(short, long, action)]
         calldata: bool,
     },
+    /// Check that the subgraph decodes randomly generated payloads the same way the encoder
+    /// produced them. Requires the subgraph's Node.js toolchain.
+    DifferentialTest {
+        /// Seed for the payload generator, so that failures can be reproduced
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+        /// How many random sequences of payloads to generate
+        #[clap(long, default_value_t = 20)]
+        cases: usize,
+        /// Only generate the Matchstick test file, without running it
+        #[clap(long, action)]
+        no_run: bool,
+    },
+    /// Compare the size of block number payloads across encoding versions, on simulated
+    /// mainnet-like epochs
+    PayloadSizes {
+        /// Seed for the simulated block production
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+        #[clap(long, default_value_t = 40)]
+        networks: usize,
+        #[clap(long, default_value_t = 100)]
+        epochs: usize,
+    },
+    /// Print the encoding crate's test vectors as JSON, for conformance tests elsewhere
+    TestVectors {
+        /// Write them to this file instead
+        #[clap(long)]
+        out: Option<std::path::PathBuf>,
+    },
 }
 
 #[tokio::main]
@@ -17,6 +49,23 @@ async fn main() -> anyhow::Result<()> {
     use Tasks::*;
     match Tasks::parse() {
         EncodeMessageSamples { calldata } => message_samples::encode(calldata)?,
+        DifferentialTest {
+            seed,
+            cases,
+            no_run,
+        } => differential::run(seed, cases, no_run)?,
+        PayloadSizes {
+            seed,
+            networks,
+            epochs,
+        } => payload_sizes::run(seed, networks, epochs)?,
+        TestVectors { out } => {
+            let json = format!("{:#}\n", epoch_encoding::test_vectors::test_vectors_json()?);
+            match out {
+                Some(path) => std::fs::write(path, json)?,
+                None => print!("{json}"),
+            }
+        }
     };
     Ok(())
 }
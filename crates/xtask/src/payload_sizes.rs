@@ -0,0 +1,84 @@
+//! Compares the size of `SetBlockNumbersForNextEpoch` payloads across encoding versions, on
+//! simulated mainnet-like epochs.
+//!
+//! Every network gets a typical block time, and every epoch lasts about as long as on mainnet
+//! (6646 Ethereum blocks), give or take a couple of minutes. Networks also produce slightly more or
+//! fewer blocks than their block time implies, which is what makes accelerations non-zero.
+
+use epoch_encoding::{BlockPtr, Encoder, Message, DELTA_ACCELERATIONS_ENCODING_VERSION};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+const BLOCK_TIMES_IN_SECONDS: &[f64] = &[0.25, 0.4, 1.0, 2.0, 3.0, 5.0, 6.0, 12.0, 13.0];
+const EPOCH_LENGTH_IN_SECONDS: f64 = 6646.0 * 12.0;
+const EPOCH_JITTER_IN_SECONDS: f64 = 120.0;
+/// How much block production may deviate from the block time, as a fraction.
+const BLOCK_PRODUCTION_JITTER: f64 = 0.002;
+/// The preamble and the merkle root.
+const FIXED_BYTES: usize = 1 + 32;
+
+pub fn run(seed: u64, networks: usize, epochs: usize) -> anyhow::Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let block_times: Vec<f64> = (0..networks)
+        .map(|_| *BLOCK_TIMES_IN_SECONDS.choose(&mut rng).unwrap())
+        .collect();
+    let ids: Vec<String> = (1..=networks).map(|i| format!("eip155:{i}")).collect();
+
+    let mut block_numbers = vec![0u64; networks];
+    let mut epoch_messages = vec![];
+    for _ in 0..epochs {
+        let duration = EPOCH_LENGTH_IN_SECONDS
+            + rng.gen_range(-EPOCH_JITTER_IN_SECONDS..=EPOCH_JITTER_IN_SECONDS);
+        for (number, block_time) in block_numbers.iter_mut().zip(&block_times) {
+            let jitter = rng.gen_range(-BLOCK_PRODUCTION_JITTER..=BLOCK_PRODUCTION_JITTER);
+            *number += (duration / block_time * (1.0 + jitter)).round() as u64;
+        }
+        epoch_messages.push(Message::SetBlockNumbersForNextEpoch(
+            ids.iter()
+                .cloned()
+                .zip(block_numbers.iter().map(|n| BlockPtr::new(*n, [0; 32])))
+                .collect(),
+        ));
+    }
+
+    println!("{networks} networks, {epochs} epochs (seed {seed})");
+    let mut sizes = vec![];
+    for version in [0, DELTA_ACCELERATIONS_ENCODING_VERSION] {
+        let size = average_payload_size(version, &ids, &epoch_messages)?;
+        println!(
+            "  version {version}: {size:.1} bytes per payload, {:.1} of them accelerations",
+            size - FIXED_BYTES as f64
+        );
+        sizes.push(size - FIXED_BYTES as f64);
+    }
+    println!(
+        "  delta encoding changes the size of accelerations by {:+.1}%",
+        (sizes[1] / sizes[0] - 1.0) * 100.0
+    );
+    Ok(())
+}
+
+/// Leaves out the payload that registers the networks, and the first epoch, whose accelerations
+/// are the whole block numbers.
+fn average_payload_size(
+    version: u64,
+    ids: &[String],
+    epoch_messages: &[Message],
+) -> anyhow::Result<f64> {
+    let mut encoder = Encoder::new(version, vec![])?;
+    encoder.compress(&[Message::RegisterNetworks {
+        remove: vec![],
+        add: ids.to_vec(),
+    }])?;
+
+    let mut total = 0;
+    for (i, message) in epoch_messages.iter().enumerate() {
+        let compressed = encoder.compress(std::slice::from_ref(message))?;
+        let payload = encoder.encode(&compressed)?;
+        if i > 0 {
+            total += payload.len();
+        }
+    }
+    Ok(total as f64 / (epoch_messages.len().max(2) - 1) as f64)
+}
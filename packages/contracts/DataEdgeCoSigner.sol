@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: MIT
+
+pragma solidity ^0.8.17;
+
+interface IDataEdge {
+    function crossChainEpochOracle(bytes calldata _payload) external;
+}
+
+/// @title DataEdgeCoSigner
+/// @notice Forwards Epoch Block Oracle payloads to the DataEdge once enough oracle operators signed
+/// them, as sent by the `block-oracle` with a `[co_signing]` table.
+///
+/// The Epoch Subgraph attributes every forwarded payload to this contract, so this contract's
+/// address, rather than the operators', must be in the subgraph's permission list.
+contract DataEdgeCoSigner {
+    /// @notice The DataEdge that payloads are forwarded to.
+    IDataEdge public immutable dataEdge;
+    /// @notice The account allowed to submit co-signed payloads, i.e. the oracle's `owner_address`.
+    /// Otherwise anyone could replay an old payload along with its signatures.
+    address public immutable submitter;
+    /// @notice How many distinct co-signers must sign every payload.
+    uint256 public immutable threshold;
+    /// @notice Whether an account is a co-signer.
+    mapping(address => bool) public isSigner;
+
+    /// @notice Emitted right before a payload is forwarded to the DataEdge, so that the subgraph
+    /// can tell co-signed payloads apart on DataEdges that emit a `Log(bytes)` event.
+    event PayloadCoSigned(bytes32 indexed payloadHash);
+
+    constructor(IDataEdge _dataEdge, address _submitter, address[] memory _signers, uint256 _threshold) {
+        require(_threshold > 0 && _threshold <= _signers.length, "invalid threshold");
+        dataEdge = _dataEdge;
+        submitter = _submitter;
+        threshold = _threshold;
+        for (uint256 i = 0; i < _signers.length; i++) {
+            isSigner[_signers[i]] = true;
+        }
+    }
+
+    /// @notice The digest that co-signers sign with `personal_sign` (EIP-191). It commits to the
+    /// chain and to this contract, so signatures can't be reused on other deployments.
+    function digest(bytes calldata _payload) public view returns (bytes32) {
+        return keccak256(abi.encode(block.chainid, address(this), keccak256(_payload)));
+    }
+
+    /// @notice Forwards `_payload` to the DataEdge. `_signatures` are 65-byte `r || s || v`
+    /// signatures of `digest(_payload)`, with `v` of 27 or 28, ordered by signer address.
+    function crossChainEpochOracle(bytes calldata _payload, bytes[] calldata _signatures) external {
+        require(msg.sender == submitter, "only the submitter");
+        require(_signatures.length >= threshold, "not enough signatures");
+
+        bytes32 message = keccak256(abi.encodePacked("\x19Ethereum Signed Message:\n32", digest(_payload)));
+        address previous = address(0);
+        for (uint256 i = 0; i < _signatures.length; i++) {
+            address signer = recover(message, _signatures[i]);
+            // Strictly ascending signers rule out counting one signature twice.
+            require(signer > previous, "signatures must be ordered by signer");
+            require(isSigner[signer], "not a co-signer");
+            previous = signer;
+        }
+
+        emit PayloadCoSigned(keccak256(_payload));
+        dataEdge.crossChainEpochOracle(_payload);
+    }
+
+    function recover(bytes32 _message, bytes calldata _signature) private pure returns (address) {
+        require(_signature.length == 65, "invalid signature length");
+        bytes32 r = bytes32(_signature[0:32]);
+        bytes32 s = bytes32(_signature[32:64]);
+        uint8 v = uint8(_signature[64]);
+        address signer = ecrecover(_message, v, r, s);
+        require(signer != address(0), "invalid signature");
+        return signer;
+    }
+}
@@ -3,6 +3,11 @@ import { BigInt } from "@graphprotocol/graph-ts";
 export const PREAMBLE_BIT_LENGTH = 8;
 export const PREAMBLE_BYTE_LENGTH = PREAMBLE_BIT_LENGTH / 8;
 export const TAG_BIT_LENGTH = 4;
+// Accelerations are differences from the previous network's acceleration.
+export const DELTA_ACCELERATIONS_ENCODING_VERSION = 1;
+// Payloads end with the first CHECKSUM_BYTE_LENGTH bytes of the keccak256 of the rest.
+export const CHECKSUM_ENCODING_VERSION = 2;
+export const CHECKSUM_BYTE_LENGTH = 4;
 //export const OWNER_ADDRESS_STRING = "";
 export const EPOCH_MANAGER_ADDRESS = "0x5a843145c43d328b9bb7a4401d94918f131bb281";
 
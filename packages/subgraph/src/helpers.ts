@@ -18,7 +18,12 @@ import {
 import { BytesReader } from "./decoding";
 import { EpochManager } from "../generated/DataEdge/EpochManager";
 import { StoreCache } from "./store-cache";
-import { BIGINT_ONE, BIGINT_ZERO, EPOCH_MANAGER_ADDRESS } from "./constants";
+import {
+  BIGINT_ONE,
+  BIGINT_ZERO,
+  CHECKSUM_BYTE_LENGTH,
+  EPOCH_MANAGER_ADDRESS
+} from "./constants";
 
 export enum MessageTag {
   SetBlockNumbersForEpochMessage = 0,
@@ -27,7 +32,8 @@ export enum MessageTag {
   RegisterNetworksMessage,
   ChangePermissionsMessage,
   ResetStateMessage,
-  RegisterNetworksAndAliasesMessage
+  RegisterNetworksAndAliasesMessage,
+  RegisterNetworksAndSetBlockNumbersMessage
 }
 
 export namespace MessageTag {
@@ -38,7 +44,8 @@ export namespace MessageTag {
     "RegisterNetworksMessage",
     "ChangePermissionsMessage",
     "ResetStateMessage",
-    "RegisterNetworksAndAliasesMessage"
+    "RegisterNetworksAndAliasesMessage",
+    "RegisterNetworksAndSetBlockNumbersMessage"
   ];
   export function toString(tag: MessageTag): string {
     return tags[tag];
@@ -54,6 +61,9 @@ let EVENT_SIGNATURE =
 let EVENT_DATA_TYPES =
   "(address,uint256,bytes,uint8,uint256,uint256,uint256,address,address,bytes,bytes)";
 let LOG_EVENT_SIGNATURE = "Log(bytes)"
+// Emitted by the co-signing contract (packages/contracts/DataEdgeCoSigner.sol) right before it
+// forwards a payload to the DataEdge
+let CO_SIGNED_EVENT_SIGNATURE = "PayloadCoSigned(bytes32)";
 
 // For some reason it's erroring when trying to parse the calldata
 export class SafeExecutionContext {
@@ -233,6 +243,23 @@ export function parseCalldata(calldata: Bytes): Bytes {
   return changetype<Bytes>(calldata.slice(68, 68 + length.toI32()));
 }
 
+// Returns the payload without its trailer, or null if the trailer doesn't match the rest of the
+// payload. Only for payloads of CHECKSUM_ENCODING_VERSION.
+export function stripChecksum(payload: Bytes): Bytes | null {
+  if (payload.length < CHECKSUM_BYTE_LENGTH) {
+    return null;
+  }
+  let length = payload.length - CHECKSUM_BYTE_LENGTH;
+  let body = changetype<Bytes>(payload.slice(0, length));
+  let hash = crypto.keccak256(body);
+  for (let i = 0; i < CHECKSUM_BYTE_LENGTH; i++) {
+    if (hash[i] != payload[length + i]) {
+      return null;
+    }
+  }
+  return body;
+}
+
 export function wipeNetworkList(
   networks: Array<Network>,
   messageId: String
@@ -299,6 +326,35 @@ export function getSafeExecutionContext(
   return parseSafeExecutionContext(log);
 }
 
+// Returns the co-signing contract that forwarded the payload to the DataEdge, or null if the
+// DataEdge wasn't called by one. Transactions to a co-signing contract are sent by any of the
+// oracle operators, so the payload must be attributed to the contract rather than the sender.
+export function getCoSignerAddress(
+  event: ethereum.Event,
+  payload: Bytes
+): Address | null {
+  let receipt = event.receipt;
+  if (receipt === null) {
+    return null;
+  }
+
+  let previousLogIndex = event.logIndex.minus(BIGINT_ONE);
+  let logs = receipt.logs;
+  for (let i = 0; i < logs.length; i++) {
+    let topics = logs[i].topics;
+    if (
+      logs[i].logIndex == previousLogIndex &&
+      topics.length == 2 &&
+      isEventLog(topics[0], CO_SIGNED_EVENT_SIGNATURE) &&
+      topics[1] == crypto.keccak256(payload)
+    ) {
+      return logs[i].address;
+    }
+  }
+
+  return null;
+}
+
 export function parseSafeExecutionContext(
   ethLog: ethereum.Log
 ): SafeExecutionContext | null {
@@ -24,10 +24,18 @@ import {
   isSubmitterAllowed,
   doesSubmitterHavePermission,
   getSafeExecutionContext,
-  SafeExecutionContext
+  getCoSignerAddress,
+  SafeExecutionContext,
+  stripChecksum
 } from "./helpers";
 import { StoreCache } from "./store-cache";
-import { BIGINT_ZERO, BIGINT_ONE, PRELOADED_ALIASES } from "./constants";
+import {
+  BIGINT_ZERO,
+  BIGINT_ONE,
+  CHECKSUM_ENCODING_VERSION,
+  DELTA_ACCELERATIONS_ENCODING_VERSION,
+  PRELOADED_ALIASES
+} from "./constants";
 
 export function handleLogCrossChainEpochOracle(event: Log): void {
   // this is used in deployments on networks that lack trace support, and needs to strip the calldata to only
@@ -40,10 +48,17 @@ export function handleLogCrossChainEpochOracle(event: Log): void {
       event.transaction.from.toHexString()
     ])
   }
+  let coSignerAddress = getCoSignerAddress(event, data);
+  let submitter = event.transaction.from.toHexString();
+  if (coSignerAddress != null) {
+    submitter = coSignerAddress.toHexString();
+  } else if (safeExecutionContext != null) {
+    submitter = safeExecutionContext.multisigAddress.toHexString();
+  }
   // Support for Multisend type of transactions ONLY for EventfulDataEdge impl
   let payloadId = [event.transaction.hash.toHexString(), event.logIndex.toString()].join("-")
   processPayload(
-    safeExecutionContext != null ? safeExecutionContext.multisigAddress.toHexString() : event.transaction.from.toHexString(),
+    submitter,
     data,
     payloadId,
     event.block.number
@@ -53,6 +68,8 @@ export function handleLogCrossChainEpochOracle(event: Log): void {
 export function handleCrossChainEpochOracle(
   call: CrossChainEpochOracleCall
 ): void {
+  // call.from is the immediate caller, so payloads forwarded by a co-signing contract are already
+  // attributed to that contract
   processPayload(
     call.from.toHexString(),
     call.inputs._payload,
@@ -82,7 +99,6 @@ export function processPayload(
   payload.valid = true;
   payload.createdAt = blockNumber;
 
-  let reader = new BytesReader(payloadBytes);
   let blockIdx = 0;
 
   if (!isSubmitterAllowed(cache, payload.submitter, blockNumber)) {
@@ -93,6 +109,20 @@ export function processPayload(
     return;
   }
 
+  // Corrupted or truncated payloads are rejected as a whole, before decoding any of them.
+  if (cache.getGlobalState().encodingVersion == CHECKSUM_ENCODING_VERSION) {
+    let body = stripChecksum(payloadBytes);
+    if (body == null) {
+      log.error("Checksum mismatch. Avoiding payload {}", [payloadId]);
+      payload.valid = false;
+      payload.errorMessage = "Checksum mismatch";
+      payload.save();
+      return;
+    }
+    payloadBytes = body!;
+  }
+  let reader = new BytesReader(payloadBytes);
+
   while (reader.length() > 0) {
     let i = blockIdx.toString();
     log.warning("New message block (num. {}) with remaining data: {}", [
@@ -193,6 +223,14 @@ export function processMessage(
     executeResetStateMessage(cache, snapshot, reader, id, messageBlock);
   } else if (tag == MessageTag.RegisterNetworksAndAliasesMessage) {
     executeRegisterNetworksAndAliasesMessage(cache, snapshot, reader, id, messageBlock);
+  } else if (tag == MessageTag.RegisterNetworksAndSetBlockNumbersMessage) {
+    executeRegisterNetworksAndSetBlockNumbersMessage(
+      cache,
+      snapshot,
+      reader,
+      id,
+      messageBlock
+    );
   } else {
     reader.fail(
       "Unknown message tag '{}'. This is most likely a bug!".replace(
@@ -207,6 +245,30 @@ export function processMessage(
   }
 }
 
+// A RegisterNetworks message immediately followed by the body of a
+// SetBlockNumbersForEpoch message, whose accelerations already cover the
+// networks that were just registered. Both halves get their own message entity,
+// and message IDs must be unique across the Message interface.
+function executeRegisterNetworksAndSetBlockNumbersMessage(
+  cache: StoreCache,
+  snapshot: BytesReader,
+  reader: BytesReader,
+  id: String,
+  messageBlock: MessageBlock
+): void {
+  executeRegisterNetworksMessage(cache, snapshot, reader, id, messageBlock);
+  if (!reader.ok) {
+    return;
+  }
+  executeSetBlockNumbersForEpochMessage(
+    cache,
+    reader.snapshot(),
+    reader,
+    [id, "block-numbers"].join("-"),
+    messageBlock
+  );
+}
+
 function executeSetBlockNumbersForEpochMessage(
   cache: StoreCache,
   snapshot: BytesReader,
@@ -279,8 +341,18 @@ function executeNonEmptySetBlockNumbersForEpochMessage(
   log.warning("Now decoding block updates: {}", [reader.data().toHexString()]);
 
   let accelerations: Array<BigInt> = [];
+  // In encoding version 1, every acceleration but the first is a difference from the previous
+  // one. Like the encoder, this relies on wrapping 64-bit arithmetic.
+  let deltaEncoded =
+    globalState.encodingVersion == DELTA_ACCELERATIONS_ENCODING_VERSION;
+  let previousAcceleration: i64 = 0;
   for (let i = 0; i < globalState.activeNetworkCount; i++) {
-    let acceleration = BigInt.fromI64(decodeI64(reader));
+    let decoded = decodeI64(reader);
+    if (deltaEncoded) {
+      decoded += previousAcceleration;
+    }
+    previousAcceleration = decoded;
+    let acceleration = BigInt.fromI64(decoded);
     if (!reader.ok) {
       log.warning("Failed to decode acceleration num. {}", [i.toString()]);
       return;
@@ -355,7 +427,17 @@ function executeCorrectEpochsMessage(
   id: String,
   messageBlock: MessageBlock
 ): void {
-  // TODO.
+  // Corrections aren't applied yet, but they must still be consumed so that the messages after
+  // them are decoded correctly. Each one is a network index, a transaction hash and a merkle root.
+  let message = cache.getCorrectEpochsMessage(id);
+  let numCorrections = decodeU64(reader) as i32;
+  for (let i = 0; i < numCorrections && reader.ok; i++) {
+    decodeU64(reader);
+    reader.advance(64);
+  }
+
+  message.block = messageBlock.id;
+  message.data = reader.diff(snapshot);
 }
 
 function executeUpdateVersionsMessage(